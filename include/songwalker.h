@@ -0,0 +1,56 @@
+/* C ABI for songwalker-core, built with `--features ffi`. */
+
+#ifndef SONGWALKER_H
+#define SONGWALKER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes. On failure, sw_last_error() describes the problem. */
+#define SW_OK 0
+#define SW_NULL_POINTER 1
+#define SW_INVALID_UTF8 2
+#define SW_PARSE_ERROR 3
+#define SW_COMPILE_ERROR 4
+#define SW_INVALID_ARGUMENT 5
+#define SW_INTERNAL_ERROR 6 /* a library bug; no Rust panic unwinds into C */
+
+/* A compiled song. */
+typedef struct SwSong SwSong;
+
+/* Library version, static. */
+const char *sw_version(void);
+
+/* Message of the last failed call on this thread, or NULL. Valid until
+ * the next failing call on the same thread. */
+const char *sw_last_error(void);
+
+/* Check that `source` (UTF-8, NUL-terminated) parses. */
+int32_t sw_parse(const char *source);
+
+/* Parse and compile `source`. Free the song with sw_song_free. */
+int32_t sw_compile(const char *source, SwSong **out_song);
+void sw_song_free(SwSong *song);
+
+/* The compiled event list as JSON. Free with sw_free_string. */
+int32_t sw_song_to_json(const SwSong *song, char **out_json);
+
+/* Render to mono float samples. Free with sw_free_samples. */
+int32_t sw_render(const SwSong *song, uint32_t sample_rate, float **out_samples, size_t *out_len);
+
+/* Render to a 16-bit stereo WAV file in memory. Free with sw_free_bytes. */
+int32_t sw_render_wav(const SwSong *song, uint32_t sample_rate, uint8_t **out_bytes, size_t *out_len);
+
+void sw_free_samples(float *samples, size_t len);
+void sw_free_bytes(uint8_t *bytes, size_t len);
+void sw_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SONGWALKER_H */
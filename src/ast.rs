@@ -1,26 +1,45 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 use serde::{Deserialize, Serialize};
+use crate::math;
+
+/// Newest `.sw` language version this crate understands.
+pub const LATEST_LANGUAGE_VERSION: u32 = 2;
 
 /// A complete SongWalker program.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Program {
     pub statements: Vec<Statement>,
+    /// Language version from the `#version N` pragma; 1 without one.
+    #[serde(default = "first_language_version")]
+    pub language_version: u32,
+}
+
+fn first_language_version() -> u32 {
+    1
 }
 
 /// A top-level statement.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Statement {
     /// `track name(params) { body }`
     TrackDef {
         name: String,
         params: Vec<String>,
         body: Vec<TrackStatement>,
+        /// `///` doc comment lines before the definition, joined by newlines.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        doc: Option<String>,
         span_start: usize,
         span_end: usize,
     },
     /// `name*vel@dur(args) step;`
     TrackCall {
         name: String,
-        velocity: Option<f64>,
+        velocity: Option<VelocityExpr>,
         play_duration: Option<DurationExpr>,
         args: Vec<Expr>,
         step: Option<DurationExpr>,
@@ -41,19 +60,63 @@ pub enum Statement {
         span_start: usize,
         span_end: usize,
     },
+    /// `marker "loop_start";` or `cue "boss_enter";`
+    Marker {
+        kind: MarkerKind,
+        name: String,
+        span_start: usize,
+        span_end: usize,
+    },
+    /// `play hook;` or `play hook at 16;` — play a track call stored with
+    /// `const hook = riff(lead);`, at the cursor or at beat `at`.
+    Play {
+        handle: String,
+        at: Option<f64>,
+        span_start: usize,
+        span_end: usize,
+    },
     /// `// text`
-    Comment(String),
+    Comment {
+        text: String,
+        span_start: usize,
+        span_end: usize,
+    },
+}
+
+/// Kind of a named position in the song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MarkerKind {
+    /// `marker "name"`: a musical position, such as a loop point.
+    Marker,
+    /// `cue "name"`: a point for the host (e.g. a game) to sync to.
+    Cue,
 }
 
 /// A statement inside a track body.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum TrackStatement {
     /// `C3*vel@audible /step`
     NoteEvent {
         pitch: String,
-        velocity: Option<f64>,
+        /// `C4->G4`: pitch to slide to over the note's duration.
+        slide_to: Option<String>,
+        velocity: Option<VelocityExpr>,
         audible_duration: Option<DurationExpr>,
         step_duration: Option<DurationExpr>,
+        /// `{pan: -0.5, brightness: 0.7}` after the note.
+        expression: NoteExpression,
+        /// `C4 "sha-"`: lyric syllable sung on the note.
+        lyric: Option<String>,
+        /// `C4!`, `C4'`, `C4_`: articulation marks after the pitch.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        articulation: Vec<Articulation>,
+        /// `choose([C4, E4, G4])`: pitches picked from at compile time with
+        /// the song seed. `pitch` holds the first.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        choices: Vec<String>,
         /// Source byte offset (start).
         span_start: usize,
         /// Source byte offset (end).
@@ -64,17 +127,26 @@ pub enum TrackStatement {
         notes: Vec<ChordNote>,
         audible_duration: Option<DurationExpr>,
         step_duration: Option<DurationExpr>,
+        /// Expression applied to every note of the chord.
+        expression: NoteExpression,
         /// Source byte offset (start).
         span_start: usize,
         /// Source byte offset (end).
         span_end: usize,
     },
-    /// Standalone number = rest for N beats.
+    /// Rest for a duration: a standalone number (`4`), or an explicit
+    /// `r4`, `r/8` or `_ /4`.
     Rest {
         duration: DurationExpr,
         span_start: usize,
         span_end: usize,
     },
+    /// `R*4`: rest for a number of whole bars of the current time signature.
+    BarRest {
+        bars: f64,
+        span_start: usize,
+        span_end: usize,
+    },
     /// `target = value;`
     Assignment {
         target: String,
@@ -82,11 +154,35 @@ pub enum TrackStatement {
         span_start: usize,
         span_end: usize,
     },
-    /// `for (init; cond; update) { body }`
+    /// `let name = value;` — a variable scoped to the enclosing block.
+    Let {
+        name: String,
+        value: Expr,
+        span_start: usize,
+        span_end: usize,
+    },
+    /// `for (init; cond; update) { body }`; each header part may be empty.
     ForLoop {
-        init: String,
-        condition: String,
-        update: String,
+        init: Option<LoopClause>,
+        condition: Option<Expr>,
+        update: Option<LoopClause>,
+        body: Vec<TrackStatement>,
+        span_start: usize,
+        span_end: usize,
+    },
+    /// `poly(3, 4) { ... }` — play the body's steps three in the time of
+    /// four, so a 3-step pattern lines up with a 4-step one.
+    Poly {
+        steps: f64,
+        span: f64,
+        body: Vec<TrackStatement>,
+        span_start: usize,
+        span_end: usize,
+    },
+    /// `maybe(0.5) { ... }` — keep the body's notes with probability 0.5,
+    /// drawn with the song seed. Its time passes either way.
+    Maybe {
+        probability: f64,
         body: Vec<TrackStatement>,
         span_start: usize,
         span_end: usize,
@@ -94,26 +190,100 @@ pub enum TrackStatement {
     /// A track call inside another track.
     TrackCall {
         name: String,
-        velocity: Option<f64>,
+        velocity: Option<VelocityExpr>,
         play_duration: Option<DurationExpr>,
         args: Vec<Expr>,
         step: Option<DurationExpr>,
         span_start: usize,
         span_end: usize,
     },
+    /// `marker "name"` or `cue "name"` inside a track.
+    Marker {
+        kind: MarkerKind,
+        name: String,
+        span_start: usize,
+        span_end: usize,
+    },
     /// `// text`
-    Comment(String),
+    Comment {
+        text: String,
+        span_start: usize,
+        span_end: usize,
+    },
 }
 
 /// A note within a chord.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ChordNote {
     pub pitch: String,
     pub audible_duration: Option<DurationExpr>,
+    /// Comments written around this note inside the chord brackets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<Comment>,
+}
+
+/// A `// text` comment that is not a statement of its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Comment {
+    pub text: String,
+    pub span_start: usize,
+    pub span_end: usize,
+}
+
+/// Per-note expression, written `C4 /4 {pan: -0.5, brightness: 0.7}`,
+/// plus the `C4~v` (vibrato) and `C4^+2` (bend) pitch modifiers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NoteExpression {
+    /// Stereo position from -1 (left) to 1 (right).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pan: Option<f64>,
+    /// Timbre from 0 (dark) to 1 (open), applied as a lowpass cutoff.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<f64>,
+    /// `~v`: pitch vibrato.
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub vibrato: bool,
+    /// `^+2`: bend in semitones, reached at the end of the gate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bend: Option<f64>,
+}
+
+impl NoteExpression {
+    pub fn is_empty(&self) -> bool {
+        *self == NoteExpression::default()
+    }
+}
+
+/// An articulation mark written after a note's pitch. The amounts come
+/// from `track.accent`, `track.staccato` and `track.legato`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Articulation {
+    /// `C4!`: louder than written.
+    Accent,
+    /// `C4'`: shorter than written.
+    Staccato,
+    /// `C4_`: held for the whole step.
+    Legato,
+}
+
+/// A velocity modifier after `*`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum VelocityExpr {
+    /// `*80`: the velocity itself.
+    Absolute(f64),
+    /// `*+10` / `*-15`: an accent relative to `track.velocity`.
+    Relative(f64),
 }
 
 /// A duration expression.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum DurationExpr {
     /// `/N` shorthand for 1/N (e.g., `/4` = quarter note).
     Inverse(f64),
@@ -121,12 +291,97 @@ pub enum DurationExpr {
     Fraction(f64, f64),
     /// Plain beat count (e.g., `2`, `8`).
     Beats(f64),
-    /// Dot shorthand: `.` = 1x default, `..` = 2x, etc.
+    /// Dot shorthand: the default note length, dotted. `.` = 1.5x the
+    /// default, `..` = 1.75x.
     Dots(usize),
+    /// A dotted duration: `/4.` = 1.5x `/4`, `/4..` = 1.75x.
+    Dotted(Box<DurationExpr>, usize),
+    /// A `let` variable holding a duration or beat count, as a step.
+    Variable(String),
 }
 
-/// A general expression (simplified for Phase 1).
+impl DurationExpr {
+    /// Length multiplier of `dots` augmentation dots: each dot adds half
+    /// of the previous value (1.5, 1.75, 1.875, ...).
+    pub fn dot_factor(dots: usize) -> f64 {
+        2.0 - math::powi(0.5, dots as i32)
+    }
+}
+
+/// The init or update part of a `for` loop header.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum LoopClause {
+    /// `let i = 0`
+    Let { name: String, value: Expr },
+    /// `i = i + 2`
+    Assign { name: String, value: Expr },
+    /// `i++` (delta 1) or `i--` (delta -1)
+    Step { name: String, delta: f64 },
+}
+
+/// A binary operator, listed from loosest to tightest binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl BinaryOp {
+    /// The operator as written.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+        }
+    }
+
+    /// Binding strength; higher binds tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::Ne => 3,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 4,
+            BinaryOp::Add | BinaryOp::Sub => 5,
+            BinaryOp::Mul | BinaryOp::Div => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum UnaryOp {
+    /// `-x`
+    Neg,
+    /// `!x`
+    Not,
+}
+
+/// A general expression.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum Expr {
     Number(f64),
     StringLit(String),
@@ -144,36 +399,52 @@ pub enum Expr {
         property: String,
     },
     DurationLit(DurationExpr),
+    /// `a + b`, `i < 4`, `x == 1 && y != 2`
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `-x` or `!x`; a minus before a number literal is folded into it.
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
 }
 
 // ── Span accessors ──────────────────────────────────────────
 
 impl Statement {
     /// Returns the source byte range `(span_start, span_end)` for this statement.
-    /// Comments have no span information and return `(usize::MAX, usize::MAX)`.
     pub fn span(&self) -> (usize, usize) {
         match self {
             Statement::TrackDef { span_start, span_end, .. }
             | Statement::TrackCall { span_start, span_end, .. }
             | Statement::ConstDecl { span_start, span_end, .. }
-            | Statement::Assignment { span_start, span_end, .. } => (*span_start, *span_end),
-            Statement::Comment(_) => (usize::MAX, usize::MAX),
+            | Statement::Assignment { span_start, span_end, .. }
+            | Statement::Marker { span_start, span_end, .. }
+            | Statement::Play { span_start, span_end, .. }
+            | Statement::Comment { span_start, span_end, .. } => (*span_start, *span_end),
         }
     }
 }
 
 impl TrackStatement {
     /// Returns the source byte range `(span_start, span_end)` for this statement.
-    /// Comments have no span information and return `(usize::MAX, usize::MAX)`.
     pub fn span(&self) -> (usize, usize) {
         match self {
             TrackStatement::NoteEvent { span_start, span_end, .. }
             | TrackStatement::Chord { span_start, span_end, .. }
             | TrackStatement::Rest { span_start, span_end, .. }
+            | TrackStatement::BarRest { span_start, span_end, .. }
             | TrackStatement::Assignment { span_start, span_end, .. }
+            | TrackStatement::Let { span_start, span_end, .. }
             | TrackStatement::ForLoop { span_start, span_end, .. }
-            | TrackStatement::TrackCall { span_start, span_end, .. } => (*span_start, *span_end),
-            TrackStatement::Comment(_) => (usize::MAX, usize::MAX),
+            | TrackStatement::Poly { span_start, span_end, .. }
+            | TrackStatement::Maybe { span_start, span_end, .. }
+            | TrackStatement::Marker { span_start, span_end, .. }
+            | TrackStatement::TrackCall { span_start, span_end, .. }
+            | TrackStatement::Comment { span_start, span_end, .. } => (*span_start, *span_end),
         }
     }
 }
@@ -1,12 +1,24 @@
-use std::collections::HashMap;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+#[cfg(not(feature = "std"))]
+use hashbrown::HashMap;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
 
 use crate::ast::*;
+use crate::dsp::tremolo::{AutoPanConfig, LfoShape, TremoloConfig};
+use crate::pitch::{midi_to_note_name, note_to_midi};
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
 
 // ── Song End Mode ───────────────────────────────────────────
 
 /// Controls how the engine determines the total output length.
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum EndMode {
     /// Hard cut when the last note's gate ends (note-off).
     Gate,
@@ -22,6 +34,140 @@ impl Default for EndMode {
     }
 }
 
+impl core::str::FromStr for EndMode {
+    type Err = String;
+
+    /// Parse `gate`, `release` or `tail`, as written in `song.endMode`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "gate" => Ok(EndMode::Gate),
+            "release" => Ok(EndMode::Release),
+            "tail" => Ok(EndMode::Tail),
+            _ => Err(format!("Unknown end mode '{s}'. Expected 'gate', 'release', or 'tail'.")),
+        }
+    }
+}
+
+// ── Time Signature ──────────────────────────────────────────
+
+/// A time signature such as 4/4 or 6/8, set with `track.timeSignature = 6/8`.
+///
+/// The beat is a quarter note, as in `track.beatsPerMinute`, so a 4/4 bar
+/// lasts 4 beats and a 6/8 bar lasts 3 beats.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TimeSignature {
+    /// Number of units per bar.
+    pub numerator: u32,
+    /// Note value of one unit (4 = quarter note, 8 = eighth note).
+    pub denominator: u32,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        TimeSignature {
+            numerator: 4,
+            denominator: 4,
+        }
+    }
+}
+
+impl core::fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+impl TimeSignature {
+    /// Create a time signature. The numerator must be 1–64 and the
+    /// denominator a power of two from 1 to 64.
+    pub fn new(numerator: u32, denominator: u32) -> Option<Self> {
+        let valid = (1..=64).contains(&numerator)
+            && denominator.is_power_of_two()
+            && denominator <= 64;
+        valid.then_some(TimeSignature {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// Parse "6/8"-style text.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (n, d) = text.trim().split_once('/')?;
+        Self::new(n.trim().parse().ok()?, d.trim().parse().ok()?)
+    }
+
+    /// Length of one unit (1/denominator note) in beats.
+    pub fn unit_beats(&self) -> f64 {
+        4.0 / self.denominator as f64
+    }
+
+    /// Length of one bar in beats.
+    pub fn beats_per_bar(&self) -> f64 {
+        self.numerator as f64 * self.unit_beats()
+    }
+
+    /// Compound meters (6/8, 9/8, 12/8, ...) group units in threes.
+    pub fn is_compound(&self) -> bool {
+        self.denominator >= 8 && self.numerator > 3 && self.numerator.is_multiple_of(3)
+    }
+
+    /// Length of the felt pulse in beats: a dotted unit in compound
+    /// meters, otherwise one unit.
+    pub fn pulse_beats(&self) -> f64 {
+        if self.is_compound() {
+            3.0 * self.unit_beats()
+        } else {
+            self.unit_beats()
+        }
+    }
+
+    /// Metronome accents for each unit of a bar: 2 on the downbeat, 1 at
+    /// the start of each secondary group, 0 elsewhere.
+    pub fn accent_pattern(&self) -> Vec<u8> {
+        let n = self.numerator as usize;
+        let groups: Vec<usize> = if self.is_compound() {
+            vec![3; n / 3]
+        } else {
+            match n {
+                4 => vec![2, 2],
+                5 => vec![3, 2],
+                7 => vec![2, 2, 3],
+                _ => vec![n],
+            }
+        };
+        let mut accents = vec![0; n];
+        let mut pos = 0;
+        for (i, len) in groups.iter().enumerate() {
+            accents[pos] = if i == 0 { 2 } else { 1 };
+            pos += len;
+        }
+        accents
+    }
+}
+
+/// A position expressed in bars.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BarPosition {
+    /// Bar number, starting at 1.
+    pub bar: u32,
+    /// Offset into the bar, in beats.
+    pub beat_in_bar: f64,
+    /// The time signature in effect.
+    pub time_signature: TimeSignature,
+}
+
+/// A metronome click.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MetronomeClick {
+    /// Beat position of the click.
+    pub time: f64,
+    /// Accent level from `TimeSignature::accent_pattern`.
+    pub accent: u8,
+}
+
 // ── Instrument Configuration ────────────────────────────────
 
 /// Built-in instrument configuration resolved at compile time.
@@ -29,57 +175,695 @@ impl Default for EndMode {
 /// Tracks are independent units — they receive instruments through parameters
 /// or inherit the parent track's instrument. The song context is passed
 /// implicitly, so `const` values at song level are accessible.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct InstrumentConfig {
-    /// Waveform type: "sine", "square", "sawtooth", "triangle".
-    pub waveform: String,
-    /// ADSR envelope attack time in seconds (None = use engine default).
+///
+/// Serialized with a `kind` tag (`oscillator`, `sampler-ref`, `composite`
+/// or `fm`). The older flat shape (`{waveform, attack, ..., preset_ref}`)
+/// is still accepted when deserializing.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum InstrumentConfig {
+    /// Built-in oscillator: `Oscillator({type: 'square'})`.
+    Oscillator(OscillatorConfig),
+    /// Registered preset (sampler or composite): `loadPreset("name")`.
+    SamplerRef(SamplerRefConfig),
+    /// Inline composite: `Layer(...)` or `Split(...)`.
+    Composite(CompositeConfig),
+    /// Two-operator FM synth: `FM({ratio: 2, index: 3})`.
+    Fm(FmConfig),
+}
+
+impl Default for InstrumentConfig {
+    fn default() -> Self {
+        InstrumentConfig::Oscillator(OscillatorConfig::default())
+    }
+}
+
+impl InstrumentConfig {
+    /// The preset this instrument plays, if it is a preset reference.
+    pub fn preset_ref(&self) -> Option<&str> {
+        match self {
+            InstrumentConfig::SamplerRef(preset) => Some(&preset.name),
+            _ => None,
+        }
+    }
+
+    /// Preset names referenced by this instrument and its composite
+    /// children.
+    pub fn preset_refs(&self) -> Vec<String> {
+        match self {
+            InstrumentConfig::SamplerRef(preset) => vec![preset.name.clone()],
+            InstrumentConfig::Composite(composite) => {
+                composite.children.iter().flat_map(|c| c.preset_refs()).collect()
+            }
+            InstrumentConfig::Oscillator(_) | InstrumentConfig::Fm(_) => Vec::new(),
+        }
+    }
+
+    /// One-line description for display, e.g. `square oscillator` or
+    /// `preset Piano`.
+    pub fn summary(&self) -> String {
+        match self {
+            InstrumentConfig::Oscillator(osc) => format!("{} oscillator", osc.waveform),
+            InstrumentConfig::SamplerRef(preset) => format!("preset {}", preset.name),
+            InstrumentConfig::Fm(fm) => format!("FM (ratio {}, index {})", fm.ratio, fm.index),
+            InstrumentConfig::Composite(composite) => {
+                let mode = match composite.mode {
+                    CompositeKind::Layer => "Layer",
+                    CompositeKind::Split => "Split",
+                };
+                format!("{mode} of {}", composite.children.len())
+            }
+        }
+    }
+
+    /// Envelope release time in seconds, if the instrument sets one.
+    pub fn release(&self) -> Option<f64> {
+        match self {
+            InstrumentConfig::Oscillator(osc) => osc.envelope.release,
+            InstrumentConfig::Fm(fm) => fm.envelope.release,
+            InstrumentConfig::SamplerRef(preset) => preset.envelope.release,
+            InstrumentConfig::Composite(_) => None,
+        }
+    }
+
+    /// Velocity response, if the instrument has one.
+    pub fn velocity(&self) -> Option<&VelocityConfig> {
+        match self {
+            InstrumentConfig::Oscillator(osc) => Some(&osc.velocity),
+            InstrumentConfig::Fm(fm) => Some(&fm.velocity),
+            InstrumentConfig::SamplerRef(preset) => Some(&preset.velocity),
+            InstrumentConfig::Composite(_) => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for InstrumentConfig {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(tag = "kind", rename_all = "kebab-case")]
+        enum Tagged {
+            Oscillator(OscillatorConfig),
+            SamplerRef(SamplerRefConfig),
+            Composite(CompositeConfig),
+            Fm(FmConfig),
+        }
+
+        /// The tagged form, keeping its error so that an instrument with
+        /// a `kind` reports what is wrong with it.
+        struct TaggedResult(Result<Tagged, String>);
+
+        impl<'de> Deserialize<'de> for TaggedResult {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Ok(TaggedResult(Tagged::deserialize(deserializer).map_err(|e| e.to_string())))
+            }
+        }
+
+        // The legacy form only matches when there is no `kind`
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Legacy(LegacyInstrumentConfig),
+            Tagged(TaggedResult),
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Legacy(legacy) => legacy.into(),
+            Repr::Tagged(TaggedResult(Err(message))) => return Err(serde::de::Error::custom(message)),
+            Repr::Tagged(TaggedResult(Ok(tagged))) => match tagged {
+                Tagged::Oscillator(c) => InstrumentConfig::Oscillator(c),
+                Tagged::SamplerRef(c) => InstrumentConfig::SamplerRef(c),
+                Tagged::Composite(c) => InstrumentConfig::Composite(c),
+                Tagged::Fm(c) => InstrumentConfig::Fm(c),
+            },
+        })
+    }
+}
+
+/// The flat instrument shape used before instruments were tagged.
+#[derive(Deserialize)]
+struct LegacyInstrumentConfig {
+    waveform: String,
+    #[serde(flatten)]
+    envelope: EnvelopeConfig,
+    #[serde(default)]
+    detune: Option<f64>,
+    #[serde(default)]
+    mixer: Option<f64>,
+    #[serde(default)]
+    preset_ref: Option<String>,
+    #[serde(default, rename = "kind")]
+    _kind: NoKind,
+}
+
+/// Fails whenever a `kind` field is present, so tagged instruments are
+/// never read as the legacy shape.
+#[derive(Default)]
+struct NoKind;
+
+impl<'de> Deserialize<'de> for NoKind {
+    fn deserialize<D: serde::Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(serde::de::Error::custom("legacy instruments have no kind"))
+    }
+}
+
+impl From<LegacyInstrumentConfig> for InstrumentConfig {
+    fn from(legacy: LegacyInstrumentConfig) -> Self {
+        match legacy.preset_ref {
+            Some(name) if name != "Oscillator" => {
+                InstrumentConfig::SamplerRef(SamplerRefConfig { name, ..Default::default() })
+            }
+            _ => InstrumentConfig::Oscillator(OscillatorConfig {
+                waveform: legacy.waveform,
+                envelope: legacy.envelope,
+                velocity: VelocityConfig::default(),
+                detune: legacy.detune,
+                mixer: legacy.mixer,
+            }),
+        }
+    }
+}
+
+/// ADSR envelope overrides, with an optional hold stage after the attack.
+/// Unset stages use the engine defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EnvelopeConfig {
+    /// Attack time in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub attack: Option<f64>,
-    /// ADSR envelope decay time in seconds.
+    /// Time in seconds held at full level between the attack and decay.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold: Option<f64>,
+    /// Decay time in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub decay: Option<f64>,
-    /// ADSR envelope sustain level [0, 1].
+    /// Sustain level [0, 1].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sustain: Option<f64>,
-    /// ADSR envelope release time in seconds.
+    /// Release time in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub release: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "attackCurve")]
+    pub attack_curve: Option<EnvelopeCurve>,
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "decayCurve")]
+    pub decay_curve: Option<EnvelopeCurve>,
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "releaseCurve")]
+    pub release_curve: Option<EnvelopeCurve>,
+}
+
+/// Shape of an envelope stage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum EnvelopeCurve {
+    #[default]
+    Linear,
+    /// Rises slowly then quickly; falls quickly then tails off, like a
+    /// natural decay.
+    Exp,
+    /// Rises quickly then levels off; falls slowly then quickly.
+    Log,
+}
+
+impl EnvelopeCurve {
+    /// The curve named `name` (`'linear'`, `'exp'` or `'log'`).
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(EnvelopeCurve::Linear),
+            "exp" => Some(EnvelopeCurve::Exp),
+            "log" => Some(EnvelopeCurve::Log),
+            _ => None,
+        }
+    }
+}
+
+/// How note velocity drives an instrument's level and brightness.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct VelocityConfig {
+    /// Velocity response; linear when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "velocityCurve")]
+    pub curve: Option<VelocityCurve>,
+    /// How much softer notes are darkened by a lowpass filter, from 0
+    /// (not at all) to 1 (the softest notes at the darkest brightness).
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "velocityFilter")]
+    pub filter: Option<f64>,
+}
+
+impl VelocityConfig {
+    /// Note level for a velocity from 0 to 1.
+    pub fn level(&self, velocity: f64) -> f64 {
+        match &self.curve {
+            Some(curve) => curve.apply(velocity),
+            None => velocity,
+        }
+    }
+
+    /// Brightness (0 to 1) a note of `velocity` plays at, if the
+    /// instrument darkens soft notes.
+    pub fn brightness(&self, velocity: f64) -> Option<f64> {
+        let depth = self.filter.filter(|d| *d > 0.0)?.min(1.0);
+        Some(1.0 - depth * (1.0 - self.level(velocity)))
+    }
+}
+
+/// Mapping from note velocity to level, both from 0 to 1.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    /// Light touch: soft notes come out louder than linear.
+    Soft,
+    /// Heavy touch: soft notes come out quieter than linear.
+    Hard,
+    /// Levels at evenly spaced velocities from 0 to 1, interpolated
+    /// linearly, e.g. `[0, 0.5, 0.8, 1]`.
+    Table(Vec<f64>),
+}
+
+impl VelocityCurve {
+    /// The curve named `name` (`'linear'`, `'soft'` or `'hard'`).
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(VelocityCurve::Linear),
+            "soft" => Some(VelocityCurve::Soft),
+            "hard" => Some(VelocityCurve::Hard),
+            _ => None,
+        }
+    }
+
+    /// Level for a velocity from 0 to 1.
+    pub fn apply(&self, velocity: f64) -> f64 {
+        let velocity = velocity.clamp(0.0, 1.0);
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Soft => velocity.sqrt(),
+            VelocityCurve::Hard => velocity * velocity,
+            VelocityCurve::Table(levels) => match levels.as_slice() {
+                [] => velocity,
+                [level] => level.clamp(0.0, 1.0),
+                levels => {
+                    let position = velocity * (levels.len() - 1) as f64;
+                    let i = (position as usize).min(levels.len() - 2);
+                    let frac = position - i as f64;
+                    (levels[i] + (levels[i + 1] - levels[i]) * frac).clamp(0.0, 1.0)
+                }
+            },
+        }
+    }
+}
+
+/// A built-in oscillator instrument.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OscillatorConfig {
+    /// Waveform type: "sine", "square", "sawtooth", "triangle".
+    pub waveform: String,
+    #[serde(flatten)]
+    pub envelope: EnvelopeConfig,
+    #[serde(flatten)]
+    pub velocity: VelocityConfig,
     /// Detune in cents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub detune: Option<f64>,
     /// Mix level [0, 1].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub mixer: Option<f64>,
-    /// Preset reference name (from `loadPreset("name")`).
-    /// Used for compile-time extraction and runtime preloading.
-    pub preset_ref: Option<String>,
 }
 
-impl Default for InstrumentConfig {
+impl Default for OscillatorConfig {
     fn default() -> Self {
-        InstrumentConfig {
+        OscillatorConfig {
             waveform: "triangle".to_string(),
-            attack: None,
-            decay: None,
-            sustain: None,
-            release: None,
+            envelope: EnvelopeConfig::default(),
+            velocity: VelocityConfig::default(),
             detune: None,
             mixer: None,
-            preset_ref: None,
         }
     }
 }
 
+/// A reference to a registered preset (from `loadPreset("name")`).
+/// Used for compile-time extraction and runtime preloading.
+///
+/// The overrides (`loadPreset("name", {gain: 0.5, transpose: 12})`) are
+/// applied when the engine starts each voice, so tracks can play the same
+/// registered preset with different settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SamplerRefConfig {
+    /// Preset name.
+    pub name: String,
+    /// Level multiplier applied on top of the note velocity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f64>,
+    /// Transposition in semitones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transpose: Option<f64>,
+    #[serde(flatten)]
+    pub envelope: EnvelopeConfig,
+    #[serde(flatten)]
+    pub velocity: VelocityConfig,
+}
+
+/// Prefix of preset names that refer to a General MIDI program rather than
+/// a specific preset, e.g. `"gm:25"`.
+pub const GM_PRESET_PREFIX: &str = "gm:";
+
+/// The preset name for General MIDI program `program` (0-127).
+pub fn gm_preset_name(program: u8) -> String {
+    format!("{GM_PRESET_PREFIX}{program}")
+}
+
+/// The General MIDI program a `"gm:N"` preset name refers to, if any.
+pub fn gm_program_of(name: &str) -> Option<u8> {
+    name.strip_prefix(GM_PRESET_PREFIX)?.parse().ok().filter(|p| *p < 128)
+}
+
+/// A two-operator FM instrument: a sine carrier phase-modulated by a sine
+/// modulator.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FmConfig {
+    /// Modulator frequency as a multiple of the carrier frequency.
+    pub ratio: f64,
+    /// Modulation index (peak phase deviation in radians).
+    pub index: f64,
+    #[serde(flatten)]
+    pub envelope: EnvelopeConfig,
+    #[serde(flatten)]
+    pub velocity: VelocityConfig,
+    /// Detune in cents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detune: Option<f64>,
+}
+
+impl Default for FmConfig {
+    fn default() -> Self {
+        FmConfig {
+            ratio: 1.0,
+            index: 1.0,
+            envelope: EnvelopeConfig::default(),
+            velocity: VelocityConfig::default(),
+            detune: None,
+        }
+    }
+}
+
+/// How an inline composite combines its children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum CompositeKind {
+    /// All children play every note.
+    Layer,
+    /// Each note plays the child covering its key range.
+    Split,
+}
+
+/// An inline composite instrument, written
+/// `Layer([Oscillator({type: 'saw'}), loadPreset("Strings")], [0.6, 0.4])`
+/// or `Split([bass, lead], [C4])` (also `Split({below: bass, at: C4, above: lead})`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CompositeConfig {
+    pub mode: CompositeKind,
+    pub children: Vec<InstrumentConfig>,
+    /// Per-child levels (Layer).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mix_levels: Option<Vec<f64>>,
+    /// MIDI notes where each child after the first takes over (Split).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_points: Option<Vec<u8>>,
+}
+
 // ── Event List (Compiler Output) ────────────────────────────
 
 /// The compiled output: a flat list of timed events.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Deserialization also accepts the older form where each note embeds
+/// its instrument, interning those into `instruments`.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct EventList {
     /// All events sorted by time.
     pub events: Vec<Event>,
+    /// Each distinct instrument played, once. Notes refer to these by
+    /// index.
+    #[serde(default)]
+    pub instruments: Vec<InstrumentConfig>,
     /// Total duration of the song in beats (cursor position at end).
     pub total_beats: f64,
     /// How the engine should determine the end of the audio.
     pub end_mode: EndMode,
+    /// Count-in played before beat 0 (`song.countIn = 1`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count_in: Option<CountIn>,
+    /// Fade applied to the start of the render (`song.fadeIn`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_in: Option<FadeLength>,
+    /// Fade applied to the end of the render (`song.fadeOut`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_out: Option<FadeLength>,
+    /// Length in beats of the pickup before bar 1 (`song.anacrusis = 1`).
+    /// The pickup plays from beat 0 and is numbered bar 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anacrusis: Option<f64>,
+}
+
+impl EventList {
+    /// Index of `instrument` in `instruments`, adding it if it is new.
+    pub fn intern_instrument(&mut self, instrument: &InstrumentConfig) -> usize {
+        intern_instrument(&mut self.instruments, instrument)
+    }
+}
+
+fn intern_instrument(instruments: &mut Vec<InstrumentConfig>, instrument: &InstrumentConfig) -> usize {
+    match instruments.iter().position(|i| i == instrument) {
+        Some(index) => index,
+        None => {
+            instruments.push(instrument.clone());
+            instruments.len() - 1
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for EventList {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// A note with its instrument embedded, as written before the
+        /// instrument table.
+        #[derive(Deserialize)]
+        enum LegacyKind {
+            Note {
+                pitch: String,
+                velocity: f64,
+                gate: f64,
+                instrument: InstrumentConfig,
+                source_start: usize,
+                source_end: usize,
+                #[serde(default)]
+                glide_from: Option<String>,
+                #[serde(default)]
+                slide_to: Option<String>,
+                #[serde(default)]
+                expression: Box<NoteExpression>,
+            },
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum KindRepr {
+            Current(EventKind),
+            Legacy(LegacyKind),
+        }
+
+        #[derive(Deserialize)]
+        struct EventRepr {
+            time: f64,
+            kind: KindRepr,
+            track_name: Option<String>,
+        }
+
+        #[derive(Deserialize)]
+        struct Repr {
+            events: Vec<EventRepr>,
+            #[serde(default)]
+            instruments: Vec<InstrumentConfig>,
+            total_beats: f64,
+            end_mode: EndMode,
+            #[serde(default)]
+            count_in: Option<CountIn>,
+            #[serde(default)]
+            fade_in: Option<FadeLength>,
+            #[serde(default)]
+            fade_out: Option<FadeLength>,
+            #[serde(default)]
+            anacrusis: Option<f64>,
+        }
+
+        let repr = Repr::deserialize(deserializer)?;
+        let mut list = EventList {
+            events: Vec::with_capacity(repr.events.len()),
+            instruments: repr.instruments,
+            total_beats: repr.total_beats,
+            end_mode: repr.end_mode,
+            count_in: repr.count_in,
+            fade_in: repr.fade_in,
+            fade_out: repr.fade_out,
+            anacrusis: repr.anacrusis,
+        };
+        for event in repr.events {
+            let kind = match event.kind {
+                KindRepr::Current(kind) => kind,
+                KindRepr::Legacy(LegacyKind::Note {
+                    pitch,
+                    velocity,
+                    gate,
+                    instrument,
+                    source_start,
+                    source_end,
+                    glide_from,
+                    slide_to,
+                    expression,
+                }) => EventKind::Note {
+                    pitch,
+                    velocity,
+                    gate,
+                    instrument: list.intern_instrument(&instrument),
+                    source_start,
+                    source_end,
+                    glide_from,
+                    slide_to,
+                    expression,
+                    sends: Sends::default(),
+                    modulation: Modulation::default(),
+                },
+            };
+            if let EventKind::Note { instrument, .. } = &kind
+                && *instrument >= list.instruments.len()
+            {
+                return Err(serde::de::Error::custom(format!(
+                    "Note at beat {} plays instrument {instrument}, but there are only {}.",
+                    event.time,
+                    list.instruments.len()
+                )));
+            }
+            list.events.push(Event { time: event.time, kind, track_name: event.track_name });
+        }
+        Ok(list)
+    }
+}
+
+/// Length of a song fade: a number of beats (`song.fadeOut = 4`) or of
+/// seconds (`song.fadeOut = '2.5s'`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum FadeLength {
+    Beats(f64),
+    Seconds(f64),
+}
+
+impl FadeLength {
+    /// Length in seconds, given the tempo over the faded region.
+    pub fn seconds(&self, bpm: f64) -> f64 {
+        match *self {
+            FadeLength::Beats(b) => b * 60.0 / bpm,
+            FadeLength::Seconds(s) => s,
+        }
+    }
+}
+
+/// Octave numbering of note names in source (`song.middleC`). Events
+/// always use scientific pitch, where middle C (MIDI 60) is C4.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum MiddleC {
+    /// Scientific pitch: middle C is C4.
+    #[default]
+    C4,
+    /// Yamaha / tracker convention: middle C is C3.
+    C3,
+}
+
+impl MiddleC {
+    /// Convert a source note name to scientific pitch. Names that are not
+    /// notes are returned unchanged.
+    pub fn to_scientific(self, pitch: &str) -> String {
+        let offset = match self {
+            MiddleC::C4 => return pitch.to_string(),
+            MiddleC::C3 => 1,
+        };
+        if note_to_midi(pitch).is_none() {
+            return pitch.to_string();
+        }
+        // The octave follows the letter and an optional accidental
+        let split = if matches!(pitch.as_bytes().get(1), Some(b'#' | b'b')) { 2 } else { 1 };
+        let (name, octave) = pitch.split_at(split);
+        match octave.parse::<i32>() {
+            Ok(octave) => format!("{name}{}", octave + offset),
+            Err(_) => pitch.to_string(),
+        }
+    }
+}
+
+/// How note velocities are written in source (`song.velocityScale`).
+/// Events always carry MIDI velocities (0–127); the engine divides by 127.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum VelocityScale {
+    /// `C4*100`: 0 to 127.
+    #[default]
+    Midi,
+    /// `C4*0.8`: 0 to 1.
+    Unit,
+}
+
+impl VelocityScale {
+    /// Loudest velocity that can be written on this scale.
+    pub fn max(self) -> f64 {
+        match self {
+            VelocityScale::Midi => 127.0,
+            VelocityScale::Unit => 1.0,
+        }
+    }
+
+    /// Convert a velocity written on this scale to MIDI units, rejecting
+    /// values outside the scale.
+    pub fn to_midi(self, velocity: f64) -> Result<f64, String> {
+        if !(0.0..=self.max()).contains(&velocity) {
+            let hint = match self {
+                VelocityScale::Midi => "",
+                VelocityScale::Unit => " (song.velocityScale is 'unit')",
+            };
+            return Err(format!("Velocity {velocity} is out of range. Expected 0 to {}{hint}.", self.max()));
+        }
+        Ok(velocity * 127.0 / self.max())
+    }
+}
+
+/// A count-in of metronome clicks rendered before beat 0.
+///
+/// The rendered audio starts `seconds` before the song, so the editor
+/// should offset its playhead by that amount.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CountIn {
+    /// Number of bars of clicks.
+    pub bars: u32,
+    /// Length of the pre-roll in beats.
+    pub beats: f64,
+    /// Length of the pre-roll in seconds, at the tempo of beat 0.
+    pub seconds: f64,
 }
 
 /// A single scheduled event.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Event {
     /// When this event fires, in beats from the start.
     pub time: f64,
@@ -88,7 +872,41 @@ pub struct Event {
     pub track_name: Option<String>,
 }
 
+/// Levels a note sends to the shared effect buses, from 0 to 1
+/// (`track.sends = {reverb: 0.3}`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct Sends {
+    pub reverb: f64,
+    pub delay: f64,
+}
+
+impl Sends {
+    pub fn is_empty(&self) -> bool {
+        *self == Sends::default()
+    }
+}
+
+/// LFO effects on a track's notes (`track.tremolo = {sync: 1/4}`,
+/// `track.autoPan = {rate: 0.5}`). The engine runs them on each voice
+/// from the song's clock, so all the notes of a track move together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
+pub struct Modulation {
+    pub tremolo: Option<TremoloConfig>,
+    pub auto_pan: Option<AutoPanConfig>,
+}
+
+impl Modulation {
+    pub fn is_empty(&self) -> bool {
+        *self == Modulation::default()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum EventKind {
     /// Play a note.
     Note {
@@ -96,12 +914,29 @@ pub enum EventKind {
         velocity: f64,
         /// Audible gate time in beats (how long the note sounds).
         gate: f64,
-        /// Instrument configuration for this note.
-        instrument: InstrumentConfig,
+        /// Index of the note's instrument in `EventList::instruments`.
+        instrument: usize,
         /// Source byte offset (for editor highlighting).
         source_start: usize,
         /// Source byte end offset.
         source_end: usize,
+        /// Pitch of a held voice on the same track to glide from instead
+        /// of starting a new voice (set by `track.voiceLeading`).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        glide_from: Option<String>,
+        /// Pitch the note slides to over its gate (`C4->G4`).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        slide_to: Option<String>,
+        /// Per-note pan, timbre and pitch modifiers.
+        #[serde(default, skip_serializing_if = "NoteExpression::is_empty")]
+        expression: Box<NoteExpression>,
+        /// Levels sent to the shared effect buses (`track.sends`).
+        #[serde(default, skip_serializing_if = "Sends::is_empty")]
+        sends: Sends,
+        /// Tremolo and auto-pan on the note's track (`track.tremolo`,
+        /// `track.autoPan`).
+        #[serde(default, skip_serializing_if = "Modulation::is_empty")]
+        modulation: Modulation,
     },
     /// Start a sub-track.
     TrackStart {
@@ -109,11 +944,30 @@ pub enum EventKind {
         velocity: Option<f64>,
         play_duration: Option<f64>,
         args: Vec<String>,
+        /// Source byte offsets of the call.
+        #[serde(default)]
+        source_start: usize,
+        #[serde(default)]
+        source_end: usize,
     },
     /// Set a property.
-    SetProperty { target: String, value: String },
+    SetProperty {
+        target: String,
+        value: String,
+        /// Source byte offsets of the assignment.
+        #[serde(default)]
+        source_start: usize,
+        #[serde(default)]
+        source_end: usize,
+    },
     /// Preset reference (for compile-time extraction / preloading).
     PresetRef { name: String },
+    /// Lyric syllable sung at this time (`C4 "sha-"`). A trailing `-`
+    /// joins the syllable to the next one.
+    Lyric { text: String },
+    /// Named position for the host to sync to (`marker "loop_start"`,
+    /// `cue "boss_enter"`).
+    Marker { kind: MarkerKind, name: String },
 }
 
 // ── Cursor Context ──────────────────────────────────────────
@@ -122,6 +976,7 @@ pub enum EventKind {
 /// Used by the editor to determine what instrument/BPM/etc. is active
 /// at the cursor, enabling features like piano keyboard preview.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct CursorContext {
     /// The instrument configuration active at the cursor.
     pub instrument: InstrumentConfig,
@@ -135,84 +990,593 @@ pub struct CursorContext {
     pub tuning_pitch: f64,
     /// Beat position at the cursor.
     pub cursor_beat: f64,
+    /// Time signature in effect at the cursor.
+    #[serde(default)]
+    pub time_signature: TimeSignature,
+    /// Bar number (from 1) containing the cursor beat.
+    #[serde(default)]
+    pub bar: u32,
+    /// Offset of the cursor beat into its bar, in beats.
+    #[serde(default)]
+    pub beat_in_bar: f64,
+    /// Octave numbering of note names in effect (`song.middleC`).
+    #[serde(default)]
+    pub middle_c: MiddleC,
+    /// How velocities are written at the cursor (`song.velocityScale`).
+    #[serde(default)]
+    pub velocity_scale: VelocityScale,
+    /// Keys the active preset can play. The compiler has no preset data,
+    /// so this is filled in by the host from its registered presets.
+    #[serde(default)]
+    pub key_coverage: Option<KeyCoverage>,
 }
 
-// ── Compiler ────────────────────────────────────────────────
+/// The keys a preset can play, so a keyboard can grey out the rest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct KeyCoverage {
+    /// Lowest playable MIDI note.
+    pub low_note: u8,
+    /// Highest playable MIDI note.
+    pub high_note: u8,
+    /// Every playable MIDI note, ascending (drum kits often have gaps).
+    pub notes: Vec<u8>,
+    /// Whether the preset is a drum kit (one unpitched sound per key).
+    pub is_drum_kit: bool,
+}
 
-/// Compile context: tracks state during compilation.
-struct CompileCtx {
-    /// Default note length in beats (e.g., 1/4 = 0.25).
-    default_note_length: f64,
-    /// Song end mode.
-    end_mode: EndMode,
-    /// Current instrument configuration (default = Triangle).
-    current_instrument: InstrumentConfig,
-    /// Current cursor position in beats.
-    cursor: f64,
-    /// Maximum cursor position reached by any track (for total_beats).
-    /// Track calls are async (parallel) — they don't advance the caller's
-    /// cursor. This field captures the furthest beat any track reached.
-    max_cursor: f64,
-    /// Name of the track currently being compiled (None = top-level).
-    current_track_name: Option<String>,
-    /// Collected events.
-    events: Vec<Event>,
-    /// Track definitions available for lookup.
-    track_defs: Vec<TrackDef>,
-    /// Song-level const bindings: `const name = Oscillator({...})`.
-    consts: HashMap<String, InstrumentConfig>,
-    /// Active parameter bindings during track body compilation.
-    param_bindings: HashMap<String, InstrumentConfig>,
+// ── Compile Trace ───────────────────────────────────────────
+
+/// A compiler decision recorded by `compile_with_trace`, for debugging
+/// why a note ended up where it did.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TraceEntry {
+    /// The track being compiled (None = top-level).
+    pub track: Option<String>,
+    /// Cursor position in beats when the decision was made.
+    pub beat: f64,
+    pub step: TraceStep,
 }
 
-struct TrackDef {
-    name: String,
-    params: Vec<String>,
-    body: Vec<TrackStatement>,
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum TraceStep {
+    /// A track call was inlined; its body ran until `end_beat`.
+    TrackInlined { name: String, end_beat: f64 },
+    /// A call to an undefined track was left as a `TrackStart` event.
+    TrackDeferred { name: String },
+    /// `track.instrument` or a `const` resolved to an instrument.
+    InstrumentResolved { target: String, expression: String, instrument: InstrumentConfig },
+    /// A for-loop body was unrolled `iterations` times.
+    LoopUnrolled { iterations: u32 },
+    /// A note was placed at `time`, which differs from the cursor when
+    /// swing moved it.
+    NotePlaced { pitch: String, time: f64, gate: f64, source_start: usize, source_end: usize },
+    /// A track or song property was set.
+    PropertySet { target: String, value: String },
 }
 
-impl CompileCtx {
-    fn new(_strict: bool) -> Self {
+/// The result of `compile_with_trace`.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TracedCompile {
+    pub event_list: EventList,
+    pub trace: Vec<TraceEntry>,
+}
+
+// ── Language Version ────────────────────────────────────────
+
+/// Property aliases kept for version 1 songs, with their replacements.
+/// Version 2 rejects them.
+const DEPRECATED_PROPERTIES: [(&str, &str); 2] = [
+    ("track.duration", "track.noteLength"),
+    ("track.a4Frequency", "track.tuningPitch"),
+];
+
+/// A deprecated construct in a song.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Deprecation {
+    pub message: String,
+    pub span_start: usize,
+    pub span_end: usize,
+}
+
+/// The language version a song declares and what in it is deprecated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LanguageReport {
+    /// Version from `#version N`, or 1 without the pragma.
+    pub version: u32,
+    /// Deprecated constructs. Version 1 songs still compile with them.
+    pub warnings: Vec<Deprecation>,
+}
+
+/// Report the language version of `program` and the deprecated
+/// constructs it uses.
+pub fn language_report(program: &Program) -> LanguageReport {
+    fn check(target: &str, span_start: usize, span_end: usize, warnings: &mut Vec<Deprecation>) {
+        if let Some((_, replacement)) = DEPRECATED_PROPERTIES.iter().find(|(old, _)| *old == target) {
+            warnings.push(Deprecation {
+                message: format!("'{target}' is deprecated; use '{replacement}'."),
+                span_start,
+                span_end,
+            });
+        }
+    }
+    fn walk_body(body: &[TrackStatement], warnings: &mut Vec<Deprecation>) {
+        for stmt in body {
+            match stmt {
+                TrackStatement::Assignment { target, span_start, span_end, .. } => {
+                    check(target, *span_start, *span_end, warnings);
+                }
+                TrackStatement::ForLoop { body, .. }
+                | TrackStatement::Poly { body, .. }
+                | TrackStatement::Maybe { body, .. } => {
+                    walk_body(body, warnings)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for stmt in &program.statements {
+        match stmt {
+            Statement::Assignment { target, span_start, span_end, .. } => {
+                check(target, *span_start, *span_end, &mut warnings);
+            }
+            Statement::TrackDef { body, .. } => walk_body(body, &mut warnings),
+            _ => {}
+        }
+    }
+    LanguageReport { version: program.language_version, warnings }
+}
+
+// ── Compiler ────────────────────────────────────────────────
+
+/// Amounts applied by articulation marks (`track.accent`,
+/// `track.staccato`, `track.legato`).
+#[derive(Debug, Clone, Copy)]
+struct ArticulationSettings {
+    /// Velocity added by `!`, in MIDI units.
+    accent: f64,
+    /// Fraction of its written length a `'` note sounds for.
+    staccato: f64,
+    /// Fraction of its step a `_` note sounds for.
+    legato: f64,
+}
+
+impl Default for ArticulationSettings {
+    fn default() -> Self {
+        ArticulationSettings { accent: 20.0, staccato: 0.5, legato: 1.0 }
+    }
+}
+
+/// Compile context: tracks state during compilation.
+struct CompileCtx {
+    /// Default note length in beats (e.g., 1/4 = 0.25).
+    default_note_length: f64,
+    /// Velocity of notes and chords written without `*`, and the base of
+    /// relative accents (`C4*+10`), in MIDI units (`track.velocity`).
+    track_velocity: f64,
+    /// Amounts for `!`, `'` and `_` marks.
+    articulation: ArticulationSettings,
+    /// Song end mode.
+    end_mode: EndMode,
+    /// Bars of count-in requested with `song.countIn`.
+    count_in_bars: u32,
+    /// Seed for `choose` and `maybe` (`song.seed`).
+    seed: u64,
+    /// Random state per track, so edits to one track don't change the
+    /// choices made in another.
+    rng_streams: HashMap<String, u64>,
+    /// Song fades (`song.fadeIn`, `song.fadeOut`).
+    fade_in: Option<FadeLength>,
+    fade_out: Option<FadeLength>,
+    /// Pickup length in beats (`song.anacrusis`), 0 for none.
+    anacrusis: f64,
+    /// Octave numbering of note names (`song.middleC`).
+    middle_c: MiddleC,
+    /// How velocities are written (`song.velocityScale`).
+    velocity_scale: VelocityScale,
+    /// Current instrument configuration (default = Triangle).
+    current_instrument: InstrumentConfig,
+    /// Instruments played so far, indexed by note events.
+    instruments: Vec<InstrumentConfig>,
+    /// Current cursor position in beats.
+    cursor: f64,
+    /// Maximum cursor position reached by any track (for total_beats).
+    /// Track calls are async (parallel) — they don't advance the caller's
+    /// cursor. This field captures the furthest beat any track reached.
+    max_cursor: f64,
+    /// Name of the track currently being compiled (None = top-level).
+    current_track_name: Option<String>,
+    /// Collected events.
+    events: Vec<Event>,
+    /// Track definitions available for lookup.
+    track_defs: Vec<TrackDef>,
+    /// Song-level const bindings: `const name = Oscillator({...})`.
+    consts: HashMap<String, InstrumentConfig>,
+    /// Stored track calls: `const hook = riff(lead)`.
+    performances: HashMap<String, Performance>,
+    /// Active parameter bindings during track body compilation.
+    param_bindings: HashMap<String, InstrumentConfig>,
+    /// `let` variables in scope, innermost last. Later entries shadow
+    /// earlier ones of the same name.
+    variables: Vec<(String, Value)>,
+    /// Current time signature (song-wide from the point it is set).
+    time_signature: TimeSignature,
+    /// Beat at which the current time signature took effect.
+    time_signature_start: f64,
+    /// Swing ratio: where the off-beat lands within a pulse
+    /// (0.5 = straight, 2/3 = triplet swing). Track-scoped.
+    swing: f64,
+    /// Glide chord voices to the next chord (`track.voiceLeading`). Track-scoped.
+    voice_leading: bool,
+    /// Levels sent to the shared effect buses (`track.sends`). Track-scoped.
+    sends: Sends,
+    /// Tremolo and auto-pan (`track.tremolo`, `track.autoPan`). Track-scoped.
+    modulation: Modulation,
+    /// Pitches of the previous chord while voice leading.
+    last_chord: Option<Vec<String>>,
+    /// Recorded decisions, when tracing.
+    trace: Option<Vec<TraceEntry>>,
+    /// Language version of the program (`#version`).
+    language_version: u32,
+}
+
+struct TrackDef {
+    name: String,
+    params: Vec<String>,
+    body: Vec<TrackStatement>,
+}
+
+/// A track call stored with `const name = track(args);` and played later
+/// with `play name`. Arguments are resolved when it plays.
+#[derive(Clone)]
+struct Performance {
+    track: String,
+    args: Vec<Expr>,
+}
+
+impl CompileCtx {
+    fn new(_strict: bool) -> Self {
         CompileCtx {
             default_note_length: 1.0, // default: 1 beat
+            track_velocity: 100.0,
+            articulation: ArticulationSettings::default(),
             end_mode: EndMode::Tail,
+            count_in_bars: 0,
+            seed: 0,
+            rng_streams: HashMap::new(),
+            fade_in: None,
+            fade_out: None,
+            anacrusis: 0.0,
+            middle_c: MiddleC::C4,
+            velocity_scale: VelocityScale::Midi,
             current_instrument: InstrumentConfig::default(),
+            instruments: Vec::new(),
             cursor: 0.0,
             max_cursor: 0.0,
             current_track_name: None,
             events: Vec::new(),
             track_defs: Vec::new(),
             consts: HashMap::new(),
+            performances: HashMap::new(),
             param_bindings: HashMap::new(),
+            variables: Vec::new(),
+            time_signature: TimeSignature::default(),
+            time_signature_start: 0.0,
+            swing: 0.5,
+            voice_leading: false,
+            sends: Sends::default(),
+            modulation: Modulation::default(),
+            last_chord: None,
+            trace: None,
+            language_version: 1,
         }
     }
 
+    /// Record a trace step at the cursor when tracing, returning its index.
+    fn trace(&mut self, step: impl FnOnce() -> TraceStep) -> Option<usize> {
+        self.trace.as_ref()?;
+        let entry = TraceEntry {
+            track: self.current_track_name.clone(),
+            beat: self.cursor,
+            step: step(),
+        };
+        let trace = self.trace.as_mut()?;
+        trace.push(entry);
+        Some(trace.len() - 1)
+    }
+
     fn emit(&mut self, kind: EventKind) {
+        self.emit_at(self.cursor, kind);
+    }
+
+    fn emit_at(&mut self, time: f64, kind: EventKind) {
         self.events.push(Event {
-            time: self.cursor,
+            time,
             kind,
             track_name: self.current_track_name.clone(),
         });
     }
 
-    fn resolve_duration(&self, dur: &Option<DurationExpr>) -> f64 {
+    /// The cursor with swing applied, for placing note onsets.
+    ///
+    /// Each pulse of the time signature is split in two, and the second
+    /// half is moved to `swing` of the way through the pulse. Compound
+    /// meters already have a ternary feel and are not swung.
+    fn swung_cursor(&self) -> f64 {
+        self.swung(self.cursor)
+    }
+
+    /// `beat` moved by swing (see `swung_cursor`).
+    fn swung(&self, beat: f64) -> f64 {
+        let rel = beat - self.time_signature_start;
+        if !self.swings() || rel < 0.0 {
+            return beat;
+        }
+        let pulse = self.time_signature.pulse_beats();
+        let pulse_start = (rel / pulse + 1e-9).floor() * pulse;
+        let phase = ((rel - pulse_start) / pulse).max(0.0);
+        let swung = if phase < 0.5 {
+            phase * 2.0 * self.swing
+        } else {
+            self.swing + (phase - 0.5) * 2.0 * (1.0 - self.swing)
+        };
+        self.time_signature_start + pulse_start + swung * pulse
+    }
+
+    /// The gate of a note placed at the swung `time`. Its end is swung
+    /// too, so a delayed off-beat does not run into the next on-beat;
+    /// swing never lengthens a gate.
+    fn swung_gate(&self, time: f64, gate: f64) -> f64 {
+        if !self.swings() {
+            return gate;
+        }
+        gate.min(self.swung(self.cursor + gate) - time)
+    }
+
+    /// Whether swing moves notes in the current time signature.
+    fn swings(&self) -> bool {
+        (self.swing - 0.5).abs() >= 1e-9 && !self.time_signature.is_compound()
+    }
+
+    /// Next random number in [0, 1) from the current track's stream.
+    fn random(&mut self) -> f64 {
+        let track = self.current_track_name.clone().unwrap_or_default();
+        let seed = self.seed;
+        let state = self.rng_streams.entry(track).or_insert_with_key(|track| stream_seed(seed, track));
+        *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn resolve_duration(&self, dur: &Option<DurationExpr>) -> Result<f64, String> {
         match dur {
-            Some(d) => duration_to_beats(d, self.default_note_length),
-            None => self.default_note_length,
+            Some(d) => self.duration_beats(d),
+            None => Ok(self.default_note_length),
+        }
+    }
+
+    /// Beats of a duration; dots scale the default note length. A
+    /// variable must hold a duration or a beat count.
+    fn duration_beats(&self, dur: &DurationExpr) -> Result<f64, String> {
+        Ok(match dur {
+            DurationExpr::Beats(n) => *n,
+            DurationExpr::Inverse(n) => 1.0 / n,
+            DurationExpr::Fraction(n, m) => n / m,
+            DurationExpr::Dots(count) => self.default_note_length * DurationExpr::dot_factor(*count),
+            DurationExpr::Dotted(base, dots) => self.duration_beats(base)? * DurationExpr::dot_factor(*dots),
+            DurationExpr::Variable(name) => match self.variable(name) {
+                Some(Value::Beats(beats) | Value::Number(beats)) if *beats >= 0.0 => *beats,
+                Some(value) => return Err(format!("Variable '{name}' is {value}, not a duration.")),
+                None => return Err(format!("Unknown variable '{name}' used as a duration.")),
+            },
+        })
+    }
+
+    /// MIDI velocity of a `*` modifier. Relative accents are taken from
+    /// `track.velocity` and clamped to 0–127.
+    fn resolve_velocity(&self, velocity: VelocityExpr) -> Result<f64, String> {
+        match velocity {
+            VelocityExpr::Absolute(v) => self.velocity_scale.to_midi(v),
+            VelocityExpr::Relative(offset) => {
+                let offset = offset * 127.0 / self.velocity_scale.max();
+                Ok((self.track_velocity + offset).clamp(0.0, 127.0))
+            }
+        }
+    }
+}
+
+// ── Variables ───────────────────────────────────────────────
+
+/// Most iterations a `for` loop may run, to catch loops that never end.
+const MAX_LOOP_ITERATIONS: u32 = 10_000;
+
+/// The value of a `let` variable.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Text(String),
+    /// A duration such as `1/4`, in beats.
+    Beats(f64),
+}
+
+impl Value {
+    fn from_bool(b: bool) -> Self {
+        Value::Number(if b { 1.0 } else { 0.0 })
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) | Value::Beats(n) => Some(*n),
+            Value::Text(_) => None,
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Number(n) | Value::Beats(n) => *n != 0.0,
+            Value::Text(s) => !s.is_empty(),
+        }
+    }
+
+    /// The value as a literal, for property assignments.
+    fn to_expr(&self) -> Expr {
+        match self {
+            Value::Number(n) | Value::Beats(n) => Expr::Number(*n),
+            Value::Text(s) => Expr::StringLit(s.clone()),
+        }
+    }
+}
+
+impl core::fmt::Display for Value {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Value::Number(n) | Value::Beats(n) => write!(f, "{n}"),
+            Value::Text(s) => write!(f, "'{s}'"),
+        }
+    }
+}
+
+fn apply_binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, String> {
+    match op {
+        BinaryOp::Or => return Ok(Value::from_bool(left.is_truthy() || right.is_truthy())),
+        BinaryOp::And => return Ok(Value::from_bool(left.is_truthy() && right.is_truthy())),
+        BinaryOp::Add if matches!((&left, &right), (Value::Text(_), _) | (_, Value::Text(_))) => {
+            let text = |v: Value| match v {
+                Value::Text(s) => s,
+                other => other.to_string(),
+            };
+            return Ok(Value::Text(text(left) + &text(right)));
         }
+        _ => {}
     }
+    let (Some(a), Some(b)) = (left.as_number(), right.as_number()) else {
+        return match op {
+            BinaryOp::Eq => Ok(Value::from_bool(left == right)),
+            BinaryOp::Ne => Ok(Value::from_bool(left != right)),
+            _ => Err(format!("Cannot apply '{}' to {left} and {right}.", op.symbol())),
+        };
+    };
+    let n = match op {
+        BinaryOp::Eq => return Ok(Value::from_bool(a == b)),
+        BinaryOp::Ne => return Ok(Value::from_bool(a != b)),
+        BinaryOp::Lt => return Ok(Value::from_bool(a < b)),
+        BinaryOp::Le => return Ok(Value::from_bool(a <= b)),
+        BinaryOp::Gt => return Ok(Value::from_bool(a > b)),
+        BinaryOp::Ge => return Ok(Value::from_bool(a >= b)),
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div if b == 0.0 => return Err(format!("Division by zero in {left} / {right}.")),
+        BinaryOp::Div => a / b,
+        BinaryOp::Or | BinaryOp::And => unreachable!("handled above"),
+    };
+    // Arithmetic on a duration stays a duration
+    Ok(if matches!(left, Value::Beats(_)) || matches!(right, Value::Beats(_)) {
+        Value::Beats(n)
+    } else {
+        Value::Number(n)
+    })
 }
 
-/// Convert a DurationExpr to a beat count.
-fn duration_to_beats(dur: &DurationExpr, default: f64) -> f64 {
-    match dur {
-        DurationExpr::Beats(n) => *n,
-        DurationExpr::Inverse(n) => 1.0 / n,
-        DurationExpr::Fraction(n, m) => n / m,
-        DurationExpr::Dots(count) => default * (*count as f64),
+impl CompileCtx {
+    fn variable(&self, name: &str) -> Option<&Value> {
+        self.variables.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// Evaluate an expression to a variable value. Identifiers that are
+    /// not variables stand for themselves, e.g. a note name.
+    fn evaluate(&self, expr: &Expr) -> Result<Value, String> {
+        Ok(match expr {
+            Expr::Number(n) => Value::Number(*n),
+            Expr::StringLit(s) => Value::Text(s.clone()),
+            Expr::DurationLit(d) => Value::Beats(self.duration_beats(d)?),
+            Expr::Identifier(name) => self.variable(name).cloned().unwrap_or_else(|| Value::Text(name.clone())),
+            Expr::Unary { op: UnaryOp::Not, operand } => Value::from_bool(!self.evaluate(operand)?.is_truthy()),
+            Expr::Unary { op: UnaryOp::Neg, operand } => match self.evaluate(operand)? {
+                Value::Number(n) => Value::Number(-n),
+                Value::Beats(b) => Value::Beats(-b),
+                text => return Err(format!("Cannot negate {text}.")),
+            },
+            Expr::Binary { op, left, right } => apply_binary(*op, self.evaluate(left)?, self.evaluate(right)?)?,
+            _ => return Err(format!("Cannot use '{}' as a value.", expr_to_string(expr))),
+        })
+    }
+
+    /// `expr` with variables and operators evaluated to a literal, or
+    /// None if it has neither.
+    fn resolve_expr(&self, expr: &Expr) -> Result<Option<Expr>, String> {
+        match expr {
+            Expr::Identifier(name) => Ok(self.variable(name).map(Value::to_expr)),
+            Expr::Binary { .. } | Expr::Unary { .. } => Ok(Some(self.evaluate(expr)?.to_expr())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Assign to an existing variable; false if `name` is not one.
+    fn assign_variable(&mut self, name: &str, value: &Expr) -> Result<bool, String> {
+        if self.variable(name).is_none() {
+            return Ok(false);
+        }
+        let value = self.evaluate(value)?;
+        if let Some((_, slot)) = self.variables.iter_mut().rev().find(|(n, _)| n == name) {
+            *slot = value;
+        }
+        Ok(true)
+    }
+
+    fn run_loop_clause(&mut self, clause: &LoopClause) -> Result<(), String> {
+        match clause {
+            LoopClause::Let { name, value } => {
+                let value = self.evaluate(value)?;
+                self.variables.push((name.clone(), value));
+            }
+            LoopClause::Assign { name, value } => {
+                if !self.assign_variable(name, value)? {
+                    return Err(format!("Unknown variable '{name}' in for loop. Declare it with 'let'."));
+                }
+            }
+            LoopClause::Step { name, delta } => {
+                let step = Expr::Binary {
+                    op: BinaryOp::Add,
+                    left: Box::new(Expr::Identifier(name.clone())),
+                    right: Box::new(Expr::Number(*delta)),
+                };
+                if !self.assign_variable(name, &step)? {
+                    return Err(format!("Unknown variable '{name}' in for loop. Declare it with 'let'."));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Scientific pitch of a written note, looking `let` variables up
+    /// first: a variable may hold a note name or a MIDI number.
+    fn note_pitch(&self, written: &str) -> Result<String, String> {
+        match self.variable(written) {
+            None => Ok(self.middle_c.to_scientific(written)),
+            Some(Value::Text(name)) => Ok(self.middle_c.to_scientific(name)),
+            Some(Value::Number(midi)) if midi.fract() == 0.0 && (0.0..128.0).contains(midi) => {
+                Ok(midi_to_note_name(*midi as i32, false))
+            }
+            Some(value) => Err(format!("Variable '{written}' is {value}, not a note name or MIDI number.")),
+        }
     }
 }
 
+/// Initial random state of a track's stream: the seed mixed with an
+/// FNV-1a hash of the track name.
+fn stream_seed(seed: u64, track: &str) -> u64 {
+    let hash = track.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+    hash ^ seed
+}
+
 fn expr_to_string(expr: &Expr) -> String {
     match expr {
         Expr::Identifier(s) => s.clone(),
@@ -224,6 +1588,103 @@ fn expr_to_string(expr: &Expr) -> String {
     }
 }
 
+/// Evaluate a numeric expression (`0.6` or `2/3`).
+fn expr_to_number(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Number(n) => Some(*n),
+        Expr::DurationLit(DurationExpr::Fraction(n, m)) => Some(n / m),
+        Expr::StringLit(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Read `{rate, sync, depth, shape}` settings of a track LFO effect over
+/// the defaults passed in. A depth of 0 turns the effect off.
+fn lfo_settings(
+    target: &str,
+    value: &Expr,
+    rate: &mut f64,
+    sync: &mut Option<f64>,
+    depth: &mut f64,
+    shape: &mut LfoShape,
+) -> Result<(), String> {
+    let Expr::ObjectLit(pairs) = value else {
+        return Err(format!(
+            "Invalid {target} '{}'. Expected settings, e.g. {{sync: 1/4, depth: 0.5}}.",
+            expr_to_string(value)
+        ));
+    };
+    for (key, setting) in pairs {
+        let number = expr_to_number(setting);
+        let invalid = |expected: &str| {
+            format!("Invalid {target} {key} '{}'. Expected {expected}.", expr_to_string(setting))
+        };
+        match key.as_str() {
+            "rate" => *rate = number.filter(|v| (0.01..=40.0).contains(v)).ok_or_else(|| invalid("0.01 to 40 Hz"))?,
+            "sync" => *sync = Some(number.filter(|v| *v > 0.0).ok_or_else(|| invalid("beats per cycle"))?),
+            "depth" => *depth = number.filter(|v| (0.0..=1.0).contains(v)).ok_or_else(|| invalid("0 to 1"))?,
+            "shape" => {
+                *shape = match setting {
+                    Expr::StringLit(s) | Expr::Identifier(s) => match s.as_str() {
+                        "sine" => LfoShape::Sine,
+                        "triangle" => LfoShape::Triangle,
+                        "square" => LfoShape::Square,
+                        _ => return Err(invalid("'sine', 'triangle' or 'square'")),
+                    },
+                    _ => return Err(invalid("'sine', 'triangle' or 'square'")),
+                }
+            }
+            _ => return Err(format!("Unknown {target} setting '{key}'. Expected rate, sync, depth or shape.")),
+        }
+    }
+    Ok(())
+}
+
+/// Check note expression values are in range.
+fn check_note_expression(expression: &NoteExpression) -> Result<(), String> {
+    if let Some(pan) = expression.pan
+        && !(-1.0..=1.0).contains(&pan)
+    {
+        return Err(format!("Note pan {pan} is out of range. Expected -1 to 1."));
+    }
+    if let Some(brightness) = expression.brightness
+        && !(0.0..=1.0).contains(&brightness)
+    {
+        return Err(format!(
+            "Note brightness {brightness} is out of range. Expected 0 to 1."
+        ));
+    }
+    if let Some(bend) = expression.bend
+        && !(-24.0..=24.0).contains(&bend)
+    {
+        return Err(format!("Note bend {bend} is out of range. Expected -24 to 24 semitones."));
+    }
+    Ok(())
+}
+
+/// Pair each pitch of `next` with the nearest unused pitch of `prev`,
+/// closest pairs first. Unpaired pitches start new voices (None).
+fn lead_voices(prev: &[String], next: &[String]) -> Vec<Option<String>> {
+    let mut pairs: Vec<(i32, usize, usize)> = Vec::new();
+    for (ni, n) in next.iter().enumerate() {
+        for (pi, p) in prev.iter().enumerate() {
+            if let (Some(a), Some(b)) = (note_to_midi(n), note_to_midi(p)) {
+                pairs.push(((a - b).abs(), ni, pi));
+            }
+        }
+    }
+    pairs.sort();
+    let mut result = vec![None; next.len()];
+    let mut used = vec![false; prev.len()];
+    for (_, ni, pi) in pairs {
+        if result[ni].is_none() && !used[pi] {
+            result[ni] = Some(prev[pi].clone());
+            used[pi] = true;
+        }
+    }
+    result
+}
+
 // ── Public API ──────────────────────────────────────────────
 
 /// Compile a parsed Program into a flat EventList.
@@ -231,17 +1692,35 @@ fn expr_to_string(expr: &Expr) -> String {
 /// Phase 1: Compiles a single-pass arrangement. Tracks are inlined,
 /// for-loops are unrolled, and the output is a flat timeline.
 pub fn compile(program: &Program) -> Result<EventList, String> {
-    compile_inner(program, false)
+    compile_inner(program, &mut CompileCtx::new(false))
 }
 
 /// Compile with strict validation (editor mode).
 /// Errors if a note is played before track.instrument is set.
 pub fn compile_strict(program: &Program) -> Result<EventList, String> {
-    compile_inner(program, true)
+    compile_inner(program, &mut CompileCtx::new(true))
+}
+
+/// Parse and compile `source`, recording each compiler decision (tracks
+/// inlined, instruments resolved, loops unrolled, notes placed).
+pub fn compile_with_trace(source: &str) -> Result<TracedCompile, String> {
+    let program = crate::parse(source).map_err(|e| e.to_string())?;
+    let mut ctx = CompileCtx::new(false);
+    ctx.trace = Some(Vec::new());
+    let event_list = compile_inner(&program, &mut ctx)?;
+    Ok(TracedCompile { event_list, trace: ctx.trace.unwrap_or_default() })
 }
 
-fn compile_inner(program: &Program, strict: bool) -> Result<EventList, String> {
-    let mut ctx = CompileCtx::new(strict);
+fn compile_inner(program: &Program, ctx: &mut CompileCtx) -> Result<EventList, String> {
+    // The parser checks `#version`; programs built or deserialized
+    // elsewhere are checked here.
+    if !(1..=LATEST_LANGUAGE_VERSION).contains(&program.language_version) {
+        return Err(format!(
+            "Unsupported language version {}; expected 1 to {LATEST_LANGUAGE_VERSION}.",
+            program.language_version
+        ));
+    }
+    ctx.language_version = program.language_version;
 
     // First pass: collect track definitions.
     for stmt in &program.statements {
@@ -256,15 +1735,41 @@ fn compile_inner(program: &Program, strict: bool) -> Result<EventList, String> {
 
     // Second pass: compile top-level statements.
     for stmt in &program.statements {
-        compile_statement(&mut ctx, stmt)?;
+        compile_statement(ctx, stmt)?;
     }
 
     ctx.events.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
 
+    if ctx.anacrusis > 0.0 {
+        let sig = bar_position(&time_signature_changes(&ctx.events), 0.0).time_signature;
+        if ctx.anacrusis >= sig.beats_per_bar() {
+            return Err(format!(
+                "song.anacrusis of {} beats must be shorter than the opening {sig} bar.",
+                ctx.anacrusis
+            ));
+        }
+    }
+
+    let count_in = (ctx.count_in_bars > 0).then(|| {
+        let sig = bar_position(&time_signature_changes(&ctx.events), 0.0).time_signature;
+        let bpm = TempoMap::from_events(&ctx.events, 120.0).bpm_at(0.0);
+        let beats = ctx.count_in_bars as f64 * sig.beats_per_bar();
+        CountIn {
+            bars: ctx.count_in_bars,
+            beats,
+            seconds: beats * 60.0 / bpm,
+        }
+    });
+
     Ok(EventList {
         total_beats: ctx.cursor.max(ctx.max_cursor),
-        events: ctx.events,
+        events: core::mem::take(&mut ctx.events),
+        instruments: core::mem::take(&mut ctx.instruments),
         end_mode: ctx.end_mode,
+        count_in,
+        fade_in: ctx.fade_in.take(),
+        fade_out: ctx.fade_out.take(),
+        anacrusis: (ctx.anacrusis > 0.0).then_some(ctx.anacrusis),
     })
 }
 
@@ -282,18 +1787,29 @@ fn compile_statement(ctx: &mut CompileCtx, stmt: &Statement) -> Result<(), Strin
             step,
             ..
         } => {
-            inline_track_call(ctx, name, velocity, play_duration, args, step)
+            inline_track_call(ctx, name, velocity, play_duration, args, step, stmt.span())
+        }
+        Statement::ConstDecl { name, value: Expr::FunctionCall { function, args }, .. }
+            if ctx.track_defs.iter().any(|td| td.name == *function) =>
+        {
+            // A track call stored to play later.
+            let performance = Performance { track: function.clone(), args: args.clone() };
+            ctx.performances.insert(name.clone(), performance);
+            Ok(())
         }
         Statement::ConstDecl { name, value, .. } => {
             // Resolve the expression to an InstrumentConfig and store it.
             let config = evaluate_instrument_expr(ctx, value)?;
-            // Emit a PresetRef event if this references an external preset.
-            if let Some(ref preset_name) = config.preset_ref {
+            ctx.trace(|| TraceStep::InstrumentResolved {
+                target: name.clone(),
+                expression: expr_to_string(value),
+                instrument: config.clone(),
+            });
+            // Emit PresetRef events for the external presets it references.
+            for preset_name in config.preset_refs() {
                 ctx.events.push(Event {
                     time: 0.0,
-                    kind: EventKind::PresetRef {
-                        name: preset_name.clone(),
-                    },
+                    kind: EventKind::PresetRef { name: preset_name },
                     track_name: ctx.current_track_name.clone(),
                 });
             }
@@ -301,9 +1817,28 @@ fn compile_statement(ctx: &mut CompileCtx, stmt: &Statement) -> Result<(), Strin
             Ok(())
         }
         Statement::Assignment { target, value, .. } => {
-            compile_assignment(ctx, target, value)
+            compile_assignment(ctx, target, value, stmt.span())
+        }
+        Statement::Marker { kind, name, .. } => {
+            ctx.emit(EventKind::Marker { kind: *kind, name: name.clone() });
+            Ok(())
+        }
+        Statement::Play { handle, at, .. } => {
+            let Some(performance) = ctx.performances.get(handle).cloned() else {
+                return Err(format!(
+                    "Unknown performance '{handle}'. Store one with 'const {handle} = track(...);'."
+                ));
+            };
+            // Like a track call, playing does not advance the cursor.
+            let saved_cursor = ctx.cursor;
+            if let Some(beat) = at {
+                ctx.cursor = *beat;
+            }
+            inline_track_call(ctx, &performance.track, &None, &None, &performance.args, &None, stmt.span())?;
+            ctx.cursor = saved_cursor;
+            Ok(())
         }
-        Statement::Comment(_) => Ok(()),
+        Statement::Comment { .. } => Ok(()),
     }
 }
 
@@ -312,113 +1847,38 @@ fn evaluate_instrument_expr(ctx: &CompileCtx, expr: &Expr) -> Result<InstrumentC
     match expr {
         Expr::FunctionCall { function, args } => {
             match function.as_str() {
-                "Oscillator" => {
-                    let mut config = InstrumentConfig::default();
-                    // First arg should be an ObjectLit with config keys.
-                    if let Some(Expr::ObjectLit(pairs)) = args.first() {
-                        for (key, value) in pairs {
-                            match key.as_str() {
-                                "type" => {
-                                    if let Expr::StringLit(s) = value {
-                                        config.waveform = s.clone();
-                                    }
-                                }
-                                "attack" => {
-                                    if let Expr::Number(n) = value {
-                                        config.attack = Some(*n);
-                                    }
-                                }
-                                "decay" => {
-                                    if let Expr::Number(n) = value {
-                                        config.decay = Some(*n);
-                                    }
-                                }
-                                "sustain" => {
-                                    if let Expr::Number(n) = value {
-                                        config.sustain = Some(*n);
-                                    }
-                                }
-                                "release" => {
-                                    if let Expr::Number(n) = value {
-                                        config.release = Some(*n);
-                                    }
-                                }
-                                "detune" => {
-                                    if let Expr::Number(n) = value {
-                                        config.detune = Some(*n);
-                                    }
-                                }
-                                "mixer" => {
-                                    if let Expr::Number(n) = value {
-                                        config.mixer = Some(*n);
-                                    }
-                                }
-                                _ => {} // ignore unknown keys
-                            }
-                        }
-                    }
-                    Ok(config)
-                }
+                "Oscillator" => Ok(InstrumentConfig::Oscillator(oscillator_config(args.first())?)),
+                "FM" => Ok(InstrumentConfig::Fm(fm_config(args.first())?)),
                 "loadPreset" => {
                     // loadPreset("name") — resolve preset by name.
-                    // Currently produces a default config; runtime preloading
-                    // uses extract_preset_refs() to discover references.
-                    let mut config = InstrumentConfig::default();
-                    if let Some(Expr::StringLit(preset_name)) = args.first() {
-                        config.preset_ref = Some(preset_name.clone());
-                        // If the preset name looks like an oscillator type, use it
-                        match preset_name.as_str() {
-                            "Oscillator" => {
-                                if let Some(Expr::ObjectLit(pairs)) = args.get(1) {
-                                    for (key, value) in pairs {
-                                        match key.as_str() {
-                                            "type" => {
-                                                if let Expr::StringLit(s) = value {
-                                                    config.waveform = s.clone();
-                                                }
-                                            }
-                                            "attack" => {
-                                                if let Expr::Number(n) = value {
-                                                    config.attack = Some(*n);
-                                                }
-                                            }
-                                            "decay" => {
-                                                if let Expr::Number(n) = value {
-                                                    config.decay = Some(*n);
-                                                }
-                                            }
-                                            "sustain" => {
-                                                if let Expr::Number(n) = value {
-                                                    config.sustain = Some(*n);
-                                                }
-                                            }
-                                            "release" => {
-                                                if let Expr::Number(n) = value {
-                                                    config.release = Some(*n);
-                                                }
-                                            }
-                                            "detune" => {
-                                                if let Expr::Number(n) = value {
-                                                    config.detune = Some(*n);
-                                                }
-                                            }
-                                            "mixer" => {
-                                                if let Expr::Number(n) = value {
-                                                    config.mixer = Some(*n);
-                                                }
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                }
-                            }
-                            _ => {
-                                // External preset — will be loaded at runtime
-                            }
+                    // Runtime preloading uses extract_preset_refs() to
+                    // discover references.
+                    match args.first() {
+                        // The built-in oscillator, configured by the second argument
+                        Some(Expr::StringLit(name)) if name == "Oscillator" => {
+                            Ok(InstrumentConfig::Oscillator(oscillator_config(args.get(1))?))
+                        }
+                        Some(Expr::StringLit(name))
+                            if name.starts_with(GM_PRESET_PREFIX) && gm_program_of(name).is_none() =>
+                        {
+                            Err(format!("Invalid General MIDI preset '{name}'; expected gm:0 to gm:127."))
+                        }
+                        // External preset — will be loaded at runtime
+                        Some(Expr::StringLit(name)) => {
+                            Ok(InstrumentConfig::SamplerRef(sampler_ref_config(name, args.get(1))?))
                         }
+                        _ => Ok(InstrumentConfig::default()),
                     }
-                    Ok(config)
                 }
+                // gm(25) — General MIDI program, resolved against the
+                // loaded library's catalog metadata at runtime.
+                "gm" => match args.first() {
+                    Some(Expr::Number(n)) if n.fract() == 0.0 && (0.0..128.0).contains(n) => {
+                        Ok(InstrumentConfig::SamplerRef(sampler_ref_config(&gm_preset_name(*n as u8), args.get(1))?))
+                    }
+                    _ => Err("gm() expects a General MIDI program number from 0 to 127.".to_string()),
+                },
+                "Layer" | "Split" => evaluate_composite(ctx, function, args),
                 _ => Err(format!("Unknown instrument preset '{function}'.")),
             }
         }
@@ -434,59 +1894,469 @@ fn evaluate_instrument_expr(ctx: &CompileCtx, expr: &Expr) -> Result<InstrumentC
         }
         Expr::StringLit(s) => {
             // Shorthand: 'triangle', 'square', etc.
-            Ok(InstrumentConfig {
+            Ok(InstrumentConfig::Oscillator(OscillatorConfig {
                 waveform: s.clone(),
-                ..InstrumentConfig::default()
-            })
+                ..OscillatorConfig::default()
+            }))
         }
         _ => Err(format!("Cannot resolve expression as instrument: {expr:?}")),
     }
 }
 
+/// Numeric value of `key` in an `{key: value}` object literal.
+fn object_number(pairs: &[(String, Expr)], key: &str) -> Option<f64> {
+    pairs.iter().find_map(|(k, v)| match v {
+        Expr::Number(n) if k == key => Some(*n),
+        _ => None,
+    })
+}
+
+/// Envelope curve named by `key` in an `{key: 'exp'}` object literal.
+fn object_curve(pairs: &[(String, Expr)], key: &str) -> Result<Option<EnvelopeCurve>, String> {
+    let Some((_, value)) = pairs.iter().find(|(k, _)| k == key) else {
+        return Ok(None);
+    };
+    match value {
+        Expr::StringLit(s) if let Some(curve) = EnvelopeCurve::parse(s) => Ok(Some(curve)),
+        other => Err(format!(
+            "Unknown {key} '{}'; expected 'linear', 'exp' or 'log'.",
+            expr_to_string(other)
+        )),
+    }
+}
+
+/// Envelope stages set in an `{attack: 0.1, attackCurve: 'exp', ...}`
+/// object literal.
+fn envelope_config(pairs: &[(String, Expr)]) -> Result<EnvelopeConfig, String> {
+    Ok(EnvelopeConfig {
+        attack: object_number(pairs, "attack"),
+        hold: object_number(pairs, "hold"),
+        decay: object_number(pairs, "decay"),
+        sustain: object_number(pairs, "sustain"),
+        release: object_number(pairs, "release"),
+        attack_curve: object_curve(pairs, "attackCurve")?,
+        decay_curve: object_curve(pairs, "decayCurve")?,
+        release_curve: object_curve(pairs, "releaseCurve")?,
+    })
+}
+
+/// Velocity response set in a `{velocityCurve: 'soft', velocityFilter:
+/// 0.5}` object literal. The curve may also be a table of levels.
+fn velocity_config(pairs: &[(String, Expr)]) -> VelocityConfig {
+    let curve = pairs.iter().find(|(k, _)| k == "velocityCurve").and_then(|(_, v)| match v {
+        Expr::StringLit(s) => VelocityCurve::parse(s),
+        Expr::Array(items) => items.iter().map(expr_to_number).collect::<Option<Vec<_>>>().map(VelocityCurve::Table),
+        _ => None,
+    });
+    VelocityConfig { curve, filter: object_number(pairs, "velocityFilter") }
+}
+
+/// Oscillator settings from an optional `{type: 'square', ...}` argument.
+/// Unknown keys are ignored.
+fn oscillator_config(arg: Option<&Expr>) -> Result<OscillatorConfig, String> {
+    let mut config = OscillatorConfig::default();
+    if let Some(Expr::ObjectLit(pairs)) = arg {
+        for (key, value) in pairs {
+            if let ("type", Expr::StringLit(s)) = (key.as_str(), value) {
+                config.waveform = s.clone();
+            }
+        }
+        config.envelope = envelope_config(pairs)?;
+        config.velocity = velocity_config(pairs);
+        config.detune = object_number(pairs, "detune");
+        config.mixer = object_number(pairs, "mixer");
+    }
+    Ok(config)
+}
+
+/// Preset reference with overrides from an optional
+/// `{gain: 0.5, transpose: 7, attack: 0.01, ...}` argument. Unknown keys
+/// are ignored.
+fn sampler_ref_config(name: &str, arg: Option<&Expr>) -> Result<SamplerRefConfig, String> {
+    let mut config = SamplerRefConfig { name: name.to_string(), ..Default::default() };
+    if let Some(Expr::ObjectLit(pairs)) = arg {
+        config.gain = object_number(pairs, "gain");
+        config.transpose = object_number(pairs, "transpose");
+        config.envelope = envelope_config(pairs)?;
+        config.velocity = velocity_config(pairs);
+    }
+    Ok(config)
+}
+
+/// FM settings from an optional `{ratio: 2, index: 3, ...}` argument.
+/// Unknown keys are ignored.
+fn fm_config(arg: Option<&Expr>) -> Result<FmConfig, String> {
+    let mut config = FmConfig::default();
+    if let Some(Expr::ObjectLit(pairs)) = arg {
+        config.ratio = object_number(pairs, "ratio").unwrap_or(config.ratio);
+        config.index = object_number(pairs, "index").unwrap_or(config.index);
+        config.envelope = envelope_config(pairs)?;
+        config.velocity = velocity_config(pairs);
+        config.detune = object_number(pairs, "detune");
+    }
+    Ok(config)
+}
+
+/// Evaluate `Layer([...], [levels])` or `Split([...], [split points])` to
+/// an inline composite instrument.
+fn evaluate_composite(ctx: &CompileCtx, function: &str, args: &[Expr]) -> Result<InstrumentConfig, String> {
+    if function == "Split"
+        && let Some(Expr::ObjectLit(pairs)) = args.first()
+    {
+        return evaluate_split_object(ctx, pairs);
+    }
+    let Some(Expr::Array(items)) = args.first() else {
+        return Err(format!("{function}() expects an array of instruments."));
+    };
+    if items.is_empty() {
+        return Err(format!("{function}() needs at least one instrument."));
+    }
+    let children = items
+        .iter()
+        .map(|item| evaluate_instrument_expr(ctx, item))
+        .collect::<Result<Vec<_>, _>>()?;
+    let values = match args.get(1) {
+        None => None,
+        Some(Expr::Array(values)) => Some(values.as_slice()),
+        Some(other) => {
+            return Err(format!("{function}() expects an array as its second argument, got {other:?}."));
+        }
+    };
+
+    let mut composite = CompositeConfig {
+        mode: CompositeKind::Layer,
+        children,
+        mix_levels: None,
+        split_points: None,
+    };
+    if function == "Layer" {
+        if let Some(values) = values {
+            let levels = values
+                .iter()
+                .map(|v| expr_to_number(v).ok_or_else(|| format!("Invalid Layer() mix level: {v:?}.")))
+                .collect::<Result<Vec<_>, _>>()?;
+            if levels.len() != composite.children.len() {
+                return Err(format!(
+                    "Layer() has {} instruments but {} mix levels.",
+                    composite.children.len(),
+                    levels.len()
+                ));
+            }
+            composite.mix_levels = Some(levels);
+        }
+    } else {
+        composite.mode = CompositeKind::Split;
+        if let Some(values) = values {
+            let points = values
+                .iter()
+                .map(|v| split_point(v, ctx.middle_c).ok_or_else(|| format!("Invalid Split() split point: {v:?}.")))
+                .collect::<Result<Vec<_>, _>>()?;
+            if points.len() + 1 != composite.children.len() {
+                return Err(format!(
+                    "Split() with {} instruments needs {} split points.",
+                    composite.children.len(),
+                    composite.children.len() - 1
+                ));
+            }
+            composite.split_points = Some(points);
+        }
+    }
+    Ok(InstrumentConfig::Composite(composite))
+}
+
+/// Evaluate `Split({below: bass, at: C3, above: lead})`: notes below the
+/// split point play `below`, the rest `above`.
+fn evaluate_split_object(ctx: &CompileCtx, pairs: &[(String, Expr)]) -> Result<InstrumentConfig, String> {
+    let (mut below, mut at, mut above) = (None, None, None);
+    for (key, value) in pairs {
+        match key.as_str() {
+            "below" => below = Some(evaluate_instrument_expr(ctx, value)?),
+            "above" => above = Some(evaluate_instrument_expr(ctx, value)?),
+            "at" => {
+                let point = split_point(value, ctx.middle_c)
+                    .ok_or_else(|| format!("Invalid Split() split point: {value:?}."))?;
+                at = Some(point);
+            }
+            other => return Err(format!("Unknown Split() key '{other}'. Expected 'below', 'at' or 'above'.")),
+        }
+    }
+    let (Some(below), Some(at), Some(above)) = (below, at, above) else {
+        return Err("Split({...}) needs 'below', 'at' and 'above', e.g. Split({below: bass, at: C3, above: lead}).".to_string());
+    };
+    Ok(InstrumentConfig::Composite(CompositeConfig {
+        mode: CompositeKind::Split,
+        children: vec![below, above],
+        mix_levels: None,
+        split_points: Some(vec![at]),
+    }))
+}
+
+/// A Split() split point: a MIDI note number or a note name like `C4`.
+fn split_point(expr: &Expr, middle_c: MiddleC) -> Option<u8> {
+    let midi = match expr {
+        Expr::Number(n) if n.fract() == 0.0 => *n as i32,
+        Expr::Identifier(name) | Expr::StringLit(name) => note_to_midi(&middle_c.to_scientific(name))?,
+        _ => return None,
+    };
+    u8::try_from(midi).ok().filter(|m| *m <= 127)
+}
+
+/// The color of a `track.displayColor` value (`'#f80'` or `'#ff8800'`),
+/// lowercased.
+fn display_color(expr: &Expr) -> Option<String> {
+    let Expr::StringLit(color) = expr else { return None };
+    let hex = color.strip_prefix('#')?;
+    (matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit())).then(|| color.to_ascii_lowercase())
+}
+
 /// Handle an assignment statement (works for both top-level and track body).
-fn compile_assignment(ctx: &mut CompileCtx, target: &str, value: &Expr) -> Result<(), String> {
+fn compile_assignment(ctx: &mut CompileCtx, target: &str, value: &Expr, span: (usize, usize)) -> Result<(), String> {
+    if let Some((_, replacement)) = DEPRECATED_PROPERTIES.iter().find(|(old, _)| *old == target)
+        && ctx.language_version >= 2
+    {
+        return Err(format!("'{target}' was removed in language version 2; use '{replacement}'."));
+    }
+    let resolved = ctx.resolve_expr(value)?;
+    let value = resolved.as_ref().unwrap_or(value);
+    if target != "track.instrument" {
+        ctx.trace(|| TraceStep::PropertySet { target: target.to_string(), value: expr_to_string(value) });
+    }
     if target == "track.beatsPerMinute" {
         ctx.emit(EventKind::SetProperty {
             target: target.to_string(),
             value: expr_to_string(value),
+            source_start: span.0,
+            source_end: span.1,
         });
     } else if target == "track.tuningPitch" || target == "track.a4Frequency" {
         // Emit as track.tuningPitch regardless of which alias was used.
         ctx.emit(EventKind::SetProperty {
             target: "track.tuningPitch".to_string(),
             value: expr_to_string(value),
+            source_start: span.0,
+            source_end: span.1,
         });
     } else if target == "track.noteLength" || target == "track.duration" {
         if let Expr::DurationLit(d) = value {
-            ctx.default_note_length = duration_to_beats(d, ctx.default_note_length);
+            ctx.default_note_length = ctx.duration_beats(d)?;
         } else if let Expr::Number(n) = value {
             ctx.default_note_length = *n;
         }
-    } else if target == "song.endMode" {
-        let mode_str = expr_to_string(value);
-        ctx.end_mode = match mode_str.as_str() {
-            "gate" => EndMode::Gate,
-            "release" => EndMode::Release,
-            "tail" => EndMode::Tail,
-            _ => {
-                return Err(format!(
-                    "Unknown song.endMode '{}'. Expected 'gate', 'release', or 'tail'.",
-                    mode_str
-                ));
+    } else if target == "track.velocity" {
+        let Expr::Number(n) = value else {
+            return Err(format!("Invalid track.velocity '{}'. Expected a number.", expr_to_string(value)));
+        };
+        ctx.track_velocity = ctx.velocity_scale.to_midi(*n)?;
+    } else if target == "track.accent" || target == "track.staccato" || target == "track.legato" {
+        let amount = match value {
+            Expr::Number(n) if *n > 0.0 => *n,
+            _ => return Err(format!("Invalid {target} '{}'. Expected a positive number.", expr_to_string(value))),
+        };
+        match target {
+            "track.accent" => ctx.articulation.accent = ctx.velocity_scale.to_midi(amount)?,
+            "track.staccato" if amount > 1.0 => {
+                return Err(format!("Invalid track.staccato '{amount}'. Expected a fraction up to 1."));
+            }
+            "track.staccato" => ctx.articulation.staccato = amount,
+            _ => ctx.articulation.legato = amount,
+        }
+    } else if target == "track.timeSignature" {
+        let sig = match value {
+            Expr::DurationLit(DurationExpr::Fraction(n, d))
+                if n.fract() == 0.0 && d.fract() == 0.0 =>
+            {
+                TimeSignature::new(*n as u32, *d as u32)
+            }
+            Expr::StringLit(s) => TimeSignature::parse(s),
+            _ => None,
+        };
+        let Some(sig) = sig else {
+            let written = match value {
+                Expr::DurationLit(DurationExpr::Fraction(n, d)) => format!("{n}/{d}"),
+                _ => expr_to_string(value),
+            };
+            return Err(format!("Invalid track.timeSignature '{written}'. Expected e.g. 4/4 or 6/8."));
+        };
+        ctx.time_signature = sig;
+        ctx.time_signature_start = ctx.cursor;
+        ctx.emit(EventKind::SetProperty {
+            target: target.to_string(),
+            value: sig.to_string(),
+            source_start: span.0,
+            source_end: span.1,
+        });
+    } else if target == "track.displayColor" {
+        // Only for editors and visualizations; it does not affect the audio
+        if display_color(value).is_none() {
+            return Err(format!(
+                "Invalid track.displayColor '{}'. Expected a color like '#ff8800'.",
+                expr_to_string(value)
+            ));
+        }
+    } else if target == "track.sends" {
+        let Expr::ObjectLit(pairs) = value else {
+            return Err(format!(
+                "Invalid track.sends '{}'. Expected levels by bus, e.g. {{reverb: 0.3}}.",
+                expr_to_string(value)
+            ));
+        };
+        // Buses left out are not sent to
+        let mut sends = Sends::default();
+        for (bus, level) in pairs {
+            let slot = match bus.as_str() {
+                "reverb" => &mut sends.reverb,
+                "delay" => &mut sends.delay,
+                _ => return Err(format!("Unknown send bus '{bus}'. Expected 'reverb' or 'delay'.")),
+            };
+            *slot = match expr_to_number(level) {
+                Some(v) if (0.0..=1.0).contains(&v) => v,
+                _ => {
+                    return Err(format!(
+                        "Invalid send level '{}' for bus '{bus}'. Expected a number from 0 to 1.",
+                        expr_to_string(level)
+                    ));
+                }
+            };
+        }
+        ctx.sends = sends;
+    } else if target == "track.tremolo" {
+        let mut tremolo = TremoloConfig::default();
+        lfo_settings(target, value, &mut tremolo.rate, &mut tremolo.sync, &mut tremolo.depth, &mut tremolo.shape)?;
+        ctx.modulation.tremolo = (tremolo.depth > 0.0).then_some(tremolo);
+    } else if target == "track.autoPan" {
+        let mut auto_pan = AutoPanConfig::default();
+        lfo_settings(target, value, &mut auto_pan.rate, &mut auto_pan.sync, &mut auto_pan.depth, &mut auto_pan.shape)?;
+        ctx.modulation.auto_pan = (auto_pan.depth > 0.0).then_some(auto_pan);
+    } else if target == "track.voiceLeading" {
+        ctx.voice_leading = match value {
+            Expr::Identifier(s) | Expr::StringLit(s) if s == "true" => true,
+            Expr::Identifier(s) | Expr::StringLit(s) if s == "false" => false,
+            Expr::Number(n) => *n != 0.0,
+            _ => {
+                return Err(format!(
+                    "Invalid track.voiceLeading '{}'. Expected true or false.",
+                    expr_to_string(value)
+                ));
+            }
+        };
+        ctx.last_chord = None;
+    } else if target == "track.swing" {
+        match expr_to_number(value) {
+            Some(v) if (0.0..1.0).contains(&v) && v > 0.0 => ctx.swing = v,
+            _ => {
+                return Err(format!(
+                    "Invalid track.swing '{}'. Expected a ratio between 0 and 1 (0.5 = straight).",
+                    expr_to_string(value)
+                ));
+            }
+        }
+    } else if target == "song.countIn" {
+        match expr_to_number(value) {
+            Some(v) if v >= 0.0 && v.fract() == 0.0 => ctx.count_in_bars = v as u32,
+            _ => {
+                return Err(format!(
+                    "Invalid song.countIn '{}'. Expected a whole number of bars.",
+                    expr_to_string(value)
+                ));
+            }
+        }
+    } else if target == "song.anacrusis" {
+        match expr_to_number(value) {
+            Some(v) if v >= 0.0 => ctx.anacrusis = v,
+            _ => {
+                return Err(format!(
+                    "Invalid song.anacrusis '{}'. Expected a pickup length in beats.",
+                    expr_to_string(value)
+                ));
+            }
+        }
+    } else if target == "song.seed" || target == "track.seed" {
+        let seed = match expr_to_number(value) {
+            Some(v) if v >= 0.0 && v.fract() == 0.0 => v as u64,
+            _ => {
+                return Err(format!(
+                    "Invalid {target} '{}'. Expected a whole number.",
+                    expr_to_string(value)
+                ));
+            }
+        };
+        if target == "song.seed" {
+            // Restart every track's choices from the new seed.
+            ctx.seed = seed;
+            ctx.rng_streams.clear();
+        } else {
+            let track = ctx.current_track_name.clone().unwrap_or_default();
+            let state = stream_seed(seed, &track);
+            ctx.rng_streams.insert(track, state);
+        }
+    } else if target == "song.middleC" {
+        ctx.middle_c = match expr_to_string(value).as_str() {
+            "C4" => MiddleC::C4,
+            "C3" => MiddleC::C3,
+            other => {
+                return Err(format!("Unknown song.middleC '{other}'. Expected 'C3' or 'C4'."));
+            }
+        };
+    } else if target == "song.velocityScale" {
+        ctx.velocity_scale = match expr_to_string(value).as_str() {
+            "midi" => VelocityScale::Midi,
+            "unit" => VelocityScale::Unit,
+            other => {
+                return Err(format!("Unknown song.velocityScale '{other}'. Expected 'midi' or 'unit'."));
             }
         };
+    } else if target == "song.fadeIn" || target == "song.fadeOut" {
+        let fade = match value {
+            Expr::Number(n) if *n >= 0.0 => Some(FadeLength::Beats(*n)),
+            Expr::DurationLit(DurationExpr::Fraction(n, m)) => Some(FadeLength::Beats(n / m)),
+            Expr::StringLit(s) => match s.trim().strip_suffix('s') {
+                Some(secs) => secs.trim().parse().ok().map(FadeLength::Seconds),
+                None => s.trim().parse().ok().map(FadeLength::Beats),
+            }
+            .filter(|f| matches!(f, FadeLength::Beats(v) | FadeLength::Seconds(v) if *v >= 0.0)),
+            _ => None,
+        };
+        let Some(fade) = fade else {
+            return Err(format!(
+                "Invalid {} '{}'. Expected beats (e.g. 4) or seconds (e.g. '2.5s').",
+                target,
+                expr_to_string(value)
+            ));
+        };
+        if target == "song.fadeIn" {
+            ctx.fade_in = Some(fade);
+        } else {
+            ctx.fade_out = Some(fade);
+        }
+    } else if target == "song.endMode" {
+        let mode_str = expr_to_string(value);
+        ctx.end_mode = mode_str.parse().map_err(|_| {
+            format!(
+                "Unknown song.endMode '{}'. Expected 'gate', 'release', or 'tail'.",
+                mode_str
+            )
+        })?;
     } else if target == "track.instrument" {
         // Resolve the value to an InstrumentConfig.
         let config = evaluate_instrument_expr(ctx, value)?;
+        ctx.trace(|| TraceStep::InstrumentResolved {
+            target: target.to_string(),
+            expression: expr_to_string(value),
+            instrument: config.clone(),
+        });
         ctx.current_instrument = config;
         ctx.emit(EventKind::SetProperty {
             target: target.to_string(),
             value: expr_to_string(value),
+            source_start: span.0,
+            source_end: span.1,
         });
     } else {
         ctx.emit(EventKind::SetProperty {
             target: target.to_string(),
             value: expr_to_string(value),
+            source_start: span.0,
+            source_end: span.1,
         });
     }
     Ok(())
@@ -496,10 +2366,11 @@ fn compile_assignment(ctx: &mut CompileCtx, target: &str, value: &Expr) -> Resul
 fn inline_track_call(
     ctx: &mut CompileCtx,
     name: &str,
-    _velocity: &Option<f64>,
+    velocity: &Option<VelocityExpr>,
     play_duration: &Option<DurationExpr>,
     args: &[Expr],
     step: &Option<DurationExpr>,
+    span: (usize, usize),
 ) -> Result<(), String> {
     let track_body = ctx
         .track_defs
@@ -511,9 +2382,20 @@ fn inline_track_call(
         // Save parent scope.
         let saved_cursor = ctx.cursor;
         let saved_note_len = ctx.default_note_length;
+        let saved_velocity = ctx.track_velocity;
+        let saved_articulation = ctx.articulation;
+        let saved_swing = ctx.swing;
+        let saved_time_signature = (ctx.time_signature, ctx.time_signature_start);
+        let saved_voice_leading = ctx.voice_leading;
+        let saved_sends = ctx.sends;
+        let saved_modulation = ctx.modulation;
+        let saved_last_chord = ctx.last_chord.take();
         let saved_instrument = ctx.current_instrument.clone();
         let saved_params = ctx.param_bindings.clone();
+        // A track body does not see its caller's variables
+        let saved_variables = core::mem::take(&mut ctx.variables);
         let saved_track_name = ctx.current_track_name.clone();
+        let trace_index = ctx.trace(|| TraceStep::TrackInlined { name: name.to_string(), end_beat: saved_cursor });
 
         // Set the current track name for event stamping.
         ctx.current_track_name = Some(name.to_string());
@@ -531,12 +2413,26 @@ fn inline_track_call(
 
         // If play_duration is set, cap the track's extent.
         if let Some(pd) = play_duration {
-            let max_dur = duration_to_beats(pd, ctx.default_note_length);
+            let max_dur = ctx.duration_beats(pd)?;
             ctx.cursor = saved_cursor + max_dur;
         }
 
         // Record the furthest beat this track reached.
         ctx.max_cursor = ctx.max_cursor.max(ctx.cursor);
+        // A time signature set in the track ends with it
+        if (ctx.time_signature, ctx.time_signature_start) != saved_time_signature {
+            ctx.emit(EventKind::SetProperty {
+                target: "track.timeSignature".to_string(),
+                value: saved_time_signature.0.to_string(),
+                source_start: span.0,
+                source_end: span.1,
+            });
+        }
+        if let (Some(i), Some(trace)) = (trace_index, &mut ctx.trace)
+            && let TraceStep::TrackInlined { end_beat, .. } = &mut trace[i].step
+        {
+            *end_beat = ctx.cursor;
+        }
 
         // Async: restore cursor — track calls don't advance the caller's
         // cursor. Consecutive track calls start at the same beat (parallel).
@@ -544,38 +2440,52 @@ fn inline_track_call(
 
         // Restore parent scope.
         ctx.default_note_length = saved_note_len;
+        ctx.track_velocity = saved_velocity;
+        ctx.articulation = saved_articulation;
+        ctx.swing = saved_swing;
+        (ctx.time_signature, ctx.time_signature_start) = saved_time_signature;
+        ctx.voice_leading = saved_voice_leading;
+        ctx.sends = saved_sends;
+        ctx.modulation = saved_modulation;
+        ctx.last_chord = saved_last_chord;
         ctx.current_instrument = saved_instrument;
         ctx.param_bindings = saved_params;
+        ctx.variables = saved_variables;
         ctx.current_track_name = saved_track_name;
 
         // Apply explicit step duration (if any).
         // `melody() 8;` advances cursor by 8 beats *after* the async call.
         if let Some(s) = step {
-            let step_beats = duration_to_beats(s, ctx.default_note_length);
+            let step_beats = ctx.duration_beats(s)?;
             ctx.cursor = saved_cursor + step_beats;
         }
     } else {
         // Unknown track: emit as a TrackStart event.
+        ctx.trace(|| TraceStep::TrackDeferred { name: name.to_string() });
         let arg_strings: Vec<String> = args.iter().map(expr_to_string).collect();
+        let velocity = velocity.map(|v| ctx.resolve_velocity(v)).transpose()?;
         ctx.emit(EventKind::TrackStart {
             track_name: name.to_string(),
-            velocity: *_velocity,
-            play_duration: play_duration
-                .as_ref()
-                .map(|d| duration_to_beats(d, ctx.default_note_length)),
+            velocity,
+            play_duration: play_duration.as_ref().map(|d| ctx.duration_beats(d)).transpose()?,
             args: arg_strings,
+            source_start: span.0,
+            source_end: span.1,
         });
         if let Some(s) = step {
-            ctx.cursor += duration_to_beats(s, ctx.default_note_length);
+            ctx.cursor += ctx.duration_beats(s)?;
         }
     }
     Ok(())
 }
 
+/// Compile a block; variables it declares go out of scope at its end.
 fn compile_track_body(ctx: &mut CompileCtx, body: &[TrackStatement]) -> Result<(), String> {
+    let scope = ctx.variables.len();
     for stmt in body {
         compile_track_statement(ctx, stmt)?;
     }
+    ctx.variables.truncate(scope);
     Ok(())
 }
 
@@ -583,24 +2493,72 @@ fn compile_track_statement(ctx: &mut CompileCtx, stmt: &TrackStatement) -> Resul
     match stmt {
         TrackStatement::NoteEvent {
             pitch,
+            slide_to,
             velocity,
             audible_duration,
             step_duration,
+            expression,
+            lyric,
+            articulation,
+            choices,
             span_start,
             span_end,
         } => {
-            let vel = velocity.unwrap_or(100.0);
-            let audible = ctx.resolve_duration(audible_duration);
-            let step = ctx.resolve_duration(step_duration);
+            check_note_expression(expression)?;
+            let pitch = if choices.is_empty() {
+                pitch
+            } else {
+                let pick = (ctx.random() * choices.len() as f64) as usize;
+                &choices[pick.min(choices.len() - 1)]
+            };
+            if let Some(target) = slide_to
+                && note_to_midi(target).is_none()
+            {
+                return Err(format!("Invalid slide target '{target}' after '{pitch}->'."));
+            }
+            let pitch = ctx.note_pitch(pitch)?;
+            let slide_to = slide_to.as_deref().map(|p| ctx.middle_c.to_scientific(p));
+            let mut vel = match velocity {
+                Some(v) => ctx.resolve_velocity(*v)?,
+                None => ctx.track_velocity,
+            };
+            let mut audible = ctx.resolve_duration(audible_duration)?;
+            let step = ctx.resolve_duration(step_duration)?;
+            for mark in articulation {
+                match mark {
+                    Articulation::Accent => vel = (vel + ctx.articulation.accent).min(127.0),
+                    Articulation::Staccato => audible *= ctx.articulation.staccato,
+                    Articulation::Legato => audible = step * ctx.articulation.legato,
+                }
+            }
 
-            ctx.emit(EventKind::Note {
+            let time = ctx.swung_cursor();
+            let audible = ctx.swung_gate(time, audible);
+            ctx.trace(|| TraceStep::NotePlaced {
                 pitch: pitch.clone(),
+                time,
+                gate: audible,
+                source_start: *span_start,
+                source_end: *span_end,
+            });
+            let instrument = intern_instrument(&mut ctx.instruments, &ctx.current_instrument);
+            ctx.emit_at(time, EventKind::Note {
+                pitch,
                 velocity: vel,
                 gate: audible,
-                instrument: ctx.current_instrument.clone(),
+                instrument,
                 source_start: *span_start,
                 source_end: *span_end,
+                glide_from: None,
+                slide_to,
+                expression: Box::new(expression.clone()),
+                sends: ctx.sends,
+                modulation: ctx.modulation,
             });
+            if let Some(text) = lyric {
+                ctx.emit_at(time, EventKind::Lyric { text: text.clone() });
+            }
+            ctx.last_chord = None;
             ctx.cursor += step;
             Ok(())
         }
@@ -608,53 +2566,156 @@ fn compile_track_statement(ctx: &mut CompileCtx, stmt: &TrackStatement) -> Resul
             notes,
             audible_duration,
             step_duration,
+            expression,
             span_start,
             span_end,
         } => {
-            let chord_audible = audible_duration
-                .as_ref()
-                .map(|d| duration_to_beats(d, ctx.default_note_length));
+            check_note_expression(expression)?;
+            let chord_audible = audible_duration.as_ref().map(|d| ctx.duration_beats(d)).transpose()?;
+            let time = ctx.swung_cursor();
+            let pitches = notes.iter().map(|n| ctx.note_pitch(&n.pitch)).collect::<Result<Vec<_>, _>>()?;
+            let glides = match &ctx.last_chord {
+                Some(prev) if ctx.voice_leading => lead_voices(prev, &pitches),
+                _ => vec![None; pitches.len()],
+            };
+            if ctx.voice_leading {
+                ctx.last_chord = Some(pitches.clone());
+            }
 
-            for note in notes {
+            for ((note, pitch), glide_from) in notes.iter().zip(&pitches).zip(glides) {
                 let note_dur = note
                     .audible_duration
                     .as_ref()
-                    .map(|d| duration_to_beats(d, ctx.default_note_length))
+                    .map(|d| ctx.duration_beats(d))
+                    .transpose()?
                     .or(chord_audible)
                     .unwrap_or(ctx.default_note_length);
+                let note_dur = ctx.swung_gate(time, note_dur);
 
-                ctx.emit(EventKind::Note {
-                    pitch: note.pitch.clone(),
-                    velocity: 100.0,
+                ctx.trace(|| TraceStep::NotePlaced {
+                    pitch: pitch.clone(),
+                    time,
+                    gate: note_dur,
+                    source_start: *span_start,
+                    source_end: *span_end,
+                });
+                let instrument = intern_instrument(&mut ctx.instruments, &ctx.current_instrument);
+                ctx.emit_at(time, EventKind::Note {
+                    pitch: pitch.clone(),
+                    velocity: ctx.track_velocity,
                     gate: note_dur,
-                    instrument: ctx.current_instrument.clone(),
+                    instrument,
                     source_start: *span_start,
                     source_end: *span_end,
+                    glide_from,
+                    slide_to: None,
+                    expression: Box::new(expression.clone()),
+                    sends: ctx.sends,
+                    modulation: ctx.modulation,
                 });
             }
 
-            let step = ctx.resolve_duration(step_duration);
-            ctx.cursor += step;
+            ctx.cursor += ctx.resolve_duration(step_duration)?;
             Ok(())
         }
         TrackStatement::Rest { duration, .. } => {
-            ctx.cursor += duration_to_beats(duration, ctx.default_note_length);
+            ctx.last_chord = None;
+            ctx.cursor += ctx.duration_beats(duration)?;
+            Ok(())
+        }
+        TrackStatement::BarRest { bars, .. } => {
+            if *bars < 1.0 || bars.fract() != 0.0 {
+                return Err(format!("Invalid bar rest 'R*{bars}'. Expected a whole number of bars."));
+            }
+            ctx.last_chord = None;
+            ctx.cursor += bars * ctx.time_signature.beats_per_bar();
             Ok(())
         }
         TrackStatement::Assignment { target, value, .. } => {
-            compile_assignment(ctx, target, value)
+            if ctx.assign_variable(target, value)? {
+                return Ok(());
+            }
+            compile_assignment(ctx, target, value, stmt.span())
         }
-        TrackStatement::ForLoop {
-            init: _,
-            condition: _,
-            update: _,
-            body,
-            ..
-        } => {
-            // Phase 1: hardcoded unroll — extract loop count from condition.
-            // For now, just compile the body once as a placeholder.
-            // TODO: properly evaluate loop bounds.
+        TrackStatement::Let { name, value, .. } => {
+            let value = ctx.evaluate(value)?;
+            ctx.variables.push((name.clone(), value));
+            Ok(())
+        }
+        TrackStatement::ForLoop { init, condition, update, body, .. } => {
+            let trace_index = ctx.trace(|| TraceStep::LoopUnrolled { iterations: 0 });
+            let scope = ctx.variables.len();
+            if let Some(init) = init {
+                ctx.run_loop_clause(init)?;
+            }
+            let mut iterations = 0;
+            loop {
+                if let Some(condition) = condition
+                    && !ctx.evaluate(condition)?.is_truthy()
+                {
+                    break;
+                }
+                if iterations == MAX_LOOP_ITERATIONS {
+                    return Err(format!("For loop ran {MAX_LOOP_ITERATIONS} times without its condition failing."));
+                }
+                iterations += 1;
+                compile_track_body(ctx, body)?;
+                if let Some(update) = update {
+                    ctx.run_loop_clause(update)?;
+                }
+            }
+            ctx.variables.truncate(scope);
+            if let (Some(i), Some(trace)) = (trace_index, &mut ctx.trace) {
+                trace[i].step = TraceStep::LoopUnrolled { iterations };
+            }
+            Ok(())
+        }
+        TrackStatement::Maybe { probability, body, .. } => {
+            // Draw before compiling the body, so the outcome does not
+            // depend on any choices inside it.
+            let keep = ctx.random() < *probability;
+            let first_event = ctx.events.len();
+            let first_trace = ctx.trace.as_ref().map_or(0, Vec::len);
+            compile_track_body(ctx, body)?;
+            if !keep {
+                // Only the notes (and their lyrics) are dropped: property
+                // changes, markers and sub-track starts still take effect.
+                let mut index = 0;
+                ctx.events.retain(|e| {
+                    index += 1;
+                    index <= first_event || !matches!(e.kind, EventKind::Note { .. } | EventKind::Lyric { .. })
+                });
+                if let Some(trace) = &mut ctx.trace {
+                    let mut index = 0;
+                    trace.retain(|t| {
+                        index += 1;
+                        index <= first_trace || !matches!(t.step, TraceStep::NotePlaced { .. })
+                    });
+                }
+            }
+            Ok(())
+        }
+        TrackStatement::Poly { steps, span, body, .. } => {
+            // Compile the body as written, then squeeze it onto the grid:
+            // `steps` of its steps take the time of `span`.
+            let start = ctx.cursor;
+            let saved_max_cursor = ctx.max_cursor;
+            let first_event = ctx.events.len();
             compile_track_body(ctx, body)?;
+            let scale = span / steps;
+            // Track calls in the body reach past it on the unscaled grid
+            if ctx.max_cursor > saved_max_cursor {
+                ctx.max_cursor = saved_max_cursor.max(start + (ctx.max_cursor - start) * scale);
+            }
+            for event in &mut ctx.events[first_event..] {
+                event.time = start + (event.time - start) * scale;
+                match &mut event.kind {
+                    EventKind::Note { gate, .. } => *gate *= scale,
+                    EventKind::TrackStart { play_duration: Some(duration), .. } => *duration *= scale,
+                    _ => {}
+                }
+            }
+            ctx.cursor = start + (ctx.cursor - start) * scale;
             Ok(())
         }
         TrackStatement::TrackCall {
@@ -665,9 +2726,13 @@ fn compile_track_statement(ctx: &mut CompileCtx, stmt: &TrackStatement) -> Resul
             step,
             ..
         } => {
-            inline_track_call(ctx, name, velocity, play_duration, args, step)
+            inline_track_call(ctx, name, velocity, play_duration, args, step, stmt.span())
+        }
+        TrackStatement::Marker { kind, name, .. } => {
+            ctx.emit(EventKind::Marker { kind: *kind, name: name.clone() });
+            Ok(())
         }
-        TrackStatement::Comment(_) => Ok(()),
+        TrackStatement::Comment { .. } => Ok(()),
     }
 }
 
@@ -685,163 +2750,2068 @@ pub fn extract_preset_refs(event_list: &EventList) -> Vec<String> {
     refs
 }
 
-// ── Cursor Context Query ────────────────────────────────────
+/// A track's use of a preset, within a `PresetNeed`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PresetTrackUse {
+    /// None = notes placed outside any track.
+    pub track_name: Option<String>,
+    pub first_beat: f64,
+    pub note_count: usize,
+}
 
-/// Determine the compilation state at a given byte offset in the source.
-///
-/// Parses the source, then walks the AST in order, compiling statements whose
-/// `span_start <= cursor_byte_offset`. When the cursor falls inside a track
-/// definition body, descends into that body and stops at the right statement.
-///
-/// Returns the accumulated instrument, BPM, tuning, beat position, etc.
-pub fn cursor_context(source: &str, cursor_byte_offset: usize) -> Result<CursorContext, String> {
-    let program = crate::parse(source).map_err(|e| e.to_string())?;
-    let mut ctx = CompileCtx::new(false);
-    let mut bpm: f64 = 120.0;
-    let mut tuning: f64 = 440.0;
+/// A preset the song references, with when it is first played, so a
+/// loader can start playback once the earliest presets are ready.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PresetNeed {
+    pub name: String,
+    /// Beat of the first note played with it; None if no note uses it.
+    pub first_beat: Option<f64>,
+    /// `first_beat` in seconds, from the tempo map.
+    pub first_seconds: Option<f64>,
+    /// Tracks playing it, in order of their first note.
+    pub tracks: Vec<PresetTrackUse>,
+    /// Download size from the library catalog, when known
+    /// (`LibraryIndex::estimate_preset_bytes`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_bytes: Option<u64>,
+}
 
-    // First pass: collect track definitions.
-    for stmt in &program.statements {
-        if let Statement::TrackDef { name, params, body, .. } = stmt {
-            ctx.track_defs.push(TrackDef {
-                name: name.clone(),
-                params: params.clone(),
-                body: body.clone(),
-            });
+/// Like `extract_preset_refs`, with the first beat each preset is needed
+/// and per-track usage. Presets are listed in the order playback reaches
+/// them, then those no note plays.
+pub fn extract_preset_needs(event_list: &EventList, default_bpm: f64) -> Vec<PresetNeed> {
+    let unplayed = |name| PresetNeed { name, first_beat: None, first_seconds: None, tracks: Vec::new(), estimated_bytes: None };
+    let mut needs: Vec<PresetNeed> = extract_preset_refs(event_list).into_iter().map(unplayed).collect();
+    let mut notes: Vec<(&Event, &InstrumentConfig)> = event_list
+        .events
+        .iter()
+        .filter_map(|e| match &e.kind {
+            EventKind::Note { instrument, .. } => Some((e, event_list.instruments.get(*instrument)?)),
+            _ => None,
+        })
+        .collect();
+    notes.sort_by(|a, b| a.0.time.partial_cmp(&b.0.time).unwrap());
+
+    for (event, instrument) in notes {
+        for name in instrument.preset_refs() {
+            let need = match needs.iter().position(|n| n.name == name) {
+                Some(i) => &mut needs[i],
+                None => {
+                    // Set by `reassign_instruments` without a PresetRef
+                    needs.push(unplayed(name));
+                    needs.last_mut().unwrap()
+                }
+            };
+            need.first_beat.get_or_insert(event.time);
+            match need.tracks.iter_mut().find(|t| t.track_name == event.track_name) {
+                Some(track) => track.note_count += 1,
+                None => need.tracks.push(PresetTrackUse {
+                    track_name: event.track_name.clone(),
+                    first_beat: event.time,
+                    note_count: 1,
+                }),
+            }
         }
     }
 
-    // Second pass: walk statements up to the cursor.
-    for stmt in &program.statements {
-        let (ss, se) = stmt.span();
+    let tempo = TempoMap::from_events(&event_list.events, default_bpm);
+    for need in &mut needs {
+        need.first_seconds = need.first_beat.map(|beat| tempo.seconds_at(beat));
+    }
+    // Stable, so unplayed presets keep their reference order at the end
+    needs.sort_by(|a, b| match (a.first_beat, b.first_beat) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap(),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    });
+    needs
+}
 
-        // Past the cursor — stop.
-        if ss > cursor_byte_offset {
-            break;
+/// Give every note of the named tracks a new instrument without
+/// recompiling. `mapping` maps track names to instruments. A `PresetRef`
+/// event is added at the start for each newly referenced preset so that
+/// preloading finds it.
+pub fn reassign_instruments(
+    event_list: &mut EventList,
+    mapping: &HashMap<String, InstrumentConfig>,
+) -> Result<(), String> {
+    for track in mapping.keys() {
+        let plays = event_list.events.iter().any(|e| {
+            e.track_name.as_ref() == Some(track) && matches!(e.kind, EventKind::Note { .. })
+        });
+        if !plays {
+            return Err(format!("No notes on track '{track}' to reassign."));
         }
+    }
 
-        // Cursor is inside a track definition — descend into body.
-        if let Statement::TrackDef { body, name, .. } = stmt {
-            if cursor_byte_offset <= se {
-                ctx.current_track_name = Some(name.clone());
-                cursor_walk_track_body(&mut ctx, body, cursor_byte_offset)?;
-                extract_bpm_tuning(&ctx.events, &mut bpm, &mut tuning);
-                return Ok(build_cursor_context(&ctx, bpm, tuning));
-            }
+    for event in &mut event_list.events {
+        if let EventKind::Note { instrument, .. } = &mut event.kind
+            && let Some(new) = event.track_name.as_ref().and_then(|t| mapping.get(t))
+        {
+            *instrument = intern_instrument(&mut event_list.instruments, new);
         }
-
-        // Compile the statement normally.
-        compile_statement(&mut ctx, stmt)?;
-        extract_bpm_tuning(&ctx.events, &mut bpm, &mut tuning);
     }
 
-    Ok(build_cursor_context(&ctx, bpm, tuning))
-}
-
-/// Walk a track body up to the cursor byte offset, compiling each statement.
-fn cursor_walk_track_body(
-    ctx: &mut CompileCtx,
-    body: &[TrackStatement],
-    cursor_byte_offset: usize,
-) -> Result<(), String> {
-    for stmt in body {
-        let (ss, _se) = stmt.span();
-        if ss > cursor_byte_offset {
-            break;
+    let known = extract_preset_refs(event_list);
+    let mut added: Vec<String> = Vec::new();
+    for name in mapping.values().flat_map(|i| i.preset_refs()) {
+        if !known.contains(&name) && !added.contains(&name) {
+            added.push(name);
         }
-        compile_track_statement(ctx, stmt)?;
     }
+    let start = event_list.events.first().map_or(0.0, |e| e.time.min(0.0));
+    event_list.events.splice(
+        0..0,
+        added.into_iter().map(|name| Event {
+            time: start,
+            kind: EventKind::PresetRef { name },
+            track_name: None,
+        }),
+    );
     Ok(())
 }
 
-/// Scan emitted events for the latest BPM and tuning property changes.
-fn extract_bpm_tuning(events: &[Event], bpm: &mut f64, tuning: &mut f64) {
-    for event in events {
-        if let EventKind::SetProperty { target, value } = &event.kind {
-            match target.as_str() {
-                "track.beatsPerMinute" => {
-                    if let Ok(v) = value.parse::<f64>() {
-                        *bpm = v;
-                    }
-                }
-                "track.tuningPitch" => {
-                    if let Ok(v) = value.parse::<f64>() {
-                        *tuning = v;
-                    }
-                }
-                _ => {}
+/// The part of `event_list` between `start_beat` and `end_beat`, for
+/// views that page through long songs. Keeps the notes sounding in the
+/// window, the other events inside it and, for each track, the last
+/// value of each property set before it, so the window plays with the
+/// right tempo and settings. Event times are not shifted.
+pub fn event_region(event_list: &EventList, start_beat: f64, end_beat: f64) -> EventList {
+    // Last change to each (track, target) before the window
+    let mut state: Vec<&Event> = Vec::new();
+    for event in &event_list.events {
+        if event.time >= start_beat {
+            break;
+        }
+        if let EventKind::SetProperty { target, .. } = &event.kind {
+            let same = |e: &&Event| {
+                e.track_name == event.track_name
+                    && matches!(&e.kind, EventKind::SetProperty { target: t, .. } if t == target)
+            };
+            match state.iter().position(same) {
+                Some(i) => state[i] = event,
+                None => state.push(event),
             }
         }
     }
-}
 
-/// Build a CursorContext from the current compile state.
-fn build_cursor_context(ctx: &CompileCtx, bpm: f64, tuning: f64) -> CursorContext {
-    CursorContext {
-        instrument: ctx.current_instrument.clone(),
-        track_name: ctx.current_track_name.clone(),
-        note_length: ctx.default_note_length,
-        bpm,
-        tuning_pitch: tuning,
-        cursor_beat: ctx.cursor,
+    let mut region = EventList {
+        events: Vec::new(),
+        instruments: Vec::new(),
+        total_beats: event_list.total_beats,
+        end_mode: event_list.end_mode,
+        count_in: event_list.count_in,
+        fade_in: event_list.fade_in,
+        fade_out: event_list.fade_out,
+        anacrusis: event_list.anacrusis,
+    };
+    for event in &event_list.events {
+        let keep = match &event.kind {
+            EventKind::Note { gate, .. } => event.time < end_beat && event.time + gate > start_beat,
+            EventKind::SetProperty { .. } if event.time < start_beat => state.iter().any(|e| core::ptr::eq(*e, event)),
+            // Loaders look for these before playback starts
+            EventKind::PresetRef { .. } => true,
+            _ => (start_beat..end_beat).contains(&event.time),
+        };
+        if !keep {
+            continue;
+        }
+        let mut event = event.clone();
+        if let EventKind::Note { instrument, .. } = &mut event.kind {
+            let Some(config) = event_list.instruments.get(*instrument) else { continue };
+            *instrument = region.intern_instrument(config);
+        }
+        region.events.push(event);
     }
+    region
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::parse;
+// ── Projects ────────────────────────────────────────────────
 
-    #[test]
-    fn test_compile_simple_track() {
-        let program = parse(
-            r#"
-track riff() {
-    C3 /2
-    D3 /4
-    E3 /4
+/// A project: several songs that share const and track definitions.
+///
+/// ```json
+/// { "shared": ["instruments.sw"],
+///   "songs": [{ "name": "Intro", "file": "intro.sw" }] }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProjectManifest {
+    /// Files whose definitions are visible to every song, in order.
+    #[serde(default)]
+    pub shared: Vec<String>,
+    /// Songs to compile, each to its own EventList.
+    pub songs: Vec<ProjectSong>,
 }
-riff();
-"#,
-        )
-        .unwrap();
 
-        let events = compile(&program).unwrap();
-        assert_eq!(events.total_beats, 1.0); // 0.5 + 0.25 + 0.25
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProjectSong {
+    pub name: String,
+    pub file: String,
+}
 
-        let notes: Vec<_> = events
-            .events
+/// The compiled songs of a project.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CompiledProject {
+    /// One entry per manifest song, in manifest order.
+    pub songs: Vec<CompiledSong>,
+    /// Presets referenced by any song, for preloading once.
+    #[serde(rename = "presetRefs")]
+    pub preset_refs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CompiledSong {
+    pub name: String,
+    #[serde(rename = "eventList")]
+    pub event_list: EventList,
+}
+
+/// Compile every song of a project. `files` maps file names to `.sw`
+/// source. Shared files may only contain `const` and `track`
+/// definitions; a song's own definitions take precedence over them.
+pub fn compile_project(
+    manifest: &ProjectManifest,
+    files: &HashMap<String, String>,
+) -> Result<CompiledProject, String> {
+    let parse_file = |file: &str| -> Result<Program, String> {
+        let source = files
+            .get(file)
+            .ok_or_else(|| format!("Project file '{file}' is missing."))?;
+        crate::parse(source).map_err(|e| format!("{file}: {e}"))
+    };
+
+    let mut shared = Vec::new();
+    for file in &manifest.shared {
+        for stmt in parse_file(file)?.statements {
+            match stmt {
+                Statement::TrackDef { .. } | Statement::ConstDecl { .. } => shared.push(stmt),
+                Statement::Comment { .. } => {}
+                _ => {
+                    return Err(format!(
+                        "{file}: shared files may only contain const and track definitions."
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut songs = Vec::new();
+    let mut preset_refs = Vec::new();
+    for song in &manifest.songs {
+        let own = parse_file(&song.file)?;
+        let own_tracks: Vec<&str> = own
+            .statements
             .iter()
-            .filter_map(|e| match &e.kind {
-                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+            .filter_map(|s| match s {
+                Statement::TrackDef { name, .. } => Some(name.as_str()),
                 _ => None,
             })
             .collect();
+        let mut statements: Vec<Statement> = shared
+            .iter()
+            .filter(|s| !matches!(s, Statement::TrackDef { name, .. } if own_tracks.contains(&name.as_str())))
+            .cloned()
+            .collect();
+        statements.extend(own.statements.iter().cloned());
 
-        assert_eq!(notes.len(), 3);
-        assert_eq!(notes[0], (0.0, "C3"));
-        assert_eq!(notes[1], (0.5, "D3"));
-        assert_eq!(notes[2], (0.75, "E3"));
+        let event_list = compile(&Program { statements, language_version: own.language_version })
+            .map_err(|e| format!("{}: {e}", song.file))?;
+        for name in extract_preset_refs(&event_list) {
+            if !preset_refs.contains(&name) {
+                preset_refs.push(name);
+            }
+        }
+        songs.push(CompiledSong { name: song.name.clone(), event_list });
     }
-
-    #[test]
-    fn test_compile_track_with_rest() {
-        let program = parse(
-            r#"
-track t() {
-    C3 /4
-    4
-    D3 /4
+    Ok(CompiledProject { songs, preset_refs })
 }
-t();
-"#,
-        )
-        .unwrap();
+
+// ── Song Structure ──────────────────────────────────────────
+
+/// A track definition, as listed by `analyze_structure`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct TrackOutline {
+    pub name: String,
+    pub params: Vec<String>,
+    /// The `///` doc comment before the definition, for hover tooltips.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+    /// Color from `track.displayColor` in the body, e.g. `#ff8800`, for
+    /// piano rolls and other visualizations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    pub span_start: usize,
+    pub span_end: usize,
+}
+
+/// The track definitions of a song, in source order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SongStructure {
+    pub tracks: Vec<TrackOutline>,
+}
+
+/// List the track definitions of `program` with their parameters, doc
+/// comments, display colors and source spans.
+pub fn analyze_structure(program: &Program) -> SongStructure {
+    let tracks = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::TrackDef { name, params, body, doc, span_start, span_end } => Some(TrackOutline {
+                name: name.clone(),
+                params: params.clone(),
+                doc: doc.clone(),
+                color: body.iter().find_map(|stmt| match stmt {
+                    TrackStatement::Assignment { target, value, .. } if target == "track.displayColor" => {
+                        display_color(value)
+                    }
+                    _ => None,
+                }),
+                span_start: *span_start,
+                span_end: *span_end,
+            }),
+            _ => None,
+        })
+        .collect();
+    SongStructure { tracks }
+}
+
+/// The resolved value of a `const` declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ConstantValue {
+    Instrument {
+        /// One-line description, e.g. `square oscillator`.
+        summary: String,
+        instrument: InstrumentConfig,
+        /// Presets the instrument loads.
+        preset_refs: Vec<String>,
+    },
+    Number { value: f64 },
+    /// A stored track call: `const hook = riff(lead)`.
+    Performance { track: String, args: Vec<String> },
+    /// The value could not be resolved.
+    Invalid { error: String },
+}
+
+/// A song-level `const` declaration, as listed by `song_constants`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SongConstant {
+    pub name: String,
+    /// The value as written.
+    pub expression: String,
+    pub value: ConstantValue,
+    pub span_start: usize,
+    pub span_end: usize,
+}
+
+/// List the `const` declarations of `program` in source order with their
+/// resolved values. A value that fails to resolve is reported as
+/// `Invalid` rather than failing the whole list.
+pub fn song_constants(program: &Program) -> Vec<SongConstant> {
+    let mut ctx = CompileCtx::new(false);
+    let track_names: Vec<&str> = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::TrackDef { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut constants = Vec::new();
+    for stmt in &program.statements {
+        let (name, value, span_start, span_end) = match stmt {
+            Statement::ConstDecl { name, value, span_start, span_end } => (name, value, *span_start, *span_end),
+            // Split points are read in the song's octave numbering
+            Statement::Assignment { target, value, .. } if target == "song.middleC" => {
+                let _ = compile_assignment(&mut ctx, target, value, stmt.span());
+                continue;
+            }
+            _ => continue,
+        };
+        let resolved = match value {
+            Expr::Number(value) => ConstantValue::Number { value: *value },
+            Expr::FunctionCall { function, args } if track_names.contains(&function.as_str()) => {
+                ConstantValue::Performance {
+                    track: function.clone(),
+                    args: args.iter().map(expr_to_string).collect(),
+                }
+            }
+            _ => match evaluate_instrument_expr(&ctx, value) {
+                Ok(instrument) => {
+                    ctx.consts.insert(name.clone(), instrument.clone());
+                    ConstantValue::Instrument {
+                        summary: instrument.summary(),
+                        preset_refs: instrument.preset_refs(),
+                        instrument,
+                    }
+                }
+                Err(error) => ConstantValue::Invalid { error },
+            },
+        };
+        constants.push(SongConstant {
+            name: name.clone(),
+            expression: expr_to_string(value),
+            value: resolved,
+            span_start,
+            span_end,
+        });
+    }
+    constants
+}
+
+// ── Instrument Usage ────────────────────────────────────────
+
+/// A stretch of beats, from the first note's onset to the end of the
+/// last note's gate.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BeatRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// One instrument as used by a track.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct InstrumentUsage {
+    /// One-line description, e.g. `preset Piano`.
+    pub summary: String,
+    pub instrument: InstrumentConfig,
+    pub preset_refs: Vec<String>,
+    /// Runs of consecutive notes on this instrument, in time order.
+    pub ranges: Vec<BeatRange>,
+    pub note_count: usize,
+}
+
+/// The instruments a track plays, as listed by `instrument_usage`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct TrackInstruments {
+    /// None = notes placed outside any track.
+    pub track_name: Option<String>,
+    pub instruments: Vec<InstrumentUsage>,
+    pub note_count: usize,
+    /// Lowest pitch played.
+    pub lowest_pitch: Option<String>,
+    /// Highest pitch played.
+    pub highest_pitch: Option<String>,
+}
+
+/// Per track, the instruments its notes use and when, with note counts
+/// and pitch range. Tracks are listed in order of their first note.
+pub fn instrument_usage(event_list: &EventList) -> Vec<TrackInstruments> {
+    let mut notes: Vec<(&Event, f64, &InstrumentConfig, &str)> = event_list
+        .events
+        .iter()
+        .filter_map(|e| match &e.kind {
+            EventKind::Note { pitch, gate, instrument, .. } => {
+                Some((e, *gate, event_list.instruments.get(*instrument)?, pitch.as_str()))
+            }
+            _ => None,
+        })
+        .collect();
+    notes.sort_by(|a, b| a.0.time.partial_cmp(&b.0.time).unwrap());
+
+    let mut tracks: Vec<TrackInstruments> = Vec::new();
+    // Per track, the instrument index of its previous note
+    let mut previous: Vec<Option<usize>> = Vec::new();
+    for (event, gate, instrument, pitch) in notes {
+        let t = match tracks.iter().position(|t| t.track_name == event.track_name) {
+            Some(t) => t,
+            None => {
+                tracks.push(TrackInstruments {
+                    track_name: event.track_name.clone(),
+                    instruments: Vec::new(),
+                    note_count: 0,
+                    lowest_pitch: None,
+                    highest_pitch: None,
+                });
+                previous.push(None);
+                tracks.len() - 1
+            }
+        };
+        let track = &mut tracks[t];
+        track.note_count += 1;
+        if let Some(midi) = note_to_midi(pitch) {
+            let lower = |p: &Option<String>| p.as_deref().and_then(note_to_midi).is_none_or(|m| midi < m);
+            let higher = |p: &Option<String>| p.as_deref().and_then(note_to_midi).is_none_or(|m| midi > m);
+            if lower(&track.lowest_pitch) {
+                track.lowest_pitch = Some(pitch.to_string());
+            }
+            if higher(&track.highest_pitch) {
+                track.highest_pitch = Some(pitch.to_string());
+            }
+        }
+
+        let i = match track.instruments.iter().position(|u| u.instrument == *instrument) {
+            Some(i) => i,
+            None => {
+                track.instruments.push(InstrumentUsage {
+                    summary: instrument.summary(),
+                    instrument: instrument.clone(),
+                    preset_refs: instrument.preset_refs(),
+                    ranges: Vec::new(),
+                    note_count: 0,
+                });
+                track.instruments.len() - 1
+            }
+        };
+        let usage = &mut track.instruments[i];
+        usage.note_count += 1;
+        let end = event.time + gate;
+        match usage.ranges.last_mut() {
+            Some(range) if previous[t] == Some(i) => range.end = range.end.max(end),
+            _ => usage.ranges.push(BeatRange { start: event.time, end }),
+        }
+        previous[t] = Some(i);
+    }
+    tracks
+}
+
+// ── Bars & Metronome ────────────────────────────────────────
+
+/// Time signature changes in `events`, as (beat, signature) pairs in time
+/// order. Always starts with the 4/4 default at beat 0.
+///
+/// A signature set inside a track lasts until the track ends, even when
+/// other tracks play alongside it; `track_time_signature_changes` gives
+/// the bars as one track sees them.
+pub fn time_signature_changes(events: &[Event]) -> Vec<(f64, TimeSignature)> {
+    collect_time_signatures(events.iter())
+}
+
+/// Time signature changes as seen by `track`: those set outside any track
+/// and those of the track itself.
+pub fn track_time_signature_changes(events: &[Event], track: &str) -> Vec<(f64, TimeSignature)> {
+    collect_time_signatures(events.iter().filter(|e| e.track_name.as_deref().is_none_or(|t| t == track)))
+}
+
+fn collect_time_signatures<'a>(events: impl Iterator<Item = &'a Event>) -> Vec<(f64, TimeSignature)> {
+    let mut changes = vec![(0.0, TimeSignature::default())];
+    let mut sig_events: Vec<(f64, TimeSignature)> = events
+        .filter_map(|e| match &e.kind {
+            EventKind::SetProperty { target, value, .. } if target == "track.timeSignature" => {
+                TimeSignature::parse(value).map(|sig| (e.time, sig))
+            }
+            _ => None,
+        })
+        .collect();
+    sig_events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    for (time, sig) in sig_events {
+        // A change at the same beat replaces the previous one
+        if changes.last().is_some_and(|(t, _)| (*t - time).abs() < 1e-9) {
+            changes.pop();
+        }
+        changes.push((time, sig));
+    }
+    changes
+}
+
+/// Locate `beat` in bars when the song opens with a pickup of
+/// `anacrusis` beats. The pickup is bar 0, positioned at the end of a full
+/// bar (a one-beat pickup in 4/4 is on beat 3 of bar 0), and bar 1 starts
+/// when it ends. Time signature changes inside the pickup take effect at
+/// bar 1.
+pub fn bar_position_with_pickup(
+    changes: &[(f64, TimeSignature)],
+    anacrusis: f64,
+    beat: f64,
+) -> BarPosition {
+    if anacrusis <= 0.0 {
+        return bar_position(changes, beat);
+    }
+    if beat < anacrusis - 1e-9 {
+        let sig = changes.first().map_or_else(TimeSignature::default, |c| c.1);
+        return BarPosition {
+            bar: 0,
+            beat_in_bar: (sig.beats_per_bar() - anacrusis + beat).max(0.0),
+            time_signature: sig,
+        };
+    }
+    bar_position(&after_pickup(changes, anacrusis), beat)
+}
+
+/// Time signature changes with the bar grid starting at the end of the
+/// pickup.
+fn after_pickup(changes: &[(f64, TimeSignature)], anacrusis: f64) -> Vec<(f64, TimeSignature)> {
+    changes.iter().map(|&(t, sig)| (t.max(anacrusis), sig)).collect()
+}
+
+/// Locate `beat` in bars. A time signature change always starts a new
+/// bar, even if the previous bar was incomplete.
+pub fn bar_position(changes: &[(f64, TimeSignature)], beat: f64) -> BarPosition {
+    let mut bar = 1u32;
+    let mut current = (0.0, TimeSignature::default());
+    for (i, &(start, sig)) in changes.iter().enumerate() {
+        if start > beat && i > 0 {
+            break;
+        }
+        if i > 0 {
+            // Count the bars (including a partial one) of the previous segment
+            let len = start - current.0;
+            bar += (len / current.1.beats_per_bar() - 1e-9).ceil().max(0.0) as u32;
+        }
+        current = (start, sig);
+    }
+    let (start, sig) = current;
+    let offset = (beat - start).max(0.0);
+    let bars_in = (offset / sig.beats_per_bar() + 1e-9).floor();
+    BarPosition {
+        bar: bar + bars_in as u32,
+        beat_in_bar: (offset - bars_in * sig.beats_per_bar()).max(0.0),
+        time_signature: sig,
+    }
+}
+
+/// Metronome clicks for the whole song: one per time-signature unit,
+/// accented according to `TimeSignature::accent_pattern`.
+pub fn metronome_clicks(event_list: &EventList) -> Vec<MetronomeClick> {
+    let mut changes = time_signature_changes(&event_list.events);
+    let mut clicks = Vec::new();
+    if let Some(anacrusis) = event_list.anacrusis.filter(|a| *a > 0.0) {
+        // Count the pickup's clicks back from the end of bar 0
+        let sig = changes[0].1;
+        let accents = sig.accent_pattern();
+        let n = accents.len();
+        let mut units_back = 1usize;
+        while anacrusis - units_back as f64 * sig.unit_beats() > -1e-9 {
+            clicks.push(MetronomeClick {
+                time: (anacrusis - units_back as f64 * sig.unit_beats()).max(0.0),
+                accent: accents[(n - units_back % n) % n],
+            });
+            units_back += 1;
+        }
+        clicks.reverse();
+        changes = after_pickup(&changes, anacrusis);
+    }
+    for (i, &(start, sig)) in changes.iter().enumerate() {
+        let end = changes
+            .get(i + 1)
+            .map_or(event_list.total_beats, |(t, _)| *t);
+        let accents = sig.accent_pattern();
+        let mut unit = 0usize;
+        loop {
+            let time = start + unit as f64 * sig.unit_beats();
+            if time >= end - 1e-9 {
+                break;
+            }
+            clicks.push(MetronomeClick {
+                time,
+                accent: accents[unit % accents.len()],
+            });
+            unit += 1;
+        }
+    }
+    clicks
+}
+
+// ── Tempo Map ───────────────────────────────────────────────
+
+/// Piecewise-constant tempo built from `track.beatsPerMinute` events,
+/// used to convert beat positions to seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    /// (beat, seconds at that beat, bpm from that beat on), in time order.
+    segments: Vec<(f64, f64, f64)>,
+}
+
+impl TempoMap {
+    /// Build the tempo map for `events`, starting at `default_bpm`.
+    pub fn from_events(events: &[Event], default_bpm: f64) -> Self {
+        let mut changes: Vec<(f64, f64)> = events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::SetProperty { target, value, .. } if target == "track.beatsPerMinute" => {
+                    value.parse::<f64>().ok().filter(|v| *v > 0.0).map(|v| (e.time, v))
+                }
+                _ => None,
+            })
+            .collect();
+        changes.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut segments = vec![(0.0, 0.0, default_bpm)];
+        for (beat, bpm) in changes {
+            let beat = beat.max(0.0);
+            let seconds = Self::seconds_in(&segments, beat);
+            if segments.last().is_some_and(|(b, _, _)| (*b - beat).abs() < 1e-9) {
+                segments.pop();
+            }
+            segments.push((beat, seconds, bpm));
+        }
+        TempoMap { segments }
+    }
+
+    fn seconds_in(segments: &[(f64, f64, f64)], beat: f64) -> f64 {
+        let idx = segments
+            .iter()
+            .rposition(|(b, _, _)| *b <= beat)
+            .unwrap_or(0);
+        let (start_beat, start_seconds, bpm) = segments[idx];
+        start_seconds + (beat - start_beat) * 60.0 / bpm
+    }
+
+    /// Time in seconds at `beat`.
+    pub fn seconds_at(&self, beat: f64) -> f64 {
+        Self::seconds_in(&self.segments, beat)
+    }
+
+    /// Beat at `seconds`; before the song starts, at the starting tempo.
+    pub fn beat_at(&self, seconds: f64) -> f64 {
+        let (beat, start, bpm) = self
+            .segments
+            .iter()
+            .rev()
+            .find(|(_, s, _)| *s <= seconds)
+            .copied()
+            .unwrap_or(self.segments[0]);
+        beat + (seconds - start) * bpm / 60.0
+    }
+
+    /// The beat and tempo of each change, in time order.
+    pub fn changes(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.segments.iter().map(|(beat, _, bpm)| (*beat, *bpm))
+    }
+
+    /// Tempo in effect at `beat`.
+    pub fn bpm_at(&self, beat: f64) -> f64 {
+        self.segments
+            .iter()
+            .rev()
+            .find(|(b, _, _)| *b <= beat)
+            .map_or(self.segments[0].2, |s| s.2)
+    }
+}
+
+/// One bar of the song, for drawing bar lines and rulers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BarInfo {
+    /// Bar number, starting at 1.
+    pub bar: u32,
+    /// Start of the bar in beats.
+    pub start_beat: f64,
+    /// Start of the bar in seconds.
+    pub start_seconds: f64,
+    /// Length of the bar in beats (shorter if cut off by a time signature
+    /// change or the end of the song).
+    pub length_beats: f64,
+    pub time_signature: TimeSignature,
+    /// Tempo at the start of the bar.
+    pub bpm: f64,
+}
+
+/// List every bar from beat 0 to the end of the song, with start times
+/// in seconds from the tempo map. A pickup (`song.anacrusis`) is listed
+/// as bar 0.
+pub fn time_map(event_list: &EventList, default_bpm: f64) -> Vec<BarInfo> {
+    let tempo = TempoMap::from_events(&event_list.events, default_bpm);
+    let mut changes = time_signature_changes(&event_list.events);
+    let mut bars = Vec::new();
+    let mut first_bar = 1;
+    if let Some(anacrusis) = event_list.anacrusis.filter(|a| *a > 0.0) {
+        bars.push(BarInfo {
+            bar: 0,
+            start_beat: 0.0,
+            start_seconds: 0.0,
+            length_beats: anacrusis,
+            time_signature: changes[0].1,
+            bpm: tempo.bpm_at(0.0),
+        });
+        changes = after_pickup(&changes, anacrusis);
+        first_bar = 0;
+    }
+    for (i, &(start, sig)) in changes.iter().enumerate() {
+        let end = changes
+            .get(i + 1)
+            .map_or(event_list.total_beats, |(t, _)| *t);
+        let mut beat = start;
+        while beat < end - 1e-9 {
+            let length = sig.beats_per_bar().min(end - beat);
+            bars.push(BarInfo {
+                bar: bars.len() as u32 + first_bar,
+                start_beat: beat,
+                start_seconds: tempo.seconds_at(beat),
+                length_beats: length,
+                time_signature: sig,
+                bpm: tempo.bpm_at(beat),
+            });
+            beat += length;
+        }
+    }
+    bars
+}
+
+/// A marker or cue with its position in beats and seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MarkerInfo {
+    pub kind: MarkerKind,
+    pub name: String,
+    pub beat: f64,
+    pub seconds: f64,
+    /// Track that placed it (None = top-level).
+    pub track_name: Option<String>,
+}
+
+/// The song's markers and cues in time order, with times in seconds from
+/// the tempo map.
+pub fn markers(event_list: &EventList, default_bpm: f64) -> Vec<MarkerInfo> {
+    let tempo = TempoMap::from_events(&event_list.events, default_bpm);
+    event_list
+        .events
+        .iter()
+        .filter_map(|e| match &e.kind {
+            EventKind::Marker { kind, name } => Some(MarkerInfo {
+                kind: *kind,
+                name: name.clone(),
+                beat: e.time,
+                seconds: tempo.seconds_at(e.time),
+                track_name: e.track_name.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// ── Note Lint ───────────────────────────────────────────────
+
+/// Beats a note may ring past the next marker before it is flagged.
+const MARKER_OVERHANG_BEATS: f64 = 2.0;
+
+/// What `lint_notes` found wrong with a note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum NoteIssueKind {
+    /// The same pitch starts again on the same track while still held.
+    Retrigger,
+    /// The note has no length, so it never sounds.
+    ZeroLength,
+    /// The note is held well past the next marker (section boundary).
+    PastMarker,
+}
+
+/// A note that probably does not sound as intended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NoteIssue {
+    pub kind: NoteIssueKind,
+    pub message: String,
+    pub beat: f64,
+    /// Track that played the note (None = top-level).
+    pub track_name: Option<String>,
+    pub span_start: usize,
+    pub span_end: usize,
+}
+
+/// Flag notes that retrigger a pitch still held on their track, notes of
+/// zero length and notes held more than a couple of beats past the next
+/// marker, in time order.
+pub fn lint_notes(event_list: &EventList) -> Vec<NoteIssue> {
+    let marker_beats: Vec<f64> = event_list
+        .events
+        .iter()
+        .filter(|e| matches!(e.kind, EventKind::Marker { kind: MarkerKind::Marker, .. }))
+        .map(|e| e.time)
+        .collect();
+    // (track, pitch, held until) of notes seen so far
+    let mut held: Vec<(Option<&str>, i32, f64)> = Vec::new();
+    let mut issues = Vec::new();
+
+    for event in &event_list.events {
+        let EventKind::Note { pitch, gate, source_start, source_end, .. } = &event.kind else {
+            continue;
+        };
+        let track = event.track_name.as_deref();
+        let mut issue = |kind, message| {
+            issues.push(NoteIssue {
+                kind,
+                message,
+                beat: event.time,
+                track_name: event.track_name.clone(),
+                span_start: *source_start,
+                span_end: *source_end,
+            })
+        };
+
+        if *gate <= 0.0 {
+            issue(NoteIssueKind::ZeroLength, format!("{pitch} has no length and will not sound."));
+            continue;
+        }
+        if let Some(midi) = note_to_midi(pitch) {
+            let end = event.time + gate;
+            if held.iter().any(|&(t, m, until)| t == track && m == midi && until > event.time) {
+                issue(NoteIssueKind::Retrigger, format!("{pitch} starts again while the previous {pitch} is still held."));
+            }
+            held.retain(|&(_, _, until)| until > event.time);
+            held.push((track, midi, end));
+        }
+        if let Some(&marker) = marker_beats.iter().find(|&&m| m > event.time)
+            && event.time + gate > marker + MARKER_OVERHANG_BEATS
+        {
+            issue(
+                NoteIssueKind::PastMarker,
+                format!("{pitch} is held {} beats past the marker at beat {marker}.", event.time + gate - marker),
+            );
+        }
+    }
+    issues
+}
+
+// ── Optimization ────────────────────────────────────────────
+
+/// What `compile_optimized` changed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OptimizationReport {
+    /// Operator expressions replaced by their value.
+    pub folded_expressions: usize,
+    /// Tracks defined but never played, in source order.
+    pub removed_tracks: Vec<String>,
+    /// SetProperty events dropped because they repeat the current value.
+    pub merged_properties: usize,
+}
+
+/// The result of `compile_optimized`.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OptimizedCompile {
+    pub event_list: EventList,
+    pub report: OptimizationReport,
+}
+
+/// Compile with the optimization pass: constants are folded and unused
+/// tracks removed before compiling, and repeated property changes are
+/// dropped after. The result plays the same as the plain compile.
+pub fn compile_optimized(program: &Program, strict: bool) -> Result<OptimizedCompile, String> {
+    let mut program = program.clone();
+    let folded_expressions = fold_constants(&mut program);
+    let removed_tracks = remove_unused_tracks(&mut program);
+    let mut event_list = if strict { compile_strict(&program)? } else { compile(&program)? };
+    let merged_properties = merge_redundant_properties(&mut event_list);
+    Ok(OptimizedCompile {
+        event_list,
+        report: OptimizationReport { folded_expressions, removed_tracks, merged_properties },
+    })
+}
+
+/// Replace operators on literals (`2 * 3`, `'C' + 4`) with their value.
+/// Operators that would fail, like a division by zero, are left for the
+/// compiler to report. Returns how many were folded.
+pub fn fold_constants(program: &mut Program) -> usize {
+    program
+        .statements
+        .iter_mut()
+        .map(|stmt| match stmt {
+            Statement::TrackDef { body, .. } => fold_track_body(body),
+            Statement::TrackCall { args, .. } => args.iter_mut().map(fold_expr).sum(),
+            Statement::ConstDecl { value, .. } | Statement::Assignment { value, .. } => fold_expr(value),
+            _ => 0,
+        })
+        .sum()
+}
+
+fn fold_track_body(body: &mut [TrackStatement]) -> usize {
+    body.iter_mut()
+        .map(|stmt| match stmt {
+            TrackStatement::Assignment { value, .. } | TrackStatement::Let { value, .. } => fold_expr(value),
+            TrackStatement::TrackCall { args, .. } => args.iter_mut().map(fold_expr).sum(),
+            TrackStatement::ForLoop { init, condition, update, body, .. } => {
+                let clauses: usize = [init, update]
+                    .into_iter()
+                    .flatten()
+                    .map(|clause| match clause {
+                        LoopClause::Let { value, .. } | LoopClause::Assign { value, .. } => fold_expr(value),
+                        LoopClause::Step { .. } => 0,
+                    })
+                    .sum();
+                clauses + condition.as_mut().map_or(0, fold_expr) + fold_track_body(body)
+            }
+            TrackStatement::Poly { body, .. } | TrackStatement::Maybe { body, .. } => fold_track_body(body),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Fold `expr` from the leaves up; returns how many operators were folded.
+fn fold_expr(expr: &mut Expr) -> usize {
+    let folded = match expr {
+        Expr::Binary { left, right, .. } => fold_expr(left) + fold_expr(right),
+        Expr::Unary { operand, .. } => fold_expr(operand),
+        Expr::Array(items) | Expr::FunctionCall { args: items, .. } => items.iter_mut().map(fold_expr).sum(),
+        Expr::ObjectLit(pairs) => pairs.iter_mut().map(|(_, value)| fold_expr(value)).sum(),
+        _ => return 0,
+    };
+    let value = match expr {
+        Expr::Binary { op, left, right } => match (literal_value(left), literal_value(right)) {
+            (Some(l), Some(r)) => apply_binary(*op, l, r).ok(),
+            _ => None,
+        },
+        Expr::Unary { op, operand } => match (op, literal_value(operand)) {
+            (UnaryOp::Not, Some(v)) => Some(Value::from_bool(!v.is_truthy())),
+            (UnaryOp::Neg, Some(Value::Number(n))) => Some(Value::Number(-n)),
+            _ => None,
+        },
+        _ => None,
+    };
+    match value {
+        Some(value) => {
+            *expr = value.to_expr();
+            folded + 1
+        }
+        None => folded,
+    }
+}
+
+/// The value of a number or string literal. Identifiers may be `let`
+/// variables, so they are never constant.
+fn literal_value(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::Number(n) => Some(Value::Number(*n)),
+        Expr::StringLit(s) => Some(Value::Text(s.clone())),
+        _ => None,
+    }
+}
+
+/// Remove track definitions that nothing plays, directly or through other
+/// tracks. Returns the removed names in source order.
+pub fn remove_unused_tracks(program: &mut Program) -> Vec<String> {
+    let mut pending = Vec::new();
+    for stmt in &program.statements {
+        match stmt {
+            Statement::TrackCall { name, args, .. } => {
+                pending.push(name.clone());
+                args.iter().for_each(|arg| expr_names(arg, &mut pending));
+            }
+            Statement::ConstDecl { value, .. } | Statement::Assignment { value, .. } => {
+                expr_names(value, &mut pending)
+            }
+            _ => {}
+        }
+    }
+
+    let mut used: Vec<String> = Vec::new();
+    while let Some(name) = pending.pop() {
+        if used.contains(&name) {
+            continue;
+        }
+        for stmt in &program.statements {
+            if let Statement::TrackDef { name: def, body, .. } = stmt
+                && *def == name
+            {
+                body_names(body, &mut pending);
+            }
+        }
+        used.push(name);
+    }
+
+    let mut removed = Vec::new();
+    program.statements.retain(|stmt| match stmt {
+        Statement::TrackDef { name, .. } if !used.contains(name) => {
+            removed.push(name.clone());
+            false
+        }
+        _ => true,
+    });
+    removed
+}
+
+/// Names a track body may play: its track calls, plus every identifier
+/// and function name in its expressions.
+fn body_names(body: &[TrackStatement], names: &mut Vec<String>) {
+    for stmt in body {
+        match stmt {
+            TrackStatement::TrackCall { name, args, .. } => {
+                names.push(name.clone());
+                args.iter().for_each(|arg| expr_names(arg, names));
+            }
+            TrackStatement::Assignment { value, .. } | TrackStatement::Let { value, .. } => expr_names(value, names),
+            TrackStatement::ForLoop { init, condition, update, body, .. } => {
+                for clause in [init, update].into_iter().flatten() {
+                    if let LoopClause::Let { value, .. } | LoopClause::Assign { value, .. } = clause {
+                        expr_names(value, names);
+                    }
+                }
+                if let Some(condition) = condition {
+                    expr_names(condition, names);
+                }
+                body_names(body, names);
+            }
+            TrackStatement::Poly { body, .. } | TrackStatement::Maybe { body, .. } => body_names(body, names),
+            _ => {}
+        }
+    }
+}
+
+fn expr_names(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Identifier(name) => names.push(name.clone()),
+        Expr::FunctionCall { function, args } => {
+            names.push(function.clone());
+            args.iter().for_each(|arg| expr_names(arg, names));
+        }
+        Expr::Array(items) => items.iter().for_each(|item| expr_names(item, names)),
+        Expr::ObjectLit(pairs) => pairs.iter().for_each(|(_, value)| expr_names(value, names)),
+        Expr::Binary { left, right, .. } => {
+            expr_names(left, names);
+            expr_names(right, names);
+        }
+        Expr::Unary { operand, .. } => expr_names(operand, names),
+        _ => {}
+    }
+}
+
+/// Drop SetProperty events that set a property to the value the same
+/// track last set it to, with no other track changing it in between.
+/// Returns how many were dropped.
+pub fn merge_redundant_properties(event_list: &mut EventList) -> usize {
+    // (target, track, value) of the latest change to each property
+    let mut current: Vec<(String, Option<String>, String)> = Vec::new();
+    let before = event_list.events.len();
+    event_list.events.retain(|event| {
+        let EventKind::SetProperty { target, value, .. } = &event.kind else {
+            return true;
+        };
+        match current.iter_mut().find(|(t, ..)| t == target) {
+            Some((_, track, v)) if *track == event.track_name && v == value => false,
+            Some((_, track, v)) => {
+                *track = event.track_name.clone();
+                *v = value.clone();
+                true
+            }
+            None => {
+                current.push((target.clone(), event.track_name.clone(), value.clone()));
+                true
+            }
+        }
+    });
+    before - event_list.events.len()
+}
+
+// ── Cursor Context Query ────────────────────────────────────
+
+/// Determine the compilation state at a given byte offset in the source.
+///
+/// Parses the source, then walks the AST in order, compiling statements whose
+/// `span_start <= cursor_byte_offset`. When the cursor falls inside a track
+/// definition body, descends into that body and stops at the right statement.
+///
+/// Returns the accumulated instrument, BPM, tuning, beat position, etc.
+pub fn cursor_context(source: &str, cursor_byte_offset: usize) -> Result<CursorContext, String> {
+    let program = crate::parse(source).map_err(|e| e.to_string())?;
+    let mut ctx = CompileCtx::new(false);
+    ctx.language_version = program.language_version;
+    let mut bpm: f64 = 120.0;
+    let mut tuning: f64 = 440.0;
+
+    // First pass: collect track definitions.
+    for stmt in &program.statements {
+        if let Statement::TrackDef { name, params, body, .. } = stmt {
+            ctx.track_defs.push(TrackDef {
+                name: name.clone(),
+                params: params.clone(),
+                body: body.clone(),
+            });
+        }
+    }
+
+    // Second pass: walk statements up to the cursor.
+    for stmt in &program.statements {
+        let (ss, se) = stmt.span();
+
+        // Past the cursor — stop.
+        if ss > cursor_byte_offset {
+            break;
+        }
+
+        // Cursor is inside a track definition — descend into body.
+        if let Statement::TrackDef { body, name, .. } = stmt {
+            if cursor_byte_offset <= se {
+                ctx.current_track_name = Some(name.clone());
+                cursor_walk_track_body(&mut ctx, body, cursor_byte_offset)?;
+                extract_bpm_tuning(&ctx.events, &mut bpm, &mut tuning);
+                return Ok(build_cursor_context(&ctx, bpm, tuning));
+            }
+        }
+
+        // Compile the statement normally.
+        compile_statement(&mut ctx, stmt)?;
+        extract_bpm_tuning(&ctx.events, &mut bpm, &mut tuning);
+    }
+
+    Ok(build_cursor_context(&ctx, bpm, tuning))
+}
+
+/// Walk a track body up to the cursor byte offset, compiling each statement.
+fn cursor_walk_track_body(
+    ctx: &mut CompileCtx,
+    body: &[TrackStatement],
+    cursor_byte_offset: usize,
+) -> Result<(), String> {
+    for stmt in body {
+        let (ss, _se) = stmt.span();
+        if ss > cursor_byte_offset {
+            break;
+        }
+        compile_track_statement(ctx, stmt)?;
+    }
+    Ok(())
+}
+
+/// Scan emitted events for the latest BPM and tuning property changes.
+fn extract_bpm_tuning(events: &[Event], bpm: &mut f64, tuning: &mut f64) {
+    for event in events {
+        if let EventKind::SetProperty { target, value, .. } = &event.kind {
+            match target.as_str() {
+                "track.beatsPerMinute" => {
+                    if let Ok(v) = value.parse::<f64>() {
+                        *bpm = v;
+                    }
+                }
+                "track.tuningPitch" => {
+                    if let Ok(v) = value.parse::<f64>() {
+                        *tuning = v;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Build a CursorContext from the current compile state.
+fn build_cursor_context(ctx: &CompileCtx, bpm: f64, tuning: f64) -> CursorContext {
+    let changes = match &ctx.current_track_name {
+        Some(track) => track_time_signature_changes(&ctx.events, track),
+        None => time_signature_changes(&ctx.events),
+    };
+    let position = bar_position_with_pickup(&changes, ctx.anacrusis, ctx.cursor);
+    CursorContext {
+        instrument: ctx.current_instrument.clone(),
+        track_name: ctx.current_track_name.clone(),
+        note_length: ctx.default_note_length,
+        bpm,
+        tuning_pitch: tuning,
+        cursor_beat: ctx.cursor,
+        time_signature: position.time_signature,
+        bar: position.bar,
+        beat_in_bar: position.beat_in_bar,
+        middle_c: ctx.middle_c,
+        velocity_scale: ctx.velocity_scale,
+        key_coverage: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn oscillator(instrument: &InstrumentConfig) -> &OscillatorConfig {
+        match instrument {
+            InstrumentConfig::Oscillator(osc) => osc,
+            other => panic!("Expected an oscillator, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_compile_simple_track() {
+        let program = parse(
+            r#"
+track riff() {
+    C3 /2
+    D3 /4
+    E3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        assert_eq!(events.total_beats, 1.0); // 0.5 + 0.25 + 0.25
+
+        let notes: Vec<_> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(notes.len(), 3);
+        assert_eq!(notes[0], (0.0, "C3"));
+        assert_eq!(notes[1], (0.5, "D3"));
+        assert_eq!(notes[2], (0.75, "E3"));
+    }
+
+    #[test]
+    fn test_compile_track_with_rest() {
+        let program = parse(
+            r#"
+track t() {
+    C3 /4
+    4
+    D3 /4
+}
+t();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        // 0.25 (C3) + 4.0 (rest) + 0.25 (D3) = 4.5
+        assert_eq!(events.total_beats, 4.5);
+
+        let notes: Vec<_> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(notes[0], (0.0, "C3"));
+        assert_eq!(notes[1], (4.25, "D3"));
+    }
+
+    #[test]
+    fn test_song_length_ends_at_last_rest() {
+        // Per plan: song ends after the last rest ends, not when last note finishes.
+        let program = parse(
+            r#"
+track t() {
+    C3 /4
+    D3 /4
+}
+t();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        // Two notes, each stepping 0.25 beats.
+        // Cursor ends at 0.5, even though the last note (D3) plays for default duration.
+        assert_eq!(events.total_beats, 0.5);
+    }
+
+    #[test]
+    fn test_compile_chord() {
+        let program = parse(
+            r#"
+track t() {
+    [C3, E3, G3]@1 /2
+}
+t();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+
+        let notes: Vec<_> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, gate, .. } => {
+                    Some((e.time, pitch.as_str(), *gate))
+                }
+                _ => None,
+            })
+            .collect();
+
+        // All three notes fire at time 0, each with audible gate 1 beat.
+        assert_eq!(notes.len(), 3);
+        for (time, _, g) in &notes {
+            assert_eq!(*time, 0.0);
+            assert_eq!(*g, 1.0);
+        }
+        // Step duration /2 = 0.5 beats.
+        assert_eq!(events.total_beats, 0.5);
+    }
+
+    #[test]
+    fn test_compile_velocity() {
+        let program = parse(
+            r#"
+track t() {
+    C3*80 /4
+}
+t();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        match &events.events[0].kind {
+            EventKind::Note { velocity, .. } => assert_eq!(*velocity, 80.0),
+            other => panic!("Expected Note, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_relative_velocity() {
+        let velocities = |src: &str| -> Vec<f64> {
+            compile(&parse(src).unwrap())
+                .unwrap()
+                .events
+                .iter()
+                .filter_map(|e| match &e.kind {
+                    EventKind::Note { velocity, .. } => Some(*velocity),
+                    _ => None,
+                })
+                .collect()
+        };
+        let src = "track t() {\n    track.velocity = 90;\n    C4*+10 1\n    D4*-15 1\n    E4*+60 1\n    F4*70 1\n}\nt();\ntrack u() {\n    C4*+10 1\n}\nu();";
+        // `track.velocity` stays inside `t`; `u` (sorted in at beat 0)
+        // accents from the default 100
+        assert_eq!(velocities(src), vec![100.0, 110.0, 75.0, 127.0, 70.0]);
+
+        let unit = "song.velocityScale = \"unit\";\ntrack t() {\n    track.velocity = 0.5;\n    C4*+0.25 1\n}\nt();";
+        assert_eq!(velocities(unit), vec![63.5 + 31.75]);
+    }
+
+    #[test]
+    fn test_track_velocity_default() {
+        let src = "track t() {\n    track.velocity = 70;\n    C4 1\n    [C4, E4] 1\n    G4*90 1\n}\nt();\ntrack u() {\n    C5 1\n}\nu();";
+        let velocities: Vec<(String, f64)> = compile(&parse(src).unwrap())
+            .unwrap()
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, velocity, .. } => Some((pitch.clone(), *velocity)),
+                _ => None,
+            })
+            .collect();
+        let expected = [("C4", 70.0), ("C5", 100.0), ("C4", 70.0), ("E4", 70.0), ("G4", 90.0)];
+        assert_eq!(velocities, expected.map(|(p, v)| (p.to_string(), v)));
+    }
+
+    #[test]
+    fn test_articulation_marks() {
+        let notes = |src: &str| -> Vec<(f64, f64)> {
+            compile(&parse(src).unwrap())
+                .unwrap()
+                .events
+                .iter()
+                .filter_map(|e| match &e.kind {
+                    EventKind::Note { velocity, gate, .. } => Some((*velocity, *gate)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let src = "track t() {\n    track.noteLength = 1/2;\n    C4! 1\n    D4' 1\n    E4_ 1\n    F4!' 2\n}\nt();";
+        assert_eq!(notes(src), vec![(120.0, 0.5), (100.0, 0.25), (100.0, 1.0), (120.0, 0.25)]);
+
+        let tuned = "track t() {\n    track.accent = 40;\n    track.staccato = 0.25;\n    track.legato = 0.9;\n    C4!*100 1\n    D4' 1\n    E4_ 2\n}\nt();";
+        assert_eq!(notes(tuned), vec![(127.0, 1.0), (100.0, 0.25), (100.0, 1.8)]);
+
+        let err = compile(&parse("track t() {\n    track.staccato = 2;\n}\nt();").unwrap()).unwrap_err();
+        assert!(err.contains("track.staccato"), "{err}");
+    }
+
+    #[test]
+    fn test_velocity_scale() {
+        let velocities = |src: &str| -> Vec<f64> {
+            compile(&parse(src).unwrap())
+                .unwrap()
+                .events
+                .iter()
+                .filter_map(|e| match &e.kind {
+                    EventKind::Note { velocity, .. } => Some(*velocity),
+                    _ => None,
+                })
+                .collect()
+        };
+        let src = "song.velocityScale = \"unit\";\ntrack t() {\n    C4*0.5 1\n    D4*1 1\n    E4 1\n}\nt();";
+        assert_eq!(velocities(src), vec![63.5, 127.0, 100.0]);
+        let ctx = cursor_context(src, src.find("E4").unwrap()).unwrap();
+        assert_eq!(ctx.velocity_scale, VelocityScale::Unit);
+
+        let err = compile(&parse("song.velocityScale = \"unit\";\ntrack t() {\n    C4*80 1\n}\nt();").unwrap()).unwrap_err();
+        assert_eq!(err, "Velocity 80 is out of range. Expected 0 to 1 (song.velocityScale is 'unit').");
+        let err = compile(&parse("track t() {\n    C4*200 1\n}\nt();").unwrap()).unwrap_err();
+        assert_eq!(err, "Velocity 200 is out of range. Expected 0 to 127.");
+        let err = compile(&parse("song.velocityScale = \"percent\";").unwrap()).unwrap_err();
+        assert!(err.contains("song.velocityScale"), "{err}");
+    }
+
+    #[test]
+    fn test_compile_track_call_with_step() {
+        let program = parse(
+            r#"
+track a() {
+    C3 /4
+}
+a() 8;
+a();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+
+        let notes: Vec<_> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        // First call: C3 at 0.0, then step 8 beats.
+        // Second call: C3 at 8.0.
+        assert_eq!(notes[0], (0.0, "C3"));
+        assert_eq!(notes[1], (8.0, "C3"));
+    }
+
+    #[test]
+    fn test_compile_default_duration_override() {
+        let program = parse(
+            r#"
+track t() {
+    track.duration = 1/4;
+    C3
+    D3
+}
+t();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        // Each note uses default step = 0.25 beats.
+        assert_eq!(events.total_beats, 0.5);
+
+        let notes: Vec<_> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(notes[0], (0.0, "C3"));
+        assert_eq!(notes[1], (0.25, "D3"));
+    }
+
+    #[test]
+    fn test_default_instrument_on_notes() {
+        // Notes without explicit instrument get the default Triangle config.
+        let program = parse(
+            r#"
+track riff() {
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            assert_eq!(oscillator(instrument).waveform, "triangle");
+        }
+    }
+
+    #[test]
+    fn test_const_oscillator_instrument() {
+        let program = parse(
+            r#"
+const synth = Oscillator({type: 'square'});
+track riff() {
+    track.instrument = synth;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            assert_eq!(oscillator(instrument).waveform, "square");
+        }
+    }
+
+    #[test]
+    fn test_track_param_instrument() {
+        // Instrument passed as track parameter — track independence.
+        let program = parse(
+            r#"
+const synth = Oscillator({type: 'sawtooth', attack: 0.05});
+melody(synth);
+
+track melody(inst) {
+    track.instrument = inst;
+    C4 /4
+    E4 /4
+}
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let notes: Vec<_> = events.events.iter().filter(|e| matches!(&e.kind, EventKind::Note { .. })).collect();
+        assert_eq!(notes.len(), 2);
+        for note in &notes {
+            if let EventKind::Note { instrument, .. } = &note.kind {
+                let instrument = &events.instruments[*instrument];
+                assert_eq!(oscillator(instrument).waveform, "sawtooth");
+                assert_eq!(oscillator(instrument).envelope.attack, Some(0.05));
+            }
+        }
+    }
+
+    #[test]
+    fn test_track_scope_isolation() {
+        // Tracks inherit parent state but don't leak changes back.
+        // With async tracks, both start at beat 0 (parallel).
+        let program = parse(
+            r#"
+const sq = Oscillator({type: 'square'});
+const tri = Oscillator({type: 'triangle'});
+
+bass(sq);
+melody(tri);
+
+track bass(inst) {
+    track.instrument = inst;
+    C2 /4
+}
+
+track melody(inst) {
+    track.instrument = inst;
+    C4 /4
+}
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let notes: Vec<_> = events.events.iter().filter_map(|e| match &e.kind {
+            EventKind::Note { pitch, instrument, .. } => {
+                Some((e.time, pitch.as_str(), oscillator(&events.instruments[*instrument]).waveform.as_str()))
+            }
+            _ => None,
+        }).collect();
+
+        // Both tracks start at beat 0 (async/parallel).
+        assert!(notes.iter().any(|(t, p, w)| *t == 0.0 && *p == "C2" && *w == "square"));
+        assert!(notes.iter().any(|(t, p, w)| *t == 0.0 && *p == "C4" && *w == "triangle"));
+    }
+
+    #[test]
+    fn test_events_carry_track_name() {
+        // Events produced inside a track body should carry that track's name.
+        // Top-level events have track_name = None.
+        let program = parse(
+            r#"
+track.beatsPerMinute = 120;
+
+track melody() {
+    C4 /4
+}
+
+track bass() {
+    C2 /4
+}
+
+melody();
+bass();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+
+        // Top-level SetProperty (BPM) should have no track name.
+        let bpm_event = events.events.iter().find(|e| matches!(&e.kind, EventKind::SetProperty { target, .. } if target == "track.beatsPerMinute")).unwrap();
+        assert_eq!(bpm_event.track_name, None);
+
+        // Notes inside "melody" should carry track_name = Some("melody").
+        let melody_note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { pitch, .. } if pitch == "C4")).unwrap();
+        assert_eq!(melody_note.track_name, Some("melody".to_string()));
+
+        // Notes inside "bass" should carry track_name = Some("bass").
+        let bass_note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { pitch, .. } if pitch == "C2")).unwrap();
+        assert_eq!(bass_note.track_name, Some("bass".to_string()));
+    }
+
+    #[test]
+    fn test_string_shorthand_instrument() {
+        let program = parse(
+            r#"
+track riff() {
+    track.instrument = 'square';
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            assert_eq!(oscillator(instrument).waveform, "square");
+        }
+    }
+
+    #[test]
+    fn test_inline_instrument_in_track() {
+        let program = parse(
+            r#"
+track riff() {
+    track.instrument = Oscillator({type: 'sine', release: 0.5});
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            assert_eq!(oscillator(instrument).waveform, "sine");
+            assert_eq!(oscillator(instrument).envelope.release, Some(0.5));
+        }
+    }
+
+    #[test]
+    fn test_instrument_inherits_from_parent() {
+        // Track inherits parent's instrument when not overridden.
+        let program = parse(
+            r#"
+track.instrument = Oscillator({type: 'sawtooth'});
+riff();
+
+track riff() {
+    C3 /4
+}
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            assert_eq!(oscillator(instrument).waveform, "sawtooth");
+        }
+    }
+
+    // ── loadPreset tests ────────────────────────────────────
+
+    #[test]
+    fn test_load_preset_sets_preset_ref() {
+        // loadPreset("name") should set preset_ref on the instrument config.
+        let program = parse(
+            r#"
+const piano = loadPreset("FluidR3_GM/Acoustic Grand Piano");
+track riff() {
+    track.instrument = piano;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            assert_eq!(
+                instrument.preset_ref(),
+                Some("FluidR3_GM/Acoustic Grand Piano")
+            );
+        } else {
+            panic!("Expected Note event");
+        }
+    }
+
+    #[test]
+    fn test_load_preset_emits_preset_ref_event() {
+        // A const decl with loadPreset should emit a PresetRef event for preloading.
+        let program = parse(
+            r#"
+const piano = loadPreset("FluidR3_GM/Acoustic Grand Piano");
+track riff() {
+    track.instrument = piano;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let preset_refs: Vec<_> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::PresetRef { name } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(preset_refs, vec!["FluidR3_GM/Acoustic Grand Piano"]);
+    }
+
+    #[test]
+    fn test_extract_preset_refs() {
+        // extract_preset_refs should collect unique preset references.
+        let program = parse(
+            r#"
+const piano = loadPreset("FluidR3_GM/Acoustic Grand Piano");
+const guitar = loadPreset("FluidR3_GM/Nylon Guitar");
+track riff() {
+    track.instrument = piano;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let event_list = compile(&program).unwrap();
+        let refs = extract_preset_refs(&event_list);
+        assert_eq!(refs.len(), 2);
+        assert!(refs.contains(&"FluidR3_GM/Acoustic Grand Piano".to_string()));
+        assert!(refs.contains(&"FluidR3_GM/Nylon Guitar".to_string()));
+    }
+
+    #[test]
+    fn test_extract_preset_refs_deduplicates() {
+        // Same preset referenced twice should appear only once.
+        let program = parse(
+            r#"
+const a = loadPreset("FluidR3_GM/Piano");
+const b = loadPreset("FluidR3_GM/Piano");
+track riff() {
+    track.instrument = a;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let event_list = compile(&program).unwrap();
+        let refs = extract_preset_refs(&event_list);
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0], "FluidR3_GM/Piano");
+    }
+
+    #[test]
+    fn test_load_preset_default_waveform() {
+        // loadPreset for an external preset produces a sampler reference;
+        // the runtime falls back to the default oscillator if it is missing.
+        let program = parse(
+            r#"
+const p = loadPreset("SomeLibrary/SomeInstrument");
+track riff() {
+    track.instrument = p;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
 
         let events = compile(&program).unwrap();
-        // 0.25 (C3) + 4.0 (rest) + 0.25 (D3) = 4.5
-        assert_eq!(events.total_beats, 4.5);
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            assert!(matches!(instrument, InstrumentConfig::SamplerRef(_)));
+            assert_eq!(
+                instrument.preset_ref(),
+                Some("SomeLibrary/SomeInstrument")
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_preset_oscillator_special_case() {
+        // loadPreset("Oscillator", {type: 'square'}) should configure waveform.
+        let program = parse(
+            r#"
+const osc = loadPreset("Oscillator", {type: 'square', attack: 0.1});
+track riff() {
+    track.instrument = osc;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            // The built-in oscillator is not an external preset
+            assert_eq!(oscillator(instrument).waveform, "square");
+            assert_eq!(oscillator(instrument).envelope.attack, Some(0.1));
+            assert_eq!(instrument.preset_ref(), None);
+        }
+    }
+
+    #[test]
+    fn test_unknown_instrument_function_errors() {
+        // An unknown function name (not Oscillator or loadPreset) should error.
+        let program = parse(
+            r#"
+const x = unknownFunc("foo");
+track riff() {
+    track.instrument = x;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let result = compile(&program);
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .contains("Unknown instrument preset 'unknownFunc'"));
+    }
+
+    #[test]
+    fn test_load_preset_no_args() {
+        // loadPreset() with no arguments — preset_ref should be None.
+        let program = parse(
+            r#"
+const p = loadPreset();
+track riff() {
+    track.instrument = p;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
+        if let EventKind::Note { instrument, .. } = &note.kind {
+            let instrument = &events.instruments[*instrument];
+            assert_eq!(instrument.preset_ref(), None);
+        }
+    }
+
+    #[test]
+    fn test_load_preset_passed_as_track_param() {
+        // loadPreset value passed as a track parameter should propagate correctly.
+        let program = parse(
+            r#"
+const piano = loadPreset("FluidR3_GM/Acoustic Grand Piano");
+melody(piano);
+
+track melody(inst) {
+    track.instrument = inst;
+    C4 /4
+    E4 /4
+}
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let notes: Vec<_> = events
+            .events
+            .iter()
+            .filter(|e| matches!(&e.kind, EventKind::Note { .. }))
+            .collect();
+        assert_eq!(notes.len(), 2);
+        for note in &notes {
+            if let EventKind::Note { instrument, .. } = &note.kind {
+                let instrument = &events.instruments[*instrument];
+                assert_eq!(
+                    instrument.preset_ref(),
+                    Some("FluidR3_GM/Acoustic Grand Piano")
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_preset_refs_empty_when_no_presets() {
+        // Songs without loadPreset should return empty refs.
+        let program = parse(
+            r#"
+const synth = Oscillator({type: 'square'});
+track riff() {
+    track.instrument = synth;
+    C3 /4
+}
+riff();
+"#,
+        )
+        .unwrap();
+
+        let event_list = compile(&program).unwrap();
+        let refs = extract_preset_refs(&event_list);
+        assert!(refs.is_empty());
+    }
+
+    // ── Async track execution tests ─────────────────────────
+
+    #[test]
+    fn test_parallel_tracks_overlap() {
+        // Two consecutive track calls without explicit step should start at
+        // the same beat (async/parallel), not sequential.
+        let program = parse(
+            r#"
+track melody() {
+    C4 /4
+    D4 /4
+    E4 /4
+}
+
+track bass() {
+    C2 /2
+    D2 /2
+}
+
+melody();
+bass();
+"#,
+        )
+        .unwrap();
 
+        let events = compile(&program).unwrap();
         let notes: Vec<_> = events
             .events
             .iter()
@@ -851,772 +4821,1734 @@ t();
             })
             .collect();
 
-        assert_eq!(notes[0], (0.0, "C3"));
-        assert_eq!(notes[1], (4.25, "D3"));
+        // melody: C4@0, D4@0.25, E4@0.5
+        // bass:   C2@0, D2@0.5    (parallel, NOT at 0.75/1.25)
+        assert!(notes.iter().any(|(t, p)| *t == 0.0 && *p == "C4"));
+        assert!(notes.iter().any(|(t, p)| *t == 0.25 && *p == "D4"));
+        assert!(notes.iter().any(|(t, p)| *t == 0.5 && *p == "E4"));
+        assert!(notes.iter().any(|(t, p)| *t == 0.0 && *p == "C2"));
+        assert!(notes.iter().any(|(t, p)| *t == 0.5 && *p == "D2"));
+
+        // total_beats = max of both tracks = 1.0 (bass: 0.5 + 0.5)
+        assert_eq!(events.total_beats, 1.0);
     }
 
     #[test]
-    fn test_song_length_ends_at_last_rest() {
-        // Per plan: song ends after the last rest ends, not when last note finishes.
+    fn test_parallel_tracks_with_stagger() {
+        // Explicit step on first track call creates a stagger.
+        // melody() 4; bass();  → melody at beat 0, bass at beat 4
         let program = parse(
             r#"
-track t() {
-    C3 /4
-    D3 /4
+track melody() {
+    C4 /4
+}
+
+track bass() {
+    C2 /4
+}
+
+melody() 4;
+bass();
+"#,
+        )
+        .unwrap();
+
+        let events = compile(&program).unwrap();
+        let notes: Vec<_> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+                _ => None,
+            })
+            .collect();
+
+        assert!(notes.iter().any(|(t, p)| *t == 0.0 && *p == "C4"));
+        assert!(notes.iter().any(|(t, p)| *t == 4.0 && *p == "C2"));
+    }
+
+    #[test]
+    fn test_total_beats_reflects_longest_track() {
+        // total_beats should be the max extent of all parallel tracks.
+        let program = parse(
+            r#"
+track short() {
+    C4 /4
+}
+
+track long() {
+    C2 1
+    D2 1
+    E2 1
+    F2 1
 }
-t();
+
+short();
+long();
 "#,
         )
         .unwrap();
 
         let events = compile(&program).unwrap();
-        // Two notes, each stepping 0.25 beats.
-        // Cursor ends at 0.5, even though the last note (D3) plays for default duration.
-        assert_eq!(events.total_beats, 0.5);
+        // short: 0.25 beats. long: 4 beats.
+        // total_beats = max(0, 4.0) = 4.0  (cursor restored to 0, max_cursor = 4)
+        assert_eq!(events.total_beats, 4.0);
     }
 
+    // ── cursor_context tests ────────────────────────────────
+
     #[test]
-    fn test_compile_chord() {
-        let program = parse(
-            r#"
-track t() {
-    [C3, E3, G3]@1 /2
+    fn test_cursor_context_top_level_defaults() {
+        let source = r#"
+track riff() {
+    C3 /4
 }
-t();
-"#,
-        )
-        .unwrap();
+riff();
+"#;
+        // Cursor at the very start — should get defaults.
+        let ctx = cursor_context(source, 0).unwrap();
+        assert_eq!(ctx.bpm, 120.0);
+        assert_eq!(ctx.tuning_pitch, 440.0);
+        assert_eq!(ctx.note_length, 1.0);
+        assert!(ctx.track_name.is_none());
+    }
 
-        let events = compile(&program).unwrap();
+    #[test]
+    fn test_cursor_context_after_bpm_assignment() {
+        let source = "track.beatsPerMinute = 140;\ntrack riff() { C3 /4 }\nriff();";
+        // Cursor past the BPM assignment — should see 140 BPM.
+        let ctx = cursor_context(source, 30).unwrap();
+        assert_eq!(ctx.bpm, 140.0);
+    }
 
-        let notes: Vec<_> = events
-            .events
-            .iter()
-            .filter_map(|e| match &e.kind {
-                EventKind::Note { pitch, gate, .. } => {
-                    Some((e.time, pitch.as_str(), *gate))
-                }
-                _ => None,
-            })
-            .collect();
+    #[test]
+    fn test_cursor_context_inside_track_def() {
+        let source = r#"const lead = Oscillator({type: "square"});
+track melody() {
+    track.instrument = lead;
+    C4 /4
+    D4 /4
+}
+melody();
+"#;
+        // Find byte offset inside the track body, after instrument assignment.
+        // "C4 /4" is well past "track.instrument = lead;" inside the body.
+        let c4_offset = source.find("C4 /4").unwrap();
+        let ctx = cursor_context(source, c4_offset).unwrap();
+        assert_eq!(ctx.track_name.as_deref(), Some("melody"));
+        assert_eq!(oscillator(&ctx.instrument).waveform, "square");
+    }
 
-        // All three notes fire at time 0, each with audible gate 1 beat.
-        assert_eq!(notes.len(), 3);
-        for (time, _, g) in &notes {
-            assert_eq!(*time, 0.0);
-            assert_eq!(*g, 1.0);
-        }
-        // Step duration /2 = 0.5 beats.
-        assert_eq!(events.total_beats, 0.5);
+    #[test]
+    fn test_cursor_context_after_tuning_change() {
+        let source = "track.tuningPitch = 432;\ntrack riff() { C3 /4 }\nriff();";
+        let ctx = cursor_context(source, 30).unwrap();
+        assert_eq!(ctx.tuning_pitch, 432.0);
     }
 
     #[test]
-    fn test_compile_velocity() {
+    fn test_cursor_context_note_length_change() {
+        let source = r#"track riff() {
+    track.noteLength = 1/8;
+    C3
+}
+riff();
+"#;
+        // Cursor after the noteLength assignment inside the track body.
+        let c3_offset = source.find("C3").unwrap();
+        let ctx = cursor_context(source, c3_offset).unwrap();
+        assert_eq!(ctx.note_length, 0.125); // 1/8
+    }
+
+    // ── time signature tests ────────────────────────────────
+
+    #[test]
+    fn test_time_signature_parse_and_lengths() {
+        let sig = TimeSignature::parse("6/8").unwrap();
+        assert_eq!(sig.beats_per_bar(), 3.0);
+        assert!(sig.is_compound());
+        assert_eq!(sig.pulse_beats(), 1.5);
+        assert_eq!(sig.accent_pattern(), vec![2, 0, 0, 1, 0, 0]);
+        assert_eq!(TimeSignature::default().accent_pattern(), vec![2, 0, 1, 0]);
+        assert!(TimeSignature::parse("4/3").is_none());
+    }
+
+    #[test]
+    fn test_time_signature_assignment_emits_event_and_bars() {
         let program = parse(
             r#"
-track t() {
-    C3*80 /4
+track.timeSignature = 3/4;
+track main() {
+    C4 1
+    D4 1
+    E4 1
+    F4 1
 }
-t();
+main();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        match &events.events[0].kind {
-            EventKind::Note { velocity, .. } => assert_eq!(*velocity, 80.0),
-            other => panic!("Expected Note, got {other:?}"),
-        }
+        let changes = time_signature_changes(&events.events);
+        assert_eq!(changes.last().unwrap().1, TimeSignature::new(3, 4).unwrap());
+        let pos = bar_position(&changes, 3.0);
+        assert_eq!(pos.bar, 2);
+        assert_eq!(pos.beat_in_bar, 0.0);
+
+        let clicks = metronome_clicks(&events);
+        assert_eq!(clicks.len(), 4);
+        assert_eq!(clicks[0].accent, 2);
+        assert_eq!(clicks[1].accent, 0);
+        assert_eq!(clicks[3].accent, 2);
     }
 
     #[test]
-    fn test_compile_track_call_with_step() {
+    fn test_invalid_time_signature_errors() {
+        let program = parse("track.timeSignature = 5/3;").unwrap();
+        let err = compile(&program).unwrap_err();
+        assert!(err.contains("Invalid track.timeSignature '5/3'"), "{err}");
+    }
+
+    #[test]
+    fn test_swing_moves_offbeats() {
         let program = parse(
             r#"
-track a() {
-    C3 /4
+track main() {
+    track.swing = 2/3;
+    C4 /2
+    D4 /2
 }
-a() 8;
-a();
+main();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-
-        let notes: Vec<_> = events
+        let times: Vec<f64> = events
             .events
             .iter()
-            .filter_map(|e| match &e.kind {
-                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
-                _ => None,
-            })
+            .filter(|e| matches!(e.kind, EventKind::Note { .. }))
+            .map(|e| e.time)
             .collect();
+        assert_eq!(times[0], 0.0);
+        assert!((times[1] - 2.0 / 3.0).abs() < 1e-9);
+
+        // Note ends are swung too: the delayed off-beat still ends on the
+        // next beat instead of overlapping it
+        let gates = |source: &str| -> Vec<f64> {
+            let events = compile(&parse(source).unwrap()).unwrap();
+            events
+                .events
+                .iter()
+                .filter_map(|e| match e.kind {
+                    EventKind::Note { gate, .. } => Some(gate),
+                    _ => None,
+                })
+                .collect()
+        };
+        let held = gates("track t() {\n    track.swing = 2/3;\n    C4@/2 /2\n    D4@/2 /2\n    [C4, E4]@/2 /2\n}\nt();");
+        assert_eq!(held.len(), 4);
+        assert_eq!(held[0], 0.5);
+        assert!((held[1] - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(held[2], 0.5);
+        let ringing = gates("track t() {\n    track.swing = 2/3;\n    C4 /2\n    D4 /2\n}\nt();");
+        assert!(ringing.iter().all(|g| (g - 1.0).abs() < 1e-9), "{ringing:?}");
+    }
 
-        // First call: C3 at 0.0, then step 8 beats.
-        // Second call: C3 at 8.0.
-        assert_eq!(notes[0], (0.0, "C3"));
-        assert_eq!(notes[1], (8.0, "C3"));
+    #[test]
+    fn test_time_signature_is_track_scoped() {
+        let source = "track waltz() {\n    track.timeSignature = 3/4;\n    C4 1\n}\ntrack march() {\n    R*1\n    D4 1\n}\nwaltz();\nmarch();";
+        let events = compile(&parse(source).unwrap()).unwrap();
+        let d4 = events
+            .events
+            .iter()
+            .find(|e| matches!(&e.kind, EventKind::Note { pitch, .. } if pitch == "D4"))
+            .unwrap();
+        // A bar of the default 4/4, not the waltz's 3/4
+        assert_eq!(d4.time, 4.0);
+
+        // The march's bars agree with where its notes were placed
+        let march = bar_position(&track_time_signature_changes(&events.events, "march"), 4.0);
+        assert_eq!((march.bar, march.beat_in_bar), (2, 0.0));
+        assert_eq!(march.time_signature, TimeSignature::default());
+        let waltz = track_time_signature_changes(&events.events, "waltz");
+        assert_eq!(waltz[0], (0.0, TimeSignature::new(3, 4).unwrap()));
+        // Song-wide, the 3/4 ends with the waltz
+        let song = time_signature_changes(&events.events);
+        assert_eq!(song.last(), Some(&(1.0, TimeSignature::default())));
     }
 
     #[test]
-    fn test_compile_default_duration_override() {
+    fn test_track_sends() {
         let program = parse(
             r#"
-track t() {
-    track.duration = 1/4;
-    C3
-    D3
+track pad() {
+    C4 1
 }
-t();
+track lead() {
+    track.sends = {reverb: 0.3};
+    D4 1
+    pad();
+    track.sends = {delay: 0.5};
+    E4 1
+}
+lead();
+pad();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        // Each note uses default step = 0.25 beats.
-        assert_eq!(events.total_beats, 0.5);
-
-        let notes: Vec<_> = events
+        let sends: Vec<(&str, Option<&str>, Sends)> = events
             .events
             .iter()
             .filter_map(|e| match &e.kind {
-                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+                EventKind::Note { pitch, sends, .. } => Some((pitch.as_str(), e.track_name.as_deref(), *sends)),
                 _ => None,
             })
             .collect();
-
-        assert_eq!(notes[0], (0.0, "C3"));
-        assert_eq!(notes[1], (0.25, "D3"));
+        // pad sends what its caller sends; a new value replaces every bus
+        assert_eq!(
+            sends,
+            vec![
+                ("D4", Some("lead"), Sends { reverb: 0.3, delay: 0.0 }),
+                ("C4", Some("pad"), Sends::default()),
+                ("C4", Some("pad"), Sends { reverb: 0.3, delay: 0.0 }),
+                ("E4", Some("lead"), Sends { reverb: 0.0, delay: 0.5 }),
+            ]
+        );
+
+        let err = compile(&parse("track.sends = {chorus: 0.2};").unwrap()).unwrap_err();
+        assert_eq!(err, "Unknown send bus 'chorus'. Expected 'reverb' or 'delay'.");
+        let err = compile(&parse("track.sends = {reverb: 2};").unwrap()).unwrap_err();
+        assert_eq!(err, "Invalid send level '2' for bus 'reverb'. Expected a number from 0 to 1.");
     }
 
     #[test]
-    fn test_default_instrument_on_notes() {
-        // Notes without explicit instrument get the default Triangle config.
-        let program = parse(
-            r#"
-track riff() {
-    C3 /4
+    fn test_track_modulation() {
+        let src = "\
+track pad() {
+    track.tremolo = {sync: 1/4, depth: 0.8, shape: 'square'};
+    C4 1
+    track.tremolo = {depth: 0};
+    D4 1
 }
-riff();
-"#,
-        )
-        .unwrap();
-
-        let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            assert_eq!(instrument.waveform, "triangle");
-        }
+track lead() {
+    track.autoPan = {rate: 2};
+    E4 1
+    pad();
+}
+lead();
+pad();";
+        let events = compile(&parse(src).unwrap()).unwrap().events;
+        let modulation: Vec<(&str, Modulation)> = events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, modulation, .. } => Some((pitch.as_str(), *modulation)),
+                _ => None,
+            })
+            .collect();
+        let tremolo = TremoloConfig { sync: Some(0.25), depth: 0.8, shape: LfoShape::Square, ..Default::default() };
+        let auto_pan = AutoPanConfig { rate: 2.0, ..Default::default() };
+        // pad keeps its caller's auto-pan; settings end with the track
+        assert_eq!(
+            modulation,
+            vec![
+                ("E4", Modulation { tremolo: None, auto_pan: Some(auto_pan) }),
+                ("C4", Modulation { tremolo: Some(tremolo), auto_pan: None }),
+                ("C4", Modulation { tremolo: Some(tremolo), auto_pan: Some(auto_pan) }),
+                ("D4", Modulation::default()),
+                ("D4", Modulation { tremolo: None, auto_pan: Some(auto_pan) }),
+            ]
+        );
+
+        let err = |src: &str| compile(&parse(src).unwrap()).unwrap_err();
+        assert_eq!(
+            err("track.tremolo = 0.5;"),
+            "Invalid track.tremolo '0.5'. Expected settings, e.g. {sync: 1/4, depth: 0.5}."
+        );
+        assert_eq!(err("track.autoPan = {depth: 2};"), "Invalid track.autoPan depth '2'. Expected 0 to 1.");
+        assert_eq!(
+            err("track.tremolo = {shape: 'saw'};"),
+            "Invalid track.tremolo shape 'saw'. Expected 'sine', 'triangle' or 'square'."
+        );
+        assert_eq!(
+            err("track.tremolo = {speed: 3};"),
+            "Unknown track.tremolo setting 'speed'. Expected rate, sync, depth or shape."
+        );
     }
 
     #[test]
-    fn test_const_oscillator_instrument() {
-        let program = parse(
-            r#"
-const synth = Oscillator({type: 'square'});
+    fn test_cursor_context_reports_bar() {
+        let source = r#"track.timeSignature = 6/8;
 track riff() {
-    track.instrument = synth;
-    C3 /4
+    C4 1
+    D4 1
+    E4 1
+    F4 1
 }
 riff();
-"#,
-        )
-        .unwrap();
+"#;
+        let ctx = cursor_context(source, source.find("F4").unwrap()).unwrap();
+        assert_eq!(ctx.time_signature, TimeSignature::new(6, 8).unwrap());
+        assert_eq!(ctx.bar, 2);
+        assert_eq!(ctx.beat_in_bar, 1.0);
+    }
 
-        let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            assert_eq!(instrument.waveform, "square");
-        }
+    // ── tempo map tests ─────────────────────────────────────
+
+    #[test]
+    fn test_tempo_map_piecewise_seconds() {
+        let events = vec![
+            Event {
+                time: 0.0,
+                kind: EventKind::SetProperty {
+                    target: "track.beatsPerMinute".to_string(),
+                    value: "60".to_string(),
+                    source_start: 0,
+                    source_end: 0,
+                },
+                track_name: None,
+            },
+            Event {
+                time: 4.0,
+                kind: EventKind::SetProperty {
+                    target: "track.beatsPerMinute".to_string(),
+                    value: "120".to_string(),
+                    source_start: 0,
+                    source_end: 0,
+                },
+                track_name: None,
+            },
+        ];
+        let tempo = TempoMap::from_events(&events, 120.0);
+        assert_eq!(tempo.seconds_at(2.0), 2.0);
+        assert_eq!(tempo.seconds_at(4.0), 4.0);
+        assert_eq!(tempo.seconds_at(6.0), 5.0);
+        assert_eq!(tempo.bpm_at(5.0), 120.0);
     }
 
     #[test]
-    fn test_track_param_instrument() {
-        // Instrument passed as track parameter — track independence.
+    fn test_time_map_bars() {
         let program = parse(
             r#"
-const synth = Oscillator({type: 'sawtooth', attack: 0.05});
-melody(synth);
-
-track melody(inst) {
-    track.instrument = inst;
-    C4 /4
-    E4 /4
+track.beatsPerMinute = 60;
+track.timeSignature = 3/4;
+track main() {
+    C4 1
+    D4 1
+    E4 1
+    F4 1
+    G4 1
+    A4 1
+    B4 1
 }
+main();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        let notes: Vec<_> = events.events.iter().filter(|e| matches!(&e.kind, EventKind::Note { .. })).collect();
-        assert_eq!(notes.len(), 2);
-        for note in &notes {
-            if let EventKind::Note { instrument, .. } = &note.kind {
-                assert_eq!(instrument.waveform, "sawtooth");
-                assert_eq!(instrument.attack, Some(0.05));
-            }
-        }
+        let bars = time_map(&events, 120.0);
+        assert_eq!(bars.len(), 3);
+        assert_eq!(bars[1].bar, 2);
+        assert_eq!(bars[1].start_beat, 3.0);
+        assert_eq!(bars[1].start_seconds, 3.0);
+        assert_eq!(bars[2].length_beats, 1.0);
     }
 
     #[test]
-    fn test_track_scope_isolation() {
-        // Tracks inherit parent state but don't leak changes back.
-        // With async tracks, both start at beat 0 (parallel).
+    fn test_count_in_reports_pre_roll() {
         let program = parse(
             r#"
-const sq = Oscillator({type: 'square'});
-const tri = Oscillator({type: 'triangle'});
-
-bass(sq);
-melody(tri);
-
-track bass(inst) {
-    track.instrument = inst;
-    C2 /4
-}
-
-track melody(inst) {
-    track.instrument = inst;
-    C4 /4
+track.beatsPerMinute = 60;
+track.timeSignature = 3/4;
+song.countIn = 2;
+track main() {
+    C4 1
 }
+main();
 "#,
         )
         .unwrap();
+        let events = compile(&program).unwrap();
+        let count_in = events.count_in.unwrap();
+        assert_eq!(count_in.bars, 2);
+        assert_eq!(count_in.beats, 6.0);
+        assert_eq!(count_in.seconds, 6.0);
+        let json = serde_json::to_string(&events).unwrap();
+        assert!(json.contains("\"count_in\""));
+    }
 
+    #[test]
+    fn test_song_fades() {
+        let program = parse("song.fadeIn = '1.5s';\nsong.fadeOut = 4;").unwrap();
         let events = compile(&program).unwrap();
-        let notes: Vec<_> = events.events.iter().filter_map(|e| match &e.kind {
-            EventKind::Note { pitch, instrument, .. } => Some((e.time, pitch.as_str(), instrument.waveform.as_str())),
-            _ => None,
-        }).collect();
+        assert_eq!(events.fade_in, Some(FadeLength::Seconds(1.5)));
+        assert_eq!(events.fade_out, Some(FadeLength::Beats(4.0)));
+        assert_eq!(FadeLength::Beats(4.0).seconds(120.0), 2.0);
 
-        // Both tracks start at beat 0 (async/parallel).
-        assert!(notes.iter().any(|(t, p, w)| *t == 0.0 && *p == "C2" && *w == "square"));
-        assert!(notes.iter().any(|(t, p, w)| *t == 0.0 && *p == "C4" && *w == "triangle"));
+        let program = parse("song.fadeOut = 'soon';").unwrap();
+        assert!(compile(&program).unwrap_err().contains("song.fadeOut"));
     }
 
     #[test]
-    fn test_events_carry_track_name() {
-        // Events produced inside a track body should carry that track's name.
-        // Top-level events have track_name = None.
+    fn test_voice_leading_pairs_nearest_tones() {
         let program = parse(
             r#"
-track.beatsPerMinute = 120;
-
-track melody() {
-    C4 /4
-}
-
-track bass() {
-    C2 /4
+track pad() {
+    track.voiceLeading = true;
+    [C4, E4, G4] 1
+    [B3, F4, G4] 1
 }
-
-melody();
-bass();
+pad();
 "#,
         )
         .unwrap();
+        let events = compile(&program).unwrap();
+        let glides: Vec<(String, Option<String>)> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, glide_from, .. } if e.time == 1.0 => {
+                    Some((pitch.clone(), glide_from.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert!(glides.contains(&("G4".to_string(), Some("G4".to_string()))));
+        assert!(glides.contains(&("F4".to_string(), Some("E4".to_string()))));
+        assert!(glides.contains(&("B3".to_string(), Some("C4".to_string()))));
+        // The first chord starts fresh voices
+        assert!(events.events.iter().all(|e| e.time > 0.0
+            || !matches!(&e.kind, EventKind::Note { glide_from: Some(_), .. })));
+    }
 
+    #[test]
+    fn test_note_expression_compiles_and_validates() {
+        let program = parse("track t() {\n    [C4, E4] /4 {pan: 0.5}\n}\nt();\n").unwrap();
         let events = compile(&program).unwrap();
+        for event in &events.events {
+            if let EventKind::Note { expression, .. } = &event.kind {
+                assert_eq!(expression.pan, Some(0.5));
+            }
+        }
 
-        // Top-level SetProperty (BPM) should have no track name.
-        let bpm_event = events.events.iter().find(|e| matches!(&e.kind, EventKind::SetProperty { target, .. } if target == "track.beatsPerMinute")).unwrap();
-        assert_eq!(bpm_event.track_name, None);
+        let program = parse("track t() {\n    C4 /4 {pan: 2}\n}\nt();\n").unwrap();
+        let err = compile(&program).unwrap_err();
+        assert!(err.contains("pan"), "{err}");
+    }
 
-        // Notes inside "melody" should carry track_name = Some("melody").
-        let melody_note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { pitch, .. } if pitch == "C4")).unwrap();
-        assert_eq!(melody_note.track_name, Some("melody".to_string()));
+    #[test]
+    fn test_slide_target_is_compiled_and_checked() {
+        let program = parse("track t() {\n    C4->G4 /2\n}\nt();\n").unwrap();
+        let events = compile(&program).unwrap();
+        assert!(events.events.iter().any(|e| matches!(
+            &e.kind,
+            EventKind::Note { pitch, slide_to: Some(to), .. } if pitch == "C4" && to == "G4"
+        )));
+
+        let program = parse("track t() {\n    C4->foo /2\n}\nt();\n").unwrap();
+        let err = compile(&program).unwrap_err();
+        assert!(err.contains("slide target"), "{err}");
+    }
 
-        // Notes inside "bass" should carry track_name = Some("bass").
-        let bass_note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { pitch, .. } if pitch == "C2")).unwrap();
-        assert_eq!(bass_note.track_name, Some("bass".to_string()));
+    #[test]
+    fn test_note_bend_range_is_checked() {
+        let program = parse("track t() {\n    C4^+30 /4\n}\nt();\n").unwrap();
+        let err = compile(&program).unwrap_err();
+        assert!(err.contains("bend"), "{err}");
     }
 
     #[test]
-    fn test_string_shorthand_instrument() {
+    fn test_inline_layer_and_split_instruments() {
         let program = parse(
             r#"
-track riff() {
-    track.instrument = 'square';
-    C3 /4
+const pad = Layer([Oscillator({type: 'saw'}), loadPreset("Strings")], [0.6, 0.4]);
+track t() {
+    track.instrument = pad;
+    C4 /4
+    track.instrument = Split([Oscillator({type: 'sine'}), pad], [C4]);
+    D4 /4
 }
-riff();
+t();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            assert_eq!(instrument.waveform, "square");
+        assert_eq!(extract_preset_refs(&events), vec!["Strings".to_string()]);
+
+        let instruments: Vec<&InstrumentConfig> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { instrument, .. } => Some(&events.instruments[*instrument]),
+                _ => None,
+            })
+            .collect();
+        let InstrumentConfig::Composite(layer) = instruments[0] else {
+            panic!("Expected a composite, got {:?}", instruments[0]);
+        };
+        assert_eq!(layer.mode, CompositeKind::Layer);
+        assert_eq!(oscillator(&layer.children[0]).waveform, "saw");
+        assert_eq!(layer.children[1].preset_ref(), Some("Strings"));
+        assert_eq!(layer.mix_levels, Some(vec![0.6, 0.4]));
+
+        let InstrumentConfig::Composite(split) = instruments[1] else {
+            panic!("Expected a composite, got {:?}", instruments[1]);
+        };
+        assert_eq!(split.mode, CompositeKind::Split);
+        assert_eq!(split.split_points, Some(vec![60]));
+        assert_eq!(split.children[1], *instruments[0]);
+        assert_eq!(instruments[1].preset_refs(), vec!["Strings".to_string()]);
+    }
+
+    #[test]
+    fn test_inline_composite_errors() {
+        for (source, message) in [
+            ("Layer('sine')", "array of instruments"),
+            ("Layer([])", "at least one"),
+            ("Layer(['sine', 'square'], [1])", "2 instruments but 1 mix levels"),
+            ("Split(['sine', 'square'], [C4, G4])", "needs 1 split points"),
+            ("Split(['sine', 'square'], [200])", "split point"),
+            ("Split({below: 'sine', above: 'square'})", "needs 'below', 'at' and 'above'"),
+            ("Split({below: 'sine', at: C3, over: 'square'})", "Unknown Split() key 'over'"),
+            ("Split({below: 'sine', at: 'X9', above: 'square'})", "split point"),
+        ] {
+            let program = parse(&format!("track.instrument = {source};\n")).unwrap();
+            let err = compile(&program).unwrap_err();
+            assert!(err.contains(message), "{source}: {err}");
         }
     }
 
     #[test]
-    fn test_inline_instrument_in_track() {
-        let program = parse(
-            r#"
-track riff() {
-    track.instrument = Oscillator({type: 'sine', release: 0.5});
-    C3 /4
-}
-riff();
-"#,
+    fn test_instrument_config_json_shapes() {
+        let osc = InstrumentConfig::Oscillator(OscillatorConfig {
+            waveform: "square".to_string(),
+            envelope: EnvelopeConfig { attack: Some(0.1), ..Default::default() },
+            ..Default::default()
+        });
+        let json = serde_json::to_string(&osc).unwrap();
+        assert_eq!(json, r#"{"kind":"oscillator","waveform":"square","attack":0.1}"#);
+        assert_eq!(serde_json::from_str::<InstrumentConfig>(&json).unwrap(), osc);
+
+        let fm: InstrumentConfig = serde_json::from_str(r#"{"kind":"fm","ratio":2,"index":3}"#).unwrap();
+        assert_eq!(fm, InstrumentConfig::Fm(FmConfig { ratio: 2.0, index: 3.0, ..Default::default() }));
+
+        // The legacy flat shape is still accepted
+        let legacy: InstrumentConfig = serde_json::from_str(
+            r#"{"waveform":"square","attack":0.1,"decay":null,"sustain":null,"release":null,
+                "detune":null,"mixer":null,"preset_ref":null}"#,
         )
         .unwrap();
+        assert_eq!(legacy, osc);
+        let legacy: InstrumentConfig =
+            serde_json::from_str(r#"{"waveform":"triangle","preset_ref":"FluidR3_GM/Piano"}"#).unwrap();
+        assert_eq!(legacy.preset_ref(), Some("FluidR3_GM/Piano"));
+
+        assert!(serde_json::from_str::<InstrumentConfig>(r#"{"kind":"organ"}"#).is_err());
+        // A tagged instrument reports its own error, not a legacy mismatch
+        let err = serde_json::from_str::<InstrumentConfig>(r#"{"kind":"organ","waveform":"sine"}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown variant `organ`"), "{err}");
+        let err = serde_json::from_str::<InstrumentConfig>(r#"{"kind":"oscillator","waveform":5}"#).unwrap_err();
+        assert!(err.to_string().contains("invalid type"), "{err}");
+    }
 
-        let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            assert_eq!(instrument.waveform, "sine");
-            assert_eq!(instrument.release, Some(0.5));
-        }
+    #[test]
+    fn test_event_list_interns_instruments() {
+        let src = "\
+const lead = Oscillator({type: 'square'});
+track t() {
+    C4 /4
+    track.instrument = lead;
+    for (let i = 0; i < 8; i++) {
+        E4 /4
+    }
+}
+t();";
+        let events = compile(&parse(src).unwrap()).unwrap();
+        assert_eq!(events.instruments.len(), 2);
+        let indices: Vec<usize> = events
+            .events
+            .iter()
+            .filter_map(|e| match e.kind {
+                EventKind::Note { instrument, .. } => Some(instrument),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(indices, [vec![0], vec![1; 8]].concat());
+        assert_eq!(oscillator(&events.instruments[1]).waveform, "square");
+
+        let json = serde_json::to_string(&events).unwrap();
+        assert_eq!(json.matches("\"square\"").count(), 1);
+        let round_trip: EventList = serde_json::from_str(&json).unwrap();
+        assert_eq!(round_trip.events, events.events);
+        assert_eq!(round_trip.instruments, events.instruments);
+
+        // Notes with their instrument embedded still load
+        let legacy = r#"{"events":[
+            {"time":0,"kind":{"Note":{"pitch":"C4","velocity":100,"gate":1,"source_start":0,"source_end":2,
+                "instrument":{"kind":"oscillator","waveform":"square"}}},"track_name":null},
+            {"time":1,"kind":{"Note":{"pitch":"D4","velocity":100,"gate":1,"source_start":3,"source_end":5,
+                "instrument":{"kind":"oscillator","waveform":"square"}}},"track_name":null}],
+            "total_beats":2,"end_mode":"Gate"}"#;
+        let legacy: EventList = serde_json::from_str(legacy).unwrap();
+        assert_eq!(legacy.instruments.len(), 1);
+        assert!(legacy.events.iter().all(|e| matches!(e.kind, EventKind::Note { instrument: 0, .. })));
+
+        let dangling = r#"{"events":[{"time":0,"kind":{"Note":{"pitch":"C4","velocity":100,"gate":1,
+            "instrument":3,"source_start":0,"source_end":2}},"track_name":null}],"total_beats":1,"end_mode":"Gate"}"#;
+        let err = serde_json::from_str::<EventList>(dangling).unwrap_err().to_string();
+        assert!(err.starts_with("Note at beat 0 plays instrument 3, but there are only 0."), "{err}");
     }
 
     #[test]
-    fn test_instrument_inherits_from_parent() {
-        // Track inherits parent's instrument when not overridden.
+    fn test_fm_instrument() {
         let program = parse(
-            r#"
-track.instrument = Oscillator({type: 'sawtooth'});
-riff();
-
-track riff() {
-    C3 /4
-}
-"#,
+            "track t() {\n    track.instrument = FM({ratio: 3.5, index: 2, release: 0.2});\n    C4 /4\n}\nt();\n",
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            assert_eq!(instrument.waveform, "sawtooth");
-        }
+        let instrument = events
+            .events
+            .iter()
+            .find_map(|e| match &e.kind {
+                EventKind::Note { instrument, .. } => Some(&events.instruments[*instrument]),
+                _ => None,
+            })
+            .unwrap();
+        let InstrumentConfig::Fm(fm) = instrument else {
+            panic!("Expected FM, got {instrument:?}");
+        };
+        assert_eq!(fm.ratio, 3.5);
+        assert_eq!(fm.index, 2.0);
+        assert_eq!(instrument.release(), Some(0.2));
     }
 
-    // ── loadPreset tests ────────────────────────────────────
-
     #[test]
-    fn test_load_preset_sets_preset_ref() {
-        // loadPreset("name") should set preset_ref on the instrument config.
+    fn test_load_preset_overrides() {
         let program = parse(
             r#"
-const piano = loadPreset("FluidR3_GM/Acoustic Grand Piano");
-track riff() {
-    track.instrument = piano;
-    C3 /4
+const low = loadPreset("Piano", {gain: 0.5, transpose: 7, release: 1.5});
+track t() {
+    track.instrument = low;
+    C4 /4
 }
-riff();
+t();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            assert_eq!(
-                instrument.preset_ref,
-                Some("FluidR3_GM/Acoustic Grand Piano".to_string())
-            );
-        } else {
-            panic!("Expected Note event");
-        }
+        assert_eq!(extract_preset_refs(&events), vec!["Piano".to_string()]);
+        let instrument = events
+            .events
+            .iter()
+            .find_map(|e| match &e.kind {
+                EventKind::Note { instrument, .. } => Some(&events.instruments[*instrument]),
+                _ => None,
+            })
+            .unwrap();
+        let InstrumentConfig::SamplerRef(preset) = instrument else {
+            panic!("Expected a preset reference, got {instrument:?}");
+        };
+        assert_eq!(preset.gain, Some(0.5));
+        assert_eq!(preset.transpose, Some(7.0));
+        assert_eq!(instrument.release(), Some(1.5));
     }
 
     #[test]
-    fn test_load_preset_emits_preset_ref_event() {
-        // A const decl with loadPreset should emit a PresetRef event for preloading.
+    fn test_gm_program_instruments() {
         let program = parse(
             r#"
-const piano = loadPreset("FluidR3_GM/Acoustic Grand Piano");
-track riff() {
-    track.instrument = piano;
-    C3 /4
+const guitar = loadPreset("gm:25");
+track t() {
+    track.instrument = gm(0);
+    C4 /4
+    track.instrument = guitar;
+    E4 /4
 }
-riff();
+t();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        let preset_refs: Vec<_> = events
+        let names: Vec<&str> = events
             .events
             .iter()
             .filter_map(|e| match &e.kind {
-                EventKind::PresetRef { name } => Some(name.as_str()),
+                EventKind::Note { instrument, .. } => events.instruments[*instrument].preset_ref(),
                 _ => None,
             })
             .collect();
+        assert_eq!(names, vec!["gm:0", "gm:25"]);
+        assert_eq!(gm_program_of("gm:25"), Some(25));
+        assert_eq!(gm_program_of("gm:128"), None);
+        assert_eq!(gm_program_of("FluidR3_GM/Piano"), None);
+    }
 
-        assert_eq!(preset_refs, vec!["FluidR3_GM/Acoustic Grand Piano"]);
+    #[test]
+    fn test_gm_program_out_of_range() {
+        for src in ["const p = gm(128);", "const p = gm(1.5);", "const p = loadPreset(\"gm:x\");"] {
+            let err = compile(&parse(src).unwrap()).unwrap_err();
+            assert!(err.contains("General MIDI"), "{src}: {err}");
+        }
     }
 
     #[test]
-    fn test_extract_preset_refs() {
-        // extract_preset_refs should collect unique preset references.
+    fn test_compile_project_shares_definitions() {
+        let manifest: ProjectManifest = serde_json::from_str(
+            r#"{"shared": ["common.sw"], "songs": [
+                {"name": "Intro", "file": "intro.sw"},
+                {"name": "Verse", "file": "verse.sw"}
+            ]}"#,
+        )
+        .unwrap();
+        let files: HashMap<String, String> = [
+            (
+                "common.sw",
+                "const piano = loadPreset(\"Piano\");\ntrack riff() { track.instrument = piano; C4 /4 E4 /4 }",
+            ),
+            ("intro.sw", "riff();"),
+            ("verse.sw", "track riff() { G4 /1 }\nriff();"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let project = compile_project(&manifest, &files).unwrap();
+        let names: Vec<&str> = project.songs.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Intro", "Verse"]);
+        let pitches = |list: &EventList| -> Vec<String> {
+            list.events
+                .iter()
+                .filter_map(|e| match &e.kind {
+                    EventKind::Note { pitch, .. } => Some(pitch.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(pitches(&project.songs[0].event_list), vec!["C4", "E4"]);
+        // The song's own riff replaces the shared one
+        assert_eq!(pitches(&project.songs[1].event_list), vec!["G4"]);
+        assert_eq!(project.preset_refs, vec!["Piano".to_string()]);
+    }
+
+    #[test]
+    fn test_compile_project_errors() {
+        let manifest = ProjectManifest {
+            shared: vec!["common.sw".to_string()],
+            songs: vec![ProjectSong { name: "A".to_string(), file: "a.sw".to_string() }],
+        };
+        let mut files = HashMap::new();
+        files.insert("common.sw".to_string(), "track t() { C4 /4 }\nt();".to_string());
+        let err = compile_project(&manifest, &files).unwrap_err();
+        assert!(err.contains("common.sw: shared files may only contain"), "{err}");
+
+        files.insert("common.sw".to_string(), "track t() { C4 /4 }".to_string());
+        let err = compile_project(&manifest, &files).unwrap_err();
+        assert_eq!(err, "Project file 'a.sw' is missing.");
+    }
+
+    #[test]
+    fn test_reassign_instruments() {
         let program = parse(
             r#"
-const piano = loadPreset("FluidR3_GM/Acoustic Grand Piano");
-const guitar = loadPreset("FluidR3_GM/Nylon Guitar");
-track riff() {
-    track.instrument = piano;
-    C3 /4
-}
-riff();
+const piano = loadPreset("Piano");
+track melody() { track.instrument = piano; C4 /4 }
+track bass() { C2 /4 }
+melody();
+bass();
 "#,
         )
         .unwrap();
-
-        let event_list = compile(&program).unwrap();
-        let refs = extract_preset_refs(&event_list);
-        assert_eq!(refs.len(), 2);
-        assert!(refs.contains(&"FluidR3_GM/Acoustic Grand Piano".to_string()));
-        assert!(refs.contains(&"FluidR3_GM/Nylon Guitar".to_string()));
+        let mut events = compile(&program).unwrap();
+        let mapping: HashMap<String, InstrumentConfig> = [(
+            "melody".to_string(),
+            InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "FluidR3_GM/Flute".to_string(),
+                ..Default::default()
+            }),
+        )]
+        .into_iter()
+        .collect();
+        reassign_instruments(&mut events, &mapping).unwrap();
+
+        let instrument_of = |track: &str| {
+            events
+                .events
+                .iter()
+                .find_map(|e| match &e.kind {
+                    EventKind::Note { instrument, .. } if e.track_name.as_deref() == Some(track) => {
+                        Some(events.instruments[*instrument].clone())
+                    }
+                    _ => None,
+                })
+                .unwrap()
+        };
+        assert_eq!(instrument_of("melody").preset_ref(), Some("FluidR3_GM/Flute"));
+        assert_eq!(instrument_of("bass"), InstrumentConfig::default());
+        assert_eq!(
+            extract_preset_refs(&events),
+            vec!["FluidR3_GM/Flute".to_string(), "Piano".to_string()]
+        );
+
+        let unknown: HashMap<String, InstrumentConfig> =
+            [("drums".to_string(), InstrumentConfig::default())].into_iter().collect();
+        let err = reassign_instruments(&mut events, &unknown).unwrap_err();
+        assert_eq!(err, "No notes on track 'drums' to reassign.");
     }
 
     #[test]
-    fn test_extract_preset_refs_deduplicates() {
-        // Same preset referenced twice should appear only once.
+    fn test_anacrusis_bars() {
         let program = parse(
             r#"
-const a = loadPreset("FluidR3_GM/Piano");
-const b = loadPreset("FluidR3_GM/Piano");
-track riff() {
-    track.instrument = a;
-    C3 /4
+song.anacrusis = 1;
+track main() {
+    G3 1
+    C4 4
+    E4 2
 }
-riff();
+main();
 "#,
         )
         .unwrap();
+        let events = compile(&program).unwrap();
+        assert_eq!(events.anacrusis, Some(1.0));
+        // The pickup still plays from beat 0
+        assert_eq!(events.events.iter().find(|e| matches!(e.kind, EventKind::Note { .. })).unwrap().time, 0.0);
+
+        let bars = time_map(&events, 120.0);
+        let numbers: Vec<u32> = bars.iter().map(|b| b.bar).collect();
+        assert_eq!(numbers, vec![0, 1, 2]);
+        assert_eq!(bars[0].length_beats, 1.0);
+        assert_eq!(bars[1].start_beat, 1.0);
+
+        let changes = time_signature_changes(&events.events);
+        let pickup = bar_position_with_pickup(&changes, 1.0, 0.0);
+        assert_eq!((pickup.bar, pickup.beat_in_bar), (0, 3.0));
+        let downbeat = bar_position_with_pickup(&changes, 1.0, 5.0);
+        assert_eq!((downbeat.bar, downbeat.beat_in_bar), (2, 0.0));
+
+        // The pickup click is the last (unaccented) beat of a bar
+        let clicks = metronome_clicks(&events);
+        assert_eq!((clicks[0].time, clicks[0].accent), (0.0, 0));
+        assert_eq!((clicks[1].time, clicks[1].accent), (1.0, 2));
+    }
 
-        let event_list = compile(&program).unwrap();
-        let refs = extract_preset_refs(&event_list);
-        assert_eq!(refs.len(), 1);
-        assert_eq!(refs[0], "FluidR3_GM/Piano");
+    #[test]
+    fn test_anacrusis_must_fit_in_a_bar() {
+        let program = parse("track.timeSignature = 3/4;\nsong.anacrusis = 3;").unwrap();
+        let err = compile(&program).unwrap_err();
+        assert!(err.contains("shorter than the opening 3/4 bar"), "{err}");
     }
 
     #[test]
-    fn test_load_preset_default_waveform() {
-        // loadPreset for an external preset should still use default waveform.
+    fn test_dotted_durations() {
         let program = parse(
             r#"
-const p = loadPreset("SomeLibrary/SomeInstrument");
-track riff() {
-    track.instrument = p;
-    C3 /4
+track t() {
+    C4 /4.
+    D4 /4..
+    E4 .
+    F4 ..
+    G4 1
 }
-riff();
+t();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            // External presets keep default waveform; runtime replaces it.
-            assert_eq!(instrument.waveform, "triangle");
-            assert_eq!(
-                instrument.preset_ref,
-                Some("SomeLibrary/SomeInstrument".to_string())
-            );
-        }
+        let times: Vec<f64> = events
+            .events
+            .iter()
+            .filter(|e| matches!(e.kind, EventKind::Note { .. }))
+            .map(|e| e.time)
+            .collect();
+        // /4. = 0.375, /4.. = 0.4375, . = 1.5 (default 1), .. = 1.75
+        assert_eq!(times, vec![0.0, 0.375, 0.8125, 2.3125, 4.0625]);
     }
 
     #[test]
-    fn test_load_preset_oscillator_special_case() {
-        // loadPreset("Oscillator", {type: 'square'}) should configure waveform.
+    fn test_explicit_and_bar_rests() {
         let program = parse(
             r#"
-const osc = loadPreset("Oscillator", {type: 'square', attack: 0.1});
-track riff() {
-    track.instrument = osc;
-    C3 /4
+track t() {
+    C4 /4
+    r/4
+    D4 /2
+    track.timeSignature = 3/4;
+    R*2
+    E4 1
+    _ 1/2
+    F4 1
 }
-riff();
+t();
 "#,
         )
         .unwrap();
+        let events = compile(&program).unwrap();
+        let times: Vec<f64> = events
+            .events
+            .iter()
+            .filter(|e| matches!(e.kind, EventKind::Note { .. }))
+            .map(|e| e.time)
+            .collect();
+        assert_eq!(times, vec![0.0, 0.5, 7.0, 8.5]);
+
+        let err = compile(&parse("track t() { R*1.5 }\nt();").unwrap()).unwrap_err();
+        assert!(err.contains("whole number of bars"), "{err}");
+    }
 
+    #[test]
+    fn test_lyric_events() {
+        let program = parse("track t() {\n    C4 /2 \"sha-\"\n    D4 \"la\" /2\n}\nt();").unwrap();
         let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            assert_eq!(instrument.waveform, "square");
-            assert_eq!(instrument.attack, Some(0.1));
-            assert_eq!(instrument.preset_ref, Some("Oscillator".to_string()));
-        }
+        let lyrics: Vec<(f64, &str, Option<&str>)> = events
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Lyric { text } => Some((e.time, text.as_str(), e.track_name.as_deref())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lyrics, vec![(0.0, "sha-", Some("t")), (0.5, "la", Some("t"))]);
     }
 
     #[test]
-    fn test_unknown_instrument_function_errors() {
-        // An unknown function name (not Oscillator or loadPreset) should error.
+    fn test_markers_and_cues() {
         let program = parse(
             r#"
-const x = unknownFunc("foo");
-track riff() {
-    track.instrument = x;
-    C3 /4
+track.beatsPerMinute = 60;
+marker "loop_start";
+track t() {
+    C4 2
+    cue "boss_enter";
+    D4 2
 }
-riff();
+t() 4;
+marker "loop_end";
 "#,
         )
         .unwrap();
+        let events = compile(&program).unwrap();
+        let found = markers(&events, 120.0);
+        let summary: Vec<(MarkerKind, &str, f64, f64)> = found
+            .iter()
+            .map(|m| (m.kind, m.name.as_str(), m.beat, m.seconds))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (MarkerKind::Marker, "loop_start", 0.0, 0.0),
+                (MarkerKind::Cue, "boss_enter", 2.0, 2.0),
+                (MarkerKind::Marker, "loop_end", 4.0, 4.0),
+            ]
+        );
+        assert_eq!(found[1].track_name.as_deref(), Some("t"));
+        let json = serde_json::to_string(&events).unwrap();
+        assert!(json.contains(r#"{"Marker":{"kind":"cue","name":"boss_enter"}}"#), "{json}");
+    }
 
-        let result = compile(&program);
-        assert!(result.is_err());
-        assert!(result
-            .unwrap_err()
-            .contains("Unknown instrument preset 'unknownFunc'"));
+    #[test]
+    fn test_middle_c_convention() {
+        let src = "song.middleC = \"C3\";\ntrack t() {\n    C3 1\n    Bb2->C3 1\n    [C3, Eb3] 1\n}\nt();";
+        let result = compile(&parse(src).unwrap()).unwrap();
+        let notes: Vec<(&str, Option<&str>)> = result
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, slide_to, .. } => Some((pitch.as_str(), slide_to.as_deref())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(notes, vec![("C4", None), ("Bb3", Some("C4")), ("C4", None), ("Eb4", None)]);
+
+        // Split points follow the convention too
+        let split = "song.middleC = 'C3';\nconst kb = Split([Oscillator({type: 'sine'}), Oscillator({type: 'square'})], [C3]);";
+        let program = parse(&format!("{split}\ntrack.instrument = kb;\ntrack t() {{\n    C3 1\n}}\nt();")).unwrap();
+        let result = compile(&program).unwrap();
+        let split_points = result.instruments.iter().find_map(|i| match i {
+            InstrumentConfig::Composite(c) => c.split_points.clone(),
+            _ => None,
+        });
+        assert_eq!(split_points, Some(vec![60]));
+
+        let err = compile(&parse("song.middleC = \"C5\";").unwrap()).unwrap_err();
+        assert!(err.contains("song.middleC"), "{err}");
+    }
+
+    #[test]
+    fn test_cursor_context_reports_middle_c() {
+        let src = "song.middleC = \"C3\";\ntrack t() {\n    C3 1\n}\nt();";
+        let ctx = cursor_context(src, src.find("C3 1").unwrap()).unwrap();
+        assert_eq!(ctx.middle_c, MiddleC::C3);
+        let ctx = cursor_context("track t() {\n    C4 1\n}\nt();", 14).unwrap();
+        assert_eq!(ctx.middle_c, MiddleC::C4);
+    }
+
+    #[test]
+    fn test_unicode_track_names() {
+        let result = compile(&parse("track Intro日本() {\n    C4 1\n}\nIntro日本();").unwrap()).unwrap();
+        let note = result.events.iter().find(|e| matches!(e.kind, EventKind::Note { .. })).unwrap();
+        assert_eq!(note.track_name.as_deref(), Some("Intro日本"));
+    }
+
+    #[test]
+    fn test_analyze_structure_lists_tracks_with_docs() {
+        let src = "/// Drum groove.\ntrack drums(fill) {\n    C2 1\n}\ntrack bass() {\n    C2 1\n}\ndrums(1);";
+        let structure = analyze_structure(&parse(src).unwrap());
+        assert_eq!(structure.tracks.len(), 2);
+        let drums = &structure.tracks[0];
+        assert_eq!((drums.name.as_str(), drums.params.clone()), ("drums", vec!["fill".to_string()]));
+        assert_eq!(drums.doc.as_deref(), Some("Drum groove."));
+        assert!(src[drums.span_start..drums.span_end].starts_with("track drums"));
+        assert_eq!(structure.tracks[1].doc, None);
+        assert_eq!(drums.color, None);
+
+        // Display colors are listed but play no part in the audio
+        let src = "track lead() {\n    track.displayColor = '#FF8800';\n    C4 1\n}\nlead();";
+        let program = parse(src).unwrap();
+        assert_eq!(analyze_structure(&program).tracks[0].color.as_deref(), Some("#ff8800"));
+        let events = compile(&program).unwrap();
+        assert!(events.events.iter().all(|e| !matches!(e.kind, EventKind::SetProperty { .. })));
+        let bad = parse("track lead() {\n    track.displayColor = 'orange';\n}\nlead();").unwrap();
+        assert_eq!(
+            compile(&bad).unwrap_err(),
+            "Invalid track.displayColor 'orange'. Expected a color like '#ff8800'."
+        );
+    }
+
+    #[test]
+    fn test_cursor_context_walks_past_comments() {
+        let source = "// tempo\ntrack.beatsPerMinute = 140;\ntrack riff() {\n    // faster\n    track.beatsPerMinute = 150;\n    C3 /4\n}";
+        let ctx = cursor_context(source, source.find("C3").unwrap()).unwrap();
+        assert_eq!(ctx.bpm, 150.0);
     }
 
     #[test]
-    fn test_load_preset_no_args() {
-        // loadPreset() with no arguments — preset_ref should be None.
-        let program = parse(
-            r#"
-const p = loadPreset();
-track riff() {
-    track.instrument = p;
-    C3 /4
+    fn test_compile_with_trace() {
+        let source = "\
+const lead = Oscillator({type: 'square'});
+track melody() {
+    track.instrument = lead;
+    track.swing = 2/3;
+    C4 /2
+    D4 /2
+    for (let i = 0; i < 4; i++) {
+        [E4, G4] 1
+    }
 }
-riff();
-"#,
-        )
-        .unwrap();
+melody();
+drums();
+";
+        let traced = compile_with_trace(source).unwrap();
+        assert_eq!(traced.event_list.events, compile(&crate::parse(source).unwrap()).unwrap().events);
+        let steps: Vec<&TraceStep> = traced.trace.iter().map(|e| &e.step).collect();
+
+        assert!(matches!(steps[0], TraceStep::InstrumentResolved { target, .. } if target == "lead"));
+        assert!(matches!(steps[1], TraceStep::TrackInlined { name, end_beat } if name == "melody" && *end_beat == 5.0));
+        assert!(matches!(steps[2], TraceStep::InstrumentResolved { target, expression, .. }
+            if target == "track.instrument" && expression == "lead"));
+        assert!(matches!(steps[3], TraceStep::PropertySet { target, .. } if target == "track.swing"));
+
+        // The swung off-beat note is placed after its cursor position
+        let d4 = traced.trace.iter().find(|e| matches!(&e.step, TraceStep::NotePlaced { pitch, .. } if pitch == "D4")).unwrap();
+        assert_eq!(d4.track.as_deref(), Some("melody"));
+        let TraceStep::NotePlaced { time, .. } = d4.step else { unreachable!() };
+        assert_eq!(d4.beat, 0.5);
+        assert!((time - 2.0 / 3.0).abs() < 1e-9);
+
+        assert!(steps.contains(&&TraceStep::LoopUnrolled { iterations: 4 }));
+        let chord_notes = steps.iter().filter(|s| matches!(s, TraceStep::NotePlaced { time, .. } if *time == 1.0)).count();
+        assert_eq!(chord_notes, 2);
+        assert_eq!(steps.last(), Some(&&TraceStep::TrackDeferred { name: "drums".to_string() }));
+
+        assert!(compile_with_trace("track t( {").is_err());
+    }
 
-        let events = compile(&program).unwrap();
-        let note = events.events.iter().find(|e| matches!(&e.kind, EventKind::Note { .. })).unwrap();
-        if let EventKind::Note { instrument, .. } = &note.kind {
-            assert_eq!(instrument.preset_ref, None);
-        }
+    #[test]
+    fn test_language_version_deprecations() {
+        let v1 = "track.a4Frequency = 432;\ntrack t() {\n    track.duration = 1/2;\n    C4\n}\nt();";
+        let program = parse(v1).unwrap();
+        let report = language_report(&program);
+        assert_eq!(report.version, 1);
+        assert_eq!(report.warnings.len(), 2);
+        let first = &report.warnings[0];
+        assert_eq!(&v1[first.span_start..first.span_end], "track.a4Frequency = 432");
+        assert!(first.message.contains("track.tuningPitch"));
+        let second = &report.warnings[1];
+        assert!(v1[second.span_start..second.span_end].starts_with("track.duration"));
+
+        // Version 1 keeps compiling; version 2 rejects the old aliases
+        assert!(compile(&program).is_ok());
+        let v2 = parse(&format!("#version 2\n{v1}")).unwrap();
+        let err = compile(&v2).unwrap_err();
+        assert!(err.contains("track.tuningPitch"), "{err}");
+        let current = parse("#version 2\ntrack.tuningPitch = 432;").unwrap();
+        assert!(language_report(&current).warnings.is_empty());
+        assert!(compile(&current).is_ok());
+
+        // Programs that bypass the parser are checked too
+        let future = Program { language_version: LATEST_LANGUAGE_VERSION + 1, ..current };
+        let err = compile(&future).unwrap_err();
+        assert!(err.contains("Unsupported language version"), "{err}");
     }
 
     #[test]
-    fn test_load_preset_passed_as_track_param() {
-        // loadPreset value passed as a track parameter should propagate correctly.
+    fn test_play_later_handles() {
         let program = parse(
             r#"
-const piano = loadPreset("FluidR3_GM/Acoustic Grand Piano");
-melody(piano);
-
-track melody(inst) {
+const hook = riff(lead);
+const lead = Oscillator({type: 'square'});
+play hook at 16;
+pad() 2;
+play hook;
+track pad() {
+    C5 2
+}
+track riff(inst) {
     track.instrument = inst;
-    C4 /4
-    E4 /4
+    C4 1
+    D4 1
 }
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        let notes: Vec<_> = events
-            .events
-            .iter()
-            .filter(|e| matches!(&e.kind, EventKind::Note { .. }))
-            .collect();
-        assert_eq!(notes.len(), 2);
-        for note in &notes {
-            if let EventKind::Note { instrument, .. } = &note.kind {
-                assert_eq!(
-                    instrument.preset_ref,
-                    Some("FluidR3_GM/Acoustic Grand Piano".to_string())
-                );
+        let notes: Vec<_> = events.events.iter().filter_map(|e| match &e.kind {
+            EventKind::Note { pitch, instrument, .. } => {
+                Some((e.time, pitch.as_str(), oscillator(&events.instruments[*instrument]).waveform.as_str()))
             }
-        }
+            _ => None,
+        }).collect();
+        // `lead` is bound when the handle plays, and playing leaves the cursor alone.
+        assert_eq!(
+            notes,
+            vec![
+                (0.0, "C5", "triangle"),
+                (2.0, "C4", "square"),
+                (3.0, "D4", "square"),
+                (16.0, "C4", "square"),
+                (17.0, "D4", "square"),
+            ]
+        );
+
+        let err = compile(&parse("play nothing at 4;").unwrap()).unwrap_err();
+        assert!(err.contains("Unknown performance 'nothing'"), "{err}");
     }
 
     #[test]
-    fn test_extract_preset_refs_empty_when_no_presets() {
-        // Songs without loadPreset should return empty refs.
+    fn test_poly_rescales_steps() {
         let program = parse(
             r#"
-const synth = Oscillator({type: 'square'});
-track riff() {
-    track.instrument = synth;
-    C3 /4
+track t() {
+    poly(3, 4) {
+        C4 1
+        E4 1
+        G4 1
+    }
+    D4 1
 }
-riff();
+t();
 "#,
         )
         .unwrap();
-
-        let event_list = compile(&program).unwrap();
-        let refs = extract_preset_refs(&event_list);
-        assert!(refs.is_empty());
+        let events = compile(&program).unwrap();
+        let notes: Vec<(f64, f64)> = events.events.iter().filter_map(|e| match &e.kind {
+            EventKind::Note { gate, .. } => Some((e.time, *gate)),
+            _ => None,
+        }).collect();
+        let times: Vec<f64> = notes.iter().map(|(t, _)| t * 3.0).collect();
+        assert_eq!(times, vec![0.0, 4.0, 8.0, 12.0]);
+        assert!((notes[0].1 - notes[3].1 * 4.0 / 3.0).abs() < 1e-9);
     }
 
-    // ── Async track execution tests ─────────────────────────
-
     #[test]
-    fn test_parallel_tracks_overlap() {
-        // Two consecutive track calls without explicit step should start at
-        // the same beat (async/parallel), not sequential.
+    fn test_poly_rescales_track_call_extent() {
         let program = parse(
             r#"
-track melody() {
-    C4 /4
-    D4 /4
-    E4 /4
+track inner() {
+    C4 1
+    E4 1
+    G4 1
 }
-
-track bass() {
-    C2 /2
-    D2 /2
+track t() {
+    poly(3, 2) {
+        inner();
+        _ 1
+        _ 1
+        _ 1
+    }
+    D4 /2
 }
-
-melody();
-bass();
+t();
 "#,
         )
         .unwrap();
-
         let events = compile(&program).unwrap();
-        let notes: Vec<_> = events
-            .events
-            .iter()
-            .filter_map(|e| match &e.kind {
-                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+        let last = events.events.iter().rfind(|e| matches!(e.kind, EventKind::Note { .. })).unwrap();
+        assert!((last.time - 2.0).abs() < 1e-9, "D4 follows the squeezed block, got {}", last.time);
+        // The inlined track ends at beat 2, not 3, so the song ends with D4
+        assert!((events.total_beats - 2.5).abs() < 1e-9, "total_beats = {}", events.total_beats);
+    }
+
+    #[test]
+    fn test_generative_choices_follow_seed() {
+        let song = |seed: u32, extra: &str| {
+            let source = format!(
+                "song.seed = {seed};\ntrack a() {{\n    choose([C4, E4, G4, B4]) 1\n    choose([C4, E4, G4, B4]) 1\n    choose([C4, E4, G4, B4]) 1\n    choose([C4, E4, G4, B4]) 1\n}}\ntrack b() {{\n    {extra}\n    choose([C5, D5]) 1\n}}\na();\nb();"
+            );
+            let events = compile(&parse(&source).unwrap()).unwrap();
+            events.events.iter().filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, .. } if e.track_name.as_deref() == Some("a") => Some(pitch.clone()),
                 _ => None,
-            })
-            .collect();
+            }).collect::<Vec<_>>()
+        };
+        let first = song(7, "");
+        assert_eq!(first, song(7, ""));
+        assert!(first.iter().all(|p| ["C4", "E4", "G4", "B4"].contains(&p.as_str())));
+        assert!((0..8).any(|seed| song(seed, "") != first), "Other seeds pick other notes");
+        // Choices in another track don't shift this one
+        assert_eq!(first, song(7, "choose([C5, D5]) 1"));
+    }
 
-        // melody: C4@0, D4@0.25, E4@0.5
-        // bass:   C2@0, D2@0.5    (parallel, NOT at 0.75/1.25)
-        assert!(notes.iter().any(|(t, p)| *t == 0.0 && *p == "C4"));
-        assert!(notes.iter().any(|(t, p)| *t == 0.25 && *p == "D4"));
-        assert!(notes.iter().any(|(t, p)| *t == 0.5 && *p == "E4"));
-        assert!(notes.iter().any(|(t, p)| *t == 0.0 && *p == "C2"));
-        assert!(notes.iter().any(|(t, p)| *t == 0.5 && *p == "D2"));
+    #[test]
+    fn test_maybe_keeps_time_either_way() {
+        let notes = |p: &str| {
+            let source = format!("track t() {{\n    maybe({p}) {{\n        C4 1\n    }}\n    D4 1\n}}\nt();");
+            let events = compile(&parse(&source).unwrap()).unwrap();
+            events.events.iter().filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, .. } => Some((e.time, pitch.clone())),
+                _ => None,
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(notes("1"), vec![(0.0, "C4".to_string()), (1.0, "D4".to_string())]);
+        assert_eq!(notes("0"), vec![(1.0, "D4".to_string())]);
+
+        // A skipped block still applies its property changes and markers
+        let source = "track t() {\n    maybe(0) {\n        track.beatsPerMinute = 90;\n        marker \"drop\"\n        C4 1\n    }\n    D4 1\n}\nt();";
+        let events = compile(&parse(source).unwrap()).unwrap();
+        assert!(events.events.iter().any(|e| matches!(
+            &e.kind,
+            EventKind::SetProperty { target, value, .. } if target == "track.beatsPerMinute" && value == "90"
+        )));
+        assert!(events.events.iter().any(|e| matches!(&e.kind, EventKind::Marker { name, .. } if name == "drop")));
+        assert_eq!(events.events.iter().filter(|e| matches!(e.kind, EventKind::Note { .. })).count(), 1);
+
+        let err = compile(&parse("song.seed = -1;").unwrap()).unwrap_err();
+        assert!(err.contains("song.seed"), "{err}");
+    }
 
-        // total_beats = max of both tracks = 1.0 (bass: 0.5 + 0.5)
-        assert_eq!(events.total_beats, 1.0);
+    #[test]
+    fn test_song_constants_resolve_values() {
+        let src = "\
+const lead = Oscillator({type: 'square'});
+const pad = Layer([lead, loadPreset(\"Strings\")]);
+const bars = 8;
+const hook = riff(lead);
+const broken = Mystery();
+track riff(inst) {
+    C4 1
+}";
+        let constants = song_constants(&parse(src).unwrap());
+        let names: Vec<&str> = constants.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["lead", "pad", "bars", "hook", "broken"]);
+        assert!(matches!(
+            &constants[0].value,
+            ConstantValue::Instrument { summary, .. } if summary == "square oscillator"
+        ));
+        assert!(matches!(
+            &constants[1].value,
+            ConstantValue::Instrument { summary, preset_refs, .. }
+                if summary == "Layer of 2" && *preset_refs == vec!["Strings".to_string()]
+        ));
+        assert_eq!(constants[2].value, ConstantValue::Number { value: 8.0 });
+        assert_eq!(
+            constants[3].value,
+            ConstantValue::Performance { track: "riff".into(), args: vec!["lead".into()] }
+        );
+        assert!(matches!(&constants[4].value, ConstantValue::Invalid { error } if error.contains("Mystery")));
+        assert!(src[constants[1].span_start..constants[1].span_end].starts_with("const pad"));
     }
 
     #[test]
-    fn test_parallel_tracks_with_stagger() {
-        // Explicit step on first track call creates a stagger.
-        // melody() 4; bass();  → melody at beat 0, bass at beat 4
-        let program = parse(
-            r#"
+    fn test_instrument_usage_per_track() {
+        let src = "\
+const lead = Oscillator({type: 'square'});
+const piano = loadPreset(\"Piano\");
 track melody() {
-    C4 /4
+    track.instrument = lead;
+    C4 1
+    G4 1
+    track.instrument = piano;
+    E5 1
+    track.instrument = lead;
+    A3 1
 }
-
 track bass() {
-    C2 /4
+    track.instrument = piano;
+    C2 2
 }
+melody();
+bass();";
+        let events = compile(&parse(src).unwrap()).unwrap();
+        let usage = instrument_usage(&events);
+        assert_eq!(usage.len(), 2);
+        let melody = &usage[0];
+        assert_eq!(melody.track_name.as_deref(), Some("melody"));
+        assert_eq!(melody.note_count, 4);
+        assert_eq!((melody.lowest_pitch.as_deref(), melody.highest_pitch.as_deref()), (Some("A3"), Some("E5")));
+        let summaries: Vec<&str> = melody.instruments.iter().map(|u| u.summary.as_str()).collect();
+        assert_eq!(summaries, vec!["square oscillator", "preset Piano"]);
+        let lead_ranges: Vec<f64> = melody.instruments[0].ranges.iter().map(|r| r.start).collect();
+        assert_eq!(lead_ranges, vec![0.0, 3.0]);
+        assert_eq!(melody.instruments[0].note_count, 3);
+        assert_eq!(melody.instruments[1].preset_refs, vec!["Piano".to_string()]);
+        assert_eq!(usage[1].instruments[0].ranges.len(), 1);
+    }
 
-melody() 4;
-bass();
-"#,
-        )
-        .unwrap();
+    #[test]
+    fn test_extract_preset_needs_orders_by_first_use() {
+        let src = "\
+track.beatsPerMinute = 60;
+const strings = loadPreset(\"Strings\");
+const piano = loadPreset(\"Piano\");
+const spare = loadPreset(\"Spare\");
+track intro() {
+    track.instrument = piano;
+    C4 1
+    D4 1
+}
+track pad() {
+    4
+    track.instrument = strings;
+    C3 2
+}
+track outro() {
+    track.instrument = piano;
+    E4 1
+}
+intro();
+pad();
+outro() 2;";
+        let events = compile(&parse(src).unwrap()).unwrap();
+        let needs = extract_preset_needs(&events, 120.0);
+        let order: Vec<(&str, Option<f64>)> = needs.iter().map(|n| (n.name.as_str(), n.first_beat)).collect();
+        assert_eq!(order, vec![("Piano", Some(0.0)), ("Strings", Some(4.0)), ("Spare", None)]);
+        assert_eq!(needs[1].first_seconds, Some(4.0));
+        let piano_tracks: Vec<(Option<&str>, usize)> =
+            needs[0].tracks.iter().map(|t| (t.track_name.as_deref(), t.note_count)).collect();
+        assert_eq!(piano_tracks, vec![(Some("intro"), 2), (Some("outro"), 1)]);
+        assert_eq!(needs[0].estimated_bytes, None);
+    }
 
-        let events = compile(&program).unwrap();
-        let notes: Vec<_> = events
+    #[test]
+    fn test_end_mode_from_str() {
+        assert_eq!("gate".parse::<EndMode>(), Ok(EndMode::Gate));
+        assert_eq!("tail".parse::<EndMode>(), Ok(EndMode::Tail));
+        assert!("fade".parse::<EndMode>().unwrap_err().starts_with("Unknown end mode 'fade'"));
+    }
+
+    #[test]
+    fn test_lint_notes() {
+        let source = "track t() {\n    C4@2 1\n    C4 1\n    E4@0 1\n    G4@8 1\n}\nt() 4;\nmarker \"chorus\";\nt();\n";
+        let events = compile(&parse(source).unwrap()).unwrap();
+        let issues = lint_notes(&events);
+        let summary: Vec<(NoteIssueKind, f64, &str)> =
+            issues.iter().map(|i| (i.kind, i.beat, &source[i.span_start..i.span_end])).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (NoteIssueKind::Retrigger, 1.0, "C4 1"),
+                (NoteIssueKind::ZeroLength, 2.0, "E4@0 1"),
+                (NoteIssueKind::PastMarker, 3.0, "G4@8 1"),
+                // The second pass has no marker ahead, but its G4 cuts
+                // into the first pass's
+                (NoteIssueKind::Retrigger, 5.0, "C4 1"),
+                (NoteIssueKind::ZeroLength, 6.0, "E4@0 1"),
+                (NoteIssueKind::Retrigger, 7.0, "G4@8 1"),
+            ]
+        );
+        assert_eq!(issues[2].message, "G4 is held 7 beats past the marker at beat 4.");
+        assert_eq!(issues[0].track_name.as_deref(), Some("t"));
+    }
+
+    #[test]
+    fn test_property_and_track_start_spans() {
+        let source = "track.beatsPerMinute = 90;\ntrack t() {\n    track.instrument = Oscillator({type: 'square'});\n    C4 1\n}\nt();\ndrums*80(kit) 4;\n";
+        let events = compile(&parse(source).unwrap()).unwrap();
+        let spans: Vec<&str> = events
             .events
             .iter()
             .filter_map(|e| match &e.kind {
-                EventKind::Note { pitch, .. } => Some((e.time, pitch.as_str())),
+                EventKind::SetProperty { source_start, source_end, .. }
+                | EventKind::TrackStart { source_start, source_end, .. } => Some(&source[*source_start..*source_end]),
                 _ => None,
             })
             .collect();
-
-        assert!(notes.iter().any(|(t, p)| *t == 0.0 && *p == "C4"));
-        assert!(notes.iter().any(|(t, p)| *t == 4.0 && *p == "C2"));
+        assert_eq!(
+            spans,
+            vec![
+                "track.beatsPerMinute = 90",
+                "track.instrument = Oscillator({type: 'square'})",
+                "drums*80(kit) 4",
+            ]
+        );
     }
 
     #[test]
-    fn test_total_beats_reflects_longest_track() {
-        // total_beats should be the max extent of all parallel tracks.
-        let program = parse(
-            r#"
-track short() {
-    C4 /4
+    fn test_let_variables_in_notes_and_durations() {
+        let notes = |src: &str| -> Vec<(f64, String, f64)> {
+            compile(&parse(src).unwrap())
+                .unwrap()
+                .events
+                .iter()
+                .filter_map(|e| match &e.kind {
+                    EventKind::Note { pitch, gate, .. } => Some((e.time, pitch.clone(), *gate)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let src = "\
+track t() {
+    let root = Eb3;
+    let step = 1/4;
+    track.noteLength = step * 2;
+    root
+    root = 60 + 7;
+    [root, C5] 1
 }
-
-track long() {
-    C2 1
-    D2 1
-    E2 1
-    F2 1
+t();";
+        assert_eq!(
+            notes(src),
+            vec![
+                (0.0, "Eb3".to_string(), 0.5),
+                (0.5, "G4".to_string(), 0.5),
+                (0.5, "C5".to_string(), 0.5),
+            ]
+        );
+
+        // Loops run until their condition fails; block variables go out of scope
+        let src = "\
+track t() {
+    for (let i = 0; i < 3; i++) {
+        let p = 60 + i * 2;
+        p /2
+    }
+    let p = C2;
+    p /2
 }
-
-short();
-long();
-"#,
-        )
-        .unwrap();
-
-        let events = compile(&program).unwrap();
-        // short: 0.25 beats. long: 4 beats.
-        // total_beats = max(0, 4.0) = 4.0  (cursor restored to 0, max_cursor = 4)
-        assert_eq!(events.total_beats, 4.0);
+t();";
+        let pitches: Vec<(f64, String)> = notes(src).into_iter().map(|(t, p, _)| (t, p)).collect();
+        assert_eq!(
+            pitches,
+            vec![(0.0, "C4".into()), (0.5, "D4".into()), (1.0, "E4".into()), (1.5, "C2".into())]
+        );
+        let countdown = notes("track t() {\n    for (let n = 4; n > 0 && n != 1; n = n - 1) {\n        C4 1\n    }\n}\nt();");
+        assert_eq!(countdown.len(), 3);
+
+        // A variable in step position is the step, not another note
+        let src = "track t() {\n    let step = 1/8;\n    C4 step\n    [D4, F4] step\n    E4 step\n}\nt();";
+        let times: Vec<(f64, String)> = notes(src).into_iter().map(|(t, p, _)| (t, p)).collect();
+        assert_eq!(
+            times,
+            vec![(0.0, "C4".into()), (0.125, "D4".into()), (0.125, "F4".into()), (0.25, "E4".into())]
+        );
     }
 
-    // ── cursor_context tests ────────────────────────────────
-
     #[test]
-    fn test_cursor_context_top_level_defaults() {
-        let source = r#"
-track riff() {
-    C3 /4
-}
-riff();
-"#;
-        // Cursor at the very start — should get defaults.
-        let ctx = cursor_context(source, 0).unwrap();
-        assert_eq!(ctx.bpm, 120.0);
-        assert_eq!(ctx.tuning_pitch, 440.0);
-        assert_eq!(ctx.note_length, 1.0);
-        assert!(ctx.track_name.is_none());
+    fn test_let_variable_errors() {
+        let err = |src: &str| compile(&parse(src).unwrap()).unwrap_err();
+        assert_eq!(
+            err("track t() {\n    for (let i = 0; ; i++) {}\n}\nt();"),
+            "For loop ran 10000 times without its condition failing."
+        );
+        assert_eq!(
+            err("track t() {\n    for (let i = 0; i < 2; j++) {}\n}\nt();"),
+            "Unknown variable 'j' in for loop. Declare it with 'let'."
+        );
+        assert_eq!(err("track t() {\n    let a = \"x\" - 1;\n}\nt();"), "Cannot apply '-' to 'x' and 1.");
+        assert_eq!(err("track t() {\n    let z = 0;\n    let d = 2 / z;\n}\nt();"), "Division by zero in 2 / 0.");
+        assert_eq!(
+            err("track t() {\n    let p = 1/4;\n    p\n}\nt();"),
+            "Variable 'p' is 0.25, not a note name or MIDI number."
+        );
+        assert_eq!(
+            err("track t() {\n    let p = C4;\n    D4 p\n}\nt();"),
+            "Variable 'p' is 'C4', not a duration."
+        );
+        // A called track does not see the caller's variables
+        let src = "track inner() {\n    x\n}\ntrack outer() {\n    let x = 60;\n    inner();\n}\nouter();";
+        let events = compile(&parse(src).unwrap()).unwrap().events;
+        assert!(events.iter().any(|e| matches!(&e.kind, EventKind::Note { pitch, .. } if pitch == "x")));
     }
 
     #[test]
-    fn test_cursor_context_after_bpm_assignment() {
-        let source = "track.beatsPerMinute = 140;\ntrack riff() { C3 /4 }\nriff();";
-        // Cursor past the BPM assignment — should see 140 BPM.
-        let ctx = cursor_context(source, 30).unwrap();
-        assert_eq!(ctx.bpm, 140.0);
+    fn test_compile_optimized() {
+        let src = "\
+track.beatsPerMinute = 60 * 2;
+track unused() {
+    C4 1
+}
+track helper() {
+    E4 1
+}
+track verse() {
+    let n = 59 + 1;
+    n 1
+    helper();
+}
+verse();
+track.beatsPerMinute = 120;
+track.beatsPerMinute = 90 + 'x';
+verse();";
+        let program = parse(src).unwrap();
+        let plain = compile(&program).unwrap();
+        let optimized = compile_optimized(&program, false).unwrap();
+        assert_eq!(
+            optimized.report,
+            OptimizationReport {
+                folded_expressions: 3,
+                removed_tracks: vec!["unused".to_string()],
+                merged_properties: 1,
+            }
+        );
+        let tempos = |events: &[Event]| -> Vec<String> {
+            events
+                .iter()
+                .filter_map(|e| match &e.kind {
+                    EventKind::SetProperty { value, .. } => Some(value.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(tempos(&plain.events), vec!["120", "120", "90x"]);
+        assert_eq!(tempos(&optimized.event_list.events), vec!["120", "90x"]);
+        let notes = |events: &[Event]| events.iter().filter(|e| matches!(e.kind, EventKind::Note { .. })).count();
+        assert_eq!(notes(&optimized.event_list.events), notes(&plain.events));
+        assert_eq!(optimized.event_list.total_beats, plain.total_beats);
+
+        // Operators that would fail stay for the compiler to report
+        let mut program = parse("track t() {\n    let z = 1 / (2 - 2);\n}\nt();").unwrap();
+        assert_eq!(fold_constants(&mut program), 1);
+        assert_eq!(compile(&program).unwrap_err(), "Division by zero in 1 / 0.");
     }
 
     #[test]
-    fn test_cursor_context_inside_track_def() {
-        let source = r#"const lead = Oscillator({type: "square"});
-track melody() {
+    fn test_event_region() {
+        let src = "\
+const lead = Oscillator({type: 'square'});
+track.beatsPerMinute = 100;
+track t() {
+    C4 /2
+    track.beatsPerMinute = 140;
+    D4@4 /1
     track.instrument = lead;
-    C4 /4
-    D4 /4
+    track.beatsPerMinute = 160;
+    E4 /1
+    marker \"chorus\"
+    F4 /1
+    G4 /1
 }
-melody();
-"#;
-        // Find byte offset inside the track body, after instrument assignment.
-        // "C4 /4" is well past "track.instrument = lead;" inside the body.
-        let c4_offset = source.find("C4 /4").unwrap();
-        let ctx = cursor_context(source, c4_offset).unwrap();
-        assert_eq!(ctx.track_name.as_deref(), Some("melody"));
-        assert_eq!(ctx.instrument.waveform, "square");
+t();";
+        let events = compile(&parse(src).unwrap()).unwrap();
+        let region = event_region(&events, 2.0, 3.0);
+        let kinds: Vec<(f64, String)> = region
+            .events
+            .iter()
+            .map(|e| {
+                let kind = match &e.kind {
+                    EventKind::Note { pitch, .. } => pitch.clone(),
+                    EventKind::SetProperty { target, value, .. } => format!("{target} = {value}"),
+                    EventKind::Marker { name, .. } => name.clone(),
+                    other => format!("{other:?}"),
+                };
+                (e.time, kind)
+            })
+            .collect();
+        // D4 is still held; the tempo in effect on each track is kept
+        assert_eq!(
+            kinds,
+            vec![
+                (0.0, "track.beatsPerMinute = 100".to_string()),
+                (0.5, "D4".to_string()),
+                (1.5, "track.instrument = lead".to_string()),
+                (1.5, "track.beatsPerMinute = 160".to_string()),
+                (1.5, "E4".to_string()),
+                (2.5, "chorus".to_string()),
+                (2.5, "F4".to_string()),
+            ]
+        );
+        assert_eq!(region.total_beats, events.total_beats);
+        assert_eq!(region.instruments.len(), 2);
+        assert_eq!(oscillator(&region.instruments[1]).waveform, "square");
+        assert!(event_region(&events, 10.0, 20.0).events.iter().all(|e| !matches!(e.kind, EventKind::Note { .. })));
     }
 
     #[test]
-    fn test_cursor_context_after_tuning_change() {
-        let source = "track.tuningPitch = 432;\ntrack riff() { C3 /4 }\nriff();";
-        let ctx = cursor_context(source, 30).unwrap();
-        assert_eq!(ctx.tuning_pitch, 432.0);
+    fn test_envelope_hold_and_curves() {
+        let program = parse(
+            "track t() {\n    track.instrument = Oscillator({attack: 0.1, hold: 0.2, attackCurve: 'exp', releaseCurve: 'log'});\n    C4 1\n}\nt();",
+        )
+        .unwrap();
+        let events = compile(&program).unwrap();
+        let envelope = &oscillator(&events.instruments[0]).envelope;
+        assert_eq!(envelope.hold, Some(0.2));
+        assert_eq!(envelope.attack_curve, Some(EnvelopeCurve::Exp));
+        assert_eq!(envelope.decay_curve, None);
+        assert_eq!(envelope.release_curve, Some(EnvelopeCurve::Log));
+
+        // Unknown curve names are errors rather than a silent linear ramp
+        let err = compile(&parse("track t() {\n    track.instrument = Oscillator({attackCurve: 'steep'});\n    C4 1\n}\nt();").unwrap()).unwrap_err();
+        assert!(err.contains("attackCurve 'steep'"), "{err}");
+        let err = compile(&parse("track t() {\n    track.instrument = loadPreset(\"Piano\", {releaseCurve: 2});\n    C4 1\n}\nt();").unwrap()).unwrap_err();
+        assert!(err.contains("releaseCurve '2'"), "{err}");
     }
 
     #[test]
-    fn test_cursor_context_note_length_change() {
-        let source = r#"track riff() {
-    track.noteLength = 1/8;
-    C3
+    fn test_instrument_velocity_response() {
+        let program = parse(
+            "track t() {\n    track.instrument = loadPreset(\"Piano\", {velocityCurve: 'soft', velocityFilter: 0.5});\n    C4 1\n    track.instrument = FM({velocityCurve: [0, 0.6, 1]});\n    D4 1\n}\nt();",
+        )
+        .unwrap();
+        let events = compile(&program).unwrap();
+        let InstrumentConfig::SamplerRef(piano) = &events.instruments[0] else { panic!() };
+        assert_eq!(piano.velocity, VelocityConfig { curve: Some(VelocityCurve::Soft), filter: Some(0.5) });
+        let fm = events.instruments[1].velocity().unwrap();
+        assert_eq!(fm.curve, Some(VelocityCurve::Table(vec![0.0, 0.6, 1.0])));
+
+        assert!((fm.level(0.25) - 0.3).abs() < 1e-12);
+        assert!((fm.level(0.75) - 0.8).abs() < 1e-12);
+        assert_eq!(piano.velocity.level(0.25), 0.5);
+        assert_eq!(piano.velocity.brightness(1.0), Some(1.0));
+        assert_eq!(piano.velocity.brightness(0.0), Some(0.5));
+        assert_eq!(VelocityCurve::Hard.apply(0.5), 0.25);
+        assert_eq!(VelocityConfig::default().brightness(0.2), None);
+    }
+
+    #[test]
+    fn test_split_object_form() {
+        let program = parse(
+            r#"
+const bass = loadPreset("Bass");
+track keys() {
+    track.instrument = Split({below: bass, at: C3, above: loadPreset("Lead")});
+    C2 /4
+    C4 /4
 }
-riff();
-"#;
-        // Cursor after the noteLength assignment inside the track body.
-        let c3_offset = source.find("C3").unwrap();
-        let ctx = cursor_context(source, c3_offset).unwrap();
-        assert_eq!(ctx.note_length, 0.125); // 1/8
+keys();
+"#,
+        )
+        .unwrap();
+        let events = compile(&program).unwrap();
+        assert_eq!(events.instruments.len(), 1);
+        let InstrumentConfig::Composite(split) = &events.instruments[0] else {
+            panic!("Expected a composite, got {:?}", events.instruments[0]);
+        };
+        assert_eq!(split.mode, CompositeKind::Split);
+        assert_eq!(split.split_points, Some(vec![48]));
+        assert_eq!(events.instruments[0].preset_refs(), vec!["Bass".to_string(), "Lead".to_string()]);
+
+        // Same as the array form
+        let array = parse("track t() {\n    track.instrument = Split([loadPreset(\"Bass\"), loadPreset(\"Lead\")], [C3]);\n    C4 1\n}\nt();");
+        let array = compile(&array.unwrap()).unwrap();
+        assert_eq!(array.instruments, events.instruments);
     }
 }
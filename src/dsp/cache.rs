@@ -0,0 +1,322 @@
+//! Render cache — rendered track audio kept between renders.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+/// Default limit for cached audio: 128 MiB.
+pub const DEFAULT_RENDER_CACHE_BYTES: usize = 128 * 1024 * 1024;
+
+/// Snapshot of the render cache for display in the editor.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RenderCacheStats {
+    pub entries: usize,
+    pub memory_bytes: usize,
+    pub max_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A track's mix: mono, or left and right once any of its notes is panned.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackMix {
+    pub left: Vec<f64>,
+    pub right: Option<Vec<f64>>,
+}
+
+impl TrackMix {
+    /// A silent mono mix of `len` samples.
+    pub fn silent(len: usize) -> Self {
+        TrackMix {
+            left: vec![0.0; len],
+            right: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty()
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of_val(self.left.as_slice())
+            + self.right.as_ref().map_or(0, |r| std::mem::size_of_val(r.as_slice()))
+    }
+
+    /// Mix `other` into this mix, becoming stereo if `other` is. Samples
+    /// of `other` past the end of this mix are dropped.
+    pub fn add(&mut self, other: &TrackMix) {
+        if self.right.is_none() && other.right.is_some() {
+            self.right = Some(self.left.clone());
+        }
+        for (out, s) in self.left.iter_mut().zip(&other.left) {
+            *out += s;
+        }
+        if let Some(right) = &mut self.right {
+            let other_right = other.right.as_ref().unwrap_or(&other.left);
+            for (out, s) in right.iter_mut().zip(other_right) {
+                *out += s;
+            }
+        }
+    }
+
+    /// The mix as mono: the average of left and right when stereo.
+    pub fn into_mono(self) -> Vec<f64> {
+        match self.right {
+            Some(right) => self
+                .left
+                .iter()
+                .zip(&right)
+                .map(|(l, r)| (l + r) * 0.5)
+                .collect(),
+            None => self.left,
+        }
+    }
+}
+
+/// Mono inputs of the shared effect buses, summed from voice sends.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BusInputs {
+    pub reverb: Vec<f64>,
+    pub delay: Vec<f64>,
+}
+
+impl BusInputs {
+    /// Silent bus inputs of `len` samples.
+    pub fn silent(len: usize) -> Self {
+        BusInputs { reverb: vec![0.0; len], delay: vec![0.0; len] }
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of_val(self.reverb.as_slice()) + std::mem::size_of_val(self.delay.as_slice())
+    }
+
+    /// Add `other` into these inputs, dropping samples past their end.
+    pub fn add(&mut self, other: &BusInputs) {
+        for (out, s) in self.reverb.iter_mut().zip(&other.reverb) {
+            *out += s;
+        }
+        for (out, s) in self.delay.iter_mut().zip(&other.delay) {
+            *out += s;
+        }
+    }
+}
+
+/// How a track used voices, to tell whether it would have met other
+/// tracks' voices in a full render.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VoiceUse {
+    /// Voices sounding in each block once the block's notes started.
+    pub per_block: Vec<u16>,
+    /// Exclusive groups its voices played, by preset.
+    pub choke_groups: Vec<(String, u32)>,
+}
+
+/// A track's cached render: its mix, what it sends to the effect buses
+/// when any of its notes use `track.sends`, and its voice use.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CachedTrack {
+    pub mix: TrackMix,
+    pub sends: Option<BusInputs>,
+    pub voices: VoiceUse,
+}
+
+impl CachedTrack {
+    pub fn memory_bytes(&self) -> usize {
+        self.mix.memory_bytes()
+            + self.sends.as_ref().map_or(0, BusInputs::memory_bytes)
+            + std::mem::size_of_val(self.voices.per_block.as_slice())
+    }
+}
+
+struct CacheEntry {
+    samples: Arc<CachedTrack>,
+    last_used: u64,
+}
+
+/// Rendered tracks keyed by a hash of their scheduled notes.
+///
+/// Least recently used entries are dropped to stay within `max_bytes`.
+pub struct RenderCache {
+    entries: HashMap<u64, CacheEntry>,
+    max_bytes: usize,
+    memory_bytes: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for RenderCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_RENDER_CACHE_BYTES)
+    }
+}
+
+impl RenderCache {
+    pub fn new(max_bytes: usize) -> Self {
+        RenderCache {
+            entries: HashMap::new(),
+            max_bytes,
+            memory_bytes: 0,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Look up a cached track, marking it as recently used.
+    pub fn get(&mut self, key: u64) -> Option<Arc<CachedTrack>> {
+        self.clock += 1;
+        match self.entries.get_mut(&key) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                self.hits += 1;
+                Some(entry.samples.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Cache a track, evicting older entries if over the limit. Tracks
+    /// larger than the whole limit are not cached.
+    pub fn insert(&mut self, key: u64, samples: Arc<CachedTrack>) {
+        let bytes = samples.memory_bytes();
+        if bytes > self.max_bytes {
+            return;
+        }
+        self.clock += 1;
+        if let Some(old) = self.entries.insert(
+            key,
+            CacheEntry {
+                samples,
+                last_used: self.clock,
+            },
+        ) {
+            self.memory_bytes -= old.samples.memory_bytes();
+        }
+        self.memory_bytes += bytes;
+        self.evict();
+    }
+
+    /// Drop all cached audio.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.memory_bytes = 0;
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Change the limit, evicting entries if now over it.
+    pub fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+        self.evict();
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> RenderCacheStats {
+        RenderCacheStats {
+            entries: self.entries.len(),
+            memory_bytes: self.memory_bytes,
+            max_bytes: self.max_bytes,
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.memory_bytes > self.max_bytes {
+            let Some(key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| *k)
+            else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.memory_bytes -= entry.samples.memory_bytes();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(len: usize) -> Arc<CachedTrack> {
+        Arc::new(CachedTrack { mix: TrackMix::silent(len), ..Default::default() })
+    }
+
+    #[test]
+    fn hit_and_miss() {
+        let mut cache = RenderCache::default();
+        assert!(cache.get(1).is_none());
+        cache.insert(1, Arc::new(CachedTrack { mix: TrackMix { left: vec![0.5; 4], right: None }, ..Default::default() }));
+        assert_eq!(cache.get(1).unwrap().mix.len(), 4);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.memory_bytes, 32);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = RenderCache::new(64);
+        cache.insert(1, track(4));
+        cache.insert(2, track(4));
+        cache.get(1);
+        cache.insert(3, track(4));
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+        assert!(cache.memory_bytes() <= 64);
+
+        cache.set_max_bytes(0);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn adding_stereo_mix_splits_channels() {
+        let mut mix = TrackMix {
+            left: vec![1.0, 1.0],
+            right: None,
+        };
+        mix.add(&TrackMix {
+            left: vec![0.5, 0.5],
+            right: Some(vec![0.0, 0.0]),
+        });
+        assert_eq!(mix.left, vec![1.5, 1.5]);
+        assert_eq!(mix.right, Some(vec![1.0, 1.0]));
+        assert_eq!(mix.into_mono(), vec![1.25, 1.25]);
+    }
+
+    #[test]
+    fn bus_sends_count_towards_memory() {
+        let mut cache = RenderCache::default();
+        let sends = BusInputs::silent(4);
+        cache.insert(1, Arc::new(CachedTrack { mix: TrackMix::silent(4), sends: Some(sends), ..Default::default() }));
+        assert_eq!(cache.memory_bytes(), 96);
+    }
+}
@@ -4,6 +4,7 @@
 //! that produce a rich, "doubled" sound.
 
 use std::f64::consts::PI;
+use crate::math;
 
 /// A stereo chorus effect with configurable rate, depth, and mix.
 #[derive(Debug, Clone)]
@@ -94,8 +95,8 @@ impl Chorus {
         self.buffer_r[self.write_pos] = right;
 
         // Calculate modulated delay times
-        let lfo_l = (2.0 * PI * self.phase_l).sin();
-        let lfo_r = (2.0 * PI * self.phase_r).sin();
+        let lfo_l = math::sin(2.0 * PI * self.phase_l);
+        let lfo_r = math::sin(2.0 * PI * self.phase_r);
 
         let delay_l = (self.delay + self.depth * lfo_l) * self.sample_rate;
         let delay_r = (self.delay + self.depth * lfo_r) * self.sample_rate;
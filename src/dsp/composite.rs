@@ -7,7 +7,8 @@
 
 use super::sampler::{SamplerVoice, Sampler};
 use super::voice::Voice;
-use crate::compiler::InstrumentConfig;
+use crate::compiler::{EnvelopeConfig, FmConfig, OscillatorConfig};
+use crate::math;
 
 /// Mode of combination for composite children.
 #[derive(Debug, Clone, PartialEq)]
@@ -37,7 +38,9 @@ pub enum CompositeChild {
     /// A sampler with zones.
     Sampler(Sampler),
     /// An oscillator with configuration.
-    Oscillator(InstrumentConfig),
+    Oscillator(OscillatorConfig),
+    /// A two-operator FM synth.
+    Fm(FmConfig),
     /// A nested composite.
     Composite(Box<CompositeInstrument>),
 }
@@ -61,6 +64,56 @@ impl CompositeInstrument {
         }
     }
 
+    /// The sampler children, nested composites included, depth first.
+    pub fn samplers(&self) -> Vec<&Sampler> {
+        let mut samplers = Vec::new();
+        for child in &self.children {
+            match child {
+                CompositeChild::Sampler(sampler) => samplers.push(sampler),
+                CompositeChild::Oscillator(_) | CompositeChild::Fm(_) => {}
+                CompositeChild::Composite(composite) => samplers.extend(composite.samplers()),
+            }
+        }
+        samplers
+    }
+
+    /// Mutable access to the sampler children, in the order of `samplers`.
+    pub fn samplers_mut(&mut self) -> Vec<&mut Sampler> {
+        let mut samplers = Vec::new();
+        for child in &mut self.children {
+            match child {
+                CompositeChild::Sampler(sampler) => samplers.push(sampler),
+                CompositeChild::Oscillator(_) | CompositeChild::Fm(_) => {}
+                CompositeChild::Composite(composite) => samplers.extend(composite.samplers_mut()),
+            }
+        }
+        samplers
+    }
+
+    /// Approximate heap memory held by all sampler children, in bytes.
+    pub fn memory_bytes(&self) -> usize {
+        self.children
+            .iter()
+            .map(|child| match child {
+                CompositeChild::Sampler(sampler) => sampler.memory_bytes(),
+                CompositeChild::Oscillator(_) | CompositeChild::Fm(_) => 0,
+                CompositeChild::Composite(composite) => composite.memory_bytes(),
+            })
+            .sum()
+    }
+
+    /// Count the sample zones held by all sampler children.
+    pub fn zone_count(&self) -> usize {
+        self.children
+            .iter()
+            .map(|child| match child {
+                CompositeChild::Sampler(sampler) => sampler.zones.len(),
+                CompositeChild::Oscillator(_) | CompositeChild::Fm(_) => 0,
+                CompositeChild::Composite(composite) => composite.zone_count(),
+            })
+            .sum()
+    }
+
     /// Trigger a note and return all active voices for that note.
     pub fn trigger_note(
         &self,
@@ -123,11 +176,50 @@ impl CompositeInstrument {
             }
         }
     }
+
+    /// Whether triggering `midi_note` would produce any voice, following
+    /// the same routing as `trigger_note`.
+    pub fn plays_note(&self, midi_note: u8) -> bool {
+        match self.mode {
+            CompositeMode::Layer => self.children.iter().any(|child| child.plays_note(midi_note)),
+            CompositeMode::Split => match &self.split_points {
+                Some(split_pts) => {
+                    let child_idx = split_pts.iter().filter(|&&pt| midi_note >= pt).count();
+                    self.children
+                        .get(child_idx.min(self.children.len().saturating_sub(1)))
+                        .is_some_and(|child| child.plays_note(midi_note))
+                }
+                None => self.children.iter().any(|child| child.plays_note(midi_note)),
+            },
+            CompositeMode::Chain => self.children.first().is_some_and(|child| child.plays_note(midi_note)),
+        }
+    }
+
+    /// Whether every child is a drum kit.
+    pub fn is_drum_kit(&self) -> bool {
+        !self.children.is_empty()
+            && self.children.iter().all(|child| match child {
+                CompositeChild::Sampler(sampler) => sampler.is_drum_kit,
+                CompositeChild::Composite(composite) => composite.is_drum_kit(),
+                CompositeChild::Oscillator(_) | CompositeChild::Fm(_) => false,
+            })
+    }
+}
+
+impl CompositeChild {
+    /// Whether this child sounds for `midi_note`.
+    fn plays_note(&self, midi_note: u8) -> bool {
+        match self {
+            CompositeChild::Sampler(sampler) => sampler.find_zone(midi_note).is_some(),
+            CompositeChild::Oscillator(_) | CompositeChild::Fm(_) => true,
+            CompositeChild::Composite(composite) => composite.plays_note(midi_note),
+        }
+    }
 }
 
 /// Convert MIDI note to frequency using the tuning pitch.
 fn midi_to_freq(midi_note: u8, tuning_pitch: f64) -> f64 {
-    tuning_pitch * (2.0_f64).powf((midi_note as f64 - 69.0) / 12.0)
+    tuning_pitch * math::powf(2.0, (midi_note as f64 - 69.0) / 12.0)
 }
 
 fn trigger_child(
@@ -138,20 +230,23 @@ fn trigger_child(
     engine_sample_rate: f64,
 ) -> Vec<CompositeVoice> {
     match child {
-        CompositeChild::Sampler(sampler) => {
-            if let Some(zone) = sampler.find_zone(midi_note) {
-                let voice = SamplerVoice::new(zone, midi_note, velocity, tuning_pitch, engine_sample_rate);
-                vec![CompositeVoice::Sampler(voice)]
-            } else {
-                Vec::new()
-            }
-        }
+        CompositeChild::Sampler(sampler) => sampler
+            .start_voice(midi_note, velocity, tuning_pitch, engine_sample_rate)
+            .map(CompositeVoice::Sampler)
+            .into_iter()
+            .collect(),
         CompositeChild::Oscillator(config) => {
             let mut voice = Voice::with_config(engine_sample_rate, config);
             let freq = midi_to_freq(midi_note, tuning_pitch);
             voice.note_on(freq, velocity);
             vec![CompositeVoice::Oscillator(voice)]
         }
+        CompositeChild::Fm(config) => {
+            let mut voice = Voice::with_fm(engine_sample_rate, config);
+            let freq = midi_to_freq(midi_note, tuning_pitch);
+            voice.note_on(freq, velocity);
+            vec![CompositeVoice::Oscillator(voice)]
+        }
         CompositeChild::Composite(composite) => {
             composite.trigger_note(midi_note, velocity, tuning_pitch, engine_sample_rate)
         }
@@ -180,12 +275,46 @@ impl CompositeVoice {
         }
     }
 
+    /// Glide the pitch by `ratio` over `samples` samples.
+    pub fn slide(&mut self, ratio: f64, samples: usize) {
+        match self {
+            CompositeVoice::Sampler(v) => v.glide_rate(ratio, samples),
+            CompositeVoice::Oscillator(v) => v.glide_to(v.target_frequency() * ratio, samples),
+        }
+    }
+
+    /// Override the envelope stages the config sets.
+    pub fn apply_envelope(&mut self, config: &EnvelopeConfig) {
+        match self {
+            CompositeVoice::Sampler(v) => v.apply_envelope(config),
+            CompositeVoice::Oscillator(v) => v.apply_envelope(config),
+        }
+    }
+
+    /// Modulate the pitch with a sine LFO of `rate` Hz and `depth`
+    /// semitones.
+    pub fn set_vibrato(&mut self, rate: f64, depth: f64) {
+        match self {
+            CompositeVoice::Sampler(v) => v.set_vibrato(rate, depth),
+            CompositeVoice::Oscillator(v) => v.set_vibrato(rate, depth),
+        }
+    }
+
     pub fn is_finished(&self) -> bool {
         match self {
             CompositeVoice::Sampler(v) => v.is_finished(),
             CompositeVoice::Oscillator(v) => v.is_finished(),
         }
     }
+
+    /// Stereo position of the zone a sampler sub-voice is playing;
+    /// oscillators are centred.
+    pub fn pan(&self) -> f64 {
+        match self {
+            CompositeVoice::Sampler(v) => v.pan(),
+            CompositeVoice::Oscillator(_) => 0.0,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -201,7 +330,7 @@ mod tests {
                 (2.0 * std::f64::consts::PI * freq * t).sin()
             })
             .collect();
-        SampleBuffer::new(data, sample_rate)
+        SampleBuffer::from_f64(&data, sample_rate)
     }
 
     fn make_zone(low: u8, high: u8, root: u8) -> LoadedZone {
@@ -213,7 +342,12 @@ mod tests {
             sample_rate: 44100,
             loop_start: None,
             loop_end: None,
-            buffer: make_sine_buffer(440.0, 0.5, 44100),
+            exclusive_group: None,
+            buffer: make_sine_buffer(440.0, 0.5, 44100).into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
         }
     }
 
@@ -374,4 +508,22 @@ mod tests {
         }
         assert!(finished, "Voice should finish after note_off");
     }
+
+    #[test]
+    fn plays_note_follows_routing() {
+        let low = Sampler::new(vec![make_zone(36, 59, 48)], false);
+        let high = Sampler::new(vec![make_zone(60, 84, 72)], false);
+        let split = CompositeInstrument::new_split(
+            vec![CompositeChild::Sampler(low.clone()), CompositeChild::Sampler(high)],
+            Some(vec![60]),
+        );
+        assert!(split.plays_note(36) && split.plays_note(84));
+        assert!(!split.plays_note(35) && !split.plays_note(85));
+        assert!(!split.is_drum_kit());
+
+        let kit = Sampler::new(vec![make_zone(36, 36, 36), make_zone(38, 38, 38)], true);
+        let drums = CompositeInstrument::new_layer(vec![CompositeChild::Sampler(kit)], None);
+        assert!(drums.is_drum_kit());
+        assert!(drums.plays_note(38) && !drums.plays_note(37));
+    }
 }
@@ -3,6 +3,8 @@
 //! Implements a feed-forward compressor with threshold, ratio, knee,
 //! attack, and release parameters matching the WebAudio DynamicsCompressorNode.
 
+use crate::math;
+
 /// A stereo dynamics compressor.
 #[derive(Debug, Clone)]
 pub struct Compressor {
@@ -62,14 +64,14 @@ impl Compressor {
         if linear <= 0.0 {
             -120.0
         } else {
-            20.0 * linear.log10()
+            20.0 * math::log10(linear)
         }
     }
 
     /// Convert dB to linear amplitude.
     #[inline]
     fn db_to_linear(db: f64) -> f64 {
-        10.0_f64.powf(db / 20.0)
+        math::powf(10.0, db / 20.0)
     }
 
     /// Compute gain reduction for a given input level (in dB).
@@ -114,8 +116,8 @@ impl Compressor {
         let input_level = (left.abs()).max(right.abs()) as f64;
 
         // Envelope follower (peak detection with attack/release)
-        let attack_coef = (-1.0 / (self.attack * self.sample_rate)).exp();
-        let release_coef = (-1.0 / (self.release * self.sample_rate)).exp();
+        let attack_coef = math::exp(-1.0 / (self.attack * self.sample_rate));
+        let release_coef = math::exp(-1.0 / (self.release * self.sample_rate));
 
         if input_level > self.envelope {
             // Attack
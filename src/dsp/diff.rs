@@ -0,0 +1,92 @@
+//! Render comparison — verify that two renders produce the same audio.
+
+use serde::Serialize;
+
+/// Differences between two sample buffers.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DiffReport {
+    /// Length of the first buffer, in samples.
+    pub len_a: usize,
+    /// Length of the second buffer, in samples.
+    pub len_b: usize,
+    /// Largest absolute difference between corresponding samples.
+    pub max_delta: f64,
+    /// Offset of the largest difference.
+    pub max_delta_offset: Option<usize>,
+    /// RMS of the difference signal.
+    pub rms_delta: f64,
+    /// First offset at which the buffers differ, if any.
+    pub first_divergence: Option<usize>,
+    /// True if both buffers are sample-for-sample equal.
+    pub identical: bool,
+}
+
+/// Compare two renders sample by sample. The shorter buffer is treated as
+/// silent past its end, so a length change always counts as a divergence.
+pub fn compare_renders(samples_a: &[f32], samples_b: &[f32]) -> DiffReport {
+    let len = samples_a.len().max(samples_b.len());
+    let mut max_delta = 0.0_f64;
+    let mut max_delta_offset = None;
+    let mut sum_sq = 0.0_f64;
+    let mut first_divergence = None;
+
+    for i in 0..len {
+        let a = samples_a.get(i).copied();
+        let b = samples_b.get(i).copied();
+        if a != b && first_divergence.is_none() {
+            first_divergence = Some(i);
+        }
+        let delta = (a.unwrap_or(0.0) as f64 - b.unwrap_or(0.0) as f64).abs();
+        sum_sq += delta * delta;
+        if delta > max_delta {
+            max_delta = delta;
+            max_delta_offset = Some(i);
+        }
+    }
+
+    DiffReport {
+        len_a: samples_a.len(),
+        len_b: samples_b.len(),
+        max_delta,
+        max_delta_offset,
+        rms_delta: if len > 0 { (sum_sq / len as f64).sqrt() } else { 0.0 },
+        first_divergence,
+        identical: first_divergence.is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_buffers() {
+        let a = [0.1_f32, -0.2, 0.3];
+        let report = compare_renders(&a, &a);
+        assert!(report.identical);
+        assert_eq!(report.max_delta, 0.0);
+        assert_eq!(report.first_divergence, None);
+    }
+
+    #[test]
+    fn reports_divergence() {
+        let a = [0.0_f32, 0.5, 0.5, 0.0];
+        let b = [0.0_f32, 0.5, 0.25, 0.0];
+        let report = compare_renders(&a, &b);
+        assert!(!report.identical);
+        assert_eq!(report.first_divergence, Some(2));
+        assert_eq!(report.max_delta_offset, Some(2));
+        assert!((report.max_delta - 0.25).abs() < 1e-9);
+        assert!((report.rms_delta - 0.125).abs() < 1e-9);
+    }
+
+    #[test]
+    fn length_change_diverges() {
+        let report = compare_renders(&[0.0, 0.0], &[0.0, 0.0, 0.0]);
+        assert!(!report.identical);
+        assert_eq!(report.first_divergence, Some(2));
+        assert_eq!(report.max_delta, 0.0);
+    }
+}
@@ -5,17 +5,43 @@
 //! sample-based playback, and composite instruments via the preset registry.
 
 use std::collections::HashMap;
-
-use crate::compiler::{EndMode, EventKind, EventList, InstrumentConfig};
-
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ast::NoteExpression;
+use crate::math;
+use crate::preset::{
+    AudioReference, KeyRange, PresetCategory, PresetDescriptor, PresetNode, SampleZone,
+    SamplerConfig, ZonePitch,
+};
+use crate::compiler::{
+    bar_position, gm_program_of, time_signature_changes, CompositeConfig, CompositeKind, CountIn,
+    EndMode, Event, EventKind, EventList, FadeLength, InstrumentConfig, KeyCoverage, OscillatorConfig,
+    Modulation, SamplerRefConfig, Sends, TempoMap,
+};
+
+use super::cache::{BusInputs, CachedTrack, RenderCache, TrackMix, VoiceUse};
 use super::chorus::Chorus;
-use super::composite::{CompositeInstrument, CompositeVoice};
+use super::composite::{CompositeChild, CompositeInstrument, CompositeVoice};
 use super::compressor::Compressor;
 use super::delay::Delay;
+use super::filter::{BiquadFilter, Filter, FilterTopology, FilterType};
+use super::meter::{LevelMeter, Levels, MeterData, TrackLevels};
 use super::mixer::Mixer;
+use super::oscillator::OscillatorQuality;
 use super::reverb::Reverb;
-use super::sampler::{Sampler, SamplerVoice};
+use super::sampler::{LoadedZone, Sampler, SamplerVoice};
+use super::tremolo::{lfo_phase, AutoPan, Tremolo};
 use super::voice::Voice;
+use super::widener::Widener;
+
+pub use crate::pitch::{
+    midi_to_frequency, midi_to_note_name, note_to_frequency, note_to_frequency_with_tuning, note_to_midi,
+};
+pub use super::tremolo::{AutoPanConfig, TremoloConfig};
 
 /// A registered preset — either a sampler or a composite instrument.
 #[derive(Debug, Clone)]
@@ -24,21 +50,372 @@ pub enum RegisteredPreset {
     Composite(CompositeInstrument),
 }
 
+impl RegisteredPreset {
+    /// Approximate heap memory held by the preset's sample data, in bytes.
+    /// Zone buffers shared with other presets are counted in each of them.
+    pub fn memory_bytes(&self) -> usize {
+        match self {
+            RegisteredPreset::Sampler(s) => s.memory_bytes(),
+            RegisteredPreset::Composite(c) => c.memory_bytes(),
+        }
+    }
+
+    /// Number of sample zones held by the preset.
+    pub fn zone_count(&self) -> usize {
+        match self {
+            RegisteredPreset::Sampler(s) => s.zones.len(),
+            RegisteredPreset::Composite(c) => c.zone_count(),
+        }
+    }
+
+    /// The preset's zones, sampler by sampler in the order of
+    /// `CompositeInstrument::samplers`.
+    pub fn zones(&self) -> Vec<&LoadedZone> {
+        match self {
+            RegisteredPreset::Sampler(s) => s.zones.iter().collect(),
+            RegisteredPreset::Composite(c) => c.samplers().into_iter().flat_map(|s| &s.zones).collect(),
+        }
+    }
+
+    /// Remove the zone at `index` in the order of `zones`.
+    fn remove_zone(&mut self, mut index: usize) {
+        let samplers = match self {
+            RegisteredPreset::Sampler(s) => vec![s],
+            RegisteredPreset::Composite(c) => c.samplers_mut(),
+        };
+        for sampler in samplers {
+            if index < sampler.zones.len() {
+                sampler.zones.remove(index);
+                return;
+            }
+            index -= sampler.zones.len();
+        }
+    }
+
+    /// The MIDI notes the preset can play, or `None` if it plays none.
+    pub fn key_coverage(&self) -> Option<KeyCoverage> {
+        let notes: Vec<u8> = (0..=127)
+            .filter(|&note| match self {
+                RegisteredPreset::Sampler(s) => s.find_zone(note).is_some(),
+                RegisteredPreset::Composite(c) => c.plays_note(note),
+            })
+            .collect();
+        Some(KeyCoverage {
+            low_note: *notes.first()?,
+            high_note: *notes.last()?,
+            is_drum_kit: match self {
+                RegisteredPreset::Sampler(s) => s.is_drum_kit,
+                RegisteredPreset::Composite(c) => c.is_drum_kit(),
+            },
+            notes,
+        })
+    }
+}
+
+// ── Preset Registry ─────────────────────────────────────────
+
+/// A registry entry with its memory footprint and per-zone use stamps.
+#[derive(Debug)]
+struct RegistryEntry {
+    preset: RegisteredPreset,
+    memory_bytes: usize,
+    /// One slot per zone, in the order of `RegisteredPreset::zones`.
+    zones: Vec<ZoneSlot>,
+    /// Identifies the source the preset was built from, while none of
+    /// its zones have been evicted.
+    fingerprint: Option<u64>,
+}
+
+/// Key range, memory and last-use stamp of one registered zone.
+#[derive(Debug)]
+struct ZoneSlot {
+    key_range_low: u8,
+    key_range_high: u8,
+    memory_bytes: usize,
+    last_used: AtomicU64,
+}
+
+impl RegistryEntry {
+    /// Mark the zones covering `midi_note` (all of them for `None`) as used.
+    fn touch(&self, midi_note: Option<u8>, stamp: u64) {
+        for zone in &self.zones {
+            if midi_note.is_none_or(|note| (zone.key_range_low..=zone.key_range_high).contains(&note)) {
+                zone.last_used.store(stamp, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Memory and usage statistics for a `PresetRegistry`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PresetBankStats {
+    /// Number of registered presets.
+    #[serde(rename = "presetCount")]
+    pub preset_count: usize,
+    /// Total number of sample zones across all presets.
+    #[serde(rename = "zoneCount")]
+    pub zone_count: usize,
+    /// Approximate bytes held by sample data.
+    #[serde(rename = "memoryBytes")]
+    pub memory_bytes: usize,
+    /// The configured memory budget in bytes, if any.
+    #[serde(rename = "memoryBudget")]
+    pub memory_budget: Option<usize>,
+    /// Number of zones evicted to stay within the budget.
+    pub evictions: u64,
+}
+
+/// Named presets available to the engine, with an optional memory budget.
+///
+/// When a budget is set, registering a preset evicts the least recently
+/// used zones until the total sample memory fits. A zone counts as used
+/// when its preset is registered or when it covers a note rendered with
+/// it. A preset whose last zone is evicted is removed. Notes without a
+/// zone fall back to the oscillator, exactly as for a preset that was
+/// never registered. Pinned presets (see `pin`) are never evicted.
+///
+/// Presets can also be assigned a General MIDI program, so that
+/// `"gm:N"` references (from `gm(N)` or `loadPreset("gm:N")`) resolve to
+/// whichever registered preset plays that program.
+///
+/// The registry is `Sync`: lookups only touch atomic use stamps, so an
+/// audio thread can render from it while other threads read it.
+#[derive(Debug, Default)]
+pub struct PresetRegistry {
+    entries: HashMap<String, RegistryEntry>,
+    gm_programs: HashMap<u8, String>,
+    memory_budget: Option<usize>,
+    memory_bytes: usize,
+    clock: AtomicU64,
+    evictions: u64,
+    pinned: Vec<String>,
+}
+
+impl PresetRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a registry that keeps sample memory within `budget` bytes.
+    pub fn with_budget(budget: usize) -> Self {
+        PresetRegistry {
+            memory_budget: Some(budget),
+            ..Self::default()
+        }
+    }
+
+    /// Insert or replace a preset, then evict other presets if over budget.
+    pub fn insert(&mut self, name: String, preset: RegisteredPreset) {
+        self.insert_entry(name, preset, None);
+    }
+
+    /// Like `insert`, remembering `fingerprint` (e.g. a hash of the data
+    /// the preset was built from) so a caller can skip rebuilding it.
+    pub fn insert_with_fingerprint(&mut self, name: String, preset: RegisteredPreset, fingerprint: u64) {
+        self.insert_entry(name, preset, Some(fingerprint));
+    }
+
+    /// The fingerprint `name` was inserted with, while it still holds
+    /// every zone it was inserted with.
+    pub fn fingerprint(&self, name: &str) -> Option<u64> {
+        self.entries.get(name)?.fingerprint
+    }
+
+    fn insert_entry(&mut self, name: String, preset: RegisteredPreset, fingerprint: Option<u64>) {
+        let memory_bytes = preset.memory_bytes();
+        if let Some(old) = self.entries.remove(&name) {
+            self.memory_bytes -= old.memory_bytes;
+        }
+        self.memory_bytes += memory_bytes;
+        let stamp = self.tick();
+        let zones = preset
+            .zones()
+            .into_iter()
+            .map(|zone| ZoneSlot {
+                key_range_low: zone.key_range_low,
+                key_range_high: zone.key_range_high,
+                memory_bytes: zone.memory_bytes(),
+                last_used: AtomicU64::new(stamp),
+            })
+            .collect();
+        self.entries.insert(name.clone(), RegistryEntry { preset, memory_bytes, zones, fingerprint });
+        self.enforce_budget(Some(&name));
+    }
+
+    /// Look up a preset by name, marking all its zones as recently used.
+    /// `"gm:N"` names resolve through the General MIDI program assignments.
+    pub fn get(&self, name: &str) -> Option<&RegisteredPreset> {
+        let entry = self.entry(name)?;
+        entry.touch(None, self.tick());
+        Some(&entry.preset)
+    }
+
+    /// Look up a preset to play `midi_note`, marking the zones that cover
+    /// the note as recently used.
+    pub fn get_for_note(&self, name: &str, midi_note: u8) -> Option<&RegisteredPreset> {
+        let entry = self.entry(name)?;
+        entry.touch(Some(midi_note), self.tick());
+        Some(&entry.preset)
+    }
+
+    /// Keep `name` from being evicted until `unpin_all`, e.g. while the
+    /// presets of a song are registered one after another for a render.
+    pub fn pin(&mut self, name: String) {
+        if !self.pinned.contains(&name) {
+            self.pinned.push(name);
+        }
+    }
+
+    /// Release all pins.
+    pub fn unpin_all(&mut self) {
+        self.pinned.clear();
+    }
+
+    fn is_pinned(&self, name: &str) -> bool {
+        self.pinned.iter().any(|pin| {
+            pin == name || gm_program_of(pin).is_some_and(|program| self.gm_program(program) == Some(name))
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entry(name).is_some()
+    }
+
+    /// Make `name` the preset that plays General MIDI program `program`.
+    /// A later assignment for the same program replaces the earlier one.
+    pub fn assign_gm_program(&mut self, program: u8, name: String) {
+        self.gm_programs.insert(program, name);
+    }
+
+    /// The preset name assigned to a General MIDI program.
+    pub fn gm_program(&self, program: u8) -> Option<&str> {
+        self.gm_programs.get(&program).map(|name| name.as_str())
+    }
+
+    fn entry(&self, name: &str) -> Option<&RegistryEntry> {
+        self.entries.get(name).or_else(|| {
+            let program = gm_program_of(name)?;
+            self.entries.get(self.gm_programs.get(&program)?)
+        })
+    }
+
+    /// Remove a preset. Returns `true` if it was registered.
+    pub fn remove(&mut self, name: &str) -> bool {
+        match self.entries.remove(name) {
+            Some(entry) => {
+                self.memory_bytes -= entry.memory_bytes;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove all presets and General MIDI assignments. The budget,
+    /// eviction count and pins are kept.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.gm_programs.clear();
+        self.memory_bytes = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Registered preset names, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|k| k.as_str())
+    }
+
+    /// Approximate bytes held by all registered sample data.
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// Set (or clear) the memory budget, evicting presets if now over it.
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+        self.enforce_budget(None);
+    }
+
+    pub fn stats(&self) -> PresetBankStats {
+        PresetBankStats {
+            preset_count: self.entries.len(),
+            zone_count: self.entries.values().map(|e| e.preset.zone_count()).sum(),
+            memory_bytes: self.memory_bytes,
+            memory_budget: self.memory_budget,
+            evictions: self.evictions,
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Evict least recently used zones until within budget. `keep` and
+    /// pinned presets are never evicted, so a single oversized preset can
+    /// still be used.
+    fn enforce_budget(&mut self, keep: Option<&str>) {
+        let Some(budget) = self.memory_budget else {
+            return;
+        };
+        while self.memory_bytes > budget {
+            let victim = self
+                .entries
+                .iter()
+                .filter(|(name, _)| Some(name.as_str()) != keep && !self.is_pinned(name))
+                .flat_map(|(name, e)| e.zones.iter().enumerate().map(move |(i, zone)| (name, i, zone)))
+                .min_by_key(|(_, _, zone)| zone.last_used.load(Ordering::Relaxed))
+                .map(|(name, i, _)| (name.clone(), i));
+            let Some((name, index)) = victim else { break };
+            self.evict_zone(&name, index);
+            self.evictions += 1;
+        }
+    }
+
+    /// Drop one zone of a preset, and the preset with its last zone.
+    fn evict_zone(&mut self, name: &str, index: usize) {
+        let Some(entry) = self.entries.get_mut(name) else { return };
+        let zone = entry.zones.remove(index);
+        entry.preset.remove_zone(index);
+        entry.memory_bytes -= zone.memory_bytes;
+        entry.fingerprint = None;
+        self.memory_bytes -= zone.memory_bytes;
+        if entry.zones.is_empty() {
+            self.remove(name);
+        }
+    }
+}
+
 /// A unified voice that can be an oscillator, sampler, or composite.
 enum ActiveVoice {
+    /// Oscillator voice.
     Oscillator(Voice),
-    Sampler(SamplerVoice),
+    /// Sampler voice. The string is the preset name, kept only when the
+    /// voice belongs to an exclusive group so it can be choked.
+    Sampler(SamplerVoice, Option<String>),
     /// Composite voice: multiple sub-voices that play together.
-    /// The usize is the release_sample for the composite group.
-    Composite(Vec<CompositeVoice>, usize),
+    /// The usize is the release_sample for the composite group. The
+    /// string is the preset name, kept only when a sampler sub-voice
+    /// belongs to an exclusive group so it can be choked.
+    Composite(Vec<CompositeVoice>, usize, Option<String>),
 }
 
 impl ActiveVoice {
     fn next_sample(&mut self) -> f64 {
         match self {
             ActiveVoice::Oscillator(v) => v.next_sample(),
-            ActiveVoice::Sampler(v) => v.next_sample(),
-            ActiveVoice::Composite(voices, _) => {
+            ActiveVoice::Sampler(v, _) => v.next_sample(),
+            ActiveVoice::Composite(voices, _, _) => {
                 let mut sum = 0.0;
                 for v in voices.iter_mut() {
                     sum += v.next_sample();
@@ -56,8 +433,8 @@ impl ActiveVoice {
     fn note_off(&mut self) {
         match self {
             ActiveVoice::Oscillator(v) => v.note_off(),
-            ActiveVoice::Sampler(v) => v.note_off(),
-            ActiveVoice::Composite(voices, _) => {
+            ActiveVoice::Sampler(v, _) => v.note_off(),
+            ActiveVoice::Composite(voices, _, _) => {
                 for v in voices.iter_mut() {
                     v.note_off();
                 }
@@ -65,91 +442,100 @@ impl ActiveVoice {
         }
     }
 
-    fn is_finished(&self) -> bool {
+    /// Glide the pitch by `ratio` over `samples` samples.
+    fn slide(&mut self, ratio: f64, samples: usize) {
         match self {
-            ActiveVoice::Oscillator(v) => v.is_finished(),
-            ActiveVoice::Sampler(v) => v.is_finished(),
-            ActiveVoice::Composite(voices, _) => voices.iter().all(|v| v.is_finished()),
+            ActiveVoice::Oscillator(v) => v.glide_to(v.target_frequency() * ratio, samples),
+            ActiveVoice::Sampler(v, _) => v.glide_rate(ratio, samples),
+            ActiveVoice::Composite(voices, _, _) => {
+                for v in voices.iter_mut() {
+                    v.slide(ratio, samples);
+                }
+            }
         }
     }
 
-    fn release_sample(&self) -> usize {
+    fn set_vibrato(&mut self, rate: f64, depth: f64) {
         match self {
-            ActiveVoice::Oscillator(v) => v.release_sample,
-            ActiveVoice::Sampler(v) => v.release_sample,
-            ActiveVoice::Composite(_, rs) => *rs,
+            ActiveVoice::Oscillator(v) => v.set_vibrato(rate, depth),
+            ActiveVoice::Sampler(v, _) => v.set_vibrato(rate, depth),
+            ActiveVoice::Composite(voices, _, _) => {
+                for v in voices.iter_mut() {
+                    v.set_vibrato(rate, depth);
+                }
+            }
         }
     }
-}
 
-/// Parse a note name (e.g. "C4", "F#3", "Bb5") into a MIDI note number.
-pub fn note_to_midi(note: &str) -> Option<i32> {
-    let bytes = note.as_bytes();
-    if bytes.is_empty() {
-        return None;
+    fn is_finished(&self) -> bool {
+        match self {
+            ActiveVoice::Oscillator(v) => v.is_finished(),
+            ActiveVoice::Sampler(v, _) => v.is_finished(),
+            ActiveVoice::Composite(voices, _, _) => voices.iter().all(|v| v.is_finished()),
+        }
     }
 
-    // Parse note name (A-G)
-    let name = bytes[0] as char;
-    let base_semitone = match name {
-        'C' => 0,
-        'D' => 2,
-        'E' => 4,
-        'F' => 5,
-        'G' => 7,
-        'A' => 9,
-        'B' => 11,
-        _ => return None,
-    };
-
-    let mut idx = 1;
-    let mut semitone = base_semitone;
+    /// Stereo position set by the sample zone, added to the note pan.
+    /// Composite sub-voices mix to one signal, so it takes their mean pan.
+    fn zone_pan(&self) -> f64 {
+        match self {
+            ActiveVoice::Sampler(v, _) => v.pan(),
+            ActiveVoice::Composite(voices, _, _) if !voices.is_empty() => {
+                voices.iter().map(CompositeVoice::pan).sum::<f64>() / voices.len() as f64
+            }
+            _ => 0.0,
+        }
+    }
 
-    // Parse accidental
-    if idx < bytes.len() {
-        match bytes[idx] as char {
-            '#' => {
-                semitone += 1;
-                idx += 1;
+    /// The (preset, exclusive group) pairs that starting this voice chokes.
+    fn choke_keys(&self) -> Vec<(&str, u32)> {
+        match self {
+            ActiveVoice::Sampler(v, Some(preset)) => {
+                v.exclusive_group().map(|group| (preset.as_str(), group)).into_iter().collect()
             }
-            'b' => {
-                semitone -= 1;
-                idx += 1;
+            ActiveVoice::Composite(voices, _, Some(preset)) => voices
+                .iter()
+                .filter_map(|v| match v {
+                    CompositeVoice::Sampler(sv) => Some((preset.as_str(), sv.exclusive_group()?)),
+                    CompositeVoice::Oscillator(_) => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Choke the sampler voices of `preset` playing a zone in `group`.
+    fn choke(&mut self, preset: &str, group: u32) {
+        match self {
+            ActiveVoice::Sampler(v, Some(name)) if name == preset && v.exclusive_group() == Some(group) => v.choke(),
+            ActiveVoice::Composite(voices, _, Some(name)) if name == preset => {
+                for v in voices.iter_mut() {
+                    if let CompositeVoice::Sampler(sv) = v
+                        && sv.exclusive_group() == Some(group)
+                    {
+                        sv.choke();
+                    }
+                }
             }
             _ => {}
         }
     }
 
-    // Parse octave number
-    let octave_str = &note[idx..];
-    let octave: i32 = octave_str.parse().ok()?;
-
-    // MIDI note number: C4 = 60
-    Some((octave + 1) * 12 + semitone)
-}
-
-/// Convert a MIDI note number to frequency using the given tuning pitch.
-///
-/// `tuning_pitch` is the frequency of A4 (MIDI 69). Default is 440.0 Hz.
-/// Formula: `tuning_pitch * 2^((midi - 69) / 12)`
-pub fn midi_to_frequency(midi: i32, tuning_pitch: f64) -> f64 {
-    tuning_pitch * (2.0_f64).powf((midi as f64 - 69.0) / 12.0)
-}
-
-/// Note-to-frequency conversion matching the JS `noteToFrequency`.
-///
-/// Uses the standard A4 = 440 Hz tuning. For custom tuning, use
-/// `note_to_midi()` + `midi_to_frequency()`.
-pub fn note_to_frequency(note: &str) -> Option<f64> {
-    note_to_frequency_with_tuning(note, 440.0)
-}
+    fn set_release_sample(&mut self, release_sample: usize) {
+        match self {
+            ActiveVoice::Oscillator(v) => v.release_sample = release_sample,
+            ActiveVoice::Sampler(v, _) => v.release_sample = release_sample,
+            ActiveVoice::Composite(_, rs, _) => *rs = release_sample,
+        }
+    }
 
-/// Note-to-frequency conversion with configurable tuning pitch.
-///
-/// `tuning_pitch` is the frequency of A4. Common values: 440.0, 432.0.
-pub fn note_to_frequency_with_tuning(note: &str, tuning_pitch: f64) -> Option<f64> {
-    let midi = note_to_midi(note)?;
-    Some(midi_to_frequency(midi, tuning_pitch))
+    fn release_sample(&self) -> usize {
+        match self {
+            ActiveVoice::Oscillator(v) => v.release_sample,
+            ActiveVoice::Sampler(v, _) => v.release_sample,
+            ActiveVoice::Composite(_, rs, _) => *rs,
+        }
+    }
 }
 
 /// Convert a frequency back to the nearest MIDI note number.
@@ -157,12 +543,15 @@ pub fn note_to_frequency_with_tuning(note: &str, tuning_pitch: f64) -> Option<f6
 /// Inverse of `midi_to_frequency`. Used for zone lookup when we only have
 /// the computed frequency from a note name.
 fn note_to_midi_from_freq(freq: f64, tuning_pitch: f64) -> u8 {
-    let midi = 69.0 + 12.0 * (freq / tuning_pitch).log2();
+    let midi = 69.0 + 12.0 * math::log2(freq / tuning_pitch);
     midi.round().clamp(0.0, 127.0) as u8
 }
 
 /// Scheduled voice event for the engine.
+#[derive(Clone)]
 struct ScheduledNote {
+    /// Index of the note's event in the EventList.
+    event_index: usize,
     /// Sample offset when the note starts.
     start_sample: usize,
     /// Sample offset when the note should be released (gate off).
@@ -171,10 +560,337 @@ struct ScheduledNote {
     velocity: f64,
     /// Instrument configuration for this note.
     instrument: InstrumentConfig,
+    /// Track that played the note.
+    track: Option<String>,
+    /// Frequency of a held voice on the same track to glide from.
+    glide_from: Option<f64>,
+    /// Frequency to slide to by the note's release.
+    slide_to: Option<f64>,
+    expression: NoteExpression,
+    /// Levels sent to the effect buses (`track.sends`).
+    sends: Sends,
+    /// Tremolo and auto-pan of the note's track.
+    modulation: Modulation,
+}
+
+/// Scheduled notes and render length for one EventList.
+struct RenderPlan {
+    tuning_pitch: f64,
+    tempo: TempoMap,
+    scheduled: Vec<ScheduledNote>,
+    total_samples: usize,
+}
+
+/// Voices sounding between blocks of a render.
+#[derive(Default)]
+struct VoiceState {
+    voices: Vec<ActiveVoice>,
+    /// Mixing state of each voice (parallel to `voices`).
+    voice_mix: Vec<VoiceMix>,
+    /// Index of the next scheduled note to start.
+    next_note: usize,
+}
+
+/// Where `AudioEngine::mix_block` writes one block. `output` starts at
+/// sample `base` of the song, so a streamed block can use a short buffer.
+struct MixBlock<'a> {
+    start: usize,
+    end: usize,
+    base: usize,
+    output: &'a mut TrackMix,
+    track_meters: Option<&'a mut TrackMeters>,
+    sends: Option<&'a mut BusInputs>,
+    /// Song tempo, for the LFOs of modulated voices.
+    tempo: &'a TempoMap,
+    /// Voice use to record, for the render cache.
+    voice_use: Option<&'a mut VoiceUse>,
+}
+
+/// A song played block by block from persistent voice state, for
+/// realtime hosts. Create one with `AudioEngine::stream` and render it
+/// with `AudioEngine::render_stream`.
+///
+/// Streaming plays the notes only, without master effects. Songs with a
+/// count-in, song fades or track sends can't be streamed.
+pub struct SongStream {
+    plan: RenderPlan,
+    state: VoiceState,
+    /// Song sample `render_stream` continues from.
+    position: usize,
+    /// The engine block holding `position`, rendered whole so any host
+    /// block size matches a full render. Reused between blocks.
+    block: TrackMix,
+    /// Song sample `block` starts at, if it is rendered.
+    block_start: Option<usize>,
+}
+
+impl SongStream {
+    /// Length of the song in samples.
+    pub fn len(&self) -> usize {
+        self.plan.total_samples
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plan.total_samples == 0
+    }
+
+    /// Song sample the next rendered sample comes from.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Jump to song sample `position`. Every voice is stopped; notes still
+    /// held at `position` start again from their attack.
+    pub fn seek(&mut self, position: usize) {
+        self.state = VoiceState::default();
+        self.position = position;
+        self.block_start = None;
+    }
+}
+
+/// A voice played outside a song, e.g. from live MIDI input. It sounds
+/// until `note_off` and its release finish.
+pub struct LiveVoice(ActiveVoice);
+
+impl LiveVoice {
+    pub fn next_sample(&mut self) -> f64 {
+        self.0.next_sample()
+    }
+
+    pub fn note_off(&mut self) {
+        self.0.note_off();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.0.is_finished()
+    }
+}
+
+/// Most `(start, end)` spans that overlap at any one sample. A span
+/// ending where another starts does not overlap it.
+fn max_overlap(spans: &[(usize, usize)]) -> usize {
+    let mut sounding = 0;
+    let mut max = 0;
+    for (_, delta) in span_edges(spans) {
+        sounding += delta;
+        max = max.max(sounding);
+    }
+    max as usize
+}
+
+/// Starts (+1) and ends (-1) of `spans` in time order, with ends before
+/// starts at the same sample.
+fn span_edges(spans: &[(usize, usize)]) -> Vec<(usize, i32)> {
+    let mut edges: Vec<(usize, i32)> = spans.iter().flat_map(|&(start, end)| [(start, 1), (end, -1)]).collect();
+    edges.sort_unstable();
+    edges
+}
+
+/// Per-track level meters filled while mixing voices.
+struct TrackMeters {
+    block: usize,
+    /// Output offset of sample 0 of the mix (the count-in length).
+    offset: usize,
+    names: Vec<Option<String>>,
+    meters: Vec<LevelMeter>,
+    /// Each track's sum for the current render block.
+    buffers: Vec<Vec<f64>>,
+}
+
+impl TrackMeters {
+    fn new(block: usize, offset: usize) -> Self {
+        TrackMeters {
+            block,
+            offset,
+            names: Vec::new(),
+            meters: Vec::new(),
+            buffers: Vec::new(),
+        }
+    }
+
+    fn track_index(&mut self, track: &Option<String>, render_block: usize) -> usize {
+        match self.names.iter().position(|t| t == track) {
+            Some(i) => i,
+            None => {
+                self.names.push(track.clone());
+                self.meters.push(LevelMeter::new(self.block));
+                self.buffers.push(vec![0.0; render_block]);
+                self.names.len() - 1
+            }
+        }
+    }
+
+    /// Meter the current render block (at master gain) and clear it.
+    fn flush(&mut self, block_start: usize, len: usize) {
+        let gain = Mixer::new().master_gain;
+        for (meter, buffer) in self.meters.iter_mut().zip(self.buffers.iter_mut()) {
+            for (i, s) in buffer[..len].iter_mut().enumerate() {
+                meter.add(self.offset + block_start + i, *s * gain);
+                *s = 0.0;
+            }
+        }
+    }
+}
+
+/// Per-voice pan gains, brightness filter and meter track.
+struct VoiceMix {
+    track: usize,
+    gain_left: f64,
+    gain_right: f64,
+    filter: Option<BiquadFilter>,
+    /// Levels sent to the effect buses.
+    sends: Sends,
+    /// Tremolo and auto-pan, from the song's clock.
+    modulation: Modulation,
+    /// Track that played the voice and the pitch in Hz it is playing or
+    /// sliding to, to find held voices for voice leading.
+    track_name: Option<String>,
+    frequency: f64,
+}
+
+impl VoiceMix {
+    fn new(track: usize, expression: &NoteExpression, zone_pan: f64, sample_rate: f64) -> Self {
+        // Balance law: the centre keeps full level in both channels
+        let pan = (expression.pan.unwrap_or(0.0) + zone_pan).clamp(-1.0, 1.0);
+        let filter = expression
+            .brightness
+            .filter(|b| *b < 1.0)
+            .map(|b| {
+                let mut f = BiquadFilter::new(FilterType::Lowpass, sample_rate);
+                f.set_frequency(brightness_cutoff(b).min(sample_rate * 0.45));
+                f
+            });
+        VoiceMix {
+            track,
+            gain_left: (1.0 - pan).min(1.0),
+            gain_right: (1.0 + pan).min(1.0),
+            filter,
+            sends: Sends::default(),
+            modulation: Modulation::default(),
+            track_name: None,
+            frequency: 0.0,
+        }
+    }
+
+    fn is_panned(&self) -> bool {
+        self.gain_left != self.gain_right || self.modulation.auto_pan.is_some()
+    }
+
+    /// Tremolo gain and auto-pan (left, right) gains at song sample `at`.
+    /// The LFOs run from the start of the song, not of the note.
+    fn modulation_at(&self, at: usize, sample_rate: f64, tempo: &TempoMap) -> (f64, f64, f64) {
+        let seconds = at as f64 / sample_rate;
+        let beat = tempo.beat_at(seconds);
+        let gain = self
+            .modulation
+            .tremolo
+            .map_or(1.0, |t| t.gain(lfo_phase(t.rate, t.sync, seconds, beat)));
+        let (left, right) = self
+            .modulation
+            .auto_pan
+            .map_or((1.0, 1.0), |p| p.gains(lfo_phase(p.rate, p.sync, seconds, beat)));
+        (gain, left, right)
+    }
+}
+
+/// Whether tracks mixed apart would have met in a full render: their
+/// voices together over `max_voices` in some block, or two of them
+/// playing the same exclusive group of a preset.
+fn tracks_share_voices(parts: &[Arc<CachedTrack>], max_voices: usize) -> bool {
+    let blocks = parts.iter().map(|p| p.voices.per_block.len()).max().unwrap_or(0);
+    let over_limit = (0..blocks).any(|block| {
+        let sounding: usize = parts
+            .iter()
+            .map(|p| p.voices.per_block.get(block).map_or(0, |&v| v as usize))
+            .sum();
+        sounding > max_voices
+    });
+    over_limit
+        || parts.iter().enumerate().any(|(i, part)| {
+            part.voices
+                .choke_groups
+                .iter()
+                .any(|group| parts[i + 1..].iter().any(|other| other.voices.choke_groups.contains(group)))
+        })
+}
+
+/// A bus input as stereo f32 for the effects, or None when nothing was
+/// sent to the bus.
+fn bus_input(input: &[f64]) -> Option<(Vec<f32>, Vec<f32>)> {
+    if input.iter().all(|s| *s == 0.0) {
+        return None;
+    }
+    let left: Vec<f32> = input.iter().map(|&s| s as f32).collect();
+    Some((left.clone(), left))
+}
+
+/// The wet output of a bus at its return `level`.
+fn bus_return(left: &[f32], right: &[f32], level: f64) -> TrackMix {
+    TrackMix {
+        left: left.iter().map(|&s| s as f64 * level).collect(),
+        right: Some(right.iter().map(|&s| s as f64 * level).collect()),
+    }
+}
+
+/// Song fade-in and fade-out lengths in samples, applied to the finished
+/// output after the master effects.
+struct SongFades {
+    /// Where the song starts, after the count-in.
+    start: usize,
+    fade_in: usize,
+    fade_out: usize,
+}
+
+impl SongFades {
+    /// Scale the fade regions of `output`; `gain` converts a gain to the
+    /// sample type.
+    fn apply<T: Copy + core::ops::MulAssign>(&self, output: &mut [T], gain: impl Fn(f64) -> T) {
+        let start = self.start.min(output.len());
+        let song = &mut output[start..];
+        for (i, s) in song.iter_mut().take(self.fade_in).enumerate() {
+            *s *= gain(i as f64 / self.fade_in as f64);
+        }
+        let len = self.fade_out.min(song.len());
+        let start = song.len() - len;
+        for (i, s) in song[start..].iter_mut().enumerate() {
+            *s *= gain(1.0 - (i + 1) as f64 / len as f64);
+        }
+    }
 }
 
+/// Lowpass cutoff for a note brightness: 200 Hz at 0 up to 20 kHz at 1.
+fn brightness_cutoff(brightness: f64) -> f64 {
+    200.0 * math::powf(100.0, brightness.clamp(0.0, 1.0))
+}
+
+/// Samples rendered per block. Voices start and release on block
+/// boundaries.
+const BLOCK_SIZE: usize = 128;
+
+/// Rate in Hz of the `~v` note vibrato.
+const VIBRATO_RATE: f64 = 5.5;
+
+/// Depth in semitones of the `~v` note vibrato.
+const VIBRATO_DEPTH: f64 = 0.3;
+
+/// Glide time when voice leading moves a held voice to a new chord tone.
+const VOICE_LEADING_GLIDE: f64 = 0.08;
+
+/// Release time in seconds of instruments that do not set one (from
+/// `Envelope::new`).
+const DEFAULT_RELEASE: f64 = 0.3;
+
+/// Bytes held per sample frame while rendering: a stereo f64 mix and a
+/// stereo f32 output.
+const RENDER_BYTES_PER_FRAME: usize = 24;
+
 /// Configuration for master effects applied to the final mix.
-#[derive(Debug, Clone)]
+///
+/// In JSON, effects left out are off and config fields left out take
+/// their defaults, e.g. `{"reverb": {"mix": 0.3}}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
 pub struct MasterEffects {
     /// Delay effect configuration.
     pub delay: Option<DelayConfig>,
@@ -184,10 +900,22 @@ pub struct MasterEffects {
     pub chorus: Option<ChorusConfig>,
     /// Compressor configuration.
     pub compressor: Option<CompressorConfig>,
+    /// Stereo widener configuration.
+    pub widener: Option<WidenerConfig>,
+    /// Filter configuration, e.g. a lo-fi low-pass.
+    pub filter: Option<FilterConfig>,
+    /// Tremolo configuration.
+    pub tremolo: Option<TremoloConfig>,
+    /// Auto-pan configuration.
+    pub auto_pan: Option<AutoPanConfig>,
+    /// Shared buses that tracks send to with `track.sends`.
+    pub buses: SendBuses,
 }
 
 /// Configuration for the delay effect.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
 pub struct DelayConfig {
     /// Delay time in seconds.
     pub time: f64,
@@ -208,7 +936,9 @@ impl Default for DelayConfig {
 }
 
 /// Configuration for the reverb effect.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
 pub struct ReverbConfig {
     /// Room size (0.0 to 1.0).
     pub room_size: f64,
@@ -216,6 +946,13 @@ pub struct ReverbConfig {
     pub damping: f64,
     /// Dry/wet mix (0.0 to 1.0).
     pub mix: f64,
+    /// Delay before the reverb starts, in milliseconds (0 to 500).
+    pub pre_delay: f64,
+    /// Frequency in Hz below which the wet signal is cut (0 = off), to
+    /// keep the low end clear.
+    pub low_cut: f64,
+    /// Frequency in Hz above which the wet signal is cut (0 = off).
+    pub high_cut: f64,
 }
 
 impl Default for ReverbConfig {
@@ -224,12 +961,28 @@ impl Default for ReverbConfig {
             room_size: 0.5,
             damping: 0.5,
             mix: 0.2,
+            pre_delay: 0.0,
+            low_cut: 0.0,
+            high_cut: 0.0,
         }
     }
 }
 
+impl ReverbConfig {
+    /// A reverb with these settings, mixed at `mix` rather than the
+    /// config's own.
+    fn reverb(&self, sample_rate: f64, mix: f64) -> Reverb {
+        let mut reverb = Reverb::with_params(sample_rate, self.room_size, self.damping, mix);
+        reverb.set_pre_delay(self.pre_delay);
+        reverb.set_cuts(self.low_cut, self.high_cut);
+        reverb
+    }
+}
+
 /// Configuration for the chorus effect.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
 pub struct ChorusConfig {
     /// LFO rate in Hz.
     pub rate: f64,
@@ -250,7 +1003,9 @@ impl Default for ChorusConfig {
 }
 
 /// Configuration for the compressor effect.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
 pub struct CompressorConfig {
     /// Threshold in dB.
     pub threshold: f64,
@@ -276,26 +1031,198 @@ impl Default for CompressorConfig {
     }
 }
 
-impl Default for MasterEffects {
+/// Configuration for the stereo widener. The mono sum is unchanged and
+/// the bass below `crossover` stays centered.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
+pub struct WidenerConfig {
+    /// Side gain (0.0 = mono, 1.0 = unchanged, 2.0 = widest).
+    pub width: f64,
+    /// Level of the Haas delay (0.0 to 1.0).
+    pub haas: f64,
+    /// Haas delay time in seconds (0.001 to 0.03).
+    pub haas_delay: f64,
+    /// Frequency in Hz below which nothing is widened.
+    pub crossover: f64,
+}
+
+impl Default for WidenerConfig {
     fn default() -> Self {
         Self {
-            delay: None,
-            reverb: None,
-            chorus: None,
-            compressor: None,
+            width: 1.5,
+            haas: 0.3,
+            haas_delay: 0.012,
+            crossover: 150.0,
         }
     }
 }
 
-/// The audio rendering engine.
-pub struct AudioEngine {
-    pub sample_rate: f64,
-    pub bpm: f64,
-    /// Tuning pitch for A4 in Hz. Default is 440.0.
-    pub tuning_pitch: f64,
-    max_voices: usize,
-    /// Registered presets, keyed by preset name (e.g. "FluidR3_GM/Acoustic Grand Piano").
-    preset_registry: HashMap<String, RegisteredPreset>,
+/// Configuration for the master filter.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
+pub struct FilterConfig {
+    /// Filter response.
+    #[serde(rename = "type")]
+    pub filter_type: FilterType,
+    /// How the filter is built; one-pole gives a gentler 6 dB/octave slope.
+    pub topology: FilterTopology,
+    /// Cutoff (or center) frequency in Hz.
+    pub cutoff: f64,
+    /// Resonance as Q (0.707 = no peak). Ignored by the one-pole.
+    pub resonance: f64,
+    /// Boost or cut in dB, for the peaking type only.
+    pub gain_db: f64,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            filter_type: FilterType::Lowpass,
+            topology: FilterTopology::Biquad,
+            cutoff: 2000.0,
+            resonance: 0.707,
+            gain_db: 0.0,
+        }
+    }
+}
+
+/// Shared effect buses that tracks send to with `track.sends`, so that
+/// many tracks share one reverb and one delay. Each bus is fully wet;
+/// the `mix` of its config sets the level of its return.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
+pub struct SendBuses {
+    pub reverb: ReverbConfig,
+    pub delay: DelayConfig,
+}
+
+impl Default for SendBuses {
+    fn default() -> Self {
+        Self {
+            reverb: ReverbConfig { mix: 1.0, ..Default::default() },
+            delay: DelayConfig { mix: 1.0, ..Default::default() },
+        }
+    }
+}
+
+impl Default for MasterEffects {
+    fn default() -> Self {
+        Self {
+            delay: None,
+            reverb: None,
+            chorus: None,
+            compressor: None,
+            widener: None,
+            filter: None,
+            tremolo: None,
+            auto_pan: None,
+            buses: SendBuses::default(),
+        }
+    }
+}
+
+/// When a note sounds in a rendered buffer, for visualizations synced to
+/// the audio.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct NoteOnset {
+    /// Index of the note's event in the EventList.
+    pub note_id: usize,
+    /// Track that played the note (None = top level).
+    pub track: Option<String>,
+    pub pitch: String,
+    pub velocity: f64,
+    /// Sample offset in the output where the note's voice starts.
+    pub start_sample: usize,
+    /// Sample offset in the output where the note is released.
+    pub release_sample: usize,
+}
+
+/// Size of a song's render, worked out without rendering it, so a UI can
+/// warn before starting a long render.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SongEstimate {
+    pub total_beats: f64,
+    /// Length of the song's beats in seconds, under the tempo map.
+    pub total_seconds: f64,
+    /// Length of the rendered audio in seconds, including the count-in and
+    /// the tails the end mode keeps.
+    pub render_seconds: f64,
+    pub event_count: usize,
+    pub note_count: usize,
+    /// Most notes sounding at once, counting their release tails.
+    pub max_voices: usize,
+    /// Voices the engine plays at once; notes beyond it are dropped.
+    pub voice_limit: usize,
+    /// Approximate memory the render needs, in bytes.
+    pub memory_bytes: usize,
+}
+
+/// How many notes sound at once over a song, and which notes the voice
+/// limit drops.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Polyphony {
+    pub voice_limit: usize,
+    /// Most notes sounding at once during each beat, counting release
+    /// tails and the notes the limit drops.
+    pub per_beat: Vec<usize>,
+    /// Notes that start while every voice is busy, in time order.
+    pub dropped: Vec<DroppedNote>,
+}
+
+/// A note that is not played because the voice limit was reached.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedNote {
+    /// Index of the note's event in the EventList.
+    pub note_id: usize,
+    /// Track that played the note (None = top level).
+    pub track: Option<String>,
+    pub pitch: String,
+    pub beat: f64,
+}
+
+/// Root note of a frozen track's sampler zone: playing this key
+/// reproduces the track's audio unchanged.
+pub const FROZEN_ROOT_NOTE: u8 = 60;
+
+/// A track rendered to audio and wrapped as a sampler preset.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FrozenTrack {
+    /// Single-zone sampler holding the track's audio at `FROZEN_ROOT_NOTE`.
+    pub preset: PresetDescriptor,
+    /// Beat of the track's first note, where the frozen audio starts.
+    pub start_beat: f64,
+    /// Length of the frozen audio in seconds.
+    pub seconds: f64,
+}
+
+/// The audio rendering engine.
+pub struct AudioEngine {
+    pub sample_rate: f64,
+    pub bpm: f64,
+    /// Tuning pitch for A4 in Hz. Default is 440.0.
+    pub tuning_pitch: f64,
+    max_voices: usize,
+    /// Oscillator rendering quality. `Naive` keeps the aliased "chip" sound.
+    pub oscillator_quality: OscillatorQuality,
+    /// Play notes of presets that are not registered yet on an oscillator
+    /// stand-in (the default). When false they are silent. Preset children
+    /// of inline composites always fall back to an oscillator.
+    pub placeholder_for_missing_presets: bool,
+    /// Registered presets, keyed by preset name (e.g. "FluidR3_GM/Acoustic Grand Piano").
+    preset_registry: PresetRegistry,
 }
 
 impl AudioEngine {
@@ -305,7 +1232,17 @@ impl AudioEngine {
             bpm: 120.0,
             tuning_pitch: 440.0,
             max_voices: 64,
-            preset_registry: HashMap::new(),
+            oscillator_quality: OscillatorQuality::default(),
+            placeholder_for_missing_presets: true,
+            preset_registry: PresetRegistry::new(),
+        }
+    }
+
+    /// Create an engine that renders with an existing preset registry.
+    pub fn with_registry(sample_rate: f64, registry: PresetRegistry) -> Self {
+        AudioEngine {
+            preset_registry: registry,
+            ..Self::new(sample_rate)
         }
     }
 
@@ -319,54 +1256,607 @@ impl AudioEngine {
         self.preset_registry.insert(name, RegisteredPreset::Composite(composite));
     }
 
-    /// Render an entire EventList to mono f64 samples.
+    pub fn registry(&self) -> &PresetRegistry {
+        &self.preset_registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut PresetRegistry {
+        &mut self.preset_registry
+    }
+
+    /// Consume the engine, returning its preset registry.
+    pub fn into_registry(self) -> PresetRegistry {
+        self.preset_registry
+    }
+
+    /// Presets played by notes of `event_list` that are not registered,
+    /// in order of first use. These play as placeholders (or not at all)
+    /// until they are loaded.
+    pub fn missing_presets(&self, event_list: &EventList) -> Vec<String> {
+        let mut notes: Vec<&Event> =
+            event_list.events.iter().filter(|e| matches!(e.kind, EventKind::Note { .. })).collect();
+        notes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        let mut missing: Vec<String> = Vec::new();
+        for event in notes {
+            let EventKind::Note { instrument, .. } = &event.kind else { continue };
+            let Some(instrument) = event_list.instruments.get(*instrument) else { continue };
+            for name in instrument.preset_refs() {
+                if !self.preset_registry.contains(&name) && !missing.contains(&name) {
+                    missing.push(name);
+                }
+            }
+        }
+        missing
+    }
+
+    /// Render an entire EventList to mono f64 samples. Panned notes are
+    /// mixed down to mono.
     pub fn render(&self, event_list: &EventList) -> Vec<f64> {
-        // Extract BPM and tuning from events
-        let mut bpm = self.bpm;
+        self.render_inner(event_list, None).0
+    }
+
+    /// Render to mono f64 samples along with peak/RMS levels of the
+    /// output and of each track, every `meter_block` samples.
+    pub fn render_with_meters(&self, event_list: &EventList, meter_block: usize) -> (Vec<f64>, MeterData) {
+        let (output, meters) = self.render_inner(event_list, Some(meter_block));
+        (output, meters.expect("metering was requested"))
+    }
+
+    /// Render to mono f64 samples along with the onset and release of
+    /// every note, as sample offsets into the returned buffer.
+    pub fn render_with_onsets(&self, event_list: &EventList) -> (Vec<f64>, Vec<NoteOnset>) {
+        (self.render(event_list), self.note_onsets(event_list))
+    }
+
+    /// The sample offsets at which `render` starts and releases each note,
+    /// ordered by start. Offsets include the count-in.
+    pub fn note_onsets(&self, event_list: &EventList) -> Vec<NoteOnset> {
+        let plan = self.plan(event_list);
+        let pre_roll = self.pre_roll(event_list, &plan).len();
+        let block_start = |sample: usize| sample / BLOCK_SIZE * BLOCK_SIZE;
+        plan.scheduled
+            .iter()
+            .filter_map(|note| {
+                let event = &event_list.events[note.event_index];
+                let EventKind::Note { pitch, velocity, .. } = &event.kind else {
+                    return None;
+                };
+                Some(NoteOnset {
+                    note_id: note.event_index,
+                    track: note.track.clone(),
+                    pitch: pitch.clone(),
+                    velocity: *velocity,
+                    start_sample: pre_roll + block_start(note.start_sample),
+                    release_sample: pre_roll + block_start(note.release_sample),
+                })
+            })
+            .collect()
+    }
+
+    /// Work out the length, note count, peak polyphony and memory of
+    /// rendering `event_list` without rendering it.
+    pub fn estimate(&self, event_list: &EventList) -> SongEstimate {
+        let plan = self.plan(event_list);
+        let frames = self.pre_roll(event_list, &plan).len() + plan.total_samples;
+        let spans = self.voice_spans(&plan);
+        SongEstimate {
+            total_beats: event_list.total_beats,
+            total_seconds: plan.tempo.seconds_at(event_list.total_beats),
+            render_seconds: frames as f64 / self.sample_rate,
+            event_count: event_list.events.len(),
+            note_count: plan.scheduled.len(),
+            max_voices: max_overlap(&spans),
+            voice_limit: self.max_voices,
+            memory_bytes: frames * RENDER_BYTES_PER_FRAME,
+        }
+    }
+
+    /// The polyphony curve of `event_list`, one value per beat, and the
+    /// notes the voice limit will drop.
+    pub fn polyphony(&self, event_list: &EventList) -> Polyphony {
+        let plan = self.plan(event_list);
+        let spans = self.voice_spans(&plan);
+        let edges = span_edges(&spans);
+        let end = edges.last().map_or(0, |e| e.0).max(plan.total_samples);
+        let sample_at = |beat: usize| (plan.tempo.seconds_at(beat as f64) * self.sample_rate) as usize;
+
+        let mut per_beat = Vec::new();
+        let (mut sounding, mut next) = (0, 0);
+        while sample_at(per_beat.len()) < end {
+            let (start, window_end) = (sample_at(per_beat.len()), sample_at(per_beat.len() + 1));
+            // Edges exactly at the window start were not applied yet
+            while next < edges.len() && edges[next].0 <= start {
+                sounding += edges[next].1;
+                next += 1;
+            }
+            let mut peak = sounding;
+            while next < edges.len() && edges[next].0 < window_end {
+                sounding += edges[next].1;
+                peak = peak.max(sounding);
+                next += 1;
+            }
+            per_beat.push(peak as usize);
+        }
+
+        // Replay voice allocation: a note starting with every voice busy
+        // is dropped
+        let mut busy_until: Vec<usize> = Vec::new();
+        let mut dropped = Vec::new();
+        for (note, &(start, end)) in plan.scheduled.iter().zip(&spans) {
+            busy_until.retain(|&until| until > start);
+            if busy_until.len() < self.max_voices {
+                busy_until.push(end);
+                continue;
+            }
+            let event = &event_list.events[note.event_index];
+            if let EventKind::Note { pitch, .. } = &event.kind {
+                dropped.push(DroppedNote {
+                    note_id: note.event_index,
+                    track: note.track.clone(),
+                    pitch: pitch.clone(),
+                    beat: event.time,
+                });
+            }
+        }
+
+        Polyphony { voice_limit: self.max_voices, per_beat, dropped }
+    }
+
+    /// Sample range each scheduled note sounds for, from its start to the
+    /// end of its release.
+    fn voice_spans(&self, plan: &RenderPlan) -> Vec<(usize, usize)> {
+        plan.scheduled
+            .iter()
+            .map(|n| (n.start_sample, n.release_sample + self.release_samples(n)))
+            .collect()
+    }
+
+    fn release_samples(&self, note: &ScheduledNote) -> usize {
+        (note.instrument.release().unwrap_or(DEFAULT_RELEASE) * self.sample_rate) as usize
+    }
+
+    fn render_inner(&self, event_list: &EventList, meter_block: Option<usize>) -> (Vec<f64>, Option<MeterData>) {
+        let (output, meters, fades) = self.render_channels(event_list, meter_block, &SendBuses::default());
+        let mut output = output.into_mono();
+        fades.apply(&mut output, |gain| gain);
+        let meters = meters.map(|mut meters| {
+            meters.master = LevelMeter::measure(&output, meters.block_size);
+            meters
+        });
+        (output, meters)
+    }
+
+    /// Render to a mono or (when notes are panned or sent to a bus)
+    /// stereo mix, with the song fades still to apply.
+    fn render_channels(
+        &self,
+        event_list: &EventList,
+        meter_block: Option<usize>,
+        buses: &SendBuses,
+    ) -> (TrackMix, Option<MeterData>, SongFades) {
+        let plan = self.plan(event_list);
+
+        // The count-in is rendered first so track meters line up with the
+        // final output.
+        let pre_roll = self.pre_roll(event_list, &plan);
+        let mut track_meters = meter_block.map(|block| TrackMeters::new(block.max(1), pre_roll.len()));
+
+        let mut sends = plan
+            .scheduled
+            .iter()
+            .any(|n| !n.sends.is_empty())
+            .then(|| BusInputs::silent(plan.total_samples));
+        let mut raw = self.mix_voices(
+            &plan.scheduled,
+            plan.total_samples,
+            plan.tuning_pitch,
+            &plan.tempo,
+            track_meters.as_mut(),
+            sends.as_mut(),
+        );
+        if let Some(sends) = sends {
+            self.add_bus_returns(&mut raw, sends, buses);
+        }
+        let fades = self.song_fades(event_list, &plan, pre_roll.len());
+        let output = self.master(raw, pre_roll);
+
+        let meters = track_meters.map(|tracks| MeterData {
+            block_size: tracks.block,
+            sample_rate: self.sample_rate,
+            master: Levels::default(),
+            tracks: tracks
+                .names
+                .into_iter()
+                .zip(tracks.meters)
+                .map(|(name, meter)| TrackLevels {
+                    name,
+                    levels: meter.finish(output.len()),
+                })
+                .collect(),
+        });
+        (output, meters, fades)
+    }
+
+    /// Render the region from `start_beat` to `end_beat` as a buffer that
+    /// loops without clicks. Only notes starting inside the region play,
+    /// and the tails of notes still sounding at the loop end are wrapped
+    /// onto the loop start, as they would be heard when the loop repeats.
+    /// The count-in and song fades are not applied.
+    pub fn render_loop(&self, event_list: &EventList, start_beat: f64, end_beat: f64) -> Vec<f64> {
+        // Render every tail in full, whatever the song's end mode
+        let mut plan = self.plan(&EventList {
+            end_mode: EndMode::Tail,
+            ..event_list.clone()
+        });
+        let start = (plan.tempo.seconds_at(start_beat.max(0.0)) * self.sample_rate) as usize;
+        let end = (plan.tempo.seconds_at(end_beat) * self.sample_rate) as usize;
+        if end <= start {
+            return Vec::new();
+        }
+        plan.scheduled.retain(|n| (start..end).contains(&n.start_sample));
+        let raw = self
+            .mix_voices(&plan.scheduled, plan.total_samples.max(end), plan.tuning_pitch, &plan.tempo, None, None)
+            .into_mono();
+
+        let len = end - start;
+        let mut looped = vec![0.0; len];
+        for (i, s) in raw[start..].iter().enumerate() {
+            looped[i % len] += s;
+        }
+        let mixer = Mixer::new();
+        looped.into_iter().map(|s| mixer.process(s)).collect()
+    }
+
+    /// Render the notes of one track (tails included) to mono audio and
+    /// wrap it as a sampler preset, so a heavy instrument can be replaced
+    /// by playing back its recording. The audio starts at the track's
+    /// first note; the count-in, fades and master effects are not applied.
+    pub fn freeze_track(&self, event_list: &EventList, track_name: &str) -> Result<FrozenTrack, String> {
+        let start_beat = event_list
+            .events
+            .iter()
+            .filter(|e| e.track_name.as_deref() == Some(track_name) && matches!(e.kind, EventKind::Note { .. }))
+            .map(|e| e.time)
+            .reduce(f64::min)
+            .ok_or_else(|| format!("No notes on track '{track_name}' to freeze."))?;
+
+        let mut plan = self.plan(&EventList {
+            end_mode: EndMode::Tail,
+            ..event_list.clone()
+        });
+        plan.scheduled.retain(|n| n.track.as_deref() == Some(track_name));
+        let start = plan.scheduled.iter().map(|n| n.start_sample).min().unwrap_or(0);
+        let raw = self
+            .mix_voices(&plan.scheduled, plan.total_samples, plan.tuning_pitch, &plan.tempo, None, None)
+            .into_mono();
+        let mixer = Mixer::new();
+        let mut samples: Vec<f64> = raw[start.min(raw.len())..].iter().map(|&s| mixer.process(s)).collect();
+        while samples.last().is_some_and(|s| s.abs() < 1e-6) {
+            samples.pop();
+        }
+
+        let zone = SampleZone {
+            key_range: KeyRange { low: 0, high: 127 },
+            velocity_range: None,
+            pitch: ZonePitch { root_note: FROZEN_ROOT_NOTE, fine_tune_cents: 0.0 },
+            sample_rate: self.sample_rate as u32,
+            r#loop: None,
+            exclusive_group: None,
+            audio: AudioReference::inline_pcm16(&samples),
+            release_audio: None,
+            gain: None,
+            pan: None,
+            key_tracking: None,
+        };
+        Ok(FrozenTrack {
+            preset: PresetDescriptor {
+                format: None,
+                version: None,
+                id: format!("frozen-{track_name}"),
+                name: format!("{track_name} (frozen)"),
+                category: PresetCategory::Sampler,
+                tags: vec!["frozen".to_string()],
+                metadata: None,
+                tuning: None,
+                graph: PresetNode::Sampler {
+                    config: SamplerConfig { zones: vec![zone], is_drum_kit: false, envelope: None },
+                },
+            },
+            start_beat,
+            seconds: samples.len() as f64 / self.sample_rate,
+        })
+    }
+
+    /// Render with a per-track cache: each track's voices are mixed on
+    /// their own and cached by a hash of the track's scheduled notes, so
+    /// tracks that did not change since an earlier render are reused.
+    ///
+    /// Tracks are mixed apart, so when their voices together would go
+    /// over the voice limit, or two tracks play the same exclusive group
+    /// of a preset, the song is rendered without the cache to match
+    /// `render`. Each track's bus sends are cached with its mix and run
+    /// through the default effect buses together. The cache key covers
+    /// which presets are registered but not their sample data, so clear it
+    /// when a preset is replaced.
+    pub fn render_cached(&self, event_list: &EventList, cache: &mut RenderCache) -> Vec<f64> {
+        let plan = self.plan(event_list);
+        let pre_roll = self.pre_roll(event_list, &plan);
+
+        let mut tracks: Vec<(&Option<String>, Vec<ScheduledNote>)> = Vec::new();
+        for note in &plan.scheduled {
+            match tracks.iter_mut().find(|(t, _)| **t == note.track) {
+                Some((_, notes)) => notes.push(note.clone()),
+                None => tracks.push((&note.track, vec![note.clone()])),
+            }
+        }
+
+        // Tracks are cached at their own length and cut or padded to the
+        // song's here, so a change that moves the song end keeps them
+        let parts: Vec<Arc<CachedTrack>> = tracks
+            .iter()
+            .map(|(_, notes)| {
+                let key = self.track_cache_key(notes, plan.tuning_pitch, &plan.tempo);
+                cache.get(key).unwrap_or_else(|| {
+                    let part = Arc::new(self.mix_track(notes, plan.tuning_pitch, &plan.tempo));
+                    cache.insert(key, part.clone());
+                    part
+                })
+            })
+            .collect();
+        if tracks_share_voices(&parts, self.max_voices) {
+            return self.render(event_list);
+        }
+
+        let mut raw = TrackMix::silent(plan.total_samples);
+        let mut bus_inputs: Option<BusInputs> = None;
+        for part in &parts {
+            raw.add(&part.mix);
+            if let Some(sends) = &part.sends {
+                bus_inputs
+                    .get_or_insert_with(|| BusInputs::silent(plan.total_samples))
+                    .add(sends);
+            }
+        }
+        if let Some(bus_inputs) = bus_inputs {
+            self.add_bus_returns(&mut raw, bus_inputs, &SendBuses::default());
+        }
+        let fades = self.song_fades(event_list, &plan, pre_roll.len());
+        let mut output = self.master(raw, pre_roll).into_mono();
+        fades.apply(&mut output, |gain| gain);
+        output
+    }
+
+    /// Play the notes of one track until its last voice finishes, for the
+    /// render cache. Unlike `mix_voices`, the length does not depend on
+    /// the rest of the song.
+    fn mix_track(&self, notes: &[ScheduledNote], tuning_pitch: f64, tempo: &TempoMap) -> CachedTrack {
+        let mut state = VoiceState::default();
+        let mut mix = TrackMix::default();
+        let mut sends = notes.iter().any(|n| !n.sends.is_empty()).then(BusInputs::default);
+        let mut voice_use = VoiceUse::default();
+        let mut block_start = 0;
+        while state.next_note < notes.len() || !state.voices.is_empty() {
+            let block_end = block_start + BLOCK_SIZE;
+            for channel in [Some(&mut mix.left), mix.right.as_mut()].into_iter().flatten() {
+                channel.resize(block_end, 0.0);
+            }
+            if let Some(sends) = &mut sends {
+                sends.reverb.resize(block_end, 0.0);
+                sends.delay.resize(block_end, 0.0);
+            }
+            let mut block = MixBlock {
+                start: block_start,
+                end: block_end,
+                base: 0,
+                output: &mut mix,
+                track_meters: None,
+                sends: sends.as_mut(),
+                tempo,
+                voice_use: Some(&mut voice_use),
+            };
+            self.mix_block(&mut state, notes, tuning_pitch, &mut block);
+            block_start = block_end;
+        }
+        CachedTrack { mix, sends, voices: voice_use }
+    }
+
+    /// Hash everything that affects the raw mix of one track.
+    fn track_cache_key(&self, notes: &[ScheduledNote], tuning_pitch: f64, tempo: &TempoMap) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.sample_rate.to_bits().hash(&mut hasher);
+        tuning_pitch.to_bits().hash(&mut hasher);
+        (self.oscillator_quality as u8).hash(&mut hasher);
+        self.max_voices.hash(&mut hasher);
+        self.placeholder_for_missing_presets.hash(&mut hasher);
+        for note in notes {
+            note.start_sample.hash(&mut hasher);
+            note.release_sample.hash(&mut hasher);
+            note.frequency.to_bits().hash(&mut hasher);
+            note.velocity.to_bits().hash(&mut hasher);
+            note.glide_from.map(f64::to_bits).hash(&mut hasher);
+            note.slide_to.map(f64::to_bits).hash(&mut hasher);
+            note.expression.pan.map(f64::to_bits).hash(&mut hasher);
+            note.expression.brightness.map(f64::to_bits).hash(&mut hasher);
+            note.expression.vibrato.hash(&mut hasher);
+            note.expression.bend.map(f64::to_bits).hash(&mut hasher);
+            note.sends.reverb.to_bits().hash(&mut hasher);
+            note.sends.delay.to_bits().hash(&mut hasher);
+            serde_json::to_string(&note.modulation).unwrap_or_default().hash(&mut hasher);
+            serde_json::to_string(&note.instrument)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+            // A preset that was evicted or registered since falls back
+            // to (or replaces) the oscillator
+            for name in note.instrument.preset_refs() {
+                self.preset_registry.contains(&name).hash(&mut hasher);
+            }
+        }
+        // Synced LFOs follow tempo changes anywhere in the song
+        let synced = |n: &ScheduledNote| {
+            n.modulation.tremolo.is_some_and(|t| t.sync.is_some())
+                || n.modulation.auto_pan.is_some_and(|p| p.sync.is_some())
+        };
+        if notes.iter().any(synced) {
+            for (beat, bpm) in tempo.changes() {
+                (beat.to_bits(), bpm.to_bits()).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Prepare `event_list` for playback block by block. Fails if the song
+    /// uses a count-in, song fades or track sends, which a stream can't
+    /// play.
+    pub fn stream(&self, event_list: &EventList) -> Result<SongStream, String> {
+        let plan = self.plan(event_list);
+        let unsupported: Vec<&str> = [
+            ("song.countIn", event_list.count_in.is_some()),
+            ("song.fadeIn", event_list.fade_in.is_some()),
+            ("song.fadeOut", event_list.fade_out.is_some()),
+            ("track.sends", plan.scheduled.iter().any(|n| !n.sends.is_empty())),
+        ]
+        .into_iter()
+        .filter_map(|(name, used)| used.then_some(name))
+        .collect();
+        if !unsupported.is_empty() {
+            return Err(format!("Streamed songs can't use {}.", unsupported.join(", ")));
+        }
+        Ok(SongStream {
+            plan,
+            state: VoiceState::default(),
+            position: 0,
+            block: TrackMix { left: vec![0.0; BLOCK_SIZE], right: Some(vec![0.0; BLOCK_SIZE]) },
+            block_start: None,
+        })
+    }
+
+    /// Render the next `left.len()` samples of `stream` (`right` must be
+    /// as long), with master gain and soft clipping. Past the end of the
+    /// song this renders silence. Does not allocate unless a voice starts.
+    pub fn render_stream(&self, stream: &mut SongStream, left: &mut [f32], right: &mut [f32]) {
+        let mixer = Mixer::new();
+        let mut done = 0;
+        while done < left.len() {
+            let block_start = stream.position / BLOCK_SIZE * BLOCK_SIZE;
+            if stream.block_start != Some(block_start) {
+                let output = &mut stream.block;
+                output.left.fill(0.0);
+                if let Some(right) = &mut output.right {
+                    right.fill(0.0);
+                }
+                let mut block = MixBlock {
+                    start: block_start,
+                    end: block_start + BLOCK_SIZE,
+                    base: block_start,
+                    output,
+                    track_meters: None,
+                    sends: None,
+                    tempo: &stream.plan.tempo,
+                    voice_use: None,
+                };
+                self.mix_block(&mut stream.state, &stream.plan.scheduled, stream.plan.tuning_pitch, &mut block);
+                stream.block_start = Some(block_start);
+            }
+            let offset = stream.position - block_start;
+            let len = (BLOCK_SIZE - offset).min(left.len() - done);
+            let block_right = stream.block.right.as_ref().unwrap_or(&stream.block.left);
+            for i in 0..len {
+                left[done + i] = mixer.process(stream.block.left[offset + i]) as f32;
+                right[done + i] = mixer.process(block_right[offset + i]) as f32;
+            }
+            stream.position += len;
+            done += len;
+        }
+    }
+
+    /// Start a voice of `instrument` that plays until its `note_off`.
+    /// `velocity` is from 0 to 1, before the instrument's velocity curve.
+    pub fn live_voice(&self, instrument: &InstrumentConfig, frequency: f64, velocity: f64) -> LiveVoice {
+        let note = ScheduledNote {
+            event_index: 0,
+            start_sample: 0,
+            release_sample: usize::MAX,
+            frequency,
+            velocity: instrument.velocity().map_or(velocity, |response| response.level(velocity)),
+            instrument: instrument.clone(),
+            track: None,
+            glide_from: None,
+            slide_to: None,
+            expression: NoteExpression::default(),
+            sends: Sends::default(),
+            modulation: Modulation::default(),
+        };
+        LiveVoice(self.start_voice(&note, self.tuning_pitch))
+    }
+
+    /// Schedule the notes of `event_list` in samples and work out the
+    /// length of the render.
+    fn plan(&self, event_list: &EventList) -> RenderPlan {
+        // Extract tuning from events; tempo changes go through the tempo map
         let mut tuning_pitch = self.tuning_pitch;
         for evt in &event_list.events {
-            if let EventKind::SetProperty { target, value } = &evt.kind {
-                if target == "track.beatsPerMinute" {
-                    if let Ok(v) = value.parse::<f64>() {
-                        bpm = v;
-                    }
-                } else if target == "track.tuningPitch" {
-                    if let Ok(v) = value.parse::<f64>() {
-                        tuning_pitch = v;
-                    }
-                }
+            if let EventKind::SetProperty { target, value, .. } = &evt.kind
+                && target == "track.tuningPitch"
+                && let Ok(v) = value.parse::<f64>()
+            {
+                tuning_pitch = v;
             }
         }
 
+        let tempo = TempoMap::from_events(&event_list.events, self.bpm);
         let cursor_samples = {
-            let seconds = event_list.total_beats * 60.0 / bpm;
+            let seconds = tempo.seconds_at(event_list.total_beats);
             (seconds * self.sample_rate) as usize
         };
 
         // Collect note events with their sample timings
         let mut scheduled: Vec<ScheduledNote> = Vec::new();
-        for evt in &event_list.events {
+        for (event_index, evt) in event_list.events.iter().enumerate() {
             if let EventKind::Note {
                 pitch,
                 velocity,
                 gate,
                 instrument,
+                glide_from,
+                slide_to,
+                expression,
+                sends,
+                modulation,
                 ..
             } = &evt.kind
             {
-                if let Some(freq) = note_to_frequency_with_tuning(pitch, tuning_pitch) {
-                    let start = {
-                        let s = evt.time * 60.0 / bpm;
-                        (s * self.sample_rate) as usize
-                    };
-                    let gate_seconds = gate * 60.0 / bpm;
+                // Notes whose instrument is out of range are skipped
+                if let Some(freq) = note_to_frequency_with_tuning(pitch, tuning_pitch)
+                    && let Some(instrument) = event_list.instruments.get(*instrument)
+                {
+                    let velocity = *velocity / 127.0;
+                    let mut expression = NoteExpression::clone(expression);
+                    if let Some(response) = instrument.velocity()
+                        && let Some(brightness) = response.brightness(velocity)
+                    {
+                        expression.brightness = Some(expression.brightness.map_or(brightness, |b| b.min(brightness)));
+                    }
+                    let start_seconds = tempo.seconds_at(evt.time);
+                    let start = (start_seconds * self.sample_rate) as usize;
+                    let gate_seconds = tempo.seconds_at(evt.time + gate) - start_seconds;
                     let release = start + (gate_seconds * self.sample_rate) as usize;
                     scheduled.push(ScheduledNote {
+                        event_index,
                         start_sample: start,
                         release_sample: release,
                         frequency: freq,
-                        velocity: *velocity / 127.0,
+                        velocity: instrument.velocity().map_or(velocity, |response| response.level(velocity)),
                         instrument: instrument.clone(),
+                        track: evt.track_name.clone(),
+                        glide_from: glide_from
+                            .as_deref()
+                            .and_then(|p| note_to_frequency_with_tuning(p, tuning_pitch)),
+                        slide_to: slide_to
+                            .as_deref()
+                            .and_then(|p| note_to_frequency_with_tuning(p, tuning_pitch)),
+                        expression,
+                        sends: *sends,
+                        modulation: *modulation,
                     });
                 }
             }
@@ -376,8 +1866,6 @@ impl AudioEngine {
         scheduled.sort_by_key(|n| n.start_sample);
 
         // Compute total output length based on EndMode
-        // Default envelope release is 0.3s (from Envelope::new)
-        let default_release = 0.3_f64;
         // Extra tail for effects (reverb, etc.) — future-proofing
         let effects_tail_samples = (0.5 * self.sample_rate) as usize;
 
@@ -391,10 +1879,7 @@ impl AudioEngine {
                 // End after all envelopes finish (per-note release time)
                 let max_release = scheduled
                     .iter()
-                    .map(|n| {
-                        let rel = n.instrument.release.unwrap_or(default_release);
-                        n.release_sample + (rel * self.sample_rate) as usize
-                    })
+                    .map(|n| n.release_sample + self.release_samples(n))
                     .max()
                     .unwrap_or(0);
                 cursor_samples.max(max_release)
@@ -403,144 +1888,445 @@ impl AudioEngine {
                 // End after all notes + effect tails finish
                 let max_tail = scheduled
                     .iter()
-                    .map(|n| {
-                        let rel = n.instrument.release.unwrap_or(default_release);
-                        n.release_sample + (rel * self.sample_rate) as usize + effects_tail_samples
-                    })
+                    .map(|n| n.release_sample + self.release_samples(n) + effects_tail_samples)
                     .max()
                     .unwrap_or(0);
                 cursor_samples.max(max_tail)
             }
         };
 
-        // Render in blocks
-        let block_size = 128;
-        let mut mixer = Mixer::new();
-        let mut voices: Vec<ActiveVoice> = Vec::new();
-        let mut output = vec![0.0_f64; total_samples];
-        let mut next_note_idx = 0;
+        RenderPlan {
+            tuning_pitch,
+            tempo,
+            scheduled,
+            total_samples,
+        }
+    }
+
+    fn pre_roll(&self, event_list: &EventList, plan: &RenderPlan) -> Vec<f64> {
+        match &event_list.count_in {
+            Some(count_in) => self.render_count_in(event_list, count_in, plan.tempo.bpm_at(0.0)),
+            None => Vec::new(),
+        }
+    }
 
+    /// Play `scheduled` notes and return the raw sum of all voices,
+    /// before master gain and clipping. The mix stays mono until the
+    /// first panned voice starts. With `sends`, each voice is also added
+    /// to the input of every bus it sends to.
+    fn mix_voices(
+        &self,
+        scheduled: &[ScheduledNote],
+        total_samples: usize,
+        tuning_pitch: f64,
+        tempo: &TempoMap,
+        mut track_meters: Option<&mut TrackMeters>,
+        mut sends: Option<&mut BusInputs>,
+    ) -> TrackMix {
+        let mut state = VoiceState::default();
+        let mut output = TrackMix::silent(total_samples);
         let mut block_start = 0;
         while block_start < total_samples {
-            let block_end = (block_start + block_size).min(total_samples);
-            let this_block = block_end - block_start;
+            let block_end = (block_start + BLOCK_SIZE).min(total_samples);
+            let mut block = MixBlock {
+                start: block_start,
+                end: block_end,
+                base: 0,
+                output: &mut output,
+                track_meters: track_meters.as_deref_mut(),
+                sends: sends.as_deref_mut(),
+                tempo,
+                voice_use: None,
+            };
+            self.mix_block(&mut state, scheduled, tuning_pitch, &mut block);
+            block_start = block_end;
+        }
+        output
+    }
 
-            // Activate new notes that start in this block
-            while next_note_idx < scheduled.len()
-                && scheduled[next_note_idx].start_sample < block_end
+    /// Start the notes of `scheduled` that begin before the end of
+    /// `block`, release those whose gate ends in it and add every voice
+    /// into `block.output`. Notes start and release on the whole block
+    /// grid, so a short final block plays like the start of a full one.
+    fn mix_block(&self, state: &mut VoiceState, scheduled: &[ScheduledNote], tuning_pitch: f64, block: &mut MixBlock) {
+        let block_start = block.start;
+        let this_block = block.end - block_start;
+        let block_end = block_start + BLOCK_SIZE;
+        let offset = block_start - block.base;
+        let VoiceState { voices, voice_mix, next_note } = state;
+
+        // Activate new notes that start in this block
+        while *next_note < scheduled.len() && scheduled[*next_note].start_sample < block_end {
+            let note = &scheduled[*next_note];
+            *next_note += 1;
+            // Released before this block (a stream that seeked past it)
+            if note.release_sample < block_start {
+                continue;
+            }
+            // Voice leading: move a held voice to the new pitch
+            if let Some(from) = note.glide_from {
+                let held = voices.iter_mut().zip(voice_mix.iter_mut()).find(|(v, mix)| {
+                    mix.track_name == note.track
+                        && v.release_sample() >= note.start_sample
+                        && (mix.frequency - from).abs() < 1e-6
+                });
+                if let Some((v, mix)) = held {
+                    let glide = (VOICE_LEADING_GLIDE * self.sample_rate) as usize;
+                    v.slide(note.frequency / mix.frequency, glide);
+                    v.set_release_sample(note.release_sample);
+                    mix.frequency = note.frequency;
+                    continue;
+                }
+            }
+            if let InstrumentConfig::SamplerRef(preset) = &note.instrument
+                && !self.placeholder_for_missing_presets
+                && !self.preset_registry.contains(&preset.name)
             {
-                let note = &scheduled[next_note_idx];
-                if voices.len() < self.max_voices {
-                    // Check if this note references a preset
-                    let voice = if let Some(ref preset_name) = note.instrument.preset_ref {
-                        if let Some(preset) = self.preset_registry.get(preset_name) {
-                            let midi_note = note_to_midi_from_freq(note.frequency, tuning_pitch);
-                            match preset {
-                                RegisteredPreset::Sampler(sampler) => {
-                                    // Use sampler voice
-                                    if let Some(zone) = sampler.find_zone(midi_note) {
-                                        let mut sv = SamplerVoice::new(
-                                            zone,
-                                            midi_note,
-                                            note.velocity,
-                                            tuning_pitch,
-                                            self.sample_rate,
-                                        );
-                                        sv.release_sample = note.release_sample;
-                                        ActiveVoice::Sampler(sv)
-                                    } else {
-                                        // No matching zone — fall back to oscillator
-                                        let mut v = Voice::with_config(self.sample_rate, &note.instrument);
-                                        v.release_sample = note.release_sample;
-                                        v.note_on(note.frequency, note.velocity);
-                                        ActiveVoice::Oscillator(v)
-                                    }
-                                }
-                                RegisteredPreset::Composite(composite) => {
-                                    // Use composite voice(s)
-                                    let sub_voices = composite.trigger_note(
-                                        midi_note,
-                                        note.velocity,
-                                        tuning_pitch,
-                                        self.sample_rate,
-                                    );
-                                    if sub_voices.is_empty() {
-                                        // No voices triggered — fall back to oscillator
-                                        let mut v = Voice::with_config(self.sample_rate, &note.instrument);
-                                        v.release_sample = note.release_sample;
-                                        v.note_on(note.frequency, note.velocity);
-                                        ActiveVoice::Oscillator(v)
-                                    } else {
-                                        ActiveVoice::Composite(sub_voices, note.release_sample)
-                                    }
-                                }
-                            }
-                        } else {
-                            // Preset not in registry — fall back to oscillator
-                            let mut v = Voice::with_config(self.sample_rate, &note.instrument);
-                            v.release_sample = note.release_sample;
-                            v.note_on(note.frequency, note.velocity);
-                            ActiveVoice::Oscillator(v)
-                        }
-                    } else {
-                        // No preset ref — standard oscillator voice
-                        let mut v = Voice::with_config(self.sample_rate, &note.instrument);
-                        v.release_sample = note.release_sample;
-                        v.note_on(note.frequency, note.velocity);
-                        ActiveVoice::Oscillator(v)
-                    };
-                    voices.push(voice);
+                continue;
+            }
+            if voices.len() >= self.max_voices {
+                continue;
+            }
+            let voice = self.start_voice(note, tuning_pitch);
+            // Exclusive groups: the new voice chokes sounding
+            // voices of the same preset and group.
+            for (preset, group) in voice.choke_keys() {
+                if let Some(usage) = block.voice_use.as_mut()
+                    && !usage.choke_groups.iter().any(|(p, g)| p == preset && *g == group)
+                {
+                    usage.choke_groups.push((preset.to_string(), group));
+                }
+                for other in voices.iter_mut() {
+                    other.choke(preset, group);
                 }
-                next_note_idx += 1;
             }
+            let zone_pan = voice.zone_pan();
+            voices.push(voice);
+            let track = match block.track_meters.as_mut() {
+                Some(meters) => meters.track_index(&note.track, BLOCK_SIZE),
+                None => 0,
+            };
+            let mut mix = VoiceMix::new(track, &note.expression, zone_pan, self.sample_rate);
+            mix.sends = note.sends;
+            mix.modulation = note.modulation;
+            mix.track_name = note.track.clone();
+            mix.frequency = note.slide_to.unwrap_or(note.frequency)
+                * note.expression.bend.map_or(1.0, |bend| math::powf(2.0, bend / 12.0));
+            if mix.is_panned() && block.output.right.is_none() {
+                block.output.right = Some(block.output.left.clone());
+            }
+            voice_mix.push(mix);
+        }
+        if let Some(usage) = block.voice_use.as_mut() {
+            usage.per_block.push(voices.len().min(u16::MAX as usize) as u16);
+        }
 
-            // Check for note releases — each voice carries its own release_sample
-            for voice in voices.iter_mut() {
-                if voice.release_sample() >= block_start && voice.release_sample() < block_end {
-                    voice.note_off();
-                }
+        // Check for note releases — each voice carries its own release_sample
+        for voice in voices.iter_mut() {
+            if voice.release_sample() >= block_start && voice.release_sample() < block_end {
+                voice.note_off();
             }
+        }
 
-            // Render voices into mixer
-            mixer.clear(this_block);
-            for voice in voices.iter_mut() {
-                if !voice.is_finished() {
-                    for i in 0..this_block {
-                        let sample = voice.next_sample();
-                        mixer.add(i, sample);
+        // Render voices into the output
+        let output = &mut *block.output;
+        for (voice, mix) in voices.iter_mut().zip(voice_mix.iter_mut()) {
+            if !voice.is_finished() {
+                for i in 0..this_block {
+                    let mut sample = voice.next_sample();
+                    if let Some(filter) = &mut mix.filter {
+                        sample = filter.process(sample);
+                    }
+                    let (mut gain_left, mut gain_right) = (mix.gain_left, mix.gain_right);
+                    if !mix.modulation.is_empty() {
+                        let (gain, left, right) = mix.modulation_at(block_start + i, self.sample_rate, block.tempo);
+                        sample *= gain;
+                        gain_left *= left;
+                        gain_right *= right;
+                    }
+                    output.left[offset + i] += sample * gain_left;
+                    if let Some(right) = &mut output.right {
+                        right[offset + i] += sample * gain_right;
+                    }
+                    if let Some(meters) = block.track_meters.as_mut() {
+                        meters.buffers[mix.track][i] += sample;
+                    }
+                    if let Some(sends) = block.sends.as_mut() {
+                        sends.reverb[offset + i] += sample * mix.sends.reverb;
+                        sends.delay[offset + i] += sample * mix.sends.delay;
                     }
                 }
             }
+        }
+        if let Some(meters) = block.track_meters.as_mut() {
+            meters.flush(block_start, this_block);
+        }
+
+        // Remove finished voices
+        let mut i = 0;
+        while i < voices.len() {
+            if voices[i].is_finished() {
+                voices.remove(i);
+                voice_mix.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Start the voice for a note, with its slide, bend and vibrato.
+    fn start_voice(&self, note: &ScheduledNote, tuning_pitch: f64) -> ActiveVoice {
+        let mut voice = match &note.instrument {
+            InstrumentConfig::Oscillator(config) => self.synth_voice(note, Voice::with_config(self.sample_rate, config)),
+            InstrumentConfig::Fm(config) => self.synth_voice(note, Voice::with_fm(self.sample_rate, config)),
+            InstrumentConfig::Composite(config) => {
+                // Inline composite: `Layer(...)` or `Split(...)`
+                let midi_note = note_to_midi_from_freq(note.frequency, tuning_pitch);
+                let composite = self.inline_composite(config, midi_note);
+                self.composite_voice(&composite, note, midi_note, note.velocity, tuning_pitch)
+            }
+            InstrumentConfig::SamplerRef(preset) => self.preset_voice(note, preset, tuning_pitch),
+        };
+        let gate_samples = note.release_sample.saturating_sub(note.start_sample);
+        if let Some(target) = note.slide_to {
+            voice.slide(target / note.frequency, gate_samples);
+        }
+        if let Some(bend) = note.expression.bend {
+            voice.slide(math::powf(2.0, bend / 12.0), gate_samples);
+        }
+        if note.expression.vibrato {
+            voice.set_vibrato(VIBRATO_RATE, VIBRATO_DEPTH);
+        }
+        voice
+    }
+
+    /// Run the bus inputs collected by `mix_voices` through the shared
+    /// reverb and delay and add their returns to `mix`.
+    fn add_bus_returns(&self, mix: &mut TrackMix, sends: BusInputs, buses: &SendBuses) {
+        let (reverb, delay) = (&buses.reverb, &buses.delay);
+        if let Some((mut left, mut right)) = bus_input(&sends.reverb) {
+            reverb.reverb(self.sample_rate, 1.0).process_block(&mut left, &mut right);
+            mix.add(&bus_return(&left, &right, reverb.mix));
+        }
+        if let Some((mut left, mut right)) = bus_input(&sends.delay) {
+            Delay::with_params(self.sample_rate, 2.0, delay.time, delay.feedback, 1.0)
+                .process_block(&mut left, &mut right);
+            mix.add(&bus_return(&left, &right, delay.mix));
+        }
+    }
+
+    /// Apply master gain and soft clipping to each channel of a raw mix,
+    /// and prepend the count-in.
+    fn master(&self, raw: TrackMix, pre_roll: Vec<f64>) -> TrackMix {
+        TrackMix {
+            right: raw.right.map(|right| Self::master_channel(right, pre_roll.clone())),
+            left: Self::master_channel(raw.left, pre_roll),
+        }
+    }
+
+    fn master_channel(raw: Vec<f64>, mut pre_roll: Vec<f64>) -> Vec<f64> {
+        let mixer = Mixer::new();
+        pre_roll.extend(raw.into_iter().map(|s| mixer.process(s)));
+        pre_roll
+    }
 
-            // Copy mixer output to main buffer
-            let mixed = mixer.output();
-            for (i, &s) in mixed.iter().enumerate() {
-                output[block_start + i] = s;
+    /// The song fades of `event_list`, for an output that starts with
+    /// `pre_roll` samples of count-in.
+    fn song_fades(&self, event_list: &EventList, plan: &RenderPlan, pre_roll: usize) -> SongFades {
+        let length = |fade: &Option<FadeLength>, beat: f64| {
+            fade.as_ref()
+                .map_or(0, |fade| (fade.seconds(plan.tempo.bpm_at(beat)) * self.sample_rate) as usize)
+        };
+        SongFades {
+            start: pre_roll,
+            fade_in: length(&event_list.fade_in, 0.0),
+            fade_out: length(&event_list.fade_out, event_list.total_beats),
+        }
+    }
+
+    /// Start the voices of a composite instrument, falling back to an
+    /// oscillator when no child plays the note.
+    fn composite_voice(
+        &self,
+        composite: &CompositeInstrument,
+        note: &ScheduledNote,
+        midi_note: u8,
+        velocity: f64,
+        tuning_pitch: f64,
+    ) -> ActiveVoice {
+        let mut sub_voices = composite.trigger_note(midi_note, velocity, tuning_pitch, self.sample_rate);
+        if sub_voices.is_empty() {
+            // No voices triggered — fall back to oscillator
+            return self.fallback_voice(note);
+        }
+        for sv in sub_voices.iter_mut() {
+            if let CompositeVoice::Oscillator(v) = sv {
+                v.oscillator.quality = self.oscillator_quality;
             }
+        }
+        ActiveVoice::Composite(sub_voices, note.release_sample, None)
+    }
 
-            // Remove finished voices
-            voices.retain(|v| !v.is_finished());
+    /// Build an inline composite instrument for `midi_note`, resolving
+    /// preset children through the registry. Children whose preset is not
+    /// registered play as oscillators.
+    fn inline_composite(&self, config: &CompositeConfig, midi_note: u8) -> CompositeInstrument {
+        let children = config
+            .children
+            .iter()
+            .map(|child| match child {
+                InstrumentConfig::Oscillator(osc) => CompositeChild::Oscillator(osc.clone()),
+                InstrumentConfig::Fm(fm) => CompositeChild::Fm(fm.clone()),
+                InstrumentConfig::Composite(nested) => {
+                    CompositeChild::Composite(Box::new(self.inline_composite(nested, midi_note)))
+                }
+                InstrumentConfig::SamplerRef(preset) => match self.preset_registry.get_for_note(&preset.name, midi_note) {
+                    Some(RegisteredPreset::Sampler(sampler)) => CompositeChild::Sampler(sampler.clone()),
+                    Some(RegisteredPreset::Composite(composite)) => {
+                        CompositeChild::Composite(Box::new(composite.clone()))
+                    }
+                    None => CompositeChild::Oscillator(OscillatorConfig::default()),
+                },
+            })
+            .collect();
+        match config.mode {
+            CompositeKind::Layer => CompositeInstrument::new_layer(children, config.mix_levels.clone()),
+            CompositeKind::Split => CompositeInstrument::new_split(children, config.split_points.clone()),
+        }
+    }
 
-            block_start = block_end;
+    /// Start a voice of a registered preset, with the instance's gain,
+    /// transpose and envelope overrides. Falls back to an oscillator when
+    /// the preset is not registered or has no zone for the note.
+    fn preset_voice(&self, note: &ScheduledNote, preset: &SamplerRefConfig, tuning_pitch: f64) -> ActiveVoice {
+        let transpose = math::powf(2.0, preset.transpose.unwrap_or(0.0) / 12.0);
+        let midi_note = note_to_midi_from_freq(note.frequency * transpose, tuning_pitch);
+        let Some(registered) = self.preset_registry.get_for_note(&preset.name, midi_note) else {
+            return self.fallback_voice(note);
+        };
+        let velocity = note.velocity * preset.gain.unwrap_or(1.0);
+        match registered {
+            RegisteredPreset::Sampler(sampler) => {
+                match sampler.start_voice(midi_note, velocity, tuning_pitch, self.sample_rate) {
+                    Some(mut sv) => {
+                        sv.release_sample = note.release_sample;
+                        sv.apply_envelope(&preset.envelope);
+                        let choke_preset = sv.exclusive_group().map(|_| preset.name.clone());
+                        ActiveVoice::Sampler(sv, choke_preset)
+                    }
+                    None => self.fallback_voice(note),
+                }
+            }
+            RegisteredPreset::Composite(composite) => {
+                let mut voice = self.composite_voice(composite, note, midi_note, velocity, tuning_pitch);
+                if let ActiveVoice::Composite(sub_voices, _, choke_preset) = &mut voice {
+                    for sv in sub_voices.iter_mut() {
+                        sv.apply_envelope(&preset.envelope);
+                    }
+                    let grouped = sub_voices
+                        .iter()
+                        .any(|sv| matches!(sv, CompositeVoice::Sampler(v) if v.exclusive_group().is_some()));
+                    if grouped {
+                        *choke_preset = Some(preset.name.clone());
+                    }
+                }
+                voice
+            }
         }
+    }
+
+    /// Default oscillator voice for notes whose instrument cannot play.
+    fn fallback_voice(&self, note: &ScheduledNote) -> ActiveVoice {
+        self.synth_voice(note, Voice::new(self.sample_rate))
+    }
+
+    /// Start an oscillator or FM voice on the note.
+    fn synth_voice(&self, note: &ScheduledNote, mut v: Voice) -> ActiveVoice {
+        v.oscillator.quality = self.oscillator_quality;
+        v.release_sample = note.release_sample;
+        v.note_on(note.frequency, note.velocity);
+        ActiveVoice::Oscillator(v)
+    }
 
+    /// Render the count-in clicks that precede beat 0. Clicks fall on
+    /// each unit of the opening time signature, with accented clicks
+    /// pitched higher.
+    fn render_count_in(&self, event_list: &EventList, count_in: &CountIn, bpm: f64) -> Vec<f64> {
+        let sig = bar_position(&time_signature_changes(&event_list.events), 0.0).time_signature;
+        let seconds_per_beat = 60.0 / bpm;
+        let total = (count_in.beats * seconds_per_beat * self.sample_rate) as usize;
+        let mut output = vec![0.0_f64; total];
+        let accents = sig.accent_pattern();
+        let click_len = (0.03 * self.sample_rate) as usize;
+        let units = count_in.bars as usize * accents.len();
+        for unit in 0..units {
+            let (freq, gain) = match accents[unit % accents.len()] {
+                2 => (1600.0, 0.5),
+                1 => (1200.0, 0.35),
+                _ => (1000.0, 0.25),
+            };
+            let start =
+                (unit as f64 * sig.unit_beats() * seconds_per_beat * self.sample_rate) as usize;
+            for i in 0..click_len.min(total.saturating_sub(start)) {
+                let t = i as f64 / self.sample_rate;
+                let env = 1.0 - i as f64 / click_len as f64;
+                output[start + i] += gain * env * env * math::sin(2.0 * std::f64::consts::PI * freq * t);
+            }
+        }
         output
     }
 
     /// Render to stereo f32 samples with optional master effects.
     ///
-    /// Returns (left_channel, right_channel) as separate vectors.
-    /// Effects are applied in order: Chorus -> Delay -> Reverb -> Compressor
+    /// Returns (left_channel, right_channel) as separate vectors. Track
+    /// sends go through `effects.buses` first (the default buses without
+    /// effects). Effects are applied in order: Widener -> Tremolo -> Auto-pan -> Chorus ->
+    /// Delay -> Reverb -> Filter -> Compressor
     pub fn render_stereo(&self, event_list: &EventList, effects: Option<&MasterEffects>) -> (Vec<f32>, Vec<f32>) {
-        let mono = self.render(event_list);
-
-        // Convert mono to stereo f32
-        let mut left: Vec<f32> = mono.iter().map(|&s| s as f32).collect();
-        let mut right = left.clone();
+        let default_buses = SendBuses::default();
+        let buses = effects.map_or(&default_buses, |fx| &fx.buses);
+        let (mix, _, fades) = self.render_channels(event_list, None, buses);
+
+        // Convert to stereo f32 (mono mixes go to both channels)
+        let mut left: Vec<f32> = mix.left.iter().map(|&s| s as f32).collect();
+        let mut right: Vec<f32> = match &mix.right {
+            Some(right) => right.iter().map(|&s| s as f32).collect(),
+            None => left.clone(),
+        };
 
         // Apply effects if configured
         if let Some(fx) = effects {
-            // 1. Chorus (thickening before space effects)
+            // 1. Widener (spread the mono voices before the other effects)
+            if let Some(widener_cfg) = &fx.widener {
+                let mut widener = Widener::with_params(
+                    self.sample_rate,
+                    widener_cfg.width,
+                    widener_cfg.haas,
+                    widener_cfg.haas_delay,
+                    widener_cfg.crossover,
+                );
+                widener.process_block(&mut left, &mut right);
+            }
+
+            // 2. Tremolo and 3. auto-pan (before the space effects smear them)
+            let tempo = TempoMap::from_events(&event_list.events, self.bpm);
+            if let Some(tremolo_cfg) = &fx.tremolo {
+                let mut tremolo =
+                    Tremolo::with_params(self.sample_rate, tremolo_cfg.rate, tremolo_cfg.depth, tremolo_cfg.shape);
+                self.modulate(&tempo, tremolo_cfg.rate, tremolo_cfg.sync, fades.start, &mut left, &mut right, |rate, l, r| {
+                    tremolo.rate = rate;
+                    tremolo.process_block(l, r);
+                });
+            }
+            if let Some(pan_cfg) = &fx.auto_pan {
+                let mut auto_pan = AutoPan::with_params(self.sample_rate, pan_cfg.rate, pan_cfg.depth, pan_cfg.shape);
+                self.modulate(&tempo, pan_cfg.rate, pan_cfg.sync, fades.start, &mut left, &mut right, |rate, l, r| {
+                    auto_pan.rate = rate;
+                    auto_pan.process_block(l, r);
+                });
+            }
+
+            // 4. Chorus (thickening before space effects)
             if let Some(chorus_cfg) = &fx.chorus {
                 let mut chorus = Chorus::with_params(
                     self.sample_rate,
@@ -551,7 +2337,7 @@ impl AudioEngine {
                 chorus.process_block(&mut left, &mut right);
             }
 
-            // 2. Delay
+            // 5. Delay
             if let Some(delay_cfg) = &fx.delay {
                 let mut delay = Delay::with_params(
                     self.sample_rate,
@@ -563,18 +2349,32 @@ impl AudioEngine {
                 delay.process_block(&mut left, &mut right);
             }
 
-            // 3. Reverb
+            // 6. Reverb
             if let Some(reverb_cfg) = &fx.reverb {
-                let mut reverb = Reverb::with_params(
-                    self.sample_rate,
-                    reverb_cfg.room_size,
-                    reverb_cfg.damping,
-                    reverb_cfg.mix,
-                );
+                let mut reverb = reverb_cfg.reverb(self.sample_rate, reverb_cfg.mix);
                 reverb.process_block(&mut left, &mut right);
             }
 
-            // 4. Compressor (last in chain for level control)
+            // 7. Filter (after the reverb so its tails are filtered too)
+            if let Some(filter_cfg) = &fx.filter {
+                let filter = || {
+                    Filter::new(
+                        filter_cfg.topology,
+                        filter_cfg.filter_type,
+                        self.sample_rate,
+                        filter_cfg.cutoff.clamp(10.0, self.sample_rate * 0.49),
+                        filter_cfg.resonance.clamp(0.1, 20.0),
+                        filter_cfg.gain_db,
+                    )
+                };
+                for (samples, mut filter) in [(&mut left, filter()), (&mut right, filter())] {
+                    for s in samples.iter_mut() {
+                        *s = filter.process(*s as f64) as f32;
+                    }
+                }
+            }
+
+            // 8. Compressor (last in chain for level control)
             if let Some(comp_cfg) = &fx.compressor {
                 let mut compressor = Compressor::with_params(
                     self.sample_rate,
@@ -588,9 +2388,39 @@ impl AudioEngine {
             }
         }
 
+        // Fade last, so the effect tails fade out with the song
+        fades.apply(&mut left, |gain| gain as f32);
+        fades.apply(&mut right, |gain| gain as f32);
         (left, right)
     }
 
+    /// Run an LFO effect over the stereo buffers in short blocks, passing
+    /// the LFO rate for each: `rate` Hz, or one cycle every `sync` beats
+    /// at the song's tempo. The song starts `start` samples in, after the
+    /// count-in, which runs at the starting tempo.
+    #[allow(clippy::too_many_arguments)]
+    fn modulate(
+        &self,
+        tempo: &TempoMap,
+        rate: f64,
+        sync: Option<f64>,
+        start: usize,
+        left: &mut [f32],
+        right: &mut [f32],
+        mut process: impl FnMut(f64, &mut [f32], &mut [f32]),
+    ) {
+        const BLOCK: usize = 64;
+        for (index, (l, r)) in left.chunks_mut(BLOCK).zip(right.chunks_mut(BLOCK)).enumerate() {
+            let seconds = ((index * BLOCK) as f64 - start as f64) / self.sample_rate;
+            let bpm = tempo.bpm_at(tempo.beat_at(seconds));
+            let block_rate = match sync {
+                Some(beats) => bpm / 60.0 / beats.max(1.0 / 64.0),
+                None => rate,
+            };
+            process(block_rate, l, r);
+        }
+    }
+
     /// Render to interleaved stereo i16 PCM (for WAV export).
     pub fn render_pcm_i16(&self, event_list: &EventList) -> Vec<i16> {
         let mono = self.render(event_list);
@@ -620,7 +2450,10 @@ impl AudioEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::compiler::{EndMode, Event, EventKind, EventList, InstrumentConfig};
+    use crate::compiler::{
+        EndMode, EnvelopeConfig, Event, EventKind, EventList, InstrumentConfig, OscillatorConfig,
+        SamplerRefConfig,
+    };
 
     fn make_simple_song() -> EventList {
         EventList {
@@ -631,6 +2464,8 @@ mod tests {
                     kind: EventKind::SetProperty {
                         target: "track.beatsPerMinute".to_string(),
                         value: "120".to_string(),
+                        source_start: 0,
+                        source_end: 0,
                     },
                 },
                 Event {
@@ -640,9 +2475,14 @@ mod tests {
                         pitch: "C4".to_string(),
                         velocity: 100.0,
                         gate: 1.0,
-                        instrument: InstrumentConfig::default(),
+                        instrument: 0,
                         source_start: 0,
                         source_end: 0,
+                        glide_from: None,
+                        slide_to: None,
+                        expression: Default::default(),
+                        sends: Default::default(),
+                        modulation: Default::default(),
                     },
                 },
                 Event {
@@ -652,14 +2492,24 @@ mod tests {
                         pitch: "E4".to_string(),
                         velocity: 80.0,
                         gate: 1.0,
-                        instrument: InstrumentConfig::default(),
+                        instrument: 0,
                         source_start: 0,
                         source_end: 0,
+                        glide_from: None,
+                        slide_to: None,
+                        expression: Default::default(),
+                        sends: Default::default(),
+                        modulation: Default::default(),
                     },
                 },
             ],
+            instruments: vec![InstrumentConfig::default()],
             total_beats: 2.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         }
     }
 
@@ -743,6 +2593,21 @@ mod tests {
         assert_eq!(note_to_midi("C-1"), Some(0));
     }
 
+    #[test]
+    fn midi_to_note_name_roundtrip() {
+        assert_eq!(midi_to_note_name(60, false), "C4");
+        assert_eq!(midi_to_note_name(61, false), "C#4");
+        assert_eq!(midi_to_note_name(61, true), "Db4");
+        assert_eq!(midi_to_note_name(0, false), "C-1");
+        assert_eq!(midi_to_note_name(127, false), "G9");
+        for midi in 0..128 {
+            for flats in [false, true] {
+                let name = midi_to_note_name(midi, flats);
+                assert_eq!(note_to_midi(&name), Some(midi), "{name} should parse back to {midi}");
+            }
+        }
+    }
+
     #[test]
     fn midi_to_frequency_basic() {
         assert!((midi_to_frequency(69, 440.0) - 440.0).abs() < 0.001);
@@ -762,6 +2627,8 @@ mod tests {
                     kind: EventKind::SetProperty {
                         target: "track.beatsPerMinute".to_string(),
                         value: "120".to_string(),
+                        source_start: 0,
+                        source_end: 0,
                     },
                 },
                 Event {
@@ -770,6 +2637,8 @@ mod tests {
                     kind: EventKind::SetProperty {
                         target: "track.tuningPitch".to_string(),
                         value: "432".to_string(),
+                        source_start: 0,
+                        source_end: 0,
                     },
                 },
                 Event {
@@ -779,14 +2648,24 @@ mod tests {
                         pitch: "A4".to_string(),
                         velocity: 100.0,
                         gate: 1.0,
-                        instrument: InstrumentConfig::default(),
+                        instrument: 0,
                         source_start: 0,
                         source_end: 0,
+                        glide_from: None,
+                        slide_to: None,
+                        expression: Default::default(),
+                        sends: Default::default(),
+                        modulation: Default::default(),
                     },
                 },
             ],
+            instruments: vec![InstrumentConfig::default()],
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
         let audio = engine.render(&song);
         // Should produce non-silent output (the tuning change is applied)
@@ -839,8 +2718,13 @@ mod tests {
         let engine = AudioEngine::new(44100.0);
         let song = EventList {
             events: vec![],
+            instruments: Vec::new(),
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
         let audio = engine.render(&song);
 
@@ -861,13 +2745,23 @@ mod tests {
                     pitch: "A4".to_string(),
                     velocity: 100.0,
                     gate: 1.0,
-                    instrument: InstrumentConfig::default(),
+                    instrument: 0,
                     source_start: 0,
                     source_end: 0,
+                    glide_from: None,
+                    slide_to: None,
+                    expression: Default::default(),
+                    sends: Default::default(),
+                    modulation: Default::default(),
                 },
             }],
+            instruments: vec![InstrumentConfig::default()],
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let tail_song = EventList {
@@ -878,13 +2772,23 @@ mod tests {
                     pitch: "A4".to_string(),
                     velocity: 100.0,
                     gate: 1.0,
-                    instrument: InstrumentConfig::default(),
+                    instrument: 0,
                     source_start: 0,
                     source_end: 0,
+                    glide_from: None,
+                    slide_to: None,
+                    expression: Default::default(),
+                    sends: Default::default(),
+                    modulation: Default::default(),
                 },
             }],
+            instruments: vec![InstrumentConfig::default()],
             total_beats: 1.0,
             end_mode: EndMode::Tail,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let gate_audio = engine.render(&gate_song);
@@ -913,6 +2817,8 @@ mod tests {
                     kind: EventKind::SetProperty {
                         target: "track.beatsPerMinute".to_string(),
                         value: "120".to_string(),
+                        source_start: 0,
+                        source_end: 0,
                     },
                 },
                 Event {
@@ -922,14 +2828,24 @@ mod tests {
                         pitch: "A4".to_string(),
                         velocity: 100.0,
                         gate: 0.1,
-                        instrument: InstrumentConfig::default(),
+                        instrument: 0,
                         source_start: 0,
                         source_end: 0,
+                        glide_from: None,
+                        slide_to: None,
+                        expression: Default::default(),
+                        sends: Default::default(),
+                        modulation: Default::default(),
                     },
                 },
             ],
+            instruments: vec![InstrumentConfig::default()],
             total_beats: 2.0,
             end_mode: EndMode::Tail,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let audio = engine.render(&song);
@@ -962,7 +2878,7 @@ mod tests {
                 (2.0 * std::f64::consts::PI * freq * t).sin()
             })
             .collect();
-        let buffer = SampleBuffer::new(data, sample_rate as u32);
+        let buffer = SampleBuffer::from_f64(&data, sample_rate as u32);
 
         let zone = LoadedZone {
             key_range_low: 0,
@@ -972,7 +2888,12 @@ mod tests {
             sample_rate: sample_rate as u32,
             loop_start: None,
             loop_end: None,
-            buffer,
+            exclusive_group: None,
+            buffer: buffer.into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
         };
 
         let sampler = Sampler::new(vec![zone], false);
@@ -986,6 +2907,8 @@ mod tests {
                     kind: EventKind::SetProperty {
                         target: "track.beatsPerMinute".to_string(),
                         value: "120".to_string(),
+                        source_start: 0,
+                        source_end: 0,
                     },
                 },
                 Event {
@@ -995,17 +2918,27 @@ mod tests {
                         pitch: "A4".to_string(),
                         velocity: 100.0,
                         gate: 1.0,
-                        instrument: InstrumentConfig {
-                            preset_ref: Some("TestPreset/Piano".to_string()),
-                            ..Default::default()
-                        },
+                        instrument: 0,
                         source_start: 0,
                         source_end: 0,
+                        glide_from: None,
+                        slide_to: None,
+                        expression: Default::default(),
+                        sends: Default::default(),
+                        modulation: Default::default(),
                     },
                 },
             ],
+            instruments: vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "TestPreset/Piano".to_string(),
+                ..Default::default()
+            })],
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let audio = engine.render(&song);
@@ -1029,16 +2962,26 @@ mod tests {
                     pitch: "C4".to_string(),
                     velocity: 100.0,
                     gate: 1.0,
-                    instrument: InstrumentConfig {
-                        preset_ref: Some("Missing/Preset".to_string()),
-                        ..Default::default()
-                    },
+                    instrument: 0,
                     source_start: 0,
                     source_end: 0,
+                    glide_from: None,
+                    slide_to: None,
+                    expression: Default::default(),
+                    sends: Default::default(),
+                    modulation: Default::default(),
                 },
             }],
+            instruments: vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "Missing/Preset".to_string(),
+                ..Default::default()
+            })],
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let audio = engine.render(&song);
@@ -1069,7 +3012,7 @@ mod tests {
                     (2.0 * std::f64::consts::PI * freq * t).sin()
                 })
                 .collect();
-            let buffer = SampleBuffer::new(data, sample_rate as u32);
+            let buffer = SampleBuffer::from_f64(&data, sample_rate as u32);
             let zone = LoadedZone {
                 key_range_low: 0,
                 key_range_high: 127,
@@ -1078,7 +3021,12 @@ mod tests {
                 sample_rate: sample_rate as u32,
                 loop_start: None,
                 loop_end: None,
-                buffer,
+                exclusive_group: None,
+                buffer: buffer.into(),
+                release_buffer: None,
+                gain: 0.0,
+                pan: 0.0,
+                key_tracking: None,
             };
             Sampler::new(vec![zone], false)
         };
@@ -1100,16 +3048,26 @@ mod tests {
                     pitch: "A4".to_string(),
                     velocity: 100.0,
                     gate: 0.5,
-                    instrument: InstrumentConfig {
-                        preset_ref: Some("TestComposite/Layered".to_string()),
-                        ..Default::default()
-                    },
+                    instrument: 0,
                     source_start: 0,
                     source_end: 0,
+                    glide_from: None,
+                    slide_to: None,
+                    expression: Default::default(),
+                    sends: Default::default(),
+                    modulation: Default::default(),
                 },
             }],
+            instruments: vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "TestComposite/Layered".to_string(),
+                ..Default::default()
+            })],
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let audio = engine.render(&song);
@@ -1129,12 +3087,12 @@ mod tests {
         let mut engine = AudioEngine::new(sample_rate as f64);
 
         // Create a composite with two oscillators
-        let osc1 = InstrumentConfig {
+        let osc1 = OscillatorConfig {
             waveform: "sine".to_string(),
             mixer: Some(0.5),
             ..Default::default()
         };
-        let osc2 = InstrumentConfig {
+        let osc2 = OscillatorConfig {
             waveform: "triangle".to_string(),
             mixer: Some(0.5),
             ..Default::default()
@@ -1157,16 +3115,26 @@ mod tests {
                     pitch: "C4".to_string(),
                     velocity: 100.0,
                     gate: 0.5,
-                    instrument: InstrumentConfig {
-                        preset_ref: Some("TestComposite/OscLayer".to_string()),
-                        ..Default::default()
-                    },
+                    instrument: 0,
                     source_start: 0,
                     source_end: 0,
+                    glide_from: None,
+                    slide_to: None,
+                    expression: Default::default(),
+                    sends: Default::default(),
+                    modulation: Default::default(),
                 },
             }],
+            instruments: vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "TestComposite/OscLayer".to_string(),
+                ..Default::default()
+            })],
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let audio = engine.render(&song);
@@ -1196,7 +3164,7 @@ mod tests {
                     (2.0 * std::f64::consts::PI * freq * t).sin()
                 })
                 .collect();
-            let buffer = SampleBuffer::new(data, sample_rate as u32);
+            let buffer = SampleBuffer::from_f64(&data, sample_rate as u32);
             let zone = LoadedZone {
                 key_range_low: low,
                 key_range_high: high,
@@ -1205,7 +3173,12 @@ mod tests {
                 sample_rate: sample_rate as u32,
                 loop_start: None,
                 loop_end: None,
-                buffer,
+                exclusive_group: None,
+                buffer: buffer.into(),
+                release_buffer: None,
+                gain: 0.0,
+                pan: 0.0,
+                key_tracking: None,
             };
             Sampler::new(vec![zone], false)
         };
@@ -1228,16 +3201,26 @@ mod tests {
                     pitch: "C4".to_string(),
                     velocity: 100.0,
                     gate: 0.5,
-                    instrument: InstrumentConfig {
-                        preset_ref: Some("TestComposite/Split".to_string()),
-                        ..Default::default()
-                    },
+                    instrument: 0,
                     source_start: 0,
                     source_end: 0,
+                    glide_from: None,
+                    slide_to: None,
+                    expression: Default::default(),
+                    sends: Default::default(),
+                    modulation: Default::default(),
                 },
             }],
+            instruments: vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "TestComposite/Split".to_string(),
+                ..Default::default()
+            })],
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let audio = engine.render(&song);
@@ -1278,6 +3261,11 @@ mod tests {
             reverb: None,
             chorus: None,
             compressor: None,
+            widener: None,
+            filter: None,
+            tremolo: None,
+            auto_pan: None,
+            buses: SendBuses::default(),
         };
 
         let (left, right) = engine.render_stereo(&song, Some(&effects));
@@ -1301,9 +3289,15 @@ mod tests {
                 room_size: 0.5,
                 damping: 0.5,
                 mix: 0.3,
+                ..Default::default()
             }),
             chorus: None,
             compressor: None,
+            widener: None,
+            filter: None,
+            tremolo: None,
+            auto_pan: None,
+            buses: SendBuses::default(),
         };
 
         let (left, right) = engine.render_stereo(&song, Some(&effects));
@@ -1326,6 +3320,11 @@ mod tests {
             reverb: Some(ReverbConfig::default()),
             chorus: None,
             compressor: None,
+            widener: None,
+            filter: None,
+            tremolo: None,
+            auto_pan: None,
+            buses: SendBuses::default(),
         };
 
         let pcm = engine.render_pcm_i16_with_effects(&song, &effects);
@@ -1353,6 +3352,11 @@ mod tests {
                 mix: 0.5,
             }),
             compressor: None,
+            widener: None,
+            filter: None,
+            tremolo: None,
+            auto_pan: None,
+            buses: SendBuses::default(),
         };
 
         let (left, right) = engine.render_stereo(&song, Some(&effects));
@@ -1368,6 +3372,166 @@ mod tests {
         // (after the initial delay fills)
     }
 
+    #[test]
+    fn render_stereo_with_widener() {
+        let engine = AudioEngine::new(44100.0);
+        let song = make_simple_song();
+        let effects = MasterEffects { widener: Some(WidenerConfig::default()), ..Default::default() };
+
+        let (dry_left, dry_right) = engine.render_stereo(&song, None);
+        assert_eq!(dry_left, dry_right);
+        let (left, right) = engine.render_stereo(&song, Some(&effects));
+        assert!(left.iter().zip(&right).any(|(l, r)| (l - r).abs() > 0.01), "Mono song should be widened");
+        for i in 0..left.len() {
+            assert!((left[i] + right[i] - 2.0 * dry_left[i]).abs() < 1e-4, "Mono sum changed at {i}");
+        }
+    }
+
+    #[test]
+    fn render_stereo_with_lowpass_filter() {
+        let engine = AudioEngine::new(44100.0);
+        let song = make_simple_song();
+        let energy = |samples: &[f32]| samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>();
+        let (dry, _) = engine.render_stereo(&song, None);
+
+        for topology in [FilterTopology::Biquad, FilterTopology::StateVariable, FilterTopology::OnePole] {
+            let effects = MasterEffects {
+                filter: Some(FilterConfig { topology, cutoff: 100.0, ..Default::default() }),
+                ..Default::default()
+            };
+            let (left, right) = engine.render_stereo(&song, Some(&effects));
+            assert_eq!(left, right);
+            assert!(energy(&left) < energy(&dry) * 0.5, "{topology:?} should darken the mix");
+        }
+
+        let json = r#"{"filter":{"type":"highpass","topology":"stateVariable","cutoff":800}}"#;
+        let effects: MasterEffects = serde_json::from_str(json).unwrap();
+        let filter = effects.filter.unwrap();
+        assert_eq!((filter.filter_type, filter.topology), (FilterType::Highpass, FilterTopology::StateVariable));
+        assert_eq!(filter.resonance, 0.707);
+    }
+
+    #[test]
+    fn render_stereo_with_tremolo_and_auto_pan() {
+        let engine = AudioEngine::new(44100.0);
+        let mut song = make_simple_song();
+        if let EventKind::SetProperty { value, .. } = &mut song.events[0].kind {
+            *value = "60".to_string();
+        }
+        let (dry, _) = engine.render_stereo(&song, None);
+
+        // One cycle per beat at 60 BPM: the dip lands half a second in
+        let tremolo = TremoloConfig { sync: Some(1.0), depth: 1.0, ..Default::default() };
+        let effects = MasterEffects { tremolo: Some(tremolo), ..Default::default() };
+        let (left, right) = engine.render_stereo(&song, Some(&effects));
+        assert_eq!(left, right);
+        assert!(left[22050].abs() < 1e-3 && dry[22050].abs() > 0.01);
+        assert!((left[44100] - dry[44100]).abs() < 1e-3);
+
+        let effects = MasterEffects { auto_pan: Some(AutoPanConfig { depth: 1.0, ..Default::default() }), ..Default::default() };
+        let (left, right) = engine.render_stereo(&song, Some(&effects));
+        let peak = |s: &[f32]| s.iter().fold(0.0_f32, |m, x| m.max(x.abs()));
+        // At 0.5 Hz the sound is hard right after half a second
+        assert!(peak(&left[21000..23100]) < 0.01 * peak(&right[21000..23100]));
+    }
+
+    #[test]
+    fn synced_modulation_skips_the_count_in() {
+        let engine = AudioEngine::new(1000.0);
+        let mut song = make_simple_song();
+        if let EventKind::SetProperty { value, .. } = &mut song.events[0].kind {
+            *value = "60".to_string();
+        }
+        song.events.push(Event {
+            time: 2.0,
+            kind: EventKind::SetProperty {
+                target: "track.beatsPerMinute".to_string(),
+                value: "240".to_string(),
+                source_start: 0,
+                source_end: 0,
+            },
+            track_name: None,
+        });
+        let tempo = TempoMap::from_events(&song.events, 60.0);
+        // Two seconds of count-in at 60 BPM, then the tempo rises at
+        // beat 2 of the song, not of the output
+        let (mut left, mut right) = (vec![0.0_f32; 6000], vec![0.0_f32; 6000]);
+        let mut rates = Vec::new();
+        engine.modulate(&tempo, 5.0, Some(1.0), 2000, &mut left, &mut right, |rate, _, _| rates.push(rate));
+        assert_eq!(rates[0], 1.0);
+        assert_eq!(rates[3500 / 64], 1.0);
+        assert_eq!(rates[4500 / 64], 4.0);
+    }
+
+    #[test]
+    fn track_tremolo_and_auto_pan_follow_the_song_clock() {
+        let engine = AudioEngine::new(44100.0);
+        let render = |settings: &str| {
+            let source = format!(
+                "track.beatsPerMinute = 60;\ntrack lead() {{\n    {settings}\n    C4 /2\n    C4 2\n}}\nlead();"
+            );
+            engine.render_stereo(&crate::compiler::compile(&crate::parse(&source).unwrap()).unwrap(), None)
+        };
+        let peak = |s: &[f32]| s.iter().fold(0.0_f32, |m, x| m.max(x.abs()));
+        let (dry, _) = render("");
+
+        // One cycle per beat from the start of the song: the second note,
+        // half a beat in, dips at 1.5 s rather than a beat after it starts
+        let (left, right) = render("track.tremolo = {sync: 1, depth: 1};");
+        assert_eq!(left, right);
+        assert!(peak(&left[66100..66200]) < 0.01 * peak(&dry[66100..66200]));
+        assert!(peak(&left[43900..44300]) > 0.99 * peak(&dry[43900..44300]));
+
+        // A quarter of the way through a two-beat sweep it is hard right
+        let (left, right) = render("track.autoPan = {sync: 2, depth: 1};");
+        assert!(peak(&left[21900..22200]) < 0.01 * peak(&right[21900..22200]));
+        assert!(peak(&right[65900..66200]) < 0.01 * peak(&left[65900..66200]));
+    }
+
+    #[test]
+    fn track_sends_share_the_effect_buses() {
+        let engine = AudioEngine::new(44100.0);
+        let compile = |sends: &str| {
+            let source = format!("track lead() {{\n    {sends}\n    C4 /4\n}}\nsong.endMode = 'tail';\nlead();");
+            crate::compiler::compile(&crate::parse(&source).unwrap()).unwrap()
+        };
+        let energy = |samples: &[f32]| samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>();
+
+        // The note has ended by the second half of the render; only the
+        // reverb return is left
+        let (dry, _) = engine.render_stereo(&compile(""), None);
+        let (wet, wet_right) = engine.render_stereo(&compile("track.sends = {reverb: 0.5};"), None);
+        let tail = dry.len() * 3 / 4;
+        assert_eq!(dry.len(), wet.len());
+        assert!(energy(&dry[tail..]) < 1e-9);
+        assert!(energy(&wet[tail..]) > 1e-6, "Reverb bus should leave a tail");
+        assert_ne!(wet, wet_right, "The reverb return is stereo");
+
+        // The bus return level comes from the master effects
+        let quiet = MasterEffects {
+            buses: SendBuses { reverb: ReverbConfig { mix: 0.1, ..Default::default() }, ..Default::default() },
+            ..Default::default()
+        };
+        let (quiet, _) = engine.render_stereo(&compile("track.sends = {reverb: 0.5};"), Some(&quiet));
+        assert!(energy(&quiet[tail..]) < energy(&wet[tail..]) * 0.05);
+    }
+
+    #[test]
+    fn render_stereo_with_pre_delayed_reverb() {
+        let engine = AudioEngine::new(44100.0);
+        let song = make_simple_song();
+        let json = r#"{"reverb": {"mix": 0.3, "preDelay": 200, "lowCut": 150, "highCut": 8000}}"#;
+        let effects: MasterEffects = serde_json::from_str(json).unwrap();
+
+        let (dry, _) = engine.render_stereo(&song, None);
+        let (wet, _) = engine.render_stereo(&song, Some(&effects));
+        // Nothing of the room is heard before the pre-delay
+        for i in 0..8820 {
+            assert!((wet[i] - dry[i] * 0.7).abs() < 1e-6, "Reverb heard at sample {i}");
+        }
+        assert!(wet[8820..].iter().zip(&dry[8820..]).any(|(w, d)| (w - d * 0.7).abs() > 1e-3));
+    }
+
     #[test]
     fn render_stereo_with_compressor() {
         let engine = AudioEngine::new(44100.0);
@@ -1384,6 +3548,11 @@ mod tests {
                 release: 0.1,
                 makeup_gain: 0.0,
             }),
+            widener: None,
+            filter: None,
+            tremolo: None,
+            auto_pan: None,
+            buses: SendBuses::default(),
         };
 
         let (left, right) = engine.render_stereo(&song, Some(&effects));
@@ -1407,6 +3576,11 @@ mod tests {
             delay: Some(DelayConfig::default()),
             reverb: Some(ReverbConfig::default()),
             compressor: Some(CompressorConfig::default()),
+            widener: None,
+            filter: None,
+            tremolo: None,
+            auto_pan: None,
+            buses: SendBuses::default(),
         };
 
         let (left, right) = engine.render_stereo(&song, Some(&effects));
@@ -1418,4 +3592,1025 @@ mod tests {
         let max_l = left.iter().fold(0.0_f32, |m, &s| m.max(s.abs()));
         assert!(max_l > 0.001, "Full effects chain should produce audio");
     }
+
+    fn make_flat_sampler(num_samples: usize) -> Sampler {
+        use crate::dsp::sampler::{LoadedZone, SampleBuffer};
+        let zone = LoadedZone {
+            key_range_low: 0,
+            key_range_high: 127,
+            root_note: 69,
+            fine_tune_cents: 0.0,
+            sample_rate: 44100,
+            loop_start: None,
+            loop_end: None,
+            exclusive_group: None,
+            buffer: SampleBuffer::new(vec![0.5; num_samples], 44100).into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
+        };
+        Sampler::new(vec![zone], false)
+    }
+
+    #[test]
+    fn registry_tracks_memory_usage() {
+        let mut registry = PresetRegistry::new();
+        registry.insert("a".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+        registry.insert("b".to_string(), RegisteredPreset::Sampler(make_flat_sampler(500)));
+
+        let stats = registry.stats();
+        assert_eq!(stats.preset_count, 2);
+        assert_eq!(stats.zone_count, 2);
+        assert_eq!(stats.memory_bytes, 6000);
+        assert_eq!(stats.memory_budget, None);
+
+        // Replacing a preset must not double-count its memory
+        registry.insert("a".to_string(), RegisteredPreset::Sampler(make_flat_sampler(100)));
+        assert_eq!(registry.memory_bytes(), 2400);
+
+        assert!(registry.remove("b"));
+        assert!(!registry.remove("b"));
+        assert_eq!(registry.memory_bytes(), 400);
+    }
+
+    #[test]
+    fn registry_evicts_least_recently_used() {
+        // Budget fits two 4000-byte presets
+        let mut registry = PresetRegistry::with_budget(8000);
+        registry.insert("a".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+        registry.insert("b".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+
+        // Touch "a" so "b" becomes the least recently used entry
+        assert!(registry.get("a").is_some());
+        registry.insert("c".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+
+        assert!(registry.contains("a"));
+        assert!(!registry.contains("b"));
+        assert!(registry.contains("c"));
+        assert_eq!(registry.stats().evictions, 1);
+        assert!(registry.memory_bytes() <= 8000);
+    }
+
+    #[test]
+    fn registry_evicts_unused_zones_first() {
+        // Two 4000-byte zones: low notes and high notes
+        let mut split = make_flat_sampler(1000);
+        let mut high = split.zones[0].clone();
+        split.zones[0].key_range_high = 59;
+        high.key_range_low = 60;
+        split.zones.push(high);
+
+        let mut registry = PresetRegistry::with_budget(12_000);
+        registry.insert("split".to_string(), RegisteredPreset::Sampler(split));
+        registry.insert("a".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+        // Play a high note, so the low zone is the least recently used
+        assert!(registry.get_for_note("split", 72).is_some());
+        registry.insert("b".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+
+        let Some(RegisteredPreset::Sampler(split)) = registry.get("split") else { panic!("split was evicted") };
+        assert_eq!(split.zones.len(), 1);
+        assert_eq!(split.zones[0].key_range_low, 60);
+        assert!(registry.contains("a") && registry.contains("b"));
+        assert_eq!(registry.stats().evictions, 1);
+        assert_eq!(registry.memory_bytes(), 12_000);
+    }
+
+    #[test]
+    fn registry_never_evicts_pinned_presets() {
+        let mut registry = PresetRegistry::with_budget(8000);
+        registry.pin("a".to_string());
+        registry.pin("gm:1".to_string());
+        registry.assign_gm_program(1, "b".to_string());
+        registry.insert("a".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+        registry.insert("b".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+        registry.insert("c".to_string(), RegisteredPreset::Sampler(make_flat_sampler(1000)));
+        // Over budget, but only the newest preset is unpinned
+        assert!(registry.contains("a") && registry.contains("b") && registry.contains("c"));
+
+        registry.unpin_all();
+        registry.set_memory_budget(Some(8000));
+        assert!(!registry.contains("a"));
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn registry_keeps_oversized_newest_preset() {
+        let mut registry = PresetRegistry::with_budget(1000);
+        registry.insert("small".to_string(), RegisteredPreset::Sampler(make_flat_sampler(100)));
+        registry.insert("huge".to_string(), RegisteredPreset::Sampler(make_flat_sampler(10_000)));
+
+        assert!(registry.contains("huge"));
+        assert!(!registry.contains("small"));
+
+        // Tightening the budget afterwards evicts it too
+        registry.set_memory_budget(Some(0));
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn render_marks_presets_used() {
+        let mut engine = AudioEngine::with_registry(44100.0, PresetRegistry::with_budget(8000));
+        engine.register_preset("a".to_string(), make_flat_sampler(1000));
+        engine.register_preset("b".to_string(), make_flat_sampler(1000));
+
+        let song = EventList {
+            events: vec![Event {
+                time: 0.0,
+                track_name: None,
+                kind: EventKind::Note {
+                    pitch: "A4".to_string(),
+                    velocity: 100.0,
+                    gate: 0.1,
+                    instrument: 0,
+                    source_start: 0,
+                    source_end: 0,
+                    glide_from: None,
+                    slide_to: None,
+                    expression: Default::default(),
+                    sends: Default::default(),
+                    modulation: Default::default(),
+                },
+            }],
+            instruments: vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "a".to_string(),
+                ..Default::default()
+            })],
+            total_beats: 0.1,
+            end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
+        };
+        engine.render(&song);
+
+        engine.register_preset("c".to_string(), make_flat_sampler(1000));
+        assert!(engine.registry().contains("a"));
+        assert!(!engine.registry().contains("b"));
+    }
+
+    #[test]
+    fn exclusive_group_chokes_same_group() {
+        use crate::dsp::sampler::{LoadedZone, SampleBuffer};
+
+        // Open hat (46) rings with a constant signal; closed hat (42) is
+        // silent so any output after it starts comes from the open hat.
+        let zone = |key: u8, value: f32| LoadedZone {
+            key_range_low: key,
+            key_range_high: key,
+            root_note: key,
+            fine_tune_cents: 0.0,
+            sample_rate: 44100,
+            loop_start: None,
+            loop_end: None,
+            exclusive_group: Some(1),
+            buffer: SampleBuffer::new(vec![value; 88200], 44100).into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
+        };
+        let mut engine = AudioEngine::new(44100.0);
+        engine.register_preset(
+            "Kit".to_string(),
+            Sampler::new(vec![zone(42, 0.0), zone(46, 0.5)], true),
+        );
+
+        let hit = |time: f64, pitch: &str| Event {
+            time,
+            track_name: None,
+            kind: EventKind::Note {
+                pitch: pitch.to_string(),
+                velocity: 127.0,
+                gate: 4.0,
+                instrument: 0,
+                source_start: 0,
+                source_end: 0,
+                glide_from: None,
+                slide_to: None,
+                expression: Default::default(),
+                sends: Default::default(),
+                modulation: Default::default(),
+            },
+        };
+        let song = EventList {
+            // F#2 = 42 (closed), A#2 = 46 (open); 120 BPM → beat 1 = 22050
+            events: vec![hit(0.0, "A#2"), hit(1.0, "F#2")],
+            instruments: vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "Kit".to_string(),
+                ..Default::default()
+            })],
+            total_beats: 2.0,
+            end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
+        };
+
+        let audio = engine.render(&song);
+        assert!(audio[11025].abs() > 0.1, "Open hat should ring before the choke");
+        let after = audio[22050 + 1000..33075]
+            .iter()
+            .fold(0.0_f64, |m, &s| m.max(s.abs()));
+        assert!(after < 1e-6, "Open hat should be choked by closed hat, max={after}");
+    }
+
+    #[test]
+    fn exclusive_group_chokes_composite_children() {
+        use crate::dsp::sampler::{LoadedZone, SampleBuffer};
+
+        // The kit of `exclusive_group_chokes_same_group`, played through
+        // a composite preset.
+        let zone = |key: u8, value: f32| LoadedZone {
+            key_range_low: key,
+            key_range_high: key,
+            root_note: key,
+            fine_tune_cents: 0.0,
+            sample_rate: 44100,
+            loop_start: None,
+            loop_end: None,
+            exclusive_group: Some(1),
+            buffer: SampleBuffer::new(vec![value; 88200], 44100).into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
+        };
+        let mut engine = AudioEngine::new(44100.0);
+        let kit = Sampler::new(vec![zone(42, 0.0), zone(46, 0.5)], true);
+        engine.register_composite(
+            "Kit".to_string(),
+            CompositeInstrument::new_layer(vec![CompositeChild::Sampler(kit)], None),
+        );
+
+        let hit = |time: f64, pitch: &str| Event {
+            time,
+            track_name: None,
+            kind: EventKind::Note {
+                pitch: pitch.to_string(),
+                velocity: 127.0,
+                gate: 4.0,
+                instrument: 0,
+                source_start: 0,
+                source_end: 0,
+                glide_from: None,
+                slide_to: None,
+                expression: Default::default(),
+                sends: Default::default(),
+                modulation: Default::default(),
+            },
+        };
+        let song = EventList {
+            // F#2 = 42 (closed), A#2 = 46 (open); 120 BPM → beat 1 = 22050
+            events: vec![hit(0.0, "A#2"), hit(1.0, "F#2")],
+            instruments: vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+                name: "Kit".to_string(),
+                ..Default::default()
+            })],
+            total_beats: 2.0,
+            end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
+        };
+
+        let audio = engine.render(&song);
+        assert!(audio[11025].abs() > 0.1, "Open hat should ring before the choke");
+        let after = audio[22050 + 1000..33075]
+            .iter()
+            .fold(0.0_f64, |m, &s| m.max(s.abs()));
+        assert!(after < 1e-6, "Composite open hat should be choked, max={after}");
+    }
+
+    #[test]
+    fn count_in_prepends_clicks() {
+        let mut song = make_simple_song();
+        let engine = AudioEngine::new(44100.0);
+        let plain = engine.render(&song);
+        song.count_in = Some(CountIn {
+            bars: 1,
+            beats: 4.0,
+            seconds: 2.0,
+        });
+        let counted = engine.render(&song);
+        // 4 beats at 120 BPM = 2 seconds of pre-roll
+        assert_eq!(counted.len(), plain.len() + 88200);
+        assert!(counted[..1000].iter().any(|s| s.abs() > 0.1), "downbeat click");
+        assert!(counted[30000..44100].iter().all(|s| *s == 0.0), "silence between clicks");
+        assert_eq!(&counted[88200..], &plain[..]);
+    }
+
+    #[test]
+    fn fade_out_silences_end() {
+        let mut song = make_simple_song();
+        let engine = AudioEngine::new(44100.0);
+        let plain = engine.render(&song);
+        song.fade_in = Some(crate::compiler::FadeLength::Seconds(0.2));
+        song.fade_out = Some(crate::compiler::FadeLength::Beats(1.0));
+        let faded = engine.render(&song);
+        assert_eq!(faded.len(), plain.len());
+        assert_eq!(faded[0], 0.0);
+        assert_eq!(*faded.last().unwrap(), 0.0);
+        // Between the fades the song is untouched
+        assert_eq!(faded[15435], plain[15435]);
+        // Half-way through the fade-in the level is halved
+        assert!((faded[4410] - plain[4410] * 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fade_out_covers_master_effect_tails() {
+        let mut song = make_simple_song();
+        song.fade_out = Some(crate::compiler::FadeLength::Beats(1.0));
+        let effects = MasterEffects {
+            delay: Some(DelayConfig { time: 0.3, feedback: 0.6, mix: 0.5 }),
+            reverb: Some(ReverbConfig { mix: 0.5, ..Default::default() }),
+            ..Default::default()
+        };
+        let engine = AudioEngine::new(44100.0);
+        let (left, right) = engine.render_stereo(&song, Some(&effects));
+        assert_eq!(*left.last().unwrap(), 0.0);
+        assert_eq!(*right.last().unwrap(), 0.0);
+        // The echoes are still fading in the last 10 ms
+        let tail = left.len() - 441;
+        assert!(left[tail..].iter().all(|s| s.abs() < 0.02));
+    }
+
+    #[test]
+    fn oscillator_quality_changes_render() {
+        let song = make_simple_song();
+        let mut engine = AudioEngine::new(44100.0);
+        let smooth = engine.render(&song);
+        engine.oscillator_quality = OscillatorQuality::Naive;
+        let chip = engine.render(&song);
+        assert_eq!(smooth.len(), chip.len());
+        assert_ne!(smooth, chip);
+    }
+
+    #[test]
+    fn voice_leading_glides_held_voice() {
+        let plain_song = make_simple_song();
+        let mut song = make_simple_song();
+        if let EventKind::Note { glide_from, .. } = &mut song.events[2].kind {
+            *glide_from = Some("C4".to_string());
+        }
+        let engine = AudioEngine::new(44100.0);
+        let plain = engine.render(&plain_song);
+        let glided = engine.render(&song);
+        assert_ne!(plain, glided);
+        // After the glide only the (reused) voice sounds, at E4 (~329.6 Hz)
+        let window = &glided[22050 + 4000..22050 + 8000];
+        let crossings = window
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count() as f64;
+        let expected = 2.0 * 329.63 * 4000.0 / 44100.0;
+        assert!((crossings - expected).abs() <= 3.0, "crossings {crossings}");
+    }
+
+    #[test]
+    fn voice_leading_glides_held_sampler_voice() {
+        use crate::dsp::sampler::{LoadedZone, SampleBuffer};
+
+        // A looped 441 Hz sine, played as A4
+        let sine: Vec<f32> = (0..44100).map(|i| (2.0 * std::f64::consts::PI * i as f64 / 100.0).sin() as f32).collect();
+        let zone = LoadedZone {
+            key_range_low: 0,
+            key_range_high: 127,
+            root_note: 69,
+            fine_tune_cents: 0.0,
+            sample_rate: 44100,
+            loop_start: Some(0),
+            loop_end: Some(44100),
+            exclusive_group: None,
+            buffer: SampleBuffer::new(sine, 44100).into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
+        };
+        let mut engine = AudioEngine::new(44100.0);
+        engine.register_preset("Sine".to_string(), Sampler::new(vec![zone], false));
+
+        let mut plain_song = make_simple_song();
+        plain_song.instruments =
+            vec![InstrumentConfig::SamplerRef(SamplerRefConfig { name: "Sine".to_string(), ..Default::default() })];
+        let mut song = plain_song.clone();
+        if let EventKind::Note { glide_from, .. } = &mut song.events[2].kind {
+            *glide_from = Some("C4".to_string());
+        }
+        let plain = engine.render(&plain_song);
+        let glided = engine.render(&song);
+        assert_ne!(plain, glided);
+        // The reused voice plays E4 (~329.6 Hz) after the glide
+        let window = &glided[22050 + 4000..22050 + 8000];
+        let crossings = window
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count() as f64;
+        let expected = 2.0 * 329.63 * 4000.0 / 44100.0;
+        assert!((crossings - expected).abs() <= 3.0, "crossings {crossings}");
+    }
+
+    #[test]
+    fn render_with_meters_reports_tracks() {
+        let mut song = make_simple_song();
+        song.events[2].track_name = Some("lead".to_string());
+        let engine = AudioEngine::new(44100.0);
+        let (output, meters) = engine.render_with_meters(&song, 1024);
+        assert_eq!(output, engine.render(&song));
+        let blocks = output.len().div_ceil(1024);
+        assert_eq!(meters.master.peak.len(), blocks);
+        assert_eq!(meters.tracks.len(), 2);
+        assert_eq!(meters.tracks[0].name, None);
+        assert_eq!(meters.tracks[1].name.as_deref(), Some("lead"));
+        // "lead" starts at 0.5s: silent before, sounding after
+        assert_eq!(meters.tracks[1].levels.peak[10], 0.0);
+        assert!(meters.tracks[1].levels.rms[30] > 0.01);
+        assert!(meters.master.peak.iter().all(|&p| p <= 1.0));
+    }
+
+    #[test]
+    fn render_cache_reuses_unchanged_tracks() {
+        let mut song = make_simple_song();
+        song.events[2].track_name = Some("lead".to_string());
+        let engine = AudioEngine::new(44100.0);
+        let mut cache = RenderCache::default();
+
+        let first = engine.render_cached(&song, &mut cache);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats().misses, 2);
+        let plain = engine.render(&song);
+        assert_eq!(first.len(), plain.len());
+        assert!(first.iter().zip(&plain).all(|(a, b)| (a - b).abs() < 1e-9));
+
+        // Change only the "lead" track: the other track comes from the cache
+        if let EventKind::Note { velocity, .. } = &mut song.events[2].kind {
+            *velocity = 60.0;
+        }
+        let second = engine.render_cached(&song, &mut cache);
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 3);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn render_cache_survives_a_longer_song() {
+        let compile = |lead: &str| {
+            let source = format!("track bass() {{\n    C2 1\n}}\ntrack lead() {{\n    {lead}\n}}\nbass();\nlead();");
+            crate::compiler::compile(&crate::parse(&source).unwrap()).unwrap()
+        };
+        let engine = AudioEngine::new(44100.0);
+        let mut cache = RenderCache::default();
+        engine.render_cached(&compile("E4 1"), &mut cache);
+
+        // Lengthening "lead" moves the song end; "bass" is still cached
+        let longer = compile("E4 1\n    G4 4");
+        let cached = engine.render_cached(&longer, &mut cache);
+        assert_eq!(cache.stats().hits, 1);
+        let plain = engine.render(&longer);
+        assert_eq!(cached.len(), plain.len());
+        assert!(cached.iter().zip(&plain).all(|(a, b)| (a - b).abs() < 1e-9));
+
+        // And back to the shorter song, cut to its length
+        let shorter = engine.render_cached(&compile("E4 1"), &mut cache);
+        assert_eq!(cache.stats().hits, 3);
+        assert_eq!(shorter.len(), engine.render(&compile("E4 1")).len());
+    }
+
+    #[test]
+    fn render_cache_keeps_bus_sends() {
+        let source = "track pad() {\n    track.sends = {reverb: 0.6, delay: 0.3};\n    C4 /2\n}\n\
+                      track bass() {\n    C2 /2\n}\nsong.endMode = 'tail';\npad();\nbass();";
+        let song = crate::compiler::compile(&crate::parse(source).unwrap()).unwrap();
+        let engine = AudioEngine::new(44100.0);
+        let plain = engine.render(&song);
+
+        let mut cache = RenderCache::default();
+        for _ in 0..2 {
+            let cached = engine.render_cached(&song, &mut cache);
+            assert_eq!(cached.len(), plain.len());
+            assert!(cached.iter().zip(&plain).all(|(a, b)| (a - b).abs() < 1e-9));
+        }
+        assert_eq!(cache.stats().hits, 2);
+
+        // The wet tail after the notes end is only there with the sends
+        let tail = plain.len() - 4410;
+        assert!(plain[tail..].iter().any(|s| s.abs() > 1e-4));
+    }
+
+    #[test]
+    fn render_cache_matches_render_when_tracks_share_voices() {
+        use crate::dsp::sampler::{LoadedZone, SampleBuffer};
+
+        // Six chord voices over a limit of four: the second track loses two
+        let source = "track a() {\n    [C4, E4, G4] 1\n}\ntrack b() {\n    [C3, E3, G3] 1\n}\na();\nb();";
+        let song = crate::compiler::compile(&crate::parse(source).unwrap()).unwrap();
+        let mut engine = AudioEngine::new(44100.0);
+        let unlimited = engine.render(&song);
+        engine.max_voices = 4;
+        let limited = engine.render(&song);
+        assert_ne!(limited, unlimited);
+        assert_eq!(engine.render_cached(&song, &mut RenderCache::default()), limited);
+
+        // An open hat on one track is choked by a closed hat on another
+        let zone = |key: u8, value: f32| LoadedZone {
+            key_range_low: key,
+            key_range_high: key,
+            root_note: key,
+            fine_tune_cents: 0.0,
+            sample_rate: 44100,
+            loop_start: None,
+            loop_end: None,
+            exclusive_group: Some(1),
+            buffer: SampleBuffer::new(vec![value; 88200], 44100).into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
+        };
+        let mut engine = AudioEngine::new(44100.0);
+        engine.register_preset("Kit".to_string(), Sampler::new(vec![zone(42, 0.0), zone(46, 0.5)], true));
+        let source = "const kit = loadPreset(\"Kit\");\ntrack open() {\n    track.instrument = kit;\n    Bb2 4\n}\n\
+                      track closed() {\n    track.instrument = kit;\n    _ 1\n    Gb2 3\n}\nopen();\nclosed();";
+        let song = crate::compiler::compile(&crate::parse(source).unwrap()).unwrap();
+        let plain = engine.render(&song);
+        assert!(plain[22050 + 1000..33075].iter().all(|s| s.abs() < 1e-6));
+        assert_eq!(engine.render_cached(&song, &mut RenderCache::default()), plain);
+    }
+
+    #[test]
+    fn note_pan_moves_voice_between_channels() {
+        let centred = make_simple_song();
+        let engine = AudioEngine::new(44100.0);
+        let (left, right) = engine.render_stereo(&centred, None);
+        assert_eq!(left, right);
+
+        let mut song = make_simple_song();
+        for event in &mut song.events {
+            if let EventKind::Note { expression, .. } = &mut event.kind {
+                expression.pan = Some(-1.0);
+            }
+        }
+        let (left, right) = engine.render_stereo(&song, None);
+        assert!(left.iter().any(|s| s.abs() > 0.01));
+        assert!(right.iter().all(|&s| s == 0.0));
+        // The mono render is the downmix of both channels
+        let mono = engine.render(&song);
+        assert!((mono[1000] - left[1000] as f64 / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn note_brightness_filters_voice() {
+        let plain = make_simple_song();
+        let mut dark = make_simple_song();
+        for event in &mut dark.events {
+            if let EventKind::Note { expression, .. } = &mut event.kind {
+                expression.brightness = Some(0.0);
+            }
+        }
+        let engine = AudioEngine::new(44100.0);
+        let plain = engine.render(&plain);
+        let dark = engine.render(&dark);
+        assert_eq!(plain.len(), dark.len());
+        let energy = |s: &[f64]| s.iter().map(|x| x * x).sum::<f64>();
+        assert!(energy(&dark) < energy(&plain) * 0.5);
+    }
+
+    #[test]
+    fn slide_glides_to_target_pitch() {
+        let mut song = make_simple_song();
+        if let EventKind::Note { slide_to, .. } = &mut song.events[1].kind {
+            *slide_to = Some("C5".to_string());
+        }
+        let engine = AudioEngine::new(44100.0);
+        let output = engine.render(&song);
+        let crossings = |window: &[f64]| {
+            window.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count() as f64
+        };
+        // The gate is 0.5s: the pitch rises from C4 towards C5
+        let early = crossings(&output[1000..5000]);
+        let late = crossings(&output[17000..21000]);
+        let c4 = 2.0 * 261.63 * 4000.0 / 44100.0;
+        assert!((early - c4).abs() < c4 * 0.15, "early {early}");
+        assert!(late > early * 1.6, "late {late}, early {early}");
+    }
+
+    #[test]
+    fn bend_and_vibrato_change_pitch() {
+        let plain = make_simple_song();
+        let mut bent = make_simple_song();
+        let mut wobbly = make_simple_song();
+        if let EventKind::Note { expression, .. } = &mut bent.events[1].kind {
+            expression.bend = Some(12.0);
+        }
+        if let EventKind::Note { expression, .. } = &mut wobbly.events[1].kind {
+            expression.vibrato = true;
+        }
+        let engine = AudioEngine::new(44100.0);
+        let plain = engine.render(&plain);
+        let bent = engine.render(&bent);
+        let wobbly = engine.render(&wobbly);
+        assert_ne!(plain, wobbly);
+
+        let crossings = |window: &[f64]| {
+            window.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count() as f64
+        };
+        // An octave bend over the 0.5s gate: near C5 by the end
+        let late = crossings(&bent[17000..21000]);
+        assert!(late > crossings(&plain[17000..21000]) * 1.6, "late {late}");
+    }
+
+    #[test]
+    fn render_with_inline_composite() {
+        use crate::compiler::{CompositeConfig, CompositeKind};
+
+        let plain = make_simple_song();
+        let mut layered = make_simple_song();
+        let layer = InstrumentConfig::Composite(CompositeConfig {
+            mode: CompositeKind::Layer,
+            children: vec![
+                InstrumentConfig::Oscillator(OscillatorConfig {
+                    waveform: "sine".to_string(),
+                    ..Default::default()
+                }),
+                // Not registered: plays as an oscillator
+                InstrumentConfig::SamplerRef(SamplerRefConfig {
+                    name: "Missing/Strings".to_string(),
+                    ..Default::default()
+                }),
+            ],
+            mix_levels: Some(vec![0.5, 0.5]),
+            split_points: None,
+        });
+        layered.instruments = vec![layer];
+        let engine = AudioEngine::new(44100.0);
+        let plain = engine.render(&plain);
+        let layered = engine.render(&layered);
+        assert_eq!(plain.len(), layered.len());
+        assert_ne!(plain, layered);
+        assert!(layered.iter().any(|s| s.abs() > 0.01));
+    }
+
+    #[test]
+    fn preset_overrides_apply_per_note() {
+        let mut engine = AudioEngine::new(44100.0);
+        engine.register_preset("Flat".to_string(), make_flat_sampler(20000));
+        let song_with = |preset: SamplerRefConfig| {
+            let mut song = make_simple_song();
+            song.instruments = vec![InstrumentConfig::SamplerRef(preset)];
+            song
+        };
+        let plain = engine.render(&song_with(SamplerRefConfig {
+            name: "Flat".to_string(),
+            ..Default::default()
+        }));
+        let quiet = engine.render(&song_with(SamplerRefConfig {
+            name: "Flat".to_string(),
+            gain: Some(0.5),
+            ..Default::default()
+        }));
+        let peak = |s: &[f64]| s.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+        assert!(peak(&quiet) < peak(&plain) * 0.75);
+
+        // A slow attack override: the first note is still fading in
+        let slow = engine.render(&song_with(SamplerRefConfig {
+            name: "Flat".to_string(),
+            envelope: EnvelopeConfig { attack: Some(0.4), ..Default::default() },
+            ..Default::default()
+        }));
+        assert!(slow[4410].abs() < plain[4410].abs() * 0.5);
+        // An octave up reads the sample twice as fast, so it runs out
+        // before the end of the first note
+        let up = engine.render(&song_with(SamplerRefConfig {
+            name: "Flat".to_string(),
+            transpose: Some(12.0),
+            ..Default::default()
+        }));
+        assert!(plain[20000].abs() > 0.01);
+        assert!(up[20000].abs() < 1e-6);
+    }
+
+    #[test]
+    fn gm_references_resolve_through_program_assignments() {
+        let mut engine = AudioEngine::new(44100.0);
+        engine.register_preset("FluidR3_GM/Acoustic Guitar".to_string(), make_flat_sampler(20000));
+        let mut song = make_simple_song();
+        song.instruments = vec![InstrumentConfig::SamplerRef(SamplerRefConfig {
+            name: "gm:25".to_string(),
+            ..Default::default()
+        })];
+        // Unassigned: the oscillator fallback plays
+        assert!(!engine.registry().contains("gm:25"));
+        let fallback = engine.render(&song);
+
+        engine.registry_mut().assign_gm_program(25, "FluidR3_GM/Acoustic Guitar".to_string());
+        assert!(engine.registry().contains("gm:25"));
+        assert_eq!(engine.registry().gm_program(25), Some("FluidR3_GM/Acoustic Guitar"));
+        let sampled = engine.render(&song);
+        assert_ne!(fallback, sampled);
+
+        engine.registry_mut().clear();
+        assert_eq!(engine.registry().gm_program(25), None);
+    }
+
+    #[test]
+    fn render_loop_wraps_tails_onto_loop_start() {
+        // Voices start on 128-sample block boundaries; pick a rate where
+        // the loop start falls on one
+        let engine = AudioEngine::new(44800.0);
+        let song = crate::compiler::compile(
+            &crate::parse("track t() {\n    G3 2\n    C4@2 2\n    E4 2\n}\nt();").unwrap(),
+        )
+        .unwrap();
+        // Loop beats 2-4 (one second at 120 BPM), where C4 plays
+        let looped = engine.render_loop(&song, 2.0, 4.0);
+        assert_eq!(looped.len(), 44800);
+
+        // C4 alone, then its release tail folded back onto the start
+        let alone = crate::compiler::compile(&crate::parse("track t() {\n    C4@2 2\n}\nt();").unwrap()).unwrap();
+        let mut plain = engine.render(&alone);
+        plain.resize(2 * 44800, 0.0);
+        let peak = |s: &[f64]| s.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+        assert!(peak(&plain[44800..]) > 0.01, "C4 should still ring after the loop end");
+        assert!((looped[100] - plain[100]).abs() > 1e-6, "the tail is wrapped onto the start");
+        // Past the wrapped tail, only C4 is heard: not the G3 before the
+        // loop nor the E4 after it
+        assert!((looped[30000] - plain[30000]).abs() < 1e-9);
+        assert_eq!(looped, engine.render_loop(&alone, 0.0, 2.0));
+
+        assert!(engine.render_loop(&song, 4.0, 2.0).is_empty());
+    }
+
+    #[test]
+    fn freeze_track_records_one_track_as_a_sampler() {
+        use base64::Engine as _;
+        // At 44800 Hz, beat 2 falls on a voice block boundary
+        let engine = AudioEngine::new(44800.0);
+        let song = crate::compiler::compile(
+            &crate::parse("track bass() {\n    C2@4 4\n}\ntrack lead() {\n    _ 2\n    E4@1 1\n}\nbass();\nlead();").unwrap(),
+        )
+        .unwrap();
+        let frozen = engine.freeze_track(&song, "lead").unwrap();
+        assert_eq!(frozen.start_beat, 2.0);
+        assert_eq!(frozen.preset.name, "lead (frozen)");
+
+        let PresetNode::Sampler { config } = &frozen.preset.graph else {
+            panic!("Expected a sampler preset");
+        };
+        let zone = &config.zones[0];
+        assert_eq!((zone.key_range.low, zone.key_range.high, zone.pitch.root_note), (0, 127, FROZEN_ROOT_NOTE));
+        let AudioReference::InlinePcm { data, .. } = &zone.audio else {
+            panic!("Expected inline PCM");
+        };
+        let pcm = base64::engine::general_purpose::STANDARD.decode(data).unwrap();
+        let frozen_samples: Vec<f64> = pcm
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f64 / 32767.0)
+            .collect();
+        assert_eq!(frozen_samples.len() as f64 / 44800.0, frozen.seconds);
+
+        // The lead alone, without the bass, from its first note
+        let alone = crate::compiler::compile(&crate::parse("track lead() {\n    E4@1 1\n}\nlead();").unwrap()).unwrap();
+        let plain = engine.render(&alone);
+        assert!(frozen.seconds > 0.5);
+        for (a, b) in frozen_samples.iter().zip(&plain) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        assert_eq!(
+            engine.freeze_track(&song, "drums").unwrap_err(),
+            "No notes on track 'drums' to freeze."
+        );
+    }
+
+    #[test]
+    fn registered_preset_reports_key_coverage() {
+        use crate::dsp::sampler::{LoadedZone, SampleBuffer};
+        let zone = |note: u8| LoadedZone {
+            key_range_low: note,
+            key_range_high: note,
+            root_note: note,
+            fine_tune_cents: 0.0,
+            sample_rate: 44100,
+            loop_start: None,
+            loop_end: None,
+            exclusive_group: None,
+            buffer: SampleBuffer::new(vec![0.5; 10], 44100).into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
+        };
+        let kit = RegisteredPreset::Sampler(Sampler::new(vec![zone(36), zone(38), zone(42)], true));
+        let coverage = kit.key_coverage().unwrap();
+        assert_eq!((coverage.low_note, coverage.high_note), (36, 42));
+        assert_eq!(coverage.notes, vec![36, 38, 42]);
+        assert!(coverage.is_drum_kit);
+
+        let piano = RegisteredPreset::Sampler(make_flat_sampler(10)).key_coverage().unwrap();
+        assert_eq!((piano.low_note, piano.high_note, piano.notes.len()), (0, 127, 128));
+        assert!(!piano.is_drum_kit);
+        assert_eq!(RegisteredPreset::Sampler(Sampler::new(vec![], false)).key_coverage(), None);
+    }
+
+    #[test]
+    fn note_onsets_match_rendered_audio() {
+        let song = crate::compiler::compile(
+            &crate::parse("track lead() {\n    C4@/4 2\n    E4@/2 1\n}\nlead();").unwrap(),
+        )
+        .unwrap();
+        // 120 BPM at 44800 Hz: two beats are 44800 samples, a whole number of blocks
+        let engine = AudioEngine::new(44800.0);
+        let (samples, onsets) = engine.render_with_onsets(&song);
+        assert_eq!(samples, engine.render(&song));
+
+        let starts: Vec<(usize, usize)> = onsets.iter().map(|o| (o.start_sample, o.release_sample)).collect();
+        assert_eq!(starts, vec![(0, 5504), (44800, 55936)]);
+        assert_eq!(onsets[1].pitch, "E4");
+        assert_eq!(onsets[1].track.as_deref(), Some("lead"));
+        assert!(matches!(song.events[onsets[1].note_id].kind, EventKind::Note { .. }));
+        // Silent after the first note's release tail until the second onset
+        assert!(samples[44800 - 1].abs() < 1e-9);
+        assert!(samples[44800..44800 + BLOCK_SIZE].iter().any(|s| s.abs() > 1e-3));
+
+        // A count-in shifts every onset
+        let counted = crate::compiler::compile(
+            &crate::parse("song.countIn = 1;\ntrack lead() {\n    C4 1\n}\nlead();").unwrap(),
+        )
+        .unwrap();
+        let (samples, onsets) = engine.render_with_onsets(&counted);
+        let pre_roll = samples.len() - engine.render(&EventList { count_in: None, ..counted.clone() }).len();
+        assert!(pre_roll > 0);
+        assert_eq!(onsets[0].start_sample, pre_roll);
+    }
+
+    /// With `deterministic`, this hash must be the same on every target
+    /// (x86_64, aarch64, wasm32).
+    #[cfg(feature = "deterministic")]
+    #[test]
+    fn deterministic_render_is_bit_identical() {
+        let samples = AudioEngine::new(44100.0).render(&make_simple_song());
+        // FNV-1a over the sample bits
+        let hash = samples.iter().fold(0xcbf29ce484222325_u64, |h, s| {
+            (h ^ s.to_bits()).wrapping_mul(0x100000001b3)
+        });
+        assert_eq!(hash, 0xab4bb0f89fca90f7, "Render changed: {hash:#018x}");
+    }
+
+    #[test]
+    fn missing_presets_are_reported_and_optionally_silent() {
+        let source = "\
+const piano = loadPreset(\"Lib/Piano\");
+const pad = Layer([Oscillator({type: 'sine'}), loadPreset(\"Lib/Pad\")]);
+track a() {
+    2
+    track.instrument = piano;
+    C4 1
+}
+track b() {
+    track.instrument = pad;
+    E4 1
+}
+a();
+b();";
+        let song = crate::compiler::compile(&crate::parse(source).unwrap()).unwrap();
+        let mut engine = AudioEngine::new(44800.0);
+        assert_eq!(engine.missing_presets(&song), vec!["Lib/Pad".to_string(), "Lib/Piano".to_string()]);
+
+        let peak = |samples: &[f64]| samples.iter().fold(0.0_f64, |m, s| m.max(s.abs()));
+        // One beat at 120 BPM is 22400 samples; the piano note starts at beat 2
+        let placeholder = engine.render(&song);
+        assert!(peak(&placeholder[44800..67200]) > 0.01);
+        engine.placeholder_for_missing_presets = false;
+        let silent = engine.render(&song);
+        assert_eq!(peak(&silent[44800..67200]), 0.0);
+        assert!(peak(&silent[..22400]) > 0.01, "Composite children still fall back");
+    }
+
+    #[test]
+    fn notes_with_an_out_of_range_instrument_are_skipped() {
+        let mut song =
+            crate::compiler::compile(&crate::parse("track a() {\n    C4 1\n    E4 1\n}\na();").unwrap()).unwrap();
+        let dangling = song.instruments.len();
+        if let Some(EventKind::Note { instrument, .. }) =
+            song.events.iter_mut().rev().map(|e| &mut e.kind).find(|k| matches!(k, EventKind::Note { .. }))
+        {
+            *instrument = dangling;
+        }
+        let engine = AudioEngine::new(44100.0);
+        assert!(engine.missing_presets(&song).is_empty());
+        let audio = engine.render(&song);
+        assert!(audio.iter().any(|s| s.abs() > 0.01), "The first note still plays");
+        song.events.retain(|e| !matches!(e.kind, EventKind::Note { instrument, .. } if instrument == dangling));
+        assert_eq!(audio, engine.render(&song));
+    }
+
+    #[test]
+    fn zone_pan_adds_to_note_pan() {
+        let source = "\
+const piano = loadPreset(\"Lib/Piano\");
+track a() {
+    track.instrument = piano;
+    C4 1
+}
+a();";
+        let song = crate::compiler::compile(&crate::parse(source).unwrap()).unwrap();
+        let mut sampler = make_flat_sampler(44100);
+        sampler.zones[0].pan = 1.0;
+        let mut engine = AudioEngine::new(44800.0);
+        engine.register_preset("Lib/Piano".to_string(), sampler);
+        let (left, right) = engine.render_stereo(&song, None);
+        assert!(right.iter().any(|s| s.abs() > 0.01));
+        assert!(left.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn zone_pan_applies_to_composite_children() {
+        let source = "\
+const piano = loadPreset(\"Lib/Piano\");
+const layer = Layer([piano]);
+track a() {
+    track.instrument = layer;
+    C4 1
+}
+a();";
+        let song = crate::compiler::compile(&crate::parse(source).unwrap()).unwrap();
+        let mut sampler = make_flat_sampler(44100);
+        sampler.zones[0].pan = -1.0;
+        let mut engine = AudioEngine::new(44800.0);
+        engine.register_preset("Lib/Piano".to_string(), sampler);
+        let (left, right) = engine.render_stereo(&song, None);
+        assert!(left.iter().any(|s| s.abs() > 0.01));
+        assert!(right.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn estimate_matches_render_without_rendering() {
+        let song = make_simple_song();
+        let engine = AudioEngine::new(44100.0);
+        let estimate = engine.estimate(&song);
+        assert_eq!(estimate.total_seconds, 1.0);
+        assert_eq!(estimate.render_seconds, engine.render(&song).len() as f64 / 44100.0);
+        assert_eq!((estimate.event_count, estimate.note_count), (3, 2));
+        // C4's release tail overlaps E4
+        assert_eq!((estimate.max_voices, estimate.voice_limit), (2, 64));
+        assert_eq!(estimate.memory_bytes, 44100 * RENDER_BYTES_PER_FRAME);
+
+        assert_eq!(max_overlap(&[(0, 10), (10, 20), (5, 6)]), 2);
+        assert_eq!(max_overlap(&[]), 0);
+    }
+
+    #[test]
+    fn polyphony_curve_and_dropped_notes() {
+        let engine = AudioEngine::new(44100.0);
+        // C4 (0..1 beat) rings 0.3s into E4's beat
+        let polyphony = engine.polyphony(&make_simple_song());
+        assert_eq!(polyphony.per_beat, vec![1, 2, 1]);
+        assert!(polyphony.dropped.is_empty());
+
+        let mut song = make_simple_song();
+        let chord = song.events[1].clone();
+        for _ in 0..65 {
+            song.events.insert(1, chord.clone());
+        }
+        let polyphony = engine.polyphony(&song);
+        assert_eq!(polyphony.per_beat[..2], [66, 67]);
+        let dropped: Vec<usize> = polyphony.dropped.iter().map(|n| n.note_id).collect();
+        // Two of the 66 C4s, then E4 while the chord's release still rings
+        assert_eq!(dropped, vec![65, 66, 67]);
+        assert_eq!(engine.estimate(&song).max_voices, 67);
+    }
+
+    #[test]
+    fn instrument_velocity_curve_and_filter() {
+        use crate::compiler::{VelocityConfig, VelocityCurve};
+        let engine = AudioEngine::new(44100.0);
+        let render = |velocity: VelocityConfig| {
+            let mut song = make_simple_song();
+            let waveform = "sawtooth".to_string();
+            song.instruments =
+                vec![InstrumentConfig::Oscillator(OscillatorConfig { waveform, velocity, ..Default::default() })];
+            engine.render(&song)
+        };
+        let peak = |s: &[f64]| s[..22050].iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+        let plain = render(VelocityConfig::default());
+
+        // The first note has velocity 100, so a hard touch plays it quieter
+        // and a soft touch louder
+        let curve = |curve| render(VelocityConfig { curve: Some(curve), filter: None });
+        let hard = peak(&curve(VelocityCurve::Hard)) / peak(&plain);
+        assert!(hard < 0.9, "Hard ratio {hard}");
+        let soft = peak(&curve(VelocityCurve::Soft)) / peak(&plain);
+        assert!(soft > 1.05, "Soft ratio {soft}");
+        let table = peak(&curve(VelocityCurve::Table(vec![0.0, 1.0]))) / peak(&plain);
+        assert!((table - 1.0).abs() < 1e-9);
+
+        // Soft notes are darkened: less high-frequency energy
+        let dark = render(VelocityConfig { curve: None, filter: Some(1.0) });
+        let edge_energy = |s: &[f64]| s.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum::<f64>();
+        assert!(edge_energy(&dark) < edge_energy(&plain) * 0.8, "{} vs {}", edge_energy(&dark), edge_energy(&plain));
+    }
 }
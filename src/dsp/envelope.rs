@@ -1,26 +1,51 @@
-//! ADSR Envelope generator.
+//! AHDSR Envelope generator.
+
+use crate::compiler::EnvelopeCurve;
+use crate::math;
+
+/// Steepness of the exponential and logarithmic curves.
+const CURVE_STEEPNESS: f64 = 5.0;
+
+/// Fraction of a stage's travel done `t` (0 to 1) of the way through it.
+/// Exponential stages fall quickly and rise slowly at first; logarithmic
+/// stages the reverse.
+pub fn curve_progress(curve: EnvelopeCurve, t: f64, rising: bool) -> f64 {
+    let slow_start = |t: f64| (math::exp(CURVE_STEEPNESS * t) - 1.0) / (math::exp(CURVE_STEEPNESS) - 1.0);
+    match (curve, rising) {
+        (EnvelopeCurve::Linear, _) => t,
+        (EnvelopeCurve::Exp, true) | (EnvelopeCurve::Log, false) => slow_start(t),
+        (EnvelopeCurve::Exp, false) | (EnvelopeCurve::Log, true) => 1.0 - slow_start(1.0 - t),
+    }
+}
 
 /// Envelope stages.
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Stage {
     Idle,
     Attack,
+    Hold,
     Decay,
     Sustain,
     Release,
 }
 
-/// ADSR Envelope with linear attack/decay/release curves.
+/// AHDSR Envelope. The attack, decay and release are linear unless given
+/// a curve.
 #[derive(Debug, Clone)]
 pub struct Envelope {
     /// Attack time in seconds.
     pub attack: f64,
+    /// Time in seconds held at full level after the attack.
+    pub hold: f64,
     /// Decay time in seconds.
     pub decay: f64,
     /// Sustain level [0, 1].
     pub sustain: f64,
     /// Release time in seconds.
     pub release: f64,
+    pub attack_curve: EnvelopeCurve,
+    pub decay_curve: EnvelopeCurve,
+    pub release_curve: EnvelopeCurve,
 
     stage: Stage,
     level: f64,
@@ -36,9 +61,13 @@ impl Envelope {
     pub fn new(sample_rate: f64) -> Self {
         Envelope {
             attack: 0.01,
+            hold: 0.0,
             decay: 0.1,
             sustain: 0.7,
             release: 0.3,
+            attack_curve: EnvelopeCurve::Linear,
+            decay_curve: EnvelopeCurve::Linear,
+            release_curve: EnvelopeCurve::Linear,
             stage: Stage::Idle,
             level: 0.0,
             sample_rate,
@@ -76,24 +105,33 @@ impl Envelope {
             Stage::Attack => {
                 if self.stage_samples == 0 {
                     self.level = 1.0;
-                    self.enter_decay();
+                    self.enter_hold();
                 } else {
                     let t = self.stage_counter as f64 / self.stage_samples as f64;
-                    self.level = self.start_level + (1.0 - self.start_level) * t;
+                    let progress = curve_progress(self.attack_curve, t, true);
+                    self.level = self.start_level + (1.0 - self.start_level) * progress;
                     self.stage_counter += 1;
                     if self.stage_counter >= self.stage_samples {
                         self.level = 1.0;
-                        self.enter_decay();
+                        self.enter_hold();
                     }
                 }
             }
+            Stage::Hold => {
+                self.level = 1.0;
+                self.stage_counter += 1;
+                if self.stage_counter >= self.stage_samples {
+                    self.enter_decay();
+                }
+            }
             Stage::Decay => {
                 if self.stage_samples == 0 {
                     self.level = self.sustain;
                     self.stage = Stage::Sustain;
                 } else {
                     let t = self.stage_counter as f64 / self.stage_samples as f64;
-                    self.level = 1.0 - (1.0 - self.sustain) * t;
+                    let progress = curve_progress(self.decay_curve, t, false);
+                    self.level = 1.0 - (1.0 - self.sustain) * progress;
                     self.stage_counter += 1;
                     if self.stage_counter >= self.stage_samples {
                         self.level = self.sustain;
@@ -110,7 +148,7 @@ impl Envelope {
                     self.stage = Stage::Idle;
                 } else {
                     let t = self.stage_counter as f64 / self.stage_samples as f64;
-                    self.level = self.start_level * (1.0 - t);
+                    self.level = self.start_level * (1.0 - curve_progress(self.release_curve, t, false));
                     self.stage_counter += 1;
                     if self.stage_counter >= self.stage_samples {
                         self.level = 0.0;
@@ -127,6 +165,16 @@ impl Envelope {
         self.stage == Stage::Idle
     }
 
+    fn enter_hold(&mut self) {
+        self.stage_samples = (self.hold * self.sample_rate) as usize;
+        self.stage_counter = 0;
+        if self.stage_samples == 0 {
+            self.enter_decay();
+        } else {
+            self.stage = Stage::Hold;
+        }
+    }
+
     fn enter_decay(&mut self) {
         self.stage = Stage::Decay;
         self.stage_samples = (self.decay * self.sample_rate) as usize;
@@ -231,4 +279,47 @@ mod tests {
 
         assert!(env.is_finished());
     }
+
+    #[test]
+    fn hold_keeps_full_level_before_decay() {
+        let mut env = Envelope::new(1000.0);
+        env.attack = 0.01;
+        env.hold = 0.05;
+        env.decay = 0.01;
+        env.sustain = 0.5;
+        env.gate_on();
+        let levels: Vec<f64> = (0..100).map(|_| env.next_sample()).collect();
+        assert!(levels[10..60].iter().all(|l| *l == 1.0), "Should hold at 1.0: {:?}", &levels[10..60]);
+        assert!(levels[61] < 1.0);
+        assert_eq!(levels[80], 0.5);
+    }
+
+    #[test]
+    fn curves_shape_each_stage() {
+        let midpoint = |curve: EnvelopeCurve| {
+            let mut env = Envelope::new(1000.0);
+            (env.attack, env.decay, env.sustain, env.release) = (0.1, 0.0, 1.0, 0.1);
+            (env.attack_curve, env.release_curve) = (curve, curve);
+            env.gate_on();
+            let attack = (0..=50).map(|_| env.next_sample()).last().unwrap();
+            (0..100).for_each(|_| {
+                env.next_sample();
+            });
+            env.gate_off();
+            let release = (0..=50).map(|_| env.next_sample()).last().unwrap();
+            (attack, release)
+        };
+        assert_eq!(midpoint(EnvelopeCurve::Linear), (0.5, 0.5));
+        // Exponential: slow rise, fast fall; logarithmic the reverse
+        let (attack, release) = midpoint(EnvelopeCurve::Exp);
+        assert!(attack < 0.2 && release < 0.2, "exp: {attack}, {release}");
+        let (attack, release) = midpoint(EnvelopeCurve::Log);
+        assert!(attack > 0.8 && release > 0.8, "log: {attack}, {release}");
+        for curve in [EnvelopeCurve::Exp, EnvelopeCurve::Log] {
+            for rising in [true, false] {
+                assert!(curve_progress(curve, 0.0, rising).abs() < 1e-12);
+                assert!((curve_progress(curve, 1.0, rising) - 1.0).abs() < 1e-12);
+            }
+        }
+    }
 }
@@ -1,9 +1,14 @@
-//! Biquad filter — matches WebAudio BiquadFilterNode coefficients.
+//! Filters — a biquad matching WebAudio BiquadFilterNode coefficients,
+//! plus state-variable and one-pole topologies.
 
-use std::f64::consts::PI;
+use core::f64::consts::PI;
+use serde::{Deserialize, Serialize};
+use crate::math;
 
 /// Filter type.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
 pub enum FilterType {
     Lowpass,
     Highpass,
@@ -62,8 +67,8 @@ impl BiquadFilter {
     /// Recompute filter coefficients from current parameters.
     pub fn update_coefficients(&mut self) {
         let w0 = 2.0 * PI * self.frequency / self.sample_rate;
-        let cos_w0 = w0.cos();
-        let sin_w0 = w0.sin();
+        let cos_w0 = math::cos(w0);
+        let sin_w0 = math::sin(w0);
         let alpha = sin_w0 / (2.0 * self.q);
 
         let (b0, b1, b2, a0, a1, a2) = match self.filter_type {
@@ -105,7 +110,7 @@ impl BiquadFilter {
                 (b0, b1, b2, a0, a1, a2)
             }
             FilterType::Peaking => {
-                let a_lin = (10.0_f64).powf(self.gain_db / 40.0);
+                let a_lin = math::powf(10.0, self.gain_db / 40.0);
                 let b0 = 1.0 + alpha * a_lin;
                 let b1 = -2.0 * cos_w0;
                 let b2 = 1.0 - alpha * a_lin;
@@ -156,6 +161,244 @@ impl BiquadFilter {
     }
 }
 
+// ── Other Topologies ────────────────────────────────────────
+
+/// How a filter is built, trading slope and character for cost.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum FilterTopology {
+    /// 12 dB/octave biquad, as in WebAudio.
+    #[default]
+    Biquad,
+    /// 12 dB/octave state-variable filter; stays stable when the cutoff
+    /// is swept quickly.
+    StateVariable,
+    /// 6 dB/octave one-pole, for gentle, warm slopes.
+    OnePole,
+}
+
+/// A 12 dB/octave state-variable filter, using the trapezoidal
+/// integration of Andrew Simper's SVF.
+#[derive(Debug, Clone)]
+pub struct StateVariableFilter {
+    pub filter_type: FilterType,
+    pub frequency: f64,
+    pub q: f64,
+    pub gain_db: f64, // only used for Peaking
+
+    // Coefficients
+    k: f64,
+    a1: f64,
+    a2: f64,
+    a3: f64,
+    peak_gain: f64,
+
+    // Integrator state
+    ic1: f64,
+    ic2: f64,
+
+    sample_rate: f64,
+    dirty: bool,
+}
+
+impl StateVariableFilter {
+    pub fn new(filter_type: FilterType, sample_rate: f64) -> Self {
+        let mut f = StateVariableFilter {
+            filter_type,
+            frequency: 1000.0,
+            q: 0.707,
+            gain_db: 0.0,
+            k: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            a3: 0.0,
+            peak_gain: 1.0,
+            ic1: 0.0,
+            ic2: 0.0,
+            sample_rate,
+            dirty: true,
+        };
+        f.update_coefficients();
+        f
+    }
+
+    /// Recompute filter coefficients from current parameters.
+    pub fn update_coefficients(&mut self) {
+        let g = math::tan(PI * self.frequency.min(self.sample_rate * 0.49) / self.sample_rate);
+        let a = math::powf(10.0, self.gain_db / 40.0);
+        self.k = match self.filter_type {
+            FilterType::Peaking => 1.0 / (self.q * a),
+            _ => 1.0 / self.q,
+        };
+        self.a1 = 1.0 / (1.0 + g * (g + self.k));
+        self.a2 = g * self.a1;
+        self.a3 = g * self.a2;
+        self.peak_gain = a * a;
+        self.dirty = false;
+    }
+
+    /// Process a single sample through the filter.
+    pub fn process(&mut self, input: f64) -> f64 {
+        if self.dirty {
+            self.update_coefficients();
+        }
+
+        let v3 = input - self.ic2;
+        let band = self.a1 * self.ic1 + self.a2 * v3;
+        let low = self.ic2 + self.a2 * self.ic1 + self.a3 * v3;
+        self.ic1 = 2.0 * band - self.ic1;
+        self.ic2 = 2.0 * low - self.ic2;
+
+        match self.filter_type {
+            FilterType::Lowpass => low,
+            FilterType::Highpass => input - self.k * band - low,
+            // Scaled by k for unity gain at the center, like the biquad
+            FilterType::Bandpass => self.k * band,
+            FilterType::Notch => input - self.k * band,
+            FilterType::Peaking => input + self.k * (self.peak_gain - 1.0) * band,
+        }
+    }
+
+    /// Reset filter state.
+    pub fn reset(&mut self) {
+        self.ic1 = 0.0;
+        self.ic2 = 0.0;
+    }
+
+    /// Set frequency and mark coefficients dirty.
+    pub fn set_frequency(&mut self, freq: f64) {
+        self.frequency = freq;
+        self.dirty = true;
+    }
+}
+
+/// A 6 dB/octave one-pole filter. Band-pass runs a one-pole high-pass
+/// into a one-pole low-pass at the same frequency; notch and peaking
+/// cut or boost that band.
+#[derive(Debug, Clone)]
+pub struct OnePoleFilter {
+    pub filter_type: FilterType,
+    pub frequency: f64,
+    pub gain_db: f64, // only used for Peaking
+
+    coefficient: f64,
+    low: f64,
+    band: f64,
+
+    sample_rate: f64,
+    dirty: bool,
+}
+
+impl OnePoleFilter {
+    pub fn new(filter_type: FilterType, sample_rate: f64) -> Self {
+        let mut f = OnePoleFilter {
+            filter_type,
+            frequency: 1000.0,
+            gain_db: 0.0,
+            coefficient: 0.0,
+            low: 0.0,
+            band: 0.0,
+            sample_rate,
+            dirty: true,
+        };
+        f.update_coefficients();
+        f
+    }
+
+    /// Recompute the pole from the current frequency.
+    pub fn update_coefficients(&mut self) {
+        self.coefficient = math::exp(-2.0 * PI * self.frequency / self.sample_rate);
+        self.dirty = false;
+    }
+
+    /// Process a single sample through the filter.
+    pub fn process(&mut self, input: f64) -> f64 {
+        if self.dirty {
+            self.update_coefficients();
+        }
+
+        let a = self.coefficient;
+        self.low = input + a * (self.low - input);
+        let high = input - self.low;
+        if matches!(self.filter_type, FilterType::Lowpass) {
+            return self.low;
+        }
+        if matches!(self.filter_type, FilterType::Highpass) {
+            return high;
+        }
+        self.band = high + a * (self.band - high);
+        match self.filter_type {
+            FilterType::Notch => input - self.band,
+            FilterType::Peaking => input + (math::powf(10.0, self.gain_db / 20.0) - 1.0) * self.band,
+            _ => self.band,
+        }
+    }
+
+    /// Reset filter state.
+    pub fn reset(&mut self) {
+        self.low = 0.0;
+        self.band = 0.0;
+    }
+
+    /// Set frequency and mark coefficients dirty.
+    pub fn set_frequency(&mut self, freq: f64) {
+        self.frequency = freq;
+        self.dirty = true;
+    }
+}
+
+/// A filter of any topology.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Biquad(BiquadFilter),
+    StateVariable(StateVariableFilter),
+    OnePole(OnePoleFilter),
+}
+
+impl Filter {
+    /// A filter at `frequency` Hz with resonance `q` (ignored by the
+    /// one-pole) and `gain_db` for peaking.
+    pub fn new(
+        topology: FilterTopology,
+        filter_type: FilterType,
+        sample_rate: f64,
+        frequency: f64,
+        q: f64,
+        gain_db: f64,
+    ) -> Self {
+        match topology {
+            FilterTopology::Biquad => {
+                let mut f = BiquadFilter::new(filter_type, sample_rate);
+                (f.frequency, f.q, f.gain_db) = (frequency, q, gain_db);
+                f.update_coefficients();
+                Filter::Biquad(f)
+            }
+            FilterTopology::StateVariable => {
+                let mut f = StateVariableFilter::new(filter_type, sample_rate);
+                (f.frequency, f.q, f.gain_db) = (frequency, q, gain_db);
+                f.update_coefficients();
+                Filter::StateVariable(f)
+            }
+            FilterTopology::OnePole => {
+                let mut f = OnePoleFilter::new(filter_type, sample_rate);
+                (f.frequency, f.gain_db) = (frequency, gain_db);
+                f.update_coefficients();
+                Filter::OnePole(f)
+            }
+        }
+    }
+
+    /// Process a single sample through the filter.
+    pub fn process(&mut self, input: f64) -> f64 {
+        match self {
+            Filter::Biquad(f) => f.process(input),
+            Filter::StateVariable(f) => f.process(input),
+            Filter::OnePole(f) => f.process(input),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -231,4 +474,45 @@ mod tests {
             assert!(out.is_finite(), "Filter output not finite at sample {i}");
         }
     }
+
+    /// Peak amplitude of a sine at `freq` after the filter settles.
+    fn sine_gain(filter: &mut Filter, freq: f64) -> f64 {
+        (0..8820)
+            .map(|i| filter.process((2.0 * PI * freq * i as f64 / 44100.0).sin()))
+            .skip(4410)
+            .fold(0.0_f64, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn topologies_agree_on_pass_and_stop_bands() {
+        for topology in [FilterTopology::Biquad, FilterTopology::StateVariable, FilterTopology::OnePole] {
+            let lowpass = || Filter::new(topology, FilterType::Lowpass, 44100.0, 500.0, 0.707, 0.0);
+            assert!(sine_gain(&mut lowpass(), 50.0) > 0.95, "{topology:?} low-pass should pass 50 Hz");
+            assert!(sine_gain(&mut lowpass(), 10000.0) < 0.1, "{topology:?} low-pass should cut 10 kHz");
+
+            let highpass = || Filter::new(topology, FilterType::Highpass, 44100.0, 500.0, 0.707, 0.0);
+            assert!(sine_gain(&mut highpass(), 50.0) < 0.15, "{topology:?} high-pass should cut 50 Hz");
+            assert!(sine_gain(&mut highpass(), 10000.0) > 0.95, "{topology:?} high-pass should pass 10 kHz");
+
+            let mut notch = Filter::new(topology, FilterType::Notch, 44100.0, 1000.0, 0.707, 0.0);
+            assert!(sine_gain(&mut notch, 1000.0) < 0.6, "{topology:?} notch should cut its center");
+            let mut peak = Filter::new(topology, FilterType::Peaking, 44100.0, 1000.0, 0.707, 6.0);
+            assert!(sine_gain(&mut peak, 1000.0) > 1.4, "{topology:?} peaking should boost its center");
+        }
+    }
+
+    #[test]
+    fn one_pole_is_gentler_than_biquad() {
+        let mut one_pole = Filter::new(FilterTopology::OnePole, FilterType::Lowpass, 44100.0, 500.0, 0.707, 0.0);
+        let mut biquad = Filter::new(FilterTopology::Biquad, FilterType::Lowpass, 44100.0, 500.0, 0.707, 0.0);
+        assert!(sine_gain(&mut one_pole, 4000.0) > 2.0 * sine_gain(&mut biquad, 4000.0));
+    }
+
+    #[test]
+    fn state_variable_resonance_peaks_at_cutoff() {
+        let mut flat = Filter::new(FilterTopology::StateVariable, FilterType::Lowpass, 44100.0, 1000.0, 0.707, 0.0);
+        let mut resonant = Filter::new(FilterTopology::StateVariable, FilterType::Lowpass, 44100.0, 1000.0, 8.0, 0.0);
+        assert!((sine_gain(&mut flat, 1000.0) - 0.707).abs() < 0.05);
+        assert!(sine_gain(&mut resonant, 1000.0) > 6.0);
+    }
 }
@@ -0,0 +1,116 @@
+//! Level metering — peak and RMS per block for the master bus and each track.
+
+use serde::Serialize;
+
+/// Peak and RMS levels of one signal, one entry per metering block.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Levels {
+    pub peak: Vec<f32>,
+    pub rms: Vec<f32>,
+}
+
+/// Levels of one track. `name` is None for notes played at the top level.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TrackLevels {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub levels: Levels,
+}
+
+/// Metering for a whole render.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct MeterData {
+    /// Samples per metering block.
+    pub block_size: usize,
+    pub sample_rate: f64,
+    /// Levels of the final output.
+    pub master: Levels,
+    /// Levels of each track before master gain, clipping and fades.
+    pub tracks: Vec<TrackLevels>,
+}
+
+/// Accumulates peak and RMS levels block by block.
+#[derive(Debug, Clone)]
+pub struct LevelMeter {
+    block_size: usize,
+    peak: Vec<f64>,
+    sum_sq: Vec<f64>,
+}
+
+impl LevelMeter {
+    pub fn new(block_size: usize) -> Self {
+        LevelMeter {
+            block_size: block_size.max(1),
+            peak: Vec::new(),
+            sum_sq: Vec::new(),
+        }
+    }
+
+    /// Add the sample at output offset `index`.
+    pub fn add(&mut self, index: usize, sample: f64) {
+        let block = index / self.block_size;
+        if block >= self.peak.len() {
+            self.peak.resize(block + 1, 0.0);
+            self.sum_sq.resize(block + 1, 0.0);
+        }
+        self.peak[block] = self.peak[block].max(sample.abs());
+        self.sum_sq[block] += sample * sample;
+    }
+
+    /// Measure a whole buffer.
+    pub fn measure(samples: &[f64], block_size: usize) -> Levels {
+        let mut meter = LevelMeter::new(block_size);
+        for (i, &s) in samples.iter().enumerate() {
+            meter.add(i, s);
+        }
+        meter.finish(samples.len())
+    }
+
+    /// Final levels for an output of `total_samples` samples. Blocks that
+    /// never received a sample read as silent.
+    pub fn finish(mut self, total_samples: usize) -> Levels {
+        let blocks = total_samples.div_ceil(self.block_size);
+        self.peak.resize(blocks, 0.0);
+        self.sum_sq.resize(blocks, 0.0);
+        let rms = self
+            .sum_sq
+            .iter()
+            .enumerate()
+            .map(|(b, sum)| {
+                let len = self.block_size.min(total_samples - b * self.block_size);
+                (sum / len as f64).sqrt() as f32
+            })
+            .collect();
+        Levels {
+            peak: self.peak.iter().map(|&p| p as f32).collect(),
+            rms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_peak_and_rms_per_block() {
+        let samples = [0.5, -0.5, 0.5, -0.5, 1.0, 0.0];
+        let levels = LevelMeter::measure(&samples, 4);
+        assert_eq!(levels.peak, vec![0.5, 1.0]);
+        assert!((levels.rms[0] - 0.5).abs() < 1e-6);
+        assert!((levels.rms[1] - (0.5_f32).sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn silent_blocks_are_padded() {
+        let mut meter = LevelMeter::new(2);
+        meter.add(1, 0.25);
+        let levels = meter.finish(6);
+        assert_eq!(levels.peak, vec![0.25, 0.0, 0.0]);
+        assert_eq!(levels.rms.len(), 3);
+    }
+}
@@ -1,5 +1,8 @@
 //! Mixer — Sums multiple voice outputs with master gain.
 
+use alloc::vec::Vec;
+use crate::math;
+
 /// A simple summing mixer that accumulates audio from multiple sources.
 #[derive(Debug, Clone)]
 pub struct Mixer {
@@ -30,10 +33,12 @@ impl Mixer {
 
     /// Get the mixed output buffer, with master gain and soft clipping applied.
     pub fn output(&self) -> Vec<f64> {
-        self.buffer
-            .iter()
-            .map(|&s| soft_clip(s * self.master_gain))
-            .collect()
+        self.buffer.iter().map(|&s| self.process(s)).collect()
+    }
+
+    /// Apply master gain and soft clipping to one mixed sample.
+    pub fn process(&self, sample: f64) -> f64 {
+        soft_clip(sample * self.master_gain)
     }
 
     /// Access the raw buffer length.
@@ -49,7 +54,7 @@ impl Mixer {
 
 /// Soft clipper using tanh to prevent harsh digital clipping.
 fn soft_clip(x: f64) -> f64 {
-    x.tanh()
+    math::tanh(x)
 }
 
 #[cfg(test)]
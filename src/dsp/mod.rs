@@ -3,18 +3,47 @@
 //! All DSP runs in Rust for deterministic, cross-platform audio output.
 //! The same code powers both the WebAudio (via AudioWorklet + WASM) and
 //! the CLI renderer (offline WAV export).
+//!
+//! Without the `std` feature only the building blocks of an oscillator
+//! voice are available; the engine, samplers and effects need `std`.
 
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
 pub mod chorus;
+#[cfg(feature = "std")]
 pub mod composite;
+#[cfg(feature = "std")]
 pub mod compressor;
+#[cfg(feature = "std")]
 pub mod delay;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
 pub mod engine;
 pub mod envelope;
 pub mod filter;
+#[cfg(feature = "std")]
+pub mod meter;
 pub mod mixer;
 pub mod oscillator;
+#[cfg(feature = "std")]
+pub mod plugin;
+#[cfg(feature = "std")]
 pub mod renderer;
+#[cfg(feature = "std")]
 pub mod reverb;
+#[cfg(feature = "std")]
 pub mod sampler;
+#[cfg(feature = "std")]
+pub mod slicer;
+#[cfg(feature = "std")]
+pub mod stretch;
+#[cfg(feature = "std")]
+pub mod tempo;
+pub mod tremolo;
+#[cfg(feature = "std")]
 pub mod tuner;
 pub mod voice;
+#[cfg(feature = "std")]
+pub mod widener;
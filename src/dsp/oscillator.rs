@@ -1,6 +1,7 @@
 //! Anti-aliased oscillators using PolyBLEP.
 
-use std::f64::consts::PI;
+use core::f64::consts::PI;
+use crate::math;
 
 /// Supported waveform shapes.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -11,12 +12,35 @@ pub enum Waveform {
     Triangle,
 }
 
-/// A band-limited oscillator with anti-aliasing (PolyBLEP).
+/// How oscillators treat waveform discontinuities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OscillatorQuality {
+    /// Naive waveforms with hard edges. Aliases at high pitches, which
+    /// gives the "chip" character of old sound hardware.
+    Naive,
+    /// Band-limited waveforms (PolyBLEP edges, PolyBLAMP corners).
+    #[default]
+    BandLimited,
+}
+
+impl OscillatorQuality {
+    /// Parse a quality name: "naive"/"chip" or "bandlimited"/"polyblep".
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "naive" | "chip" => Some(OscillatorQuality::Naive),
+            "bandlimited" | "band-limited" | "polyblep" => Some(OscillatorQuality::BandLimited),
+            _ => None,
+        }
+    }
+}
+
+/// An oscillator, band-limited with PolyBLEP unless set to naive quality.
 #[derive(Debug, Clone)]
 pub struct Oscillator {
     pub waveform: Waveform,
     pub frequency: f64,
     pub detune: f64, // in cents
+    pub quality: OscillatorQuality,
     phase: f64,
     sample_rate: f64,
 }
@@ -27,6 +51,7 @@ impl Oscillator {
             waveform,
             frequency: 440.0,
             detune: 0.0,
+            quality: OscillatorQuality::default(),
             phase: 0.0,
             sample_rate,
         }
@@ -34,7 +59,12 @@ impl Oscillator {
 
     /// Effective frequency accounting for detune (in cents).
     fn effective_freq(&self) -> f64 {
-        self.frequency * (2.0_f64).powf(self.detune / 1200.0)
+        self.frequency * math::powf(2.0, self.detune / 1200.0)
+    }
+
+    /// Output sample rate in Hz.
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
     }
 
     /// Phase increment per sample.
@@ -61,40 +91,46 @@ impl Oscillator {
     }
 
     fn sine(&self) -> f64 {
-        (2.0 * PI * self.phase).sin()
+        math::sin(2.0 * PI * self.phase)
+    }
+
+    fn band_limited(&self) -> bool {
+        self.quality == OscillatorQuality::BandLimited
     }
 
     /// Naive sawtooth: rises from -1 to +1, then drops.
     /// PolyBLEP corrects the discontinuity at the wrap.
     fn sawtooth(&self, inc: f64) -> f64 {
         let naive = 2.0 * self.phase - 1.0;
+        if !self.band_limited() {
+            return naive;
+        }
         naive - poly_blep(self.phase, inc)
     }
 
-    /// Square wave via two sawtooth waves with PolyBLEP.
+    /// Square wave with PolyBLEP corrections at both edges.
     fn square(&self, inc: f64) -> f64 {
         let mut value = if self.phase < 0.5 { 1.0 } else { -1.0 };
+        if !self.band_limited() {
+            return value;
+        }
         value += poly_blep(self.phase, inc);
         value -= poly_blep((self.phase + 0.5) % 1.0, inc);
         value
     }
 
-    /// Triangle wave integrated from a PolyBLEP square wave.
+    /// Triangle: piecewise linear, -1→+1 in [0, 0.5], +1→-1 in [0.5, 1].
+    /// PolyBLAMP rounds off the corners, where the slope changes by ±8.
     fn triangle(&self, inc: f64) -> f64 {
-        // Integrate a square wave to get a triangle
-        // We use the "leaky integrator" approach:
-        // triangle ≈ integrated square * (4 * freq / sr)
-        let sq = self.square(inc);
-        // However, for simplicity and correctness, we use a direct
-        // band-limited triangle via the phase:
-        let _ = sq;
-        // Direct computation: piecewise linear, -1→+1 in [0, 0.5], +1→-1 in [0.5, 1]
         let value = if self.phase < 0.5 {
             4.0 * self.phase - 1.0
         } else {
             3.0 - 4.0 * self.phase
         };
-        value
+        if !self.band_limited() {
+            return value;
+        }
+        value + 4.0 * inc * (poly_blamp(self.phase, inc) - poly_blamp((self.phase + 0.5) % 1.0, inc))
     }
 
     /// Reset oscillator phase.
@@ -122,6 +158,20 @@ fn poly_blep(t: f64, dt: f64) -> f64 {
     }
 }
 
+/// PolyBLAMP (integrated PolyBLEP) correction for a slope discontinuity
+/// at phase 0, scaled for a slope change of 2 per unit phase increment.
+fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = 1.0 - t / dt;
+        t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -192,4 +242,47 @@ mod tests {
             "1200 cents detune should double frequency"
         );
     }
+
+    #[test]
+    fn naive_quality_keeps_hard_edges() {
+        let mut osc = Oscillator::new(Waveform::Square, 44100.0);
+        osc.frequency = 5000.0;
+        osc.quality = OscillatorQuality::Naive;
+        for _ in 0..1000 {
+            let s = osc.next_sample();
+            assert!(s == 1.0 || s == -1.0, "Naive square should be ±1, got {s}");
+        }
+        assert_eq!(OscillatorQuality::parse("chip"), Some(OscillatorQuality::Naive));
+    }
+
+    #[test]
+    fn band_limited_triangle_rounds_corners() {
+        let mut naive = Oscillator::new(Waveform::Triangle, 44100.0);
+        naive.frequency = 4410.0;
+        naive.quality = OscillatorQuality::Naive;
+        let mut smooth = Oscillator::new(Waveform::Triangle, 44100.0);
+        smooth.frequency = 4410.0;
+        // Phase 0 is the corner at -1: the band-limited version sits above it
+        let (a, b) = (naive.next_sample(), smooth.next_sample());
+        assert_eq!(a, -1.0);
+        assert!(b > a, "corner should be rounded: {b}");
+        for _ in 0..1000 {
+            let s = smooth.next_sample();
+            assert!(s.abs() <= 1.0, "Triangle out of range: {s}");
+        }
+    }
+
+    #[test]
+    fn band_limiting_reduces_aliasing() {
+        // A high saw's energy above Nyquist folds back as aliasing, which
+        // shows up as sample-to-sample jumps the band-limited version avoids.
+        fn roughness(quality: OscillatorQuality) -> f64 {
+            let mut osc = Oscillator::new(Waveform::Sawtooth, 44100.0);
+            osc.frequency = 3520.0;
+            osc.quality = quality;
+            let samples: Vec<f64> = (0..4410).map(|_| osc.next_sample()).collect();
+            samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f64::max)
+        }
+        assert!(roughness(OscillatorQuality::BandLimited) < roughness(OscillatorQuality::Naive));
+    }
 }
@@ -0,0 +1,383 @@
+//! Plugin engine — a facade for shipping SongWalker as an instrument
+//! plugin (CLAP, VST3).
+//!
+//! A wrapper crate owns one `PluginEngine` per plugin instance, hands it
+//! the host's loaded presets with `with_registry`, loads the song with
+//! `set_song`, exposes `params()` to the host and calls `process` once per
+//! audio block. The song streams block by block in sync with the host
+//! transport; incoming MIDI notes play the MIDI instrument on top.
+//!
+//! `set_song`, `set_midi_instrument` and `registry_mut` prepare state and
+//! may allocate, so call them off the audio thread. `process` reuses its
+//! buffers; it only allocates when a composite voice starts.
+//!
+//! Limits: streamed songs play their notes only, without master effects.
+//! `set_song` rejects songs with a count-in, song fades or track sends,
+//! and when the transport jumps, notes held at the new position restart
+//! from their attack.
+
+use crate::compiler::{self, InstrumentConfig};
+use crate::dsp::engine::{AudioEngine, LiveVoice, PresetRegistry, SongStream};
+use crate::pitch::midi_to_frequency;
+
+/// Most MIDI notes sounding at once; the oldest is stolen beyond this.
+pub const MAX_MIDI_VOICES: usize = 32;
+
+/// Frames rendered per step of `process`.
+const CHUNK: usize = 128;
+
+/// Parameter id of the song playback level.
+pub const PARAM_SONG_GAIN: u32 = 0;
+/// Parameter id of the live MIDI voice level.
+pub const PARAM_MIDI_GAIN: u32 = 1;
+
+/// A host-automatable parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamInfo {
+    pub id: u32,
+    pub name: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub default: f64,
+}
+
+const PARAMS: [ParamInfo; 2] = [
+    ParamInfo { id: PARAM_SONG_GAIN, name: "Song Level", min: 0.0, max: 2.0, default: 1.0 },
+    ParamInfo { id: PARAM_MIDI_GAIN, name: "MIDI Level", min: 0.0, max: 2.0, default: 0.25 },
+];
+
+/// A MIDI message, timed by its sample offset within the block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiEvent {
+    NoteOn { offset: usize, note: u8, velocity: u8 },
+    NoteOff { offset: usize, note: u8 },
+}
+
+impl MidiEvent {
+    fn offset(&self) -> usize {
+        match *self {
+            MidiEvent::NoteOn { offset, .. } | MidiEvent::NoteOff { offset, .. } => offset,
+        }
+    }
+}
+
+/// Host transport state at the start of a block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Transport {
+    pub playing: bool,
+    /// Song position of the block's first sample.
+    pub sample_position: u64,
+}
+
+/// A SongWalker instrument as seen by a plugin host.
+pub struct PluginEngine {
+    engine: AudioEngine,
+    song: Option<SongStream>,
+    /// Instrument played by incoming MIDI notes.
+    midi_instrument: InstrumentConfig,
+    /// Sounding MIDI notes with the voice playing each.
+    voices: Vec<(u8, LiveVoice)>,
+    song_gain: f64,
+    midi_gain: f64,
+}
+
+impl PluginEngine {
+    pub fn new(sample_rate: f64) -> Self {
+        Self::with_registry(sample_rate, PresetRegistry::new())
+    }
+
+    /// Create an engine that plays presets from `registry`.
+    pub fn with_registry(sample_rate: f64, registry: PresetRegistry) -> Self {
+        PluginEngine {
+            engine: AudioEngine::with_registry(sample_rate, registry),
+            song: None,
+            midi_instrument: InstrumentConfig::default(),
+            voices: Vec::with_capacity(MAX_MIDI_VOICES),
+            song_gain: PARAMS[0].default,
+            midi_gain: PARAMS[1].default,
+        }
+    }
+
+    /// The presets the song and MIDI instrument can play. Register presets
+    /// here before `set_song`.
+    pub fn registry_mut(&mut self) -> &mut PresetRegistry {
+        self.engine.registry_mut()
+    }
+
+    /// Compile `.sw` source and prepare it for playback. Songs with a
+    /// count-in, song fades or track sends are rejected. On error the
+    /// previous song keeps playing.
+    pub fn set_song(&mut self, source: &str) -> Result<(), String> {
+        let program = crate::parse(source).map_err(|e| format!("{e}"))?;
+        let event_list = compiler::compile(&program)?;
+        self.song = Some(self.engine.stream(&event_list)?);
+        Ok(())
+    }
+
+    /// Stop playing the song; MIDI input still sounds.
+    pub fn clear_song(&mut self) {
+        self.song = None;
+    }
+
+    /// Length of the loaded song in samples (0 without one).
+    pub fn song_length(&self) -> usize {
+        self.song.as_ref().map_or(0, SongStream::len)
+    }
+
+    /// Play incoming MIDI notes on `instrument` (an oscillator by default).
+    pub fn set_midi_instrument(&mut self, instrument: InstrumentConfig) {
+        self.midi_instrument = instrument;
+    }
+
+    /// The parameters to expose to the host.
+    pub fn params(&self) -> &'static [ParamInfo] {
+        &PARAMS
+    }
+
+    /// Current value of parameter `id`.
+    pub fn param(&self, id: u32) -> Option<f64> {
+        match id {
+            PARAM_SONG_GAIN => Some(self.song_gain),
+            PARAM_MIDI_GAIN => Some(self.midi_gain),
+            _ => None,
+        }
+    }
+
+    /// Set parameter `id`, clamped to its range.
+    pub fn set_param(&mut self, id: u32, value: f64) -> Result<(), String> {
+        let info = PARAMS.iter().find(|p| p.id == id).ok_or_else(|| format!("Unknown parameter id {id}."))?;
+        let value = value.clamp(info.min, info.max);
+        match id {
+            PARAM_SONG_GAIN => self.song_gain = value,
+            _ => self.midi_gain = value,
+        }
+        Ok(())
+    }
+
+    /// Render one block. `audio_out` holds one slice per output channel,
+    /// all the same length; even channels get the left mix and odd ones
+    /// the right. `midi_in` must be sorted by offset.
+    pub fn process(&mut self, audio_out: &mut [&mut [f32]], midi_in: &[MidiEvent], transport: &Transport) {
+        let frames = audio_out.first().map_or(0, |channel| channel.len());
+        if transport.playing
+            && let Some(song) = &mut self.song
+            && song.position() as u64 != transport.sample_position
+        {
+            song.seek(transport.sample_position as usize);
+        }
+
+        let mut events = midi_in.iter().peekable();
+        let (mut song_left, mut song_right) = ([0.0_f32; CHUNK], [0.0_f32; CHUNK]);
+        let mut chunk_start = 0;
+        while chunk_start < frames {
+            let len = CHUNK.min(frames - chunk_start);
+            let (song_left, song_right) = (&mut song_left[..len], &mut song_right[..len]);
+            match (transport.playing, &mut self.song) {
+                (true, Some(song)) => self.engine.render_stream(song, song_left, song_right),
+                _ => {
+                    song_left.fill(0.0);
+                    song_right.fill(0.0);
+                }
+            }
+            for i in 0..len {
+                let frame = chunk_start + i;
+                while let Some(event) = events.next_if(|e| e.offset() <= frame) {
+                    self.handle_midi(event);
+                }
+                let live: f64 = self.voices.iter_mut().map(|(_, voice)| voice.next_sample()).sum();
+                let left = song_left[i] as f64 * self.song_gain + live * self.midi_gain;
+                let right = song_right[i] as f64 * self.song_gain + live * self.midi_gain;
+                for (c, channel) in audio_out.iter_mut().enumerate() {
+                    channel[frame] = if c % 2 == 0 { left } else { right } as f32;
+                }
+            }
+            chunk_start += len;
+        }
+        // Events past the block end still take effect
+        events.for_each(|event| self.handle_midi(event));
+        self.voices.retain(|(_, voice)| !voice.is_finished());
+    }
+
+    fn handle_midi(&mut self, event: &MidiEvent) {
+        match *event {
+            MidiEvent::NoteOn { note, velocity: 0, .. } | MidiEvent::NoteOff { note, .. } => {
+                for (_, voice) in self.voices.iter_mut().filter(|(n, _)| *n == note) {
+                    voice.note_off();
+                }
+            }
+            MidiEvent::NoteOn { note, velocity, .. } => {
+                if self.voices.len() == MAX_MIDI_VOICES {
+                    self.voices.remove(0);
+                }
+                let frequency = midi_to_frequency(note as i32, self.engine.tuning_pitch);
+                let voice = self.engine.live_voice(&self.midi_instrument, frequency, velocity as f64 / 127.0);
+                self.voices.push((note, voice));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::engine::RegisteredPreset;
+    use crate::dsp::sampler::{LoadedZone, SampleBuffer, Sampler};
+
+    const SONG: &str = "track t() {\n    C4 1\n}\nt();";
+
+    fn run(engine: &mut PluginEngine, frames: usize, midi: &[MidiEvent], transport: Transport) -> Vec<f32> {
+        let (mut left, mut right) = (vec![0.0; frames], vec![0.0; frames]);
+        engine.process(&mut [&mut left, &mut right], midi, &transport);
+        assert_eq!(left, right);
+        left
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |m, s| m.max(s.abs()))
+    }
+
+    /// A registry with "Flat", a sampler playing a constant level.
+    fn flat_registry() -> PresetRegistry {
+        let zone = LoadedZone {
+            key_range_low: 0,
+            key_range_high: 127,
+            root_note: 60,
+            fine_tune_cents: 0.0,
+            sample_rate: 44100,
+            loop_start: None,
+            loop_end: None,
+            exclusive_group: None,
+            buffer: SampleBuffer::new(vec![0.5; 88200], 44100).into(),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
+        };
+        let mut registry = PresetRegistry::new();
+        registry.insert("Flat".to_string(), RegisteredPreset::Sampler(Sampler::new(vec![zone], false)));
+        registry
+    }
+
+    #[test]
+    fn plays_song_with_transport() {
+        let mut engine = PluginEngine::new(44100.0);
+        engine.set_song(SONG).unwrap();
+        assert!(engine.song_length() > 22050);
+
+        let stopped = run(&mut engine, 4096, &[], Transport { playing: false, sample_position: 1024 });
+        assert_eq!(peak(&stopped), 0.0);
+        let playing = run(&mut engine, 4096, &[], Transport { playing: true, sample_position: 1024 });
+        assert!(peak(&playing) > 0.01);
+
+        // Blocks of any size continue the same render
+        let mut whole = PluginEngine::new(44100.0);
+        whole.set_song(SONG).unwrap();
+        let full = run(&mut whole, 2048, &[], Transport { playing: true, sample_position: 0 });
+        let mut pieces = PluginEngine::new(44100.0);
+        pieces.set_song(SONG).unwrap();
+        let mut joined = Vec::new();
+        for (start, len) in [(0, 300), (300, 700), (1000, 1048)] {
+            joined.extend(run(&mut pieces, len, &[], Transport { playing: true, sample_position: start }));
+        }
+        assert_eq!(joined, full);
+
+        assert!(engine.set_song("track t( {").is_err());
+        assert!(engine.song_length() > 0, "A failed load keeps the old song");
+    }
+
+    #[test]
+    fn rejects_songs_a_stream_cannot_play() {
+        let mut engine = PluginEngine::new(44100.0);
+        let error = engine.set_song(&format!("song.countIn = 1;\nsong.fadeOut = 2;\n{SONG}")).unwrap_err();
+        assert_eq!(error, "Streamed songs can't use song.countIn, song.fadeOut.");
+        let sends = "track t() {\n    track.sends = {reverb: 0.5};\n    C4 1\n}\nt();";
+        assert_eq!(engine.set_song(sends).unwrap_err(), "Streamed songs can't use track.sends.");
+        assert_eq!(engine.song_length(), 0);
+    }
+
+    #[test]
+    fn streams_like_an_offline_render() {
+        let source = "track t() {\n    C4 1/2\n    track.instrument = loadPreset(\"Flat\");\n    E4 1/2\n    G4 1\n}\nt();";
+        let mut engine = PluginEngine::with_registry(44100.0, flat_registry());
+        engine.set_song(source).unwrap();
+        let length = engine.song_length();
+        let mut streamed = Vec::new();
+        while streamed.len() < length {
+            let position = streamed.len() as u64;
+            streamed.extend(run(&mut engine, 512, &[], Transport { playing: true, sample_position: position }));
+        }
+
+        let event_list = compiler::compile(&crate::parse(source).unwrap()).unwrap();
+        let offline = AudioEngine::with_registry(44100.0, flat_registry());
+        let (left, _) = offline.render_stereo(&event_list, None);
+        let end = length - 128;
+        assert_eq!(&streamed[..end], &left[..end]);
+        // The preset notes play the sampler, not silence or the oscillator
+        assert!((streamed[22050 + 1000] - left[22050 + 1000]).abs() < 1e-9 && peak(&streamed[22050..]) > 0.1);
+    }
+
+    #[test]
+    fn seeking_restarts_held_notes() {
+        let mut engine = PluginEngine::new(44100.0);
+        engine.set_song(SONG).unwrap();
+        run(&mut engine, 512, &[], Transport { playing: true, sample_position: 0 });
+        // Jump into the held C4, then past its end
+        let held = run(&mut engine, 2048, &[], Transport { playing: true, sample_position: 10_000 });
+        assert!(peak(&held) > 0.01);
+        let after = run(&mut engine, 2048, &[], Transport { playing: true, sample_position: 40_000 });
+        assert_eq!(peak(&after), 0.0);
+    }
+
+    #[test]
+    fn midi_notes_sound_and_release() {
+        let mut engine = PluginEngine::new(44100.0);
+        let stopped = Transport::default();
+        let block = run(&mut engine, 512, &[MidiEvent::NoteOn { offset: 256, note: 69, velocity: 100 }], stopped);
+        assert_eq!(peak(&block[..256]), 0.0);
+        assert!(peak(&block[256..]) > 0.0);
+
+        run(&mut engine, 512, &[MidiEvent::NoteOff { offset: 0, note: 69 }], stopped);
+        for _ in 0..200 {
+            run(&mut engine, 512, &[], stopped);
+        }
+        assert!(engine.voices.is_empty());
+
+        let notes: Vec<MidiEvent> =
+            (0..40).map(|note| MidiEvent::NoteOn { offset: 0, note, velocity: 64 }).collect();
+        run(&mut engine, 64, &notes, stopped);
+        assert_eq!(engine.voices.len(), MAX_MIDI_VOICES);
+    }
+
+    #[test]
+    fn midi_plays_the_midi_instrument() {
+        let mut engine = PluginEngine::with_registry(44100.0, flat_registry());
+        engine.set_param(PARAM_MIDI_GAIN, 1.0).unwrap();
+        engine.set_midi_instrument(InstrumentConfig::SamplerRef(compiler::SamplerRefConfig {
+            name: "Flat".to_string(),
+            ..Default::default()
+        }));
+        let block = run(&mut engine, 1024, &[MidiEvent::NoteOn { offset: 0, note: 60, velocity: 127 }], Transport::default());
+        // The flat sample holds a steady level once the attack is over
+        assert!((block[900] - block[1000]).abs() < 1e-6 && block[1000] > 0.1, "{}", block[1000]);
+    }
+
+    #[test]
+    fn params_are_clamped_and_applied() {
+        let mut engine = PluginEngine::new(44100.0);
+        assert_eq!(engine.params().len(), 2);
+        assert_eq!(engine.param(PARAM_SONG_GAIN), Some(1.0));
+        engine.set_param(PARAM_SONG_GAIN, 5.0).unwrap();
+        assert_eq!(engine.param(PARAM_SONG_GAIN), Some(2.0));
+        assert!(engine.set_param(99, 0.0).is_err());
+
+        engine.set_song(SONG).unwrap();
+        engine.set_param(PARAM_SONG_GAIN, 0.0).unwrap();
+        let silent = run(&mut engine, 4096, &[], Transport { playing: true, sample_position: 0 });
+        assert_eq!(peak(&silent), 0.0);
+
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<PluginEngine>();
+        assert_sync::<PresetRegistry>();
+    }
+}
@@ -66,13 +66,23 @@ mod tests {
                     pitch: "C4".to_string(),
                     velocity: 100.0,
                     gate: 1.0,
-                    instrument: InstrumentConfig::default(),
+                    instrument: 0,
                     source_start: 0,
                     source_end: 0,
+                    glide_from: None,
+                    slide_to: None,
+                    expression: Default::default(),
+                    sends: Default::default(),
+                    modulation: Default::default(),
                 },
             }],
+            instruments: vec![InstrumentConfig::default()],
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let wav = render_wav(&song, 44100);
@@ -96,8 +106,13 @@ mod tests {
     fn wav_size_correct() {
         let song = EventList {
             events: vec![],
+            instruments: Vec::new(),
             total_beats: 1.0,
             end_mode: EndMode::Gate,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
         };
 
         let wav = render_wav(&song, 44100);
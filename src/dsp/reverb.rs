@@ -1,7 +1,10 @@
 //! Reverb effect — Schroeder-style algorithmic reverb.
 //!
 //! Uses parallel comb filters followed by series allpass filters,
-//! based on the classic Schroeder/Moorer reverb design.
+//! based on the classic Schroeder/Moorer reverb design. The wet signal can
+//! be delayed and band-limited before it enters the filters.
+
+use super::filter::{BiquadFilter, FilterType};
 
 /// A comb filter delay line with feedback.
 #[derive(Debug, Clone)]
@@ -91,6 +94,8 @@ impl AllpassFilter {
 const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
 const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
 const STEREO_SPREAD: usize = 23;
+/// Longest pre-delay in milliseconds.
+const MAX_PRE_DELAY_MS: f64 = 500.0;
 
 /// A stereo algorithmic reverb using the Schroeder/Freeverb design.
 #[derive(Debug, Clone)]
@@ -110,6 +115,12 @@ pub struct Reverb {
     pub width: f64,
 
     gain: f32,
+    sample_rate: f64,
+    /// Pre-delay line for the wet signal; empty when there is none.
+    pre_delay: Vec<f32>,
+    pre_delay_pos: usize,
+    low_cut: Option<BiquadFilter>,
+    high_cut: Option<BiquadFilter>,
 }
 
 impl Reverb {
@@ -160,6 +171,11 @@ impl Reverb {
             mix: 0.3,
             width: 1.0,
             gain: 0.015,
+            sample_rate,
+            pre_delay: Vec::new(),
+            pre_delay_pos: 0,
+            low_cut: None,
+            high_cut: None,
         };
         
         reverb.update_parameters();
@@ -176,6 +192,29 @@ impl Reverb {
         r
     }
 
+    /// Delay the start of the reverb by `ms` milliseconds (0 to 500), so
+    /// the dry attack is heard before the room.
+    pub fn set_pre_delay(&mut self, ms: f64) {
+        let samples = (ms.clamp(0.0, MAX_PRE_DELAY_MS) / 1000.0 * self.sample_rate) as usize;
+        self.pre_delay = vec![0.0; samples];
+        self.pre_delay_pos = 0;
+    }
+
+    /// Remove the wet signal below `low_cut` Hz and above `high_cut` Hz
+    /// (0 leaves that side open). The dry signal is not filtered.
+    pub fn set_cuts(&mut self, low_cut: f64, high_cut: f64) {
+        let filter = |filter_type, frequency: f64| {
+            (frequency > 0.0).then(|| {
+                let mut f = BiquadFilter::new(filter_type, self.sample_rate);
+                f.set_frequency(frequency.min(self.sample_rate * 0.49));
+                f.update_coefficients();
+                f
+            })
+        };
+        self.low_cut = filter(FilterType::Highpass, low_cut);
+        self.high_cut = filter(FilterType::Lowpass, high_cut);
+    }
+
     /// Update internal parameters after changing room_size or damping.
     pub fn update_parameters(&mut self) {
         let room_scale = 0.28;
@@ -196,7 +235,20 @@ impl Reverb {
     /// Process a stereo sample pair, returning the processed output.
     #[inline]
     pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
-        let input = (left + right) * self.gain;
+        let mut input = (left + right) * self.gain;
+        if !self.pre_delay.is_empty() {
+            let delayed = self.pre_delay[self.pre_delay_pos];
+            self.pre_delay[self.pre_delay_pos] = input;
+            self.pre_delay_pos = (self.pre_delay_pos + 1) % self.pre_delay.len();
+            input = delayed;
+        }
+        // The reverb is linear, so cutting its input cuts the wet signal
+        if let Some(filter) = &mut self.low_cut {
+            input = filter.process(input as f64) as f32;
+        }
+        if let Some(filter) = &mut self.high_cut {
+            input = filter.process(input as f64) as f32;
+        }
         
         // Sum comb filters in parallel
         let mut out_l = 0.0f32;
@@ -256,6 +308,10 @@ impl Reverb {
         for allpass in &mut self.allpass_r {
             allpass.clear();
         }
+        self.pre_delay.fill(0.0);
+        for filter in self.low_cut.iter_mut().chain(&mut self.high_cut) {
+            filter.reset();
+        }
     }
 }
 
@@ -320,4 +376,40 @@ mod tests {
         // (with room_size 0.3, it should decay relatively quickly)
         assert!(later_max < 0.1, "Reverb should decay over time");
     }
+
+    /// Index of the first wet sample after an impulse.
+    fn first_output(reverb: &mut Reverb) -> usize {
+        reverb.process(1.0, 1.0);
+        (1..44100).find(|_| reverb.process(0.0, 0.0).0.abs() > 1e-9).unwrap()
+    }
+
+    #[test]
+    fn test_reverb_pre_delay() {
+        let plain = first_output(&mut Reverb::with_params(44100.0, 0.5, 0.5, 1.0));
+        let mut delayed = Reverb::with_params(44100.0, 0.5, 0.5, 1.0);
+        delayed.set_pre_delay(100.0);
+        assert_eq!(first_output(&mut delayed), plain + 4410);
+    }
+
+    #[test]
+    fn test_reverb_cuts_only_the_wet_signal() {
+        let wet_energy = |low_cut: f64, high_cut: f64, freq: f64| {
+            let mut reverb = Reverb::with_params(44100.0, 0.5, 0.0, 1.0);
+            reverb.set_cuts(low_cut, high_cut);
+            (0..22050)
+                .map(|i| {
+                    let s = (2.0 * std::f64::consts::PI * freq * i as f64 / 44100.0).sin() as f32;
+                    reverb.process(s, s).0.powi(2)
+                })
+                .sum::<f32>()
+        };
+        assert!(wet_energy(300.0, 0.0, 50.0) < wet_energy(0.0, 0.0, 50.0) * 0.1, "Low cut should remove the bass");
+        assert!(wet_energy(0.0, 1000.0, 8000.0) < wet_energy(0.0, 0.0, 8000.0) * 0.1, "High cut should remove the highs");
+        assert!(wet_energy(300.0, 1000.0, 550.0) > wet_energy(0.0, 0.0, 550.0) * 0.3);
+
+        // The dry signal is untouched
+        let mut reverb = Reverb::with_params(44100.0, 0.5, 0.5, 0.0);
+        reverb.set_cuts(300.0, 1000.0);
+        assert_eq!(reverb.process(0.5, -0.5), (0.5, -0.5));
+    }
 }
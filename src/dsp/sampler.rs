@@ -1,34 +1,90 @@
 //! Sample-based synthesis engine.
 //!
 //! Plays back audio samples with pitch-shifting via linear interpolation
-//! resampling. Supports multi-zone key splits, loop points, and
-//! tuning-aware playback rate calculation.
+//! resampling. Supports multi-zone key splits, loop points, release
+//! (note-off) samples, and tuning-aware playback rate calculation.
+//!
+//! Zone audio is held as f32 to halve memory use compared to f64; samples
+//! are widened to f64 only when read by a voice.
+
+use std::sync::Arc;
 
-use crate::preset::{sample_playback_rate, SampleZone};
+use serde::{Deserialize, Serialize};
+
+use super::filter::{BiquadFilter, FilterType};
+use super::voice::vibrato_ratio;
+use super::envelope::curve_progress;
+use crate::compiler::{EnvelopeConfig, EnvelopeCurve};
+use crate::math;
+use crate::preset::{sample_playback_rate, KeyTracking, SampleZone};
 
 /// A single sample buffer loaded into memory.
 #[derive(Debug, Clone)]
 pub struct SampleBuffer {
-    /// Mono f64 samples.
-    pub data: Vec<f64>,
+    /// Mono f32 samples.
+    pub data: Vec<f32>,
     /// Native sample rate of the audio.
     pub sample_rate: u32,
 }
 
 impl SampleBuffer {
-    pub fn new(data: Vec<f64>, sample_rate: u32) -> Self {
+    pub fn new(data: Vec<f32>, sample_rate: u32) -> Self {
         SampleBuffer { data, sample_rate }
     }
 
     /// Create from 16-bit signed PCM data.
     pub fn from_i16(pcm: &[i16], sample_rate: u32) -> Self {
-        let data: Vec<f64> = pcm.iter().map(|&s| s as f64 / 32768.0).collect();
+        let data: Vec<f32> = pcm.iter().map(|&s| s as f32 / 32768.0).collect();
+        SampleBuffer { data, sample_rate }
+    }
+
+    /// Create from big-endian 16-bit signed PCM bytes (e.g. AIFF). A
+    /// trailing partial sample is ignored.
+    pub fn from_i16_be(bytes: &[u8], sample_rate: u32) -> Self {
+        let data = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect();
+        SampleBuffer { data, sample_rate }
+    }
+
+    /// Create from little-endian 24-bit signed PCM bytes (WAV). A
+    /// trailing partial sample is ignored.
+    pub fn from_i24_le(bytes: &[u8], sample_rate: u32) -> Self {
+        let data = bytes
+            .chunks_exact(3)
+            .map(|b| i24_to_f32([b[0], b[1], b[2]]))
+            .collect();
+        SampleBuffer { data, sample_rate }
+    }
+
+    /// Create from big-endian 24-bit signed PCM bytes (AIFF). A trailing
+    /// partial sample is ignored.
+    pub fn from_i24_be(bytes: &[u8], sample_rate: u32) -> Self {
+        let data = bytes
+            .chunks_exact(3)
+            .map(|b| i24_to_f32([b[2], b[1], b[0]]))
+            .collect();
+        SampleBuffer { data, sample_rate }
+    }
+
+    /// Create from 8-bit unsigned PCM (WAV), centred on 128.
+    pub fn from_u8(bytes: &[u8], sample_rate: u32) -> Self {
+        let data = bytes.iter().map(|&s| (s as f32 - 128.0) / 128.0).collect();
         SampleBuffer { data, sample_rate }
     }
 
     /// Create from f32 samples.
     pub fn from_f32(samples: &[f32], sample_rate: u32) -> Self {
-        let data: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+        SampleBuffer {
+            data: samples.to_vec(),
+            sample_rate,
+        }
+    }
+
+    /// Create from f64 samples, narrowing to the f32 storage format.
+    pub fn from_f64(samples: &[f64], sample_rate: u32) -> Self {
+        let data: Vec<f32> = samples.iter().map(|&s| s as f32).collect();
         SampleBuffer { data, sample_rate }
     }
 
@@ -40,6 +96,11 @@ impl SampleBuffer {
         self.data.is_empty()
     }
 
+    /// Approximate heap memory held by the sample data, in bytes.
+    pub fn memory_bytes(&self) -> usize {
+        self.data.len() * std::mem::size_of::<f32>()
+    }
+
     /// Read a sample with linear interpolation at a fractional position.
     pub fn read_interpolated(&self, position: f64) -> f64 {
         if self.data.is_empty() || position < 0.0 {
@@ -49,18 +110,86 @@ impl SampleBuffer {
         let idx = position as usize;
         if idx >= self.data.len() - 1 {
             return if idx < self.data.len() {
-                self.data[idx]
+                self.data[idx] as f64
             } else {
                 0.0
             };
         }
 
         let frac = position - idx as f64;
-        self.data[idx] * (1.0 - frac) + self.data[idx + 1] * frac
+        self.data[idx] as f64 * (1.0 - frac) + self.data[idx + 1] as f64 * frac
     }
+
+    /// Subtract the mean so the sample is centred on zero.
+    pub fn remove_dc(&mut self) {
+        if self.data.is_empty() {
+            return;
+        }
+        let mean = self.data.iter().map(|&s| s as f64).sum::<f64>() / self.data.len() as f64;
+        for s in &mut self.data {
+            *s -= mean as f32;
+        }
+    }
+
+    /// Largest absolute sample value.
+    pub fn peak(&self) -> f32 {
+        self.data.iter().fold(0.0, |m, s| m.max(s.abs()))
+    }
+
+    /// Root-mean-square level of the samples.
+    pub fn rms(&self) -> f32 {
+        if self.data.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.data.iter().map(|&s| s as f64 * s as f64).sum();
+        (sum / self.data.len() as f64).sqrt() as f32
+    }
+
+    /// Multiply every sample by `gain`.
+    pub fn apply_gain(&mut self, gain: f32) {
+        for s in &mut self.data {
+            *s *= gain;
+        }
+    }
+
+    /// Fade in the first `fade_in` samples and out the last `fade_out`,
+    /// linearly, to remove clicks at badly trimmed edges.
+    pub fn fade_edges(&mut self, fade_in: usize, fade_out: usize) {
+        let len = self.data.len();
+        for i in 0..fade_in.min(len) {
+            self.data[i] *= i as f32 / fade_in as f32;
+        }
+        for i in 0..fade_out.min(len) {
+            self.data[len - 1 - i] *= i as f32 / fade_out as f32;
+        }
+    }
+}
+
+/// Convert a little-endian 24-bit signed sample to -1.0..1.0.
+fn i24_to_f32(bytes: [u8; 3]) -> f32 {
+    // Sign-extend by placing the sample in the top bytes of an i32
+    let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+    value as f32 / 8_388_608.0
+}
+
+/// Clean-up applied to zone audio when a preset is registered. Everything
+/// is off by default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
+pub struct SamplePreprocess {
+    /// Remove any DC offset.
+    pub remove_dc: bool,
+    /// Scale each zone so its peak reaches this level (e.g. 0.9).
+    pub normalize_peak: Option<f32>,
+    /// Length in samples of the fades applied at the buffer edges.
+    pub edge_fade_samples: usize,
 }
 
 /// A loaded zone: metadata + its audio buffer.
+///
+/// The buffer is reference-counted so cloning a zone (or spawning a voice
+/// from it) never copies the sample data.
 #[derive(Debug, Clone)]
 pub struct LoadedZone {
     pub key_range_low: u8,
@@ -70,12 +199,23 @@ pub struct LoadedZone {
     pub sample_rate: u32,
     pub loop_start: Option<u64>,
     pub loop_end: Option<u64>,
-    pub buffer: SampleBuffer,
+    /// Exclusive (choke) group shared with other zones of the same preset.
+    pub exclusive_group: Option<u32>,
+    pub buffer: Arc<SampleBuffer>,
+    /// Optional short sample played on note-off (e.g. a harpsichord jack
+    /// falling back), mixed with the decaying main sample.
+    pub release_buffer: Option<Arc<SampleBuffer>>,
+    /// Level offset in dB.
+    pub gain: f64,
+    /// Stereo position from -1 (left) to 1 (right), added to the note pan.
+    pub pan: f64,
+    /// Level and lowpass scaling by key distance from the root note.
+    pub key_tracking: Option<KeyTracking>,
 }
 
 impl LoadedZone {
     /// Create from a SampleZone descriptor and a sample buffer.
-    pub fn from_zone(zone: &SampleZone, buffer: SampleBuffer) -> Self {
+    pub fn from_zone(zone: &SampleZone, buffer: impl Into<Arc<SampleBuffer>>) -> Self {
         LoadedZone {
             key_range_low: zone.key_range.low,
             key_range_high: zone.key_range.high,
@@ -84,17 +224,106 @@ impl LoadedZone {
             sample_rate: zone.sample_rate,
             loop_start: zone.r#loop.as_ref().map(|l| l.start),
             loop_end: zone.r#loop.as_ref().map(|l| l.end),
-            buffer,
+            exclusive_group: zone.exclusive_group,
+            buffer: buffer.into(),
+            release_buffer: None,
+            gain: zone.gain.unwrap_or(0.0),
+            pan: zone.pan.unwrap_or(0.0),
+            key_tracking: zone.key_tracking,
         }
     }
 
+    /// Attach a note-off sample to this zone.
+    pub fn with_release_buffer(mut self, buffer: impl Into<Arc<SampleBuffer>>) -> Self {
+        self.release_buffer = Some(buffer.into());
+        self
+    }
+
+    /// Approximate heap memory held by this zone's audio, in bytes.
+    pub fn memory_bytes(&self) -> usize {
+        self.buffer.memory_bytes()
+            + self.release_buffer.as_ref().map_or(0, |b| b.memory_bytes())
+    }
+
     /// Check if a MIDI note falls within this zone's key range.
     pub fn contains_note(&self, midi_note: u8) -> bool {
         midi_note >= self.key_range_low && midi_note <= self.key_range_high
     }
+
+    /// Apply `options` to this zone's audio. The release sample gets the
+    /// same gain as the main sample, keeping their balance. Edges inside
+    /// the loop are not faded.
+    pub fn preprocess(&mut self, options: &SamplePreprocess) {
+        let buffer = Arc::make_mut(&mut self.buffer);
+        if options.remove_dc {
+            buffer.remove_dc();
+        }
+        let gain = match options.normalize_peak {
+            Some(target) if buffer.peak() > 0.0 => target / buffer.peak(),
+            _ => 1.0,
+        };
+        buffer.apply_gain(gain);
+        let fade = options.edge_fade_samples;
+        let len = buffer.len() as u64;
+        let fade_in = match self.loop_start {
+            Some(start) if start < fade as u64 => 0,
+            _ => fade,
+        };
+        let fade_out = match self.loop_end {
+            Some(end) if end + fade as u64 > len => 0,
+            _ => fade,
+        };
+        buffer.fade_edges(fade_in, fade_out);
+
+        if let Some(release) = &mut self.release_buffer {
+            let release = Arc::make_mut(release);
+            if options.remove_dc {
+                release.remove_dc();
+            }
+            release.apply_gain(gain);
+            release.fade_edges(fade, fade);
+        }
+    }
+
+    /// Check that the loop satisfies `loop_start < loop_end <= buffer
+    /// length` (the end is exclusive) and repair it if not: an end past
+    /// the buffer is clamped, and a loop that is still empty, inverted or
+    /// missing one point is removed. Returns what was repaired.
+    pub fn repair_loop(&mut self) -> Option<String> {
+        let len = self.buffer.len() as u64;
+        match (self.loop_start, self.loop_end) {
+            (None, None) => None,
+            (Some(start), Some(end)) if start < end && end <= len => None,
+            (Some(start), Some(end)) if start < len.min(end) => {
+                self.loop_end = Some(len);
+                Some(format!("Loop end {end} is past the buffer end {len}; clamped to {len}."))
+            }
+            (start, end) => {
+                self.loop_start = None;
+                self.loop_end = None;
+                let show = |p: Option<u64>| p.map_or("none".to_string(), |p| p.to_string());
+                Some(format!(
+                    "Loop {}..{} does not fit a {len}-sample buffer; looping disabled.",
+                    show(start),
+                    show(end)
+                ))
+            }
+        }
+    }
+}
+
+/// A zone whose loop points were repaired when building a `Sampler`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopWarning {
+    /// Index of the zone in `Sampler::zones`.
+    pub zone_index: usize,
+    pub message: String,
 }
 
 /// A sampler instrument with loaded zone data.
+///
+/// Drum kits play every zone at its recorded pitch: the note only selects
+/// the zone and is never used to repitch the sample.
 #[derive(Debug, Clone)]
 pub struct Sampler {
     pub zones: Vec<LoadedZone>,
@@ -102,8 +331,35 @@ pub struct Sampler {
 }
 
 impl Sampler {
+    /// Create a sampler, repairing bad loop points (see
+    /// `LoadedZone::repair_loop`).
     pub fn new(zones: Vec<LoadedZone>, is_drum_kit: bool) -> Self {
-        Sampler { zones, is_drum_kit }
+        Self::with_loop_warnings(zones, is_drum_kit).0
+    }
+
+    /// Like `new`, also returning the zones whose loops were repaired,
+    /// e.g. to report bad loop metadata from converted SoundFonts.
+    pub fn with_loop_warnings(mut zones: Vec<LoadedZone>, is_drum_kit: bool) -> (Self, Vec<LoopWarning>) {
+        let warnings = zones
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(zone_index, zone)| {
+                zone.repair_loop().map(|message| LoopWarning { zone_index, message })
+            })
+            .collect();
+        (Sampler { zones, is_drum_kit }, warnings)
+    }
+
+    /// Approximate heap memory held by all zone buffers, in bytes.
+    pub fn memory_bytes(&self) -> usize {
+        self.zones.iter().map(|z| z.memory_bytes()).sum()
+    }
+
+    /// Apply `options` to every zone (see `LoadedZone::preprocess`).
+    pub fn preprocess(&mut self, options: &SamplePreprocess) {
+        for zone in &mut self.zones {
+            zone.preprocess(options);
+        }
     }
 
     /// Find the best zone for a given MIDI note.
@@ -112,6 +368,22 @@ impl Sampler {
             .iter()
             .find(|z| z.contains_note(midi_note))
     }
+
+    /// Start a voice for a MIDI note, or `None` if no zone covers it.
+    pub fn start_voice(
+        &self,
+        midi_note: u8,
+        velocity: f64,
+        tuning_pitch: f64,
+        engine_sample_rate: f64,
+    ) -> Option<SamplerVoice> {
+        let zone = self.find_zone(midi_note)?;
+        if self.is_drum_kit {
+            Some(SamplerVoice::new_unpitched(zone, velocity, engine_sample_rate))
+        } else {
+            Some(SamplerVoice::new(zone, midi_note, velocity, tuning_pitch, engine_sample_rate))
+        }
+    }
 }
 
 /// A playing sampler voice — reads from a zone buffer at a calculated rate.
@@ -127,32 +399,70 @@ pub struct SamplerVoice {
     loop_start: Option<u64>,
     /// Loop end in samples.
     loop_end: Option<u64>,
-    /// Velocity (0.0 - 1.0).
+    /// Output gain: velocity (0.0 - 1.0) times any zone and key-tracking
+    /// gain.
     velocity: f64,
+    /// Stereo position of the zone (-1 to 1).
+    pan: f64,
     /// Reference to the zone's buffer length.
     buffer_len: usize,
     /// Whether the voice has finished playing.
     finished: bool,
     /// Whether the note has been released.
     released: bool,
+    /// Exclusive group of the zone this voice plays.
+    exclusive_group: Option<u32>,
     /// The release sample offset (set by the engine).
     pub release_sample: usize,
     /// Simple envelope state.
     envelope: SamplerEnvelope,
-    /// Reference data (clone of the buffer for self-contained voice).
-    buffer: SampleBuffer,
+    /// Shared handle to the zone's sample data.
+    buffer: Arc<SampleBuffer>,
+    /// Whether the main sample has ended (or its envelope has closed).
+    main_finished: bool,
+    /// Note-off sample still to be played. Dropped once it has finished.
+    release_buffer: Option<Arc<SampleBuffer>>,
+    /// Read position in the note-off sample, set when it is triggered.
+    release_position: Option<f64>,
+    /// Sample rate ratio for the note-off sample (its rate / engine rate).
+    release_sample_rate_ratio: f64,
+    /// Key-tracked lowpass filter, if the zone configures one.
+    filter: Option<BiquadFilter>,
+    /// Playback rate the current glide ends on.
+    glide_target: f64,
+    /// Per-sample playback rate ratio while gliding.
+    glide_ratio: f64,
+    /// Samples left in the current glide.
+    glide_remaining: usize,
+    /// Vibrato LFO phase increment per engine sample.
+    vibrato_step: f64,
+    /// Vibrato depth in semitones (0 = off).
+    vibrato_depth: f64,
+    /// Vibrato LFO phase (0.0 - 1.0).
+    vibrato_phase: f64,
+    /// Engine sample rate, for the vibrato LFO.
+    engine_sample_rate: f64,
 }
 
-/// Simple ADSR envelope for sampler voices.
+/// Fade time in seconds used when a voice is choked by its exclusive group.
+const CHOKE_RELEASE: f64 = 0.005;
+
+/// Simple AHDSR envelope for sampler voices.
 #[derive(Debug, Clone)]
 struct SamplerEnvelope {
     attack: f64,
+    hold: f64,
     decay: f64,
     sustain: f64,
     release: f64,
+    attack_curve: EnvelopeCurve,
+    decay_curve: EnvelopeCurve,
+    release_curve: EnvelopeCurve,
     sample_rate: f64,
     state: EnvState,
     level: f64,
+    /// Level at note-off, where the release ramp starts.
+    release_level: f64,
     samples_in_state: usize,
 }
 
@@ -160,6 +470,7 @@ struct SamplerEnvelope {
 enum EnvState {
     Idle,
     Attack,
+    Hold,
     Decay,
     Sustain,
     Release,
@@ -170,12 +481,17 @@ impl SamplerEnvelope {
     fn new(sample_rate: f64) -> Self {
         SamplerEnvelope {
             attack: 0.005,  // 5ms click-free attack
+            hold: 0.0,
             decay: 0.1,
             sustain: 1.0,   // Samplers typically use full sustain
             release: 0.1,   // Short release for samples
+            attack_curve: EnvelopeCurve::Linear,
+            decay_curve: EnvelopeCurve::Linear,
+            release_curve: EnvelopeCurve::Linear,
             sample_rate,
             state: EnvState::Idle,
             level: 0.0,
+            release_level: 0.0,
             samples_in_state: 0,
         }
     }
@@ -189,6 +505,7 @@ impl SamplerEnvelope {
         if self.state != EnvState::Done && self.state != EnvState::Idle {
             self.state = EnvState::Release;
             self.samples_in_state = 0;
+            self.release_level = self.level;
         }
     }
 
@@ -199,11 +516,19 @@ impl SamplerEnvelope {
             EnvState::Attack => {
                 let attack_samples = (self.attack * self.sample_rate) as usize;
                 if attack_samples == 0 || self.samples_in_state >= attack_samples {
-                    self.state = EnvState::Decay;
+                    self.state = if self.hold > 0.0 { EnvState::Hold } else { EnvState::Decay };
                     self.samples_in_state = 0;
                     self.level = 1.0;
                 } else {
-                    self.level = self.samples_in_state as f64 / attack_samples as f64;
+                    let t = self.samples_in_state as f64 / attack_samples as f64;
+                    self.level = curve_progress(self.attack_curve, t, true);
+                }
+                self.level
+            }
+            EnvState::Hold => {
+                if self.samples_in_state >= (self.hold * self.sample_rate) as usize {
+                    self.state = EnvState::Decay;
+                    self.samples_in_state = 0;
                 }
                 self.level
             }
@@ -215,7 +540,7 @@ impl SamplerEnvelope {
                     self.level = self.sustain;
                 } else {
                     let t = self.samples_in_state as f64 / decay_samples as f64;
-                    self.level = 1.0 - t * (1.0 - self.sustain);
+                    self.level = 1.0 - curve_progress(self.decay_curve, t, false) * (1.0 - self.sustain);
                 }
                 self.level
             }
@@ -230,7 +555,7 @@ impl SamplerEnvelope {
                     self.level = 0.0;
                 } else {
                     let t = self.samples_in_state as f64 / release_samples as f64;
-                    self.level = self.sustain * (1.0 - t);
+                    self.level = self.release_level * (1.0 - curve_progress(self.release_curve, t, false));
                 }
                 self.level
             }
@@ -273,28 +598,147 @@ impl SamplerVoice {
         let mut envelope = SamplerEnvelope::new(engine_sample_rate);
         envelope.note_on();
 
+        // Key tracking: scale level and filter cutoff by distance from root
+        let semitones = midi_note as f64 - zone.root_note as f64;
+        let key_gain = zone.key_tracking.map_or(1.0, |kt| kt.gain(semitones));
+        let zone_gain = math::powf(10.0, zone.gain / 20.0);
+        let filter = zone
+            .key_tracking
+            .and_then(|kt| kt.cutoff(semitones))
+            .map(|cutoff| {
+                let mut f = BiquadFilter::new(FilterType::Lowpass, engine_sample_rate);
+                f.set_frequency(cutoff.clamp(20.0, engine_sample_rate * 0.45));
+                f
+            });
+
         SamplerVoice {
             position: 0.0,
             playback_rate: pitch_rate,
             sample_rate_ratio: sr_ratio,
             loop_start: zone.loop_start,
             loop_end: zone.loop_end,
-            velocity,
+            velocity: velocity * key_gain * zone_gain,
+            pan: zone.pan.clamp(-1.0, 1.0),
             buffer_len: zone.buffer.len(),
             finished: false,
             released: false,
+            exclusive_group: zone.exclusive_group,
             release_sample: usize::MAX,
             envelope,
-            buffer: zone.buffer.clone(),
+            buffer: Arc::clone(&zone.buffer),
+            main_finished: false,
+            release_buffer: zone.release_buffer.clone(),
+            release_position: None,
+            release_sample_rate_ratio: zone
+                .release_buffer
+                .as_ref()
+                .map_or(sr_ratio, |b| b.sample_rate as f64 / engine_sample_rate),
+            filter,
+            glide_target: pitch_rate,
+            glide_ratio: 1.0,
+            glide_remaining: 0,
+            vibrato_step: 0.0,
+            vibrato_depth: 0.0,
+            vibrato_phase: 0.0,
+            engine_sample_rate,
+        }
+    }
+
+    /// Create a voice that plays the zone at its recorded pitch, ignoring
+    /// the note and tuning. Only the sample rate is converted.
+    pub fn new_unpitched(zone: &LoadedZone, velocity: f64, engine_sample_rate: f64) -> Self {
+        let mut voice = Self::new(zone, zone.root_note, velocity, 440.0, engine_sample_rate);
+        voice.playback_rate = 1.0;
+        voice.glide_target = 1.0;
+        voice
+    }
+
+    /// Glide the playback rate by `ratio` (a pitch ratio) over `samples`
+    /// samples.
+    pub fn glide_rate(&mut self, ratio: f64, samples: usize) {
+        self.glide_target *= ratio;
+        if samples == 0 {
+            self.playback_rate = self.glide_target;
+            self.glide_remaining = 0;
+        } else {
+            self.glide_ratio = math::powf(self.glide_target / self.playback_rate, 1.0 / samples as f64);
+            self.glide_remaining = samples;
         }
     }
 
+    /// Override the envelope stages the config sets.
+    pub fn apply_envelope(&mut self, config: &EnvelopeConfig) {
+        if let Some(a) = config.attack {
+            self.envelope.attack = a;
+        }
+        if let Some(d) = config.decay {
+            self.envelope.decay = d;
+        }
+        if let Some(s) = config.sustain {
+            self.envelope.sustain = s;
+        }
+        if let Some(r) = config.release {
+            self.envelope.release = r;
+        }
+        if let Some(h) = config.hold {
+            self.envelope.hold = h;
+        }
+        if let Some(curve) = config.attack_curve {
+            self.envelope.attack_curve = curve;
+        }
+        if let Some(curve) = config.decay_curve {
+            self.envelope.decay_curve = curve;
+        }
+        if let Some(curve) = config.release_curve {
+            self.envelope.release_curve = curve;
+        }
+    }
+
+    /// Modulate the playback rate with a sine LFO of `rate` Hz and
+    /// `depth` semitones.
+    pub fn set_vibrato(&mut self, rate: f64, depth: f64) {
+        self.vibrato_step = rate / self.engine_sample_rate;
+        self.vibrato_depth = depth;
+    }
+
     /// Generate the next audio sample.
     pub fn next_sample(&mut self) -> f64 {
         if self.finished {
             return 0.0;
         }
 
+        if self.glide_remaining > 0 {
+            self.glide_remaining -= 1;
+            self.playback_rate = if self.glide_remaining == 0 {
+                self.glide_target
+            } else {
+                self.playback_rate * self.glide_ratio
+            };
+        }
+
+        let base_rate = self.playback_rate;
+        if self.vibrato_depth > 0.0 {
+            self.playback_rate *= vibrato_ratio(self.vibrato_phase, self.vibrato_depth);
+            self.vibrato_phase = (self.vibrato_phase + self.vibrato_step).fract();
+        }
+        let main = if self.main_finished {
+            0.0
+        } else {
+            self.next_main_sample()
+        };
+        let tail = self.next_release_sample();
+        self.playback_rate = base_rate;
+
+        // A pending note-off sample keeps the voice alive until released
+        self.finished = self.main_finished && self.release_buffer.is_none();
+        match &mut self.filter {
+            Some(filter) => filter.process(main + tail),
+            None => main + tail,
+        }
+    }
+
+    /// Next sample of the main (looping, enveloped) zone buffer.
+    fn next_main_sample(&mut self) -> f64 {
         // Read from buffer with interpolation
         let sample = self.buffer.read_interpolated(self.position);
 
@@ -314,25 +758,63 @@ impl SamplerVoice {
 
         // Check if past end of buffer
         if self.position >= self.buffer_len as f64 {
-            self.finished = true;
+            self.main_finished = true;
             return 0.0;
         }
 
         // Apply envelope and velocity
         let env = self.envelope.next_sample();
         if self.envelope.is_done() {
-            self.finished = true;
+            self.main_finished = true;
         }
 
         sample * env * self.velocity
     }
 
-    /// Trigger note release.
+    /// Next sample of the note-off buffer, once triggered. Played at the
+    /// same pitch as the main sample, without looping or envelope.
+    fn next_release_sample(&mut self) -> f64 {
+        let (Some(buffer), Some(position)) = (&self.release_buffer, self.release_position) else {
+            return 0.0;
+        };
+        if position >= buffer.len() as f64 {
+            self.release_buffer = None;
+            self.release_position = None;
+            return 0.0;
+        }
+        let sample = buffer.read_interpolated(position);
+        self.release_position = Some(position + self.playback_rate * self.release_sample_rate_ratio);
+        sample * self.velocity
+    }
+
+    /// Trigger note release (and the note-off sample, if any).
     pub fn note_off(&mut self) {
+        if !self.released && self.release_buffer.is_some() {
+            self.release_position = Some(0.0);
+        }
         self.released = true;
         self.envelope.note_off();
     }
 
+    /// Cut the voice off with a very short fade (exclusive-group choke).
+    /// A choked voice does not play its note-off sample.
+    pub fn choke(&mut self) {
+        self.release_buffer = None;
+        self.release_position = None;
+        self.envelope.release = CHOKE_RELEASE;
+        self.note_off();
+    }
+
+    /// Stereo position of the zone this voice is playing.
+    pub fn pan(&self) -> f64 {
+        self.pan
+    }
+
+    /// The exclusive group of the zone this voice is playing, if any.
+    pub fn exclusive_group(&self) -> Option<u32> {
+        self.exclusive_group
+    }
+
     /// Check if this voice has finished playing.
     pub fn is_finished(&self) -> bool {
         self.finished
@@ -357,7 +839,7 @@ mod tests {
             })
             .collect();
 
-        SampleBuffer::new(data, sample_rate)
+        SampleBuffer::from_f64(&data, sample_rate)
     }
 
     fn make_test_zone() -> LoadedZone {
@@ -369,7 +851,12 @@ mod tests {
             sample_rate: 44100,
             loop_start: None,
             loop_end: None,
-            buffer: make_test_buffer(),
+            exclusive_group: None,
+            buffer: Arc::new(make_test_buffer()),
+            release_buffer: None,
+            gain: 0.0,
+            pan: 0.0,
+            key_tracking: None,
         }
     }
 
@@ -394,6 +881,16 @@ mod tests {
         assert!((buf.data[2] + 0.5).abs() < 0.01);
     }
 
+    #[test]
+    fn sample_buffer_memory_is_f32() {
+        let buf = SampleBuffer::from_f64(&[0.25; 1000], 44100);
+        assert_eq!(buf.memory_bytes(), 4000);
+        assert!((buf.read_interpolated(10.0) - 0.25).abs() < 1e-6);
+
+        let sampler = Sampler::new(vec![make_test_zone(), make_test_zone()], false);
+        assert_eq!(sampler.memory_bytes(), 2 * 44100 * 4);
+    }
+
     #[test]
     fn zone_contains_note() {
         let zone = make_test_zone();
@@ -437,6 +934,21 @@ mod tests {
         assert!(max_val > 0.1, "Voice should produce audible output, max={max_val}");
     }
 
+    #[test]
+    fn sampler_voices_share_zone_buffer() {
+        let zone = make_test_zone();
+        let voices: Vec<SamplerVoice> = (0..8)
+            .map(|_| SamplerVoice::new(&zone, 69, 1.0, 440.0, 44100.0))
+            .collect();
+
+        // Zone + 8 voices all point at the same sample data
+        assert_eq!(Arc::strong_count(&zone.buffer), 9);
+        assert!(voices.iter().all(|v| Arc::ptr_eq(&v.buffer, &zone.buffer)));
+
+        drop(voices);
+        assert_eq!(Arc::strong_count(&zone.buffer), 1);
+    }
+
     #[test]
     fn sampler_voice_at_root_pitch() {
         // Playing A4 on a sample recorded at A4 should play at rate ~1.0
@@ -474,11 +986,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn drum_kit_plays_at_native_rate() {
+        let sampler = Sampler::new(vec![make_test_zone()], true);
+        // Any key, any tuning: a drum hit must not be repitched
+        let mut voice = sampler.start_voice(81, 1.0, 432.0, 44100.0).unwrap();
+        for _ in 0..100 {
+            voice.next_sample();
+        }
+        assert!(
+            (voice.position - 100.0).abs() < 1e-9,
+            "Drum kit voice should advance at rate 1.0, got {}",
+            voice.position
+        );
+
+        // The same zone in a melodic sampler is repitched
+        let melodic = Sampler::new(vec![make_test_zone()], false);
+        let voice = melodic.start_voice(81, 1.0, 440.0, 44100.0).unwrap();
+        assert!((voice.playback_rate - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn choked_voice_finishes_quickly() {
+        let zone = LoadedZone {
+            exclusive_group: Some(1),
+            ..make_test_zone()
+        };
+        let mut voice = SamplerVoice::new(&zone, 69, 1.0, 440.0, 44100.0);
+        assert_eq!(voice.exclusive_group(), Some(1));
+        for _ in 0..1000 {
+            voice.next_sample();
+        }
+        voice.choke();
+
+        // 5 ms at 44.1 kHz is ~220 samples
+        for _ in 0..300 {
+            voice.next_sample();
+        }
+        assert!(voice.is_finished(), "Choked voice should stop within a few ms");
+    }
+
     #[test]
     fn sampler_voice_finishes() {
         let short_buf = SampleBuffer::new(vec![1.0; 100], 44100);
         let zone = LoadedZone {
-            buffer: short_buf,
+            buffer: Arc::new(short_buf),
             ..make_test_zone()
         };
 
@@ -498,7 +1050,7 @@ mod tests {
         let zone = LoadedZone {
             loop_start: Some(500),
             loop_end: Some(900),
-            buffer: buf,
+            buffer: Arc::new(buf),
             ..make_test_zone()
         };
 
@@ -521,7 +1073,7 @@ mod tests {
         let zone = LoadedZone {
             loop_start: Some(500),
             loop_end: Some(9000),
-            buffer: buf,
+            buffer: Arc::new(buf),
             ..make_test_zone()
         };
 
@@ -546,6 +1098,115 @@ mod tests {
         assert!(finished, "Voice should finish after release + buffer end");
     }
 
+    #[test]
+    fn release_sample_plays_on_note_off() {
+        let zone = LoadedZone {
+            loop_start: Some(100),
+            loop_end: Some(900),
+            ..make_test_zone()
+        }
+        .with_release_buffer(SampleBuffer::new(vec![0.25; 2000], 44100));
+        assert_eq!(zone.memory_bytes(), 44100 * 4 + 2000 * 4);
+
+        let mut voice = SamplerVoice::new(&zone, 69, 1.0, 440.0, 44100.0);
+        for _ in 0..1000 {
+            voice.next_sample();
+        }
+        assert!(voice.release_position.is_none(), "Release sample waits for note-off");
+
+        voice.note_off();
+        let first = voice.next_sample();
+        assert!(voice.release_position.is_some());
+        assert!(first.abs() > 0.0);
+
+        let mut count = 1;
+        while !voice.is_finished() && count < 100_000 {
+            voice.next_sample();
+            count += 1;
+        }
+        assert!(voice.is_finished(), "Voice should finish after both samples end");
+        assert!(voice.release_buffer.is_none(), "Finished note-off sample is dropped");
+        assert!(count >= 2000, "Voice must play the full note-off sample, got {count}");
+    }
+
+    #[test]
+    fn release_sample_outlives_short_main_sample() {
+        // Main sample ends before note-off; the voice stays alive (silent)
+        // until released, then plays the note-off sample.
+        let zone = LoadedZone {
+            buffer: Arc::new(SampleBuffer::new(vec![0.5; 100], 44100)),
+            ..make_test_zone()
+        }
+        .with_release_buffer(SampleBuffer::new(vec![0.25; 500], 44100));
+        let mut voice = SamplerVoice::new(&zone, 69, 1.0, 440.0, 44100.0);
+        for _ in 0..1000 {
+            voice.next_sample();
+        }
+        assert!(!voice.is_finished());
+
+        voice.note_off();
+        let s = voice.next_sample();
+        assert!((s - 0.25).abs() < 1e-6, "Only the note-off sample should sound, got {s}");
+        for _ in 0..600 {
+            voice.next_sample();
+        }
+        assert!(voice.is_finished());
+    }
+
+    #[test]
+    fn key_tracking_scales_amplitude() {
+        let zone = LoadedZone {
+            key_tracking: Some(KeyTracking {
+                amp_db_per_octave: -6.0,
+                filter_cutoff: None,
+                filter_key_track: 1.0,
+            }),
+            ..make_test_zone()
+        };
+        let root = SamplerVoice::new(&zone, 69, 1.0, 440.0, 44100.0);
+        let up = SamplerVoice::new(&zone, 81, 1.0, 440.0, 44100.0);
+        assert!((root.velocity - 1.0).abs() < 1e-9);
+        assert!((up.velocity - 0.501).abs() < 0.01, "Octave up should be ~-6 dB");
+        assert!(root.filter.is_none());
+    }
+
+    #[test]
+    fn key_tracking_filter_follows_key() {
+        let zone = LoadedZone {
+            key_tracking: Some(KeyTracking {
+                amp_db_per_octave: 0.0,
+                filter_cutoff: Some(1000.0),
+                filter_key_track: 1.0,
+            }),
+            ..make_test_zone()
+        };
+        let up = SamplerVoice::new(&zone, 81, 1.0, 440.0, 44100.0);
+        let down = SamplerVoice::new(&zone, 57, 1.0, 440.0, 44100.0);
+        assert!((up.filter.as_ref().unwrap().frequency - 2000.0).abs() < 1e-6);
+        assert!((down.filter.as_ref().unwrap().frequency - 500.0).abs() < 1e-6);
+
+        // A cutoff far below the 440 Hz test tone should attenuate it
+        let dark_zone = LoadedZone {
+            key_tracking: Some(KeyTracking {
+                amp_db_per_octave: 0.0,
+                filter_cutoff: Some(50.0),
+                filter_key_track: 0.0,
+            }),
+            ..make_test_zone()
+        };
+        let mut dark = SamplerVoice::new(&dark_zone, 69, 1.0, 440.0, 44100.0);
+        let mut plain = SamplerVoice::new(&make_test_zone(), 69, 1.0, 440.0, 44100.0);
+        let (mut dark_peak, mut plain_peak) = (0.0_f64, 0.0_f64);
+        for i in 0..4410 {
+            let (d, p) = (dark.next_sample(), plain.next_sample());
+            if i > 2000 {
+                dark_peak = dark_peak.max(d.abs());
+                plain_peak = plain_peak.max(p.abs());
+            }
+        }
+        assert!(dark_peak < plain_peak * 0.1, "dark={dark_peak} plain={plain_peak}");
+    }
+
     #[test]
     fn sampler_voice_tuning_432() {
         // At 432 Hz tuning, playing A4 should advance slower (432/440 rate)
@@ -591,4 +1252,168 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn glide_rate_ramps_playback_rate() {
+        let zone = make_test_zone();
+        let mut voice = SamplerVoice::new(&zone, 69, 1.0, 440.0, 44100.0);
+        voice.glide_rate(2.0, 100);
+        voice.next_sample();
+        assert!(voice.playback_rate > 1.0 && voice.playback_rate < 2.0);
+        for _ in 0..99 {
+            voice.next_sample();
+        }
+        assert!((voice.playback_rate - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sampler_repairs_bad_loop_points() {
+        let zone = |start: Option<u64>, end: Option<u64>| LoadedZone {
+            loop_start: start,
+            loop_end: end,
+            buffer: Arc::new(SampleBuffer::new(vec![0.5; 1000], 44100)),
+            ..make_test_zone()
+        };
+        let zones = vec![
+            zone(Some(100), Some(900)),
+            zone(Some(100), Some(5000)),
+            zone(Some(900), Some(100)),
+            zone(Some(100), None),
+            zone(None, None),
+            zone(Some(1000), Some(2000)),
+        ];
+        let (sampler, warnings) = Sampler::with_loop_warnings(zones, false);
+        let repaired: Vec<usize> = warnings.iter().map(|w| w.zone_index).collect();
+        assert_eq!(repaired, vec![1, 2, 3, 5]);
+        let loops: Vec<(Option<u64>, Option<u64>)> =
+            sampler.zones.iter().map(|z| (z.loop_start, z.loop_end)).collect();
+        assert_eq!(
+            loops,
+            vec![
+                (Some(100), Some(900)),
+                (Some(100), Some(1000)),
+                (None, None),
+                (None, None),
+                (None, None),
+                (None, None),
+            ]
+        );
+        assert!(warnings[0].message.contains("clamped"), "{}", warnings[0].message);
+
+        // The clamped loop sustains instead of running off the buffer
+        let clamped = Sampler::new(vec![zone(Some(100), Some(5000))], false);
+        let mut voice = clamped.start_voice(69, 1.0, 440.0, 44100.0).unwrap();
+        for _ in 0..5000 {
+            voice.next_sample();
+        }
+        assert!(!voice.is_finished());
+    }
+
+    #[test]
+    fn preprocess_removes_dc_normalizes_and_fades() {
+        let data: Vec<f32> = (0..1000).map(|i| 0.25 + 0.1 * ((i % 2) as f32 * 2.0 - 1.0)).collect();
+        let mut zone = LoadedZone {
+            buffer: Arc::new(SampleBuffer::new(data, 44100)),
+            ..make_test_zone()
+        }
+        .with_release_buffer(SampleBuffer::new(vec![0.05; 100], 44100));
+        zone.preprocess(&SamplePreprocess { remove_dc: true, normalize_peak: Some(0.8), edge_fade_samples: 10 });
+
+        let buffer = &zone.buffer.data;
+        let mean = buffer.iter().sum::<f32>() / buffer.len() as f32;
+        assert!(mean.abs() < 1e-3, "DC left: {mean}");
+        assert!((zone.buffer.peak() - 0.8).abs() < 1e-5);
+        assert_eq!((buffer[0], buffer[999]), (0.0, 0.0));
+        assert!(buffer[5].abs() < buffer[500].abs());
+        // The release sample was all DC, so nothing is left of it
+        assert!(zone.release_buffer.as_ref().unwrap().peak() < 1e-6);
+
+        // Defaults leave the audio alone
+        let mut plain = make_test_zone();
+        let before = plain.buffer.data.clone();
+        plain.preprocess(&SamplePreprocess::default());
+        assert_eq!(plain.buffer.data, before);
+    }
+
+    #[test]
+    fn preprocess_keeps_loop_edges() {
+        let mut zone = LoadedZone {
+            loop_start: Some(0),
+            loop_end: Some(1000),
+            buffer: Arc::new(SampleBuffer::new(vec![0.5; 1000], 44100)),
+            ..make_test_zone()
+        };
+        zone.preprocess(&SamplePreprocess { edge_fade_samples: 16, ..Default::default() });
+        assert_eq!((zone.buffer.data[0], zone.buffer.data[999]), (0.5, 0.5));
+    }
+
+    #[test]
+    fn zone_gain_and_pan_come_from_descriptor() {
+        let json = r#"{"keyRange":{"low":0,"high":127},"pitch":{"rootNote":69,"fineTuneCents":0},
+            "sampleRate":44100,"audio":{"type":"external","url":"a.wav","codec":"wav"},"gain":-6,"pan":-0.5}"#;
+        let descriptor: SampleZone = serde_json::from_str(json).unwrap();
+        let zone = LoadedZone::from_zone(&descriptor, make_test_buffer());
+        assert_eq!((zone.gain, zone.pan), (-6.0, -0.5));
+
+        let voice = SamplerVoice::new(&zone, 69, 1.0, 440.0, 44100.0);
+        assert!((voice.velocity - 0.501).abs() < 0.01, "-6 dB should about halve the level");
+        assert_eq!(voice.pan(), -0.5);
+    }
+
+    #[test]
+    fn sample_buffer_decodes_8_16_and_24_bit_pcm() {
+        let u8_buffer = SampleBuffer::from_u8(&[0, 128, 255], 8000);
+        assert_eq!(u8_buffer.data, vec![-1.0, 0.0, 127.0 / 128.0]);
+
+        let i16_buffer = SampleBuffer::from_i16_be(&[0x80, 0x00, 0x40, 0x00, 0x12], 8000);
+        assert_eq!(i16_buffer.data, vec![-1.0, 0.5]);
+
+        // -1.0, 0.5 and a trailing partial sample
+        let le = [0x00, 0x00, 0x80, 0x00, 0x00, 0x40, 0x01];
+        assert_eq!(SampleBuffer::from_i24_le(&le, 48000).data, vec![-1.0, 0.5]);
+        let be = [0x80, 0x00, 0x00, 0x40, 0x00, 0x00];
+        assert_eq!(SampleBuffer::from_i24_be(&be, 48000).data, vec![-1.0, 0.5]);
+        let max = SampleBuffer::from_i24_le(&[0xff, 0xff, 0x7f], 48000);
+        assert!((max.data[0] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sampler_envelope_hold_and_curves() {
+        let zone = make_test_zone();
+        let mut voice = SamplerVoice::new(&zone, 69, 1.0, 440.0, 1000.0);
+        voice.apply_envelope(&EnvelopeConfig {
+            attack: Some(0.1),
+            hold: Some(0.1),
+            decay: Some(0.1),
+            sustain: Some(0.5),
+            attack_curve: Some(EnvelopeCurve::Exp),
+            ..Default::default()
+        });
+        let levels: Vec<f64> = (0..400).map(|_| voice.envelope.next_sample()).collect();
+        assert!(levels[49] < 0.2, "Exponential attack rises slowly, got {}", levels[49]);
+        assert!(levels[100..200].iter().all(|l| *l == 1.0), "Holds at full level");
+        assert!((levels[249] - 0.75).abs() < 0.01, "Linear decay, got {}", levels[249]);
+        assert_eq!(levels[350], 0.5);
+    }
+
+    #[test]
+    fn sampler_release_starts_from_level_at_note_off() {
+        let zone = make_test_zone();
+        let mut voice = SamplerVoice::new(&zone, 69, 1.0, 440.0, 1000.0);
+        voice.apply_envelope(&EnvelopeConfig {
+            attack: Some(1.0),
+            sustain: Some(0.8),
+            release: Some(0.1),
+            ..Default::default()
+        });
+        let attack: Vec<f64> = (0..100).map(|_| voice.envelope.next_sample()).collect();
+        let at_note_off = attack[99];
+        assert!(at_note_off < 0.11, "Still in the attack, got {at_note_off}");
+
+        // Released mid-attack: ramp down from there, not jump to sustain
+        voice.envelope.note_off();
+        let release: Vec<f64> = (0..100).map(|_| voice.envelope.next_sample()).collect();
+        assert!(release[0] <= at_note_off, "Release jumped to {}", release[0]);
+        assert!((release[49] - at_note_off * 0.5).abs() < 0.01, "Halfway, got {}", release[49]);
+    }
 }
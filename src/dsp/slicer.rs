@@ -0,0 +1,205 @@
+//! Loop slicer — split a drum loop into one-shot slices mapped across keys.
+//!
+//! Each slice becomes a single-key zone of a drum-kit sampler, starting at
+//! C2 (the General MIDI kick), so a loop can be re-sequenced note by note.
+
+use crate::dsp::tempo::onset_envelope;
+use crate::preset::{
+    AudioReference, KeyRange, PresetCategory, PresetDescriptor, PresetNode, SampleZone,
+    SamplerConfig, ZonePitch,
+};
+
+/// Key of the first slice (C2).
+pub const FIRST_SLICE_KEY: u8 = 36;
+/// Onset envelope resolution (seconds per frame).
+const HOP_SECONDS: f64 = 0.005;
+/// Closest two detected slices may start (seconds).
+const MIN_SLICE_SECONDS: f64 = 0.05;
+/// Onsets weaker than this fraction of the strongest are ignored.
+const ONSET_THRESHOLD: f64 = 0.3;
+/// Fade applied to the end of each slice to avoid clicks (seconds).
+const FADE_SECONDS: f64 = 0.002;
+
+/// How to choose slice points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceMode {
+    /// Split into this many equal slices.
+    Count(usize),
+    /// Split at detected transients.
+    Onsets,
+}
+
+/// Sample offsets of the transients in a mono buffer, always starting
+/// with 0.
+pub fn detect_onsets(samples: &[f64], sample_rate: u32) -> Vec<usize> {
+    let sr = sample_rate as f64;
+    let hop = ((sr * HOP_SECONDS).round() as usize).max(1);
+    let envelope = onset_envelope(samples, hop);
+    let strongest = envelope.iter().cloned().fold(0.0, f64::max);
+    let min_gap = (sr * MIN_SLICE_SECONDS) as usize;
+
+    let mut onsets = vec![0];
+    if strongest <= 0.0 {
+        return onsets;
+    }
+    for i in 1..envelope.len() {
+        let value = envelope[i];
+        let is_peak = value >= envelope[i - 1] && envelope.get(i + 1).is_none_or(|&next| value > next);
+        // The transient lies within frame `i`; slice at its start
+        let offset = i * hop;
+        if is_peak && value >= strongest * ONSET_THRESHOLD && offset >= onsets[onsets.len() - 1] + min_gap {
+            onsets.push(offset);
+        }
+    }
+    onsets
+}
+
+/// Slice a mono loop and build a drum-kit sampler preset with one slice
+/// per key from `FIRST_SLICE_KEY` upward. Slices are stored as inline
+/// 16-bit PCM.
+pub fn slice_loop(
+    samples: &[f64],
+    sample_rate: u32,
+    mode: SliceMode,
+) -> Result<PresetDescriptor, String> {
+    if samples.is_empty() {
+        return Err("Cannot slice an empty loop.".to_string());
+    }
+    let starts: Vec<usize> = match mode {
+        SliceMode::Count(count) if count == 0 || count > samples.len() => {
+            return Err(format!("Cannot split a loop of {} samples into {count} slices.", samples.len()));
+        }
+        SliceMode::Count(count) => (0..count).map(|i| i * samples.len() / count).collect(),
+        SliceMode::Onsets => detect_onsets(samples, sample_rate),
+    };
+    let max_slices = 128 - FIRST_SLICE_KEY as usize;
+    if starts.len() > max_slices {
+        return Err(format!(
+            "Cannot map {} slices to keys; at most {max_slices} fit above C2.",
+            starts.len()
+        ));
+    }
+
+    let fade_len = (sample_rate as f64 * FADE_SECONDS) as usize;
+    let zones = starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = starts.get(i + 1).copied().unwrap_or(samples.len());
+            let slice = &samples[start..end];
+            let fade = fade_len.min(slice.len());
+            let faded: Vec<f64> = slice
+                .iter()
+                .enumerate()
+                .map(|(j, &s)| {
+                    let from_end = slice.len() - j;
+                    if from_end <= fade { s * from_end as f64 / (fade + 1) as f64 } else { s }
+                })
+                .collect();
+            let key = FIRST_SLICE_KEY + i as u8;
+            SampleZone {
+                key_range: KeyRange { low: key, high: key },
+                velocity_range: None,
+                pitch: ZonePitch { root_note: key, fine_tune_cents: 0.0 },
+                sample_rate,
+                r#loop: None,
+                exclusive_group: None,
+                audio: AudioReference::inline_pcm16(&faded),
+                release_audio: None,
+                gain: None,
+                pan: None,
+                key_tracking: None,
+            }
+        })
+        .collect();
+
+    Ok(PresetDescriptor {
+        format: None,
+        version: None,
+        id: "sliced-loop".to_string(),
+        name: "Sliced Loop".to_string(),
+        category: PresetCategory::Sampler,
+        tags: vec!["percussion".to_string(), "sliced".to_string()],
+        metadata: None,
+        tuning: None,
+        graph: PresetNode::Sampler {
+            config: SamplerConfig { zones, is_drum_kit: true, envelope: None },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine as _;
+
+    /// Silence with a decaying noise burst at each offset.
+    fn hits(offsets: &[usize], len: usize) -> Vec<f64> {
+        let mut samples = vec![0.0; len];
+        let mut rng: u64 = 3;
+        for &start in offsets {
+            for i in 0..2000 {
+                if let Some(s) = samples.get_mut(start + i) {
+                    rng = rng.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    let noise = (rng >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0;
+                    *s = noise * 0.8 * (-(i as f64) / 300.0).exp();
+                }
+            }
+        }
+        samples
+    }
+
+    fn sampler_zones(preset: &PresetDescriptor) -> &[SampleZone] {
+        match &preset.graph {
+            PresetNode::Sampler { config } => {
+                assert!(config.is_drum_kit);
+                &config.zones
+            }
+            other => panic!("Expected a sampler, got {other:?}"),
+        }
+    }
+
+    fn zone_len(zone: &SampleZone) -> usize {
+        match &zone.audio {
+            AudioReference::InlinePcm { data, bits_per_sample: 16 } => {
+                base64::engine::general_purpose::STANDARD.decode(data).unwrap().len() / 2
+            }
+            other => panic!("Expected inline PCM, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn onsets_are_found_at_transients() {
+        let onsets = detect_onsets(&hits(&[0, 11025, 16537, 33075], 44100), 44100);
+        assert_eq!(onsets.len(), 4, "{onsets:?}");
+        for (found, expected) in onsets.iter().zip([0, 11025, 16537, 33075]) {
+            assert!(found.abs_diff(expected) < 300, "Onset at {found}, expected {expected}");
+        }
+    }
+
+    #[test]
+    fn slices_map_one_per_key() {
+        let samples = hits(&[0, 11025, 22050, 33075], 44100);
+        let preset = slice_loop(&samples, 44100, SliceMode::Count(4)).unwrap();
+        let zones = sampler_zones(&preset);
+
+        assert_eq!(zones.len(), 4);
+        for (i, zone) in zones.iter().enumerate() {
+            let key = FIRST_SLICE_KEY + i as u8;
+            assert_eq!((zone.key_range.low, zone.key_range.high, zone.pitch.root_note), (key, key, key));
+            assert_eq!(zone_len(zone), 11025);
+        }
+
+        let detected = slice_loop(&samples, 44100, SliceMode::Onsets).unwrap();
+        assert_eq!(sampler_zones(&detected).len(), 4);
+        let json = serde_json::to_string(&detected).unwrap();
+        assert!(json.contains(r#""isDrumKit":true"#));
+    }
+
+    #[test]
+    fn invalid_slicing_is_rejected() {
+        assert!(slice_loop(&[], 44100, SliceMode::Count(4)).is_err());
+        assert!(slice_loop(&[0.0; 1000], 44100, SliceMode::Count(0)).is_err());
+        assert!(slice_loop(&[0.0; 1000], 44100, SliceMode::Count(93)).is_err());
+    }
+}
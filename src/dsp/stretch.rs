@@ -0,0 +1,244 @@
+//! Offline time-stretching and pitch-shifting with a phase vocoder.
+//!
+//! Audio is analysed in overlapping Hann-windowed frames; each frame is
+//! resynthesised at a new hop with phases advanced by the measured
+//! frequency of every bin, so duration changes without changing pitch.
+//! Pitch-shifting stretches and then resamples back to the input length.
+//! Intended for tools (conforming loops to a song tempo), not real time.
+
+use std::f64::consts::PI;
+
+/// Analysis frame length in samples (a power of two).
+const FRAME_LEN: usize = 2048;
+/// Synthesis hop in samples (75% frame overlap).
+const SYNTH_HOP: usize = FRAME_LEN / 4;
+
+/// Change the duration of `samples` by `factor` without changing pitch:
+/// 2.0 is twice as long, 0.5 half as long.
+pub fn time_stretch(samples: &[f64], factor: f64) -> Result<Vec<f64>, String> {
+    if !(factor.is_finite() && factor > 0.0) {
+        return Err(format!("Time-stretch factor must be a positive number, got {factor}."));
+    }
+    let out_len = (samples.len() as f64 * factor).round() as usize;
+    if samples.is_empty() || out_len == 0 {
+        return Ok(Vec::new());
+    }
+    Ok(vocode(samples, factor, out_len))
+}
+
+/// Shift the pitch of `samples` by `semitones`, keeping their duration.
+pub fn pitch_shift(samples: &[f64], semitones: f64) -> Result<Vec<f64>, String> {
+    if !semitones.is_finite() {
+        return Err(format!("Pitch shift must be a finite number of semitones, got {semitones}."));
+    }
+    let ratio = 2.0_f64.powf(semitones / 12.0);
+    let stretched = time_stretch(samples, ratio)?;
+    if stretched.is_empty() {
+        return Ok(Vec::new());
+    }
+    // Play the stretched audio back `ratio` times faster
+    Ok((0..samples.len())
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let idx = position as usize;
+            let frac = position - idx as f64;
+            let a = stretched.get(idx).copied().unwrap_or(0.0);
+            let b = stretched.get(idx + 1).copied().unwrap_or(0.0);
+            a * (1.0 - frac) + b * frac
+        })
+        .collect())
+}
+
+/// Time-stretch a loop recorded at `loop_bpm` so it plays in time at
+/// `song_bpm`.
+pub fn conform_to_tempo(samples: &[f64], loop_bpm: f64, song_bpm: f64) -> Result<Vec<f64>, String> {
+    if !(loop_bpm > 0.0 && song_bpm > 0.0) {
+        return Err("Loop and song tempos must be positive.".to_string());
+    }
+    time_stretch(samples, loop_bpm / song_bpm)
+}
+
+/// Phase-vocoder resynthesis of `samples` at `factor` times the length.
+fn vocode(samples: &[f64], factor: f64, out_len: usize) -> Vec<f64> {
+    let window: Vec<f64> = (0..FRAME_LEN)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / FRAME_LEN as f64).cos())
+        .collect();
+    let analysis_hop = SYNTH_HOP as f64 / factor;
+    let bins = FRAME_LEN / 2 + 1;
+
+    let mut output = vec![0.0; out_len + FRAME_LEN];
+    let mut norm = vec![0.0; out_len + FRAME_LEN];
+    let mut prev_phase = vec![0.0; bins];
+    let mut synth_phase = vec![0.0; bins];
+    let mut prev_pos: Option<isize> = None;
+    let mut re = vec![0.0; FRAME_LEN];
+    let mut im = vec![0.0; FRAME_LEN];
+
+    // Frames are centred on their position, so start half a frame early
+    let offset = (FRAME_LEN / 2) as isize;
+    let frames = out_len.div_ceil(SYNTH_HOP) + 1;
+    for k in 0..frames {
+        let pos = (k as f64 * analysis_hop).round() as isize - offset;
+        for (i, (r, m)) in re.iter_mut().zip(im.iter_mut()).enumerate() {
+            let idx = pos + i as isize;
+            let sample = if idx >= 0 { samples.get(idx as usize).copied().unwrap_or(0.0) } else { 0.0 };
+            *r = sample * window[i];
+            *m = 0.0;
+        }
+        fft(&mut re, &mut im, false);
+
+        let advance = prev_pos.map(|p| (pos - p) as f64);
+        for b in 0..bins {
+            let magnitude = re[b].hypot(im[b]);
+            let phase = im[b].atan2(re[b]);
+            synth_phase[b] = match advance {
+                Some(delta) if delta > 0.0 => {
+                    let omega = 2.0 * PI * b as f64 / FRAME_LEN as f64;
+                    let deviation = wrap_phase(phase - prev_phase[b] - omega * delta);
+                    synth_phase[b] + (omega + deviation / delta) * SYNTH_HOP as f64
+                }
+                Some(_) => synth_phase[b],
+                None => phase,
+            };
+            prev_phase[b] = phase;
+            re[b] = magnitude * synth_phase[b].cos();
+            im[b] = magnitude * synth_phase[b].sin();
+        }
+        // Mirror the spectrum so the inverse transform is real
+        for b in bins..FRAME_LEN {
+            re[b] = re[FRAME_LEN - b];
+            im[b] = -im[FRAME_LEN - b];
+        }
+        prev_pos = Some(pos);
+        fft(&mut re, &mut im, true);
+
+        let start = k as isize * SYNTH_HOP as isize - offset;
+        for i in 0..FRAME_LEN {
+            let idx = start + i as isize;
+            if idx >= 0 && (idx as usize) < output.len() {
+                output[idx as usize] += re[i] * window[i];
+                norm[idx as usize] += window[i] * window[i];
+            }
+        }
+    }
+
+    output.truncate(out_len);
+    output
+        .iter()
+        .zip(&norm)
+        .map(|(&s, &w)| if w > 1e-6 { s / w } else { 0.0 })
+        .collect()
+}
+
+/// Wrap a phase into [-π, π].
+fn wrap_phase(phase: f64) -> f64 {
+    phase - 2.0 * PI * (phase / (2.0 * PI)).round()
+}
+
+/// In-place iterative radix-2 FFT. The inverse transform is scaled by
+/// 1/N. `re.len()` must be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        for (r, m) in re.iter_mut().zip(im.iter_mut()) {
+            *r /= n as f64;
+            *m /= n as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::tuner::detect_pitch;
+
+    fn sine(freq: f64, sample_rate: u32, seconds: f64) -> Vec<f64> {
+        (0..(sample_rate as f64 * seconds) as usize)
+            .map(|i| 0.5 * (2.0 * PI * freq * i as f64 / sample_rate as f64).sin())
+            .collect()
+    }
+
+    fn rms(samples: &[f64]) -> f64 {
+        (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn fft_round_trips() {
+        let original: Vec<f64> = (0..64).map(|i| ((i * 7) % 13) as f64 - 6.0).collect();
+        let mut re = original.clone();
+        let mut im = vec![0.0; 64];
+        fft(&mut re, &mut im, false);
+        fft(&mut re, &mut im, true);
+        for (a, b) in re.iter().zip(&original) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn time_stretch_keeps_pitch() {
+        let input = sine(440.0, 22050, 1.0);
+        for factor in [0.5, 1.5, 2.0] {
+            let output = time_stretch(&input, factor).unwrap();
+            assert_eq!(output.len(), (22050.0 * factor) as usize);
+            let middle = &output[output.len() / 4..output.len() * 3 / 4];
+            let pitch = detect_pitch(middle, 22050, None, None);
+            assert!((pitch.frequency - 440.0).abs() < 3.0, "factor {factor}: {} Hz", pitch.frequency);
+            assert!((rms(middle) - rms(&input)).abs() < 0.05, "factor {factor}: level {}", rms(middle));
+        }
+    }
+
+    #[test]
+    fn pitch_shift_keeps_duration() {
+        let input = sine(440.0, 22050, 1.0);
+        let output = pitch_shift(&input, 12.0).unwrap();
+        assert_eq!(output.len(), input.len());
+        let pitch = detect_pitch(&output[5000..15000], 22050, None, None);
+        assert!((pitch.frequency - 880.0).abs() < 6.0, "{} Hz", pitch.frequency);
+
+        let down = pitch_shift(&input, -7.0).unwrap();
+        let pitch = detect_pitch(&down[5000..15000], 22050, None, None);
+        assert_eq!(pitch.midi_note, 62, "{} Hz", pitch.frequency);
+    }
+
+    #[test]
+    fn invalid_factors_are_rejected() {
+        assert!(time_stretch(&[0.0; 100], 0.0).is_err());
+        assert!(time_stretch(&[0.0; 100], f64::NAN).is_err());
+        assert!(pitch_shift(&[0.0; 100], f64::INFINITY).is_err());
+        assert_eq!(conform_to_tempo(&[0.0; 100], 120.0, 60.0).unwrap().len(), 200);
+        assert!(time_stretch(&[], 2.0).unwrap().is_empty());
+    }
+}
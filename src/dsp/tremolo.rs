@@ -0,0 +1,255 @@
+//! Tremolo and auto-pan — LFO-driven volume and pan modulation.
+//!
+//! Both effects leave the LFO rate as a public field so the engine can
+//! follow the song's tempo when they are synced to note lengths. The
+//! configs also give the gains at any LFO phase, for tracks whose notes
+//! are modulated voice by voice (`track.tremolo`, `track.autoPan`).
+
+use core::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// Waveform of a modulation LFO.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum LfoShape {
+    #[default]
+    Sine,
+    Triangle,
+    /// Square with softened edges, so it chops without clicking.
+    Square,
+}
+
+impl LfoShape {
+    /// The waveform `phase` cycles in, from -1.0 to 1.0.
+    pub fn value(self, phase: f64) -> f64 {
+        let phase = phase - phase.floor();
+        match self {
+            LfoShape::Sine => math::sin(2.0 * PI * phase),
+            LfoShape::Triangle => 1.0 - 4.0 * ((phase + 0.25).fract() - 0.5).abs(),
+            LfoShape::Square => math::tanh(8.0 * math::sin(2.0 * PI * phase)) / math::tanh(8.0),
+        }
+    }
+}
+
+/// Configuration for the tremolo effect.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
+pub struct TremoloConfig {
+    /// LFO rate in Hz.
+    pub rate: f64,
+    /// Beats per LFO cycle (e.g. 0.5 for eighth notes), following the
+    /// song's tempo. Overrides `rate`.
+    pub sync: Option<f64>,
+    /// How far the volume dips (0.0 to 1.0).
+    pub depth: f64,
+    pub shape: LfoShape,
+}
+
+impl Default for TremoloConfig {
+    fn default() -> Self {
+        Self {
+            rate: 5.0,
+            sync: None,
+            depth: 0.5,
+            shape: LfoShape::Sine,
+        }
+    }
+}
+
+impl TremoloConfig {
+    /// Volume at `phase` LFO cycles in. The volume starts at its peak.
+    pub fn gain(&self, phase: f64) -> f64 {
+        1.0 - self.depth.clamp(0.0, 1.0) * 0.5 * (1.0 - self.shape.value(phase + 0.25))
+    }
+}
+
+/// Configuration for the auto-pan effect.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default, rename_all = "camelCase")]
+pub struct AutoPanConfig {
+    /// LFO rate in Hz.
+    pub rate: f64,
+    /// Beats per LFO cycle (e.g. 4.0 for a bar of 4/4), following the
+    /// song's tempo. Overrides `rate`.
+    pub sync: Option<f64>,
+    /// How far the sound swings (0.0 = centered, 1.0 = hard left to right).
+    pub depth: f64,
+    pub shape: LfoShape,
+}
+
+impl Default for AutoPanConfig {
+    fn default() -> Self {
+        Self {
+            rate: 0.5,
+            sync: None,
+            depth: 0.7,
+            shape: LfoShape::Sine,
+        }
+    }
+}
+
+impl AutoPanConfig {
+    /// Left and right gains at `phase` LFO cycles in, as `AutoPan`
+    /// applies them. The sweep starts centered, heading right.
+    pub fn gains(&self, phase: f64) -> (f64, f64) {
+        let pan = self.depth.clamp(0.0, 1.0) * self.shape.value(phase);
+        (math::cos(pan.max(0.0) * PI * 0.5), math::cos((-pan).max(0.0) * PI * 0.5))
+    }
+}
+
+/// LFO cycles after `seconds` of the song: `rate` Hz, or one cycle every
+/// `sync` beats when synced, where `beat` is the song position at that
+/// time.
+pub fn lfo_phase(rate: f64, sync: Option<f64>, seconds: f64, beat: f64) -> f64 {
+    match sync {
+        Some(beats) => beat / beats.max(1.0 / 64.0),
+        None => seconds * rate.clamp(0.01, 40.0),
+    }
+}
+
+/// A low-frequency oscillator from -1.0 to 1.0.
+#[derive(Debug, Clone)]
+struct Lfo {
+    shape: LfoShape,
+    phase: f64,
+}
+
+impl Lfo {
+    #[inline]
+    fn next(&mut self, increment: f64) -> f64 {
+        let phase = self.phase;
+        self.phase = (self.phase + increment).fract();
+        self.shape.value(phase)
+    }
+}
+
+/// Rhythmic volume modulation.
+#[derive(Debug, Clone)]
+pub struct Tremolo {
+    lfo: Lfo,
+    sample_rate: f64,
+
+    /// LFO rate in Hz.
+    pub rate: f64,
+    /// How far the volume dips (0.0 = none, 1.0 = to silence).
+    pub depth: f64,
+}
+
+impl Tremolo {
+    /// Create a tremolo. The volume starts at its peak.
+    pub fn with_params(sample_rate: f64, rate: f64, depth: f64, shape: LfoShape) -> Self {
+        Self {
+            lfo: Lfo { shape, phase: 0.25 },
+            sample_rate,
+            rate: rate.clamp(0.01, 40.0),
+            depth: depth.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Process a stereo sample pair.
+    #[inline]
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let lfo = self.lfo.next(self.rate / self.sample_rate);
+        let gain = (1.0 - self.depth * 0.5 * (1.0 - lfo)) as f32;
+        (left * gain, right * gain)
+    }
+
+    /// Process a block of stereo audio in-place.
+    pub fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for i in 0..left.len().min(right.len()) {
+            (left[i], right[i]) = self.process(left[i], right[i]);
+        }
+    }
+}
+
+/// Sweeps the sound between the left and right speakers.
+#[derive(Debug, Clone)]
+pub struct AutoPan {
+    lfo: Lfo,
+    sample_rate: f64,
+
+    /// LFO rate in Hz.
+    pub rate: f64,
+    /// How far the sound swings (0.0 = stays centered, 1.0 = hard left
+    /// to hard right).
+    pub depth: f64,
+}
+
+impl AutoPan {
+    /// Create an auto-pan. The sweep starts centered, heading right.
+    pub fn with_params(sample_rate: f64, rate: f64, depth: f64, shape: LfoShape) -> Self {
+        Self {
+            lfo: Lfo { shape, phase: 0.0 },
+            sample_rate,
+            rate: rate.clamp(0.01, 40.0),
+            depth: depth.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Process a stereo sample pair. Centered audio passes unchanged; the
+    /// far side is faded out along a quarter cosine as the sound swings.
+    #[inline]
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let pan = self.depth * self.lfo.next(self.rate / self.sample_rate);
+        let left_gain = math::cos(pan.max(0.0) * PI * 0.5) as f32;
+        let right_gain = math::cos((-pan).max(0.0) * PI * 0.5) as f32;
+        (left * left_gain, right * right_gain)
+    }
+
+    /// Process a block of stereo audio in-place.
+    pub fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for i in 0..left.len().min(right.len()) {
+            (left[i], right[i]) = self.process(left[i], right[i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peaks(samples: &[f32], block: usize) -> Vec<f32> {
+        samples.chunks(block).map(|c| c.iter().fold(0.0_f32, |m, s| m.max(s.abs()))).collect()
+    }
+
+    #[test]
+    fn tremolo_dips_at_the_lfo_rate() {
+        let (mut left, mut right) = (vec![1.0_f32; 44100], vec![1.0_f32; 44100]);
+        Tremolo::with_params(44100.0, 2.0, 0.8, LfoShape::Sine).process_block(&mut left, &mut right);
+        assert_eq!(left, right);
+        assert!((left[0] - 1.0).abs() < 1e-6, "Starts at full volume");
+        // Troughs fall half a cycle after each peak
+        assert!((left[11025] - 0.2).abs() < 1e-3, "Trough was {}", left[11025]);
+        assert!((left[22050] - 1.0).abs() < 1e-3);
+        assert!(left.iter().all(|s| (0.2 - 1e-3..=1.0).contains(s)));
+    }
+
+    #[test]
+    fn square_tremolo_chops_without_clicks() {
+        let (mut left, mut right) = (vec![1.0_f32; 44100], vec![1.0_f32; 44100]);
+        Tremolo::with_params(44100.0, 4.0, 1.0, LfoShape::Square).process_block(&mut left, &mut right);
+        let quiet = left.iter().filter(|s| **s < 0.05).count();
+        assert!(quiet > 15000, "Square should stay near silent for most of each low half, got {quiet}");
+        let jump = left.windows(2).fold(0.0_f32, |m, w| m.max((w[1] - w[0]).abs()));
+        assert!(jump < 0.01, "Largest step was {jump}");
+    }
+
+    #[test]
+    fn auto_pan_swings_between_speakers() {
+        let (mut left, mut right) = (vec![1.0_f32; 44100], vec![1.0_f32; 44100]);
+        AutoPan::with_params(44100.0, 1.0, 1.0, LfoShape::Triangle).process_block(&mut left, &mut right);
+        assert_eq!((left[0], right[0]), (1.0, 1.0), "Starts centered");
+        // A quarter cycle in the sound is hard right, three quarters in hard left
+        assert!(left[11025].abs() < 1e-3 && right[11025] == 1.0);
+        assert!(right[33075].abs() < 1e-3 && left[33075] == 1.0);
+        assert_eq!(peaks(&left, 11025), peaks(&right, 11025).into_iter().rev().collect::<Vec<_>>());
+    }
+}
@@ -4,6 +4,8 @@
 //! estimate the fundamental frequency of a sample, then computes
 //! the MIDI note number and fine-tune cents needed for preset metadata.
 
+use crate::format::{format_beats, format_decimal};
+
 /// Result of pitch detection on a sample.
 #[derive(Debug, Clone, PartialEq)]
 pub struct PitchEstimate {
@@ -209,6 +211,130 @@ pub fn suggest_corrections(
     }).collect()
 }
 
+// ── Melody Transcription ────────────────────────────────────
+
+/// Lowest pitch considered when transcribing a melody (Hz).
+const TRANSCRIBE_MIN_FREQ: f64 = 60.0;
+/// Frames quieter than this fraction of the loudest frame are silence.
+const SILENCE_RATIO: f64 = 0.1;
+/// Notes shorter than this are treated as glitches and dropped (seconds).
+const MIN_NOTE_SECONDS: f64 = 0.06;
+/// Note starts and ends snap to this fraction of a beat.
+const BEAT_GRID: f64 = 0.25;
+
+/// A note found in a recorded melody.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscribedNote {
+    /// MIDI note number.
+    pub midi_note: u8,
+    /// Start time in beats, snapped to a sixteenth-note grid.
+    pub start_beat: f64,
+    /// Length in beats, snapped to a sixteenth-note grid.
+    pub duration: f64,
+}
+
+/// Segment a monophonic recording (e.g. a hummed or sung melody) into
+/// notes, with times quantized to beats at `bpm`.
+///
+/// Each frame is pitch-tracked with `detect_pitch`; consecutive voiced
+/// frames with the same nearest MIDI note form one note. Repeated notes
+/// are only separated when there is a gap of silence between them.
+pub fn segment_melody(samples: &[f64], sample_rate: u32, bpm: f64) -> Vec<TranscribedNote> {
+    let sr = sample_rate as f64;
+    let frame_len = 2 * (sr / TRANSCRIBE_MIN_FREQ).ceil() as usize;
+    let hop = (frame_len / 2).max(1);
+    if samples.len() < frame_len || bpm <= 0.0 {
+        return Vec::new();
+    }
+
+    let frames: Vec<&[f64]> = (0..=(samples.len() - frame_len) / hop)
+        .map(|i| &samples[i * hop..i * hop + frame_len])
+        .collect();
+    let levels: Vec<f64> = frames
+        .iter()
+        .map(|f| (f.iter().map(|s| s * s).sum::<f64>() / f.len() as f64).sqrt())
+        .collect();
+    let loudest = levels.iter().cloned().fold(0.0, f64::max);
+    if loudest <= 0.0 {
+        return Vec::new();
+    }
+
+    // Nearest MIDI note per frame, or None for silence and unpitched frames
+    let pitches: Vec<Option<u8>> = frames
+        .iter()
+        .zip(&levels)
+        .map(|(frame, &level)| {
+            if level < loudest * SILENCE_RATIO {
+                return None;
+            }
+            let estimate = detect_pitch(frame, sample_rate, Some(TRANSCRIBE_MIN_FREQ), None);
+            (!estimate.is_noise).then_some(estimate.midi_note)
+        })
+        .collect();
+
+    // Runs of equal pitch, as (midi, first frame, frame count)
+    let mut runs: Vec<(u8, usize, usize)> = Vec::new();
+    for (i, pitch) in pitches.iter().enumerate() {
+        let Some(midi) = *pitch else { continue };
+        match runs.last_mut() {
+            Some((last, first, count)) if *last == midi && *first + *count == i => *count += 1,
+            _ => runs.push((midi, i, 1)),
+        }
+    }
+
+    let beats_per_second = bpm / 60.0;
+    let snap = |seconds: f64| (seconds * beats_per_second / BEAT_GRID).round() * BEAT_GRID;
+    let mut notes: Vec<TranscribedNote> = Vec::new();
+    for (midi, first, count) in runs {
+        // A frame covers its hop; the last frame also covers its tail
+        let start = (first * hop) as f64 / sr;
+        let end = ((first + count) * hop) as f64 / sr;
+        if end - start < MIN_NOTE_SECONDS {
+            continue;
+        }
+        let mut start_beat = snap(start);
+        let end_beat = snap(end);
+        if let Some(prev) = notes.last() {
+            start_beat = start_beat.max(prev.start_beat + prev.duration);
+        }
+        if end_beat - start_beat < BEAT_GRID / 2.0 {
+            continue;
+        }
+        match notes.last_mut() {
+            // Merge pieces of one note split by a dropped glitch
+            Some(prev) if prev.midi_note == midi && (prev.start_beat + prev.duration - start_beat).abs() < 1e-9 => {
+                prev.duration = end_beat - prev.start_beat;
+            }
+            _ => notes.push(TranscribedNote { midi_note: midi, start_beat, duration: end_beat - start_beat }),
+        }
+    }
+    notes
+}
+
+/// Transcribe a monophonic recording into draft `.sw` source: a single
+/// `melody` track at `bpm` with one note or rest per line.
+pub fn transcribe_monophonic(samples: &[f64], sample_rate: u32, bpm: f64) -> Result<String, String> {
+    if !(bpm.is_finite() && bpm > 0.0) {
+        return Err(format!("Transcription tempo must be a positive number of beats per minute, got {bpm}."));
+    }
+    let notes = segment_melody(samples, sample_rate, bpm);
+
+    let mut out = String::from("// Transcribed melody\n");
+    out.push_str(&format!("track.beatsPerMinute = {};\n\ntrack melody() {{\n", format_decimal(bpm)));
+    let mut cursor = 0.0;
+    for note in &notes {
+        if note.start_beat - cursor > 1e-9 {
+            out.push_str(&format!("    _ {}\n", format_beats(note.start_beat - cursor)));
+        }
+        let name = crate::pitch::midi_to_note_name(note.midi_note as i32, true);
+        // `@` takes no fractions, so the gate is written as a decimal
+        out.push_str(&format!("    {name}@{} {}\n", format_decimal(note.duration), format_beats(note.duration)));
+        cursor = note.start_beat + note.duration;
+    }
+    out.push_str("}\n\nmelody();\n");
+    Ok(out)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -323,4 +449,41 @@ mod tests {
         assert!(corrections[0].deviation_cents < 20.0,
             "Deviation should be small: {}", corrections[0].deviation_cents);
     }
+
+    fn melody(notes: &[(f64, f64)], sample_rate: u32) -> Vec<f64> {
+        // (frequency, seconds) pairs; a frequency of 0 is silence
+        notes
+            .iter()
+            .flat_map(|&(freq, secs)| {
+                let tone = generate_sine(freq.max(1.0), sample_rate, secs);
+                tone.into_iter().map(move |s| if freq > 0.0 { s * 0.5 } else { 0.0 })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn segment_melody_finds_notes_and_gaps() {
+        // At 120 BPM a beat is 0.5 s: C4 for a beat, a half-beat rest, E4 and G4
+        let samples = melody(&[(261.63, 0.5), (0.0, 0.25), (329.63, 0.5), (392.0, 1.0)], 22050);
+        let notes = segment_melody(&samples, 22050, 120.0);
+
+        let summary: Vec<(u8, f64, f64)> =
+            notes.iter().map(|n| (n.midi_note, n.start_beat, n.duration)).collect();
+        assert_eq!(summary, vec![(60, 0.0, 1.0), (64, 1.5, 1.0), (67, 2.5, 2.0)]);
+    }
+
+    #[test]
+    fn transcribe_monophonic_writes_compilable_source() {
+        let samples = melody(&[(0.0, 0.25), (440.0, 0.75), (466.16, 0.5)], 22050);
+        let source = transcribe_monophonic(&samples, 22050, 120.0).unwrap();
+
+        assert!(source.contains("track.beatsPerMinute = 120;"), "{source}");
+        assert!(source.contains("    _ 1/2\n    A4@1.5 3/2\n    Bb4@1 1\n"), "{source}");
+        let program = crate::parse(&source).unwrap();
+        crate::compiler::compile(&program).unwrap();
+
+        assert!(transcribe_monophonic(&samples, 22050, 0.0).is_err());
+        let silent = transcribe_monophonic(&[0.0; 22050], 22050, 90.0).unwrap();
+        assert!(silent.contains("track melody() {\n}"), "{silent}");
+    }
 }
@@ -1,11 +1,15 @@
 //! Voice — A single note instance combining oscillator + envelope.
 
-use crate::compiler::InstrumentConfig;
+use crate::compiler::{EnvelopeConfig, FmConfig, OscillatorConfig};
 
 use super::envelope::Envelope;
 use super::oscillator::{Oscillator, Waveform};
+use crate::math;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
 
-/// A single voice: one oscillator shaped by an ADSR envelope.
+/// A single voice: one oscillator (or FM operator pair) shaped by an ADSR
+/// envelope.
 #[derive(Debug, Clone)]
 pub struct Voice {
     pub oscillator: Oscillator,
@@ -16,6 +20,42 @@ pub struct Voice {
     pub release_sample: usize,
     /// Whether this voice has been released and envelope is done.
     finished: bool,
+    /// Frequency the current glide ends on.
+    glide_target: f64,
+    /// Per-sample frequency ratio while gliding.
+    glide_ratio: f64,
+    /// Samples left in the current glide.
+    glide_remaining: usize,
+    /// Vibrato LFO phase increment per sample.
+    vibrato_step: f64,
+    /// Vibrato depth in semitones (0 = off).
+    vibrato_depth: f64,
+    /// Vibrato LFO phase (0.0 - 1.0).
+    vibrato_phase: f64,
+    /// FM operators replacing the oscillator's waveform, if any. The
+    /// oscillator still holds the carrier frequency and detune.
+    fm: Option<FmOperators>,
+}
+
+/// A sine carrier phase-modulated by a sine modulator.
+#[derive(Debug, Clone)]
+struct FmOperators {
+    ratio: f64,
+    index: f64,
+    carrier_phase: f64,
+    modulator_phase: f64,
+}
+
+impl FmOperators {
+    fn next_sample(&mut self, carrier: &Oscillator) -> f64 {
+        use core::f64::consts::TAU;
+        let freq = carrier.frequency * math::powf(2.0, carrier.detune / 1200.0);
+        let modulator = math::sin(TAU * self.modulator_phase);
+        let sample = math::sin(TAU * self.carrier_phase + self.index * modulator);
+        self.carrier_phase = (self.carrier_phase + freq / carrier.sample_rate()).fract();
+        self.modulator_phase = (self.modulator_phase + freq * self.ratio / carrier.sample_rate()).fract();
+        sample
+    }
 }
 
 /// Parse a waveform string to a Waveform enum value.
@@ -29,6 +69,12 @@ fn parse_waveform(s: &str) -> Waveform {
     }
 }
 
+/// Pitch ratio of a sine vibrato at `phase` (0.0 - 1.0) with `depth`
+/// semitones.
+pub(crate) fn vibrato_ratio(phase: f64, depth: f64) -> f64 {
+    math::powf(2.0, depth * math::sin(core::f64::consts::TAU * phase) / 12.0)
+}
+
 impl Voice {
     pub fn new(sample_rate: f64) -> Self {
         Voice {
@@ -37,37 +83,68 @@ impl Voice {
             velocity: 1.0,
             release_sample: usize::MAX,
             finished: false,
+            glide_target: 0.0,
+            glide_ratio: 1.0,
+            glide_remaining: 0,
+            vibrato_step: 0.0,
+            vibrato_depth: 0.0,
+            vibrato_phase: 0.0,
+            fm: None,
+        }
+    }
+
+    /// Create an oscillator voice from its instrument settings.
+    pub fn with_config(sample_rate: f64, config: &OscillatorConfig) -> Self {
+        let mut voice = Voice::new(sample_rate);
+        voice.oscillator = Oscillator::new(parse_waveform(&config.waveform), sample_rate);
+        if let Some(detune) = config.detune {
+            voice.oscillator.detune = detune;
         }
+        voice.apply_envelope(&config.envelope);
+        voice
     }
 
-    /// Create a voice configured from an InstrumentConfig.
-    pub fn with_config(sample_rate: f64, config: &InstrumentConfig) -> Self {
-        let waveform = parse_waveform(&config.waveform);
-        let mut osc = Oscillator::new(waveform, sample_rate);
+    /// Create a two-operator FM voice from its instrument settings.
+    pub fn with_fm(sample_rate: f64, config: &FmConfig) -> Self {
+        let mut voice = Voice::new(sample_rate);
         if let Some(detune) = config.detune {
-            osc.detune = detune;
+            voice.oscillator.detune = detune;
         }
+        voice.apply_envelope(&config.envelope);
+        voice.fm = Some(FmOperators {
+            ratio: config.ratio,
+            index: config.index,
+            carrier_phase: 0.0,
+            modulator_phase: 0.0,
+        });
+        voice
+    }
 
-        let mut env = Envelope::new(sample_rate);
+    /// Override the envelope stages the config sets.
+    pub fn apply_envelope(&mut self, config: &EnvelopeConfig) {
         if let Some(a) = config.attack {
-            env.attack = a;
+            self.envelope.attack = a;
         }
         if let Some(d) = config.decay {
-            env.decay = d;
+            self.envelope.decay = d;
         }
         if let Some(s) = config.sustain {
-            env.sustain = s;
+            self.envelope.sustain = s;
         }
         if let Some(r) = config.release {
-            env.release = r;
+            self.envelope.release = r;
         }
-
-        Voice {
-            oscillator: osc,
-            envelope: env,
-            velocity: 1.0,
-            release_sample: usize::MAX,
-            finished: false,
+        if let Some(h) = config.hold {
+            self.envelope.hold = h;
+        }
+        if let Some(curve) = config.attack_curve {
+            self.envelope.attack_curve = curve;
+        }
+        if let Some(curve) = config.decay_curve {
+            self.envelope.decay_curve = curve;
+        }
+        if let Some(curve) = config.release_curve {
+            self.envelope.release_curve = curve;
         }
     }
 
@@ -75,11 +152,42 @@ impl Voice {
     pub fn note_on(&mut self, frequency: f64, velocity: f64) {
         self.oscillator.frequency = frequency;
         self.oscillator.reset();
+        if let Some(fm) = &mut self.fm {
+            fm.carrier_phase = 0.0;
+            fm.modulator_phase = 0.0;
+        }
         self.velocity = velocity;
         self.finished = false;
+        self.glide_target = frequency;
+        self.glide_remaining = 0;
         self.envelope.gate_on();
     }
 
+    /// Glide the sounding note to `frequency` over `samples` samples,
+    /// without retriggering the envelope.
+    pub fn glide_to(&mut self, frequency: f64, samples: usize) {
+        self.glide_target = frequency;
+        if samples == 0 {
+            self.oscillator.frequency = frequency;
+            self.glide_remaining = 0;
+        } else {
+            self.glide_ratio = math::powf(frequency / self.oscillator.frequency, 1.0 / samples as f64);
+            self.glide_remaining = samples;
+        }
+    }
+
+    /// Modulate the pitch with a sine LFO of `rate` Hz and `depth`
+    /// semitones.
+    pub fn set_vibrato(&mut self, rate: f64, depth: f64) {
+        self.vibrato_step = rate / self.oscillator.sample_rate();
+        self.vibrato_depth = depth;
+    }
+
+    /// The frequency this voice is playing, or gliding towards.
+    pub fn target_frequency(&self) -> f64 {
+        self.glide_target
+    }
+
     /// Release the note.
     pub fn note_off(&mut self) {
         self.envelope.gate_off();
@@ -91,7 +199,25 @@ impl Voice {
             return 0.0;
         }
 
-        let osc = self.oscillator.next_sample();
+        if self.glide_remaining > 0 {
+            self.glide_remaining -= 1;
+            self.oscillator.frequency = if self.glide_remaining == 0 {
+                self.glide_target
+            } else {
+                self.oscillator.frequency * self.glide_ratio
+            };
+        }
+
+        let base = self.oscillator.frequency;
+        if self.vibrato_depth > 0.0 {
+            self.oscillator.frequency = base * vibrato_ratio(self.vibrato_phase, self.vibrato_depth);
+            self.vibrato_phase = (self.vibrato_phase + self.vibrato_step).fract();
+        }
+        let osc = match &mut self.fm {
+            Some(fm) => fm.next_sample(&self.oscillator),
+            None => self.oscillator.next_sample(),
+        };
+        self.oscillator.frequency = base;
         let env = self.envelope.next_sample();
 
         if self.envelope.is_finished() {
@@ -166,4 +292,56 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn glide_reaches_target_without_retrigger() {
+        let mut v = Voice::new(44100.0);
+        v.note_on(220.0, 1.0);
+        for _ in 0..1000 {
+            v.next_sample();
+        }
+        v.glide_to(440.0, 100);
+        assert_eq!(v.target_frequency(), 440.0);
+        v.next_sample();
+        assert!(v.oscillator.frequency > 220.0 && v.oscillator.frequency < 440.0);
+        for _ in 0..99 {
+            v.next_sample();
+        }
+        assert_eq!(v.oscillator.frequency, 440.0);
+        assert!(!v.is_finished());
+    }
+
+    #[test]
+    fn vibrato_modulates_pitch_around_base() {
+        let mut plain = Voice::new(44100.0);
+        let mut vibrato = Voice::new(44100.0);
+        plain.note_on(440.0, 1.0);
+        vibrato.note_on(440.0, 1.0);
+        vibrato.set_vibrato(5.0, 1.0);
+        let a: Vec<f64> = (0..4410).map(|_| plain.next_sample()).collect();
+        let b: Vec<f64> = (0..4410).map(|_| vibrato.next_sample()).collect();
+        assert_ne!(a, b);
+        // The base frequency is left untouched between samples
+        assert_eq!(vibrato.oscillator.frequency, 440.0);
+    }
+
+    #[test]
+    fn fm_voice_differs_from_plain_sine() {
+        let sine = OscillatorConfig { waveform: "sine".to_string(), ..Default::default() };
+        let mut plain = Voice::with_config(44100.0, &sine);
+        let mut fm = Voice::with_fm(44100.0, &FmConfig { ratio: 2.0, index: 3.0, ..Default::default() });
+        plain.note_on(220.0, 1.0);
+        fm.note_on(220.0, 1.0);
+        let a: Vec<f64> = (0..2000).map(|_| plain.next_sample()).collect();
+        let b: Vec<f64> = (0..2000).map(|_| fm.next_sample()).collect();
+        assert!(b.iter().any(|s| s.abs() > 0.1));
+        assert!(b.iter().all(|s| s.abs() <= 1.0));
+        assert_ne!(a, b);
+
+        // With no modulation the FM voice is a plain sine
+        let mut pure = Voice::with_fm(44100.0, &FmConfig { ratio: 2.0, index: 0.0, ..Default::default() });
+        pure.note_on(220.0, 1.0);
+        let c: Vec<f64> = (0..2000).map(|_| pure.next_sample()).collect();
+        assert!(a.iter().zip(&c).all(|(x, y)| (x - y).abs() < 1e-3));
+    }
 }
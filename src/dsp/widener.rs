@@ -0,0 +1,114 @@
+//! Stereo widener — mid/side width plus a short Haas delay, for spreading
+//! the mostly mono output of the synth voices.
+//!
+//! The delayed copy is added to the side signal only, in opposite polarity
+//! on the two channels, so the mono sum (L + R) is left unchanged. Width
+//! and delay only act above a crossover, keeping the bass centered.
+
+use super::filter::{BiquadFilter, FilterType};
+
+/// A mid/side stereo widener with a Haas delay.
+#[derive(Debug, Clone)]
+pub struct Widener {
+    buffer: Vec<f64>,
+    write_pos: usize,
+    side_highpass: BiquadFilter,
+    haas_highpass: BiquadFilter,
+
+    /// Side gain above the crossover (0.0 = mono, 1.0 = unchanged, 2.0 = widest).
+    pub width: f64,
+    /// Level of the delayed mid added to the side (0.0 to 1.0).
+    pub haas: f64,
+}
+
+impl Widener {
+    /// Create a widener. `haas_delay` is in seconds (1–30 ms) and
+    /// `crossover` in Hz (20–1000).
+    pub fn with_params(sample_rate: f64, width: f64, haas: f64, haas_delay: f64, crossover: f64) -> Self {
+        let delay_samples = ((haas_delay.clamp(0.001, 0.03) * sample_rate) as usize).max(1);
+        let highpass = || {
+            let mut filter = BiquadFilter::new(FilterType::Highpass, sample_rate);
+            filter.set_frequency(crossover.clamp(20.0, 1000.0));
+            filter.update_coefficients();
+            filter
+        };
+        Self {
+            buffer: vec![0.0; delay_samples],
+            write_pos: 0,
+            side_highpass: highpass(),
+            haas_highpass: highpass(),
+            width: width.clamp(0.0, 2.0),
+            haas: haas.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Process a stereo sample pair.
+    #[inline]
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let mid = (left as f64 + right as f64) * 0.5;
+        let side = (left as f64 - right as f64) * 0.5;
+
+        // The buffer holds exactly the delay, so the oldest sample is next
+        let delayed = self.buffer[self.write_pos];
+        self.buffer[self.write_pos] = mid;
+        self.write_pos = (self.write_pos + 1) % self.buffer.len();
+
+        let side_high = self.side_highpass.process(side);
+        let side_low = side - side_high;
+        let haas = self.haas_highpass.process(delayed) * self.haas;
+        let side = side_low + side_high * self.width + haas;
+
+        ((mid + side) as f32, (mid - side) as f32)
+    }
+
+    /// Process a block of stereo audio in-place.
+    pub fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for i in 0..left.len().min(right.len()) {
+            let (out_l, out_r) = self.process(left[i], right[i]);
+            left[i] = out_l;
+            right[i] = out_r;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / 44100.0).sin() as f32 * 0.5).collect()
+    }
+
+    #[test]
+    fn widens_mono_without_changing_the_mono_sum() {
+        let input = sine(1000.0, 4410);
+        let (mut left, mut right) = (input.clone(), input.clone());
+        Widener::with_params(44100.0, 1.5, 0.5, 0.012, 150.0).process_block(&mut left, &mut right);
+
+        let difference = left.iter().zip(&right).fold(0.0_f32, |m, (l, r)| m.max((l - r).abs()));
+        assert!(difference > 0.1, "A mono source should come out wider, got {difference}");
+        for i in 0..input.len() {
+            assert!(((left[i] + right[i]) - 2.0 * input[i]).abs() < 1e-5, "Mono sum changed at {i}");
+        }
+    }
+
+    #[test]
+    fn keeps_bass_centered() {
+        let input = sine(40.0, 44100);
+        let (mut left, mut right) = (input.clone(), input);
+        Widener::with_params(44100.0, 2.0, 1.0, 0.02, 200.0).process_block(&mut left, &mut right);
+        let difference = left[22050..].iter().zip(&right[22050..]).fold(0.0_f32, |m, (l, r)| m.max((l - r).abs()));
+        let peak = left[22050..].iter().fold(0.0_f32, |m, s| m.max(s.abs()));
+        assert!(difference < peak * 0.1, "Bass spread {difference} of peak {peak}");
+    }
+
+    #[test]
+    fn unit_width_without_haas_passes_through() {
+        let (mut left, mut right) = (sine(500.0, 1000), sine(700.0, 1000));
+        let (l0, r0) = (left.clone(), right.clone());
+        Widener::with_params(44100.0, 1.0, 0.0, 0.01, 150.0).process_block(&mut left, &mut right);
+        for i in 0..l0.len() {
+            assert!((left[i] - l0[i]).abs() < 1e-5 && (right[i] - r0[i]).abs() < 1e-5);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::token::{Span, Token};
-use std::fmt;
+use alloc::string::String;
+use core::fmt;
 
 #[derive(Debug)]
 pub enum SongWalkerError {
@@ -12,6 +13,7 @@ pub enum LexError {
     UnexpectedChar { ch: char, pos: usize },
     UnterminatedString { pos: usize },
     UnterminatedRegex { pos: usize },
+    UnterminatedComment { pos: usize },
     InvalidNumber { text: String, pos: usize },
 }
 
@@ -36,7 +38,7 @@ impl fmt::Display for SongWalkerError {
     }
 }
 
-impl std::error::Error for SongWalkerError {}
+impl core::error::Error for SongWalkerError {}
 
 impl fmt::Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -44,12 +46,13 @@ impl fmt::Display for LexError {
             LexError::UnexpectedChar { ch, pos } => write!(f, "Unexpected char '{ch}' at pos {pos}"),
             LexError::UnterminatedString { pos } => write!(f, "Unterminated string at pos {pos}"),
             LexError::UnterminatedRegex { pos } => write!(f, "Unterminated regex at pos {pos}"),
+            LexError::UnterminatedComment { pos } => write!(f, "Unterminated block comment at pos {pos}"),
             LexError::InvalidNumber { text, pos } => write!(f, "Invalid number '{text}' at pos {pos}"),
         }
     }
 }
 
-impl std::error::Error for LexError {}
+impl core::error::Error for LexError {}
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -64,7 +67,7 @@ impl fmt::Display for ParseError {
     }
 }
 
-impl std::error::Error for ParseError {}
+impl core::error::Error for ParseError {}
 
 impl From<LexError> for SongWalkerError {
     fn from(e: LexError) -> Self {
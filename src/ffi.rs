@@ -0,0 +1,369 @@
+//! C ABI bindings for native hosts (DAW plugins, game engines).
+//!
+//! Every fallible function returns an `SW_*` status code; on failure the
+//! message is available from `sw_last_error` until the next call on the
+//! same thread. Buffers and strings returned through out-parameters are
+//! owned by the caller and must be released with the matching `sw_free_*`
+//! function. A panic inside the library never unwinds into C: it is
+//! caught and reported as `SW_INTERNAL_ERROR`. See `include/songwalker.h`
+//! for the C declarations.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::compiler::{self, EventList};
+use crate::dsp::engine::AudioEngine;
+use crate::dsp::renderer::encode_wav_public;
+use crate::parse;
+
+/// Success.
+pub const SW_OK: i32 = 0;
+/// A required pointer argument was null.
+pub const SW_NULL_POINTER: i32 = 1;
+/// The source text was not valid UTF-8.
+pub const SW_INVALID_UTF8: i32 = 2;
+/// The source failed to lex or parse.
+pub const SW_PARSE_ERROR: i32 = 3;
+/// The program failed to compile.
+pub const SW_COMPILE_ERROR: i32 = 4;
+/// An argument was out of range (e.g. a zero sample rate).
+pub const SW_INVALID_ARGUMENT: i32 = 5;
+/// The library panicked; this is a bug in songwalker-core.
+pub const SW_INTERNAL_ERROR: i32 = 6;
+
+/// A compiled song, opaque to C.
+pub struct SwSong {
+    event_list: EventList,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Record `message` as the thread's last error and return `code`.
+fn fail(code: i32, message: impl Into<String>) -> i32 {
+    // Interior NULs would truncate the message; replace them
+    let message = message.into().replace('\0', " ");
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(message).ok());
+    code
+}
+
+/// Run an FFI body, catching any panic so it cannot unwind across the C
+/// boundary. A panic is recorded as `SW_INTERNAL_ERROR` and `on_panic` is
+/// returned instead.
+fn guard<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        fail(SW_INTERNAL_ERROR, format!("Internal error: {}", panic_message(&*payload)));
+        on_panic
+    })
+}
+
+/// The message a panic was raised with, if it was a string.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("panic")
+}
+
+/// Read a NUL-terminated UTF-8 string argument.
+unsafe fn read_str<'a>(s: *const c_char) -> Result<&'a str, i32> {
+    if s.is_null() {
+        return Err(fail(SW_NULL_POINTER, "Source pointer is null."));
+    }
+    // SAFETY: the caller guarantees `s` is a valid NUL-terminated string
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map_err(|e| fail(SW_INVALID_UTF8, format!("Source is not valid UTF-8: {e}")))
+}
+
+/// Hand a buffer to C, writing its pointer and length to the out-parameters.
+unsafe fn give_buffer<T>(buffer: Vec<T>, out_ptr: *mut *mut T, out_len: *mut usize) {
+    let mut buffer = buffer.into_boxed_slice();
+    // SAFETY: the caller checked both out-parameters are non-null
+    unsafe {
+        *out_len = buffer.len();
+        *out_ptr = buffer.as_mut_ptr();
+    }
+    std::mem::forget(buffer);
+}
+
+/// Take back a buffer handed out by `give_buffer`.
+unsafe fn take_buffer<T>(ptr: *mut T, len: usize) {
+    if !ptr.is_null() {
+        // SAFETY: `ptr` and `len` came from `give_buffer`
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)) });
+    }
+}
+
+/// The songwalker-core version as a static NUL-terminated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn sw_version() -> *const c_char {
+    guard(ptr::null(), || {
+        concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+    })
+}
+
+/// The message of the last failed call on this thread, or null. The
+/// string stays valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn sw_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+    })
+}
+
+/// Check that `source` parses. Returns `SW_OK` or `SW_PARSE_ERROR`.
+///
+/// # Safety
+/// `source` must be a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_parse(source: *const c_char) -> i32 {
+    guard(SW_INTERNAL_ERROR, || {
+        let source = match unsafe { read_str(source) } {
+            Ok(source) => source,
+            Err(code) => return code,
+        };
+        match parse(source) {
+            Ok(_) => SW_OK,
+            Err(e) => fail(SW_PARSE_ERROR, e.to_string()),
+        }
+    })
+}
+
+/// Parse and compile `source`, writing the song to `*out_song`. Release it
+/// with `sw_song_free`.
+///
+/// # Safety
+/// `source` must be a valid NUL-terminated string and `out_song` a valid
+/// pointer to write to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_compile(source: *const c_char, out_song: *mut *mut SwSong) -> i32 {
+    guard(SW_INTERNAL_ERROR, || {
+        if out_song.is_null() {
+            return fail(SW_NULL_POINTER, "Output pointer is null.");
+        }
+        let source = match unsafe { read_str(source) } {
+            Ok(source) => source,
+            Err(code) => return code,
+        };
+        let program = match parse(source) {
+            Ok(program) => program,
+            Err(e) => return fail(SW_PARSE_ERROR, e.to_string()),
+        };
+        match compiler::compile(&program) {
+            Ok(event_list) => {
+                // SAFETY: checked non-null above
+                unsafe { *out_song = Box::into_raw(Box::new(SwSong { event_list })) };
+                SW_OK
+            }
+            Err(e) => fail(SW_COMPILE_ERROR, e),
+        }
+    })
+}
+
+/// Free a song returned by `sw_compile`. Null is ignored.
+///
+/// # Safety
+/// `song` must come from `sw_compile` and not have been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_song_free(song: *mut SwSong) {
+    guard((), || {
+        if !song.is_null() {
+            // SAFETY: `song` came from `Box::into_raw` in `sw_compile`
+            drop(unsafe { Box::from_raw(song) });
+        }
+    })
+}
+
+/// The song's compiled event list as JSON, written to `*out_json`.
+/// Release it with `sw_free_string`.
+///
+/// # Safety
+/// `song` must be a live song from `sw_compile` and `out_json` a valid
+/// pointer to write to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_song_to_json(song: *const SwSong, out_json: *mut *mut c_char) -> i32 {
+    guard(SW_INTERNAL_ERROR, || {
+        if song.is_null() || out_json.is_null() {
+            return fail(SW_NULL_POINTER, "Song or output pointer is null.");
+        }
+        // SAFETY: checked non-null; the caller guarantees the song is live
+        let song = unsafe { &*song };
+        let json = serde_json::to_string(&song.event_list).expect("event lists serialize to JSON");
+        // JSON escapes control characters, so there are no interior NULs
+        let json = CString::new(json).expect("JSON has no NUL bytes");
+        unsafe { *out_json = json.into_raw() };
+        SW_OK
+    })
+}
+
+/// Render the song to mono f32 samples at `sample_rate`. The buffer is
+/// written to `*out_samples` and its length to `*out_len`; release it with
+/// `sw_free_samples`.
+///
+/// # Safety
+/// `song` must be a live song from `sw_compile`; the out-parameters must be
+/// valid pointers to write to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_render(
+    song: *const SwSong,
+    sample_rate: u32,
+    out_samples: *mut *mut f32,
+    out_len: *mut usize,
+) -> i32 {
+    guard(SW_INTERNAL_ERROR, || {
+        if song.is_null() || out_samples.is_null() || out_len.is_null() {
+            return fail(SW_NULL_POINTER, "Song or output pointer is null.");
+        }
+        if sample_rate == 0 {
+            return fail(SW_INVALID_ARGUMENT, "Sample rate must be positive.");
+        }
+        // SAFETY: checked non-null; the caller guarantees the song is live
+        let song = unsafe { &*song };
+        let engine = AudioEngine::new(sample_rate as f64);
+        let samples: Vec<f32> = engine.render(&song.event_list).iter().map(|&s| s as f32).collect();
+        unsafe { give_buffer(samples, out_samples, out_len) };
+        SW_OK
+    })
+}
+
+/// Render the song to a 16-bit stereo WAV file in memory. The bytes are
+/// written to `*out_bytes` and the length to `*out_len`; release them with
+/// `sw_free_bytes`.
+///
+/// # Safety
+/// `song` must be a live song from `sw_compile`; the out-parameters must be
+/// valid pointers to write to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_render_wav(
+    song: *const SwSong,
+    sample_rate: u32,
+    out_bytes: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    guard(SW_INTERNAL_ERROR, || {
+        if song.is_null() || out_bytes.is_null() || out_len.is_null() {
+            return fail(SW_NULL_POINTER, "Song or output pointer is null.");
+        }
+        if sample_rate == 0 {
+            return fail(SW_INVALID_ARGUMENT, "Sample rate must be positive.");
+        }
+        // SAFETY: checked non-null; the caller guarantees the song is live
+        let song = unsafe { &*song };
+        let engine = AudioEngine::new(sample_rate as f64);
+        let wav = encode_wav_public(&engine.render_pcm_i16(&song.event_list), sample_rate, 2);
+        unsafe { give_buffer(wav, out_bytes, out_len) };
+        SW_OK
+    })
+}
+
+/// Free samples returned by `sw_render`. Null is ignored.
+///
+/// # Safety
+/// `samples` and `len` must be exactly as returned by `sw_render`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_free_samples(samples: *mut f32, len: usize) {
+    guard((), || {
+        unsafe { take_buffer(samples, len) };
+    })
+}
+
+/// Free bytes returned by `sw_render_wav`. Null is ignored.
+///
+/// # Safety
+/// `bytes` and `len` must be exactly as returned by `sw_render_wav`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_free_bytes(bytes: *mut u8, len: usize) {
+    guard((), || {
+        unsafe { take_buffer(bytes, len) };
+    })
+}
+
+/// Free a string returned by `sw_song_to_json`. Null is ignored.
+///
+/// # Safety
+/// `s` must come from this library and not have been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sw_free_string(s: *mut c_char) {
+    guard((), || {
+        if !s.is_null() {
+            // SAFETY: `s` came from `CString::into_raw`
+            drop(unsafe { CString::from_raw(s) });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(sw_last_error()) }.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn compile_render_and_free() {
+        let source = c"track t() {\n    C4 1\n}\nt();";
+        let mut song = ptr::null_mut();
+        assert_eq!(unsafe { sw_compile(source.as_ptr(), &mut song) }, SW_OK);
+        assert!(!song.is_null());
+
+        let (mut samples, mut len) = (ptr::null_mut(), 0);
+        assert_eq!(unsafe { sw_render(song, 44100, &mut samples, &mut len) }, SW_OK);
+        let rendered = unsafe { std::slice::from_raw_parts(samples, len) };
+        assert!(rendered.iter().any(|s| s.abs() > 0.001));
+        unsafe { sw_free_samples(samples, len) };
+
+        let (mut wav, mut wav_len) = (ptr::null_mut(), 0);
+        assert_eq!(unsafe { sw_render_wav(song, 44100, &mut wav, &mut wav_len) }, SW_OK);
+        assert_eq!(unsafe { std::slice::from_raw_parts(wav, 4) }, b"RIFF");
+        unsafe { sw_free_bytes(wav, wav_len) };
+
+        let mut json = ptr::null_mut();
+        assert_eq!(unsafe { sw_song_to_json(song, &mut json) }, SW_OK);
+        assert!(unsafe { CStr::from_ptr(json) }.to_str().unwrap().contains("\"C4\""));
+        unsafe { sw_free_string(json) };
+
+        unsafe { sw_song_free(song) };
+        let version = unsafe { CStr::from_ptr(sw_version()) }.to_str().unwrap();
+        assert_eq!(version, crate::VERSION);
+    }
+
+    #[test]
+    fn errors_return_codes_and_messages() {
+        assert_eq!(unsafe { sw_parse(c"track t( {".as_ptr()) }, SW_PARSE_ERROR);
+        assert!(!last_error().is_empty());
+
+        let mut song = ptr::null_mut();
+        let unknown = c"track t() {\n    track.instrument = nothing;\n    C4 1\n}\nt();";
+        assert_eq!(unsafe { sw_compile(unknown.as_ptr(), &mut song) }, SW_COMPILE_ERROR);
+        assert!(song.is_null());
+        assert!(last_error().contains("nothing"), "{}", last_error());
+
+        assert_eq!(unsafe { sw_parse(ptr::null()) }, SW_NULL_POINTER);
+        assert_eq!(unsafe { sw_parse(c"C4 \xff".as_ptr()) }, SW_INVALID_UTF8);
+
+        let mut samples = ptr::null_mut();
+        let mut len = 0;
+        assert_eq!(unsafe { sw_render(ptr::null(), 44100, &mut samples, &mut len) }, SW_NULL_POINTER);
+    }
+
+    #[test]
+    fn panics_become_internal_errors() {
+        let code = guard(SW_INTERNAL_ERROR, || -> i32 { panic!("voice table overflow") });
+        assert_eq!(code, SW_INTERNAL_ERROR);
+        assert!(last_error().contains("voice table overflow"), "{}", last_error());
+
+        let code = guard(SW_INTERNAL_ERROR, || -> i32 { panic!("{} voices", 3) });
+        assert_eq!(code, SW_INTERNAL_ERROR);
+        assert!(last_error().contains("3 voices"), "{}", last_error());
+
+        // Calls after a caught panic work normally
+        assert_eq!(unsafe { sw_parse(c"track t() {\n    C4 1\n}\nt();".as_ptr()) }, SW_OK);
+    }
+}
@@ -0,0 +1,24 @@
+//! Number formatting for generated `.sw` source.
+
+/// Tolerance when snapping a beat count to a whole number or fraction.
+const EPSILON: f64 = 1e-6;
+
+/// A step or rest length: a whole number, `N/D` fraction or decimal.
+pub(crate) fn format_beats(beats: f64) -> String {
+    if (beats - beats.round()).abs() < EPSILON {
+        return format!("{}", beats.round());
+    }
+    for denominator in [2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0, 24.0, 32.0, 48.0, 64.0] {
+        let numerator = beats * denominator;
+        if (numerator - numerator.round()).abs() < EPSILON {
+            return format!("{}/{}", numerator.round(), denominator);
+        }
+    }
+    format_decimal(beats)
+}
+
+/// A number with at most six decimals and no trailing zeros.
+pub(crate) fn format_decimal(value: f64) -> String {
+    let text = format!("{value:.6}");
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
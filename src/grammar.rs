@@ -0,0 +1,491 @@
+//! Machine-readable description of the `.sw` grammar, for generating
+//! editor grammars (tree-sitter, TextMate) and syntax docs.
+//!
+//! The rules mirror `parser.rs` in W3C EBNF notation. Upper-case names
+//! are tokens from the lexer. Every rule carries examples, and the tests
+//! run them through the lexer and parser so the two cannot drift apart.
+
+use alloc::string::String;
+use serde::Serialize;
+
+/// Where a rule's examples are valid, and so how tests check them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum RuleScope {
+    /// A whole program or top-level statement.
+    Program,
+    /// A statement inside a track body.
+    Track,
+    /// The right-hand side of an assignment.
+    Expression,
+    /// A single lexer token.
+    Token,
+}
+
+/// One production of the grammar.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GrammarRule {
+    pub name: &'static str,
+    /// Right-hand side of the production.
+    pub ebnf: &'static str,
+    pub doc: &'static str,
+    pub scope: RuleScope,
+    /// Source the rule matches.
+    pub examples: &'static [&'static str],
+}
+
+/// The grammar returned by `get_grammar`.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Grammar {
+    /// Words the lexer reserves; they cannot name tracks or constants.
+    pub keywords: &'static [&'static str],
+    /// Words with a special meaning only where the parser expects them,
+    /// e.g. `poly` before `(3, 4) {`.
+    pub contextual_keywords: &'static [&'static str],
+    /// Productions, starting with `program`.
+    pub rules: &'static [GrammarRule],
+}
+
+pub const KEYWORDS: &[&str] = &["track", "const", "let", "for"];
+
+pub const CONTEXTUAL_KEYWORDS: &[&str] =
+    &["poly", "maybe", "marker", "cue", "play", "at", "choose", "r", "R", "_", "pan", "brightness"];
+
+pub const RULES: &[GrammarRule] = &[
+    // ── Program ─────────────────────────────────────────────
+    GrammarRule {
+        name: "program",
+        ebnf: r#"version_pragma? (statement ";"?)*"#,
+        doc: "A `.sw` file.",
+        scope: RuleScope::Program,
+        examples: &["#version 2\nconst x = 1;\nriff();"],
+    },
+    GrammarRule {
+        name: "version_pragma",
+        ebnf: r##""#version" NUMBER"##,
+        doc: "Language version, once, before any statement.",
+        scope: RuleScope::Program,
+        examples: &["#version 1"],
+    },
+    GrammarRule {
+        name: "statement",
+        ebnf: "track_def | const_decl | assignment | track_call | marker | play | COMMENT",
+        doc: "A top-level statement.",
+        scope: RuleScope::Program,
+        examples: &["const bpm = 120", "song.bpm = 96", "// intro"],
+    },
+    GrammarRule {
+        name: "track_def",
+        ebnf: r#"DOC_COMMENT* "track" IDENT "(" (IDENT ("," IDENT)*)? ")" block"#,
+        doc: "Define a track; its parameters are bound by each call.",
+        scope: RuleScope::Program,
+        examples: &["/// Bass line\ntrack bass(inst, oct) {\n    C2 1\n}", "track empty() {}"],
+    },
+    GrammarRule {
+        name: "const_decl",
+        ebnf: r#""const" IDENT "=" expr"#,
+        doc: "A song-level constant.",
+        scope: RuleScope::Program,
+        examples: &[r#"const piano = loadPreset("FluidR3/Piano")"#],
+    },
+    GrammarRule {
+        name: "assignment",
+        ebnf: r#"dotted_name "=" expr"#,
+        doc: "Set a song, track or variable property.",
+        scope: RuleScope::Program,
+        examples: &["track.beatsPerMeasure = 3", "song.endMode = \"tail\""],
+    },
+    GrammarRule {
+        name: "dotted_name",
+        ebnf: r#"(IDENT | "track") ("." IDENT)*"#,
+        doc: "A name with optional property path.",
+        scope: RuleScope::Program,
+        examples: &["track.instrument = x", "a.b.c = 1"],
+    },
+    GrammarRule {
+        name: "track_call",
+        ebnf: r#"IDENT modifiers "(" (expr ("," expr)*)? ")" step?"#,
+        doc: "Play a track; the trailing duration advances the caller.",
+        scope: RuleScope::Program,
+        examples: &["riff()", "riff*80@2(piano, 3) 4"],
+    },
+    GrammarRule {
+        name: "marker",
+        ebnf: r#"("marker" | "cue") STRING"#,
+        doc: "A named position for navigation and looping.",
+        scope: RuleScope::Program,
+        examples: &["marker \"Chorus\"", "cue \"drop\""],
+    },
+    GrammarRule {
+        name: "play",
+        ebnf: r#""play" IDENT ("at" NUMBER)?"#,
+        doc: "Play a frozen or registered handle.",
+        scope: RuleScope::Program,
+        examples: &["play intro", "play intro at 16"],
+    },
+    // ── Track Body ──────────────────────────────────────────
+    GrammarRule {
+        name: "block",
+        ebnf: r#""{" (track_statement ";"?)* "}""#,
+        doc: "A track body.",
+        scope: RuleScope::Track,
+        examples: &["maybe(0.5) {\n    C4 1; D4 1\n}"],
+    },
+    GrammarRule {
+        name: "track_statement",
+        ebnf: "note | chord | rest | bar_rest | let_decl | assignment | track_call | for_loop | poly | maybe | marker | COMMENT",
+        doc: "A statement inside a track body.",
+        scope: RuleScope::Track,
+        examples: &["C4 1", "[C4, E4] 1", "2", "track.velocity = 90", "fill()"],
+    },
+    GrammarRule {
+        name: "let_decl",
+        ebnf: r#""let" IDENT "=" expr"#,
+        doc: "A variable for the rest of the block; assign with `name = expr`, play a note variable by name.",
+        scope: RuleScope::Track,
+        examples: &["let root = C4", "let step = 1/4", "let n = 3; n = n + 1"],
+    },
+    GrammarRule {
+        name: "note",
+        ebnf: r#"(IDENT | choose) ("->" IDENT)? pitch_modifier* articulation* modifiers note_tail"#,
+        doc: "Play a pitch. `C4_` (legato) is one IDENT ending in `_`.",
+        scope: RuleScope::Track,
+        examples: &["C4", "C4*90@/8 /4", "C4->G4 1", "F4~v^+2!' 1", "C4_ /2", "Bb3*+10 1"],
+    },
+    GrammarRule {
+        name: "note_tail",
+        ebnf: "STRING? note_expression? step? note_expression? STRING?",
+        doc: "Lyric, expression and step after a note, in either order around the step.",
+        scope: RuleScope::Track,
+        examples: &["C4 \"la\" 1", "C4 1 \"la\"", "C4 {pan: -0.5} 1", "C4 1 {brightness: 0.7}"],
+    },
+    GrammarRule {
+        name: "choose",
+        ebnf: r#""choose" "(" "[" IDENT ("," IDENT)* "]" ")""#,
+        doc: "Pick one of the pitches at random (seeded).",
+        scope: RuleScope::Track,
+        examples: &["choose([C4, E4, G4]) /4"],
+    },
+    GrammarRule {
+        name: "pitch_modifier",
+        ebnf: r#""~" "v" | "^" ("+" | "-")? NUMBER"#,
+        doc: "Vibrato, or a bend in semitones.",
+        scope: RuleScope::Track,
+        examples: &["C4~v 1", "C4^-2 1"],
+    },
+    GrammarRule {
+        name: "articulation",
+        ebnf: r#""!" | "'""#,
+        doc: "Accent or staccato.",
+        scope: RuleScope::Track,
+        examples: &["C4! 1", "C4' 1", "C4!' 1"],
+    },
+    GrammarRule {
+        name: "modifiers",
+        ebnf: r#"("*" ("+" | "-")? NUMBER)? ("@" simple_duration)?"#,
+        doc: "Velocity (absolute or relative to track.velocity) and audible length.",
+        scope: RuleScope::Track,
+        examples: &["C4*90 1", "C4*-10 1", "C4@/8 /4", "C4@. 1"],
+    },
+    GrammarRule {
+        name: "chord",
+        ebnf: r#""[" chord_note ("," chord_note)* "]" modifiers note_expression? step? note_expression?"#,
+        doc: "Notes started together.",
+        scope: RuleScope::Track,
+        examples: &["[C4, E4, G4] 1", "[C3@2, G3]@/2 {pan: 0.3} /2"],
+    },
+    GrammarRule {
+        name: "chord_note",
+        ebnf: r#"IDENT ("@" duration)?"#,
+        doc: "A chord member with an optional audible length of its own.",
+        scope: RuleScope::Track,
+        examples: &["[C4@/2, E4] 1"],
+    },
+    GrammarRule {
+        name: "note_expression",
+        ebnf: r#""{" (expression_field ("," expression_field)*)? "}""#,
+        doc: "Per-note expression.",
+        scope: RuleScope::Track,
+        examples: &["C4 {pan: -1, brightness: 0.5} 1", "C4 {} 1"],
+    },
+    GrammarRule {
+        name: "expression_field",
+        ebnf: r#"("pan" | "brightness") ":" ("+" | "-")? NUMBER"#,
+        doc: "A note expression value.",
+        scope: RuleScope::Track,
+        examples: &["C4 {pan: +0.5} 1"],
+    },
+    GrammarRule {
+        name: "rest",
+        ebnf: r#"NUMBER ("/" NUMBER)? "."* | "."+ | ("r" | "_") duration | REST_NAME "."*"#,
+        doc: "Advance time without playing.",
+        scope: RuleScope::Track,
+        examples: &["1/2", ".", "r /8", "_ 2", "r4", "r4."],
+    },
+    GrammarRule {
+        name: "bar_rest",
+        ebnf: r#""R" ("*" NUMBER)?"#,
+        doc: "Rest for whole bars.",
+        scope: RuleScope::Track,
+        examples: &["R", "R*4"],
+    },
+    GrammarRule {
+        name: "for_loop",
+        ebnf: r#""for" "(" loop_clause? ";" expr? ";" loop_clause? ")" block"#,
+        doc: "A counted loop.",
+        scope: RuleScope::Track,
+        examples: &["for (let i = 0; i < 4; i++) {\n    C4 /4\n}", "for (; ; ) {}"],
+    },
+    GrammarRule {
+        name: "loop_clause",
+        ebnf: r#""let" IDENT "=" expr | IDENT ("=" expr | "++" | "--")"#,
+        doc: "The init or update part of a loop header.",
+        scope: RuleScope::Track,
+        examples: &["for (i = 8; i >= 0; i = i - 2) {}", "for (let n = 3; n != 0; n--) {}"],
+    },
+    GrammarRule {
+        name: "poly",
+        ebnf: r#""poly" "(" NUMBER "," NUMBER ")" block"#,
+        doc: "Fit the body's steps into a span of beats.",
+        scope: RuleScope::Track,
+        examples: &["poly(3, 2) {\n    C4 1\n    D4 1\n    E4 1\n}"],
+    },
+    GrammarRule {
+        name: "maybe",
+        ebnf: r#""maybe" "(" NUMBER ")" block"#,
+        doc: "Play the body with a probability from 0 to 1.",
+        scope: RuleScope::Track,
+        examples: &["maybe(0.25) { C5 /4 }"],
+    },
+    // ── Durations ───────────────────────────────────────────
+    GrammarRule {
+        name: "duration",
+        ebnf: r#""+"? (("/" NUMBER | NUMBER ("/" NUMBER)?) "."* | "."+)"#,
+        doc: "Beats (`2`), a fraction (`3/4`), an inverse (`/4` is a quarter beat) or dots (`.`).",
+        scope: RuleScope::Track,
+        examples: &["C4 2", "C4 3/4", "C4 /4.", "C4 ..", "C4 +1"],
+    },
+    GrammarRule {
+        name: "step",
+        ebnf: "duration | IDENT",
+        doc: "How far a note, chord or track call advances; IDENT is a `let` variable.",
+        scope: RuleScope::Track,
+        examples: &["let s = 1/8; C4 s", "let beats = 2\n[C4, E4] beats"],
+    },
+    GrammarRule {
+        name: "simple_duration",
+        ebnf: r#"("/" NUMBER | NUMBER) "."* | "."+"#,
+        doc: "A duration after `@`, without the fraction form.",
+        scope: RuleScope::Track,
+        examples: &["C4@/4. 1", "C4@2 1", "C4@.. 1"],
+    },
+    // ── Expressions ─────────────────────────────────────────
+    GrammarRule {
+        name: "expr",
+        ebnf: "unary (binary_op unary)*",
+        doc: "A value, with operators applied by precedence.",
+        scope: RuleScope::Expression,
+        examples: &["1 + 2 * 3", "i < 4 && !done", "(a || b) == c"],
+    },
+    GrammarRule {
+        name: "binary_op",
+        ebnf: r#""||" | "&&" | "==" | "!=" | "<" | "<=" | ">" | ">=" | "+" | "-" | "*" | "/""#,
+        doc: "Loosest to tightest: `||`, `&&`, `==` `!=`, comparisons, `+` `-`, `*` `/`; all left-associative.",
+        scope: RuleScope::Expression,
+        examples: &["a <= b", "a >= b", "a - b / c"],
+    },
+    GrammarRule {
+        name: "unary",
+        ebnf: r#"("+" | "-" | "!") unary | primary"#,
+        doc: "A negated or inverted operand; `-3` is a single number.",
+        scope: RuleScope::Expression,
+        examples: &["-3", "-x", "!flag"],
+    },
+    GrammarRule {
+        name: "primary",
+        ebnf: r#"NUMBER ("/" NUMBER)? | STRING | REGEX | IDENT ("(" (expr ("," expr)*)? ")" | ("." IDENT)+)? | array | object | "(" expr ")""#,
+        doc: "An operand. `3/4` is a duration literal, not a division.",
+        scope: RuleScope::Expression,
+        examples: &["3/4", "\"text\"", "/Piano/i", "x", "loadPreset(\"a\", 2)", "song.bpm", "(1)"],
+    },
+    GrammarRule {
+        name: "array",
+        ebnf: r#""[" (expr ("," expr)*)? "]""#,
+        doc: "A list of values.",
+        scope: RuleScope::Expression,
+        examples: &["[]", "[1, \"two\", [3]]"],
+    },
+    GrammarRule {
+        name: "object",
+        ebnf: r#""{" (property ("," property)* ","?)? "}""#,
+        doc: "A map of values; a trailing comma is allowed.",
+        scope: RuleScope::Expression,
+        examples: &["{}", "{attack: 0.01, \"release\": 0.5,}"],
+    },
+    GrammarRule {
+        name: "property",
+        ebnf: r#"(IDENT | STRING) ":" expr"#,
+        doc: "An object entry.",
+        scope: RuleScope::Expression,
+        examples: &["{type: \"sine\"}"],
+    },
+    // ── Tokens ──────────────────────────────────────────────
+    GrammarRule {
+        name: "IDENT",
+        ebnf: r#"(XID_START | "_") XID_CONTINUE*"#,
+        doc: "A Unicode identifier that is not a keyword.",
+        scope: RuleScope::Token,
+        examples: &["riff", "C4", "_", "Intro日本"],
+    },
+    GrammarRule {
+        name: "REST_NAME",
+        ebnf: r#""r" [0-9]+"#,
+        doc: "An IDENT like `r4`, read as a rest of that many beats.",
+        scope: RuleScope::Token,
+        examples: &["r4", "r16"],
+    },
+    GrammarRule {
+        name: "NUMBER",
+        ebnf: r#"[0-9]+ ("." [0-9]+)?"#,
+        doc: "A non-negative number; signs are separate tokens.",
+        scope: RuleScope::Token,
+        examples: &["4", "0.25"],
+    },
+    GrammarRule {
+        name: "STRING",
+        ebnf: r#"'"' [^"]* '"' | "'" [^']* "'""#,
+        doc: "Backslash escapes `\\n`, `\\t` and `\\\\`.",
+        scope: RuleScope::Token,
+        examples: &["\"Piano\"", "'it\\n'"],
+    },
+    GrammarRule {
+        name: "REGEX",
+        ebnf: r#""/" [^/]+ "/" [a-zA-Z]*"#,
+        doc: "A regex literal, where an expression starts (after `=`, `(`, `,`, `[`, `;` or `{`) and closed on the same line.",
+        scope: RuleScope::Expression,
+        examples: &["/FluidR3.*Guitar/i", "/Piano/"],
+    },
+    GrammarRule {
+        name: "COMMENT",
+        ebnf: r#""//" [^\n]* | "/*" .* "*/""#,
+        doc: "Comments are kept in the AST for formatting.",
+        scope: RuleScope::Token,
+        examples: &["// note", "/* block */"],
+    },
+    GrammarRule {
+        name: "DOC_COMMENT",
+        ebnf: r#""///" [^\n]*"#,
+        doc: "Documents the track definition that follows.",
+        scope: RuleScope::Token,
+        examples: &["/// Main riff"],
+    },
+];
+
+/// The whole grammar.
+pub fn grammar() -> Grammar {
+    Grammar { keywords: KEYWORDS, contextual_keywords: CONTEXTUAL_KEYWORDS, rules: RULES }
+}
+
+/// The rules as EBNF text, one `name ::= rhs` production per line.
+pub fn to_ebnf() -> String {
+    let mut out = String::new();
+    for rule in RULES {
+        out.push_str(&alloc::format!("/* {} */\n{} ::= {}\n", rule.doc, rule.name, rule.ebnf));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::token::Token;
+    use alloc::format;
+    use alloc::vec::Vec;
+
+    /// Names an EBNF right-hand side refers to, skipping quoted
+    /// terminals and character classes.
+    fn references(ebnf: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut chars = ebnf.chars().peekable();
+        let mut word = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' | '\'' | '[' => {
+                    let close = if c == '[' { ']' } else { c };
+                    for d in chars.by_ref() {
+                        if d == close {
+                            break;
+                        }
+                    }
+                }
+                c if c.is_ascii_alphanumeric() || c == '_' => {
+                    word.push(c);
+                    continue;
+                }
+                _ => {}
+            }
+            if !word.is_empty() {
+                names.push(core::mem::take(&mut word));
+            }
+        }
+        if !word.is_empty() {
+            names.push(word);
+        }
+        names
+    }
+
+    #[test]
+    fn every_referenced_rule_is_defined() {
+        const BUILTINS: &[&str] = &["XID_START", "XID_CONTINUE"];
+        for rule in RULES {
+            for name in references(rule.ebnf) {
+                assert!(
+                    BUILTINS.contains(&name.as_str()) || RULES.iter().any(|r| r.name == name),
+                    "{} refers to undefined '{name}'",
+                    rule.name
+                );
+            }
+        }
+        assert_eq!(RULES[0].name, "program");
+        assert!(to_ebnf().contains("\nchord ::= \"[\" chord_note"));
+    }
+
+    #[test]
+    fn examples_match_the_parser() {
+        for rule in RULES {
+            assert!(!rule.examples.is_empty(), "{} has no examples", rule.name);
+            for example in rule.examples {
+                let source = match rule.scope {
+                    RuleScope::Program => example.to_string(),
+                    RuleScope::Track => format!("track t() {{\n{example}\n}}"),
+                    RuleScope::Expression => format!("const x = {example}"),
+                    RuleScope::Token => {
+                        let tokens = Lexer::new(example).tokenize().unwrap();
+                        assert_eq!(tokens.len(), 2, "{}: '{example}' is not one token", rule.name);
+                        continue;
+                    }
+                };
+                if let Err(e) = crate::parse(&source) {
+                    panic!("{}: '{example}' does not parse: {e}", rule.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keywords_match_the_lexer() {
+        for keyword in KEYWORDS {
+            let token = Lexer::new(keyword).tokenize().unwrap().remove(0).token;
+            assert!(!matches!(token, Token::Ident(_)), "'{keyword}' lexes as an identifier");
+        }
+        for word in CONTEXTUAL_KEYWORDS {
+            let token = Lexer::new(word).tokenize().unwrap().remove(0).token;
+            assert_eq!(token, Token::Ident(word.to_string()));
+        }
+    }
+}
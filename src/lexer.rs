@@ -1,3 +1,7 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
 use crate::error::LexError;
 use crate::token::{Span, Spanned, Token};
 
@@ -9,6 +13,8 @@ pub struct Lexer {
     byte_offsets: Vec<usize>,
     pos: usize,
     prev_significant: Option<Token>,
+    /// Whether `/` may open a regex literal; off, it is always `Slash`.
+    regex_literals: bool,
 }
 
 impl Lexer {
@@ -27,16 +33,23 @@ impl Lexer {
             byte_offsets,
             pos: 0,
             prev_significant: None,
+            regex_literals: true,
         }
     }
 
+    /// Enable or disable regex literals (enabled by default).
+    pub fn regex_literals(mut self, enabled: bool) -> Self {
+        self.regex_literals = enabled;
+        self
+    }
+
     pub fn tokenize(&mut self) -> Result<Vec<Spanned>, LexError> {
         let mut tokens = Vec::new();
         loop {
             let spanned = self.next_token()?;
             let is_eof = spanned.token == Token::EOF;
             match &spanned.token {
-                Token::Newline | Token::Comment(_) => {}
+                Token::Newline | Token::Comment(_) | Token::DocComment(_) => {}
                 _ => {
                     self.prev_significant = Some(spanned.token.clone());
                 }
@@ -87,6 +100,27 @@ impl Lexer {
         }
     }
 
+    /// Whether the `/` at the cursor opens a regex literal: it must follow
+    /// a token an expression can follow, and close on the same line
+    /// (`riff(a, /4)` keeps its `/` as a duration).
+    fn starts_regex(&self) -> bool {
+        if !self.regex_literals || !self.is_regex_context() || self.peek_at(1).is_none_or(|c| c == ' ') {
+            return false;
+        }
+        let mut i = self.pos + 1;
+        while let Some(&c) = self.chars.get(i) {
+            match c {
+                '\n' => return false,
+                '\\' => i += 1,
+                // A `//` ahead starts a comment rather than closing a regex
+                '/' => return self.chars.get(i + 1) != Some(&'/'),
+                _ => {}
+            }
+            i += 1;
+        }
+        false
+    }
+
     /// Convert a char index to a byte offset.
     fn byte_pos_of(&self, char_idx: usize) -> usize {
         self.byte_offsets[char_idx.min(self.chars.len())]
@@ -124,9 +158,11 @@ impl Lexer {
                 Ok(self.spanned(Token::Newline, start))
             }
             '/' if self.peek_at(1) == Some('/') => self.lex_comment(start),
-            '/' if self.is_regex_context() && self.peek_at(1).map_or(false, |c| c != ' ') => {
-                self.lex_regex(start)
-            }
+            '/' if self.peek_at(1) == Some('*') => match self.lex_block_comment(start)? {
+                Some(comment) => Ok(comment),
+                None => self.next_token(),
+            },
+            '/' if self.starts_regex() => self.lex_regex(start),
             '/' => {
                 self.advance();
                 Ok(self.spanned(Token::Slash, start))
@@ -151,6 +187,10 @@ impl Lexer {
                 self.advance();
                 Ok(self.spanned(Token::Comma, start))
             }
+            '=' if self.peek_at(1) == Some('=') => {
+                self.pos += 2;
+                Ok(self.spanned(Token::EqEq, start))
+            }
             '=' => {
                 self.advance();
                 Ok(self.spanned(Token::Eq, start))
@@ -179,18 +219,56 @@ impl Lexer {
                 self.advance();
                 Ok(self.spanned(Token::RBrace, start))
             }
+            '<' if self.peek_at(1) == Some('=') => {
+                self.pos += 2;
+                Ok(self.spanned(Token::LtEq, start))
+            }
             '<' => {
                 self.advance();
                 Ok(self.spanned(Token::Lt, start))
             }
+            '>' if self.peek_at(1) == Some('=') => {
+                self.pos += 2;
+                Ok(self.spanned(Token::GtEq, start))
+            }
             '>' => {
                 self.advance();
                 Ok(self.spanned(Token::Gt, start))
             }
+            '&' if self.peek_at(1) == Some('&') => {
+                self.pos += 2;
+                Ok(self.spanned(Token::AndAnd, start))
+            }
+            '|' if self.peek_at(1) == Some('|') => {
+                self.pos += 2;
+                Ok(self.spanned(Token::OrOr, start))
+            }
+            '~' => {
+                self.advance();
+                Ok(self.spanned(Token::Tilde, start))
+            }
+            '^' => {
+                self.advance();
+                Ok(self.spanned(Token::Caret, start))
+            }
             ':' => {
                 self.advance();
                 Ok(self.spanned(Token::Colon, start))
             }
+            '!' if self.peek_at(1) == Some('=') => {
+                self.pos += 2;
+                Ok(self.spanned(Token::BangEq, start))
+            }
+            '!' => {
+                self.advance();
+                Ok(self.spanned(Token::Bang, start))
+            }
+            // `C4'` / `C4!'` (staccato): a quote touching a note does not
+            // open a string
+            '\'' if start > 0 && (self.chars[start - 1] == '!' || unicode_ident::is_xid_continue(self.chars[start - 1])) => {
+                self.advance();
+                Ok(self.spanned(Token::Apostrophe, start))
+            }
             '+' if self.peek_at(1) == Some('+') => {
                 self.pos += 2;
                 Ok(self.spanned(Token::PlusPlus, start))
@@ -203,25 +281,73 @@ impl Lexer {
                 self.pos += 2;
                 Ok(self.spanned(Token::MinusMinus, start))
             }
+            '-' if self.peek_at(1) == Some('>') => {
+                self.pos += 2;
+                Ok(self.spanned(Token::Arrow, start))
+            }
             '-' => {
                 self.advance();
                 Ok(self.spanned(Token::Minus, start))
             }
+            '#' => Ok(self.lex_pragma(start)),
             '"' | '\'' => self.lex_string(start),
             c if c.is_ascii_digit() => self.lex_number(start),
-            c if c.is_ascii_alphabetic() || c == '_' => self.lex_ident(start),
+            c if c == '_' || unicode_ident::is_xid_start(c) => self.lex_ident(start),
             _ => Err(LexError::UnexpectedChar { ch, pos: self.byte_pos_of(start) }),
         }
     }
 
     fn lex_comment(&mut self, start: usize) -> Result<Spanned, LexError> {
         self.pos += 2; // skip //
+        // `///` is a doc comment, but `////...` is an ordinary comment
+        let is_doc = self.peek_at(0) == Some('/') && self.peek_at(1) != Some('/');
+        if is_doc {
+            self.pos += 1;
+        }
+        let text_start = self.pos;
+        while self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
+            self.pos += 1;
+        }
+        let text = self.chars[text_start..self.pos].iter().collect::<String>().trim().to_string();
+        let token = if is_doc { Token::DocComment(text) } else { Token::Comment(text) };
+        Ok(self.spanned(token, start))
+    }
+
+    /// Lex a `#name value` directive up to the end of the line.
+    fn lex_pragma(&mut self, start: usize) -> Spanned {
+        self.pos += 1; // skip #
         let text_start = self.pos;
         while self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
             self.pos += 1;
         }
+        let text = self.chars[text_start..self.pos].iter().collect::<String>().trim().to_string();
+        self.spanned(Token::Pragma(text), start)
+    }
+
+    /// Lex a `/* ... */` block comment. Only a comment that ends its line
+    /// becomes a `Comment` token; one followed by code on the same line
+    /// (`C4 /* accent */ 1`) is skipped like whitespace.
+    fn lex_block_comment(&mut self, start: usize) -> Result<Option<Spanned>, LexError> {
+        self.pos += 2; // skip /*
+        let text_start = self.pos;
+        loop {
+            match self.peek_at(0) {
+                Some('*') if self.peek_at(1) == Some('/') => break,
+                Some(_) => self.pos += 1,
+                None => return Err(LexError::UnterminatedComment { pos: self.byte_pos_of(start) }),
+            }
+        }
         let text: String = self.chars[text_start..self.pos].iter().collect();
-        Ok(self.spanned(Token::Comment(text.trim().to_string()), start))
+        self.pos += 2; // skip */
+
+        let rest_of_line = self.chars[self.pos..].iter().take_while(|&&c| c != '\n');
+        let ends_line = rest_of_line.clone().all(|c| c.is_whitespace())
+            || rest_of_line.collect::<String>().trim_start().starts_with("//");
+        if !ends_line {
+            return Ok(None);
+        }
+        let text = text.lines().map(str::trim).collect::<Vec<_>>().join("\n");
+        Ok(Some(self.spanned(Token::Comment(text.trim().to_string()), start)))
     }
 
     fn lex_string(&mut self, start: usize) -> Result<Spanned, LexError> {
@@ -301,10 +427,12 @@ impl Lexer {
         Ok(self.spanned(Token::Number(num), start))
     }
 
+    /// Lex a Unicode (XID) identifier. Note names are always ASCII, so the
+    /// parser tells them apart from track names like `Intro日本`.
     fn lex_ident(&mut self, start: usize) -> Result<Spanned, LexError> {
         while self.pos < self.chars.len() {
             let ch = self.chars[self.pos];
-            if ch.is_ascii_alphanumeric() || ch == '_' {
+            if ch == '_' || unicode_ident::is_xid_continue(ch) {
                 self.pos += 1;
             } else {
                 break;
@@ -455,4 +583,122 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_pitch_modifier_tokens() {
+        let tokens = lex("C4->G4 /2 x - 1 ~ ^");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("C4".into()),
+                Token::Arrow,
+                Token::Ident("G4".into()),
+                Token::Slash,
+                Token::Number(2.0),
+                Token::Ident("x".into()),
+                Token::Minus,
+                Token::Number(1.0),
+                Token::Tilde,
+                Token::Caret,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let src = "track Intro日本() {\n    C4 /2\n}\nIntro日本();\nrésumé_2";
+        let tokens = Lexer::new(src).tokenize().unwrap();
+        assert_eq!(tokens[1].token, Token::Ident("Intro日本".into()));
+        // Spans are byte offsets: each CJK character is three bytes
+        assert_eq!((tokens[1].span.start, tokens[1].span.end), (6, 17));
+        let note = tokens.iter().find(|t| t.token == Token::Ident("C4".into())).unwrap();
+        assert_eq!(&src[note.span.start..note.span.end], "C4");
+        let last = &tokens[tokens.len() - 2];
+        assert_eq!(last.token, Token::Ident("résumé_2".into()));
+        assert_eq!(&src[last.span.start..last.span.end], "résumé_2");
+    }
+
+    #[test]
+    fn test_block_and_doc_comments() {
+        let tokens = lex("/* intro\n   section */\nC4 /* accent */ 1\n/// Lead line\n//// rule");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Comment("intro\nsection".into()),
+                Token::Newline,
+                Token::Ident("C4".into()),
+                Token::Number(1.0),
+                Token::Newline,
+                Token::DocComment("Lead line".into()),
+                Token::Newline,
+                Token::Comment("// rule".into()),
+            ]
+        );
+        assert!(matches!(
+            Lexer::new("C4 /* never closed").tokenize(),
+            Err(LexError::UnterminatedComment { pos: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_articulation_marks() {
+        assert_eq!(
+            lex("C4! D4' 'la'"),
+            vec![
+                Token::Ident("C4".into()),
+                Token::Bang,
+                Token::Ident("D4".into()),
+                Token::Apostrophe,
+                Token::StringLit("la".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_regex_needs_closing_slash_on_line() {
+        assert_eq!(
+            lex("riff(a, /4)"),
+            vec![
+                Token::Ident("riff".into()),
+                Token::LParen,
+                Token::Ident("a".into()),
+                Token::Comma,
+                Token::Slash,
+                Token::Number(4.0),
+                Token::RParen,
+            ]
+        );
+        assert_eq!(lex("(/4\n/")[..3], [Token::LParen, Token::Slash, Token::Number(4.0)]);
+        assert_eq!(lex("(/4 // x")[1], Token::Slash);
+
+        let tokens: Vec<Token> =
+            Lexer::new("x = /a/").regex_literals(false).tokenize().unwrap().into_iter().map(|s| s.token).collect();
+        assert_eq!(tokens[2..5], [Token::Slash, Token::Ident("a".into()), Token::Slash]);
+    }
+
+    #[test]
+    fn test_multi_char_operators() {
+        assert_eq!(
+            lex("a<=b>=c==d!=e&&f||g<h>i"),
+            vec![
+                Token::Ident("a".into()),
+                Token::LtEq,
+                Token::Ident("b".into()),
+                Token::GtEq,
+                Token::Ident("c".into()),
+                Token::EqEq,
+                Token::Ident("d".into()),
+                Token::BangEq,
+                Token::Ident("e".into()),
+                Token::AndAnd,
+                Token::Ident("f".into()),
+                Token::OrOr,
+                Token::Ident("g".into()),
+                Token::Lt,
+                Token::Ident("h".into()),
+                Token::Gt,
+                Token::Ident("i".into()),
+            ]
+        );
+    }
 }
@@ -0,0 +1,68 @@
+//! Float math for `no_std` and deterministic builds.
+//!
+//! The transcendental functions on the rendering path (`sin`, `powf`, …)
+//! go through the free functions here. With the `deterministic` feature,
+//! or without `std`, they use the pure-Rust `libm` crate, so the same song
+//! renders bit-identically on x86, ARM and wasm32. Otherwise they use the
+//! platform's math library, which is faster but may differ in the last
+//! bit between targets.
+//!
+//! `core` has no `f64` methods for rounding either, so without `std` the
+//! `Float` trait supplies the ones the core modules call.
+
+/// Defines a function that calls `libm` in deterministic builds and the
+/// inherent `f64` method otherwise.
+macro_rules! dispatch {
+    ($( $(#[$doc:meta])* fn $name:ident($($arg:ident: $ty:ty),*) => $libm:ident; )*) => {$(
+        $(#[$doc])*
+        // Some are only called by the std-only effects
+        #[cfg_attr(not(feature = "std"), allow(dead_code))]
+        #[inline]
+        pub(crate) fn $name(x: f64 $(, $arg: $ty)*) -> f64 {
+            #[cfg(any(not(feature = "std"), feature = "deterministic"))]
+            return libm::$libm(x $(, $arg as f64)*);
+            #[cfg(all(feature = "std", not(feature = "deterministic")))]
+            return x.$name($($arg),*);
+        }
+    )*};
+}
+
+dispatch! {
+    fn sin() => sin;
+    fn cos() => cos;
+    fn tan() => tan;
+    fn exp() => exp;
+    fn tanh() => tanh;
+    fn log2() => log2;
+    fn log10() => log10;
+    fn powf(n: f64) => pow;
+    /// Integer powers; `libm` has no dedicated routine, so this is `pow`.
+    fn powi(n: i32) => pow;
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) trait Float {
+    fn ceil(self) -> Self;
+    fn floor(self) -> Self;
+    fn fract(self) -> Self;
+    fn sqrt(self) -> Self;
+}
+
+#[cfg(not(feature = "std"))]
+impl Float for f64 {
+    fn ceil(self) -> f64 {
+        libm::ceil(self)
+    }
+
+    fn floor(self) -> f64 {
+        libm::floor(self)
+    }
+
+    fn fract(self) -> f64 {
+        self - libm::trunc(self)
+    }
+
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+}
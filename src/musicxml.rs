@@ -0,0 +1,403 @@
+//! MusicXML import — converts a `score-partwise` document to `.sw` source.
+//!
+//! Each part becomes a track, or one track per voice when a part has
+//! several voices. Durations are converted from the part's `divisions`
+//! to beats (quarter notes), tied notes are merged into one, and the
+//! first tempo and time signature become song-level settings.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use roxmltree::{Document, Node, ParsingOptions};
+
+use crate::compiler::{compile, EventList};
+use crate::format::{format_beats, format_decimal};
+use crate::pitch::midi_to_note_name;
+
+/// Tolerance for comparing beat positions.
+const EPSILON: f64 = 1e-6;
+
+/// A note read from the score, in beats.
+#[derive(Debug, Clone, PartialEq)]
+struct ImportedNote {
+    start: f64,
+    duration: f64,
+    midi: i32,
+}
+
+/// The notes of one voice of one part.
+#[derive(Debug)]
+struct ImportedVoice {
+    part_name: String,
+    voice: String,
+    notes: Vec<ImportedNote>,
+}
+
+/// Song-level settings taken from the first measure that sets them.
+#[derive(Debug, Default)]
+struct ScoreSettings {
+    tempo: Option<f64>,
+    time_signature: Option<(u32, u32)>,
+}
+
+/// Convert a MusicXML score to `.sw` source.
+pub fn musicxml_to_source(xml: &str) -> Result<String, String> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let doc = Document::parse_with_options(xml, options).map_err(|e| format!("Invalid MusicXML: {e}"))?;
+    let root = doc.root_element();
+    match root.tag_name().name() {
+        "score-partwise" => {}
+        "score-timewise" => return Err("Only partwise MusicXML scores are supported.".to_string()),
+        other => return Err(format!("Expected a MusicXML score, found <{other}>.")),
+    }
+
+    let part_names: HashMap<&str, &str> = root
+        .children()
+        .filter(|n| n.has_tag_name("part-list"))
+        .flat_map(|list| list.children().filter(|n| n.has_tag_name("score-part")))
+        .filter_map(|part| Some((part.attribute("id")?, child_text(part, "part-name")?)))
+        .collect();
+
+    let mut settings = ScoreSettings::default();
+    let mut voices = Vec::new();
+    for part in root.children().filter(|n| n.has_tag_name("part")) {
+        let id = part.attribute("id").unwrap_or("part");
+        let name = part_names.get(id).copied().unwrap_or(id);
+        voices.extend(read_part(part, name, &mut settings)?);
+    }
+    Ok(write_source(&voices, &settings))
+}
+
+/// Import a MusicXML score and compile it to an EventList.
+pub fn import_musicxml(xml: &str) -> Result<EventList, String> {
+    let source = musicxml_to_source(xml)?;
+    let program = crate::parse(&source).map_err(|e| format!("Imported source failed to parse: {e}"))?;
+    compile(&program)
+}
+
+// ── Reading ─────────────────────────────────────────────────
+
+fn read_part(part: Node, name: &str, settings: &mut ScoreSettings) -> Result<Vec<ImportedVoice>, String> {
+    let mut voices: Vec<ImportedVoice> = Vec::new();
+    // Open ties by (voice, MIDI note): index of the note being extended
+    let mut open_ties: HashMap<(String, i32), usize> = HashMap::new();
+    let mut divisions = 1.0;
+    let mut position = 0.0;
+    let mut chord_start = 0.0;
+
+    for measure in part.children().filter(|n| n.has_tag_name("measure")) {
+        for element in measure.children().filter(|n| n.is_element()) {
+            match element.tag_name().name() {
+                "attributes" => {
+                    if let Some(d) = child_number(element, "divisions").filter(|d| *d > 0.0) {
+                        divisions = d;
+                    }
+                    if settings.time_signature.is_none()
+                        && let Some(time) = element.children().find(|n| n.has_tag_name("time"))
+                        && let (Some(beats), Some(beat_type)) =
+                            (child_number(time, "beats"), child_number(time, "beat-type"))
+                    {
+                        settings.time_signature = Some((beats as u32, beat_type as u32));
+                    }
+                }
+                "direction" | "sound" => {
+                    let tempo = element
+                        .descendants()
+                        .filter(|n| n.has_tag_name("sound"))
+                        .find_map(|n| n.attribute("tempo")?.parse::<f64>().ok());
+                    if settings.tempo.is_none() {
+                        settings.tempo = tempo.filter(|t| *t > 0.0);
+                    }
+                }
+                "backup" => position -= child_number(element, "duration").unwrap_or(0.0) / divisions,
+                "forward" => position += child_number(element, "duration").unwrap_or(0.0) / divisions,
+                "note" => {
+                    if element.children().any(|n| n.has_tag_name("grace")) {
+                        continue;
+                    }
+                    let duration = child_number(element, "duration").unwrap_or(0.0) / divisions;
+                    let start = if element.children().any(|n| n.has_tag_name("chord")) {
+                        chord_start
+                    } else {
+                        chord_start = position;
+                        position += duration;
+                        chord_start
+                    };
+                    let Some(pitch) = element.children().find(|n| n.has_tag_name("pitch")) else {
+                        continue; // rest or unpitched
+                    };
+                    let midi = pitch_to_midi(pitch)?;
+                    let voice = child_text(element, "voice").unwrap_or("1").to_string();
+                    let ties: Vec<&str> = element
+                        .children()
+                        .filter(|n| n.has_tag_name("tie"))
+                        .filter_map(|n| n.attribute("type"))
+                        .collect();
+
+                    let index = match voices.iter().position(|v| v.voice == voice) {
+                        Some(i) => i,
+                        None => {
+                            voices.push(ImportedVoice {
+                                part_name: name.to_string(),
+                                voice: voice.clone(),
+                                notes: Vec::new(),
+                            });
+                            voices.len() - 1
+                        }
+                    };
+                    let notes = &mut voices[index].notes;
+                    let key = (voice, midi);
+                    match open_ties.get(&key) {
+                        Some(&tied) if ties.contains(&"stop") => {
+                            notes[tied].duration += duration;
+                            if !ties.contains(&"start") {
+                                open_ties.remove(&key);
+                            }
+                        }
+                        _ => {
+                            notes.push(ImportedNote { start, duration, midi });
+                            if ties.contains(&"start") {
+                                open_ties.insert(key, notes.len() - 1);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(voices)
+}
+
+/// MIDI note number of a `<pitch>` element.
+fn pitch_to_midi(pitch: Node) -> Result<i32, String> {
+    let step = match child_text(pitch, "step") {
+        Some("C") => 0,
+        Some("D") => 2,
+        Some("E") => 4,
+        Some("F") => 5,
+        Some("G") => 7,
+        Some("A") => 9,
+        Some("B") => 11,
+        other => return Err(format!("Invalid pitch step {other:?} in MusicXML note.")),
+    };
+    let alter = child_number(pitch, "alter").unwrap_or(0.0).round() as i32;
+    let octave = child_number(pitch, "octave").ok_or("MusicXML pitch is missing its octave.")? as i32;
+    let midi = (octave + 1) * 12 + step + alter;
+    if midi < 12 {
+        return Err(format!("MusicXML note {} is below C0.", midi_to_note_name(midi, true)));
+    }
+    Ok(midi)
+}
+
+fn child_text<'a>(node: Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(tag))?
+        .text()
+        .map(str::trim)
+}
+
+fn child_number(node: Node, tag: &str) -> Option<f64> {
+    child_text(node, tag)?.parse().ok()
+}
+
+// ── Writing ─────────────────────────────────────────────────
+
+fn write_source(voices: &[ImportedVoice], settings: &ScoreSettings) -> String {
+    let mut out = String::from("// Imported from MusicXML\n");
+    if let Some(tempo) = settings.tempo {
+        let _ = writeln!(out, "track.beatsPerMinute = {};", format_decimal(tempo));
+    }
+    if let Some((beats, beat_type)) = settings.time_signature {
+        let _ = writeln!(out, "track.timeSignature = {beats}/{beat_type};");
+    }
+
+    let mut names: Vec<String> = Vec::new();
+    for voice in voices.iter().filter(|v| !v.notes.is_empty()) {
+        let several = voices.iter().filter(|v| v.part_name == voice.part_name).count() > 1;
+        let name = track_name(voice, several, &names);
+        let _ = writeln!(out, "\n// {}", voice.part_name.split_whitespace().collect::<Vec<_>>().join(" "));
+        let _ = writeln!(out, "track {name}() {{");
+        write_notes(&mut out, &voice.notes);
+        out.push_str("}\n");
+        names.push(name);
+    }
+
+    out.push('\n');
+    for name in &names {
+        let _ = writeln!(out, "{name}();");
+    }
+    out
+}
+
+/// Write a voice's notes as note, chord and rest statements.
+fn write_notes(out: &mut String, notes: &[ImportedNote]) {
+    let mut notes = notes.to_vec();
+    notes.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap().then(a.midi.cmp(&b.midi)));
+    let mut groups: Vec<Vec<&ImportedNote>> = Vec::new();
+    for note in &notes {
+        match groups.last_mut() {
+            Some(group) if (group[0].start - note.start).abs() < EPSILON => group.push(note),
+            _ => groups.push(vec![note]),
+        }
+    }
+
+    let mut cursor = 0.0;
+    for (i, group) in groups.iter().enumerate() {
+        let start = group[0].start;
+        if start - cursor > EPSILON {
+            let _ = writeln!(out, "    _ {}", format_beats(start - cursor));
+        }
+        let longest = group.iter().map(|n| n.duration).fold(0.0, f64::max);
+        // Step to the next group, or past this one if a rest follows
+        let step = groups.get(i + 1).map_or(longest, |next| (next[0].start - start).min(longest));
+        let name = |n: &ImportedNote| midi_to_note_name(n.midi, true);
+        if let [note] = group.as_slice() {
+            let _ = writeln!(out, "    {}@{} {}", name(note), format_decimal(note.duration), format_beats(step));
+        } else if group.iter().all(|n| (n.duration - longest).abs() < EPSILON) {
+            let pitches: Vec<String> = group.iter().map(|n| name(n)).collect();
+            let _ = writeln!(out, "    [{}]@{} {}", pitches.join(", "), format_decimal(longest), format_beats(step));
+        } else {
+            let pitches: Vec<String> = group
+                .iter()
+                .map(|n| format!("{}@{}", name(n), format_beats(n.duration)))
+                .collect();
+            let _ = writeln!(out, "    [{}] {}", pitches.join(", "), format_beats(step));
+        }
+        cursor = start + step;
+    }
+}
+
+/// A unique track identifier for a voice, from its part name.
+fn track_name(voice: &ImportedVoice, several_voices: bool, taken: &[String]) -> String {
+    let mut base: String = voice
+        .part_name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>()
+        .split('_')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+    if base.is_empty()
+        || base.starts_with(|c: char| c.is_ascii_digit())
+        || matches!(base.as_str(), "track" | "const" | "for" | "marker" | "cue")
+    {
+        base = format!("part_{base}").trim_end_matches('_').to_string();
+    }
+    if several_voices {
+        let _ = write!(base, "_v{}", voice.voice);
+    }
+    let mut name = base.clone();
+    let mut n = 2;
+    while taken.contains(&name) {
+        name = format!("{base}_{n}");
+        n += 1;
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::EventKind;
+
+    const SCORE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+<score-partwise version="4.0">
+  <part-list>
+    <score-part id="P1"><part-name>Lead Voice</part-name></score-part>
+    <score-part id="P2"><part-name>Piano</part-name></score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes>
+        <divisions>2</divisions>
+        <time><beats>3</beats><beat-type>4</beat-type></time>
+      </attributes>
+      <direction><sound tempo="90"/></direction>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice></note>
+      <note><pitch><step>F</step><alter>1</alter><octave>4</octave></pitch><duration>3</duration><voice>1</voice></note>
+      <note><pitch><step>G</step><octave>4</octave></pitch><duration>1</duration><voice>1</voice><tie type="start"/></note>
+    </measure>
+    <measure number="2">
+      <note><pitch><step>G</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><tie type="stop"/></note>
+      <note><rest/><duration>2</duration><voice>1</voice></note>
+      <note><pitch><step>E</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice></note>
+    </measure>
+  </part>
+  <part id="P2">
+    <measure number="1">
+      <attributes><divisions>1</divisions></attributes>
+      <note><pitch><step>C</step><octave>3</octave></pitch><duration>3</duration><voice>1</voice></note>
+      <note><chord/><pitch><step>E</step><octave>3</octave></pitch><duration>3</duration><voice>1</voice></note>
+      <backup><duration>3</duration></backup>
+      <note><rest/><duration>1</duration><voice>2</voice></note>
+      <note><pitch><step>G</step><octave>2</octave></pitch><duration>2</duration><voice>2</voice></note>
+    </measure>
+  </part>
+</score-partwise>"#;
+
+    #[test]
+    fn converts_parts_voices_ties_and_rests() {
+        let source = musicxml_to_source(SCORE).unwrap();
+        assert_eq!(
+            source,
+            "// Imported from MusicXML
+track.beatsPerMinute = 90;
+track.timeSignature = 3/4;
+
+// Lead Voice
+track lead_voice() {
+    C4@1 1
+    Gb4@1.5 3/2
+    G4@1.5 3/2
+    _ 1
+    E4@1 1
+}
+
+// Piano
+track piano_v1() {
+    [C3, E3]@3 3
+}
+
+// Piano
+track piano_v2() {
+    _ 1
+    G2@2 2
+}
+
+lead_voice();
+piano_v1();
+piano_v2();
+"
+        );
+    }
+
+    #[test]
+    fn imports_to_an_event_list() {
+        let events = import_musicxml(SCORE).unwrap();
+        let lead: Vec<(f64, &str, f64)> = events
+            .events
+            .iter()
+            .filter(|e| e.track_name.as_deref() == Some("lead_voice"))
+            .filter_map(|e| match &e.kind {
+                EventKind::Note { pitch, gate, .. } => Some((e.time, pitch.as_str(), *gate)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lead, vec![(0.0, "C4", 1.0), (1.0, "Gb4", 1.5), (2.5, "G4", 1.5), (5.0, "E4", 1.0)]);
+    }
+
+    #[test]
+    fn rejects_non_partwise_documents() {
+        let err = musicxml_to_source("<score-timewise/>").unwrap_err();
+        assert!(err.contains("partwise"), "{err}");
+        assert!(musicxml_to_source("<svg/>").is_err());
+        assert!(musicxml_to_source("not xml").unwrap_err().starts_with("Invalid MusicXML"));
+    }
+}
@@ -1,15 +1,28 @@
+use alloc::boxed::Box;
+use alloc::{format, vec};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
 use crate::ast::*;
 use crate::error::ParseError;
-use crate::token::{token_to_string, Spanned, Token};
+use crate::lexer::Lexer;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::token::{Span, Spanned, Token};
 
 pub struct Parser {
     tokens: Vec<Spanned>,
     pos: usize,
+    /// Version set by `#version`, for gating syntax added in later versions.
+    language_version: Option<u32>,
+    /// `let` variables declared in the enclosing track bodies, so a step
+    /// can name one (`C4 step`) without it reading as another note.
+    variables: Vec<String>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Spanned>) -> Self {
-        Parser { tokens, pos: 0 }
+        Parser { tokens, pos: 0, language_version: None, variables: Vec::new() }
     }
 
     // ── Helpers ──────────────────────────────────────────────
@@ -42,7 +55,7 @@ impl Parser {
     }
 
     fn check(&self, expected: &Token) -> bool {
-        std::mem::discriminant(&self.tokens[self.pos].token) == std::mem::discriminant(expected)
+        core::mem::discriminant(&self.tokens[self.pos].token) == core::mem::discriminant(expected)
     }
 
     fn eat(&mut self, expected: &Token) -> bool {
@@ -94,6 +107,16 @@ impl Parser {
         }
     }
 
+    /// Parse a number with an optional leading `-` or `+`.
+    fn parse_signed_number(&mut self) -> Result<f64, ParseError> {
+        if self.eat(&Token::Minus) {
+            Ok(-self.expect_number()?)
+        } else {
+            self.eat(&Token::Plus);
+            self.expect_number()
+        }
+    }
+
     /// Skip newlines and standalone comments (collecting comments into a vec).
     fn skip_newlines(&mut self) {
         while matches!(self.peek(), Token::Newline) {
@@ -101,8 +124,8 @@ impl Parser {
         }
     }
 
-    /// Skip newlines and return any comments found.
-    fn skip_newlines_collecting_comments(&mut self) -> Vec<String> {
+    /// Skip newlines and return any comments found, with their spans.
+    fn skip_newlines_collecting_comments(&mut self) -> Vec<(String, Span)> {
         let mut comments = Vec::new();
         loop {
             match self.peek() {
@@ -110,7 +133,14 @@ impl Parser {
                     self.advance();
                 }
                 Token::Comment(text) => {
-                    comments.push(text);
+                    comments.push((text, self.span()));
+                    self.advance();
+                }
+                Token::DocComment(text) => {
+                    // A track definition's doc comment is attached to it instead
+                    if !self.doc_comment_precedes_track_def() {
+                        comments.push((text, self.span()));
+                    }
                     self.advance();
                 }
                 _ => break,
@@ -119,6 +149,61 @@ impl Parser {
         comments
     }
 
+    /// Consume comments on the rest of the current line, without crossing
+    /// a newline.
+    fn collect_line_comments(&mut self) -> Vec<Comment> {
+        let mut comments = Vec::new();
+        while let Token::Comment(text) | Token::DocComment(text) = self.peek() {
+            comments.push(comment((text, self.span())));
+            self.advance();
+        }
+        comments
+    }
+
+    /// Whether the doc comment at the cursor (and any that follow it)
+    /// comes right before a `track name(...)` definition.
+    fn doc_comment_precedes_track_def(&self) -> bool {
+        let mut i = self.pos;
+        while matches!(self.tokens[i].token, Token::DocComment(_) | Token::Newline) {
+            i += 1;
+        }
+        self.tokens[i].token == Token::Track
+            && matches!(self.tokens.get(i + 1).map(|t| &t.token), Some(Token::Ident(_)))
+    }
+
+    /// The `///` lines directly before the token at `pos`, joined by newlines.
+    fn doc_comment_before(&self, pos: usize) -> Option<String> {
+        let mut lines = Vec::new();
+        for spanned in self.tokens[..pos].iter().rev() {
+            match &spanned.token {
+                Token::Newline => {}
+                Token::DocComment(text) => lines.push(text.clone()),
+                _ => break,
+            }
+        }
+        lines.reverse();
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// Re-lex a regex literal at the cursor as `/` and what follows it,
+    /// where only a duration can come next. The lexer only sees the
+    /// previous token, so `(` or `,` before `/4 ... /` reads as a regex.
+    fn relex_regex_as_slash(&mut self) {
+        let Token::RegexLit(text) = self.peek() else {
+            return;
+        };
+        let Ok(mut tokens) = Lexer::new(&text).regex_literals(false).tokenize() else {
+            return;
+        };
+        tokens.pop(); // EOF
+        let start = self.span().start;
+        for token in &mut tokens {
+            token.span.start += start;
+            token.span.end += start;
+        }
+        self.tokens.splice(self.pos..=self.pos, tokens);
+    }
+
     /// Skip an optional semicolon and/or newlines.
     fn skip_terminator(&mut self) {
         self.eat(&Token::Semicolon);
@@ -134,25 +219,54 @@ impl Parser {
         while !self.is_at_end() {
             // Collect any comments as statements
             let comments = self.skip_newlines_collecting_comments();
-            for c in comments {
-                statements.push(Statement::Comment(c));
+            for (text, span) in comments {
+                statements.push(Statement::Comment { text, span_start: span.start, span_end: span.end });
             }
             if self.is_at_end() {
                 break;
             }
+            if let Token::Pragma(text) = self.peek() {
+                let at_top = statements.iter().all(|s| matches!(s, Statement::Comment { .. }));
+                self.parse_pragma(&text, at_top)?;
+                continue;
+            }
             statements.push(self.parse_statement()?);
             self.skip_terminator();
         }
-        Ok(Program { statements })
+        Ok(Program { statements, language_version: self.language_version.unwrap_or(1) })
+    }
+
+    /// `#version N`, once, before any statement.
+    fn parse_pragma(&mut self, text: &str, at_top: bool) -> Result<(), ParseError> {
+        let span = self.span();
+        let error = |expected: String| ParseError::UnexpectedToken {
+            expected,
+            found: Token::Pragma(text.to_string()),
+            span,
+        };
+        let Some(("version", value)) = text.split_once(char::is_whitespace) else {
+            return Err(error("`#version N` (the only supported pragma)".into()));
+        };
+        if !at_top || self.language_version.is_some() {
+            return Err(error("a single `#version` before any statement".into()));
+        }
+        match value.trim().parse::<u32>() {
+            Ok(version) if (1..=LATEST_LANGUAGE_VERSION).contains(&version) => {
+                self.language_version = Some(version);
+                self.advance();
+                Ok(())
+            }
+            _ => Err(error(format!("language version 1 to {LATEST_LANGUAGE_VERSION}"))),
+        }
     }
 
     // ── Top-Level Statement ─────────────────────────────────
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
         match self.peek() {
-            Token::Comment(text) => {
-                self.advance();
-                Ok(Statement::Comment(text))
+            Token::Comment(text) | Token::DocComment(text) => {
+                let span = self.advance().span;
+                Ok(Statement::Comment { text, span_start: span.start, span_end: span.end })
             }
             Token::Track => {
                 // Distinguish `track name(...)` from `track.prop = ...`
@@ -176,6 +290,7 @@ impl Parser {
 
     fn parse_track_def(&mut self) -> Result<Statement, ParseError> {
         let start_span = self.span().start;
+        let doc = self.doc_comment_before(self.pos);
         self.expect(&Token::Track)?;
         let name = self.expect_ident()?;
         self.expect(&Token::LParen)?;
@@ -185,7 +300,7 @@ impl Parser {
         let body = self.parse_track_body()?;
         self.expect(&Token::RBrace)?;
         let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
-        Ok(Statement::TrackDef { name, params, body, span_start: start_span, span_end: end_span })
+        Ok(Statement::TrackDef { name, params, body, doc, span_start: start_span, span_end: end_span })
     }
 
     fn parse_param_list(&mut self) -> Result<Vec<String>, ParseError> {
@@ -203,13 +318,12 @@ impl Parser {
 
     fn parse_track_body(&mut self) -> Result<Vec<TrackStatement>, ParseError> {
         let mut stmts = Vec::new();
+        let scope = self.variables.len();
         self.skip_newlines();
 
         while !self.check(&Token::RBrace) && !self.is_at_end() {
             let comments = self.skip_newlines_collecting_comments();
-            for c in comments {
-                stmts.push(TrackStatement::Comment(c));
-            }
+            stmts.extend(comments.into_iter().map(track_comment));
             if self.check(&Token::RBrace) || self.is_at_end() {
                 break;
             }
@@ -218,14 +332,15 @@ impl Parser {
             self.eat(&Token::Semicolon);
             self.skip_newlines();
         }
+        self.variables.truncate(scope);
         Ok(stmts)
     }
 
     fn parse_track_statement(&mut self) -> Result<TrackStatement, ParseError> {
         match self.peek() {
-            Token::Comment(text) => {
-                self.advance();
-                Ok(TrackStatement::Comment(text))
+            Token::Comment(text) | Token::DocComment(text) => {
+                let span = self.advance().span;
+                Ok(track_comment((text, span)))
             }
             Token::LBracket => self.parse_chord(),
             Token::Number(_) => {
@@ -240,6 +355,9 @@ impl Parser {
                 self.parse_track_body_assignment()
             }
             Token::For => self.parse_for_loop(),
+            Token::Let => self.parse_let(),
+            Token::Ident(name) if name == "poly" && self.at_block_header(2) => self.parse_poly(),
+            Token::Ident(name) if name == "maybe" && self.at_block_header(1) => self.parse_maybe(),
             Token::Ident(_) => self.parse_ident_statement_in_track(),
             Token::Dot => {
                 // Dot shorthand as a rest: `.` or `..`
@@ -249,7 +367,7 @@ impl Parser {
                 Ok(TrackStatement::Rest { duration: dur, span_start: start_span, span_end: end_span })
             }
             _ => Err(ParseError::UnexpectedToken {
-                expected: "track statement (note, chord, rest, assignment, or for loop)".into(),
+                expected: "track statement (note, chord, rest, let, assignment, or for loop)".into(),
                 found: self.peek(),
                 span: self.span(),
             }),
@@ -258,8 +376,91 @@ impl Parser {
 
     // ── Ident-leading statement (note event or track call) ──
 
+    /// Parse `marker "name"` or `cue "name"`, if that is what follows.
+    fn try_parse_marker(&mut self) -> Option<(MarkerKind, String)> {
+        let kind = match self.peek() {
+            Token::Ident(word) if word == "marker" => MarkerKind::Marker,
+            Token::Ident(word) if word == "cue" => MarkerKind::Cue,
+            _ => return None,
+        };
+        let Token::StringLit(name) = self.peek_at(1) else {
+            return None;
+        };
+        self.advance();
+        self.advance();
+        Some((kind, name))
+    }
+
+    /// Parse `play handle` or `play handle at beat`, if the next tokens
+    /// start one. `play(...)` is still a call to a track named `play`.
+    fn try_parse_play(&mut self) -> Result<Option<(String, Option<f64>)>, ParseError> {
+        let (Token::Ident(word), Token::Ident(handle)) = (self.peek(), self.peek_at(1)) else {
+            return Ok(None);
+        };
+        if word != "play" {
+            return Ok(None);
+        }
+        self.advance();
+        self.advance();
+        if !matches!(self.peek(), Token::Ident(word) if word == "at") {
+            return Ok(Some((handle, None)));
+        }
+        self.advance();
+        match self.peek() {
+            Token::Number(beat) => {
+                self.advance();
+                Ok(Some((handle, Some(beat))))
+            }
+            found => Err(ParseError::UnexpectedToken {
+                expected: "beat number after 'at'".into(),
+                found,
+                span: self.span(),
+            }),
+        }
+    }
+
+    /// Parse the rest of an explicit rest whose leading identifier `name`
+    /// has been consumed: `r4`, `r/8`, `_ /4`, or `R*4` for whole bars.
+    /// Returns `None` if `name` does not start a rest (e.g. `r()` calls a
+    /// track named `r`).
+    fn try_parse_explicit_rest(
+        &mut self,
+        name: &str,
+        start_span: usize,
+    ) -> Result<Option<TrackStatement>, ParseError> {
+        if self.check(&Token::LParen) {
+            return Ok(None);
+        }
+        let duration = match name {
+            "R" => {
+                let bars = if self.eat(&Token::Star) { self.expect_number()? } else { 1.0 };
+                let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
+                return Ok(Some(TrackStatement::BarRest { bars, span_start: start_span, span_end: end_span }));
+            }
+            "r" | "_" => self.parse_duration_expr()?,
+            _ => {
+                // `r4` lexes as one identifier
+                let digits = name.strip_prefix('r').filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+                let Some(n) = digits.and_then(|n| n.parse::<f64>().ok()) else {
+                    return Ok(None);
+                };
+                self.parse_dotted(DurationExpr::Beats(n))
+            }
+        };
+        let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
+        Ok(Some(TrackStatement::Rest { duration, span_start: start_span, span_end: end_span }))
+    }
+
     fn parse_ident_statement(&mut self, _in_track: bool) -> Result<Statement, ParseError> {
         let start_span = self.span().start;
+        if let Some((kind, name)) = self.try_parse_marker() {
+            let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
+            return Ok(Statement::Marker { kind, name, span_start: start_span, span_end: end_span });
+        }
+        if let Some((handle, at)) = self.try_parse_play()? {
+            let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
+            return Ok(Statement::Play { handle, at, span_start: start_span, span_end: end_span });
+        }
         let name = self.expect_ident()?;
 
         // Check for assignment: `name.prop = value` or `name = value`
@@ -312,6 +513,10 @@ impl Parser {
 
     fn parse_ident_statement_in_track(&mut self) -> Result<TrackStatement, ParseError> {
         let start_span = self.span().start;
+        if let Some((kind, name)) = self.try_parse_marker() {
+            let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
+            return Ok(TrackStatement::Marker { kind, name, span_start: start_span, span_end: end_span });
+        }
         let name = self.expect_ident()?;
 
         // Check for assignment: `name.prop = value` or `name = value`
@@ -335,11 +540,29 @@ impl Parser {
                 span_end: end_span,
             });
         }
+        if let Some(rest) = self.try_parse_explicit_rest(&name, start_span)? {
+            return Ok(rest);
+        }
+        let choices = if name == "choose" && self.check(&Token::LParen) && self.peek_at(1) == Token::LBracket {
+            self.parse_choices()?
+        } else {
+            Vec::new()
+        };
+        let name = choices.first().cloned().unwrap_or(name);
+
+        // Optional slide target: `C4->G4`
+        let slide_to = if self.eat(&Token::Arrow) {
+            Some(self.expect_ident()?)
+        } else {
+            None
+        };
+        let (vibrato, bend) = self.parse_pitch_modifiers()?;
+        let mut articulation = self.parse_articulation();
 
         // Parse optional modifiers: *vel @dur
         let (velocity, play_duration) = self.parse_modifiers()?;
 
-        if self.check(&Token::LParen) {
+        if slide_to.is_none() && articulation.is_empty() && self.check(&Token::LParen) {
             // Track call inside a track
             self.advance();
             let args = self.parse_call_args()?;
@@ -356,20 +579,68 @@ impl Parser {
                 span_end: end_span,
             })
         } else {
-            // Note event: pitch was `name`, parse optional step duration
+            // Note event: pitch was `name`, parse optional step duration.
+            // A lyric may come before or after the step.
+            let mut lyric = self.try_parse_lyric();
+            let mut expression = self.parse_note_expression()?;
             let step = self.try_parse_duration()?;
+            if expression.is_empty() {
+                expression = self.parse_note_expression()?;
+            }
+            if lyric.is_none() {
+                lyric = self.try_parse_lyric();
+            }
+            expression.vibrato = vibrato;
+            expression.bend = bend;
+            // `C4_` (legato) lexes as a single name
+            let name = match name.strip_suffix('_') {
+                Some(pitch) if crate::pitch::note_to_midi(pitch).is_some() => {
+                    articulation.insert(0, Articulation::Legato);
+                    pitch.to_string()
+                }
+                _ => name,
+            };
             let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
             Ok(TrackStatement::NoteEvent {
                 pitch: name,
+                slide_to,
                 velocity,
                 audible_duration: play_duration,
                 step_duration: step,
+                expression,
+                lyric,
+                articulation,
+                choices,
                 span_start: start_span,
                 span_end: end_span,
             })
         }
     }
 
+    /// Parse the `([C4, E4, G4])` of `choose(...)`.
+    fn parse_choices(&mut self) -> Result<Vec<String>, ParseError> {
+        self.expect(&Token::LParen)?;
+        self.expect(&Token::LBracket)?;
+        let mut choices = vec![self.expect_ident()?];
+        while self.eat(&Token::Comma) {
+            choices.push(self.expect_ident()?);
+        }
+        self.expect(&Token::RBracket)?;
+        self.expect(&Token::RParen)?;
+        Ok(choices)
+    }
+
+    /// Parse an optional lyric string after a note: `C4 "sha-"`.
+    fn try_parse_lyric(&mut self) -> Option<String> {
+        match self.peek() {
+            Token::StringLit(text) => {
+                self.advance();
+                Some(text)
+            }
+            _ => None,
+        }
+    }
+
     fn parse_dotted_ident_rest(&mut self, first: String) -> Result<String, ParseError> {
         let mut result = first;
         while self.eat(&Token::Dot) {
@@ -419,30 +690,118 @@ impl Parser {
     fn parse_chord(&mut self) -> Result<TrackStatement, ParseError> {
         let start_span = self.span().start;
         self.expect(&Token::LBracket)?;
-        let mut notes = Vec::new();
+        let mut notes: Vec<ChordNote> = Vec::new();
+        // Comments on their own line go with the next note, comments after
+        // a note (or its comma) with that note
+        let mut leading = self.skip_newlines_collecting_comments();
         if !self.check(&Token::RBracket) {
-            notes.push(self.parse_chord_note()?);
-            while self.eat(&Token::Comma) {
-                self.skip_newlines();
-                notes.push(self.parse_chord_note()?);
+            loop {
+                let mut note = self.parse_chord_note()?;
+                note.comments = leading.drain(..).map(comment).collect();
+                note.comments.extend(self.collect_line_comments());
+                let more = self.eat(&Token::Comma);
+                note.comments.extend(self.collect_line_comments());
+                notes.push(note);
+                leading = self.skip_newlines_collecting_comments();
+                if !more {
+                    break;
+                }
             }
         }
+        if let Some(last) = notes.last_mut() {
+            last.comments.extend(leading.into_iter().map(comment));
+        }
         self.expect(&Token::RBracket)?;
 
         // Parse optional modifiers on the whole chord
         let (_, audible_duration) = self.parse_modifiers()?;
+        let mut expression = self.parse_note_expression()?;
         let step_duration = self.try_parse_duration()?;
+        if expression.is_empty() {
+            expression = self.parse_note_expression()?;
+        }
         let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
 
         Ok(TrackStatement::Chord {
             notes,
             audible_duration,
             step_duration,
+            expression,
             span_start: start_span,
             span_end: end_span,
         })
     }
 
+    /// Parse `~v` (vibrato) and `^+2` (bend) modifiers after a pitch.
+    fn parse_pitch_modifiers(&mut self) -> Result<(bool, Option<f64>), ParseError> {
+        let mut vibrato = false;
+        let mut bend = None;
+        loop {
+            if self.eat(&Token::Tilde) {
+                let found = self.peek();
+                let span = self.span();
+                if self.expect_ident()? != "v" {
+                    return Err(ParseError::UnexpectedToken {
+                        expected: "v (vibrato) after ~".into(),
+                        found,
+                        span,
+                    });
+                }
+                vibrato = true;
+            } else if self.eat(&Token::Caret) {
+                bend = Some(self.parse_signed_number()?);
+            } else {
+                return Ok((vibrato, bend));
+            }
+        }
+    }
+
+    /// Parse `!` (accent) and `'` (staccato) marks after a pitch.
+    fn parse_articulation(&mut self) -> Vec<Articulation> {
+        let mut marks = Vec::new();
+        loop {
+            if self.eat(&Token::Bang) {
+                marks.push(Articulation::Accent);
+            } else if self.eat(&Token::Apostrophe) {
+                marks.push(Articulation::Staccato);
+            } else {
+                return marks;
+            }
+        }
+    }
+
+    /// Parse an optional `{pan: -0.5, brightness: 0.7}` note expression,
+    /// which may come before or after the step duration.
+    fn parse_note_expression(&mut self) -> Result<NoteExpression, ParseError> {
+        let mut expression = NoteExpression::default();
+        if !self.eat(&Token::LBrace) {
+            return Ok(expression);
+        }
+        while !self.check(&Token::RBrace) {
+            let key_token = self.peek();
+            let key_span = self.span();
+            let key = self.expect_ident()?;
+            self.expect(&Token::Colon)?;
+            let value = self.parse_signed_number()?;
+            match key.as_str() {
+                "pan" => expression.pan = Some(value),
+                "brightness" => expression.brightness = Some(value),
+                _ => {
+                    return Err(ParseError::UnexpectedToken {
+                        expected: "note expression field (pan, brightness)".into(),
+                        found: key_token,
+                        span: key_span,
+                    })
+                }
+            }
+            if !self.eat(&Token::Comma) {
+                break;
+            }
+        }
+        self.expect(&Token::RBrace)?;
+        Ok(expression)
+    }
+
     fn parse_chord_note(&mut self) -> Result<ChordNote, ParseError> {
         let pitch = self.expect_ident()?;
         let audible_duration = if self.eat(&Token::At) {
@@ -453,6 +812,7 @@ impl Parser {
         Ok(ChordNote {
             pitch,
             audible_duration,
+            comments: Vec::new(),
         })
     }
 
@@ -463,17 +823,18 @@ impl Parser {
         self.expect(&Token::For)?;
         self.expect(&Token::LParen)?;
 
-        // Collect three parts separated by semicolons (as opaque strings)
-        let init = self.collect_tokens_until(&Token::Semicolon)?;
+        let init = if self.check(&Token::Semicolon) { None } else { Some(self.parse_loop_clause()?) };
         self.expect(&Token::Semicolon)?;
-        let condition = self.collect_tokens_until(&Token::Semicolon)?;
+        let condition = if self.check(&Token::Semicolon) { None } else { Some(self.parse_expr()?) };
         self.expect(&Token::Semicolon)?;
-        let update = self.collect_tokens_until(&Token::RParen)?;
+        let update = if self.check(&Token::RParen) { None } else { Some(self.parse_loop_clause()?) };
         self.expect(&Token::RParen)?;
 
-        self.skip_newlines();
+        // Comments between the header and `{` lead the body
+        let mut body: Vec<TrackStatement> =
+            self.skip_newlines_collecting_comments().into_iter().map(track_comment).collect();
         self.expect(&Token::LBrace)?;
-        let body = self.parse_track_body()?;
+        body.extend(self.parse_track_body()?);
         self.expect(&Token::RBrace)?;
         let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
 
@@ -487,23 +848,138 @@ impl Parser {
         })
     }
 
-    fn collect_tokens_until(&mut self, sentinel: &Token) -> Result<String, ParseError> {
-        let mut parts = Vec::new();
-        while !self.check(sentinel) && !self.is_at_end() {
-            let s = self.advance();
-            parts.push(token_to_string(&s.token));
+    // ── Variables ───────────────────────────────────────────
+
+    fn parse_let(&mut self) -> Result<TrackStatement, ParseError> {
+        let start_span = self.span().start;
+        self.expect(&Token::Let)?;
+        let name = self.expect_ident()?;
+        self.expect(&Token::Eq)?;
+        let value = self.parse_expr()?;
+        let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
+        self.variables.push(name.clone());
+        Ok(TrackStatement::Let { name, value, span_start: start_span, span_end: end_span })
+    }
+
+    /// Parse `let i = 0`, `i = expr`, `i++` or `i--` in a loop header.
+    fn parse_loop_clause(&mut self) -> Result<LoopClause, ParseError> {
+        let is_let = self.eat(&Token::Let);
+        let name = self.expect_ident()?;
+        if !is_let {
+            if self.eat(&Token::PlusPlus) {
+                return Ok(LoopClause::Step { name, delta: 1.0 });
+            }
+            if self.eat(&Token::MinusMinus) {
+                return Ok(LoopClause::Step { name, delta: -1.0 });
+            }
+        }
+        self.expect(&Token::Eq)?;
+        let value = self.parse_expr()?;
+        if is_let {
+            self.variables.push(name.clone());
+        }
+        Ok(if is_let { LoopClause::Let { name, value } } else { LoopClause::Assign { name, value } })
+    }
+
+    // ── Polyrhythm and Chance Blocks ────────────────────────
+
+    /// Whether the tokens ahead are a block header with `arity` number
+    /// arguments, like `poly(3, 4) {`, rather than a call to a track of
+    /// the same name.
+    fn at_block_header(&self, arity: usize) -> bool {
+        let mut header = vec![Token::LParen];
+        for i in 0..arity {
+            if i > 0 {
+                header.push(Token::Comma);
+            }
+            header.push(Token::Number(0.0));
+        }
+        header.push(Token::RParen);
+        if !header.iter().enumerate().all(|(i, t)| {
+            core::mem::discriminant(&self.peek_at(i + 1)) == core::mem::discriminant(t)
+        }) {
+            return false;
+        }
+        let mut offset = header.len() + 1;
+        while matches!(self.peek_at(offset), Token::Newline | Token::Comment(_) | Token::DocComment(_)) {
+            offset += 1;
+        }
+        self.peek_at(offset) == Token::LBrace
+    }
+
+    fn parse_poly(&mut self) -> Result<TrackStatement, ParseError> {
+        let start_span = self.span().start;
+        self.advance();
+        self.expect(&Token::LParen)?;
+        let steps = self.expect_poly_count()?;
+        self.expect(&Token::Comma)?;
+        let span = self.expect_poly_count()?;
+        self.expect(&Token::RParen)?;
+
+        let mut body: Vec<TrackStatement> =
+            self.skip_newlines_collecting_comments().into_iter().map(track_comment).collect();
+        self.expect(&Token::LBrace)?;
+        body.extend(self.parse_track_body()?);
+        self.expect(&Token::RBrace)?;
+        let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
+
+        Ok(TrackStatement::Poly { steps, span, body, span_start: start_span, span_end: end_span })
+    }
+
+    fn parse_maybe(&mut self) -> Result<TrackStatement, ParseError> {
+        let start_span = self.span().start;
+        self.advance();
+        self.expect(&Token::LParen)?;
+        let probability = match self.peek() {
+            Token::Number(p) if (0.0..=1.0).contains(&p) => {
+                self.advance();
+                p
+            }
+            found => {
+                return Err(ParseError::UnexpectedToken {
+                    expected: "probability between 0 and 1 for maybe()".into(),
+                    found,
+                    span: self.span(),
+                });
+            }
+        };
+        self.expect(&Token::RParen)?;
+
+        let mut body: Vec<TrackStatement> =
+            self.skip_newlines_collecting_comments().into_iter().map(track_comment).collect();
+        self.expect(&Token::LBrace)?;
+        body.extend(self.parse_track_body()?);
+        self.expect(&Token::RBrace)?;
+        let end_span = self.tokens[self.pos.saturating_sub(1)].span.end;
+
+        Ok(TrackStatement::Maybe { probability, body, span_start: start_span, span_end: end_span })
+    }
+
+    fn expect_poly_count(&mut self) -> Result<f64, ParseError> {
+        match self.peek() {
+            Token::Number(n) if n >= 1.0 && n.fract() == 0.0 => {
+                self.advance();
+                Ok(n)
+            }
+            found => Err(ParseError::UnexpectedToken {
+                expected: "whole number of steps for poly()".into(),
+                found,
+                span: self.span(),
+            }),
         }
-        Ok(parts.join(" "))
     }
 
     // ── Modifiers ───────────────────────────────────────────
 
-    /// Parse optional `*velocity` and `@duration` modifiers.
-    fn parse_modifiers(&mut self) -> Result<(Option<f64>, Option<DurationExpr>), ParseError> {
-        let velocity = if self.eat(&Token::Star) {
-            Some(self.expect_number()?)
-        } else {
+    /// Parse optional `*velocity` (or relative `*+10` / `*-10`) and
+    /// `@duration` modifiers.
+    fn parse_modifiers(&mut self) -> Result<(Option<VelocityExpr>, Option<DurationExpr>), ParseError> {
+        let velocity = if !self.eat(&Token::Star) {
             None
+        } else if self.check(&Token::Plus) || self.check(&Token::Minus) {
+            Some(VelocityExpr::Relative(self.parse_signed_number()?))
+        } else {
+            Some(VelocityExpr::Absolute(self.expect_number()?))
         };
 
         let duration = if self.eat(&Token::At) {
@@ -519,15 +995,16 @@ impl Parser {
 
     /// Parse a simple duration: `/N` or `N` (no fraction form).
     fn parse_simple_duration(&mut self) -> Result<DurationExpr, ParseError> {
+        self.relex_regex_as_slash();
         match self.peek() {
             Token::Slash => {
                 self.advance();
                 let n = self.expect_number()?;
-                Ok(DurationExpr::Inverse(n))
+                Ok(self.parse_dotted(DurationExpr::Inverse(n)))
             }
             Token::Number(n) => {
                 self.advance();
-                Ok(DurationExpr::Beats(n))
+                Ok(self.parse_dotted(DurationExpr::Beats(n)))
             }
             Token::Dot => {
                 let mut count = 0;
@@ -546,41 +1023,73 @@ impl Parser {
 
     // ── Duration Expressions ────────────────────────────────
 
-    /// Try to parse an optional duration expression (step duration).
+    /// Wrap `base` in any augmentation dots that follow it (`/4.`, `/4..`).
+    fn parse_dotted(&mut self, base: DurationExpr) -> DurationExpr {
+        let mut dots = 0;
+        while self.eat(&Token::Dot) {
+            dots += 1;
+        }
+        if dots == 0 {
+            base
+        } else {
+            DurationExpr::Dotted(Box::new(base), dots)
+        }
+    }
+
+    /// Try to parse an optional duration expression (step duration). A
+    /// `let` variable on the same line is a step too, unless it is being
+    /// assigned.
     fn try_parse_duration(&mut self) -> Result<Option<DurationExpr>, ParseError> {
+        self.relex_regex_as_slash();
         match self.peek() {
-            Token::Slash | Token::Number(_) | Token::Dot => {
+            Token::Slash | Token::Number(_) | Token::Dot | Token::Plus | Token::Minus => {
                 Ok(Some(self.parse_duration_expr()?))
             }
+            Token::Ident(name) if self.variables.contains(&name) && self.peek_at(1) != Token::Eq => {
+                self.advance();
+                Ok(Some(DurationExpr::Variable(name)))
+            }
             _ => Ok(None),
         }
     }
 
-    /// Parse a duration expression: `/N`, `N/M`, `N`, or dots.
+    /// Parse a duration expression: `/N`, `N/M`, `N`, or dots, optionally
+    /// preceded by a unary `+`.
     fn parse_duration_expr(&mut self) -> Result<DurationExpr, ParseError> {
+        self.relex_regex_as_slash();
         match self.peek() {
+            Token::Plus => {
+                self.advance();
+                self.parse_duration_expr()
+            }
+            Token::Minus => Err(ParseError::UnexpectedToken {
+                expected: "duration (durations cannot be negative)".into(),
+                found: Token::Minus,
+                span: self.span(),
+            }),
             Token::Slash => {
                 self.advance();
                 let n = self.expect_number()?;
-                Ok(DurationExpr::Inverse(n))
+                Ok(self.parse_dotted(DurationExpr::Inverse(n)))
             }
             Token::Number(n) => {
                 self.advance();
                 // Check for fraction: N/M
-                if self.check(&Token::Slash) {
+                let base = if self.check(&Token::Slash) {
                     let saved = self.pos;
                     self.advance(); // consume /
                     if let Token::Number(m) = self.peek() {
                         self.advance();
-                        Ok(DurationExpr::Fraction(n, m))
+                        DurationExpr::Fraction(n, m)
                     } else {
                         // Not a fraction, backtrack. The `/` belongs to something else.
                         self.pos = saved;
-                        Ok(DurationExpr::Beats(n))
+                        DurationExpr::Beats(n)
                     }
                 } else {
-                    Ok(DurationExpr::Beats(n))
-                }
+                    DurationExpr::Beats(n)
+                };
+                Ok(self.parse_dotted(base))
             }
             Token::Dot => {
                 let mut count = 0;
@@ -611,7 +1120,53 @@ impl Parser {
     }
 
     fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.parse_binary(0)
+    }
+
+    /// Precedence climbing: parse operands joined by operators that bind
+    /// at least as tightly as `min_precedence`, left-associatively.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        while let Some(op) = binary_op(&self.peek())
+            && op.precedence() >= min_precedence
+        {
+            self.advance();
+            let right = self.parse_binary(op.precedence() + 1)?;
+            left = Expr::Binary { op, left: Box::new(left), right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
         match self.peek() {
+            sign @ (Token::Minus | Token::Plus) => {
+                self.advance();
+                let negate = sign == Token::Minus;
+                Ok(match self.parse_unary()? {
+                    Expr::Number(n) => Expr::Number(if negate { -n } else { n }),
+                    Expr::DurationLit(DurationExpr::Fraction(n, m)) => {
+                        Expr::DurationLit(DurationExpr::Fraction(if negate { -n } else { n }, m))
+                    }
+                    operand if negate => Expr::Unary { op: UnaryOp::Neg, operand: Box::new(operand) },
+                    operand => operand,
+                })
+            }
+            Token::Bang => {
+                self.advance();
+                Ok(Expr::Unary { op: UnaryOp::Not, operand: Box::new(self.parse_unary()?) })
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Token::LParen => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect(&Token::RParen)?;
+                Ok(expr)
+            }
             Token::Number(n) => {
                 self.advance();
                 // Check for fraction
@@ -717,6 +1272,34 @@ impl Parser {
     }
 }
 
+/// The binary operator a token stands for, if any.
+fn binary_op(token: &Token) -> Option<BinaryOp> {
+    Some(match token {
+        Token::OrOr => BinaryOp::Or,
+        Token::AndAnd => BinaryOp::And,
+        Token::EqEq => BinaryOp::Eq,
+        Token::BangEq => BinaryOp::Ne,
+        Token::Lt => BinaryOp::Lt,
+        Token::LtEq => BinaryOp::Le,
+        Token::Gt => BinaryOp::Gt,
+        Token::GtEq => BinaryOp::Ge,
+        Token::Plus => BinaryOp::Add,
+        Token::Minus => BinaryOp::Sub,
+        Token::Star => BinaryOp::Mul,
+        Token::Slash => BinaryOp::Div,
+        _ => return None,
+    })
+}
+
+/// A collected comment as a track statement.
+fn track_comment((text, span): (String, Span)) -> TrackStatement {
+    TrackStatement::Comment { text, span_start: span.start, span_end: span.end }
+}
+
+fn comment((text, span): (String, Span)) -> Comment {
+    Comment { text, span_start: span.start, span_end: span.end }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -777,7 +1360,7 @@ track t() {
                     ..
                 } => {
                     assert_eq!(pitch, "C2");
-                    assert_eq!(*velocity, Some(90.0));
+                    assert_eq!(*velocity, Some(VelocityExpr::Absolute(90.0)));
                     assert_eq!(*audible_duration, Some(DurationExpr::Inverse(4.0)));
                     assert_eq!(*step_duration, Some(DurationExpr::Inverse(2.0)));
                 }
@@ -812,7 +1395,7 @@ track t() {
                 ..
             } => {
                 assert_eq!(name, "drums");
-                assert_eq!(*velocity, Some(96.0));
+                assert_eq!(*velocity, Some(VelocityExpr::Absolute(96.0)));
                 assert_eq!(*play_duration, Some(DurationExpr::Beats(4.0)));
                 assert_eq!(args.len(), 1);
                 assert_eq!(*step, Some(DurationExpr::Beats(8.0)));
@@ -933,9 +1516,9 @@ track t() {
                     body,
                     ..
                 } => {
-                    assert!(init.contains("let"));
-                    assert!(condition.contains("<"));
-                    assert!(update.contains("++"));
+                    assert!(matches!(init, Some(LoopClause::Let { name, value: Expr::Number(0.0) }) if name == "i"));
+                    assert!(matches!(condition, Some(Expr::Binary { op: BinaryOp::Lt, .. })));
+                    assert!(matches!(update, Some(LoopClause::Step { delta: 1.0, .. })));
                     let notes: Vec<_> = body
                         .iter()
                         .filter(|s| matches!(s, TrackStatement::NoteEvent { .. }))
@@ -1033,8 +1616,406 @@ track riff(instrument) {
         let non_comment: Vec<_> = program
             .statements
             .iter()
-            .filter(|s| !matches!(s, Statement::Comment(_)))
+            .filter(|s| !matches!(s, Statement::Comment { .. }))
             .collect();
         assert_eq!(non_comment.len(), 5);
     }
+
+    #[test]
+    fn test_parse_note_expression() {
+        let program = parse("track t() {\n    C4*90@/4 {pan: -0.5, brightness: 0.7} /4\n}\n").unwrap();
+        match &program.statements[0] {
+            Statement::TrackDef { body, .. } => match &body[0] {
+                TrackStatement::NoteEvent { expression, .. } => {
+                    assert_eq!(expression.pan, Some(-0.5));
+                    assert_eq!(expression.brightness, Some(0.7));
+                }
+                other => panic!("Expected NoteEvent, got {other:?}"),
+            },
+            other => panic!("Expected TrackDef, got {other:?}"),
+        }
+
+        let err = parse("track t() {\n    C4 /4 {volume: 1}\n}\n").unwrap_err();
+        assert!(err.to_string().contains("note expression field"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_slide() {
+        let program = parse("track t() {\n    C4->G4*90 /2\n}\n").unwrap();
+        match &program.statements[0] {
+            Statement::TrackDef { body, .. } => match &body[0] {
+                TrackStatement::NoteEvent { pitch, slide_to, velocity, step_duration, .. } => {
+                    assert_eq!(pitch, "C4");
+                    assert_eq!(slide_to.as_deref(), Some("G4"));
+                    assert_eq!(*velocity, Some(VelocityExpr::Absolute(90.0)));
+                    assert_eq!(*step_duration, Some(DurationExpr::Inverse(2.0)));
+                }
+                other => panic!("Expected NoteEvent, got {other:?}"),
+            },
+            other => panic!("Expected TrackDef, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_vibrato_and_bend() {
+        let program = parse("track t() {\n    C4~v^+2*90 /4\n    D4^-1 /4\n}\n").unwrap();
+        match &program.statements[0] {
+            Statement::TrackDef { body, .. } => {
+                match &body[0] {
+                    TrackStatement::NoteEvent { expression, velocity, .. } => {
+                        assert!(expression.vibrato);
+                        assert_eq!(expression.bend, Some(2.0));
+                        assert_eq!(*velocity, Some(VelocityExpr::Absolute(90.0)));
+                    }
+                    other => panic!("Expected NoteEvent, got {other:?}"),
+                }
+                match &body[1] {
+                    TrackStatement::NoteEvent { expression, .. } => {
+                        assert!(!expression.vibrato);
+                        assert_eq!(expression.bend, Some(-1.0));
+                    }
+                    other => panic!("Expected NoteEvent, got {other:?}"),
+                }
+            }
+            other => panic!("Expected TrackDef, got {other:?}"),
+        }
+
+        let err = parse("track t() {\n    C4~x /4\n}\n").unwrap_err();
+        assert!(err.to_string().contains("v (vibrato)"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_dotted_durations() {
+        let program = parse(
+            r#"
+track t() {
+    C3 /4.
+    D3 /4..
+    E3@/8. 3/8.
+    F3@2. /8
+}
+"#,
+        )
+        .unwrap();
+        let Statement::TrackDef { body, .. } = &program.statements[0] else {
+            panic!("Expected TrackDef");
+        };
+        let steps: Vec<_> = body
+            .iter()
+            .map(|s| match s {
+                TrackStatement::NoteEvent { step_duration, audible_duration, .. } => {
+                    (audible_duration.clone(), step_duration.clone())
+                }
+                other => panic!("Expected NoteEvent, got {other:?}"),
+            })
+            .collect();
+        let dotted = |d: DurationExpr, n| Some(DurationExpr::Dotted(Box::new(d), n));
+        assert_eq!(steps[0], (None, dotted(DurationExpr::Inverse(4.0), 1)));
+        assert_eq!(steps[1], (None, dotted(DurationExpr::Inverse(4.0), 2)));
+        assert_eq!(steps[2], (dotted(DurationExpr::Inverse(8.0), 1), dotted(DurationExpr::Fraction(3.0, 8.0), 1)));
+        assert_eq!(steps[3], (dotted(DurationExpr::Beats(2.0), 1), Some(DurationExpr::Inverse(8.0))));
+    }
+
+    #[test]
+    fn test_parse_explicit_rests() {
+        let program = parse(
+            r#"
+track t() {
+    r4
+    r/8
+    _ /4.
+    R*4
+    R
+    r()
+}
+"#,
+        )
+        .unwrap();
+        let Statement::TrackDef { body, .. } = &program.statements[0] else {
+            panic!("Expected TrackDef");
+        };
+        let rest = |i: usize| match &body[i] {
+            TrackStatement::Rest { duration, .. } => duration.clone(),
+            other => panic!("Expected Rest, got {other:?}"),
+        };
+        assert_eq!(rest(0), DurationExpr::Beats(4.0));
+        assert_eq!(rest(1), DurationExpr::Inverse(8.0));
+        assert_eq!(rest(2), DurationExpr::Dotted(Box::new(DurationExpr::Inverse(4.0)), 1));
+        assert!(matches!(body[3], TrackStatement::BarRest { bars, .. } if bars == 4.0));
+        assert!(matches!(body[4], TrackStatement::BarRest { bars, .. } if bars == 1.0));
+        // A track named `r` can still be called
+        assert!(matches!(&body[5], TrackStatement::TrackCall { name, .. } if name == "r"));
+    }
+
+    #[test]
+    fn test_parse_lyrics() {
+        let program = parse("track t() {\n    C4 \"sha-\" D4 /4 \"la\"\n    E4\n}").unwrap();
+        let Statement::TrackDef { body, .. } = &program.statements[0] else {
+            panic!("Expected TrackDef");
+        };
+        let lyrics: Vec<Option<&str>> = body
+            .iter()
+            .map(|s| match s {
+                TrackStatement::NoteEvent { lyric, .. } => lyric.as_deref(),
+                other => panic!("Expected NoteEvent, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(lyrics, vec![Some("sha-"), Some("la"), None]);
+    }
+
+    #[test]
+    fn test_parse_markers_and_cues() {
+        let program = parse("marker \"intro\";\ntrack t() {\n    cue \"boss_enter\"\n    C4 /4\n}").unwrap();
+        assert!(matches!(
+            &program.statements[0],
+            Statement::Marker { kind: MarkerKind::Marker, name, .. } if name == "intro"
+        ));
+        let Statement::TrackDef { body, .. } = &program.statements[1] else {
+            panic!("Expected TrackDef");
+        };
+        assert!(matches!(
+            &body[0],
+            TrackStatement::Marker { kind: MarkerKind::Cue, name, .. } if name == "boss_enter"
+        ));
+    }
+
+    #[test]
+    fn test_parse_unary_minus_and_plus() {
+        let program = parse(
+            r#"
+track.transpose = -12;
+const lead = Oscillator({type: 'square', detune: -7, mixer: +0.5});
+track t() {
+    track.swing = -1/8;
+    C4 +1
+}
+"#,
+        )
+        .unwrap();
+
+        match &program.statements[0] {
+            Statement::Assignment { value: Expr::Number(n), .. } => assert_eq!(*n, -12.0),
+            other => panic!("Expected a numeric Assignment, got {other:?}"),
+        }
+        match &program.statements[1] {
+            Statement::ConstDecl { value: Expr::FunctionCall { args, .. }, .. } => match &args[0] {
+                Expr::ObjectLit(props) => {
+                    assert!(matches!(props[1], (ref k, Expr::Number(n)) if k == "detune" && n == -7.0));
+                    assert!(matches!(props[2], (ref k, Expr::Number(n)) if k == "mixer" && n == 0.5));
+                }
+                other => panic!("Expected ObjectLit, got {other:?}"),
+            },
+            other => panic!("Expected ConstDecl, got {other:?}"),
+        }
+        match &program.statements[2] {
+            Statement::TrackDef { body, .. } => {
+                assert!(matches!(
+                    &body[0],
+                    TrackStatement::Assignment { value: Expr::DurationLit(DurationExpr::Fraction(n, m)), .. }
+                        if *n == -1.0 && *m == 8.0
+                ));
+                assert!(matches!(
+                    &body[1],
+                    TrackStatement::NoteEvent { step_duration: Some(DurationExpr::Beats(n)), .. } if *n == 1.0
+                ));
+            }
+            other => panic!("Expected TrackDef, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_unary_minus_errors() {
+        assert!(parse("track.transpose = -;").is_err());
+        // Step durations cannot be negative
+        let err = parse("track t() {\n    C4 -1\n}").unwrap_err();
+        assert!(err.to_string().contains("durations cannot be negative"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_doc_comments_on_tracks() {
+        let program = parse(
+            "/// Main melody.\n/// Plays twice.\ntrack lead() {\n    /// not a doc\n    C4 1\n}\n/// stray\nlead();\n/* block */\ntrack bass() {\n    C2 1\n}",
+        )
+        .unwrap();
+        let docs: Vec<Option<&str>> = program
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::TrackDef { doc, .. } => Some(doc.as_deref()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(docs, vec![Some("Main melody.\nPlays twice."), None]);
+
+        // Doc comments not attached to a track definition stay comments
+        let comments: Vec<&str> = program
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Comment { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(comments, vec!["stray", "block"]);
+        match &program.statements[0] {
+            Statement::TrackDef { body, .. } => assert!(matches!(&body[0], TrackStatement::Comment { text, .. } if text == "not a doc")),
+            other => panic!("Expected TrackDef, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_comments_keep_spans() {
+        let input = "// intro\ntrack t() {\n    C4 1 // root\n}";
+        let program = parse(input).unwrap();
+        let (start, end) = program.statements[0].span();
+        assert_eq!(&input[start..end], "// intro");
+        match &program.statements[1] {
+            Statement::TrackDef { body, .. } => {
+                let (start, end) = body[1].span();
+                assert!(matches!(&body[1], TrackStatement::Comment { text, .. } if text == "root"));
+                assert_eq!(&input[start..end], "// root");
+            }
+            other => panic!("Expected TrackDef, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_comments_inside_chords_and_loops() {
+        let source = "track t() {\n    [ // triad\n        C4, // root\n        E4,\n        // fifth\n        G4\n    ] 1\n    for (let i = 0; i < 2; i++) // twice\n    {\n        // body\n        C4 1\n    }\n}";
+        let program = parse(source).unwrap();
+        let body = match &program.statements[0] {
+            Statement::TrackDef { body, .. } => body,
+            other => panic!("Expected TrackDef, got {other:?}"),
+        };
+        match &body[0] {
+            TrackStatement::Chord { notes, .. } => {
+                let comments: Vec<Vec<&str>> =
+                    notes.iter().map(|n| n.comments.iter().map(|c| c.text.as_str()).collect()).collect();
+                assert_eq!(comments, vec![vec!["triad", "root"], vec![], vec!["fifth"]]);
+                // Each comment keeps its source span
+                for comment in notes.iter().flat_map(|n| &n.comments) {
+                    assert_eq!(&source[comment.span_start..comment.span_end], format!("// {}", comment.text));
+                }
+            }
+            other => panic!("Expected Chord, got {other:?}"),
+        }
+        match &body[1] {
+            TrackStatement::ForLoop { body, .. } => {
+                let texts: Vec<&str> = body
+                    .iter()
+                    .filter_map(|s| match s {
+                        TrackStatement::Comment { text, .. } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                assert_eq!(texts, vec!["twice", "body"]);
+                assert_eq!(body.len(), 3);
+            }
+            other => panic!("Expected ForLoop, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_version_pragma() {
+        let program = parse("// A song\n#version 2\ntrack.beatsPerMinute = 90;").unwrap();
+        assert_eq!(program.language_version, 2);
+        assert_eq!(program.statements.len(), 2);
+        assert_eq!(parse("track.beatsPerMinute = 90;").unwrap().language_version, 1);
+
+        for bad in [
+            "#version 3\n",
+            "#version two\n",
+            "#tempo 120\n",
+            "#version 2\n#version 2\n",
+            "track.beatsPerMinute = 90;\n#version 2\n",
+        ] {
+            assert!(parse(bad).is_err(), "{bad:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_parse_play_statement() {
+        let program = parse("const hook = riff(lead);\nplay hook at 16;\nplay hook;\nplay(lead);").unwrap();
+        assert!(matches!(
+            &program.statements[1],
+            Statement::Play { handle, at: Some(at), .. } if handle == "hook" && *at == 16.0
+        ));
+        assert!(matches!(&program.statements[2], Statement::Play { at: None, .. }));
+        assert!(matches!(&program.statements[3], Statement::TrackCall { name, .. } if name == "play"));
+        assert!(parse("play hook at soon;").is_err());
+    }
+
+    #[test]
+    fn test_parse_poly_block() {
+        let program = parse("track t() {\n    poly(3, 4)\n    {\n        C4 1\n    }\n    poly(1, 2);\n}").unwrap();
+        let Statement::TrackDef { body, .. } = &program.statements[0] else {
+            panic!("Expected TrackDef");
+        };
+        assert!(matches!(
+            &body[0],
+            TrackStatement::Poly { steps, span, body, .. } if *steps == 3.0 && *span == 4.0 && body.len() == 1
+        ));
+        // Without a block it is still a call to a track named `poly`
+        assert!(matches!(&body[1], TrackStatement::TrackCall { name, .. } if name == "poly"));
+        assert!(parse("track t() {\n    poly(3, 1/2) {\n        C4\n    }\n}").is_err());
+    }
+
+    #[test]
+    fn test_parse_choose_and_maybe() {
+        let program = parse("track t() {\n    choose([C4, E4])*80 /8\n    maybe(0.25) {\n        G4\n    }\n}").unwrap();
+        let Statement::TrackDef { body, .. } = &program.statements[0] else {
+            panic!("Expected TrackDef");
+        };
+        assert!(matches!(
+            &body[0],
+            TrackStatement::NoteEvent { pitch, choices, velocity: Some(_), .. } if pitch == "C4" && choices.len() == 2
+        ));
+        assert!(matches!(&body[1], TrackStatement::Maybe { probability, .. } if *probability == 0.25));
+        assert!(parse("track t() {\n    maybe(2) {\n        G4\n    }\n}").is_err());
+    }
+
+    #[test]
+    fn test_regex_relexed_as_duration() {
+        // At the start of input `/4 /8` lexes as a regex
+        let tokens = Lexer::new("/4 /8").tokenize().unwrap();
+        assert_eq!(tokens[0].token, Token::RegexLit("/4 /".into()));
+        let mut parser = Parser::new(tokens);
+        assert_eq!(parser.parse_duration_expr().unwrap(), DurationExpr::Inverse(4.0));
+        let span = parser.span();
+        assert_eq!(parser.parse_duration_expr().unwrap(), DurationExpr::Inverse(8.0));
+        assert_eq!((span.start, span.end), (3, 4));
+    }
+
+    #[test]
+    fn test_parse_operator_precedence() {
+        let value = |src: &str| match parse(&format!("x = {src};")).unwrap().statements.remove(0) {
+            Statement::Assignment { value, .. } => value,
+            other => panic!("Expected Assignment, got {other:?}"),
+        };
+        // `||` binds loosest, then `&&`, comparisons, `+`, `*`
+        let Expr::Binary { op: BinaryOp::Or, left, right } = value("a == 1 && b != 2 || !c") else {
+            panic!("Expected ||");
+        };
+        assert!(matches!(*left, Expr::Binary { op: BinaryOp::And, .. }));
+        assert!(matches!(*right, Expr::Unary { op: UnaryOp::Not, .. }));
+
+        let Expr::Binary { op: BinaryOp::Le, right, .. } = value("i + 1 <= n * 2") else {
+            panic!("Expected <=");
+        };
+        assert!(matches!(*right, Expr::Binary { op: BinaryOp::Mul, .. }));
+
+        // Left-associative, with parentheses overriding
+        let Expr::Binary { op: BinaryOp::Sub, left, .. } = value("a - b - c") else {
+            panic!("Expected -");
+        };
+        assert!(matches!(*left, Expr::Binary { op: BinaryOp::Sub, .. }));
+        let Expr::Binary { op: BinaryOp::Mul, left, .. } = value("(a + b) * 2") else {
+            panic!("Expected *");
+        };
+        assert!(matches!(*left, Expr::Binary { op: BinaryOp::Add, .. }));
+
+        assert!(matches!(value("-foo"), Expr::Unary { op: UnaryOp::Neg, .. }));
+        assert!(matches!(value("3/4"), Expr::DurationLit(DurationExpr::Fraction(..))));
+        assert!(matches!(value("x / 4"), Expr::Binary { op: BinaryOp::Div, .. }));
+    }
 }
@@ -0,0 +1,87 @@
+//! Note names, MIDI note numbers and frequencies.
+
+use alloc::format;
+use alloc::string::String;
+use crate::math;
+
+/// Parse a note name (e.g. "C4", "F#3", "Bb5") into a MIDI note number.
+pub fn note_to_midi(note: &str) -> Option<i32> {
+    let bytes = note.as_bytes();
+    if bytes.is_empty() {
+        return None;
+    }
+
+    // Parse note name (A-G)
+    let name = bytes[0] as char;
+    let base_semitone = match name {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    let mut idx = 1;
+    let mut semitone = base_semitone;
+
+    // Parse accidental
+    if idx < bytes.len() {
+        match bytes[idx] as char {
+            '#' => {
+                semitone += 1;
+                idx += 1;
+            }
+            'b' => {
+                semitone -= 1;
+                idx += 1;
+            }
+            _ => {}
+        }
+    }
+
+    // Parse octave number
+    let octave_str = &note[idx..];
+    let octave: i32 = octave_str.parse().ok()?;
+
+    // MIDI note number: C4 = 60
+    Some((octave + 1) * 12 + semitone)
+}
+
+/// Format a MIDI note number as a note name (e.g. 60 → "C4", 61 → "C#4").
+///
+/// Inverse of `note_to_midi`. Black keys use sharps unless `prefer_flats`
+/// is set (61 → "Db4").
+pub fn midi_to_note_name(midi: i32, prefer_flats: bool) -> String {
+    const SHARPS: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    const FLATS: [&str; 12] = ["C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B"];
+    let names = if prefer_flats { &FLATS } else { &SHARPS };
+    let octave = midi.div_euclid(12) - 1;
+    format!("{}{}", names[midi.rem_euclid(12) as usize], octave)
+}
+
+/// Convert a MIDI note number to frequency using the given tuning pitch.
+///
+/// `tuning_pitch` is the frequency of A4 (MIDI 69). Default is 440.0 Hz.
+/// Formula: `tuning_pitch * 2^((midi - 69) / 12)`
+pub fn midi_to_frequency(midi: i32, tuning_pitch: f64) -> f64 {
+    tuning_pitch * math::powf(2.0, (midi as f64 - 69.0) / 12.0)
+}
+
+/// Note-to-frequency conversion matching the JS `noteToFrequency`.
+///
+/// Uses the standard A4 = 440 Hz tuning. For custom tuning, use
+/// `note_to_midi()` + `midi_to_frequency()`.
+pub fn note_to_frequency(note: &str) -> Option<f64> {
+    note_to_frequency_with_tuning(note, 440.0)
+}
+
+/// Note-to-frequency conversion with configurable tuning pitch.
+///
+/// `tuning_pitch` is the frequency of A4. Common values: 440.0, 432.0.
+pub fn note_to_frequency_with_tuning(note: &str, tuning_pitch: f64) -> Option<f64> {
+    let midi = note_to_midi(note)?;
+    Some(midi_to_frequency(midi, tuning_pitch))
+}
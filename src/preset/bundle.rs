@@ -0,0 +1,315 @@
+//! Song bundles: a song's source, its compiled events and the presets it
+//! uses, packed into one file for offline sharing.
+//!
+//! The container is a flat list of named entries:
+//!
+//! ```text
+//! "SWBUNDLE"  magic
+//! u32 LE      format version (1)
+//! u32 LE      entry count
+//! per entry:  u32 LE name length, name (UTF-8), u64 LE data length, data
+//! ```
+//!
+//! Entries are `song.sw`, `events.json`, `presets.json` (the preset names,
+//! descriptors and file lists) and `presets/{index}/{path}` for each audio
+//! file. Content-addressed samples are bundled with their hash as the path.
+
+use serde::{Deserialize, Serialize};
+
+use crate::compiler::{self, EventList};
+use crate::preset::store::AudioStore;
+use crate::preset::{AudioReference, PresetDescriptor};
+
+const MAGIC: &[u8; 8] = b"SWBUNDLE";
+const FORMAT_VERSION: u32 = 1;
+
+/// A preset to pack into a bundle.
+#[derive(Debug, Clone)]
+pub struct BundledPreset {
+    /// Name the song loads the preset by (e.g. `"FluidR3/Piano"`).
+    pub name: String,
+    pub descriptor: PresetDescriptor,
+    /// Audio files the descriptor references by URL.
+    pub files: Vec<BundleFile>,
+}
+
+impl BundledPreset {
+    /// Bundle a preset whose content-addressed samples are in `store`.
+    pub fn from_store(name: &str, descriptor: PresetDescriptor, store: &dyn AudioStore) -> Result<Self, String> {
+        let mut files: Vec<BundleFile> = Vec::new();
+        for hash in content_hashes(&descriptor) {
+            if files.iter().any(|f| f.path == hash) {
+                continue;
+            }
+            let data = store.get(hash)?.ok_or_else(|| format!("Sample {hash} is not in the store"))?;
+            files.push(BundleFile { path: hash.to_string(), data });
+        }
+        Ok(BundledPreset { name: name.to_string(), descriptor, files })
+    }
+}
+
+/// Hashes of the content-addressed samples a preset uses.
+fn content_hashes(descriptor: &PresetDescriptor) -> Vec<&str> {
+    descriptor
+        .graph
+        .audio_references()
+        .into_iter()
+        .filter_map(|audio| match audio {
+            AudioReference::ContentAddressed { hash, .. } => Some(hash.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// An audio file inside a bundle, keyed by its URL relative to the
+/// preset descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleFile {
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+/// A bundle opened with `load_bundle`.
+#[derive(Debug, Clone)]
+pub struct SongBundle {
+    pub source: String,
+    pub events: EventList,
+    pub presets: Vec<BundledPreset>,
+}
+
+impl SongBundle {
+    /// Put the bundled content-addressed samples into `store`, so the
+    /// presets load from it offline.
+    pub fn import_audio(&self, store: &dyn AudioStore) -> Result<(), String> {
+        for preset in &self.presets {
+            for hash in content_hashes(&preset.descriptor) {
+                let file = preset
+                    .files
+                    .iter()
+                    .find(|f| f.path == hash)
+                    .ok_or_else(|| format!("Bundle is missing sample {hash} of '{}'", preset.name))?;
+                if store.put(&file.data)? != hash {
+                    return Err(format!("Bundled sample {hash} does not match its hash"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Manifest entry for one preset, stored in `presets.json`.
+#[derive(Serialize, Deserialize)]
+struct PresetEntry {
+    name: String,
+    descriptor: PresetDescriptor,
+    files: Vec<String>,
+}
+
+/// Compile `source` and pack it with the presets it references. Presets
+/// the song does not use are left out; referenced presets missing from
+/// `presets` are left for the player to resolve (or stand in for).
+pub fn bundle_song(source: &str, presets: &[BundledPreset]) -> Result<Vec<u8>, String> {
+    let program = crate::parse(source).map_err(|e| e.to_string())?;
+    let events = compiler::compile(&program)?;
+    // Presets played by the notes, including inline `loadPreset` calls
+    // and the children of layers and splits
+    let used: Vec<String> = events.instruments.iter().flat_map(|i| i.preset_refs()).collect();
+    let presets: Vec<&BundledPreset> = presets.iter().filter(|p| used.contains(&p.name)).collect();
+
+    let manifest: Vec<PresetEntry> = presets
+        .iter()
+        .map(|p| PresetEntry {
+            name: p.name.clone(),
+            descriptor: p.descriptor.clone(),
+            files: p.files.iter().map(|f| f.path.clone()).collect(),
+        })
+        .collect();
+    let events_json = serde_json::to_vec(&events).map_err(|e| e.to_string())?;
+    let manifest_json = serde_json::to_vec(&manifest).map_err(|e| e.to_string())?;
+
+    let mut entries: Vec<(String, &[u8])> = vec![
+        ("song.sw".to_string(), source.as_bytes()),
+        ("events.json".to_string(), &events_json),
+        ("presets.json".to_string(), &manifest_json),
+    ];
+    for (index, preset) in presets.iter().enumerate() {
+        for file in &preset.files {
+            entries.push((format!("presets/{index}/{}", file.path), &file.data));
+        }
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (name, data) in entries {
+        out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        out.extend_from_slice(data);
+    }
+    Ok(out)
+}
+
+/// Open a bundle written by `bundle_song`.
+pub fn load_bundle(bytes: &[u8]) -> Result<SongBundle, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("Not a SongWalker bundle".to_string());
+    }
+    let version = reader.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(format!("Unsupported bundle version {version}"));
+    }
+    let count = reader.u32()?;
+    let mut entries = std::collections::HashMap::new();
+    for _ in 0..count {
+        let name_len = reader.u32()? as usize;
+        let name = std::str::from_utf8(reader.take(name_len)?)
+            .map_err(|_| "Bundle entry name is not UTF-8".to_string())?;
+        let data_len = usize::try_from(reader.u64()?).map_err(|_| "Bundle entry too large".to_string())?;
+        entries.insert(name.to_string(), reader.take(data_len)?);
+    }
+
+    let entry = |name: &str| {
+        entries
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("Bundle is missing '{name}'"))
+    };
+    let source = String::from_utf8(entry("song.sw")?.to_vec())
+        .map_err(|_| "Bundled song is not UTF-8".to_string())?;
+    let events: EventList = serde_json::from_slice(entry("events.json")?).map_err(|e| e.to_string())?;
+    let manifest: Vec<PresetEntry> =
+        serde_json::from_slice(entry("presets.json")?).map_err(|e| e.to_string())?;
+    let presets = manifest
+        .into_iter()
+        .enumerate()
+        .map(|(index, p)| {
+            let files = p
+                .files
+                .into_iter()
+                .map(|path| {
+                    let data = entry(&format!("presets/{index}/{path}"))?.to_vec();
+                    Ok(BundleFile { path, data })
+                })
+                .collect::<Result<_, String>>()?;
+            Ok(BundledPreset { name: p.name, descriptor: p.descriptor, files })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(SongBundle { source, events, presets })
+}
+
+/// Reads little-endian fields from a bundle, failing on truncation.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| "Bundle is truncated".to_string())?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(name: &str) -> BundledPreset {
+        let json = format!(
+            r#"{{"name":"{name}","category":"sampler","graph":{{"type":"sampler","config":{{"zones":[{{
+                "keyRange":{{"low":0,"high":127}},"pitch":{{"rootNote":60,"fineTuneCents":0}},
+                "sampleRate":44100,"audio":{{"type":"external","url":"samples/c4.wav","codec":"wav"}}}}]}}}}}}"#
+        );
+        BundledPreset {
+            name: name.to_string(),
+            descriptor: serde_json::from_str(&json).unwrap(),
+            files: vec![BundleFile { path: "samples/c4.wav".to_string(), data: vec![1, 2, 3] }],
+        }
+    }
+
+    const SONG: &str = "\
+const piano = loadPreset(\"Lib/Piano\");
+track a() {
+    track.instrument = piano;
+    C4 1
+}
+a();";
+
+    #[test]
+    fn bundle_round_trips_song_and_used_presets() {
+        let bytes = bundle_song(SONG, &[preset("Lib/Piano"), preset("Lib/Unused")]).unwrap();
+        let bundle = load_bundle(&bytes).unwrap();
+        assert_eq!(bundle.source, SONG);
+        assert_eq!(bundle.events.events.len(), compiler::compile(&crate::parse(SONG).unwrap()).unwrap().events.len());
+        assert_eq!(bundle.presets.len(), 1);
+        let piano = &bundle.presets[0];
+        assert_eq!((piano.name.as_str(), piano.descriptor.name.as_str()), ("Lib/Piano", "Lib/Piano"));
+        assert_eq!(piano.files, preset("Lib/Piano").files);
+    }
+
+    #[test]
+    fn bundle_includes_inline_and_layered_presets() {
+        let source = "\
+track a() {
+    track.instrument = loadPreset(\"Lib/Piano\");
+    C4 1
+    track.instrument = Layer([Oscillator({type: 'sine'}), loadPreset(\"Lib/Pad\")]);
+    E4 1
+}
+a();";
+        let all = [preset("Lib/Piano"), preset("Lib/Pad"), preset("Lib/Unused")];
+        let bundle = load_bundle(&bundle_song(source, &all).unwrap()).unwrap();
+        let names: Vec<&str> = bundle.presets.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["Lib/Piano", "Lib/Pad"]);
+    }
+
+    #[test]
+    fn load_bundle_rejects_bad_input() {
+        assert_eq!(load_bundle(b"RIFF....").unwrap_err(), "Not a SongWalker bundle");
+        let bytes = bundle_song(SONG, &[]).unwrap();
+        assert_eq!(load_bundle(&bytes[..bytes.len() - 1]).unwrap_err(), "Bundle is truncated");
+        assert!(bundle_song("track a( {", &[]).is_err());
+    }
+
+    #[test]
+    fn content_addressed_samples_move_through_the_store() {
+        use crate::preset::store::{content_hash, MemoryStore};
+
+        let source = MemoryStore::new();
+        let hash = source.put(b"pcm bytes").unwrap();
+        let json = format!(
+            r#"{{"name":"Piano","category":"sampler","graph":{{"type":"sampler","config":{{"zones":[{{
+                "keyRange":{{"low":0,"high":127}},"pitch":{{"rootNote":60,"fineTuneCents":0}},
+                "sampleRate":44100,"audio":{{"type":"content-addressed","hash":"{hash}","codec":"raw"}}}}]}}}}}}"#
+        );
+        let piano = BundledPreset::from_store("Lib/Piano", serde_json::from_str(&json).unwrap(), &source).unwrap();
+        assert_eq!(piano.files[0].path, hash);
+
+        let bundle = load_bundle(&bundle_song(SONG, &[piano]).unwrap()).unwrap();
+        let target = MemoryStore::new();
+        bundle.import_audio(&target).unwrap();
+        assert_eq!(target.get(&hash).unwrap(), Some(b"pcm bytes".to_vec()));
+
+        let empty = MemoryStore::new();
+        let missing = BundledPreset::from_store("Lib/Piano", bundle.presets[0].descriptor.clone(), &empty);
+        assert_eq!(missing.unwrap_err(), format!("Sample {} is not in the store", content_hash(b"pcm bytes")));
+    }
+}
@@ -0,0 +1,568 @@
+//! Building and editing presets in code, so editors always write a
+//! `preset.json` that loads.
+
+use serde::{Deserialize, Serialize};
+
+use crate::dsp::sampler::SampleBuffer;
+use crate::dsp::tuner::PitchEstimate;
+use crate::preset::{
+    ADSRConfig, AudioReference, CompositeMode, KeyRange, OscillatorConfig, PresetCategory,
+    PresetDescriptor, PresetNode, SampleZone, SamplerConfig, VelocityRange, WaveformType, ZonePitch,
+};
+
+/// One change to a preset, as sent by the web preset editor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum PresetEdit {
+    SetName { name: String },
+    AddZone { zone: SampleZone },
+    #[serde(rename_all = "camelCase")]
+    UpdateZone { index: usize, zone: SampleZone },
+    RemoveZone { index: usize },
+    SetEnvelope { envelope: ADSRConfig },
+    #[serde(rename_all = "camelCase")]
+    SetDrumKit { is_drum_kit: bool },
+    AddLayer { node: PresetNode },
+}
+
+/// Builds a new preset, e.g. `PresetBuilder::sampler("Piano")
+/// .add_zone(zone).envelope(adsr).build()`. `build` reports the first
+/// edit that failed, then checks the result with
+/// `PresetDescriptor::validate`.
+#[derive(Debug, Clone)]
+pub struct PresetBuilder {
+    descriptor: PresetDescriptor,
+    /// First edit that failed; reported by `build`.
+    error: Option<String>,
+}
+
+impl PresetBuilder {
+    /// An empty sampler preset.
+    pub fn sampler(name: &str) -> Self {
+        let config = SamplerConfig { zones: Vec::new(), is_drum_kit: false, envelope: None };
+        Self::with_graph(name, PresetCategory::Sampler, PresetNode::Sampler { config })
+    }
+
+    /// An oscillator preset.
+    pub fn oscillator(name: &str, waveform: WaveformType) -> Self {
+        let config = OscillatorConfig { waveform, detune: None, envelope: None, mixer: None };
+        Self::with_graph(name, PresetCategory::Synth, PresetNode::Oscillator { config })
+    }
+
+    fn with_graph(name: &str, category: PresetCategory, graph: PresetNode) -> Self {
+        let descriptor = PresetDescriptor {
+            format: None,
+            version: None,
+            id: String::new(),
+            name: name.to_string(),
+            category,
+            tags: Vec::new(),
+            metadata: None,
+            tuning: None,
+            graph,
+        };
+        PresetBuilder { descriptor, error: None }
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.descriptor.id = id.to_string();
+        self
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.descriptor.tags.push(tag.to_string());
+        self
+    }
+
+    pub fn drum_kit(self, is_drum_kit: bool) -> Self {
+        self.edit(PresetEdit::SetDrumKit { is_drum_kit })
+    }
+
+    pub fn add_zone(self, zone: SampleZone) -> Self {
+        self.edit(PresetEdit::AddZone { zone })
+    }
+
+    pub fn envelope(self, envelope: ADSRConfig) -> Self {
+        self.edit(PresetEdit::SetEnvelope { envelope })
+    }
+
+    pub fn add_layer(self, node: PresetNode) -> Self {
+        self.edit(PresetEdit::AddLayer { node })
+    }
+
+    fn edit(mut self, edit: PresetEdit) -> Self {
+        if self.error.is_none() {
+            self.error = self.descriptor.apply(edit).err();
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<PresetDescriptor, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.descriptor.validate()?;
+        Ok(self.descriptor)
+    }
+}
+
+impl PresetDescriptor {
+    /// Apply one edit. The preset is unchanged if the edit fails.
+    pub fn apply(&mut self, edit: PresetEdit) -> Result<(), String> {
+        match edit {
+            PresetEdit::SetName { name } => {
+                self.name = name;
+                Ok(())
+            }
+            PresetEdit::AddZone { zone } => self.add_zone(zone),
+            PresetEdit::UpdateZone { index, zone } => {
+                let zones = self.sampler_zones()?;
+                let len = zones.len();
+                let slot = zones.get_mut(index).ok_or_else(|| zone_out_of_range(index, len))?;
+                *slot = zone;
+                Ok(())
+            }
+            PresetEdit::RemoveZone { index } => self.remove_zone(index).map(|_| ()),
+            PresetEdit::SetEnvelope { envelope } => self.set_envelope(envelope),
+            PresetEdit::SetDrumKit { is_drum_kit } => match &mut self.graph {
+                PresetNode::Sampler { config } => {
+                    config.is_drum_kit = is_drum_kit;
+                    Ok(())
+                }
+                _ => Err(not_a_sampler()),
+            },
+            PresetEdit::AddLayer { node } => {
+                self.add_layer(node);
+                Ok(())
+            }
+        }
+    }
+
+    /// Add a zone to a sampler preset.
+    pub fn add_zone(&mut self, zone: SampleZone) -> Result<(), String> {
+        self.sampler_zones()?.push(zone);
+        Ok(())
+    }
+
+    pub fn remove_zone(&mut self, index: usize) -> Result<SampleZone, String> {
+        let zones = self.sampler_zones()?;
+        if index >= zones.len() {
+            return Err(zone_out_of_range(index, zones.len()));
+        }
+        Ok(zones.remove(index))
+    }
+
+    /// Set the envelope of the preset's sound sources (every layer of a
+    /// composite).
+    pub fn set_envelope(&mut self, envelope: ADSRConfig) -> Result<(), String> {
+        fn set(node: &mut PresetNode, envelope: &ADSRConfig) -> bool {
+            match node {
+                PresetNode::Sampler { config } => config.envelope = Some(envelope.clone()),
+                PresetNode::Oscillator { config } => config.envelope = Some(envelope.clone()),
+                PresetNode::Composite { children, .. } => {
+                    let mut any = false;
+                    for child in children {
+                        any |= set(child, envelope);
+                    }
+                    return any;
+                }
+                PresetNode::Effect { .. } => return false,
+            }
+            true
+        }
+        if set(&mut self.graph, &envelope) {
+            Ok(())
+        } else {
+            Err("Preset has no oscillator or sampler to set an envelope on".to_string())
+        }
+    }
+
+    /// Layer `node` over the preset, turning it into a layer composite if
+    /// it is not one already.
+    pub fn add_layer(&mut self, node: PresetNode) {
+        if let PresetNode::Composite { mode: CompositeMode::Layer, children, config } = &mut self.graph {
+            if let Some(levels) = config.as_mut().and_then(|c| c.mix_levels.as_mut()) {
+                levels.push(1.0);
+            }
+            children.push(node);
+            return;
+        }
+        let base = std::mem::replace(
+            &mut self.graph,
+            PresetNode::Composite { mode: CompositeMode::Layer, children: Vec::new(), config: None },
+        );
+        if let PresetNode::Composite { children, .. } = &mut self.graph {
+            children.extend([base, node]);
+        }
+        self.category = PresetCategory::Composite;
+    }
+
+    fn sampler_zones(&mut self) -> Result<&mut Vec<SampleZone>, String> {
+        match &mut self.graph {
+            PresetNode::Sampler { config } => Ok(&mut config.zones),
+            _ => Err(not_a_sampler()),
+        }
+    }
+
+    /// Check that the preset would load: key ranges, root notes, loop
+    /// points, envelopes and composite layouts are in range.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Preset name is empty".to_string());
+        }
+        validate_node(&self.graph)
+    }
+}
+
+// ── Automatic key mapping ───────────────────────────────────
+
+/// A sample to place with `auto_map_zones`, with the pitch the tuner
+/// detected for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct TunedSample {
+    pub audio: AudioReference,
+    pub sample_rate: u32,
+    pub root_note: u8,
+    #[serde(default)]
+    pub fine_tune_cents: f64,
+    /// RMS level of the recording, to order velocity layers of one root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<f64>,
+}
+
+impl TunedSample {
+    /// Use a `detect_pitch` result, or `None` if it found no clear pitch.
+    pub fn from_estimate(audio: AudioReference, sample_rate: u32, estimate: &PitchEstimate) -> Option<Self> {
+        (!estimate.is_noise).then_some(TunedSample {
+            audio,
+            sample_rate,
+            root_note: estimate.midi_note,
+            fine_tune_cents: estimate.fine_tune_cents,
+            level: None,
+        })
+    }
+
+    /// Set `level` to the RMS of the recording.
+    pub fn with_level(mut self, buffer: &SampleBuffer) -> Self {
+        self.level = Some(buffer.rms() as f64);
+        self
+    }
+}
+
+/// Build a sampler from tuned samples: each root gets the keys nearer to
+/// it than to any other root, and together they cover 0..127.
+///
+/// Several recordings of one root become velocity layers: they are sorted
+/// by `level` and split 0..127 into equal velocity bands, the quietest
+/// taking the lowest. Zones are in root note, then velocity order.
+pub fn auto_map_zones(samples: &[TunedSample]) -> Result<SamplerConfig, String> {
+    if samples.is_empty() {
+        return Err("No samples to map".to_string());
+    }
+    if samples.iter().any(|s| s.root_note > 127) {
+        return Err("Root notes must be within 0..127".to_string());
+    }
+    let mut sorted: Vec<&TunedSample> = samples.iter().collect();
+    sorted.sort_by(|a, b| {
+        a.root_note
+            .cmp(&b.root_note)
+            .then(a.level.unwrap_or(0.0).total_cmp(&b.level.unwrap_or(0.0)))
+    });
+    let layers: Vec<&[&TunedSample]> = sorted.chunk_by(|a, b| a.root_note == b.root_note).collect();
+    if let Some(layer) = layers.iter().find(|l| l.len() > 1 && l.iter().any(|s| s.level.is_none())) {
+        return Err(format!(
+            "Several samples have root note {}; give each a level to use them as velocity layers",
+            layer[0].root_note
+        ));
+    }
+
+    let mut zones = Vec::new();
+    for (i, layer) in layers.iter().enumerate() {
+        let root = layer[0].root_note;
+        // Split halfway between neighbouring roots; the lower zone takes
+        // the middle key of an even gap
+        let low = match i {
+            0 => 0,
+            _ => (layers[i - 1][0].root_note + root) / 2 + 1,
+        };
+        let high = match layers.get(i + 1) {
+            Some(next) => (root + next[0].root_note) / 2,
+            None => 127,
+        };
+        for (band, sample) in layer.iter().enumerate() {
+            let velocity_range = (layer.len() > 1).then_some(VelocityRange {
+                low: (band * 128 / layer.len()) as u8,
+                high: ((band + 1) * 128 / layer.len() - 1) as u8,
+            });
+            zones.push(SampleZone {
+                key_range: KeyRange { low, high },
+                velocity_range,
+                pitch: ZonePitch { root_note: root, fine_tune_cents: sample.fine_tune_cents },
+                sample_rate: sample.sample_rate,
+                r#loop: None,
+                exclusive_group: None,
+                audio: sample.audio.clone(),
+                release_audio: None,
+                key_tracking: None,
+                gain: None,
+                pan: None,
+            });
+        }
+    }
+    Ok(SamplerConfig { zones, is_drum_kit: false, envelope: None })
+}
+
+fn not_a_sampler() -> String {
+    "Preset graph is not a sampler".to_string()
+}
+
+fn zone_out_of_range(index: usize, len: usize) -> String {
+    format!("Zone {index} does not exist (the preset has {len})")
+}
+
+fn validate_node(node: &PresetNode) -> Result<(), String> {
+    match node {
+        PresetNode::Oscillator { config } => {
+            if let Some(envelope) = &config.envelope {
+                validate_envelope(envelope)?;
+            }
+            match config.mixer {
+                Some(mixer) if !(0.0..=1.0).contains(&mixer) => {
+                    Err(format!("Oscillator mixer {mixer} is outside 0..1"))
+                }
+                _ => Ok(()),
+            }
+        }
+        PresetNode::Sampler { config } => {
+            if let Some(envelope) = &config.envelope {
+                validate_envelope(envelope)?;
+            }
+            for (i, zone) in config.zones.iter().enumerate() {
+                validate_zone(zone).map_err(|e| format!("Zone {i}: {e}"))?;
+            }
+            Ok(())
+        }
+        PresetNode::Effect { .. } => Ok(()),
+        PresetNode::Composite { mode, children, config } => {
+            if children.is_empty() {
+                return Err("Composite has no children".to_string());
+            }
+            if let Some(config) = config {
+                if let Some(levels) = &config.mix_levels
+                    && levels.len() != children.len()
+                {
+                    return Err(format!(
+                        "Composite has {} mix levels for {} children",
+                        levels.len(),
+                        children.len()
+                    ));
+                }
+                if let (CompositeMode::Split, Some(points)) = (mode, &config.split_points) {
+                    if points.len() + 1 != children.len() {
+                        return Err(format!(
+                            "Split has {} split points for {} children",
+                            points.len(),
+                            children.len()
+                        ));
+                    }
+                    if points.windows(2).any(|w| w[0] >= w[1]) {
+                        return Err("Split points must be in ascending order".to_string());
+                    }
+                }
+            }
+            children.iter().try_for_each(validate_node)
+        }
+    }
+}
+
+fn validate_zone(zone: &SampleZone) -> Result<(), String> {
+    let keys = &zone.key_range;
+    if keys.low > keys.high || keys.high > 127 {
+        return Err(format!("key range {}..{} is not within 0..127", keys.low, keys.high));
+    }
+    if let Some(velocity) = &zone.velocity_range
+        && (velocity.low > velocity.high || velocity.high > 127)
+    {
+        return Err(format!("velocity range {}..{} is not within 0..127", velocity.low, velocity.high));
+    }
+    if zone.pitch.root_note > 127 {
+        return Err(format!("root note {} is above 127", zone.pitch.root_note));
+    }
+    if zone.sample_rate == 0 {
+        return Err("sample rate is 0".to_string());
+    }
+    if let Some(points) = &zone.r#loop
+        && points.start >= points.end
+    {
+        return Err(format!("loop {}..{} is empty", points.start, points.end));
+    }
+    if let Some(pan) = zone.pan
+        && !(-1.0..=1.0).contains(&pan)
+    {
+        return Err(format!("pan {pan} is outside -1..1"));
+    }
+    Ok(())
+}
+
+fn validate_envelope(envelope: &ADSRConfig) -> Result<(), String> {
+    let times = [
+        ("attack", envelope.attack),
+        ("hold", envelope.hold.unwrap_or(0.0)),
+        ("decay", envelope.decay),
+        ("release", envelope.release),
+    ];
+    for (stage, time) in times {
+        if !time.is_finite() || time < 0.0 {
+            return Err(format!("Envelope {stage} {time} must be a non-negative time"));
+        }
+    }
+    if !(0.0..=1.0).contains(&envelope.sustain) {
+        return Err(format!("Envelope sustain {} is outside 0..1", envelope.sustain));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preset::{AudioCodec, AudioReference, KeyRange, ZonePitch};
+
+    fn zone(low: u8, high: u8, root: u8) -> SampleZone {
+        SampleZone {
+            key_range: KeyRange { low, high },
+            velocity_range: None,
+            pitch: ZonePitch { root_note: root, fine_tune_cents: 0.0 },
+            sample_rate: 44100,
+            r#loop: None,
+            exclusive_group: None,
+            audio: AudioReference::External { url: format!("{root}.wav"), codec: AudioCodec::Wav, sha256: None },
+            release_audio: None,
+            key_tracking: None,
+            gain: None,
+            pan: None,
+        }
+    }
+
+    fn adsr(sustain: f64) -> ADSRConfig {
+        ADSRConfig { attack: 0.01, decay: 0.1, sustain, release: 0.3, ..Default::default() }
+    }
+
+    #[test]
+    fn builder_makes_a_loadable_sampler() {
+        let piano = PresetBuilder::sampler("Piano")
+            .id("piano")
+            .add_zone(zone(0, 59, 48))
+            .add_zone(zone(60, 127, 72))
+            .envelope(adsr(0.8))
+            .build()
+            .unwrap();
+        let json = serde_json::to_string(&piano).unwrap();
+        let loaded: PresetDescriptor = serde_json::from_str(&json).unwrap();
+        match loaded.graph {
+            PresetNode::Sampler { config } => {
+                assert_eq!(config.zones.len(), 2);
+                assert_eq!(config.envelope.map(|e| e.sustain), Some(0.8));
+            }
+            other => panic!("Expected a sampler, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn builder_reports_invalid_presets() {
+        let inverted = PresetBuilder::sampler("Piano").add_zone(zone(60, 50, 55)).build();
+        assert_eq!(inverted.unwrap_err(), "Zone 0: key range 60..50 is not within 0..127");
+        let sustain = PresetBuilder::sampler("Piano").envelope(adsr(1.5)).build();
+        assert_eq!(sustain.unwrap_err(), "Envelope sustain 1.5 is outside 0..1");
+        let zone_on_synth = PresetBuilder::oscillator("Lead", WaveformType::Square).add_zone(zone(0, 127, 60));
+        assert_eq!(zone_on_synth.build().unwrap_err(), "Preset graph is not a sampler");
+    }
+
+    #[test]
+    fn edits_mutate_zones_and_layers() {
+        let mut preset = PresetBuilder::sampler("Pad").add_zone(zone(0, 127, 60)).build().unwrap();
+        let edits: Vec<PresetEdit> = serde_json::from_str(
+            r#"[{"op": "setName", "name": "Warm Pad"},
+                {"op": "addLayer", "node": {"type": "oscillator", "config": {"waveform": "sine"}}},
+                {"op": "setEnvelope", "envelope": {"attack": 0.5, "decay": 0.1, "sustain": 1, "release": 1}}]"#,
+        )
+        .unwrap();
+        for edit in edits {
+            preset.apply(edit).unwrap();
+        }
+        assert_eq!(preset.name, "Warm Pad");
+        assert_eq!(preset.category, PresetCategory::Composite);
+        let PresetNode::Composite { children, .. } = &preset.graph else {
+            panic!("Expected a layer composite");
+        };
+        assert_eq!(children.len(), 2);
+        assert!(children.iter().all(|c| match c {
+            PresetNode::Sampler { config } => config.envelope.is_some(),
+            PresetNode::Oscillator { config } => config.envelope.is_some(),
+            _ => false,
+        }));
+        assert_eq!(preset.remove_zone(0).unwrap_err(), "Preset graph is not a sampler");
+        preset.validate().unwrap();
+    }
+
+    #[test]
+    fn auto_map_splits_keys_between_roots() {
+        let tuned = |root: u8| TunedSample {
+            audio: AudioReference::External { url: format!("{root}.wav"), codec: AudioCodec::Wav, sha256: None },
+            sample_rate: 44100,
+            root_note: root,
+            fine_tune_cents: -8.0,
+            level: None,
+        };
+        let config = auto_map_zones(&[tuned(72), tuned(48), tuned(60)]).unwrap();
+        let ranges: Vec<(u8, u8, u8)> = config
+            .zones
+            .iter()
+            .map(|z| (z.key_range.low, z.key_range.high, z.pitch.root_note))
+            .collect();
+        assert_eq!(ranges, vec![(0, 54, 48), (55, 66, 60), (67, 127, 72)]);
+        assert_eq!(config.zones[0].pitch.fine_tune_cents, -8.0);
+
+        // Odd gaps split evenly; the result passes the preset lint
+        let config = auto_map_zones(&[tuned(60), tuned(63)]).unwrap();
+        assert_eq!((config.zones[0].key_range.high, config.zones[1].key_range.low), (61, 62));
+        let mut piano = PresetBuilder::sampler("Auto").build().unwrap();
+        piano.graph = PresetNode::Sampler { config };
+        assert_eq!(crate::preset::lint::validate_preset(&piano), vec![]);
+
+        let unlevelled = auto_map_zones(&[tuned(60), tuned(60)]).unwrap_err();
+        assert!(unlevelled.starts_with("Several samples have root note 60"));
+    }
+
+    #[test]
+    fn auto_map_sorts_recordings_into_velocity_layers() {
+        let take = |root: u8, amplitude: f32| {
+            let audio = AudioReference::External { url: format!("{root}-{amplitude}.wav"), codec: AudioCodec::Wav, sha256: None };
+            let estimate = PitchEstimate {
+                frequency: 0.0,
+                confidence: 1.0,
+                midi_note: root,
+                fine_tune_cents: 0.0,
+                is_noise: false,
+            };
+            TunedSample::from_estimate(audio, 44100, &estimate)
+                .unwrap()
+                .with_level(&SampleBuffer::new(vec![amplitude, -amplitude], 44100))
+        };
+        let config = auto_map_zones(&[take(60, 0.9), take(60, 0.1), take(60, 0.4), take(72, 0.5)]).unwrap();
+        let layers: Vec<(u8, Option<(u8, u8)>)> = config
+            .zones
+            .iter()
+            .map(|z| (z.pitch.root_note, z.velocity_range.as_ref().map(|v| (v.low, v.high))))
+            .collect();
+        assert_eq!(
+            layers,
+            vec![(60, Some((0, 41))), (60, Some((42, 84))), (60, Some((85, 127))), (72, None)]
+        );
+        let AudioReference::External { url, .. } = &config.zones[0].audio else { unreachable!() };
+        assert_eq!(url, "60-0.1.wav", "The quietest take plays softest");
+        assert!(config.zones[..3].iter().all(|z| (z.key_range.low, z.key_range.high) == (0, 66)));
+    }
+}
@@ -20,6 +20,8 @@ pub struct LoadedZone {
     pub channels: u16,
     /// Original sample rate.
     pub sample_rate: u32,
+    /// Decoded note-off sample, if the zone has `releaseAudio`.
+    pub release_pcm: Option<Arc<[f32]>>,
 }
 
 impl PresetInstance {
@@ -0,0 +1,98 @@
+//! SHA-256 checks for external preset audio.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::preset::store::content_hash;
+use crate::preset::{AudioReference, PresetDescriptor};
+
+/// Audio whose bytes do not match the `sha256` its preset declares.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityError {
+    pub url: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Integrity check failed for {}: expected sha256 {}, got {}",
+            self.url, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// Check `bytes` fetched from `url` against an expected SHA-256 (hex, any
+/// case).
+pub fn verify_sha256(url: &str, bytes: &[u8], expected: &str) -> Result<(), IntegrityError> {
+    let actual = content_hash(bytes);
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(IntegrityError { url: url.to_string(), expected: expected.to_string(), actual })
+    }
+}
+
+/// Fill in `sha256` for the preset's external audio that lacks one,
+/// reading each file's bytes with `read(url)`. Returns how many hashes
+/// were added. Existing hashes are kept as they are.
+pub fn hash_preset_assets(
+    descriptor: &mut PresetDescriptor,
+    mut read: impl FnMut(&str) -> Result<Vec<u8>, String>,
+) -> Result<usize, String> {
+    let mut added = 0;
+    for audio in descriptor.graph.audio_references_mut() {
+        if let AudioReference::External { url, sha256: sha256 @ None, .. } = audio {
+            *sha256 = Some(content_hash(&read(url)?));
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_sha256_reports_mismatch() {
+        let hash = content_hash(b"sample");
+        assert!(verify_sha256("a.wav", b"sample", &hash.to_uppercase()).is_ok());
+        let err = verify_sha256("a.wav", b"tampered", &hash).unwrap_err();
+        assert_eq!(err.expected, hash);
+        assert_eq!(err.actual, content_hash(b"tampered"));
+        assert!(err.to_string().starts_with("Integrity check failed for a.wav"));
+    }
+
+    #[test]
+    fn hash_preset_assets_backfills_missing_hashes() {
+        let json = r#"{"name":"Piano","category":"sampler","graph":{"type":"sampler","config":{"zones":[
+            {"keyRange":{"low":0,"high":59},"pitch":{"rootNote":48,"fineTuneCents":0},"sampleRate":44100,
+             "audio":{"type":"external","url":"c3.wav","codec":"wav"}},
+            {"keyRange":{"low":60,"high":127},"pitch":{"rootNote":72,"fineTuneCents":0},"sampleRate":44100,
+             "audio":{"type":"external","url":"c5.wav","codec":"wav","sha256":"kept"}}]}}}"#;
+        let mut descriptor: PresetDescriptor = serde_json::from_str(json).unwrap();
+        let added = hash_preset_assets(&mut descriptor, |url| Ok(url.as_bytes().to_vec())).unwrap();
+        assert_eq!(added, 1);
+        let hashes: Vec<Option<String>> = descriptor
+            .graph
+            .audio_references()
+            .into_iter()
+            .map(|a| match a {
+                AudioReference::External { sha256, .. } => sha256.clone(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(hashes, vec![Some(content_hash(b"c3.wav")), Some("kept".to_string())]);
+
+        let failed = hash_preset_assets(&mut descriptor.clone(), |_| Err("unreachable".to_string()));
+        assert_eq!(failed, Ok(0), "Presets already hashed read nothing");
+    }
+}
@@ -0,0 +1,226 @@
+//! Preset linting: problems that load but play badly, plus anything
+//! `PresetDescriptor::validate` rejects.
+
+use serde::Serialize;
+
+use crate::preset::{AudioReference, CompositeMode, PresetDescriptor, PresetNode, SampleZone, SamplerConfig};
+
+/// Fine tune beyond this many cents suggests the wrong root note.
+const SUSPICIOUS_FINE_TUNE_CENTS: f64 = 50.0;
+
+/// Root notes further than this many semitones from their zone's keys
+/// stretch the sample audibly.
+const MAX_ROOT_DISTANCE: u8 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum IssueSeverity {
+    /// The preset will not load or cannot make sound.
+    Error,
+    /// The preset loads but probably does not sound as intended.
+    Warning,
+}
+
+/// A problem found by `validate_preset`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PresetIssue {
+    pub severity: IssueSeverity,
+    /// Where in the graph, e.g. `graph.children[1].zones[3]`.
+    pub path: String,
+    pub message: String,
+}
+
+/// Check a preset for key-range gaps and overlaps, root notes far from
+/// their keys, suspicious fine tuning, zones without audio and chains
+/// without a sound source.
+pub fn validate_preset(descriptor: &PresetDescriptor) -> Vec<PresetIssue> {
+    let mut issues = Vec::new();
+    if let Err(message) = descriptor.validate() {
+        issues.push(PresetIssue { severity: IssueSeverity::Error, path: String::new(), message });
+    }
+    lint_node(&descriptor.graph, "graph", &mut issues);
+    issues
+}
+
+fn lint_node(node: &PresetNode, path: &str, issues: &mut Vec<PresetIssue>) {
+    match node {
+        PresetNode::Sampler { config } => lint_sampler(config, path, issues),
+        PresetNode::Composite { mode, children, .. } => {
+            if *mode == CompositeMode::Chain && !children.iter().any(is_source) {
+                issues.push(PresetIssue {
+                    severity: IssueSeverity::Error,
+                    path: path.to_string(),
+                    message: "Chain has no oscillator or sampler to make sound".to_string(),
+                });
+            }
+            for (i, child) in children.iter().enumerate() {
+                lint_node(child, &format!("{path}.children[{i}]"), issues);
+            }
+        }
+        PresetNode::Oscillator { .. } | PresetNode::Effect { .. } => {}
+    }
+}
+
+/// Whether a node makes sound (rather than only processing it).
+fn is_source(node: &PresetNode) -> bool {
+    match node {
+        PresetNode::Oscillator { .. } | PresetNode::Sampler { .. } => true,
+        PresetNode::Composite { children, .. } => children.iter().any(is_source),
+        PresetNode::Effect { .. } => false,
+    }
+}
+
+fn lint_sampler(config: &SamplerConfig, path: &str, issues: &mut Vec<PresetIssue>) {
+    let warn = |issues: &mut Vec<PresetIssue>, path: String, message: String| {
+        issues.push(PresetIssue { severity: IssueSeverity::Warning, path, message });
+    };
+    if config.zones.is_empty() {
+        issues.push(PresetIssue {
+            severity: IssueSeverity::Error,
+            path: path.to_string(),
+            message: "Sampler has no zones".to_string(),
+        });
+        return;
+    }
+
+    for (i, zone) in config.zones.iter().enumerate() {
+        let zone_path = format!("{path}.zones[{i}]");
+        if has_no_audio(&zone.audio) {
+            issues.push(PresetIssue {
+                severity: IssueSeverity::Error,
+                path: zone_path.clone(),
+                message: "Zone has no audio".to_string(),
+            });
+        }
+        let cents = zone.pitch.fine_tune_cents;
+        if cents.abs() > SUSPICIOUS_FINE_TUNE_CENTS {
+            warn(issues, zone_path.clone(), format!("Fine tune of {cents} cents suggests the wrong root note"));
+        }
+        let (low, high, root) = (zone.key_range.low, zone.key_range.high, zone.pitch.root_note);
+        let distance = if root < low { low - root } else { root.saturating_sub(high) };
+        if !config.is_drum_kit && distance > MAX_ROOT_DISTANCE {
+            warn(
+                issues,
+                zone_path.clone(),
+                format!("Root note {root} is {distance} semitones outside keys {low}..{high}"),
+            );
+        }
+        for (j, other) in config.zones.iter().enumerate().skip(i + 1) {
+            if zones_overlap(zone, other) {
+                warn(issues, zone_path.clone(), format!("Keys overlap zone {j}"));
+            }
+        }
+    }
+
+    // Drum kits map single keys, so gaps between them are expected
+    if !config.is_drum_kit {
+        for (start, end) in coverage_gaps(&config.zones) {
+            warn(issues, path.to_string(), format!("Keys {start}..{end} are not covered by any zone"));
+        }
+    }
+}
+
+fn has_no_audio(audio: &AudioReference) -> bool {
+    match audio {
+        AudioReference::InlinePcm { data, .. } | AudioReference::InlineFile { data, .. } => data.is_empty(),
+        AudioReference::External { url, .. } => url.is_empty(),
+        AudioReference::ContentAddressed { hash, .. } => hash.is_empty(),
+    }
+}
+
+/// Whether two zones would both answer some note at some velocity.
+fn zones_overlap(a: &SampleZone, b: &SampleZone) -> bool {
+    let keys = a.key_range.low <= b.key_range.high && b.key_range.low <= a.key_range.high;
+    let velocity = |z: &SampleZone| z.velocity_range.as_ref().map_or((0, 127), |v| (v.low, v.high));
+    let ((a_low, a_high), (b_low, b_high)) = (velocity(a), velocity(b));
+    keys && a_low <= b_high && b_low <= a_high
+}
+
+/// Inclusive key spans between the lowest and highest covered key that no
+/// zone covers.
+fn coverage_gaps(zones: &[SampleZone]) -> Vec<(u8, u8)> {
+    let mut covered = [false; 128];
+    for zone in zones {
+        for key in zone.key_range.low..=zone.key_range.high.min(127) {
+            covered[key as usize] = true;
+        }
+    }
+    let first = covered.iter().position(|&c| c).unwrap_or(0);
+    let last = covered.iter().rposition(|&c| c).unwrap_or(0);
+    let mut gaps = Vec::new();
+    let mut key = first;
+    while key <= last {
+        if covered[key] {
+            key += 1;
+            continue;
+        }
+        let start = key;
+        while !covered[key] {
+            key += 1;
+        }
+        gaps.push((start as u8, (key - 1) as u8));
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(zones: &str, drum_kit: bool) -> PresetDescriptor {
+        let json = format!(
+            r#"{{"name":"Test","category":"sampler","graph":{{"type":"sampler","config":{{
+                "isDrumKit":{drum_kit},"zones":[{zones}]}}}}}}"#
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn zone(low: u8, high: u8, root: u8, cents: f64) -> String {
+        format!(
+            r#"{{"keyRange":{{"low":{low},"high":{high}}},"pitch":{{"rootNote":{root},"fineTuneCents":{cents}}},
+                "sampleRate":44100,"audio":{{"type":"external","url":"{root}.wav","codec":"wav"}}}}"#
+        )
+    }
+
+    fn messages(issues: &[PresetIssue]) -> Vec<(String, String)> {
+        issues.iter().map(|i| (i.path.clone(), i.message.clone())).collect()
+    }
+
+    #[test]
+    fn clean_preset_has_no_issues() {
+        let zones = [zone(0, 59, 48, 0.0), zone(60, 127, 72, 12.0)].join(",");
+        assert_eq!(validate_preset(&preset(&zones, false)), vec![]);
+    }
+
+    #[test]
+    fn reports_gaps_overlaps_roots_and_tuning() {
+        let zones = [zone(0, 50, 48, 0.0), zone(48, 60, 55, 70.0), zone(64, 70, 90, 0.0)].join(",");
+        let issues = validate_preset(&preset(&zones, false));
+        assert!(issues.iter().all(|i| i.severity == IssueSeverity::Warning));
+        assert_eq!(
+            messages(&issues),
+            vec![
+                ("graph.zones[0]".to_string(), "Keys overlap zone 1".to_string()),
+                ("graph.zones[1]".to_string(), "Fine tune of 70 cents suggests the wrong root note".to_string()),
+                ("graph.zones[2]".to_string(), "Root note 90 is 20 semitones outside keys 64..70".to_string()),
+                ("graph".to_string(), "Keys 61..63 are not covered by any zone".to_string()),
+            ]
+        );
+
+        // Drum kits skip the gap and root checks
+        let kit = [zone(36, 36, 36, 0.0), zone(42, 42, 80, 0.0)].join(",");
+        assert_eq!(validate_preset(&preset(&kit, true)), vec![]);
+    }
+
+    #[test]
+    fn chain_without_source_is_an_error() {
+        let json = r#"{"name":"Fx","category":"composite","graph":{"type":"composite","mode":"chain","children":[
+            {"type":"effect","effectType":"reverb","config":{}}]}}"#;
+        let issues = validate_preset(&serde_json::from_str(json).unwrap());
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, IssueSeverity::Error);
+        assert_eq!(issues[0].path, "graph");
+    }
+}
@@ -7,6 +7,9 @@ use crate::preset::{
 };
 
 use super::cache::DiskCache;
+use super::integrity::verify_sha256;
+use super::store::AudioStore;
+use crate::dsp::sampler::SampleBuffer;
 
 /// Default base URL for the songwalker-library.
 pub const DEFAULT_LIBRARY_URL: &str = "https://clevertree.github.io/songwalker-library";
@@ -19,6 +22,9 @@ pub struct PresetLoader {
     client: reqwest::Client,
     /// Disk cache for persistence.
     cache: DiskCache,
+    /// Store checked before fetching content-addressed samples, and
+    /// filled with the ones fetched.
+    store: Option<Arc<dyn AudioStore + Send + Sync>>,
 }
 
 impl PresetLoader {
@@ -31,6 +37,7 @@ impl PresetLoader {
                 .build()
                 .unwrap_or_default(),
             cache: DiskCache::new(),
+            store: None,
         }
     }
 
@@ -39,6 +46,12 @@ impl PresetLoader {
         self
     }
 
+    /// Resolve content-addressed samples through `store` first.
+    pub fn with_store(mut self, store: Arc<dyn AudioStore + Send + Sync>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
     /// Initialize: ensure cache directories exist.
     pub fn init(&self) {
         let _ = self.cache.ensure_dirs();
@@ -217,12 +230,20 @@ impl PresetLoader {
             let pcm = self
                 .load_sample(library, preset_path, &zone.audio, zone.sample_rate, host_sample_rate)
                 .await?;
+            let release_pcm = match &zone.release_audio {
+                Some(audio) => Some(Arc::from(
+                    self.load_sample(library, preset_path, audio, zone.sample_rate, host_sample_rate)
+                        .await?,
+                )),
+                None => None,
+            };
 
             loaded.push(LoadedZone {
                 zone: zone.clone(),
                 pcm_data: Arc::from(pcm),
                 channels: 1, // TODO: detect stereo
                 sample_rate: zone.sample_rate,
+                release_pcm,
             });
         }
 
@@ -247,7 +268,7 @@ impl PresetLoader {
 
         // Fetch and decode
         let raw_bytes = match audio_ref {
-            AudioReference::External { url, .. } => {
+            AudioReference::External { url, sha256, .. } => {
                 let full_url = if url.starts_with("http") {
                     url.clone()
                 } else {
@@ -271,11 +292,15 @@ impl PresetLoader {
                 if !response.status().is_success() {
                     return Err(format!("HTTP {} fetching sample: {}", response.status(), full_url));
                 }
-                response
+                let bytes = response
                     .bytes()
                     .await
                     .map_err(|e| format!("Failed to read sample bytes: {}", e))?
-                    .to_vec()
+                    .to_vec();
+                if let Some(expected) = sha256 {
+                    verify_sha256(&full_url, &bytes, expected).map_err(|e| e.to_string())?;
+                }
+                bytes
             }
             AudioReference::InlineFile { data, .. } => {
                 base64::engine::general_purpose::STANDARD.decode(data)
@@ -290,20 +315,15 @@ impl PresetLoader {
                 return Ok(samples);
             }
             AudioReference::ContentAddressed { hash, .. } => {
-                let url = format!("{}/{}/{}", self.base_url, library, hash);
-                let response = self.client
-                    .get(&url)
-                    .send()
-                    .await
-                    .map_err(|e| format!("Failed to fetch content-addressed sample {}: {}", url, e))?;
-                if !response.status().is_success() {
-                    return Err(format!("HTTP {} fetching sample: {}", response.status(), url));
+                if let Some(bytes) = self.stored_blob(hash)? {
+                    bytes
+                } else {
+                    let bytes = self.fetch_content_addressed(library, hash).await?;
+                    if let Some(store) = &self.store {
+                        store.put(&bytes)?;
+                    }
+                    bytes
                 }
-                response
-                    .bytes()
-                    .await
-                    .map_err(|e| format!("Failed to read sample bytes: {}", e))?
-                    .to_vec()
             }
         };
 
@@ -318,6 +338,32 @@ impl PresetLoader {
 
         Ok(samples)
     }
+
+    /// A content-addressed sample from the store, if there is one.
+    fn stored_blob(&self, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        match &self.store {
+            Some(store) => store.get(hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch a content-addressed sample from the library.
+    async fn fetch_content_addressed(&self, library: &str, hash: &str) -> Result<Vec<u8>, String> {
+        let url = format!("{}/{}/{}", self.base_url, library, hash);
+        let response = self.client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch content-addressed sample {}: {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {} fetching sample: {}", response.status(), url));
+        }
+        Ok(response
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to read sample bytes: {}", e))?
+            .to_vec())
+    }
 }
 
 /// Extract all SampleZones from a preset graph (recursively for composites).
@@ -423,16 +469,8 @@ fn decode_raw_pcm(bytes: &[u8], bits_per_sample: u8) -> Vec<f32> {
                 .map(|chunk| i16::from_le_bytes([chunk[0], chunk[1]]) as f32 / 32768.0)
                 .collect()
         }
-        24 => {
-            bytes.chunks_exact(3)
-                .map(|chunk| {
-                    let val = (chunk[0] as i32) | ((chunk[1] as i32) << 8) | ((chunk[2] as i32) << 16);
-                    // Sign extend from 24 to 32 bits
-                    let val = if val & 0x800000 != 0 { val | !0xFFFFFF } else { val };
-                    val as f32 / 8388608.0
-                })
-                .collect()
-        }
+        8 => SampleBuffer::from_u8(bytes, 0).data,
+        24 => SampleBuffer::from_i24_le(bytes, 0).data,
         32 => {
             bytes.chunks_exact(4)
                 .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
@@ -593,6 +593,16 @@ impl PresetManager {
         sorted
     }
 
+    /// Find a preset for General MIDI program `program`, searching the
+    /// loaded libraries in index order. Returns the library name and entry.
+    pub fn find_gm_program(&self, program: u8) -> Option<(&str, &PresetInfo)> {
+        self.libraries.iter().find_map(|lib| {
+            let presets = self.library_presets.get(&lib.name)?;
+            let preset = presets.iter().find(|p| p.gm_program == Some(program))?;
+            Some((lib.name.as_str(), preset))
+        })
+    }
+
     /// Get presets for a given library, filtered by current search/category.
     pub fn filtered_presets_for_library(&self, library_name: &str) -> Vec<&PresetInfo> {
         let query = self.search_query.to_lowercase();
@@ -2,6 +2,11 @@ pub mod types;
 pub use types::*;
 pub mod instance;
 pub use instance::*;
+pub mod bundle;
+pub mod editor;
+pub mod integrity;
+pub mod lint;
+pub mod store;
 
 #[cfg(feature = "catalog")]
 pub mod cache;
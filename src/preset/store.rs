@@ -0,0 +1,161 @@
+//! Content-addressed audio storage for `AudioReference::ContentAddressed`.
+//!
+//! Blobs are keyed by the lowercase hex SHA-256 of their bytes, so the same
+//! sample shared by many presets is stored once.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use sha2::{Digest, Sha256};
+
+/// A place to keep audio blobs by content hash.
+pub trait AudioStore {
+    /// Store `bytes` and return their hash.
+    fn put(&self, bytes: &[u8]) -> Result<String, String>;
+
+    /// The blob with this hash, or `None` if the store does not have it.
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String>;
+
+    fn contains(&self, hash: &str) -> Result<bool, String> {
+        Ok(self.get(hash)?.is_some())
+    }
+}
+
+/// Lowercase hex SHA-256 of `bytes`, the key used by every store.
+pub fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Check that `hash` looks like a content hash, so it is safe to use as a
+/// file name.
+#[cfg(feature = "catalog")]
+fn validate_hash(hash: &str) -> Result<(), String> {
+    if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        Ok(())
+    } else {
+        Err(format!("Invalid content hash '{hash}'"))
+    }
+}
+
+// ── In-memory ───────────────────────────────────────────────
+
+/// A store that keeps blobs in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    blobs: RwLock<HashMap<String, Arc<[u8]>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of blobs held.
+    pub fn len(&self) -> usize {
+        self.blobs.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl AudioStore for MemoryStore {
+    fn put(&self, bytes: &[u8]) -> Result<String, String> {
+        let hash = content_hash(bytes);
+        self.blobs
+            .write()
+            .unwrap()
+            .entry(hash.clone())
+            .or_insert_with(|| Arc::from(bytes));
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.blobs.read().unwrap().get(hash).map(|b| b.to_vec()))
+    }
+
+    fn contains(&self, hash: &str) -> Result<bool, String> {
+        Ok(self.blobs.read().unwrap().contains_key(hash))
+    }
+}
+
+// ── Filesystem ──────────────────────────────────────────────
+
+/// A store that keeps blobs as files under a directory, at
+/// `{dir}/{first two hash digits}/{hash}`.
+#[cfg(feature = "catalog")]
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(feature = "catalog")]
+impl FileStore {
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn blob_path(&self, hash: &str) -> Result<std::path::PathBuf, String> {
+        validate_hash(hash)?;
+        Ok(self.dir.join(&hash[..2]).join(hash))
+    }
+}
+
+#[cfg(feature = "catalog")]
+impl AudioStore for FileStore {
+    fn put(&self, bytes: &[u8]) -> Result<String, String> {
+        let hash = content_hash(bytes);
+        let path = self.blob_path(&hash)?;
+        if !path.exists() {
+            let parent = path.parent().expect("blob path has a parent");
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+            // Write then rename, so a reader never sees a partial blob
+            let partial = path.with_extension("partial");
+            std::fs::write(&partial, bytes).map_err(|e| format!("Failed to write {}: {e}", partial.display()))?;
+            std::fs::rename(&partial, &path).map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+        }
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        match std::fs::read(self.blob_path(hash)?) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read blob {hash}: {e}")),
+        }
+    }
+
+    fn contains(&self, hash: &str) -> Result<bool, String> {
+        Ok(self.blob_path(hash)?.exists())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store_round_trips_by_hash() {
+        let store = MemoryStore::new();
+        let hash = store.put(b"abc").unwrap();
+        assert_eq!(hash, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(store.put(b"abc").unwrap(), hash);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(&hash).unwrap(), Some(b"abc".to_vec()));
+        assert!(!store.contains(&content_hash(b"abd")).unwrap());
+    }
+
+    #[cfg(feature = "catalog")]
+    #[test]
+    fn file_store_round_trips_and_rejects_bad_hashes() {
+        let dir = std::env::temp_dir().join(format!("sw-store-test-{}", std::process::id()));
+        let store = FileStore::new(&dir);
+        let hash = store.put(b"sample data").unwrap();
+        assert!(dir.join(&hash[..2]).join(&hash).exists());
+        assert_eq!(store.get(&hash).unwrap(), Some(b"sample data".to_vec()));
+        assert_eq!(store.get(&content_hash(b"other")).unwrap(), None);
+        assert!(store.get("../../etc/passwd").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -6,11 +6,15 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::compiler::{EnvelopeConfig, EnvelopeCurve};
+use crate::math;
+
 // ── Preset Descriptor (top-level) ───────────────────────────
 
 /// Top-level preset descriptor. Each preset file (`preset.json`)
 /// contains one of these.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PresetDescriptor {
     /// Preset format identifier (e.g., "songwalker-preset"). Ignored during deser.
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -42,6 +46,7 @@ pub struct PresetDescriptor {
 
 /// Preset categories.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum PresetCategory {
     Synth,
@@ -52,6 +57,7 @@ pub enum PresetCategory {
 
 /// Metadata about a preset's source and classification.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PresetMetadata {
     /// GM program number (0-127).
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -75,6 +81,7 @@ pub struct PresetMetadata {
 
 /// Tuning analysis results for a preset (populated by the tuner).
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct TuningInfo {
     /// Has a human/tool verified the tuning?
     pub verified: bool,
@@ -98,6 +105,7 @@ pub struct TuningInfo {
 /// A node in the preset graph. Presets are modular — they can be
 /// oscillators, samplers, effects, or composites of other nodes.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum PresetNode {
     Oscillator {
@@ -119,10 +127,43 @@ pub enum PresetNode {
     },
 }
 
+impl PresetNode {
+    /// Every zone's audio and note-off audio, through composites.
+    pub fn audio_references(&self) -> Vec<&AudioReference> {
+        match self {
+            PresetNode::Sampler { config } => config
+                .zones
+                .iter()
+                .flat_map(|z| std::iter::once(&z.audio).chain(z.release_audio.as_ref()))
+                .collect(),
+            PresetNode::Composite { children, .. } => {
+                children.iter().flat_map(|c| c.audio_references()).collect()
+            }
+            PresetNode::Oscillator { .. } | PresetNode::Effect { .. } => Vec::new(),
+        }
+    }
+
+    /// Mutable form of `audio_references`.
+    pub fn audio_references_mut(&mut self) -> Vec<&mut AudioReference> {
+        match self {
+            PresetNode::Sampler { config } => config
+                .zones
+                .iter_mut()
+                .flat_map(|z| std::iter::once(&mut z.audio).chain(z.release_audio.as_mut()))
+                .collect(),
+            PresetNode::Composite { children, .. } => {
+                children.iter_mut().flat_map(|c| c.audio_references_mut()).collect()
+            }
+            PresetNode::Oscillator { .. } | PresetNode::Effect { .. } => Vec::new(),
+        }
+    }
+}
+
 // ── Oscillator ──────────────────────────────────────────────
 
 /// Configuration for an oscillator node.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct OscillatorConfig {
     /// Waveform type.
     pub waveform: WaveformType,
@@ -138,6 +179,7 @@ pub struct OscillatorConfig {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum WaveformType {
     Sine,
@@ -151,6 +193,7 @@ pub enum WaveformType {
 
 /// Configuration for a sampler node.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SamplerConfig {
     /// Sample zones covering the MIDI key range.
     pub zones: Vec<SampleZone>,
@@ -164,6 +207,7 @@ pub struct SamplerConfig {
 
 /// A single sample zone within a sampler.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SampleZone {
     /// MIDI key range this zone covers.
     #[serde(rename = "keyRange")]
@@ -179,17 +223,37 @@ pub struct SampleZone {
     /// Loop points (sample offsets).
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub r#loop: Option<LoopPoints>,
+    /// Exclusive (choke) group: starting a note in this group cuts off any
+    /// sounding note of the same preset in the same group, e.g. a closed
+    /// hi-hat choking an open hi-hat.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "exclusiveGroup")]
+    pub exclusive_group: Option<u32>,
     /// Reference to the audio data.
     pub audio: AudioReference,
+    /// Optional note-off sample, played when the note is released
+    /// (harpsichord, organ key clicks). Shares the zone's sample rate.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "releaseAudio")]
+    pub release_audio: Option<AudioReference>,
+    /// Level and brightness scaling by key distance from the root note.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "keyTracking")]
+    pub key_tracking: Option<KeyTracking>,
+    /// Level offset in dB, e.g. to balance the mics of a multi-mic set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f64>,
+    /// Stereo position from -1 (left) to 1 (right), added to the note pan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pan: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct KeyRange {
     pub low: u8,
     pub high: u8,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct VelocityRange {
     pub low: u8,
     pub high: u8,
@@ -197,6 +261,7 @@ pub struct VelocityRange {
 
 /// Pitch information for a sample zone.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ZonePitch {
     /// The MIDI note the sample was recorded at (0-127).
     #[serde(rename = "rootNote")]
@@ -207,13 +272,51 @@ pub struct ZonePitch {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct LoopPoints {
     pub start: u64,
     pub end: u64,
 }
 
+/// Key tracking for a sample zone: how its level and brightness change as
+/// the played note moves away from the zone's root note. Keeps heavily
+/// repitched notes from sounding thin (or harsh).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct KeyTracking {
+    /// Gain change in dB per octave above the root (negative rolls off
+    /// higher notes). Notes below the root get the opposite change.
+    #[serde(default, rename = "ampDbPerOctave")]
+    pub amp_db_per_octave: f64,
+    /// Lowpass cutoff in Hz at the root note. `None` disables the filter.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "filterCutoff")]
+    pub filter_cutoff: Option<f64>,
+    /// How far the cutoff follows the key: 1.0 moves it with the pitch,
+    /// 0.0 keeps it fixed.
+    #[serde(default = "default_filter_key_track", rename = "filterKeyTrack")]
+    pub filter_key_track: f64,
+}
+
+fn default_filter_key_track() -> f64 {
+    1.0
+}
+
+impl KeyTracking {
+    /// Linear gain for a note `semitones` away from the root.
+    pub fn gain(&self, semitones: f64) -> f64 {
+        math::powf(10.0, self.amp_db_per_octave * semitones / 12.0 / 20.0)
+    }
+
+    /// Lowpass cutoff in Hz for a note `semitones` away from the root.
+    pub fn cutoff(&self, semitones: f64) -> Option<f64> {
+        self.filter_cutoff
+            .map(|hz| hz * math::powf(2.0, self.filter_key_track * semitones / 12.0))
+    }
+}
+
 /// Reference to audio data — can be inline or external.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(tag = "type", rename_all = "kebab-case")]
 pub enum AudioReference {
     /// Raw 16-bit PCM data, base64 encoded.
@@ -241,7 +344,24 @@ pub enum AudioReference {
     },
 }
 
+impl AudioReference {
+    /// Encode mono samples in [-1, 1] as inline 16-bit PCM.
+    pub fn inline_pcm16(samples: &[f64]) -> Self {
+        use base64::Engine as _;
+        let mut pcm = Vec::with_capacity(samples.len() * 2);
+        for &s in samples {
+            let value = (s * 32767.0).round().clamp(-32768.0, 32767.0) as i16;
+            pcm.extend_from_slice(&value.to_le_bytes());
+        }
+        AudioReference::InlinePcm {
+            data: base64::engine::general_purpose::STANDARD.encode(&pcm),
+            bits_per_sample: 16,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum AudioCodec {
     Wav,
@@ -254,6 +374,7 @@ pub enum AudioCodec {
 // ── Effects ─────────────────────────────────────────────────
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum EffectType {
     Reverb,
@@ -268,6 +389,7 @@ pub enum EffectType {
 
 /// How children in a composite node are combined.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum CompositeMode {
     /// All children play simultaneously, mixed together.
@@ -280,6 +402,7 @@ pub enum CompositeMode {
 
 /// Configuration for a composite node.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct CompositeConfig {
     /// For split mode: MIDI note boundaries between children.
     #[serde(default, skip_serializing_if = "Option::is_none", rename = "splitPoints")]
@@ -291,17 +414,43 @@ pub struct CompositeConfig {
 
 // ── ADSR Envelope ───────────────────────────────────────────
 
-/// ADSR envelope configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// ADSR envelope configuration, with an optional hold stage and stage
+/// curves (linear when absent).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ADSRConfig {
     /// Attack time in seconds.
     pub attack: f64,
+    /// Time in seconds held at full level between the attack and decay.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold: Option<f64>,
     /// Decay time in seconds.
     pub decay: f64,
     /// Sustain level [0.0, 1.0].
     pub sustain: f64,
     /// Release time in seconds.
     pub release: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "attackCurve")]
+    pub attack_curve: Option<EnvelopeCurve>,
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "decayCurve")]
+    pub decay_curve: Option<EnvelopeCurve>,
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "releaseCurve")]
+    pub release_curve: Option<EnvelopeCurve>,
+}
+
+impl From<&ADSRConfig> for EnvelopeConfig {
+    fn from(adsr: &ADSRConfig) -> Self {
+        EnvelopeConfig {
+            attack: Some(adsr.attack),
+            hold: adsr.hold,
+            decay: Some(adsr.decay),
+            sustain: Some(adsr.sustain),
+            release: Some(adsr.release),
+            attack_curve: adsr.attack_curve,
+            decay_curve: adsr.decay_curve,
+            release_curve: adsr.release_curve,
+        }
+    }
 }
 
 // ── Catalog Entry (from index.json) ─────────────────────────
@@ -309,6 +458,7 @@ pub struct ADSRConfig {
 /// An entry in the library's root `index.json` catalog.
 /// Contains enough metadata for search/filter without loading full preset.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct CatalogEntry {
     pub id: String,
     pub name: String,
@@ -326,10 +476,14 @@ pub struct CatalogEntry {
     pub key_range: Option<KeyRange>,
     #[serde(default, rename = "tuningVerified")]
     pub tuning_verified: bool,
+    /// Total size of the preset's sample files, when the catalog records it.
+    #[serde(default, skip_serializing_if = "Option::is_none", rename = "sizeBytes")]
+    pub size_bytes: Option<u64>,
 }
 
 /// The root index.json structure.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct LibraryIndex {
     pub version: u32,
     #[serde(rename = "generatedAt")]
@@ -337,10 +491,35 @@ pub struct LibraryIndex {
     pub presets: Vec<CatalogEntry>,
 }
 
+impl LibraryIndex {
+    /// The first preset in the catalog tagged with General MIDI program
+    /// `program`.
+    pub fn find_gm_program(&self, program: u8) -> Option<&CatalogEntry> {
+        self.presets.iter().find(|p| p.gm_program == Some(program))
+    }
+
+    /// The entry a `loadPreset` name refers to: a `gm:N` program, or a
+    /// preset id or display name.
+    pub fn find_preset(&self, name: &str) -> Option<&CatalogEntry> {
+        match crate::compiler::gm_program_of(name) {
+            Some(program) => self.find_gm_program(program),
+            None => self.presets.iter().find(|p| p.id == name || p.name == name),
+        }
+    }
+
+    /// Fill in `estimated_bytes` of each need from the catalog's sizes.
+    pub fn estimate_preset_bytes(&self, needs: &mut [crate::compiler::PresetNeed]) {
+        for need in needs {
+            need.estimated_bytes = self.find_preset(&need.name).and_then(|entry| entry.size_bytes);
+        }
+    }
+}
+
 // ── Root Index (songwalker-library/index.json) ──────────────
 
 /// An entry in the root library index.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct LibraryEntry {
     /// Type discriminator (usually "index")
     #[serde(rename = "type")]
@@ -371,6 +550,7 @@ impl LibraryEntry {
 
 /// The root index.json structure (lists libraries).
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct RootIndex {
     pub format: String,
     pub version: u32,
@@ -399,7 +579,7 @@ pub fn sample_playback_rate(
 ) -> f64 {
     // Standard rate (pitch shift from root) 
     let semitone_diff = target_midi_note as f64 - root_note as f64 - fine_tune_cents / 100.0;
-    let base_rate = (2.0_f64).powf(semitone_diff / 12.0);
+    let base_rate = math::powf(2.0, semitone_diff / 12.0);
 
     // Tuning adjustment (ratio to standard 440 Hz)
     let tuning_ratio = tuning_pitch / 440.0;
@@ -483,6 +663,25 @@ pub fn gm_category_display(program: u8) -> &'static str {
 mod tests {
     use super::*;
 
+    #[test]
+    fn key_tracking_scales_gain_and_cutoff() {
+        let kt = KeyTracking {
+            amp_db_per_octave: -6.0,
+            filter_cutoff: Some(4000.0),
+            filter_key_track: 1.0,
+        };
+        assert!((kt.gain(0.0) - 1.0).abs() < 1e-9);
+        // -6 dB one octave up ≈ half amplitude, +6 dB one octave down
+        assert!((kt.gain(12.0) - 0.501).abs() < 0.01);
+        assert!((kt.gain(-12.0) - 1.995).abs() < 0.01);
+        assert!((kt.cutoff(12.0).unwrap() - 8000.0).abs() < 1e-6);
+
+        let no_filter: KeyTracking =
+            serde_json::from_str(r#"{"ampDbPerOctave": 3.0}"#).unwrap();
+        assert_eq!(no_filter.cutoff(12.0), None);
+        assert_eq!(no_filter.filter_key_track, 1.0);
+    }
+
     // ── Playback Rate Tests (S-1 through S-10) ──
 
     #[test]
@@ -608,6 +807,7 @@ mod tests {
                         decay: 0.1,
                         sustain: 0.7,
                         release: 0.3,
+                        ..Default::default()
                     }),
                     mixer: None,
                 },
@@ -655,11 +855,20 @@ mod tests {
                                 start: 12345,
                                 end: 56789,
                             }),
+                            exclusive_group: None,
                             audio: AudioReference::External {
                                 url: "zone_C3.wav".to_string(),
                                 codec: AudioCodec::Wav,
                                 sha256: None,
                             },
+                            release_audio: Some(AudioReference::External {
+                                url: "zone_C3_release.wav".to_string(),
+                                codec: AudioCodec::Wav,
+                                sha256: None,
+                            }),
+                            gain: None,
+                            pan: None,
+                            key_tracking: None,
                         },
                         SampleZone {
                             key_range: KeyRange { low: 61, high: 127 },
@@ -670,11 +879,20 @@ mod tests {
                             },
                             sample_rate: 44100,
                             r#loop: None,
+                            exclusive_group: Some(1),
                             audio: AudioReference::External {
                                 url: "zone_C5.wav".to_string(),
                                 codec: AudioCodec::Wav,
                                 sha256: None,
                             },
+                            release_audio: None,
+                            gain: None,
+                            pan: None,
+                            key_tracking: Some(KeyTracking {
+                                amp_db_per_octave: -3.0,
+                                filter_cutoff: Some(8000.0),
+                                filter_key_track: 0.5,
+                            }),
                         },
                     ],
                     is_drum_kit: false,
@@ -691,6 +909,11 @@ mod tests {
             assert_eq!(config.zones.len(), 2);
             assert_eq!(config.zones[0].pitch.root_note, 48);
             assert_eq!(config.zones[1].key_range.low, 61);
+            assert_eq!(config.zones[0].exclusive_group, None);
+            assert_eq!(config.zones[1].exclusive_group, Some(1));
+            assert!(config.zones[0].release_audio.is_some());
+            assert!(config.zones[1].release_audio.is_none());
+            assert_eq!(config.zones[1].key_tracking.unwrap().filter_key_track, 0.5);
         } else {
             panic!("Expected sampler node");
         }
@@ -759,10 +982,72 @@ mod tests {
             zone_count: 22,
             key_range: Some(KeyRange { low: 0, high: 127 }),
             tuning_verified: false,
+            size_bytes: Some(1_048_576),
         };
 
         let json = serde_json::to_string(&entry).unwrap();
         let deserialized: CatalogEntry = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.zone_count, 22);
+        assert_eq!(deserialized.size_bytes, Some(1_048_576));
+    }
+
+    #[test]
+    fn library_index_finds_gm_program() {
+        let index: LibraryIndex = serde_json::from_str(
+            r#"{
+                "version": 1, "generatedAt": "2026-01-01",
+                "presets": [
+                    {"id": "drums", "name": "Drums", "path": "d.json", "category": "sampler", "tags": []},
+                    {"id": "steel", "name": "Steel Guitar", "path": "s.json", "category": "sampler",
+                     "tags": [], "gmProgram": 25}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(index.find_gm_program(25).map(|e| e.id.as_str()), Some("steel"));
+        assert!(index.find_gm_program(0).is_none());
+    }
+
+    #[test]
+    fn library_index_estimates_preset_bytes() {
+        let index: LibraryIndex = serde_json::from_str(
+            r#"{
+                "version": 1, "generatedAt": "2026-01-01",
+                "presets": [
+                    {"id": "piano", "name": "Piano", "path": "p.json", "category": "sampler", "tags": [],
+                     "gmProgram": 0, "sizeBytes": 2048},
+                    {"id": "pad", "name": "Warm Pad", "path": "w.json", "category": "sampler", "tags": []}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let need = |name: &str| crate::compiler::PresetNeed {
+            name: name.to_string(),
+            first_beat: None,
+            first_seconds: None,
+            tracks: Vec::new(),
+            estimated_bytes: None,
+        };
+        let mut needs = vec![need("gm:0"), need("Warm Pad"), need("Unknown")];
+        index.estimate_preset_bytes(&mut needs);
+        let sizes: Vec<Option<u64>> = needs.iter().map(|n| n.estimated_bytes).collect();
+        assert_eq!(sizes, vec![Some(2048), None, None]);
+        assert_eq!(index.find_preset("pad").map(|e| e.name.as_str()), Some("Warm Pad"));
+    }
+
+    #[test]
+    fn adsr_hold_and_curves_convert_to_envelope_config() {
+        let adsr: ADSRConfig = serde_json::from_str(
+            r#"{"attack":0.01,"hold":0.05,"decay":0.2,"sustain":0.6,"release":0.4,"decayCurve":"exp"}"#,
+        )
+        .unwrap();
+        let envelope = EnvelopeConfig::from(&adsr);
+        assert_eq!(envelope.hold, Some(0.05));
+        assert_eq!(envelope.decay_curve, Some(EnvelopeCurve::Exp));
+        assert_eq!(envelope.attack_curve, None);
+        assert_eq!(envelope.sustain, Some(0.6));
+        // Plain ADSR serializes without the new fields
+        let json = serde_json::to_string(&ADSRConfig { attack: 0.01, decay: 0.1, sustain: 1.0, release: 0.2, ..Default::default() }).unwrap();
+        assert_eq!(json, r#"{"attack":0.01,"decay":0.1,"sustain":1.0,"release":0.2}"#);
     }
 }
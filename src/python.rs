@@ -0,0 +1,148 @@
+//! Python bindings, built as the `songwalker` extension module with
+//! maturin (`maturin develop`, configured in `pyproject.toml`).
+//!
+//! ASTs and event lists are returned as plain dicts and lists (via their
+//! JSON form), so they can be inspected or loaded into pandas directly.
+//! Errors raise `ValueError` with the same message the CLI prints.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use crate::dsp::engine::AudioEngine;
+use crate::dsp::tuner::{self, PitchEstimate};
+use crate::{compiler, dsp};
+
+/// Convert a serializable value to Python objects through its JSON form.
+fn to_python<'py, T: serde::Serialize>(py: Python<'py>, value: &T) -> PyResult<Bound<'py, PyAny>> {
+    let json = serde_json::to_string(value).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    py.import("json")?.call_method1("loads", (json,))
+}
+
+fn compile_source(source: &str) -> PyResult<compiler::EventList> {
+    let program = crate::parse(source).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    compiler::compile(&program).map_err(PyValueError::new_err)
+}
+
+fn pitch_dict<'py>(py: Python<'py>, estimate: &PitchEstimate) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("frequency", estimate.frequency)?;
+    dict.set_item("confidence", estimate.confidence)?;
+    dict.set_item("midi_note", estimate.midi_note)?;
+    dict.set_item("fine_tune_cents", estimate.fine_tune_cents)?;
+    dict.set_item("is_noise", estimate.is_noise)?;
+    Ok(dict)
+}
+
+/// Parse `.sw` source into its AST.
+#[pyfunction]
+fn parse<'py>(py: Python<'py>, source: &str) -> PyResult<Bound<'py, PyAny>> {
+    let program = crate::parse(source).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    to_python(py, &program)
+}
+
+/// Compile `.sw` source into its event list.
+#[pyfunction]
+fn compile<'py>(py: Python<'py>, source: &str) -> PyResult<Bound<'py, PyAny>> {
+    to_python(py, &compile_source(source)?)
+}
+
+/// Compile and render `.sw` source to a 16-bit stereo WAV file.
+#[pyfunction]
+#[pyo3(signature = (source, sample_rate = 44100))]
+fn render_wav<'py>(py: Python<'py>, source: &str, sample_rate: u32) -> PyResult<Bound<'py, PyBytes>> {
+    if sample_rate == 0 {
+        return Err(PyValueError::new_err("Sample rate must be positive."));
+    }
+    let event_list = compile_source(source)?;
+    let pcm = AudioEngine::new(sample_rate as f64).render_pcm_i16(&event_list);
+    Ok(PyBytes::new(py, &dsp::renderer::encode_wav_public(&pcm, sample_rate, 2)))
+}
+
+/// Detect the fundamental frequency of a mono sample.
+#[pyfunction]
+#[pyo3(signature = (samples, sample_rate, min_freq = None, max_freq = None))]
+fn detect_pitch<'py>(
+    py: Python<'py>,
+    samples: Vec<f64>,
+    sample_rate: u32,
+    min_freq: Option<f64>,
+    max_freq: Option<f64>,
+) -> PyResult<Bound<'py, PyDict>> {
+    pitch_dict(py, &tuner::detect_pitch(&samples, sample_rate, min_freq, max_freq))
+}
+
+/// Suggest tuning corrections for preset zones, each given as
+/// `(samples, sample_rate, root_note, fine_tune_cents)`.
+#[pyfunction]
+fn suggest_corrections<'py>(
+    py: Python<'py>,
+    zones: Vec<(Vec<f64>, u32, u8, f64)>,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    tuner::suggest_corrections(&zones)
+        .iter()
+        .map(|correction| {
+            let dict = PyDict::new(py);
+            dict.set_item("zone_index", correction.zone_index)?;
+            dict.set_item("detected", pitch_dict(py, &correction.detected)?)?;
+            dict.set_item("suggested_root", correction.suggested_root)?;
+            dict.set_item("suggested_fine_tune", correction.suggested_fine_tune)?;
+            dict.set_item("deviation_cents", correction.deviation_cents)?;
+            Ok(dict)
+        })
+        .collect()
+}
+
+/// The `songwalker` Python module.
+#[pymodule]
+#[pyo3(name = "songwalker")]
+fn songwalker_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", crate::VERSION)?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(render_wav, m)?)?;
+    m.add_function(wrap_pyfunction!(detect_pitch, m)?)?;
+    m.add_function(wrap_pyfunction!(suggest_corrections, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SONG: &str = "track t() {\n    C4 1\n    E4 1\n}\nt();";
+
+    #[test]
+    fn module_compiles_and_renders() {
+        Python::initialize();
+        Python::attach(|py| {
+            let events = compile(py, SONG).unwrap();
+            let events = events.get_item("events").unwrap();
+            assert_eq!(events.len().unwrap(), 2);
+
+            let wav = render_wav(py, SONG, 22050).unwrap();
+            assert_eq!(&wav.as_bytes()[..4], b"RIFF");
+
+            let err = compile(py, "track t( {").unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+        });
+    }
+
+    #[test]
+    fn tuner_returns_dicts() {
+        Python::initialize();
+        Python::attach(|py| {
+            let sine: Vec<f64> = (0..22050)
+                .map(|i| (2.0 * std::f64::consts::PI * 440.0 * i as f64 / 22050.0).sin())
+                .collect();
+            let pitch = detect_pitch(py, sine.clone(), 22050, None, None).unwrap();
+            let midi: u8 = pitch.get_item("midi_note").unwrap().unwrap().extract().unwrap();
+            assert_eq!(midi, 69);
+
+            let corrections = suggest_corrections(py, vec![(sine, 22050, 67, 0.0)]).unwrap();
+            assert_eq!(corrections.len(), 1);
+            let deviation: f64 = corrections[0].get_item("deviation_cents").unwrap().unwrap().extract().unwrap();
+            assert!((deviation - 200.0).abs() < 5.0, "{deviation}");
+        });
+    }
+}
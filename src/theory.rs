@@ -0,0 +1,351 @@
+//! Music theory analysis — chord symbols and key estimation.
+//!
+//! Works on pitch names as they appear in `EventKind::Note` (e.g. "A3",
+//! "C#4"). Chords are detected by matching the set of pitch classes
+//! against interval templates; keys are estimated with the
+//! Krumhansl–Schmuckler profile correlation, weighting notes by length.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::compiler::{EventKind, EventList};
+use crate::pitch::note_to_midi;
+use crate::math;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// A detected chord.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChordSymbol {
+    /// Root note name without octave (e.g. "A", "Bb").
+    pub root: String,
+    /// Quality suffix (e.g. "", "m", "7", "maj7", "dim").
+    pub quality: String,
+    /// Bass note name when the chord is inverted (e.g. "E" in "C/E").
+    pub bass: Option<String>,
+    /// Full display symbol (e.g. "Am7", "C/E").
+    pub symbol: String,
+}
+
+/// A key estimate for a passage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct KeyEstimate {
+    /// Tonic note name without octave (e.g. "A").
+    pub tonic: String,
+    /// "major" or "minor".
+    pub mode: String,
+    /// Display name (e.g. "A minor").
+    pub name: String,
+    /// Correlation with the key profile, in [-1, 1]. Higher is more certain.
+    pub confidence: f64,
+}
+
+/// A chord detected at a point in an EventList.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChordEvent {
+    /// Beat position of the chord onset.
+    pub time: f64,
+    /// The sounding pitches, as written.
+    pub pitches: Vec<String>,
+    /// The chord symbol.
+    pub chord: ChordSymbol,
+    /// Source byte range covering the chord's notes (for editor display).
+    pub source_start: usize,
+    pub source_end: usize,
+}
+
+/// Result of `analyze_harmony`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HarmonyAnalysis {
+    /// The estimated key of the whole EventList, if it contains notes.
+    pub key: Option<KeyEstimate>,
+    /// Chords found at note onsets, in time order.
+    pub chords: Vec<ChordEvent>,
+}
+
+const SHARP_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+const FLAT_NAMES: [&str; 12] = ["C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B"];
+
+/// Chord templates: (quality suffix, intervals above the root).
+/// Listed so that richer chords are preferred when several roots match.
+const CHORD_TEMPLATES: &[(&str, &[u8])] = &[
+    ("maj7", &[0, 4, 7, 11]),
+    ("7", &[0, 4, 7, 10]),
+    ("m7", &[0, 3, 7, 10]),
+    ("mMaj7", &[0, 3, 7, 11]),
+    ("m7b5", &[0, 3, 6, 10]),
+    ("dim7", &[0, 3, 6, 9]),
+    ("6", &[0, 4, 7, 9]),
+    ("m6", &[0, 3, 7, 9]),
+    ("add9", &[0, 2, 4, 7]),
+    ("", &[0, 4, 7]),
+    ("m", &[0, 3, 7]),
+    ("dim", &[0, 3, 6]),
+    ("aug", &[0, 4, 8]),
+    ("sus4", &[0, 5, 7]),
+    ("sus2", &[0, 2, 7]),
+    ("5", &[0, 7]),
+];
+
+/// Krumhansl–Kessler major key profile (index 0 = tonic).
+const MAJOR_PROFILE: [f64; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+/// Krumhansl–Kessler minor key profile (index 0 = tonic).
+const MINOR_PROFILE: [f64; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+/// Pitch class (0 = C … 11 = B) of a note name.
+pub fn pitch_class(pitch: &str) -> Option<u8> {
+    note_to_midi(pitch).map(|m| m.rem_euclid(12) as u8)
+}
+
+/// Name of a pitch class, spelled with sharps or flats.
+pub fn pitch_class_name(pc: u8, prefer_flats: bool) -> &'static str {
+    let names = if prefer_flats { &FLAT_NAMES } else { &SHARP_NAMES };
+    names[(pc % 12) as usize]
+}
+
+/// Whether a key is conventionally spelled with flats.
+fn key_prefers_flats(tonic: u8, minor: bool) -> bool {
+    // Flat major keys: F Bb Eb Ab Db Gb; their relative minors: D G C F Bb Eb
+    let major_tonic = if minor { (tonic + 3) % 12 } else { tonic };
+    matches!(major_tonic, 5 | 10 | 3 | 8 | 1 | 6)
+}
+
+/// Detect the chord formed by a set of pitches.
+///
+/// The lowest pitch is taken as the bass; if the chord's root differs,
+/// a slash chord is returned (e.g. "C/E"). Returns `None` if fewer than
+/// two distinct pitch classes are present or no template matches.
+pub fn detect_chord(pitches: &[&str], prefer_flats: bool) -> Option<ChordSymbol> {
+    let midis: Vec<i32> = pitches.iter().filter_map(|p| note_to_midi(p)).collect();
+    let bass_pc = midis.iter().min()?.rem_euclid(12) as u8;
+
+    let mut mask = 0u16;
+    for m in &midis {
+        mask |= 1 << m.rem_euclid(12);
+    }
+    if mask.count_ones() < 2 {
+        return None;
+    }
+
+    let mut best: Option<(u8, &str)> = None;
+    for &(quality, intervals) in CHORD_TEMPLATES {
+        for root in 0..12u8 {
+            let template = intervals
+                .iter()
+                .fold(0u16, |acc, &i| acc | 1 << ((root + i) % 12));
+            if template != mask {
+                continue;
+            }
+            // Prefer the root that is also the bass (root position)
+            if root == bass_pc {
+                return Some(make_symbol(root, quality, bass_pc, prefer_flats));
+            }
+            best.get_or_insert((root, quality));
+        }
+    }
+    best.map(|(root, quality)| make_symbol(root, quality, bass_pc, prefer_flats))
+}
+
+fn make_symbol(root: u8, quality: &str, bass: u8, prefer_flats: bool) -> ChordSymbol {
+    let root_name = pitch_class_name(root, prefer_flats).to_string();
+    let bass = (bass != root).then(|| pitch_class_name(bass, prefer_flats).to_string());
+    let symbol = match &bass {
+        Some(b) => format!("{root_name}{quality}/{b}"),
+        None => format!("{root_name}{quality}"),
+    };
+    ChordSymbol {
+        root: root_name,
+        quality: quality.to_string(),
+        bass,
+        symbol,
+    }
+}
+
+/// Estimate the key from `(pitch, weight)` pairs, e.g. notes weighted by
+/// their duration. Returns `None` if no pitch is recognised.
+pub fn estimate_key<'a>(notes: impl IntoIterator<Item = (&'a str, f64)>) -> Option<KeyEstimate> {
+    let mut histogram = [0.0_f64; 12];
+    let mut first_pc = None;
+    for (pitch, weight) in notes {
+        if let Some(pc) = pitch_class(pitch) {
+            histogram[pc as usize] += weight.max(0.0);
+            first_pc.get_or_insert(pc);
+        }
+    }
+    let first_pc = first_pc?;
+
+    let mut best: Option<(f64, u8, bool)> = None;
+    for tonic in 0..12u8 {
+        for (minor, profile) in [(false, &MAJOR_PROFILE), (true, &MINOR_PROFILE)] {
+            let rotated: Vec<f64> = (0..12)
+                .map(|i| profile[(i + 12 - tonic as usize) % 12])
+                .collect();
+            let r = correlation(&histogram, &rotated);
+            if !r.is_nan() && best.is_none_or(|(b, _, _)| r > b) {
+                best = Some((r, tonic, minor));
+            }
+        }
+    }
+
+    // A flat histogram has no variance; fall back to the first pitch heard
+    let (confidence, tonic, minor) = best.unwrap_or((0.0, first_pc, false));
+    let tonic_name = pitch_class_name(tonic, key_prefers_flats(tonic, minor)).to_string();
+    let mode = if minor { "minor" } else { "major" };
+    Some(KeyEstimate {
+        name: format!("{tonic_name} {mode}"),
+        tonic: tonic_name,
+        mode: mode.to_string(),
+        confidence,
+    })
+}
+
+/// Pearson correlation of two equal-length series.
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+    let mut cov = 0.0;
+    let mut var_a = 0.0;
+    let mut var_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += math::powi(x - mean_a, 2);
+        var_b += math::powi(y - mean_b, 2);
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+/// Analyse the harmony of a compiled EventList.
+///
+/// Notes starting at the same beat (across all tracks) are grouped and
+/// matched against chord templates. Chord names are spelled to suit the
+/// estimated key.
+pub fn analyze_harmony(event_list: &EventList) -> HarmonyAnalysis {
+    let key = estimate_key(event_list.events.iter().filter_map(|e| match &e.kind {
+        EventKind::Note { pitch, gate, .. } => Some((pitch.as_str(), *gate)),
+        _ => None,
+    }));
+    let prefer_flats = key.as_ref().is_some_and(|k| {
+        pitch_class(&format!("{}4", k.tonic))
+            .is_some_and(|pc| key_prefers_flats(pc, k.mode == "minor"))
+    });
+
+    // Group note onsets by time (events are sorted by time)
+    let mut chords = Vec::new();
+    let mut group: Vec<(&str, usize, usize)> = Vec::new();
+    let mut group_time = 0.0;
+    let notes = event_list.events.iter().filter_map(|e| match &e.kind {
+        EventKind::Note {
+            pitch,
+            source_start,
+            source_end,
+            ..
+        } => Some((e.time, pitch.as_str(), *source_start, *source_end)),
+        _ => None,
+    });
+    for (time, pitch, start, end) in notes {
+        if !group.is_empty() && (time - group_time).abs() > 1e-9 {
+            flush_chord(group_time, &group, prefer_flats, &mut chords);
+            group.clear();
+        }
+        if group.is_empty() {
+            group_time = time;
+        }
+        group.push((pitch, start, end));
+    }
+    flush_chord(group_time, &group, prefer_flats, &mut chords);
+
+    HarmonyAnalysis { key, chords }
+}
+
+fn flush_chord(
+    time: f64,
+    group: &[(&str, usize, usize)],
+    prefer_flats: bool,
+    chords: &mut Vec<ChordEvent>,
+) {
+    let pitches: Vec<&str> = group.iter().map(|(p, _, _)| *p).collect();
+    if let Some(chord) = detect_chord(&pitches, prefer_flats) {
+        chords.push(ChordEvent {
+            time,
+            pitches: pitches.iter().map(|p| p.to_string()).collect(),
+            chord,
+            source_start: group.iter().map(|g| g.1).min().unwrap_or(0),
+            source_end: group.iter().map(|g| g.2).max().unwrap_or(0),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_triads() {
+        assert_eq!(detect_chord(&["C4", "E4", "G4"], false).unwrap().symbol, "C");
+        assert_eq!(detect_chord(&["A3", "C4", "E4"], false).unwrap().symbol, "Am");
+        assert_eq!(detect_chord(&["B3", "D4", "F4"], false).unwrap().symbol, "Bdim");
+        assert_eq!(detect_chord(&["C4", "E4", "G#4"], false).unwrap().symbol, "Caug");
+        assert_eq!(detect_chord(&["D4", "G4", "A4"], false).unwrap().symbol, "Dsus4");
+    }
+
+    #[test]
+    fn detects_sevenths_and_spelling() {
+        assert_eq!(detect_chord(&["A3", "C4", "E4", "G4"], false).unwrap().symbol, "Am7");
+        assert_eq!(detect_chord(&["G3", "B3", "D4", "F4"], false).unwrap().symbol, "G7");
+        assert_eq!(detect_chord(&["Bb3", "D4", "F4", "A4"], true).unwrap().symbol, "Bbmaj7");
+        assert_eq!(detect_chord(&["A#3", "D4", "F4", "A4"], false).unwrap().symbol, "A#maj7");
+    }
+
+    #[test]
+    fn detects_inversions_as_slash_chords() {
+        let chord = detect_chord(&["E3", "G3", "C4"], false).unwrap();
+        assert_eq!(chord.root, "C");
+        assert_eq!(chord.bass.as_deref(), Some("E"));
+        assert_eq!(chord.symbol, "C/E");
+    }
+
+    #[test]
+    fn rejects_single_notes_and_clusters() {
+        assert!(detect_chord(&["C4"], false).is_none());
+        assert!(detect_chord(&["C4", "C5"], false).is_none());
+        assert!(detect_chord(&["C4", "C#4", "D4"], false).is_none());
+    }
+
+    #[test]
+    fn estimates_major_and_minor_keys() {
+        let c_major = ["C4", "D4", "E4", "F4", "G4", "A4", "B4", "C5", "G4", "E4", "C4"];
+        let key = estimate_key(c_major.iter().map(|p| (*p, 1.0))).unwrap();
+        assert_eq!(key.name, "C major");
+
+        let a_minor = [("A3", 2.0), ("C4", 1.0), ("E4", 1.0), ("A4", 2.0), ("G#4", 0.5), ("B3", 0.5)];
+        let key = estimate_key(a_minor.iter().copied()).unwrap();
+        assert_eq!(key.name, "A minor");
+
+        assert!(estimate_key(std::iter::empty()).is_none());
+        // Zero-weight notes give a flat histogram with no correlation
+        let single = estimate_key([("G4", 0.0)]).unwrap();
+        assert_eq!(single.name, "G major");
+        assert_eq!(single.confidence, 0.0);
+    }
+
+    #[test]
+    fn analyze_harmony_from_source() {
+        let src = "track song() {\n  [A3, C4, E4, G4] /1\n  [D4, F4, A4] /1\n  [E3, Ab3, B3, D4] /1\n  [A3, C4, E4] /1\n}\nsong();\n";
+        let program = crate::parse(src).unwrap();
+        let event_list = crate::compiler::compile(&program).unwrap();
+        let analysis = analyze_harmony(&event_list);
+
+        let symbols: Vec<&str> = analysis.chords.iter().map(|c| c.chord.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["Am7", "Dm", "E7", "Am"]);
+        assert_eq!(analysis.chords[1].time, 1.0);
+        assert!(analysis.chords[0].source_end > analysis.chords[0].source_start);
+        assert_eq!(analysis.key.unwrap().name, "A minor");
+    }
+}
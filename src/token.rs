@@ -1,3 +1,6 @@
+use alloc::format;
+use alloc::string::String;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Literals
@@ -28,15 +31,31 @@ pub enum Token {
     RBrace,     // }
     Lt,         // <
     Gt,         // >
+    LtEq,       // <=
+    GtEq,       // >=
+    EqEq,       // ==
+    BangEq,     // !=
+    AndAnd,     // &&
+    OrOr,       // ||
     Plus,       // +
     Minus,      // -
     PlusPlus,   // ++
     MinusMinus, // --
+    Arrow,      // ->
+    Tilde,      // ~
+    Caret,      // ^
     Colon,      // :
+    Bang,       // !
+    /// `'` right after a note, marking it staccato.
+    Apostrophe,
 
     // Structural
     Newline,
     Comment(String),
+    /// `/// text` before a track definition.
+    DocComment(String),
+    /// `#name value` directive, e.g. `#version 2`; holds the text after `#`.
+    Pragma(String),
     EOF,
 }
 
@@ -84,13 +103,26 @@ pub fn token_to_string(token: &Token) -> String {
         Token::RBrace => "}".into(),
         Token::Lt => "<".into(),
         Token::Gt => ">".into(),
+        Token::LtEq => "<=".into(),
+        Token::GtEq => ">=".into(),
+        Token::EqEq => "==".into(),
+        Token::BangEq => "!=".into(),
+        Token::AndAnd => "&&".into(),
+        Token::OrOr => "||".into(),
         Token::Plus => "+".into(),
         Token::Minus => "-".into(),
         Token::PlusPlus => "++".into(),
         Token::MinusMinus => "--".into(),
+        Token::Arrow => "->".into(),
+        Token::Tilde => "~".into(),
+        Token::Caret => "^".into(),
         Token::Colon => ":".into(),
+        Token::Bang => "!".into(),
+        Token::Apostrophe => "'".into(),
         Token::Newline => "\n".into(),
         Token::Comment(s) => format!("// {s}"),
+        Token::DocComment(s) => format!("/// {s}"),
+        Token::Pragma(s) => format!("#{s}"),
         Token::EOF => "".into(),
     }
 }
@@ -0,0 +1,1777 @@
+//! WASM bindings — the JavaScript-facing API used by the web editor.
+//!
+//! Re-exported from the crate root; requires the `std` feature.
+
+use crate::{compiler, dsp, grammar, parse, pitch, preset, theory, VERSION};
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::prelude::*;
+
+/// WASM-exposed: return the songwalker-core version string.
+#[wasm_bindgen]
+pub fn core_version() -> String {
+    VERSION.to_string()
+}
+
+/// WASM-exposed: compile `.sw` source into a JSON event list (strict/editor mode).
+/// Errors if a note plays before track.instrument is set. `end_mode`
+/// overrides the song's `song.endMode` as in `render_song_wav`.
+#[wasm_bindgen]
+pub fn compile_song(source: &str, end_mode: Option<String>) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let mut event_list =
+        compiler::compile_strict(&program).map_err(|e| JsValue::from_str(&e))?;
+    override_end_mode(&mut event_list, end_mode)?;
+    serde_wasm_bindgen::to_value(&event_list).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: compile `.sw` source and record each compiler decision.
+/// Returns a `compiler::TracedCompile` (`{event_list, trace}`).
+#[wasm_bindgen]
+pub fn compile_with_trace(source: &str) -> Result<JsValue, JsValue> {
+    let traced = compiler::compile_with_trace(source).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&traced).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: compile `.sw` source and return only the events between
+/// `start_beat` and `end_beat` (notes sounding in the window, plus the
+/// property values in effect at its start), so editors can page through
+/// long songs. Returns a `compiler::EventList`.
+#[wasm_bindgen]
+pub fn compile_song_region(source: &str, start_beat: f64, end_beat: f64) -> Result<JsValue, JsValue> {
+    if end_beat <= start_beat {
+        return Err(JsValue::from_str(&format!(
+            "Region end {end_beat} must be after its start {start_beat}."
+        )));
+    }
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let event_list = compiler::compile_strict(&program).map_err(|e| JsValue::from_str(&e))?;
+    let region = compiler::event_region(&event_list, start_beat, end_beat);
+    serde_wasm_bindgen::to_value(&region).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: compile `.sw` source with the optimization pass (constant
+/// folding, unused tracks removed, repeated property changes merged).
+/// Returns a `compiler::OptimizedCompile` (`{event_list, report}`).
+#[wasm_bindgen]
+pub fn compile_song_optimized(source: &str, end_mode: Option<String>) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let mut optimized = compiler::compile_optimized(&program, true).map_err(|e| JsValue::from_str(&e))?;
+    override_end_mode(&mut optimized.event_list, end_mode)?;
+    serde_wasm_bindgen::to_value(&optimized).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: the language version `.sw` source declares (`#version N`)
+/// and the deprecated constructs it uses, with byte spans for the editor.
+/// Returns a `compiler::LanguageReport`.
+#[wasm_bindgen]
+pub fn get_language_report(source: &str) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    serde_wasm_bindgen::to_value(&compiler::language_report(&program))
+        .map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: parse `.sw` source without compiling it. Returns the
+/// `ast::Program` with the byte spans of every statement and note, for
+/// tooling that needs the real syntax tree rather than the event list.
+#[wasm_bindgen]
+pub fn parse_song_ast(source: &str) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    serde_wasm_bindgen::to_value(&program).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: the `.sw` grammar as a `grammar::Grammar` (keywords and
+/// EBNF rules with examples), for generating editor grammars and docs.
+#[wasm_bindgen]
+pub fn get_grammar() -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(&grammar::grammar()).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: compile a multi-file project. `manifest_json` is a
+/// `compiler::ProjectManifest`; `files_json` is an object mapping file
+/// names to `.sw` source. Returns a `compiler::CompiledProject`.
+#[wasm_bindgen]
+pub fn compile_project(manifest_json: &str, files_json: &str) -> Result<JsValue, JsValue> {
+    let manifest: compiler::ProjectManifest = serde_json::from_str(manifest_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid project manifest JSON: {e}")))?;
+    let files: std::collections::HashMap<String, String> = serde_json::from_str(files_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid project files JSON: {e}")))?;
+    let project =
+        compiler::compile_project(&manifest, &files).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&project).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// An instrument in a `reassign_instruments` mapping: a preset name as
+/// passed to `loadPreset`, or a full instrument config.
+#[derive(serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+enum WasmInstrumentChoice {
+    Preset(String),
+    Config(compiler::InstrumentConfig),
+}
+
+/// WASM-exposed: change the instruments of tracks in a compiled song
+/// without recompiling. `mapping_json` maps track names to a preset name
+/// (e.g. `{"melody": "FluidR3_GM/Flute"}`) or an instrument config.
+/// Returns the updated EventList.
+#[wasm_bindgen]
+pub fn reassign_instruments(event_list_json: &str, mapping_json: &str) -> Result<JsValue, JsValue> {
+    let mut event_list: compiler::EventList = serde_json::from_str(event_list_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid event list JSON: {e}")))?;
+    let choices: std::collections::HashMap<String, WasmInstrumentChoice> =
+        serde_json::from_str(mapping_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid instrument mapping JSON: {e}")))?;
+    let mapping = choices
+        .into_iter()
+        .map(|(track, choice)| {
+            let instrument = match choice {
+                WasmInstrumentChoice::Preset(name) if name == "Oscillator" => {
+                    compiler::InstrumentConfig::default()
+                }
+                WasmInstrumentChoice::Preset(name) => {
+                    compiler::InstrumentConfig::SamplerRef(compiler::SamplerRefConfig {
+                        name,
+                        ..Default::default()
+                    })
+                }
+                WasmInstrumentChoice::Config(config) => config,
+            };
+            (track, instrument)
+        })
+        .collect();
+    compiler::reassign_instruments(&mut event_list, &mapping).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&event_list).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// Set `event_list.end_mode` from a `"gate"`, `"release"` or `"tail"`
+/// override, leaving the song's own `song.endMode` when there is none.
+fn override_end_mode(event_list: &mut compiler::EventList, end_mode: Option<String>) -> Result<(), JsValue> {
+    if let Some(mode) = end_mode {
+        event_list.end_mode = mode.parse().map_err(|e: String| JsValue::from_str(&e))?;
+    }
+    Ok(())
+}
+
+/// Compile `.sw` source for one of the render entry points, applying their
+/// `end_mode` override.
+fn compile_for_render(source: &str, end_mode: Option<String>) -> Result<compiler::EventList, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let mut event_list =
+        compiler::compile(&program).map_err(|e| JsValue::from_str(&e))?;
+    override_end_mode(&mut event_list, end_mode)?;
+    Ok(event_list)
+}
+
+/// WASM-exposed: compile and render `.sw` source to a WAV byte array.
+///
+/// Every `render_song_*` function takes an optional `end_mode` last
+/// (`"gate"`, `"release"` or `"tail"`) that overrides the song's
+/// `song.endMode`, e.g. `"gate"` for quick previews and `"tail"` for the
+/// final export. Leave it `undefined` to use the song's own setting.
+#[wasm_bindgen]
+pub fn render_song_wav(source: &str, sample_rate: u32, end_mode: Option<String>) -> Result<Vec<u8>, JsValue> {
+    let event_list = compile_for_render(source, end_mode)?;
+    let mut engine = dsp::engine::AudioEngine::new(sample_rate as f64);
+    engine.oscillator_quality = OSCILLATOR_QUALITY.with(|q| q.get());
+    let pcm = engine.render_pcm_i16(&event_list);
+    Ok(dsp::renderer::encode_wav_public(&pcm, sample_rate, 2))
+}
+
+/// WASM-exposed: compile and render `.sw` source to mono f32 samples.
+/// Returns the raw audio buffer for AudioWorklet playback.
+#[wasm_bindgen]
+pub fn render_song_samples(source: &str, sample_rate: u32, end_mode: Option<String>) -> Result<Vec<f32>, JsValue> {
+    let event_list = compile_for_render(source, end_mode)?;
+    let mut engine = dsp::engine::AudioEngine::new(sample_rate as f64);
+    engine.oscillator_quality = OSCILLATOR_QUALITY.with(|q| q.get());
+    let samples_f64 = engine.render(&event_list);
+    Ok(samples_f64.iter().map(|&s| s as f32).collect())
+}
+
+/// WASM-exposed: render the beats from `start_beat` to `end_beat` of
+/// `.sw` source as mono f32 samples that loop seamlessly: the tails of
+/// notes sounding at the loop end are wrapped onto the loop start.
+#[wasm_bindgen]
+pub fn render_loop(source: &str, sample_rate: u32, start_beat: f64, end_beat: f64) -> Result<Vec<f32>, JsValue> {
+    if !(start_beat >= 0.0 && end_beat > start_beat) {
+        return Err(JsValue::from_str("Loop end must be after loop start, and start at beat 0 or later."));
+    }
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let event_list =
+        compiler::compile(&program).map_err(|e| JsValue::from_str(&e))?;
+    let mut engine = dsp::engine::AudioEngine::new(sample_rate as f64);
+    engine.oscillator_quality = OSCILLATOR_QUALITY.with(|q| q.get());
+    let samples_f64 = engine.render_loop(&event_list, start_beat, end_beat);
+    Ok(samples_f64.iter().map(|&s| s as f32).collect())
+}
+
+thread_local! {
+    /// Oscillator quality used by all WASM renders.
+    static OSCILLATOR_QUALITY: Cell<dsp::oscillator::OscillatorQuality> =
+        Cell::new(dsp::oscillator::OscillatorQuality::default());
+}
+
+/// WASM-exposed: choose the oscillator quality for subsequent renders:
+/// "bandlimited" (default, PolyBLEP anti-aliasing) or "naive"/"chip"
+/// for the raw aliased sound of the original waveforms.
+#[wasm_bindgen]
+pub fn set_oscillator_quality(quality: &str) -> Result<(), JsValue> {
+    let quality = dsp::oscillator::OscillatorQuality::parse(quality).ok_or_else(|| {
+        JsValue::from_str(&format!(
+            "Unknown oscillator quality '{quality}'. Expected 'bandlimited' or 'naive'."
+        ))
+    })?;
+    OSCILLATOR_QUALITY.with(|q| q.set(quality));
+    Ok(())
+}
+
+// ── Note Utilities ──────────────────────────────────────────
+
+/// WASM-exposed: parse a note name (e.g. "C4", "F#3", "Bb5") into a MIDI
+/// note number (C4 = 60). Returns `undefined` for an invalid name.
+#[wasm_bindgen]
+pub fn note_name_to_midi(name: &str) -> Option<i32> {
+    pitch::note_to_midi(name)
+}
+
+/// WASM-exposed: format a MIDI note number as a note name (60 → "C4").
+/// Black keys use flats instead of sharps when `prefer_flats` is set.
+#[wasm_bindgen]
+pub fn midi_to_note_name(midi: i32, prefer_flats: bool) -> String {
+    pitch::midi_to_note_name(midi, prefer_flats)
+}
+
+/// WASM-exposed: frequency in Hz of a MIDI note, given the A4 tuning pitch.
+#[wasm_bindgen]
+pub fn midi_to_freq(midi: i32, tuning_pitch: f64) -> f64 {
+    pitch::midi_to_frequency(midi, tuning_pitch)
+}
+
+/// WASM-exposed: detect chord symbols and estimate the key of a compiled
+/// song. `event_list_json` is an EventList as returned by `compile_song`
+/// (serialized to JSON); the result is a `theory::HarmonyAnalysis`.
+#[wasm_bindgen]
+pub fn analyze_harmony(event_list_json: &str) -> Result<JsValue, JsValue> {
+    let event_list: compiler::EventList = serde_json::from_str(event_list_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid event list JSON: {e}")))?;
+    let analysis = theory::analyze_harmony(&event_list);
+    serde_wasm_bindgen::to_value(&analysis).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: list the bars of `.sw` source with their start beats and
+/// start times in seconds, for drawing bar lines and a ruler in sync with
+/// playback. Returns an array of `compiler::BarInfo`.
+#[wasm_bindgen]
+pub fn get_time_map(source: &str) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let event_list =
+        compiler::compile(&program).map_err(|e| JsValue::from_str(&e))?;
+    let bars = compiler::time_map(&event_list, 120.0);
+    serde_wasm_bindgen::to_value(&bars).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: list the markers and cues of `.sw` source with their
+/// times in beats and seconds. Returns an array of `compiler::MarkerInfo`.
+#[wasm_bindgen]
+pub fn get_markers(source: &str) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let event_list =
+        compiler::compile(&program).map_err(|e| JsValue::from_str(&e))?;
+    let markers = compiler::markers(&event_list, 120.0);
+    serde_wasm_bindgen::to_value(&markers).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: list the track definitions of `.sw` source with their
+/// parameters, `///` doc comments and source spans. Returns a
+/// `compiler::SongStructure`.
+#[wasm_bindgen]
+pub fn analyze_structure(source: &str) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let structure = compiler::analyze_structure(&program);
+    serde_wasm_bindgen::to_value(&structure).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: the presets `.sw` source needs, ordered by the first
+/// beat each is played, with per-track usage. With `catalog_json` (a
+/// library `index.json`), sizes from the catalog are filled in. Returns
+/// an array of `compiler::PresetNeed`.
+#[wasm_bindgen]
+pub fn get_preset_needs(source: &str, catalog_json: Option<String>) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let event_list =
+        compiler::compile(&program).map_err(|e| JsValue::from_str(&e))?;
+    let mut needs = compiler::extract_preset_needs(&event_list, 120.0);
+    if let Some(json) = catalog_json {
+        let catalog: preset::LibraryIndex = serde_json::from_str(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid catalog JSON: {e}")))?;
+        catalog.estimate_preset_bytes(&mut needs);
+    }
+    serde_wasm_bindgen::to_value(&needs).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: per track, the instruments and presets its notes use,
+/// over which beat ranges, with note counts and pitch range. Returns an
+/// array of `compiler::TrackInstruments`.
+#[wasm_bindgen]
+pub fn analyze_instruments(source: &str) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let event_list =
+        compiler::compile(&program).map_err(|e| JsValue::from_str(&e))?;
+    let usage = compiler::instrument_usage(&event_list);
+    serde_wasm_bindgen::to_value(&usage).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: compile `.sw` source and estimate its render without
+/// running it: total beats and seconds, render length, event and note
+/// counts, peak polyphony against the voice limit and memory needed at
+/// `sample_rate` (44100 if not given). Returns a `dsp::engine::SongEstimate`.
+#[wasm_bindgen]
+pub fn estimate_song(source: &str, sample_rate: Option<u32>) -> Result<JsValue, JsValue> {
+    let event_list = compile_for_render(source, None)?;
+    let engine = dsp::engine::AudioEngine::new(sample_rate.unwrap_or(44100) as f64);
+    let estimate = engine.estimate(&event_list);
+    serde_wasm_bindgen::to_value(&estimate).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: the polyphony of `.sw` source: the most notes sounding at
+/// once during each beat (counting release tails) and the notes the voice
+/// limit will drop, to find where a song runs out of voices. Returns a
+/// `dsp::engine::Polyphony`.
+#[wasm_bindgen]
+pub fn analyze_polyphony(source: &str) -> Result<JsValue, JsValue> {
+    let event_list = compile_for_render(source, None)?;
+    let polyphony = dsp::engine::AudioEngine::new(44100.0).polyphony(&event_list);
+    serde_wasm_bindgen::to_value(&polyphony).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: flag notes of `.sw` source that retrigger a pitch still
+/// held on their track, have zero length, or are held well past the next
+/// marker. Returns an array of `compiler::NoteIssue` with source spans.
+#[wasm_bindgen]
+pub fn lint_song(source: &str) -> Result<JsValue, JsValue> {
+    let event_list = compile_for_render(source, None)?;
+    let issues = compiler::lint_notes(&event_list);
+    serde_wasm_bindgen::to_value(&issues).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: list the `const` declarations of `.sw` source with their
+/// resolved values and source spans, for a side panel of song-level
+/// definitions. Returns an array of `compiler::SongConstant`.
+#[wasm_bindgen]
+pub fn get_song_constants(source: &str) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let constants = compiler::song_constants(&program);
+    serde_wasm_bindgen::to_value(&constants).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: compare two sample buffers (e.g. from
+/// `render_song_samples`) and return a `dsp::diff::DiffReport` with the
+/// max and RMS sample delta and the first divergent offset.
+#[wasm_bindgen]
+pub fn compare_renders(samples_a: &[f32], samples_b: &[f32]) -> Result<JsValue, JsValue> {
+    let report = dsp::diff::compare_renders(samples_a, samples_b);
+    serde_wasm_bindgen::to_value(&report).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: transcribe a recorded monophonic melody (e.g. humming)
+/// into draft `.sw` source, with notes quantized to beats at `bpm`.
+#[wasm_bindgen]
+pub fn transcribe_monophonic(samples: &[f32], sample_rate: u32, bpm: f64) -> Result<String, JsValue> {
+    let samples: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+    dsp::tuner::transcribe_monophonic(&samples, sample_rate, bpm).map_err(|e| JsValue::from_str(&e))
+}
+
+/// WASM-exposed: estimate the tempo of an audio loop (e.g. a drum loop
+/// imported as a sample zone). Returns a `dsp::tempo::TempoEstimate`, or
+/// `null` if the audio is silent or too short.
+#[wasm_bindgen]
+pub fn detect_tempo(samples: &[f32], sample_rate: u32) -> Result<JsValue, JsValue> {
+    let samples: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+    let estimate = dsp::tempo::detect_tempo(&samples, sample_rate);
+    serde_wasm_bindgen::to_value(&estimate).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: slice a drum loop into a drum-kit sampler with one slice
+/// per key from C2 upward, returned as `preset.json` text. Splits into
+/// `num_slices` equal parts, or at detected transients when `undefined`.
+#[wasm_bindgen]
+pub fn slice_loop(samples: &[f32], sample_rate: u32, num_slices: Option<usize>) -> Result<String, JsValue> {
+    let samples: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+    let mode = num_slices.map_or(dsp::slicer::SliceMode::Onsets, dsp::slicer::SliceMode::Count);
+    let preset = dsp::slicer::slice_loop(&samples, sample_rate, mode).map_err(|e| JsValue::from_str(&e))?;
+    serde_json::to_string(&preset).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: change the duration of mono samples by `factor` (2.0 is
+/// twice as long) without changing their pitch.
+#[wasm_bindgen]
+pub fn time_stretch(samples: &[f32], factor: f64) -> Result<Vec<f32>, JsValue> {
+    let samples: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+    let stretched = dsp::stretch::time_stretch(&samples, factor).map_err(|e| JsValue::from_str(&e))?;
+    Ok(stretched.iter().map(|&s| s as f32).collect())
+}
+
+/// WASM-exposed: shift the pitch of mono samples by `semitones` without
+/// changing their duration.
+#[wasm_bindgen]
+pub fn pitch_shift(samples: &[f32], semitones: f64) -> Result<Vec<f32>, JsValue> {
+    let samples: Vec<f64> = samples.iter().map(|&s| s as f64).collect();
+    let shifted = dsp::stretch::pitch_shift(&samples, semitones).map_err(|e| JsValue::from_str(&e))?;
+    Ok(shifted.iter().map(|&s| s as f32).collect())
+}
+
+/// A loaded preset zone transferred from JS → WASM.
+#[derive(serde::Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct WasmLoadedZone {
+    #[serde(rename = "keyRangeLow")]
+    key_range_low: u8,
+    #[serde(rename = "keyRangeHigh")]
+    key_range_high: u8,
+    #[serde(rename = "rootNote")]
+    root_note: u8,
+    #[serde(rename = "fineTuneCents")]
+    fine_tune_cents: f64,
+    #[serde(rename = "sampleRate")]
+    sample_rate: u32,
+    #[serde(rename = "loopStart")]
+    loop_start: Option<u64>,
+    #[serde(rename = "loopEnd")]
+    loop_end: Option<u64>,
+    /// Exclusive (choke) group, e.g. open/closed hi-hats.
+    #[serde(default, rename = "exclusiveGroup")]
+    exclusive_group: Option<u32>,
+    /// Mono f32 PCM samples, decoded on the JS side.
+    samples: Vec<f32>,
+    /// Optional note-off sample at the zone's sample rate.
+    #[serde(default, rename = "releaseSamples")]
+    release_samples: Option<Vec<f32>>,
+    /// Optional key-tracked level and filter scaling.
+    #[serde(default, rename = "keyTracking")]
+    key_tracking: Option<preset::KeyTracking>,
+    /// Level offset in dB.
+    #[serde(default)]
+    gain: f64,
+    /// Stereo position from -1 (left) to 1 (right).
+    #[serde(default)]
+    pan: f64,
+}
+
+/// A child node in a composite preset.
+#[derive(serde::Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum WasmLoadedChild {
+    Sampler {
+        zones: Vec<WasmLoadedZone>,
+        #[serde(default, rename = "isDrumKit")]
+        is_drum_kit: bool,
+    },
+    Oscillator {
+        waveform: String,
+        #[serde(default)]
+        mixer: Option<f64>,
+        #[serde(flatten)]
+        envelope: compiler::EnvelopeConfig,
+    },
+}
+
+/// A loaded preset transferred from JS → WASM.
+/// Can be a simple sampler or a composite with multiple children.
+#[derive(serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct WasmLoadedPreset {
+    /// The preset name as it appears in loadPreset("name").
+    name: String,
+    /// Preset type: "sampler" or "composite"
+    #[serde(default, rename = "presetType")]
+    preset_type: Option<String>,
+    /// Whether this is a drum kit (percussion mode) — for simple samplers.
+    #[serde(default, rename = "isDrumKit")]
+    is_drum_kit: bool,
+    /// Loaded sample zones with PCM data — for simple samplers.
+    #[serde(default)]
+    zones: Vec<WasmLoadedZone>,
+    /// Composite mode: "layer", "split", or "chain"
+    #[serde(default)]
+    mode: Option<String>,
+    /// Children for composite presets.
+    #[serde(default)]
+    children: Vec<WasmLoadedChild>,
+    /// Mix levels for layer mode.
+    #[serde(default, rename = "mixLevels")]
+    mix_levels: Option<Vec<f64>>,
+    /// General MIDI program this preset plays, so `gm(N)` and
+    /// `loadPreset("gm:N")` resolve to it.
+    #[serde(default, rename = "gmProgram")]
+    gm_program: Option<u8>,
+    /// Clean-up applied to the zones' audio (DC removal, normalization,
+    /// edge fades); none by default.
+    #[serde(default)]
+    preprocess: dsp::sampler::SamplePreprocess,
+}
+
+/// Build a sampler from zones, taking ownership of the decoded PCM. Also
+/// returns the zones whose bad loop points were repaired.
+fn build_sampler_from_zones(
+    zones: Vec<WasmLoadedZone>,
+    is_drum_kit: bool,
+    preprocess: &dsp::sampler::SamplePreprocess,
+) -> (dsp::sampler::Sampler, Vec<dsp::sampler::LoopWarning>) {
+    let loaded_zones = zones.into_iter().map(|z| {
+        let buffer = dsp::sampler::SampleBuffer::new(z.samples, z.sample_rate);
+        dsp::sampler::LoadedZone {
+            key_range_low: z.key_range_low,
+            key_range_high: z.key_range_high,
+            root_note: z.root_note,
+            fine_tune_cents: z.fine_tune_cents,
+            sample_rate: z.sample_rate,
+            loop_start: z.loop_start,
+            loop_end: z.loop_end,
+            exclusive_group: z.exclusive_group,
+            buffer: buffer.into(),
+            release_buffer: z.release_samples.map(|pcm| {
+                dsp::sampler::SampleBuffer::new(pcm, z.sample_rate).into()
+            }),
+            gain: z.gain,
+            pan: z.pan,
+            key_tracking: z.key_tracking,
+        }
+    }).collect();
+    let (mut sampler, warnings) = dsp::sampler::Sampler::with_loop_warnings(loaded_zones, is_drum_kit);
+    sampler.preprocess(preprocess);
+    (sampler, warnings)
+}
+
+/// Build a composite child from the WASM data, with the loop warnings of
+/// a sampler child.
+fn build_composite_child(
+    child: WasmLoadedChild,
+    preprocess: &dsp::sampler::SamplePreprocess,
+) -> (dsp::composite::CompositeChild, Vec<dsp::sampler::LoopWarning>) {
+    match child {
+        WasmLoadedChild::Sampler { zones, is_drum_kit } => {
+            let (sampler, warnings) = build_sampler_from_zones(zones, is_drum_kit, preprocess);
+            (dsp::composite::CompositeChild::Sampler(sampler), warnings)
+        }
+        WasmLoadedChild::Oscillator { waveform, mixer, envelope } => {
+            let child = dsp::composite::CompositeChild::Oscillator(compiler::OscillatorConfig {
+                waveform,
+                envelope,
+                velocity: Default::default(),
+                mixer,
+                detune: None,
+            });
+            (child, Vec::new())
+        }
+    }
+}
+
+/// Insert a preset into a registry unless it already holds an identical
+/// copy, assigning its General MIDI program if it has one. Returns
+/// whether the registry changed, and a message for each zone whose loop
+/// points were repaired.
+fn register_preset(registry: &mut dsp::engine::PresetRegistry, source: PresetSource) -> (bool, Vec<String>) {
+    let mut changed = false;
+    if let Some(program) = source.gm_program
+        && registry.gm_program(program) != Some(source.name.as_str())
+    {
+        registry.assign_gm_program(program, source.name.clone());
+        changed = true;
+    }
+    let Some(preset) = source.preset else {
+        return (changed, Vec::new());
+    };
+    let (built, warnings) = build_preset(preset);
+    registry.insert_with_fingerprint(source.name, built, source.fingerprint);
+    (true, warnings)
+}
+
+/// Build a preset (sampler or composite) from the WASM-transferred data,
+/// describing each repaired loop as e.g. `Piano child 1 zone 3: ...`.
+fn build_preset(preset: WasmLoadedPreset) -> (dsp::engine::RegisteredPreset, Vec<String>) {
+    let name = &preset.name;
+    // Check if this is a composite preset
+    let is_composite = preset.preset_type.as_deref() == Some("composite") 
+        || !preset.children.is_empty();
+
+    if is_composite {
+        let mut warnings = Vec::new();
+        let mut children: Vec<dsp::composite::CompositeChild> = Vec::new();
+        for (index, child) in preset.children.into_iter().enumerate() {
+            let (child, loop_warnings) = build_composite_child(child, &preset.preprocess);
+            warnings.extend(
+                loop_warnings
+                    .into_iter()
+                    .map(|w| format!("{name} child {index} zone {}: {}", w.zone_index, w.message)),
+            );
+            children.push(child);
+        }
+
+        let mode = match preset.mode.as_deref() {
+            Some("split") => dsp::composite::CompositeMode::Split,
+            Some("chain") => dsp::composite::CompositeMode::Chain,
+            _ => dsp::composite::CompositeMode::Layer,
+        };
+
+        let composite = match mode {
+            dsp::composite::CompositeMode::Layer => 
+                dsp::composite::CompositeInstrument::new_layer(children, preset.mix_levels),
+            dsp::composite::CompositeMode::Split => 
+                dsp::composite::CompositeInstrument::new_split(children, None),
+            dsp::composite::CompositeMode::Chain => {
+                // Chain mode uses layer structure for now (effects not fully impl)
+                dsp::composite::CompositeInstrument::new_layer(children, None)
+            }
+        };
+
+        (dsp::engine::RegisteredPreset::Composite(composite), warnings)
+    } else {
+        // Simple sampler preset
+        let (sampler, loop_warnings) =
+            build_sampler_from_zones(preset.zones, preset.is_drum_kit, &preset.preprocess);
+        let warnings = loop_warnings
+            .into_iter()
+            .map(|w| format!("{name} zone {}: {}", w.zone_index, w.message))
+            .collect();
+        (dsp::engine::RegisteredPreset::Sampler(sampler), warnings)
+    }
+}
+
+// ── Preset Bank ─────────────────────────────────────────────
+
+/// Default sample-memory budget for the persistent preset bank (256 MiB).
+const DEFAULT_PRESET_BANK_BUDGET: usize = 256 * 1024 * 1024;
+
+thread_local! {
+    /// Presets registered from JS. Kept across calls so the front end only
+    /// needs to send each preset's PCM once; least recently used zones
+    /// are evicted when the memory budget is exceeded.
+    static PRESET_BANK: RefCell<dsp::engine::PresetRegistry> =
+        RefCell::new(dsp::engine::PresetRegistry::with_budget(DEFAULT_PRESET_BANK_BUDGET));
+    /// Whether bank renders play missing presets on an oscillator.
+    static PLACEHOLDER_FOR_MISSING_PRESETS: Cell<bool> = const { Cell::new(true) };
+    /// Missing presets met by bank renders, not yet taken by JS.
+    static MISSING_PRESETS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// One preset of a presets JSON array. `preset` is only parsed when the
+/// bank does not already hold a whole copy with the same JSON.
+struct PresetSource {
+    name: String,
+    gm_program: Option<u8>,
+    fingerprint: u64,
+    preset: Option<WasmLoadedPreset>,
+}
+
+/// The fields of a preset that are read before deciding to parse it.
+#[derive(serde::Deserialize)]
+struct PresetHeader {
+    name: String,
+    #[serde(default, rename = "gmProgram")]
+    gm_program: Option<u8>,
+}
+
+/// Parse a JSON array of `WasmLoadedPreset` objects ("" or "[]" for
+/// none), skipping the sample data of presets `registry` already has.
+fn parse_presets_json(
+    presets_json: &str,
+    registry: &dsp::engine::PresetRegistry,
+) -> Result<Vec<PresetSource>, JsValue> {
+    if presets_json.is_empty() || presets_json == "[]" {
+        return Ok(Vec::new());
+    }
+    let error = |e: serde_json::Error| JsValue::from_str(&format!("Failed to parse presets JSON: {e}"));
+    let raw: Vec<&serde_json::value::RawValue> = serde_json::from_str(presets_json).map_err(error)?;
+    raw.into_iter()
+        .map(|json| {
+            let header: PresetHeader = serde_json::from_str(json.get()).map_err(error)?;
+            let mut hasher = std::hash::DefaultHasher::new();
+            std::hash::Hash::hash(json.get(), &mut hasher);
+            let fingerprint = std::hash::Hasher::finish(&hasher);
+            if registry.fingerprint(&header.name) == Some(fingerprint) {
+                let PresetHeader { name, gm_program } = header;
+                return Ok(PresetSource { name, gm_program, fingerprint, preset: None });
+            }
+            let preset: WasmLoadedPreset = serde_json::from_str(json.get()).map_err(error)?;
+            Ok(PresetSource { name: header.name, gm_program: preset.gm_program, fingerprint, preset: Some(preset) })
+        })
+        .collect()
+}
+
+/// Parse a JSON `MasterEffects` object ("" for no effects).
+fn parse_effects_json(effects_json: &str) -> Result<dsp::engine::MasterEffects, JsValue> {
+    if effects_json.is_empty() {
+        return Ok(dsp::engine::MasterEffects::default());
+    }
+    serde_json::from_str(effects_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse effects JSON: {e}")))
+}
+
+/// An engine holding the preset bank's registry, which goes back into
+/// the bank when dropped, even if the render panics.
+struct BankEngine(Option<dsp::engine::AudioEngine>);
+
+impl Drop for BankEngine {
+    fn drop(&mut self) {
+        if let Some(engine) = self.0.take() {
+            let mut registry = engine.into_registry();
+            registry.unpin_all();
+            // The bank is already gone if the thread is exiting
+            let _ = PRESET_BANK.try_with(|bank| *bank.borrow_mut() = registry);
+        }
+    }
+}
+
+/// Register `presets_json` into the preset bank, then run `f` with an
+/// engine that renders against the bank. The presets stay in the bank
+/// for later calls, like those of `register_presets`. The `needed`
+/// presets are pinned until `f` returns, so registering the call's
+/// presets cannot evict each other.
+fn with_bank_engine<T>(
+    sample_rate: u32,
+    presets_json: &str,
+    needed: Vec<String>,
+    f: impl FnOnce(&dsp::engine::AudioEngine) -> T,
+) -> Result<T, JsValue> {
+    let presets = PRESET_BANK.with(|bank| parse_presets_json(presets_json, &bank.borrow()))?;
+    let registry = PRESET_BANK.with(|bank| std::mem::take(&mut *bank.borrow_mut()));
+    let mut bank_engine = BankEngine(Some(dsp::engine::AudioEngine::with_registry(sample_rate as f64, registry)));
+    let engine = bank_engine.0.as_mut().expect("set above");
+    engine.oscillator_quality = OSCILLATOR_QUALITY.with(|q| q.get());
+    engine.placeholder_for_missing_presets = PLACEHOLDER_FOR_MISSING_PRESETS.with(|p| p.get());
+    for name in needed {
+        engine.registry_mut().pin(name);
+    }
+    // Loop repairs are reported by `register_presets`; a render has no
+    // channel for them. Cached track audio is only stale when a preset
+    // was added or replaced.
+    let mut changed = false;
+    for preset in presets {
+        changed |= register_preset(engine.registry_mut(), preset).0;
+    }
+    if changed {
+        clear_render_cache();
+    }
+    Ok(f(engine))
+}
+
+/// Like `with_bank_engine` for rendering `event_list`, pinning the presets
+/// it uses and noting those that are not in the bank for
+/// `take_missing_presets`.
+fn with_song_engine<T>(
+    sample_rate: u32,
+    presets_json: &str,
+    event_list: &compiler::EventList,
+    f: impl FnOnce(&dsp::engine::AudioEngine) -> T,
+) -> Result<T, JsValue> {
+    let mut needed = compiler::extract_preset_refs(event_list);
+    needed.extend(event_list.instruments.iter().flat_map(|instrument| instrument.preset_refs()));
+    with_bank_engine(sample_rate, presets_json, needed, |engine| {
+        let missing = engine.missing_presets(event_list);
+        MISSING_PRESETS.with(|reported| {
+            let mut reported = reported.borrow_mut();
+            for name in missing {
+                if !reported.contains(&name) {
+                    reported.push(name);
+                }
+            }
+        });
+        f(engine)
+    })
+}
+
+/// WASM-exposed: whether notes of presets that are not in the bank yet
+/// play on an oscillator stand-in (the default) or are left silent.
+#[wasm_bindgen]
+pub fn set_placeholder_for_missing_presets(enabled: bool) {
+    PLACEHOLDER_FOR_MISSING_PRESETS.with(|p| p.set(enabled));
+    clear_render_cache();
+}
+
+/// WASM-exposed: the missing presets renders have run into since the last
+/// call, in the order they were first needed, so the UI can show
+/// "loading Piano…" while they download. Clears the list.
+#[wasm_bindgen]
+pub fn take_missing_presets() -> Vec<String> {
+    MISSING_PRESETS.with(|reported| std::mem::take(&mut *reported.borrow_mut()))
+}
+
+/// WASM-exposed: register loaded presets into the persistent preset bank.
+///
+/// `presets_json` is a JSON array of `WasmLoadedPreset` objects. Presets
+/// with an existing name are replaced. Returns a warning for each zone
+/// whose loop points did not fit its sample and were clamped or dropped,
+/// e.g. from a badly converted SoundFont.
+#[wasm_bindgen]
+pub fn register_presets(presets_json: &str) -> Result<Vec<String>, JsValue> {
+    let presets = PRESET_BANK.with(|bank| parse_presets_json(presets_json, &bank.borrow()))?;
+    let mut changed = false;
+    let warnings = PRESET_BANK.with(|bank| {
+        let mut bank = bank.borrow_mut();
+        presets
+            .into_iter()
+            .flat_map(|preset| {
+                let (registered, warnings) = register_preset(&mut bank, preset);
+                changed |= registered;
+                warnings
+            })
+            .collect()
+    });
+    if changed {
+        clear_render_cache();
+    }
+    Ok(warnings)
+}
+
+/// WASM-exposed: remove a preset from the bank. Returns whether it existed.
+#[wasm_bindgen]
+pub fn unregister_preset(name: &str) -> bool {
+    PRESET_BANK.with(|bank| bank.borrow_mut().remove(name))
+}
+
+/// WASM-exposed: remove all presets from the bank.
+#[wasm_bindgen]
+pub fn clear_preset_bank() {
+    PRESET_BANK.with(|bank| bank.borrow_mut().clear());
+}
+
+/// WASM-exposed: set the bank's sample-memory budget in bytes.
+/// Pass `undefined` to remove the limit.
+#[wasm_bindgen]
+pub fn set_preset_memory_budget(budget_bytes: Option<usize>) {
+    PRESET_BANK.with(|bank| bank.borrow_mut().set_memory_budget(budget_bytes));
+}
+
+/// WASM-exposed: report preset count, zone count, memory usage, budget,
+/// and eviction count for the preset bank.
+#[wasm_bindgen]
+pub fn preset_bank_stats() -> Result<JsValue, JsValue> {
+    let stats = PRESET_BANK.with(|bank| bank.borrow().stats());
+    serde_wasm_bindgen::to_value(&stats).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: compile and render `.sw` source to mono f32 samples
+/// with loaded preset data for sampler-based instruments.
+///
+/// `presets_json` is a JSON array of `WasmLoadedPreset` objects, each
+/// containing the preset name and pre-decoded PCM zone data. They are
+/// added to the preset bank, so presets registered by earlier calls are
+/// also available.
+#[wasm_bindgen]
+pub fn render_song_samples_with_presets(
+    source: &str,
+    sample_rate: u32,
+    presets_json: &str,
+    end_mode: Option<String>,
+) -> Result<Vec<f32>, JsValue> {
+    let event_list = compile_for_render(source, end_mode)?;
+
+    let samples_f64 = with_song_engine(sample_rate, presets_json, &event_list, |engine| {
+        engine.render(&event_list)
+    })?;
+    Ok(samples_f64.iter().map(|&s| s as f32).collect())
+}
+
+/// Samples and level meters returned by `render_song_with_meters`.
+#[derive(serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct MeteredRender {
+    samples: Vec<f32>,
+    meters: dsp::meter::MeterData,
+}
+
+/// WASM-exposed: render `.sw` source like `render_song_samples_with_presets`
+/// and also return peak/RMS levels every `meter_block` samples for the
+/// master output and each track, as `{ samples, meters }`.
+#[wasm_bindgen]
+pub fn render_song_with_meters(
+    source: &str,
+    sample_rate: u32,
+    presets_json: &str,
+    meter_block: usize,
+    end_mode: Option<String>,
+) -> Result<JsValue, JsValue> {
+    let event_list = compile_for_render(source, end_mode)?;
+
+    let (samples_f64, meters) = with_song_engine(sample_rate, presets_json, &event_list, |engine| {
+        engine.render_with_meters(&event_list, meter_block)
+    })?;
+    let result = MeteredRender {
+        samples: samples_f64.iter().map(|&s| s as f32).collect(),
+        meters,
+    };
+    serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// Samples and note onsets returned by `render_song_with_onsets`.
+#[derive(serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct OnsetRender {
+    samples: Vec<f32>,
+    onsets: Vec<dsp::engine::NoteOnset>,
+}
+
+/// WASM-exposed: render `.sw` source like `render_song_samples_with_presets`
+/// and also return the start and release of every note as sample offsets
+/// into the buffer, as `{ samples, onsets }`. Each onset has `noteId`,
+/// `track`, `pitch`, `velocity`, `startSample` and `releaseSample`, so a
+/// falling-notes view can stay in sync with playback.
+#[wasm_bindgen]
+pub fn render_song_with_onsets(
+    source: &str,
+    sample_rate: u32,
+    presets_json: &str,
+    end_mode: Option<String>,
+) -> Result<JsValue, JsValue> {
+    let event_list = compile_for_render(source, end_mode)?;
+
+    let (samples_f64, onsets) = with_song_engine(sample_rate, presets_json, &event_list, |engine| {
+        engine.render_with_onsets(&event_list)
+    })?;
+    let result = OnsetRender {
+        samples: samples_f64.iter().map(|&s| s as f32).collect(),
+        onsets,
+    };
+    serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: compile and render `.sw` source to a WAV byte array
+/// with loaded preset data for sampler-based instruments.
+#[wasm_bindgen]
+pub fn render_song_wav_with_presets(
+    source: &str,
+    sample_rate: u32,
+    presets_json: &str,
+    end_mode: Option<String>,
+) -> Result<Vec<u8>, JsValue> {
+    let event_list = compile_for_render(source, end_mode)?;
+
+    let pcm = with_song_engine(sample_rate, presets_json, &event_list, |engine| {
+        engine.render_pcm_i16(&event_list)
+    })?;
+    Ok(dsp::renderer::encode_wav_public(&pcm, sample_rate, 2))
+}
+
+/// WASM-exposed: render `.sw` source like `render_song_wav_with_presets`
+/// through the master effects in `effects_json`, a `MasterEffects` object
+/// such as `{"reverb": {"roomSize": 0.8}, "compressor": {}}`.
+#[wasm_bindgen]
+pub fn render_song_wav_with_effects(
+    source: &str,
+    sample_rate: u32,
+    effects_json: &str,
+    presets_json: &str,
+    end_mode: Option<String>,
+) -> Result<Vec<u8>, JsValue> {
+    let effects = parse_effects_json(effects_json)?;
+    let event_list = compile_for_render(source, end_mode)?;
+
+    let pcm = with_song_engine(sample_rate, presets_json, &event_list, |engine| {
+        engine.render_pcm_i16_with_effects(&event_list, &effects)
+    })?;
+    Ok(dsp::renderer::encode_wav_public(&pcm, sample_rate, 2))
+}
+
+/// WASM-exposed: render `.sw` source through the master effects in
+/// `effects_json` (see `render_song_wav_with_effects`) to interleaved
+/// stereo f32 samples, since chorus and reverb widen the mix.
+#[wasm_bindgen]
+pub fn render_song_samples_with_effects(
+    source: &str,
+    sample_rate: u32,
+    effects_json: &str,
+    presets_json: &str,
+    end_mode: Option<String>,
+) -> Result<Vec<f32>, JsValue> {
+    let effects = parse_effects_json(effects_json)?;
+    let event_list = compile_for_render(source, end_mode)?;
+
+    let (left, right) = with_song_engine(sample_rate, presets_json, &event_list, |engine| {
+        engine.render_stereo(&event_list, Some(&effects))
+    })?;
+    Ok(interleave(&left, &right))
+}
+
+/// WASM-exposed: render `.sw` source like `render_song_samples_with_presets`
+/// but keep both channels, as interleaved stereo f32 samples (L, R, L, R…),
+/// so panning survives AudioWorklet playback.
+#[wasm_bindgen]
+pub fn render_song_samples_stereo(
+    source: &str,
+    sample_rate: u32,
+    presets_json: &str,
+    end_mode: Option<String>,
+) -> Result<Vec<f32>, JsValue> {
+    let event_list = compile_for_render(source, end_mode)?;
+
+    let (left, right) = with_song_engine(sample_rate, presets_json, &event_list, |engine| {
+        engine.render_stereo(&event_list, None)
+    })?;
+    Ok(interleave(&left, &right))
+}
+
+fn interleave(left: &[f32], right: &[f32]) -> Vec<f32> {
+    left.iter().zip(right).flat_map(|(&l, &r)| [l, r]).collect()
+}
+
+/// WASM-exposed: render one track of `.sw` source, with presets from the
+/// preset bank, and wrap it as a sampler preset for "freezing" a heavy
+/// instrument. Returns a `dsp::engine::FrozenTrack`: the preset plays the
+/// recording at C4, starting from the track's first note at `startBeat`.
+#[wasm_bindgen]
+pub fn freeze_track(source: &str, track_name: &str, sample_rate: u32) -> Result<JsValue, JsValue> {
+    let program = parse(source).map_err(|e| JsValue::from_str(&format!("{e}")))?;
+    let event_list =
+        compiler::compile(&program).map_err(|e| JsValue::from_str(&e))?;
+
+    let frozen = with_song_engine(sample_rate, "[]", &event_list, |engine| {
+        engine.freeze_track(&event_list, track_name)
+    })?
+    .map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&frozen).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+// ── Render Cache ────────────────────────────────────────────
+
+thread_local! {
+    /// Rendered track mixes reused by `render_song_samples_cached`.
+    static RENDER_CACHE: RefCell<dsp::cache::RenderCache> =
+        RefCell::new(dsp::cache::RenderCache::default());
+}
+
+/// WASM-exposed: render like `render_song_samples_with_presets`, reusing
+/// the audio of tracks that have not changed since an earlier cached
+/// render. Intended for re-rendering while editing one track at a time.
+#[wasm_bindgen]
+pub fn render_song_samples_cached(
+    source: &str,
+    sample_rate: u32,
+    presets_json: &str,
+    end_mode: Option<String>,
+) -> Result<Vec<f32>, JsValue> {
+    let event_list = compile_for_render(source, end_mode)?;
+
+    let samples_f64 = with_song_engine(sample_rate, presets_json, &event_list, |engine| {
+        RENDER_CACHE.with(|cache| engine.render_cached(&event_list, &mut cache.borrow_mut()))
+    })?;
+    Ok(samples_f64.iter().map(|&s| s as f32).collect())
+}
+
+/// WASM-exposed: drop all cached track audio.
+#[wasm_bindgen]
+pub fn clear_render_cache() {
+    RENDER_CACHE.with(|cache| cache.borrow_mut().clear());
+}
+
+/// WASM-exposed: limit the memory used by cached track audio, in bytes.
+#[wasm_bindgen]
+pub fn set_render_cache_max_bytes(max_bytes: usize) {
+    RENDER_CACHE.with(|cache| cache.borrow_mut().set_max_bytes(max_bytes));
+}
+
+/// WASM-exposed: report entry count, memory use, limit, hits and misses
+/// of the render cache.
+#[wasm_bindgen]
+pub fn render_cache_stats() -> Result<JsValue, JsValue> {
+    let stats = RENDER_CACHE.with(|cache| cache.borrow().stats());
+    serde_wasm_bindgen::to_value(&stats).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+// ── Piano Keyboard: Single Note Rendering ───────────────────
+
+/// WASM-exposed: query the compilation state at a given cursor byte offset.
+///
+/// Returns a JSON object with the active instrument, BPM, tuning, note length,
+/// track name, and beat position at the cursor. Used by the editor to determine
+/// which instrument to preview when a piano key is pressed. When the
+/// instrument is a preset in the preset bank, `keyCoverage` reports the keys
+/// it can play and whether it is a drum kit.
+#[wasm_bindgen]
+pub fn get_instrument_at_cursor(
+    source: &str,
+    cursor_byte_offset: usize,
+) -> Result<JsValue, JsValue> {
+    let mut ctx = compiler::cursor_context(source, cursor_byte_offset)
+        .map_err(|e| JsValue::from_str(&e))?;
+    if let Some(name) = ctx.instrument.preset_ref() {
+        ctx.key_coverage = PRESET_BANK
+            .with(|bank| bank.borrow().get(name).and_then(|preset| preset.key_coverage()));
+    }
+    serde_wasm_bindgen::to_value(&ctx).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: render a single note to mono f32 PCM samples.
+///
+/// Used by the piano keyboard to preview notes with the instrument active
+/// at the cursor. Constructs a minimal EventList, renders through the
+/// AudioEngine with `EndMode::Release`, and caps at 4 seconds.
+///
+/// * `pitch` — note name (e.g. "C4", "A3")
+/// * `velocity` — note velocity 0–127
+/// * `gate_beats` — audible note duration in beats
+/// * `bpm` — tempo for beat→seconds conversion
+/// * `tuning_pitch` — A4 reference frequency (e.g. 440.0)
+/// * `sample_rate` — output sample rate
+/// * `instrument_json` — `InstrumentConfig` serialized as JSON
+/// * `presets_json` — optional JSON array of loaded preset data (pass "[]" to
+///   use only the presets already in the bank)
+#[wasm_bindgen]
+pub fn render_single_note(
+    pitch: &str,
+    velocity: f64,
+    gate_beats: f64,
+    bpm: f64,
+    tuning_pitch: f64,
+    sample_rate: u32,
+    instrument_json: &str,
+    presets_json: &str,
+) -> Result<Vec<f32>, JsValue> {
+    let instrument: compiler::InstrumentConfig = serde_json::from_str(instrument_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid instrument JSON: {e}")))?;
+
+    let event_list = preview_event_list(&[pitch], velocity, gate_beats, bpm, tuning_pitch, &instrument);
+    render_preview(&event_list, sample_rate, presets_json)
+}
+
+/// WASM-exposed: render a chord to mono f32 PCM samples.
+///
+/// Like `render_single_note`, but plays every pitch of `pitches_json` (a
+/// JSON array of note names, e.g. `["C4", "E4", "G4"]`) together, so the
+/// editor can audition a chord.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn render_chord(
+    pitches_json: &str,
+    velocity: f64,
+    gate_beats: f64,
+    bpm: f64,
+    tuning_pitch: f64,
+    sample_rate: u32,
+    instrument_json: &str,
+    presets_json: &str,
+) -> Result<Vec<f32>, JsValue> {
+    let pitches: Vec<String> = serde_json::from_str(pitches_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid pitches JSON: {e}")))?;
+    let instrument: compiler::InstrumentConfig = serde_json::from_str(instrument_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid instrument JSON: {e}")))?;
+
+    let pitches: Vec<&str> = pitches.iter().map(String::as_str).collect();
+    let event_list = preview_event_list(&pitches, velocity, gate_beats, bpm, tuning_pitch, &instrument);
+    render_preview(&event_list, sample_rate, presets_json)
+}
+
+/// WASM-exposed: render a single note as it would sound at the cursor.
+///
+/// Like `render_single_note`, but the instrument, tempo and tuning are taken
+/// from the song state at `cursor_byte_offset` instead of being passed in,
+/// so the preview matches playback of that part of the song.
+///
+/// * `pitch` — note name in scientific octave numbering (e.g. "C4")
+/// * `velocity` — note velocity 0–127
+/// * `gate_beats` — audible note duration in beats; defaults to the note
+///   length in effect at the cursor
+/// * `presets_json` — optional JSON array of loaded preset data (pass "[]" to
+///   use only the presets already in the bank)
+#[wasm_bindgen]
+pub fn render_note_in_context(
+    source: &str,
+    cursor_byte_offset: usize,
+    pitch: &str,
+    velocity: f64,
+    gate_beats: Option<f64>,
+    sample_rate: u32,
+    presets_json: &str,
+) -> Result<Vec<f32>, JsValue> {
+    let ctx = compiler::cursor_context(source, cursor_byte_offset)
+        .map_err(|e| JsValue::from_str(&e))?;
+    let gate = gate_beats.unwrap_or(ctx.note_length);
+    let event_list = preview_event_list(&[pitch], velocity, gate, ctx.bpm, ctx.tuning_pitch, &ctx.instrument);
+    render_preview(&event_list, sample_rate, presets_json)
+}
+
+/// Build a minimal EventList playing `pitches` together from beat 0.
+fn preview_event_list(
+    pitches: &[&str],
+    velocity: f64,
+    gate_beats: f64,
+    bpm: f64,
+    tuning_pitch: f64,
+    instrument: &compiler::InstrumentConfig,
+) -> compiler::EventList {
+    let mut events = vec![
+        // Set BPM
+        compiler::Event {
+            time: 0.0,
+            kind: compiler::EventKind::SetProperty {
+                target: "track.beatsPerMinute".to_string(),
+                value: format!("{bpm}"),
+                source_start: 0,
+                source_end: 0,
+            },
+            track_name: None,
+        },
+        // Set tuning
+        compiler::Event {
+            time: 0.0,
+            kind: compiler::EventKind::SetProperty {
+                target: "track.tuningPitch".to_string(),
+                value: format!("{tuning_pitch}"),
+                source_start: 0,
+                source_end: 0,
+            },
+            track_name: None,
+        },
+    ];
+    events.extend(pitches.iter().map(|pitch| compiler::Event {
+        time: 0.0,
+        kind: compiler::EventKind::Note {
+            pitch: pitch.to_string(),
+            velocity,
+            gate: gate_beats,
+            instrument: 0,
+            source_start: 0,
+            source_end: 0,
+            glide_from: None,
+            slide_to: None,
+            expression: Default::default(),
+            sends: Default::default(),
+            modulation: Default::default(),
+        },
+        track_name: None,
+    }));
+    compiler::EventList {
+        events,
+        instruments: vec![instrument.clone()],
+        total_beats: gate_beats,
+        end_mode: compiler::EndMode::Release,
+        count_in: None,
+        fade_in: None,
+        fade_out: None,
+        anacrusis: None,
+    }
+}
+
+/// Render a preview EventList to mono f32 samples, capped at 4 seconds.
+fn render_preview(
+    event_list: &compiler::EventList,
+    sample_rate: u32,
+    presets_json: &str,
+) -> Result<Vec<f32>, JsValue> {
+    let samples_f64 = with_song_engine(sample_rate, presets_json, event_list, |engine| {
+        engine.render(event_list)
+    })?;
+
+    // Cap at 4 seconds.
+    let max_samples = (4.0 * sample_rate as f64) as usize;
+    let capped = if samples_f64.len() > max_samples {
+        &samples_f64[..max_samples]
+    } else {
+        &samples_f64
+    };
+
+    Ok(capped.iter().map(|&s| s as f32).collect())
+}
+
+// ── Audio store & bundles ───────────────────────────────────
+
+#[wasm_bindgen]
+extern "C" {
+    /// JS object holding content-addressed samples: `get(hash)` returns a
+    /// `Uint8Array` or `undefined`, `put(hash, bytes)` keeps one.
+    pub type JsAudioStoreCallbacks;
+
+    #[wasm_bindgen(method, catch)]
+    fn get(this: &JsAudioStoreCallbacks, hash: &str) -> Result<Option<Vec<u8>>, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn put(this: &JsAudioStoreCallbacks, hash: &str, bytes: &[u8]) -> Result<(), JsValue>;
+}
+
+/// An `AudioStore` backed by JS callbacks, e.g. over IndexedDB.
+pub struct JsAudioStore<'a>(pub &'a JsAudioStoreCallbacks);
+
+impl preset::store::AudioStore for JsAudioStore<'_> {
+    fn put(&self, bytes: &[u8]) -> Result<String, String> {
+        let hash = preset::store::content_hash(bytes);
+        self.0.put(&hash, bytes).map_err(|e| format!("{e:?}"))?;
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        self.0.get(hash).map_err(|e| format!("{e:?}"))
+    }
+}
+
+/// WASM-exposed: check fetched preset audio against the `sha256` its
+/// `AudioReference` declares. Returns `null` when it matches, or an
+/// `IntegrityError` object.
+#[wasm_bindgen]
+pub fn check_audio_integrity(url: &str, bytes: &[u8], expected_sha256: &str) -> Result<JsValue, JsValue> {
+    match preset::integrity::verify_sha256(url, bytes, expected_sha256) {
+        Ok(()) => Ok(JsValue::NULL),
+        Err(e) => serde_wasm_bindgen::to_value(&e).map_err(|e| JsValue::from_str(&format!("{e}"))),
+    }
+}
+
+/// WASM-exposed: apply a JSON array of `PresetEdit`s to a preset and
+/// return the validated preset JSON.
+#[wasm_bindgen]
+pub fn apply_preset_edits(preset_json: &str, edits_json: &str) -> Result<String, JsValue> {
+    let mut descriptor: preset::PresetDescriptor = serde_json::from_str(preset_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid preset JSON: {e}")))?;
+    let edits: Vec<preset::editor::PresetEdit> = serde_json::from_str(edits_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid edits JSON: {e}")))?;
+    for edit in edits {
+        descriptor.apply(edit).map_err(|e| JsValue::from_str(&e))?;
+    }
+    descriptor.validate().map_err(|e| JsValue::from_str(&e))?;
+    serde_json::to_string(&descriptor).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: lint a preset JSON, returning a `PresetIssue` array
+/// (empty when the preset is clean).
+#[wasm_bindgen]
+pub fn validate_preset(preset_json: &str) -> Result<JsValue, JsValue> {
+    let descriptor: preset::PresetDescriptor = serde_json::from_str(preset_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid preset JSON: {e}")))?;
+    let issues = preset::lint::validate_preset(&descriptor);
+    serde_wasm_bindgen::to_value(&issues).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// WASM-exposed: map a JSON array of `TunedSample`s onto the keyboard,
+/// returning the `SamplerConfig`.
+#[wasm_bindgen]
+pub fn auto_map_zones(samples_json: &str) -> Result<JsValue, JsValue> {
+    let samples: Vec<preset::editor::TunedSample> = serde_json::from_str(samples_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid samples JSON: {e}")))?;
+    let config = preset::editor::auto_map_zones(&samples).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&config).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+/// A preset in a song bundle, as seen from JS.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct WasmBundlePreset {
+    name: String,
+    descriptor: preset::PresetDescriptor,
+}
+
+/// A song bundle opened by `import_song_bundle`.
+#[derive(serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct WasmSongBundle {
+    source: String,
+    events: compiler::EventList,
+    presets: Vec<WasmBundlePreset>,
+}
+
+/// WASM-exposed: pack a song and the presets it uses (a JSON array of
+/// `{name, descriptor}`) into a bundle. Content-addressed samples are
+/// read from `store`.
+#[wasm_bindgen]
+pub fn export_song_bundle(
+    source: &str,
+    presets_json: &str,
+    store: &JsAudioStoreCallbacks,
+) -> Result<Vec<u8>, JsValue> {
+    let presets: Vec<WasmBundlePreset> = serde_json::from_str(presets_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid presets JSON: {e}")))?;
+    let store = JsAudioStore(store);
+    let presets = presets
+        .into_iter()
+        .map(|p| preset::bundle::BundledPreset::from_store(&p.name, p.descriptor, &store))
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| JsValue::from_str(&e))?;
+    preset::bundle::bundle_song(source, &presets).map_err(|e| JsValue::from_str(&e))
+}
+
+/// WASM-exposed: open a bundle, putting its samples into `store`.
+#[wasm_bindgen]
+pub fn import_song_bundle(bytes: &[u8], store: &JsAudioStoreCallbacks) -> Result<JsValue, JsValue> {
+    let bundle = preset::bundle::load_bundle(bytes).map_err(|e| JsValue::from_str(&e))?;
+    bundle.import_audio(&JsAudioStore(store)).map_err(|e| JsValue::from_str(&e))?;
+    let result = WasmSongBundle {
+        source: bundle.source,
+        events: bundle.events,
+        presets: bundle
+            .presets
+            .into_iter()
+            .map(|p| WasmBundlePreset { name: p.name, descriptor: p.descriptor })
+            .collect(),
+    };
+    serde_wasm_bindgen::to_value(&result).map_err(|e| JsValue::from_str(&format!("{e}")))
+}
+
+// ── Schemas ─────────────────────────────────────────────────
+
+/// JSON Schemas of the values crossing the WASM boundary, keyed by type
+/// name: the outputs of the functions above and the JSON they accept.
+#[cfg(feature = "schema")]
+pub fn json_schemas() -> std::collections::BTreeMap<&'static str, schemars::Schema> {
+    use schemars::schema_for;
+    std::collections::BTreeMap::from([
+        ("EventList", schema_for!(compiler::EventList)),
+        ("Program", schema_for!(crate::ast::Program)),
+        ("Grammar", schema_for!(grammar::Grammar)),
+        ("InstrumentConfig", schema_for!(compiler::InstrumentConfig)),
+        ("InstrumentChoice", schema_for!(WasmInstrumentChoice)),
+        ("CursorContext", schema_for!(compiler::CursorContext)),
+        ("TracedCompile", schema_for!(compiler::TracedCompile)),
+        ("OptimizedCompile", schema_for!(compiler::OptimizedCompile)),
+        ("LanguageReport", schema_for!(compiler::LanguageReport)),
+        ("ProjectManifest", schema_for!(compiler::ProjectManifest)),
+        ("CompiledProject", schema_for!(compiler::CompiledProject)),
+        ("SongStructure", schema_for!(compiler::SongStructure)),
+        ("SongConstant", schema_for!(compiler::SongConstant)),
+        ("TrackInstruments", schema_for!(compiler::TrackInstruments)),
+        ("PresetNeed", schema_for!(compiler::PresetNeed)),
+        ("BarInfo", schema_for!(compiler::BarInfo)),
+        ("MarkerInfo", schema_for!(compiler::MarkerInfo)),
+        ("NoteIssue", schema_for!(compiler::NoteIssue)),
+        ("HarmonyAnalysis", schema_for!(theory::HarmonyAnalysis)),
+        ("DiffReport", schema_for!(dsp::diff::DiffReport)),
+        ("TempoEstimate", schema_for!(dsp::tempo::TempoEstimate)),
+        ("PresetDescriptor", schema_for!(preset::PresetDescriptor)),
+        ("LoadedPreset", schema_for!(WasmLoadedPreset)),
+        ("PresetEdit", schema_for!(preset::editor::PresetEdit)),
+        ("PresetIssue", schema_for!(preset::lint::PresetIssue)),
+        ("TunedSample", schema_for!(preset::editor::TunedSample)),
+        ("SamplerConfig", schema_for!(preset::SamplerConfig)),
+        ("BundlePreset", schema_for!(WasmBundlePreset)),
+        ("SongBundle", schema_for!(WasmSongBundle)),
+        ("IntegrityError", schema_for!(preset::integrity::IntegrityError)),
+        ("PresetBankStats", schema_for!(dsp::engine::PresetBankStats)),
+        ("MeteredRender", schema_for!(MeteredRender)),
+        ("OnsetRender", schema_for!(OnsetRender)),
+        ("MasterEffects", schema_for!(dsp::engine::MasterEffects)),
+        ("FrozenTrack", schema_for!(dsp::engine::FrozenTrack)),
+        ("SongEstimate", schema_for!(dsp::engine::SongEstimate)),
+        ("Polyphony", schema_for!(dsp::engine::Polyphony)),
+        ("RenderCacheStats", schema_for!(dsp::cache::RenderCacheStats)),
+    ])
+}
+
+/// WASM-exposed: the schemas from `json_schemas` as a JSON object mapping
+/// type names to JSON Schemas, for generating TypeScript definitions.
+#[cfg(feature = "schema")]
+#[wasm_bindgen]
+pub fn export_schemas() -> String {
+    serde_json::to_string_pretty(&json_schemas()).expect("schemas serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_single_note_produces_audio() {
+        // Build the same minimal EventList that render_single_note does,
+        // but call the engine directly (no WASM).
+        let instrument = compiler::InstrumentConfig::default(); // triangle
+        let event_list = compiler::EventList {
+            events: vec![
+                compiler::Event {
+                    time: 0.0,
+                    kind: compiler::EventKind::SetProperty {
+                        target: "track.beatsPerMinute".to_string(),
+                        value: "120".to_string(),
+                        source_start: 0,
+                        source_end: 0,
+                    },
+                    track_name: None,
+                },
+                compiler::Event {
+                    time: 0.0,
+                    kind: compiler::EventKind::Note {
+                        pitch: "A4".to_string(),
+                        velocity: 100.0,
+                        gate: 1.0,
+                        instrument: 0,
+                        source_start: 0,
+                        source_end: 0,
+                        glide_from: None,
+                        slide_to: None,
+                        expression: Default::default(),
+                        sends: Default::default(),
+                        modulation: Default::default(),
+                    },
+                    track_name: None,
+                },
+            ],
+            instruments: vec![instrument],
+            total_beats: 1.0,
+            end_mode: compiler::EndMode::Release,
+            count_in: None,
+            fade_in: None,
+            fade_out: None,
+            anacrusis: None,
+        };
+
+        let engine = dsp::engine::AudioEngine::new(44100.0);
+        let samples = engine.render(&event_list);
+
+        // Should produce non-silent output.
+        assert!(!samples.is_empty());
+        assert!(samples.iter().any(|&s| s.abs() > 0.001));
+
+        // Should be capped reasonably (1 beat at 120 BPM = 0.5s + release).
+        let max_samples = (4.0 * 44100.0) as usize;
+        assert!(samples.len() <= max_samples);
+    }
+
+    #[test]
+    fn test_preset_bank_persists_across_renders() {
+        clear_preset_bank();
+        let presets_json = r#"[{
+            "name": "Bank/Test",
+            "zones": [{
+                "keyRangeLow": 0, "keyRangeHigh": 127, "rootNote": 69,
+                "fineTuneCents": 0.0, "sampleRate": 44100,
+                "loopStart": null, "loopEnd": null,
+                "samples": [0.5, 0.5, 0.5, 0.5]
+            }]
+        }]"#;
+        assert!(register_presets(presets_json).unwrap().is_empty());
+
+        let stats = PRESET_BANK.with(|bank| bank.borrow().stats());
+        assert_eq!(stats.preset_count, 1);
+        assert_eq!(stats.zone_count, 1);
+        assert_eq!(stats.memory_bytes, 16);
+        assert_eq!(stats.memory_budget, Some(DEFAULT_PRESET_BANK_BUDGET));
+
+        // A render with no new presets still sees the banked one
+        let found = with_bank_engine(44100, "[]", Vec::new(), |engine| {
+            engine.registry().contains("Bank/Test")
+        })
+        .unwrap();
+        assert!(found);
+        assert!(PRESET_BANK.with(|bank| bank.borrow().contains("Bank/Test")));
+
+        assert!(unregister_preset("Bank/Test"));
+        assert_eq!(PRESET_BANK.with(|bank| bank.borrow().memory_bytes()), 0);
+    }
+
+    #[test]
+    fn test_resending_presets_keeps_the_render_cache() {
+        clear_preset_bank();
+        clear_render_cache();
+        let presets = |level: f32| {
+            format!(
+                r#"[{{"name": "Bank/Cached", "zones": [{{
+                "keyRangeLow": 0, "keyRangeHigh": 127, "rootNote": 69,
+                "fineTuneCents": 0.0, "sampleRate": 8000,
+                "loopStart": null, "loopEnd": null,
+                "samples": [{level}, -{level}, {level}, -{level}]
+            }}]}}]"#
+            )
+        };
+        let source = "const keys = loadPreset(\"Bank/Cached\");\ntrack t() {\n    track.instrument = keys;\n    C4 1\n}\nt();";
+        let hits = || RENDER_CACHE.with(|cache| cache.borrow().stats().hits);
+
+        let first = render_song_samples_cached(source, 8000, &presets(0.5), None).unwrap();
+        // The same presets again are not rebuilt, so the track stays cached
+        let again = render_song_samples_cached(source, 8000, &presets(0.5), None).unwrap();
+        assert_eq!(again, first);
+        assert_eq!(hits(), 1);
+        // New sample data replaces the preset and its cached audio
+        let louder = render_song_samples_cached(source, 8000, &presets(0.9), None).unwrap();
+        assert_ne!(louder, first);
+        assert_eq!(hits(), 1);
+        assert!(unregister_preset("Bank/Cached"));
+    }
+
+    #[test]
+    fn test_register_presets_reports_repaired_loops() {
+        let zone = |loop_start: u64, loop_end: u64| {
+            format!(
+                r#"{{"keyRangeLow": 0, "keyRangeHigh": 127, "rootNote": 69,
+                "fineTuneCents": 0.0, "sampleRate": 44100,
+                "loopStart": {loop_start}, "loopEnd": {loop_end},
+                "samples": [0.5, 0.5, 0.5, 0.5]}}"#
+            )
+        };
+        let presets_json = format!(
+            r#"[{{"name": "Bank/Looped", "zones": [{}, {}]}},
+               {{"name": "Bank/Layered", "presetType": "composite", "children": [
+                   {{"type": "oscillator", "waveform": "sine"}},
+                   {{"type": "sampler", "zones": [{}]}}]}}]"#,
+            zone(0, 4),
+            zone(1, 10),
+            zone(3, 2),
+        );
+        let warnings = register_presets(&presets_json).unwrap();
+        assert_eq!(warnings.len(), 2, "{warnings:?}");
+        assert!(warnings[0].starts_with("Bank/Looped zone 1: Loop end 10"), "{}", warnings[0]);
+        assert!(warnings[1].starts_with("Bank/Layered child 1 zone 0: "), "{}", warnings[1]);
+        assert!(unregister_preset("Bank/Looped"));
+        assert!(unregister_preset("Bank/Layered"));
+    }
+
+    #[test]
+    fn test_bank_survives_a_panicking_render() {
+        let presets_json = r#"[{
+            "name": "Bank/Kept",
+            "zones": [{
+                "keyRangeLow": 0, "keyRangeHigh": 127, "rootNote": 69,
+                "fineTuneCents": 0.0, "sampleRate": 44100,
+                "samples": [0.5, 0.5, 0.5, 0.5]
+            }]
+        }]"#;
+        let panicked = std::panic::catch_unwind(|| {
+            with_bank_engine(44100, presets_json, Vec::new(), |_| panic!("render failed")).ok();
+        });
+        assert!(panicked.is_err());
+        // The bank was restored, with the call's presets still in it
+        assert!(PRESET_BANK.with(|bank| bank.borrow().contains("Bank/Kept")));
+        assert!(with_bank_engine(44100, "[]", Vec::new(), |engine| engine.registry().contains("Bank/Kept")).unwrap());
+        assert!(unregister_preset("Bank/Kept"));
+    }
+
+    #[test]
+    fn test_gm_program_presets_resolve() {
+        let presets_json = r#"[{
+            "name": "GM/Nylon Guitar",
+            "gmProgram": 24,
+            "zones": [{
+                "keyRangeLow": 0, "keyRangeHigh": 127, "rootNote": 69,
+                "fineTuneCents": 0.0, "sampleRate": 44100,
+                "loopStart": null, "loopEnd": null,
+                "samples": [0.5, 0.5, 0.5, 0.5]
+            }]
+        }]"#;
+        let found = with_bank_engine(44100, presets_json, Vec::new(), |engine| {
+            engine.registry().contains("gm:24") && !engine.registry().contains("gm:25")
+        })
+        .unwrap();
+        assert!(found);
+        assert!(unregister_preset("GM/Nylon Guitar"));
+    }
+
+    #[test]
+    fn test_render_note_in_context_matches_single_note() {
+        let source = "track.beatsPerMinute = 90;\ntrack.tuningPitch = 432;\ntrack lead() {\n    track.instrument = Oscillator({type: 'square'});\n    track.noteLength = 1/2;\n    C4\n}\nlead();";
+        let cursor = source.find("C4").unwrap();
+        let in_context = render_note_in_context(source, cursor, "A4", 100.0, None, 44100, "[]").unwrap();
+
+        let instrument = serde_json::to_string(&compiler::cursor_context(source, cursor).unwrap().instrument).unwrap();
+        let direct = render_single_note("A4", 100.0, 0.5, 90.0, 432.0, 44100, &instrument, "[]").unwrap();
+        assert!(in_context.iter().any(|&s| s.abs() > 0.001));
+        assert_eq!(in_context, direct);
+
+        let default_instrument = render_note_in_context(source, 0, "A4", 100.0, Some(0.5), 44100, "[]").unwrap();
+        assert_ne!(default_instrument, direct);
+    }
+
+    #[test]
+    fn test_render_chord_sums_its_notes() {
+        let instrument = serde_json::to_string(&compiler::InstrumentConfig::default()).unwrap();
+        let chord = render_chord(r#"["C4", "E4", "G4"]"#, 80.0, 1.0, 120.0, 440.0, 44100, &instrument, "[]").unwrap();
+        let root = render_single_note("C4", 80.0, 1.0, 120.0, 440.0, 44100, &instrument, "[]").unwrap();
+        assert_eq!(chord.len(), root.len());
+        assert_ne!(chord, root);
+        assert!(chord.iter().any(|&s| s.abs() > 0.001));
+
+        let single = render_chord(r#"["C4"]"#, 80.0, 1.0, 120.0, 440.0, 44100, &instrument, "[]").unwrap();
+        assert_eq!(single, root);
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_export_schemas_match_outputs() {
+        let schemas: serde_json::Value = serde_json::from_str(&export_schemas()).unwrap();
+        let event_list = &schemas["EventList"];
+        assert_eq!(event_list["type"], "object");
+        for field in ["events", "total_beats", "end_mode"] {
+            assert!(event_list["properties"][field].is_object(), "EventList.{field}");
+        }
+        assert!(schemas["CursorContext"]["properties"]["key_coverage"].is_object());
+        assert!(schemas["LoadedPreset"]["properties"]["gmProgram"].is_object());
+        assert!(schemas["Program"]["properties"]["statements"].is_object());
+        assert!(schemas["Grammar"]["properties"]["contextualKeywords"].is_object());
+
+        // Compiled output validates structurally against the schema's required fields
+        let program = parse("track t() {\n    C4 1\n}\nt();").unwrap();
+        let output = serde_json::to_value(compiler::compile(&program).unwrap()).unwrap();
+        for required in event_list["required"].as_array().unwrap() {
+            assert!(output.get(required.as_str().unwrap()).is_some(), "missing {required}");
+        }
+    }
+
+    #[test]
+    fn test_missing_presets_are_reported_once() {
+        take_missing_presets();
+        let source = "const piano = loadPreset(\"Nowhere/Piano\");\ntrack t() {\n    track.instrument = piano;\n    C4 1\n}\nt();";
+        let placeholder = render_song_samples_with_presets(source, 8000, "[]", None).unwrap();
+        render_song_samples_with_presets(source, 8000, "[]", None).unwrap();
+        assert_eq!(take_missing_presets(), vec!["Nowhere/Piano".to_string()]);
+        assert!(take_missing_presets().is_empty());
+
+        set_placeholder_for_missing_presets(false);
+        let silent = render_song_samples_with_presets(source, 8000, "[]", None).unwrap();
+        set_placeholder_for_missing_presets(true);
+        assert!(placeholder.iter().any(|s| s.abs() > 0.01));
+        assert!(silent.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_registered_presets_are_preprocessed() {
+        let presets_json = r#"[{
+            "name": "Bank/Quiet",
+            "preprocess": {"normalizePeak": 0.5},
+            "zones": [{
+                "keyRangeLow": 0, "keyRangeHigh": 127, "rootNote": 69,
+                "fineTuneCents": 0.0, "sampleRate": 44100,
+                "loopStart": null, "loopEnd": null,
+                "samples": [0.1, -0.2, 0.1, 0.0]
+            }]
+        }]"#;
+        register_presets(presets_json).unwrap();
+        let peak = PRESET_BANK.with(|bank| match bank.borrow().get("Bank/Quiet") {
+            Some(dsp::engine::RegisteredPreset::Sampler(sampler)) => sampler.zones[0].buffer.peak(),
+            _ => panic!("Expected a sampler"),
+        });
+        assert!((peak - 0.5).abs() < 1e-6);
+        assert!(unregister_preset("Bank/Quiet"));
+    }
+
+    #[test]
+    fn test_render_with_effects_parses_partial_config() {
+        let effects: dsp::engine::MasterEffects =
+            serde_json::from_str(r#"{"reverb": {"roomSize": 0.9}, "compressor": {}}"#).unwrap();
+        let reverb = effects.reverb.unwrap();
+        assert_eq!((reverb.room_size, reverb.mix), (0.9, dsp::engine::ReverbConfig::default().mix));
+        assert!(effects.compressor.is_some() && effects.delay.is_none());
+
+        let source = "track t() {\n    C4 1\n}\nt();";
+        let dry = render_song_samples(source, 8000, None).unwrap();
+        let wet = render_song_samples_with_effects(source, 8000, r#"{"delay": {"time": 0.1}}"#, "[]", None).unwrap();
+        assert_eq!(wet.len(), dry.len() * 2, "Interleaved stereo");
+        let plain = render_song_samples_with_effects(source, 8000, "", "[]", None).unwrap();
+        assert_ne!(wet, plain);
+        let wav = render_song_wav_with_effects(source, 8000, r#"{"reverb": {}}"#, "[]", None).unwrap();
+        assert_eq!(&wav[..4], b"RIFF");
+    }
+
+    #[test]
+    fn test_render_song_samples_stereo_keeps_pan() {
+        let source = "track t() {\n    C4 1 {pan: -1}\n}\nt();";
+        let mono = render_song_samples(source, 8000, None).unwrap();
+        let stereo = render_song_samples_stereo(source, 8000, "[]", None).unwrap();
+        assert_eq!(stereo.len(), mono.len() * 2);
+        let energy = |channel: usize| stereo.iter().skip(channel).step_by(2).map(|s| s * s).sum::<f32>();
+        assert!(energy(0) > 0.0);
+        assert!(energy(1) < energy(0) * 0.01, "Hard-left track is silent on the right");
+    }
+
+    #[test]
+    fn test_end_mode_override() {
+        let source = "song.endMode = 'tail';\ntrack t() {\n    C4 1\n}\nt();";
+        let tail = render_song_samples(source, 8000, None).unwrap();
+        let gate = render_song_samples(source, 8000, Some("gate".to_string())).unwrap();
+        assert!(gate.len() < tail.len());
+        let event_list = compile_for_render(source, Some("release".to_string())).unwrap();
+        assert_eq!(event_list.end_mode, compiler::EndMode::Release);
+        assert_eq!(compile_for_render(source, None).unwrap().end_mode, compiler::EndMode::Tail);
+    }
+}